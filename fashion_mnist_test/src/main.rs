@@ -1,14 +1,13 @@
 extern crate easynn;
-extern crate rust_mnist;
 extern crate serde;
 extern crate serde_json;
 
 use easynn::prelude::*;
-use rust_mnist::{print_sample_image, Mnist};
+use easynn::datasets::load_mnist;
 use serde::{ Deserialize, Serialize };
 use std::fs::File;
 use std::io::prelude::*;
-use std::time::{ Duration, Instant };
+use std::time::Instant;
 
 #[derive(Serialize, Deserialize)]
 struct TrainingProc {
@@ -17,42 +16,6 @@ struct TrainingProc {
     epoch_times: Vec<u128>,
 }
 
-fn get_data(path: &str) -> (Vec::<Tensor<f64>>, Vec::<Tensor<f64>>, Vec::<Tensor<f64>>, Vec::<Tensor<f64>>) {
-    // create the training set
-    let mnist = Mnist::new(path);
-    // Print one image (the one at index 5) for verification.
-    // print_sample_image(&mnist.train_data[5], mnist.train_labels[5]);
-    let mut train_ims = Vec::<Tensor<f64>>::new();      // images
-    let mut train_lbs = Vec::<Tensor<f64>>::new();      // one-hot classifications
-    let mut test_ims = Vec::<Tensor<f64>>::new();      // images
-    let mut test_lbs = Vec::<Tensor<f64>>::new();      // one-hot classifications
-    for im in mnist.train_data {
-        let mut im_f = Vec::<f64>::new();
-        for x in im {
-            im_f.push(x.into());
-        }
-        train_ims.push(Tensor::new(sh!([28, 28]), im_f));
-    }
-    for cl in mnist.train_labels {
-        let mut cl_hot = Tensor::<f64>::zeros(sh!([10]));
-        cl_hot.set([cl as usize], 1.);
-        train_lbs.push(cl_hot);
-    }
-    for im in mnist.test_data {
-        let mut im_f = Vec::<f64>::new();
-        for x in im {
-            im_f.push(x.into());
-        }
-        test_ims.push(Tensor::new(sh!([28, 28]), im_f));
-    }
-    for cl in mnist.test_labels {
-        let mut cl_hot = Tensor::<f64>::zeros(sh!([10]));
-        cl_hot.set([cl as usize], 1.);
-        test_lbs.push(cl_hot);
-    }
-    (train_ims, train_lbs, test_ims, test_lbs)
-}
-
 fn main() {
     let start = Instant::now();
 
@@ -68,7 +31,9 @@ fn main() {
     nn.add(Dense::<f64>::new(sh!([64]), sh!([10]), Activation::Relu));
 
     // Please download the dataset to the directory
-    let (train_ims, train_lbs, test_ims, test_lbs) = get_data("../data/FashionMNIST/raw/");
+    let mnist = load_mnist::<f64, _>("../data/FashionMNIST/raw/").unwrap();
+    let (train_ims, train_lbs) = (mnist.train.images, mnist.train.labels);
+    let (test_ims, test_lbs) = (mnist.test.images, mnist.test.labels);
 
     let mut tproc = TrainingProc {
         train_losses: Vec::<f64>::new(),
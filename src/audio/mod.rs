@@ -0,0 +1,183 @@
+//! The audio module, computing spectral features from raw waveforms.
+//!
+//! A waveform is a rank-1 tensor of samples. Frames of `n_fft` samples are taken
+//! every `hop` samples and weighted by a Hann window, without padding.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::audio::*;
+//!     let sr = 8000;
+//!     let wave = Tensor::<f64>::new(sh!([sr]), (0..sr).map(|i| (i as f64 * 0.3).sin()).collect());
+//!     let config = SpectrogramConfig::new(sr, 256, 128, 40);
+//!     let feats = mfcc(&wave, &config, 13).unwrap();
+//!     assert_eq!(feats.get_shape(), sh!([61, 13]));
+//! ```
+
+#[cfg(feature = "wav")]
+pub mod wav;
+
+use crate::tensor::*;
+use crate::tensor::fft::dft;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+use std::f64::consts::PI;
+
+/// The settings shared by the spectral features
+#[derive(Debug, Copy, Clone)]
+pub struct SpectrogramConfig {
+    pub sample_rate: usize,
+    /// The frame length, also the DFT size
+    pub n_fft: usize,
+    /// The step between two frames
+    pub hop: usize,
+    /// The count of mel bands
+    pub n_mels: usize,
+    /// The lowest frequency of the mel filters, in Hz
+    pub f_min: f64,
+    /// The highest frequency of the mel filters, in Hz
+    pub f_max: f64,
+}
+
+impl SpectrogramConfig {
+    /// Mel filters covering 0 to the Nyquist frequency
+    pub fn new(sample_rate: usize, n_fft: usize, hop: usize, n_mels: usize) -> Self {
+        SpectrogramConfig { sample_rate, n_fft, hop, n_mels, f_min: 0., f_max: sample_rate as f64 / 2. }
+    }
+}
+
+/// The count of frames of a waveform
+fn frame_count(wave: &Tensor<f64>, n_fft: usize, hop: usize) -> Result<usize> {
+    let len = wave.flattened.len();
+    if wave.shape.rank() != 1 || n_fft == 0 || hop == 0 || len < n_fft {
+        return Err(EasynnError::invalid("frames", format!("no frames of {} by {} over the wave {:?}", n_fft, hop, wave.shape.dims())));
+    }
+    Ok(1 + (len - n_fft) / hop)
+}
+
+/// Convert to a f64 tensor, the features are computed in f64
+fn to_f64<T: NumT>(t: &Tensor<T>) -> Tensor<f64> {
+    Tensor::new(&t.shape, t.flattened.iter().map(|x| x.to_f64().unwrap()).collect())
+}
+
+fn from_f64<T: NumT>(t: Tensor<f64>) -> Tensor<T> {
+    Tensor::new(&t.shape, t.flattened.into_iter().map(|x| T::from(x).unwrap()).collect())
+}
+
+/// The short-time Fourier transform of a Hann windowed waveform,
+/// returning the real and imaginary parts, each of shape `[frames, n_fft / 2 + 1]`
+pub fn stft<T: NumT>(wave: &Tensor<T>, n_fft: usize, hop: usize) -> Result<(Tensor<T>, Tensor<T>)> {
+    let wave = to_f64(wave);
+    let frames = frame_count(&wave, n_fft, hop)?;
+    let bins = n_fft / 2 + 1;
+    let window: Vec<f64> = (0..n_fft).map(|i| 0.5 - 0.5 * (2. * PI * i as f64 / n_fft as f64).cos()).collect();
+    let mut out_re = Tensor::<f64>::zeros(&Shape::new([frames, bins]));
+    let mut out_im = Tensor::<f64>::zeros(&Shape::new([frames, bins]));
+    for f in 0..frames {
+        let mut re: Vec<f64> = wave.flattened[f * hop..f * hop + n_fft].iter().zip(window.iter()).map(|(x, w)| x * w).collect();
+        let mut im = vec![0.; n_fft];
+        dft(&mut re, &mut im);
+        out_re.flattened[f * bins..(f + 1) * bins].copy_from_slice(&re[..bins]);
+        out_im.flattened[f * bins..(f + 1) * bins].copy_from_slice(&im[..bins]);
+    }
+    Ok((from_f64(out_re), from_f64(out_im)))
+}
+
+/// The power spectrogram `|stft|^2`, of shape `[frames, n_fft / 2 + 1]`
+pub fn power_spectrogram<T: NumT>(wave: &Tensor<T>, n_fft: usize, hop: usize) -> Result<Tensor<T>> {
+    let (re, im) = stft(wave, n_fft, hop)?;
+    let power = re.flattened.iter().zip(im.flattened.iter()).map(|(r, i)| *r * *r + *i * *i).collect();
+    Ok(Tensor::new(&re.shape, power))
+}
+
+fn hz_to_mel(f: f64) -> f64 {
+    2595. * (1. + f / 700.).log10()
+}
+
+fn mel_to_hz(m: f64) -> f64 {
+    700. * (10_f64.powf(m / 2595.) - 1.)
+}
+
+/// Triangular filters evenly spaced on the mel scale, of shape `[n_mels, n_fft / 2 + 1]`
+pub fn mel_filterbank<T: NumT>(config: &SpectrogramConfig) -> Tensor<T> {
+    let bins = config.n_fft / 2 + 1;
+    let (m_lo, m_hi) = (hz_to_mel(config.f_min), hz_to_mel(config.f_max));
+    // the n_mels + 2 edges of the triangles
+    let edges: Vec<f64> = (0..config.n_mels + 2)
+        .map(|i| mel_to_hz(m_lo + (m_hi - m_lo) * i as f64 / (config.n_mels + 1) as f64))
+        .collect();
+    let mut fb = Tensor::<f64>::zeros(&Shape::new([config.n_mels, bins]));
+    for m in 0..config.n_mels {
+        let (lo, mid, hi) = (edges[m], edges[m + 1], edges[m + 2]);
+        for k in 0..bins {
+            let f = k as f64 * config.sample_rate as f64 / config.n_fft as f64;
+            let w = if f > lo && f <= mid {
+                (f - lo) / (mid - lo)
+            } else if f > mid && f < hi {
+                (hi - f) / (hi - mid)
+            } else {
+                0.
+            };
+            fb.flattened[m * bins + k] = w;
+        }
+    }
+    from_f64(fb)
+}
+
+/// The mel spectrogram, of shape `[frames, n_mels]`
+pub fn mel_spectrogram<T: NumT>(wave: &Tensor<T>, config: &SpectrogramConfig) -> Result<Tensor<T>> {
+    let power = power_spectrogram(wave, config.n_fft, config.hop)?;
+    let fb = mel_filterbank::<T>(config);
+    let (frames, bins) = (power.shape[0], power.shape[1]);
+    let mut mel = Tensor::<T>::zeros(&Shape::new([frames, config.n_mels]));
+    for f in 0..frames {
+        let p_f = &power.flattened[f * bins..(f + 1) * bins];
+        for m in 0..config.n_mels {
+            let w_m = &fb.flattened[m * bins..(m + 1) * bins];
+            mel.flattened[f * config.n_mels + m] = p_f.iter().zip(w_m.iter()).map(|(p, w)| *p * *w).sum();
+        }
+    }
+    Ok(mel)
+}
+
+/// The mel-frequency cepstral coefficients, i.e. the orthonormal DCT-II of the log mel spectrogram,
+/// of shape `[frames, n_mfcc]`
+pub fn mfcc<T: NumT>(wave: &Tensor<T>, config: &SpectrogramConfig, n_mfcc: usize) -> Result<Tensor<T>> {
+    if n_mfcc > config.n_mels {
+        return Err(EasynnError::invalid("mfcc", format!("{} coefficients exceed the {} mel bands", n_mfcc, config.n_mels)));
+    }
+    let mel = to_f64(&mel_spectrogram(wave, config)?);
+    let (frames, n) = (mel.shape[0], config.n_mels);
+    let mut out = Tensor::<f64>::zeros(&Shape::new([frames, n_mfcc]));
+    for f in 0..frames {
+        let log_mel: Vec<f64> = mel.flattened[f * n..(f + 1) * n].iter().map(|m| (m + 1e-10).ln()).collect();
+        for k in 0..n_mfcc {
+            let scale = if k == 0 { (1. / n as f64).sqrt() } else { (2. / n as f64).sqrt() };
+            out.flattened[f * n_mfcc + k] = scale * log_mel.iter().enumerate()
+                .map(|(i, x)| x * (PI * k as f64 * (2 * i + 1) as f64 / (2 * n) as f64).cos())
+                .sum::<f64>();
+        }
+    }
+    Ok(from_f64(out))
+}
+
+#[test]
+fn test_spectral_features() {
+    // a 1 kHz tone sampled at 8 kHz peaks at bin 1000 / (8000 / 64) = 8
+    let sr = 8000;
+    let wave = Tensor::<f64>::new(&Shape::new([1024]), (0..1024).map(|i| (2. * PI * 1000. * i as f64 / sr as f64).sin()).collect());
+    let power = power_spectrogram(&wave, 64, 32).unwrap();
+    assert_eq!(power.shape, Shape::new([31, 33]));
+    let frame0 = &power.flattened[..33];
+    let peak = (0..33).fold(0, |b, k| if frame0[k] > frame0[b] { k } else { b });
+    assert_eq!(peak, 8);
+
+    let config = SpectrogramConfig::new(sr, 64, 32, 10);
+    let fb = mel_filterbank::<f64>(&config);
+    for m in 0..10 {
+        assert!(fb.flattened[m * 33..(m + 1) * 33].iter().all(|w| (0. ..=1.).contains(w)));
+    }
+    assert_eq!(mel_spectrogram(&wave, &config).unwrap().shape, Shape::new([31, 10]));
+    assert_eq!(mfcc(&wave, &config, 5).unwrap().shape, Shape::new([31, 5]));
+    assert!(mfcc(&wave, &config, 11).is_err());
+    assert!(stft(&Tensor::<f64>::zeros(&Shape::new([10])), 64, 32).is_err());
+}
@@ -0,0 +1,179 @@
+//! WAV file reading, enabled by the `wav` feature.
+//!
+//! PCM of 8, 16, 24 and 32 bits and 32-bit IEEE float are supported,
+//! the samples are scaled into `[-1, 1]`.
+
+use crate::tensor::*;
+
+use std::fs;
+use std::io::{ Error, ErrorKind, Result };
+use std::path::Path;
+
+/// How the samples are post-processed after decoding
+#[derive(Debug, Copy, Clone, Default)]
+pub struct WavOptions {
+    /// Average all the channels into one
+    pub mono: bool,
+    /// Resample to this rate by linear interpolation
+    pub sample_rate: Option<usize>,
+}
+
+/// A decoded WAV file
+#[derive(Debug, Clone)]
+pub struct Wav {
+    pub sample_rate: usize,
+    pub channels: usize,
+    /// Of shape `[len]` if there is one channel, otherwise `[channels, len]`
+    pub samples: Tensor<f32>,
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid WAV: {}", msg))
+}
+
+fn u16_at(b: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([b[pos], b[pos + 1]])
+}
+
+fn u32_at(b: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([b[pos], b[pos + 1], b[pos + 2], b[pos + 3]])
+}
+
+/// Decode one sample of the given format and bit depth
+fn decode(b: &[u8], float: bool, bits: u16) -> f32 {
+    match (float, bits) {
+        (false, 8) => (b[0] as f32 - 128.) / 128.,
+        (false, 16) => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.,
+        (false, 24) => (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f32 / 8388608.,
+        (false, 32) => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.,
+        (true, 32) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+        _ => unreachable!(),
+    }
+}
+
+/// Resample one channel by linear interpolation
+fn resample(x: &[f32], from: usize, to: usize) -> Vec<f32> {
+    if from == to || x.is_empty() {
+        return x.to_vec();
+    }
+    let len = ((x.len() as u64 * to as u64) / from as u64).max(1) as usize;
+    (0..len).map(|i| {
+        let pos = i as f64 * from as f64 / to as f64;
+        let lo = (pos.floor() as usize).min(x.len() - 1);
+        let hi = (lo + 1).min(x.len() - 1);
+        let frac = (pos - lo as f64) as f32;
+        x[lo] * (1. - frac) + x[hi] * frac
+    }).collect()
+}
+
+/// Decode the bytes of a WAV file
+pub fn parse_wav(bytes: &[u8], options: WavOptions) -> Result<Wav> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("missing RIFF/WAVE header"));
+    }
+    let mut format: Option<(bool, usize, usize, u16)> = None;
+    let mut data: Option<&[u8]> = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = u32_at(bytes, pos + 4) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + size).min(bytes.len())];
+        if id == b"fmt " {
+            if body.len() < 16 {
+                return Err(invalid("truncated fmt chunk"));
+            }
+            let mut tag = u16_at(body, 0);
+            // WAVE_FORMAT_EXTENSIBLE keeps the real tag in the sub-format
+            if tag == 0xFFFE && body.len() >= 26 {
+                tag = u16_at(body, 24);
+            }
+            let bits = u16_at(body, 14);
+            let float = match (tag, bits) {
+                (1, 8) | (1, 16) | (1, 24) | (1, 32) => false,
+                (3, 32) => true,
+                _ => return Err(invalid(&format!("unsupported format {} of {} bits", tag, bits))),
+            };
+            format = Some((float, u16_at(body, 2) as usize, u32_at(body, 4) as usize, bits));
+        } else if id == b"data" {
+            data = Some(body);
+        }
+        // chunks are padded to even sizes
+        pos += 8 + size + size % 2;
+    }
+    let (float, channels, sample_rate, bits) = format.ok_or_else(|| invalid("missing fmt chunk"))?;
+    let data = data.ok_or_else(|| invalid("missing data chunk"))?;
+    if channels == 0 || sample_rate == 0 {
+        return Err(invalid("zero channels or sample rate"));
+    }
+
+    // de-interleave the frames
+    let width = bits as usize / 8;
+    let frames = data.len() / (width * channels);
+    let mut chans = vec![Vec::<f32>::with_capacity(frames); channels];
+    for frame in data.chunks_exact(width * channels) {
+        for (c, s) in chans.iter_mut().zip(frame.chunks_exact(width)) {
+            c.push(decode(s, float, bits));
+        }
+    }
+    if options.mono && channels > 1 {
+        let mixed = (0..frames).map(|i| chans.iter().map(|c| c[i]).sum::<f32>() / channels as f32).collect();
+        chans = vec![mixed];
+    }
+    let rate = options.sample_rate.unwrap_or(sample_rate);
+    if rate == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, "the target sample rate is 0"));
+    }
+    let chans: Vec<Vec<f32>> = chans.iter().map(|c| resample(c, sample_rate, rate)).collect();
+    let len = chans[0].len();
+    let shape = if chans.len() == 1 { Shape::new([len]) } else { Shape::new([chans.len(), len]) };
+    Ok(Wav {
+        sample_rate: rate,
+        channels: chans.len(),
+        samples: Tensor::new(&shape, chans.concat()),
+    })
+}
+
+/// Read and decode a WAV file
+pub fn read_wav<P: AsRef<Path>>(path: P, options: WavOptions) -> Result<Wav> {
+    parse_wav(&fs::read(path)?, options)
+}
+
+#[test]
+fn test_parse_wav() {
+    // 16 bit stereo at 4 Hz, 4 frames
+    let frames: [(i16, i16); 4] = [(0, 16384), (16384, 16384), (-16384, 0), (32767, -32768)];
+    let mut data = Vec::<u8>::new();
+    for (l, r) in frames {
+        data.extend_from_slice(&l.to_le_bytes());
+        data.extend_from_slice(&r.to_le_bytes());
+    }
+    let mut bytes = Vec::<u8>::new();
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&(36 + data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(b"WAVEfmt ");
+    bytes.extend_from_slice(&16_u32.to_le_bytes());
+    for v in [1_u16, 2] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    bytes.extend_from_slice(&4_u32.to_le_bytes());
+    bytes.extend_from_slice(&16_u32.to_le_bytes());
+    for v in [4_u16, 16] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&data);
+
+    let wav = parse_wav(&bytes, WavOptions::default()).unwrap();
+    assert_eq!((wav.sample_rate, wav.channels), (4, 2));
+    assert_eq!(wav.samples.get_shape(), &Shape::new([2, 4]));
+    assert_eq!(wav.samples.get([0, 1]), 0.5);
+    assert_eq!(wav.samples.get([1, 3]), -1.);
+
+    let mono = parse_wav(&bytes, WavOptions { mono: true, sample_rate: Some(8) }).unwrap();
+    assert_eq!(mono.samples.get_shape(), &Shape::new([8]));
+    assert_eq!(mono.samples.get([0]), 0.25);
+    assert_eq!(mono.samples.get([1]), 0.375);
+
+    assert!(parse_wav(&bytes[..20], WavOptions::default()).is_err());
+}
@@ -0,0 +1,627 @@
+//! Forward-mode differentiation by dual numbers, next to the reverse mode of
+//! backpropagation: a `Dual` carries a value and its derivative along one direction,
+//! so one forward pass over duals gives the outputs and their Jacobian-vector product.
+//!
+//! `ForwardMode` runs a copy of a `Sequential` over duals, rebuilt from the records of
+//! its layers. A JVP costs about one prediction, which makes the sensitivities of the
+//! outputs to a few input directions, or the whole Jacobian of a model of few inputs,
+//! cheaper than a backward pass per output.
+//!
+//! Backpropagating over duals whose parameters move along a vector gives the exact
+//! Hessian-vector product of the loss, `hvp`, on which the second-order utilities are
+//! built: the sharpness of the loss by `top_eigen`, and the inverse-Hessian products of
+//! influence functions or of preconditioners by `solve_hessian`.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::autograd::ForwardMode;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([2]), sh!([3]), Activation::Tanh));
+//!     let fm = ForwardMode::new(&nn).unwrap();
+//!     let x = Tensor::new(sh!([2]), vec![0.5, -1.]);
+//!     // the outputs and their derivatives along the first input
+//!     let (y, dy) = fm.jvp(&x, &Tensor::new(sh!([2]), vec![1., 0.])).unwrap();
+//!     assert_eq!(y, nn.predict(&x).unwrap());
+//!     // the derivatives along every input, as columns
+//!     let jacobian = fm.jacobian(&x).unwrap();
+//!     assert_eq!(jacobian.get_shape(), &Shape::new([3, 2]));
+//!     assert_eq!(jacobian.get([1, 0]), dy.get([1]));
+//! ```
+
+use crate::tensor::*;
+use crate::layers::record::LayerRecord;
+use crate::models::Model;
+use crate::models::sequential::Sequential;
+
+use num_traits::{ Float, Num, NumCast, One, ToPrimitive, Zero };
+use rand::Rng;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::io;
+use std::num::FpCategory;
+use std::ops::{ Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, RemAssign, Sub, SubAssign };
+
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// A dual number `re + eps ε`, with `ε² = 0`: a value and its derivative.
+/// Duals are compared by their values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Dual<T> {
+    pub re: T,
+    pub eps: T,
+}
+
+impl<T: NumT> Dual<T> {
+    pub fn new(re: T, eps: T) -> Self {
+        Dual { re, eps }
+    }
+    /// The dual of zero derivative
+    pub fn constant(re: T) -> Self {
+        Dual { re, eps: T::zero() }
+    }
+    /// The dual of value `re` and of derivative `d` times ours, by the chain rule
+    fn chain(self, re: T, d: T) -> Self {
+        Dual { re, eps: self.eps * d }
+    }
+}
+
+impl<T: NumT> PartialEq for Dual<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.re == other.re
+    }
+}
+
+impl<T: NumT> PartialOrd for Dual<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.re.partial_cmp(&other.re)
+    }
+}
+
+impl<T: NumT> fmt::Display for Dual<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{}ε", self.re, self.eps)
+    }
+}
+
+impl<T: NumT> Add for Dual<T> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Dual { re: self.re + rhs.re, eps: self.eps + rhs.eps }
+    }
+}
+
+impl<T: NumT> Sub for Dual<T> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Dual { re: self.re - rhs.re, eps: self.eps - rhs.eps }
+    }
+}
+
+impl<T: NumT> Mul for Dual<T> {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Dual { re: self.re * rhs.re, eps: self.eps * rhs.re + self.re * rhs.eps }
+    }
+}
+
+impl<T: NumT> Div for Dual<T> {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        Dual { re: self.re / rhs.re, eps: (self.eps * rhs.re - self.re * rhs.eps) / (rhs.re * rhs.re) }
+    }
+}
+
+impl<T: NumT> Rem for Dual<T> {
+    type Output = Self;
+    /// `a % b = a - b trunc(a / b)`, the truncation being locally constant
+    fn rem(self, rhs: Self) -> Self {
+        Dual { re: self.re % rhs.re, eps: self.eps - rhs.eps * (self.re / rhs.re).trunc() }
+    }
+}
+
+impl<T: NumT> Neg for Dual<T> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Dual { re: -self.re, eps: -self.eps }
+    }
+}
+
+macro_rules! impl_assign {
+    ($($op:ident $method:ident $by:tt),*) => ($(
+        impl<T: NumT> $op for Dual<T> {
+            fn $method(&mut self, rhs: Self) {
+                *self = *self $by rhs;
+            }
+        }
+    )*)
+}
+
+impl_assign!(AddAssign add_assign +, SubAssign sub_assign -, MulAssign mul_assign *, DivAssign div_assign /, RemAssign rem_assign %);
+
+impl<T: NumT> std::iter::Sum for Dual<T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |a, b| a + b)
+    }
+}
+
+impl<T: NumT> Zero for Dual<T> {
+    fn zero() -> Self {
+        Self::constant(T::zero())
+    }
+    fn is_zero(&self) -> bool {
+        self.re.is_zero()
+    }
+}
+
+impl<T: NumT> One for Dual<T> {
+    fn one() -> Self {
+        Self::constant(T::one())
+    }
+}
+
+impl<T: NumT> Num for Dual<T> {
+    type FromStrRadixErr = T::FromStrRadixErr;
+    fn from_str_radix(s: &str, radix: u32) -> std::result::Result<Self, Self::FromStrRadixErr> {
+        T::from_str_radix(s, radix).map(Self::constant)
+    }
+}
+
+impl<T: NumT> ToPrimitive for Dual<T> {
+    fn to_i64(&self) -> Option<i64> {
+        self.re.to_i64()
+    }
+    fn to_u64(&self) -> Option<u64> {
+        self.re.to_u64()
+    }
+    fn to_f64(&self) -> Option<f64> {
+        self.re.to_f64()
+    }
+}
+
+impl<T: NumT> NumCast for Dual<T> {
+    fn from<N: ToPrimitive>(n: N) -> Option<Self> {
+        T::from(n).map(Self::constant)
+    }
+}
+
+/// The constants of `Float`, of zero derivative
+macro_rules! constants {
+    ($($name:ident)*) => ($(
+        fn $name() -> Self {
+            Self::constant(T::$name())
+        }
+    )*)
+}
+
+/// The predicates of `Float`, on the value
+macro_rules! predicates {
+    ($($name:ident)*) => ($(
+        fn $name(self) -> bool {
+            self.re.$name()
+        }
+    )*)
+}
+
+/// The piecewise constant functions of `Float`, of zero derivative
+macro_rules! steps {
+    ($($name:ident)*) => ($(
+        fn $name(self) -> Self {
+            Self::constant(self.re.$name())
+        }
+    )*)
+}
+
+impl<T: NumT> Float for Dual<T> {
+    constants!(nan infinity neg_infinity neg_zero min_value min_positive_value max_value epsilon);
+    predicates!(is_nan is_infinite is_finite is_normal is_sign_positive is_sign_negative);
+    steps!(floor ceil round trunc signum);
+
+    fn classify(self) -> FpCategory {
+        self.re.classify()
+    }
+    fn integer_decode(self) -> (u64, i16, i8) {
+        self.re.integer_decode()
+    }
+    fn fract(self) -> Self {
+        Dual { re: self.re.fract(), eps: self.eps }
+    }
+    fn abs(self) -> Self {
+        self.chain(self.re.abs(), self.re.signum())
+    }
+    fn mul_add(self, a: Self, b: Self) -> Self {
+        self * a + b
+    }
+    fn recip(self) -> Self {
+        self.chain(self.re.recip(), -(self.re * self.re).recip())
+    }
+    fn powi(self, n: i32) -> Self {
+        self.chain(self.re.powi(n), T::from(n).unwrap() * self.re.powi(n - 1))
+    }
+    fn powf(self, n: Self) -> Self {
+        if n.eps.is_zero() {
+            return self.chain(self.re.powf(n.re), n.re * self.re.powf(n.re - T::one()));
+        }
+        // d(x^y) = x^y (dy ln x + y dx / x)
+        let re = self.re.powf(n.re);
+        Dual { re, eps: re * (n.eps * self.re.ln() + n.re * self.eps / self.re) }
+    }
+    fn sqrt(self) -> Self {
+        let s = self.re.sqrt();
+        self.chain(s, (s + s).recip())
+    }
+    fn exp(self) -> Self {
+        let e = self.re.exp();
+        self.chain(e, e)
+    }
+    fn exp2(self) -> Self {
+        let e = self.re.exp2();
+        self.chain(e, e * T::from(2).unwrap().ln())
+    }
+    fn ln(self) -> Self {
+        self.chain(self.re.ln(), self.re.recip())
+    }
+    fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+    fn log2(self) -> Self {
+        self.chain(self.re.log2(), (self.re * T::from(2).unwrap().ln()).recip())
+    }
+    fn log10(self) -> Self {
+        self.chain(self.re.log10(), (self.re * T::from(10).unwrap().ln()).recip())
+    }
+    fn max(self, other: Self) -> Self {
+        if other.re.is_nan() || self.re >= other.re { self } else { other }
+    }
+    fn min(self, other: Self) -> Self {
+        if other.re.is_nan() || self.re <= other.re { self } else { other }
+    }
+    fn abs_sub(self, other: Self) -> Self {
+        if self.re <= other.re { Self::zero() } else { self - other }
+    }
+    fn cbrt(self) -> Self {
+        let c = self.re.cbrt();
+        self.chain(c, (T::from(3).unwrap() * c * c).recip())
+    }
+    fn hypot(self, other: Self) -> Self {
+        let h = self.re.hypot(other.re);
+        Dual { re: h, eps: (self.re * self.eps + other.re * other.eps) / h }
+    }
+    fn sin(self) -> Self {
+        self.chain(self.re.sin(), self.re.cos())
+    }
+    fn cos(self) -> Self {
+        self.chain(self.re.cos(), -self.re.sin())
+    }
+    fn tan(self) -> Self {
+        let t = self.re.tan();
+        self.chain(t, T::one() + t * t)
+    }
+    fn asin(self) -> Self {
+        self.chain(self.re.asin(), (T::one() - self.re * self.re).sqrt().recip())
+    }
+    fn acos(self) -> Self {
+        self.chain(self.re.acos(), -(T::one() - self.re * self.re).sqrt().recip())
+    }
+    fn atan(self) -> Self {
+        self.chain(self.re.atan(), (T::one() + self.re * self.re).recip())
+    }
+    fn atan2(self, other: Self) -> Self {
+        let r2 = self.re * self.re + other.re * other.re;
+        Dual { re: self.re.atan2(other.re), eps: (other.re * self.eps - self.re * other.eps) / r2 }
+    }
+    fn sin_cos(self) -> (Self, Self) {
+        (self.sin(), self.cos())
+    }
+    fn exp_m1(self) -> Self {
+        self.chain(self.re.exp_m1(), self.re.exp())
+    }
+    fn ln_1p(self) -> Self {
+        self.chain(self.re.ln_1p(), (T::one() + self.re).recip())
+    }
+    fn sinh(self) -> Self {
+        self.chain(self.re.sinh(), self.re.cosh())
+    }
+    fn cosh(self) -> Self {
+        self.chain(self.re.cosh(), self.re.sinh())
+    }
+    fn tanh(self) -> Self {
+        let t = self.re.tanh();
+        self.chain(t, T::one() - t * t)
+    }
+    fn asinh(self) -> Self {
+        self.chain(self.re.asinh(), (self.re * self.re + T::one()).sqrt().recip())
+    }
+    fn acosh(self) -> Self {
+        self.chain(self.re.acosh(), (self.re * self.re - T::one()).sqrt().recip())
+    }
+    fn atanh(self) -> Self {
+        self.chain(self.re.atanh(), (T::one() - self.re * self.re).recip())
+    }
+}
+
+impl<T: NumT> NumT for Dual<T> { }
+
+/// The tensor of duals of the values and the derivatives, of the same shape
+fn dual_tensor<T: NumT>(op: &'static str, re: &Tensor<T>, eps: &Tensor<T>) -> Result<Tensor<Dual<T>>> {
+    check_shape(op, &re.shape, &eps.shape)?;
+    Ok(Tensor::new(&re.shape, re.flattened.iter().zip(eps.flattened.iter()).map(|(r, e)| Dual::new(*r, *e)).collect()))
+}
+
+/// The values and the derivatives of a tensor of duals
+fn split<T: NumT>(t: Tensor<Dual<T>>) -> (Tensor<T>, Tensor<T>) {
+    (Tensor::new(&t.shape, t.flattened.iter().map(|d| d.re).collect()), Tensor::new(&t.shape, t.flattened.iter().map(|d| d.eps).collect()))
+}
+
+/// The copy of the model over duals, of the parameters moving along the tangents, listed
+/// like `Layer::parameters` layer after layer, or constant.
+/// Fails if a layer has no `LayerRecord` to be rebuilt from.
+fn dual_model<T: NumT>(model: &Sequential<T>, tangents: Option<&[Vec<T>]>) -> io::Result<Sequential<Dual<T>>> {
+    let mut dual = Sequential::new(model.loss);
+    let mut slot = 0;
+    for (i, layer) in model.layers().iter().enumerate() {
+        let record = layer.record().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+            format!("layer {} ({}) cannot be differentiated in forward mode", i, layer.name())))?;
+        dual.layers_mut().push(dual_record(i, record, tangents, &mut slot)?.into_layer()?);
+    }
+    Ok(dual)
+}
+
+/// The record over duals of the record of layer `i`, and of its inner records,
+/// the parameters taking the tangents from `slot` on
+fn dual_record<T: NumT>(i: usize, record: LayerRecord<T>, tangents: Option<&[Vec<T>]>, slot: &mut usize) -> io::Result<LayerRecord<Dual<T>>> {
+    let parameters = record.parameters.iter().map(|p| {
+        *slot += 1;
+        match tangents {
+            Some(t) => p.iter().zip(t[*slot - 1].iter()).map(|(x, e)| Dual::new(*x, *e)).collect(),
+            None => p.iter().map(|x| Dual::constant(*x)).collect(),
+        }
+    }).collect();
+    let activation = record.activation.map(Dual::constant).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData,
+        format!("the activation of layer {} is not registered over duals", i)))?;
+    let inner = record.inner.into_iter().map(|r| dual_record(i, r, tangents, slot)).collect::<io::Result<Vec<_>>>()?;
+    Ok(LayerRecord { kind: record.kind, config: record.config, activation, parameters, inner, floats: record.floats })
+}
+
+/// A model run over duals, for its Jacobian-vector products, see the module
+pub struct ForwardMode<T: NumT> {
+    model: Sequential<Dual<T>>,
+}
+
+impl<T: NumT> ForwardMode<T> {
+    /// Copy the model over duals, its parameters being constants.
+    /// Fails if a layer has no `LayerRecord` to be rebuilt from.
+    pub fn new(model: &Sequential<T>) -> io::Result<Self> {
+        Ok(ForwardMode { model: dual_model(model, None)? })
+    }
+
+    /// The outputs of the input, and their derivatives along the tangent of the input
+    pub fn jvp(&self, input: &Tensor<T>, tangent: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        Ok(split(self.model.predict(&dual_tensor("jvp", input, tangent)?)?))
+    }
+
+    /// The JVPs of a batch of inputs stacked as `[batch, ..input_shape]`, each along its own
+    /// tangent, e.g. the per-sample sensitivities of the outputs to a perturbation
+    pub fn jvp_batch(&self, inputs: &Tensor<T>, tangents: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        Ok(split(self.model.predict_batch(&dual_tensor("jvp_batch", inputs, tangents)?)?))
+    }
+
+    /// The Jacobian of the outputs by the inputs at the input, as an `[outputs, inputs]`
+    /// matrix of the flattened shapes, by a batch of one JVP per input
+    pub fn jacobian(&self, input: &Tensor<T>) -> Result<Tensor<T>> {
+        let n = input.shape.size();
+        let inputs = Tensor::new(&input.shape.batched(n), input.flattened.iter().cycle().take(n * n).copied().collect());
+        let mut tangents = Tensor::zeros(&input.shape.batched(n));
+        for i in 0..n {
+            tangents.flattened[i * n + i] = T::one();
+        }
+        let (_, columns) = self.jvp_batch(&inputs, &tangents)?;
+        let m = columns.shape.size() / n.max(1);
+        Ok(columns.into_shape(&Shape::new([n, m]))?.transpose())
+    }
+}
+
+/// The vectors of the parameters, listed like `Layer::parameters` layer after layer
+pub type ParamVec<T> = Vec<Vec<T>>;
+
+fn dot<T: NumT>(x: &[Vec<T>], y: &[Vec<T>]) -> T {
+    x.iter().zip(y).map(|(a, b)| a.iter().zip(b).map(|(p, q)| *p * *q).sum::<T>()).sum()
+}
+
+/// `y += alpha * x`
+fn axpy<T: NumT>(alpha: T, x: &[Vec<T>], y: &mut [Vec<T>]) {
+    y.iter_mut().zip(x).for_each(|(b, a)| b.iter_mut().zip(a).for_each(|(q, p)| *q += alpha * *p));
+}
+
+fn scaled<T: NumT>(alpha: T, x: &[Vec<T>]) -> ParamVec<T> {
+    x.iter().map(|a| a.iter().map(|p| alpha * *p).collect()).collect()
+}
+
+/// The length of each parameter of the model
+fn param_lens<T: NumT>(model: &Sequential<T>) -> Vec<usize> {
+    model.layers().iter().flat_map(|l| l.parameters().into_iter().map(|p| p.len())).collect()
+}
+
+/// The Hessian of the mean loss of the samples by the parameters times the vector `v`.
+///
+/// The gradient is backpropagated over duals whose parameters move along `v`, giving
+/// the exact product (forward over reverse mode). If a layer cannot be run over duals,
+/// the product falls back to the central difference of the gradients at the parameters
+/// moved by `±h v`, restored afterwards.
+pub fn hvp<T: NumT>(model: &mut Sequential<T>, inputs: &[Tensor<T>], truths: &[Tensor<T>], v: &[Vec<T>]) -> Result<ParamVec<T>> {
+    let params = param_lens(model);
+    check_len("hvp", params.len(), v.len())?;
+    for (len, vi) in params.iter().zip(v) {
+        check_len("hvp", *len, vi.len())?;
+    }
+    let dual = match dual_model(model, Some(v)) {
+        Ok(dual) => dual,
+        Err(_) => return hvp_finite_difference(model, inputs, truths, v),
+    };
+    let constant = |ts: &[Tensor<T>]| ts.iter().map(|t| dual_tensor("hvp", t, &Tensor::zeros(&t.shape))).collect::<Result<Vec<_>>>();
+    let grads = dual.loss_gradients(&constant(inputs)?, &constant(truths)?)?;
+    Ok(grads.iter().map(|g| g.iter().map(|d| d.eps).collect()).collect())
+}
+
+fn hvp_finite_difference<T: NumT>(model: &mut Sequential<T>, inputs: &[Tensor<T>], truths: &[Tensor<T>], v: &[Vec<T>]) -> Result<ParamVec<T>> {
+    let norm = dot(v, v).sqrt();
+    if norm == T::zero() {
+        return Ok(scaled(T::zero(), v));
+    }
+    let h = T::epsilon().cbrt() / norm;
+    model.add_to_parameters(h, v);
+    let plus = model.loss_gradients(inputs, truths);
+    model.add_to_parameters(-(h + h), v);
+    let minus = model.loss_gradients(inputs, truths);
+    model.add_to_parameters(h, v);
+    let mut hv = plus?;
+    axpy(-T::one(), &minus?, &mut hv);
+    Ok(scaled((h + h).recip(), &hv))
+}
+
+/// The largest eigenvalue of the Hessian in magnitude and its unit eigenvector, by `iterations`
+/// steps of the power iteration: the sharpness of the loss at the parameters
+pub fn top_eigen<T: NumT>(model: &mut Sequential<T>, inputs: &[Tensor<T>], truths: &[Tensor<T>], iterations: usize) -> Result<(T, ParamVec<T>)> {
+    let mut rng = rand::thread_rng();
+    let mut v: ParamVec<T> = param_lens(model).iter().map(|n| (0..*n).map(|_| T::from(rng.gen_range(-1_f64..1.)).unwrap()).collect()).collect();
+    let mut lambda = T::zero();
+    for _ in 0..iterations.max(1) {
+        let norm = dot(&v, &v).sqrt();
+        if norm == T::zero() {
+            break;
+        }
+        v = scaled(norm.recip(), &v);
+        let hv = hvp(model, inputs, truths, &v)?;
+        lambda = dot(&v, &hv);
+        v = hv;
+    }
+    let norm = dot(&v, &v).sqrt();
+    Ok((lambda, if norm == T::zero() { v } else { scaled(norm.recip(), &v) }))
+}
+
+/// Solve `(H + damping I) x = b` by at most `iterations` steps of the conjugate gradient,
+/// e.g. the inverse-Hessian products of influence functions; the damping keeps the system
+/// positive definite where the loss is not convex
+pub fn solve_hessian<T: NumT>(model: &mut Sequential<T>, inputs: &[Tensor<T>], truths: &[Tensor<T>], b: &[Vec<T>], damping: T, iterations: usize) -> Result<ParamVec<T>> {
+    let mut x = scaled(T::zero(), b);
+    let mut r = b.to_vec();
+    let mut p = r.clone();
+    let mut rr = dot(&r, &r);
+    let tolerance = rr * T::epsilon();
+    for _ in 0..iterations {
+        if rr <= tolerance {
+            break;
+        }
+        let mut ap = hvp(model, inputs, truths, &p)?;
+        axpy(damping, &p, &mut ap);
+        let alpha = rr / dot(&p, &ap);
+        axpy(alpha, &p, &mut x);
+        axpy(-alpha, &ap, &mut r);
+        let rr_next = dot(&r, &r);
+        p = scaled(rr_next / rr, &p);
+        axpy(T::one(), &r, &mut p);
+        rr = rr_next;
+    }
+    Ok(x)
+}
+
+#[test]
+fn test_dual() {
+    let x = Dual::new(2_f64, 1.);
+    assert_eq!((x * x).eps, 4.);
+    assert_eq!((x.powi(3) / x).eps, 4.);
+    assert_eq!(x.sin().eps, 2_f64.cos());
+    assert_eq!(x.exp().ln().eps, 1.);
+    assert_eq!(x.sqrt().eps, 0.5 / 2_f64.sqrt());
+    assert!((x.powf(Dual::constant(0.5)).eps - x.sqrt().eps).abs() < 1e-15);
+    assert_eq!((x % Dual::constant(1.5)).eps, 1.);
+    let mut y = x;
+    y -= Dual::new(1., 3.);
+    assert_eq!((y.re, y.eps), (1., -2.));
+    assert!(x > y && x == Dual::constant(2.));
+    assert_eq!(<Dual<f64> as NumCast>::from(3).unwrap().eps, 0.);
+}
+
+#[test]
+fn test_forward_mode() {
+    use crate::layers::dense::Dense;
+    use crate::layers::activation::Activation;
+    use crate::models::losses::Loss;
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([3]), &Shape::new([4]), Activation::Tanh));
+    nn.add(Dense::new(&Shape::new([4]), &Shape::new([2]), Activation::Sigmoid));
+    let fm = ForwardMode::new(&nn).unwrap();
+    let x = Tensor::new(&Shape::new([3]), vec![0.3, -0.7, 1.1]);
+    let jacobian = fm.jacobian(&x).unwrap();
+    assert_eq!(jacobian.get_shape(), &Shape::new([2, 3]));
+
+    // the columns are the central differences along each input
+    let h = 1e-6;
+    for i in 0..3 {
+        let mut step = Tensor::zeros(&Shape::new([3]));
+        step.flattened[i] = h;
+        let diff = (nn.predict(&(&x + &step)).unwrap() - nn.predict(&(&x - &step)).unwrap()) / (2. * h);
+        for j in 0..2 {
+            assert!((jacobian.get([j, i]) - diff.get([j])).abs() < 1e-8);
+        }
+    }
+    // the JVP is the Jacobian times the tangent
+    let v = Tensor::new(&Shape::new([3]), vec![1., 2., -1.]);
+    let (y, dy) = fm.jvp(&x, &v).unwrap();
+    assert_eq!(y, nn.predict(&x).unwrap());
+    for j in 0..2 {
+        let jv: f64 = (0..3).map(|i| jacobian.get([j, i]) * v.get([i])).sum();
+        assert!((dy.get([j]) - jv).abs() < 1e-12);
+    }
+    assert!(fm.jvp(&x, &Tensor::zeros(&Shape::new([2]))).is_err());
+
+    // a kernel convolved through the FFT over reals is convolved directly over duals
+    use crate::layers::conv::Conv2D;
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Conv2D::new(&Shape::new([1, 9, 9]), 1, (8, 8), (1, 1), Padding::Valid, Activation::Tanh));
+    let x = Tensor::new(&Shape::new([1, 9, 9]), (0..81).map(|i| (i as f64 * 0.37).sin()).collect());
+    let jacobian = ForwardMode::new(&nn).unwrap().jacobian(&x).unwrap();
+    let mut step = Tensor::zeros(&Shape::new([1, 9, 9]));
+    step.flattened[40] = h;
+    let diff = (nn.predict(&(&x + &step)).unwrap() - nn.predict(&(&x - &step)).unwrap()) / (2. * h);
+    for j in 0..4 {
+        assert!((jacobian.get([j, 40]) - diff.flattened[j]).abs() < 1e-7);
+    }
+}
+
+#[test]
+fn test_hvp() {
+    use crate::layers::dense::Dense;
+    use crate::layers::activation::Activation;
+    use crate::models::losses::Loss;
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([2]), &Shape::new([3]), Activation::Tanh));
+    nn.add(Dense::new(&Shape::new([3]), &Shape::new([1]), Activation::No));
+    let inputs: Vec<_> = (0..5).map(|i| Tensor::new(&Shape::new([2]), vec![i as f64 * 0.3 - 0.6, 0.5])).collect();
+    let truths: Vec<_> = (0..5).map(|i| Tensor::new(&Shape::new([1]), vec![(i as f64).sin()])).collect();
+    let v: ParamVec<f64> = nn.layers().iter().flat_map(|l| l.parameters().into_iter()
+        .map(|p| (0..p.len()).map(|i| (i as f64 * 0.7).cos()).collect::<Vec<_>>())).collect();
+
+    // the exact product against the difference of the gradients
+    let exact = hvp(&mut nn, &inputs, &truths, &v).unwrap();
+    let approx = hvp_finite_difference(&mut nn, &inputs, &truths, &v).unwrap();
+    for (e, a) in exact.concat().iter().zip(approx.concat()) {
+        assert!((e - a).abs() < 1e-6);
+    }
+    assert!(hvp(&mut nn, &inputs, &truths, &v[1..]).is_err());
+
+    // H v = lambda v for the top eigenvector
+    let (lambda, u) = top_eigen(&mut nn, &inputs, &truths, 200).unwrap();
+    let hu = hvp(&mut nn, &inputs, &truths, &u).unwrap();
+    for (h, x) in hu.concat().iter().zip(u.concat()) {
+        assert!((h - lambda * x).abs() < 1e-4 * lambda.abs().max(1.));
+    }
+
+    // (H + I) x = v
+    let x = solve_hessian(&mut nn, &inputs, &truths, &v, 1., 100).unwrap();
+    let mut hx = hvp(&mut nn, &inputs, &truths, &x).unwrap();
+    axpy(1., &x, &mut hx);
+    for (a, b) in hx.concat().iter().zip(v.concat()) {
+        assert!((a - b).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,283 @@
+//! The cache of downloaded artifacts, e.g. the pretrained weights of the model zoo
+//! or the files of datasets, each verified by its SHA-256.
+//!
+//! An `Artifact` names a file of the cache directory, the URL it is downloaded from
+//! and its SHA-256. `fetch` returns the path of the file, downloading it unless a
+//! verified copy is already cached. A download goes to `<name>.part` first and is
+//! resumed from there if interrupted, then renamed into place once verified.
+//! `file://` URLs are read directly. Other URLs are downloaded by the `curl` of the
+//! system, so no HTTP client is linked in.
+//!
+//! The cache directory is set by `set_cache_dir`, or by the `EASYNN_CACHE_DIR`
+//! environment variable, and defaults to `easynn` in the user cache directory.
+//! In offline mode, set by `set_offline` or `EASYNN_OFFLINE=1`, nothing is
+//! downloaded: a missing file is an error telling where to place it.
+//!
+//! ```rust,no_run
+//!     use easynn::cache::{ self, Artifact };
+//!     let weights = Artifact::new(
+//!         "mnist_mlp.eznn",
+//!         "https://example.com/mnist_mlp.eznn",
+//!         "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+//!     );
+//!     cache::set_cache_dir(Some(std::env::temp_dir().join("easynn_cache")));
+//!     let path = cache::fetch(&weights).unwrap();
+//! ```
+
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Error, ErrorKind, Read, Seek, SeekFrom };
+use std::path::{ Component, Path, PathBuf };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Mutex;
+
+static CACHE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set the cache directory, `None` to go back to the environment and the default
+pub fn set_cache_dir(dir: Option<PathBuf>) {
+    *CACHE_DIR.lock().unwrap() = dir;
+}
+
+/// The cache directory: the one set, else `$EASYNN_CACHE_DIR`, else `easynn` in
+/// `$XDG_CACHE_HOME` or `$HOME/.cache`, else in the temporary directory
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = CACHE_DIR.lock().unwrap().clone() {
+        return dir;
+    }
+    let var = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    var("EASYNN_CACHE_DIR")
+        .or_else(|| var("XDG_CACHE_HOME").map(|d| d.join("easynn")))
+        .or_else(|| var("HOME").map(|d| d.join(".cache").join("easynn")))
+        .unwrap_or_else(|| std::env::temp_dir().join("easynn"))
+}
+
+/// Turn offline mode on or off, `EASYNN_OFFLINE=1` turns it on as well
+pub fn set_offline(on: bool) {
+    OFFLINE.store(on, Ordering::Relaxed);
+}
+
+/// Check whether downloads are disabled; `file://` URLs are still read
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed) || std::env::var("EASYNN_OFFLINE").is_ok_and(|v| !v.is_empty() && v != "0")
+}
+
+/// The SHA-256 digest of the data
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    // The data, a one bit, zeros up to 56 bytes modulo 64, and the length in bits
+    let mut msg = data.to_vec();
+    msg.push(0x80);
+    while msg.len() % 64 != 56 {
+        msg.push(0);
+    }
+    msg.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_be_bytes());
+    for block in msg.chunks(64) {
+        let mut w = [0_u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh.wrapping_add(s1).wrapping_add(ch).wrapping_add(K[i]).wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+    let mut digest = [0; 32];
+    for (i, x) in h.iter().enumerate() {
+        digest[4 * i..4 * i + 4].copy_from_slice(&x.to_be_bytes());
+    }
+    digest
+}
+
+/// The SHA-256 of the data in lowercase hexadecimal
+pub fn sha256_hex(data: &[u8]) -> String {
+    sha256(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A file of the cache, downloaded from the URL and verified by its SHA-256
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artifact {
+    /// The file name in the cache directory, a plain name without separators
+    pub name: String,
+    pub url: String,
+    /// The SHA-256 of the file, in hexadecimal
+    pub sha256: String,
+}
+
+impl Artifact {
+    pub fn new(name: &str, url: &str, sha256: &str) -> Self {
+        Artifact { name: name.to_string(), url: url.to_string(), sha256: sha256.to_string() }
+    }
+    /// Check that the name is a single file name, so that the file stays in the cache directory
+    pub fn check_name(&self) -> io::Result<()> {
+        let mut components = Path::new(&self.name).components();
+        let plain = matches!((components.next(), components.next()), (Some(Component::Normal(n)), None) if n == self.name.as_str());
+        if !plain || self.name.contains(['/', '\\', '\0']) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("{:?} is not a plain file name", self.name)));
+        }
+        Ok(())
+    }
+    /// The path of the file in the cache directory
+    pub fn path_in(&self, dir: &Path) -> PathBuf {
+        dir.join(&self.name)
+    }
+    /// Check that the data is the file of the artifact
+    pub fn verify(&self, data: &[u8]) -> io::Result<()> {
+        let hash = sha256_hex(data);
+        if !hash.eq_ignore_ascii_case(&self.sha256) {
+            let message = format!("the SHA-256 of {} from {} is {}, not the expected {}", self.name, self.url, hash, self.sha256);
+            return Err(Error::new(ErrorKind::InvalidData, message));
+        }
+        Ok(())
+    }
+    /// What to do when the file cannot be downloaded
+    fn placement_hint(&self, dir: &Path) -> String {
+        format!("download {} and place it at {} (SHA-256 {})", self.url, self.path_in(dir).display(), self.sha256)
+    }
+}
+
+/// Append the rest of the URL content to the partial file, after its current length
+fn download(url: &str, partial: &Path) -> io::Result<()> {
+    if let Some(source) = url.strip_prefix("file://") {
+        let mut source = File::open(source)?;
+        let mut out = OpenOptions::new().create(true).append(true).open(partial)?;
+        let done = out.metadata()?.len();
+        if done > source.metadata()?.len() {
+            out.set_len(0)?;
+        } else {
+            source.seek(SeekFrom::Start(done))?;
+        }
+        io::copy(&mut source, &mut out)?;
+        return Ok(());
+    }
+    // `-C -` continues from the length of the output file
+    let output = std::process::Command::new("curl").arg("-fsSL").arg("-C").arg("-").arg("-o").arg(partial).arg(url).output()
+        .map_err(|e| Error::new(e.kind(), format!("cannot run curl: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::other(format!("curl failed: {}", String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    Ok(())
+}
+
+/// The path of the verified artifact in the cache directory, see the module
+pub fn fetch(artifact: &Artifact) -> io::Result<PathBuf> {
+    fetch_in(artifact, &cache_dir())
+}
+
+/// The path of the verified artifact in the given directory, see the module
+pub fn fetch_in(artifact: &Artifact, dir: &Path) -> io::Result<PathBuf> {
+    artifact.check_name()?;
+    let path = artifact.path_in(dir);
+    if let Ok(data) = fs::read(&path) {
+        if artifact.verify(&data).is_ok() {
+            return Ok(path);
+        }
+        fs::remove_file(&path)?;
+    }
+    if is_offline() && !artifact.url.starts_with("file://") {
+        let message = format!("{} is not cached and easynn is offline: {}", artifact.name, artifact.placement_hint(dir));
+        return Err(Error::new(ErrorKind::NotFound, message));
+    }
+    fs::create_dir_all(dir)?;
+    let partial = dir.join(format!("{}.part", artifact.name));
+    download(&artifact.url, &partial)
+        .map_err(|e| Error::new(e.kind(), format!("downloading {} failed: {}; {}", artifact.name, e, artifact.placement_hint(dir))))?;
+    let mut data = Vec::new();
+    File::open(&partial)?.read_to_end(&mut data)?;
+    if let Err(e) = artifact.verify(&data) {
+        // A corrupt download is not resumed
+        fs::remove_file(&partial)?;
+        return Err(e);
+    }
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+#[test]
+fn test_sha256() {
+    assert_eq!(sha256_hex(b""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(sha256_hex(b"abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    let long = sha256_hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq");
+    assert_eq!(long, "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1");
+}
+
+#[test]
+fn test_cache_fetch() {
+    let dir = std::env::temp_dir().join(format!("easynn_cache_{}", std::process::id()));
+    let (source, cache) = (dir.join("source.bin"), dir.join("cache"));
+    fs::create_dir_all(&cache).unwrap();
+    let content: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    fs::write(&source, &content).unwrap();
+    let artifact = Artifact::new("data.bin", &format!("file://{}", source.display()), &sha256_hex(&content));
+
+    // an interrupted download is resumed from its partial file
+    fs::write(cache.join("data.bin.part"), &content[..400]).unwrap();
+    let path = fetch_in(&artifact, &cache).unwrap();
+    assert_eq!(fs::read(&path).unwrap(), content);
+    assert!(!cache.join("data.bin.part").exists());
+    // a cached file is not downloaded again, a corrupted one is, which fails without the source
+    fs::remove_file(&source).unwrap();
+    assert_eq!(fetch_in(&artifact, &cache).unwrap(), path);
+    fs::write(&path, b"corrupted").unwrap();
+    let e = fetch_in(&artifact, &cache).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    assert!(e.to_string().contains(&path.display().to_string()));
+
+    // a corrupted partial file fails the check and is dropped
+    fs::write(&source, &content).unwrap();
+    fs::write(cache.join("data.bin.part"), b"garbage").unwrap();
+    assert_eq!(fetch_in(&artifact, &cache).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert!(!cache.join("data.bin.part").exists());
+    assert_eq!(fs::read(fetch_in(&artifact, &cache).unwrap()).unwrap(), content);
+
+    // offline, a remote artifact says where to place it
+    let remote = Artifact::new("remote.bin", "https://example.com/remote.bin", &sha256_hex(&content));
+    set_offline(true);
+    let e = fetch_in(&remote, &cache).unwrap_err();
+    set_offline(false);
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+    assert!(e.to_string().contains(&cache.join("remote.bin").display().to_string()));
+    fs::copy(&source, cache.join("remote.bin")).unwrap();
+    assert_eq!(fetch_in(&remote, &cache).unwrap(), cache.join("remote.bin"));
+
+    // the names that would leave the cache directory are rejected
+    for name in ["", ".", "..", "../data.bin", "/tmp/data.bin", "sub/data.bin", "sub\\data.bin"] {
+        let outside = Artifact::new(name, &format!("file://{}", source.display()), &sha256_hex(&content));
+        assert_eq!(fetch_in(&outside, &cache).unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+
+    set_cache_dir(Some(cache.clone()));
+    assert_eq!(cache_dir(), cache);
+    set_cache_dir(None);
+    fs::remove_dir_all(&dir).unwrap();
+}
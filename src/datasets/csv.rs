@@ -0,0 +1,198 @@
+//! Reading the features and the labels of the samples of a CSV file, one sample per row.
+//!
+//! The label columns are taken apart, any other column is a feature. A label column of
+//! any value that is not a number is categorical: its values are replaced by their index
+//! among the sorted distinct values, e.g. to be one-hot encoded by `one_hot`.
+//! Fields may be quoted with `"`, a quote inside being doubled; blank lines are skipped.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::{ read_csv, CsvOptions, one_hot };
+//!     let text = "length,width,species\n5.1,3.5,setosa\n7.0,3.2,versicolor\n";
+//!     let data = read_csv::<f64, _>(text.as_bytes(), &CsvOptions::default().label_columns(&[2])).unwrap();
+//!     assert_eq!(data.features[1].as_slice(), [7.0, 3.2]);
+//!     assert_eq!(data.categories[0], ["setosa", "versicolor"]);
+//!     let labels: Vec<f64> = data.labels.iter().map(|l| l.as_slice()[0]).collect();
+//!     assert_eq!(one_hot(&labels, 2).unwrap()[1].as_slice(), [0., 1.]);
+//! ```
+
+use crate::tensor::*;
+
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{ BufRead, BufReader, Error, ErrorKind, Read, Result };
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvOptions {
+    /// ',' by default
+    pub delimiter: char,
+    /// Whether the first row names the columns, true by default
+    pub header: bool,
+    /// The indices of the label columns, none by default
+    pub label_columns: Vec<usize>,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions { delimiter: ',', header: true, label_columns: Vec::new() }
+    }
+}
+
+impl CsvOptions {
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+    pub fn label_columns(mut self, columns: &[usize]) -> Self {
+        self.label_columns = columns.to_vec();
+        self
+    }
+}
+
+/// The samples of a CSV file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvData<T: NumT> {
+    /// The names of the feature columns, then of the label columns, if there is a header
+    pub columns: Option<Vec<String>>,
+    /// A `[features]` tensor per row
+    pub features: Vec<Tensor<T>>,
+    /// A `[label columns]` tensor per row, of no element without label columns
+    pub labels: Vec<Tensor<T>>,
+    /// For each label column, the values of the categories by their index, empty if numeric
+    pub categories: Vec<Vec<String>>,
+}
+
+fn invalid(line: usize, msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line, msg))
+}
+
+/// The fields of a row
+fn split_row(row: &str, delimiter: char, line: usize) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = row.trim_end_matches(['\r', '\n']).chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            c if c == delimiter && !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(invalid(line, "a quote is not closed".to_string()));
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// Read the samples of a CSV file
+pub fn read_csv<T: NumT, R: Read>(reader: R, options: &CsvOptions) -> Result<CsvData<T>> {
+    let mut columns = None;
+    let mut rows = Vec::new();
+    for (i, row) in BufReader::new(reader).lines().enumerate() {
+        let row = row?;
+        if row.trim().is_empty() {
+            continue;
+        }
+        let fields = split_row(&row, options.delimiter, i + 1)?;
+        if options.header && columns.is_none() {
+            columns = Some(fields);
+            continue;
+        }
+        rows.push((i + 1, fields));
+    }
+    let width = columns.as_ref().or(rows.first().map(|(_, f)| f)).map_or(0, |f| f.len());
+    for c in options.label_columns.iter() {
+        if *c >= width {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("the label column {} is not below {}", c, width)));
+        }
+    }
+    let is_label = |c: usize| options.label_columns.contains(&c);
+    let parse = |s: &str| s.trim().parse::<f64>().ok().and_then(T::from);
+
+    // the categorical label columns, by their sorted distinct values
+    let categories: Vec<Vec<String>> = options.label_columns.iter().map(|c| {
+        let values: Vec<&str> = rows.iter().filter_map(|(_, f)| f.get(*c).map(|s| s.trim())).collect();
+        if values.iter().all(|s| parse(s).is_some()) {
+            Vec::new()
+        } else {
+            values.into_iter().map(String::from).collect::<BTreeSet<_>>().into_iter().collect()
+        }
+    }).collect();
+
+    let (mut features, mut labels) = (Vec::with_capacity(rows.len()), Vec::with_capacity(rows.len()));
+    for (line, fields) in rows {
+        if fields.len() != width {
+            return Err(invalid(line, format!("{} fields, {} expected", fields.len(), width)));
+        }
+        let mut x = Vec::with_capacity(width - options.label_columns.len());
+        for (c, s) in fields.iter().enumerate().filter(|(c, _)| !is_label(*c)) {
+            x.push(parse(s).ok_or_else(|| invalid(line, format!("the feature {:?} of column {} is not a number", s, c)))?);
+        }
+        let y = options.label_columns.iter().zip(categories.iter()).map(|(c, names)| {
+            let s = fields[*c].trim();
+            match names.is_empty() {
+                true => parse(s).unwrap(),
+                false => T::from(names.binary_search_by(|n| n.as_str().cmp(s)).unwrap()).unwrap(),
+            }
+        }).collect::<Vec<T>>();
+        features.push(Tensor::new(&Shape::new([x.len()]), x));
+        labels.push(Tensor::new(&Shape::new([y.len()]), y));
+    }
+    let columns = columns.map(|names| {
+        let mut ordered: Vec<String> = names.iter().enumerate().filter(|(c, _)| !is_label(*c)).map(|(_, n)| n.trim().to_string()).collect();
+        ordered.extend(options.label_columns.iter().map(|c| names[*c].trim().to_string()));
+        ordered
+    });
+    Ok(CsvData { columns, features, labels, categories })
+}
+
+/// Read the samples of the CSV file at the path
+pub fn load_csv<T: NumT, P: AsRef<Path>>(path: P, options: &CsvOptions) -> Result<CsvData<T>> {
+    read_csv(File::open(path)?, options)
+}
+
+#[test]
+fn test_csv() {
+    let text = "a;\"b;c\";label\n1;2.5;3\n\n-1;\"1e3\";0\r\n";
+    let options = CsvOptions::default().delimiter(';').label_columns(&[2]);
+    let data = read_csv::<f64, _>(text.as_bytes(), &options).unwrap();
+    assert_eq!(data.columns.unwrap(), ["a", "b;c", "label"]);
+    assert_eq!(data.features[1].as_slice(), [-1., 1000.]);
+    assert_eq!(data.labels.iter().map(|l| l.as_slice()[0]).collect::<Vec<_>>(), [3., 0.]);
+    assert_eq!(data.categories, [Vec::<String>::new()]);
+
+    // no header, the labels of the first column categorical
+    let text = "cat,1,2\ndog,3,4\n\"cat\",5,\"6\"\n";
+    let data = read_csv::<f32, _>(text.as_bytes(), &CsvOptions::default().header(false).label_columns(&[0])).unwrap();
+    assert_eq!(data.columns, None);
+    assert_eq!(data.features[2].as_slice(), [5., 6.]);
+    assert_eq!(data.labels.iter().map(|l| l.as_slice()[0]).collect::<Vec<_>>(), [0., 1., 0.]);
+    assert_eq!(data.categories[0], ["cat", "dog"]);
+    // without labels, every column is a feature
+    let data = read_csv::<f32, _>("1,2\n".as_bytes(), &CsvOptions::default().header(false)).unwrap();
+    assert_eq!((data.features[0].as_slice(), data.labels[0].as_slice().len()), (&[1., 2.][..], 0));
+
+    for (text, options) in [
+        ("a,b\n1,2\n3\n", CsvOptions::default()),
+        ("a,b\n1,x\n", CsvOptions::default()),
+        ("a,b\n1,\"2\n", CsvOptions::default()),
+        ("a,b\n1,2\n", CsvOptions::default().label_columns(&[2])),
+    ] {
+        assert!(read_csv::<f32, _>(text.as_bytes(), &options).is_err());
+    }
+}
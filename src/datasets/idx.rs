@@ -0,0 +1,152 @@
+//! Reading the IDX files of MNIST and Fashion-MNIST.
+//!
+//! An IDX file is two zero bytes, the type of the elements, the count of dims, the dims as
+//! big-endian `u32` and the elements in big-endian. The files must be decompressed first,
+//! e.g. `gunzip train-images-idx3-ubyte.gz`.
+//!
+//! `load_mnist` reads the four files of a directory into images of `[28, 28]` scaled
+//! into `[0, 1]` and one-hot labels of `[10]`, ready for `Sequential::train_once`.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::read_idx;
+//!     // two 2x2 images of unsigned bytes
+//!     let bytes = [0u8, 0, 0x08, 3, 0, 0, 0, 2, 0, 0, 0, 2, 0, 0, 0, 2, 0, 255, 1, 2, 3, 4, 5, 6];
+//!     let images = read_idx::<f32, _>(&bytes[..]).unwrap();
+//!     assert_eq!(images.get_shape(), sh!([2, 2, 2]));
+//!     assert_eq!(images.get([0, 0, 1]), 255.);
+//! ```
+
+use crate::tensor::*;
+use crate::datasets::preprocess::one_hot;
+
+use std::fs::File;
+use std::io::{ BufReader, Error, ErrorKind, Read, Result };
+use std::path::Path;
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
+
+/// Read an IDX file into a tensor of its dims
+pub fn read_idx<T: NumT, R: Read>(mut reader: R) -> Result<Tensor<T>> {
+    let mut header = [0u8; 4];
+    reader.read_exact(&mut header)?;
+    if header[0] != 0 || header[1] != 0 {
+        return Err(invalid("not an IDX file"));
+    }
+    let width = match header[2] {
+        0x08 | 0x09 => 1,
+        0x0B => 2,
+        0x0C | 0x0D => 4,
+        0x0E => 8,
+        t => return Err(invalid(format!("unknown IDX element type {:#04x}", t))),
+    };
+    let mut dims = Vec::with_capacity(header[3] as usize);
+    for _ in 0..header[3] {
+        let mut dim = [0u8; 4];
+        reader.read_exact(&mut dim)?;
+        dims.push(u32::from_be_bytes(dim) as usize);
+    }
+    let shape = Shape::from_slice(&dims);
+    let len = dims.iter().try_fold(1_usize, |n, d| n.checked_mul(*d)).ok_or_else(|| invalid("the IDX dims overflow"))?;
+    let bytes = len.checked_mul(width).ok_or_else(|| invalid("the IDX dims overflow"))?;
+    check_allocation::<T>(len).map_err(|e| invalid(e.to_string()))?;
+    let mut data = Vec::new();
+    reader.take(bytes as u64).read_to_end(&mut data)?;
+    if data.len() != bytes {
+        return Err(invalid(format!("the IDX file has {} bytes of data, {} expected", data.len(), bytes)));
+    }
+    let flattened = data.chunks(width).map(|b| {
+        let x = match header[2] {
+            0x08 => b[0] as f64,
+            0x09 => b[0] as i8 as f64,
+            0x0B => i16::from_be_bytes([b[0], b[1]]) as f64,
+            0x0C => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            0x0D => f32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            _ => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]),
+        };
+        T::from(x).unwrap()
+    }).collect();
+    Ok(Tensor::new(&shape, flattened))
+}
+
+/// Read the IDX file at the path
+pub fn load_idx<T: NumT, P: AsRef<Path>>(path: P) -> Result<Tensor<T>> {
+    read_idx(BufReader::new(File::open(path)?))
+}
+
+/// A split of MNIST, images of `[rows, columns]` in `[0, 1]` and one-hot labels of `[10]`
+#[derive(Debug, Clone)]
+pub struct MnistSplit<T: NumT> {
+    pub images: Vec<Tensor<T>>,
+    pub labels: Vec<Tensor<T>>,
+}
+
+/// The training and the test splits of MNIST
+#[derive(Debug, Clone)]
+pub struct Mnist<T: NumT> {
+    pub train: MnistSplit<T>,
+    pub test: MnistSplit<T>,
+}
+
+/// Read the images and the labels IDX files of a split of MNIST
+pub fn load_mnist_split<T: NumT, P: AsRef<Path>, Q: AsRef<Path>>(images: P, labels: Q) -> Result<MnistSplit<T>> {
+    let images = load_idx::<T, _>(images)?;
+    let labels = load_idx::<T, _>(labels)?;
+    if images.shape.rank() != 3 || labels.shape.rank() != 1 || images.shape[0] != labels.shape[0] {
+        return Err(invalid(format!("the images {:?} do not match the labels {:?}", images.shape.dims(), labels.shape.dims())));
+    }
+    let scale = T::from(255).unwrap();
+    let images = images.unstack().into_iter().map(|im| im.map(|x| x / scale)).collect();
+    let labels = one_hot(&labels.flattened, 10).map_err(invalid)?;
+    Ok(MnistSplit { images, labels })
+}
+
+/// Read the four files of MNIST, or of Fashion-MNIST, from the directory:
+/// `train-images-idx3-ubyte`, `train-labels-idx1-ubyte`, `t10k-images-idx3-ubyte` and `t10k-labels-idx1-ubyte`
+pub fn load_mnist<T: NumT, P: AsRef<Path>>(dir: P) -> Result<Mnist<T>> {
+    let dir = dir.as_ref();
+    Ok(Mnist {
+        train: load_mnist_split(dir.join("train-images-idx3-ubyte"), dir.join("train-labels-idx1-ubyte"))?,
+        test: load_mnist_split(dir.join("t10k-images-idx3-ubyte"), dir.join("t10k-labels-idx1-ubyte"))?,
+    })
+}
+
+#[test]
+fn test_idx() {
+    // big-endian i16 and f64
+    let shorts = [0u8, 0, 0x0B, 1, 0, 0, 0, 2, 0xff, 0xfe, 0x01, 0x00];
+    assert_eq!(read_idx::<f64, _>(&shorts[..]).unwrap().as_slice(), [-2., 256.]);
+    let mut doubles = vec![0u8, 0, 0x0E, 1, 0, 0, 0, 1];
+    doubles.extend(1.5_f64.to_be_bytes());
+    assert_eq!(read_idx::<f64, _>(&doubles[..]).unwrap().as_slice(), [1.5]);
+    assert!(read_idx::<f64, _>(&shorts[..11]).is_err());
+    assert!(read_idx::<f64, _>(&[1u8, 0, 0x08, 0][..]).is_err());
+    assert!(read_idx::<f64, _>(&[0u8, 0, 0x07, 0][..]).is_err());
+    // 2^63 doubles overflow the byte count
+    assert!(read_idx::<f64, _>(&[0u8, 0, 0x0E, 3, 0x80, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 2][..]).is_err());
+
+    let dir = std::env::temp_dir().join(format!("easynn_mnist_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let images = |n: u8| {
+        let mut b = vec![0u8, 0, 0x08, 3, 0, 0, 0, n, 0, 0, 0, 2, 0, 0, 0, 2];
+        b.extend((0..n * 4).map(|x| x * 17));
+        b
+    };
+    let labels = |l: &[u8]| [&[0u8, 0, 0x08, 1, 0, 0, 0, l.len() as u8][..], l].concat();
+    std::fs::write(dir.join("train-images-idx3-ubyte"), images(3)).unwrap();
+    std::fs::write(dir.join("train-labels-idx1-ubyte"), labels(&[5, 0, 9])).unwrap();
+    std::fs::write(dir.join("t10k-images-idx3-ubyte"), images(1)).unwrap();
+    std::fs::write(dir.join("t10k-labels-idx1-ubyte"), labels(&[3])).unwrap();
+    let mnist = load_mnist::<f32, _>(&dir).unwrap();
+    assert_eq!((mnist.train.images.len(), mnist.test.labels.len()), (3, 1));
+    assert_eq!(mnist.train.images[1].get_shape(), &Shape::new([2, 2]));
+    assert_eq!(mnist.train.images[2].as_slice(), [136. / 255., 153. / 255., 170. / 255., 187. / 255.]);
+    assert_eq!(mnist.train.labels[0].get([5]), 1.);
+    assert_eq!(mnist.test.labels[0].as_slice().iter().sum::<f32>(), 1.);
+    // the labels of another count
+    std::fs::write(dir.join("t10k-labels-idx1-ubyte"), labels(&[3, 4])).unwrap();
+    assert!(load_mnist::<f32, _>(&dir).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
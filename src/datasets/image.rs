@@ -0,0 +1,141 @@
+//! Images tagged with their memory layout, so that a `[height, width, channels]` image,
+//! as most decoders produce, is never fed silently to layers expecting `[channels, height, width]`.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::{ Images, Layout };
+//!     // two 2x2 RGB images as decoded, pixels of interleaved channels
+//!     let decoded = vec![Tensor::<f32>::new(sh!([2, 2, 3]), (0..12).map(|x| x as f32).collect()); 2];
+//!     let images = Images::new(decoded, Layout::Hwc).unwrap();
+//!     let conv = Conv2D::<f32>::new(sh!([3, 2, 2]), 4, (2, 2), (1, 1), Padding::Valid, Activation::Relu);
+//!     // converted to the layout of the layer, and checked against its input shape
+//!     let inputs = images.for_input(&sh!([3, 2, 2])).unwrap();
+//!     assert_eq!(inputs[0].get([1, 0, 0]), 1.);
+//!     assert!(images.for_input(&sh!([2, 2, 3])).is_err());
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// The order of the dimensions of an image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Layout {
+    /// `[channels, height, width]`, what `Conv2D` and the pooling layers take
+    Chw,
+    /// `[height, width, channels]`, interleaved channels as decoded from image files
+    Hwc,
+}
+
+/// Move the dims `[a, b, c]` of a rank-3 tensor into `[c, a, b]` if `to_front`, else `[b, c, a]`
+fn rotate<T: NumT>(image: &Tensor<T>, to_front: bool) -> Result<Tensor<T>> {
+    if image.shape.rank() != 3 {
+        return Err(EasynnError::invalid("rotate", format!("the image {:?} is not of rank 3", image.shape.dims())));
+    }
+    let (a, b, c) = (image.shape[0], image.shape[1], image.shape[2]);
+    let mut out = Vec::with_capacity(image.flattened.len());
+    if to_front {
+        for k in 0..c {
+            out.extend((0..a * b).map(|ij| image.flattened[ij * c + k]));
+        }
+        Ok(Tensor::new(&Shape::new([c, a, b]), out))
+    } else {
+        for jk in 0..b * c {
+            out.extend((0..a).map(|i| image.flattened[i * b * c + jk]));
+        }
+        Ok(Tensor::new(&Shape::new([b, c, a]), out))
+    }
+}
+
+/// `[height, width, channels]` into `[channels, height, width]`
+pub fn hwc_to_chw<T: NumT>(image: &Tensor<T>) -> Result<Tensor<T>> {
+    rotate(image, true)
+}
+
+/// `[channels, height, width]` into `[height, width, channels]`
+pub fn chw_to_hwc<T: NumT>(image: &Tensor<T>) -> Result<Tensor<T>> {
+    rotate(image, false)
+}
+
+/// Images of the same shape and a known layout
+#[derive(Debug, Clone)]
+pub struct Images<T: NumT> {
+    images: Vec<Tensor<T>>,
+    layout: Layout,
+}
+
+impl<T: NumT> Images<T> {
+    /// The images must be rank 3 and of the same shape
+    pub fn new(images: Vec<Tensor<T>>, layout: Layout) -> Result<Self> {
+        if let Some(first) = images.first() {
+            if first.shape.rank() != 3 {
+                return Err(EasynnError::invalid("Images::new", format!("the image {:?} is not of rank 3", first.shape.dims())));
+            }
+            for i in &images {
+                check_shape("Images::new", &first.shape, &i.shape)?;
+            }
+        }
+        Ok(Images { images, layout })
+    }
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+    pub fn images(&self) -> &[Tensor<T>] {
+        &self.images
+    }
+    pub fn len(&self) -> usize {
+        self.images.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.images.is_empty()
+    }
+    /// The `(channels, height, width)` whatever the layout
+    pub fn dims(&self) -> Option<(usize, usize, usize)> {
+        let s = &self.images.first()?.shape;
+        Some(match self.layout {
+            Layout::Chw => (s[0], s[1], s[2]),
+            Layout::Hwc => (s[2], s[0], s[1]),
+        })
+    }
+    /// The images converted into the layout
+    pub fn to_layout(&self, layout: Layout) -> Self {
+        let images = match (self.layout, layout) {
+            (Layout::Hwc, Layout::Chw) => self.images.iter().map(|i| hwc_to_chw(i).unwrap()).collect(),
+            (Layout::Chw, Layout::Hwc) => self.images.iter().map(|i| chw_to_hwc(i).unwrap()).collect(),
+            _ => self.images.clone(),
+        };
+        Images { images, layout }
+    }
+    /// Apply a transform keeping the layout, e.g. normalizing or augmenting
+    pub fn map<F: Fn(&Tensor<T>) -> Tensor<T>>(&self, f: F) -> Result<Self> {
+        Images::new(self.images.iter().map(f).collect(), self.layout)
+    }
+    /// The images in the `[channels, height, width]` layout of the layers,
+    /// failing unless they match the input shape of the model
+    pub fn for_input(&self, input_shape: &Shape) -> Result<Vec<Tensor<T>>> {
+        let chw = self.to_layout(Layout::Chw);
+        for i in &chw.images {
+            check_shape("for_input", input_shape, &i.shape)?;
+        }
+        Ok(chw.images)
+    }
+}
+
+#[test]
+fn test_image_layout() {
+    // a 2x3 image of 2 channels, the channel value is 10 * c + the pixel index
+    let hwc = Tensor::<f64>::new(&Shape::new([2, 3, 2]), (0..6).flat_map(|p| [p as f64, 10. + p as f64]).collect());
+    let chw = hwc_to_chw(&hwc).unwrap();
+    assert_eq!(chw, Tensor::new(&Shape::new([2, 2, 3]), vec![0., 1., 2., 3., 4., 5., 10., 11., 12., 13., 14., 15.]));
+    assert_eq!(chw_to_hwc(&chw).unwrap(), hwc);
+    assert!(hwc_to_chw(&Tensor::<f64>::zeros(&Shape::new([4, 4]))).is_err());
+
+    let images = Images::new(vec![hwc.clone(), hwc.clone()], Layout::Hwc).unwrap();
+    assert_eq!(images.dims(), Some((2, 2, 3)));
+    let converted = images.to_layout(Layout::Chw);
+    assert_eq!(converted.layout(), Layout::Chw);
+    assert_eq!(converted.dims(), Some((2, 2, 3)));
+    assert_eq!(converted.images()[1], chw);
+    assert_eq!(converted.to_layout(Layout::Hwc).images()[0], hwc);
+    assert_eq!(images.map(|i| i.map(|x| x / 10.)).unwrap().layout(), Layout::Hwc);
+    assert!(Images::new(vec![hwc, chw], Layout::Hwc).is_err());
+}
@@ -0,0 +1,264 @@
+//! A `DataLoader` iterating over the mini-batches of a dataset, epoch by epoch.
+//!
+//! Each epoch the order of the samples is shuffled, by a random generator seeded once so
+//! that a seeded loader gives the same epochs in every run. The batches are gathered on
+//! the calling thread, or with `prefetch` on a background thread up to that many batches
+//! ahead, so that loading, e.g. decoding or augmenting in `Dataset::get`, overlaps training.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::{ DataLoader, TensorDataset };
+//!     let inputs: Vec<_> = (0..10).map(|i| Tensor::new(sh!([1]), vec![i as f64])).collect();
+//!     let truths: Vec<_> = inputs.iter().map(|x| x.map(|v| 2. * v)).collect();
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+//!     let mut loader = DataLoader::new(TensorDataset::new(inputs, truths), 4).seed(42).prefetch(2);
+//!     assert_eq!(loader.len(), 3);
+//!     for _epoch in 0..2 {
+//!         for batch in loader.epoch() {
+//!             nn.train_once(&batch.inputs, &batch.truths, batch.len(), 0.001, false);
+//!         }
+//!     }
+//! ```
+
+use crate::tensor::*;
+use crate::datasets::windowed::WindowedDataset;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+
+use std::sync::Arc;
+use std::sync::mpsc::{ sync_channel, Receiver };
+use std::thread::JoinHandle;
+
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// Samples of (input, truth) pairs, loaded by index
+pub trait Dataset<T: NumT> {
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// The input and the truth of the sample
+    fn get(&self, index: usize) -> (Tensor<T>, Tensor<T>);
+}
+
+/// A dataset of samples in memory
+#[derive(Debug, Clone)]
+pub struct TensorDataset<T: NumT> {
+    pub inputs: Vec<Tensor<T>>,
+    pub truths: Vec<Tensor<T>>,
+}
+
+impl<T: NumT> TensorDataset<T> {
+    pub fn new(inputs: Vec<Tensor<T>>, truths: Vec<Tensor<T>>) -> Self {
+        assert_eq!(inputs.len(), truths.len(), "One truth per input is expected!");
+        TensorDataset { inputs, truths }
+    }
+}
+
+impl<T: NumT> Dataset<T> for TensorDataset<T> {
+    fn len(&self) -> usize {
+        self.inputs.len()
+    }
+    fn get(&self, index: usize) -> (Tensor<T>, Tensor<T>) {
+        (self.inputs[index].clone(), self.truths[index].clone())
+    }
+}
+
+impl<T: NumT> Dataset<T> for WindowedDataset<T> {
+    fn len(&self) -> usize {
+        WindowedDataset::len(self)
+    }
+    fn get(&self, index: usize) -> (Tensor<T>, Tensor<T>) {
+        WindowedDataset::get(self, index)
+    }
+}
+
+/// A mini-batch of samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct Batch<T: NumT> {
+    /// The indices of the samples in the dataset
+    pub indices: Vec<usize>,
+    pub inputs: Vec<Tensor<T>>,
+    pub truths: Vec<Tensor<T>>,
+}
+
+impl<T: NumT> Batch<T> {
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+    /// The inputs and the truths stacked along a leading batch dimension
+    pub fn stack(&self) -> Result<(Tensor<T>, Tensor<T>)> {
+        let (x, y) = match (self.inputs.first(), self.truths.first()) {
+            (Some(x), Some(y)) => (x.get_shape(), y.get_shape()),
+            _ => return Err(EasynnError::invalid("Batch::stack", "the batch is empty")),
+        };
+        Ok((Tensor::stack(x, &self.inputs)?, Tensor::stack(y, &self.truths)?))
+    }
+}
+
+fn gather_batch<T: NumT, D: Dataset<T>>(dataset: &D, indices: Vec<usize>) -> Batch<T> {
+    let (inputs, truths) = indices.iter().map(|i| dataset.get(*i)).unzip();
+    Batch { indices, inputs, truths }
+}
+
+pub struct DataLoader<T: NumT, D: Dataset<T>> {
+    dataset: Arc<D>,
+    pub batch_size: usize,
+    /// Whether the samples are shuffled each epoch, true by default
+    pub shuffle: bool,
+    /// Whether the last batch is dropped when smaller than the batch size, false by default
+    pub drop_last: bool,
+    /// The count of batches gathered ahead on a background thread, 0 (none) by default
+    pub prefetch: usize,
+    rng: StdRng,
+    _t: std::marker::PhantomData<T>,
+}
+
+impl<T: NumT, D: Dataset<T>> DataLoader<T, D> {
+    pub fn new(dataset: D, batch_size: usize) -> Self {
+        if batch_size == 0 {
+            panic!("DataLoader needs a batch size!");
+        }
+        DataLoader {
+            dataset: Arc::new(dataset),
+            batch_size,
+            shuffle: true,
+            drop_last: false,
+            prefetch: 0,
+            rng: StdRng::from_entropy(),
+            _t: std::marker::PhantomData,
+        }
+    }
+    /// Shuffle by a generator of the seed
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+    pub fn shuffle(mut self, shuffle: bool) -> Self {
+        self.shuffle = shuffle;
+        self
+    }
+    pub fn drop_last(mut self, drop_last: bool) -> Self {
+        self.drop_last = drop_last;
+        self
+    }
+    pub fn prefetch(mut self, batches: usize) -> Self {
+        self.prefetch = batches;
+        self
+    }
+    pub fn dataset(&self) -> &D {
+        &self.dataset
+    }
+    /// The count of batches of an epoch
+    pub fn len(&self) -> usize {
+        match self.drop_last {
+            true => self.dataset.len() / self.batch_size,
+            false => self.dataset.len().div_ceil(self.batch_size),
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The indices of the batches of the next epoch
+    fn epoch_indices(&mut self) -> Vec<Vec<usize>> {
+        let mut order: Vec<usize> = (0..self.dataset.len()).collect();
+        if self.shuffle {
+            order.shuffle(&mut self.rng);
+        }
+        let mut batches: Vec<Vec<usize>> = order.chunks(self.batch_size).map(|c| c.to_vec()).collect();
+        batches.truncate(self.len());
+        batches
+    }
+}
+
+impl<T: NumT, D: Dataset<T> + Send + Sync + 'static> DataLoader<T, D> {
+    /// The batches of the next epoch
+    pub fn epoch(&mut self) -> Batches<T, D> {
+        let indices = self.epoch_indices().into_iter();
+        if self.prefetch == 0 {
+            return Batches { dataset: self.dataset.clone(), indices, prefetched: None, worker: None };
+        }
+        let (tx, rx) = sync_channel(self.prefetch);
+        let dataset = self.dataset.clone();
+        let worker = std::thread::spawn(move || {
+            for batch in indices {
+                // the receiver is gone, the epoch was left early
+                if tx.send(gather_batch(&*dataset, batch)).is_err() {
+                    break;
+                }
+            }
+        });
+        Batches { dataset: self.dataset.clone(), indices: Vec::new().into_iter(), prefetched: Some(rx), worker: Some(worker) }
+    }
+}
+
+/// The iterator over the batches of an epoch
+pub struct Batches<T: NumT, D: Dataset<T>> {
+    dataset: Arc<D>,
+    indices: std::vec::IntoIter<Vec<usize>>,
+    prefetched: Option<Receiver<Batch<T>>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl<T: NumT, D: Dataset<T>> Iterator for Batches<T, D> {
+    type Item = Batch<T>;
+    fn next(&mut self) -> Option<Batch<T>> {
+        match &self.prefetched {
+            Some(rx) => rx.recv().ok(),
+            None => self.indices.next().map(|i| gather_batch(&*self.dataset, i)),
+        }
+    }
+}
+
+impl<T: NumT, D: Dataset<T>> Drop for Batches<T, D> {
+    fn drop(&mut self) {
+        // unblock the worker before waiting for it
+        self.prefetched.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[test]
+fn test_data_loader() {
+    let inputs: Vec<_> = (0..10).map(|i| Tensor::new(&Shape::new([1]), vec![i as f64])).collect();
+    let dataset = TensorDataset::new(inputs.clone(), inputs.iter().map(|x| x.map(|v| -v)).collect());
+    let sorted = |batches: &[Batch<f64>]| {
+        let mut all: Vec<usize> = batches.iter().flat_map(|b| b.indices.clone()).collect();
+        all.sort();
+        all
+    };
+
+    // in order, without shuffling
+    let mut loader = DataLoader::new(dataset.clone(), 4).shuffle(false);
+    let batches: Vec<_> = loader.epoch().collect();
+    assert_eq!(batches.iter().map(|b| b.len()).collect::<Vec<_>>(), [4, 4, 2]);
+    assert_eq!(batches[2].indices, [8, 9]);
+    assert_eq!(batches[1].truths[0].as_slice(), [-4.]);
+    let (x, y) = batches[0].stack().unwrap();
+    assert_eq!((x.get_shape(), y.as_slice()), (&Shape::new([4, 1]), &[-0., -1., -2., -3.][..]));
+
+    // shuffled every epoch, the same epochs for the same seed, with or without prefetching
+    let mut a = DataLoader::new(dataset.clone(), 3).seed(7).drop_last(true);
+    let mut b = DataLoader::new(dataset.clone(), 3).seed(7).drop_last(true).prefetch(1);
+    assert_eq!(a.len(), 3);
+    let (a1, a2): (Vec<_>, Vec<_>) = (a.epoch().collect(), a.epoch().collect());
+    let (b1, b2): (Vec<_>, Vec<_>) = (b.epoch().collect(), b.epoch().collect());
+    assert_eq!((&a1, &a2), (&b1, &b2));
+    assert_ne!(a1, a2);
+    assert_eq!(a1.len(), 3);
+    assert!(a1.iter().all(|batch| batch.inputs.iter().zip(batch.truths.iter()).zip(batch.indices.iter())
+        .all(|((x, y), i)| x.as_slice()[0] == *i as f64 && y.as_slice()[0] == -(*i as f64))));
+    let mut full = DataLoader::new(dataset, 3).prefetch(2);
+    assert_eq!(sorted(&full.epoch().collect::<Vec<_>>()), (0..10).collect::<Vec<_>>());
+    // leaving an epoch early stops the worker
+    assert!(full.epoch().next().is_some());
+}
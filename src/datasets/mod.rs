@@ -0,0 +1,19 @@
+//! The datasets module, adapting raw data into (input, truth) samples for training.
+//!
+
+pub mod windowed;
+pub use windowed::*;
+pub mod sampler;
+pub use sampler::*;
+pub mod image;
+pub use image::*;
+pub mod preprocess;
+pub use preprocess::*;
+pub mod idx;
+pub use idx::*;
+pub mod csv;
+pub use csv::*;
+pub mod loader;
+pub use loader::*;
+pub mod negative;
+pub use negative::*;
@@ -0,0 +1,126 @@
+//! Negative sampling for embedding training: `NegativeSampler` draws indices, e.g. tokens,
+//! by their frequency raised to a power, 0.75 in word2vec, so that frequent tokens are
+//! drawn more often but less than their share; and `skipgram_pairs` lists the (center,
+//! context) pairs of a token sequence within a window.
+//!
+//! ```rust
+//!     use easynn::datasets::{ NegativeSampler, skipgram_pairs };
+//!     let sampler = NegativeSampler::new(&[100, 10, 0, 1], 0.75);
+//!     let mut rng = rand::thread_rng();
+//!     let negatives = sampler.sample_excluding(&mut rng, 5, &[0]).unwrap();
+//!     assert!(negatives.iter().all(|i| *i == 1 || *i == 3));
+//!     assert_eq!(skipgram_pairs(&[7, 8, 9], 1), vec![(7, 8), (8, 7), (8, 9), (9, 8)]);
+//! ```
+
+use crate::tensor::EasynnError;
+
+use rand::Rng;
+
+#[derive(Debug, Clone)]
+pub struct NegativeSampler {
+    /// The cumulative weights of the indices
+    cumulative: Vec<f64>,
+}
+
+impl NegativeSampler {
+    /// The sampler of the indices by `counts[i]^power`, at least one count being positive
+    pub fn new(counts: &[usize], power: f64) -> Self {
+        let mut total = 0.;
+        let cumulative: Vec<f64> = counts.iter().map(|c| {
+            if *c > 0 {
+                total += (*c as f64).powf(power);
+            }
+            total
+        }).collect();
+        if total <= 0. || !total.is_finite() {
+            panic!("NegativeSampler needs a positive count!");
+        }
+        NegativeSampler { cumulative }
+    }
+    /// The sampler of the frequencies of the tokens below `vocab` in the sequence
+    pub fn from_tokens(tokens: &[usize], vocab: usize, power: f64) -> Self {
+        let mut counts = vec![0; vocab];
+        for t in tokens {
+            counts[*t] += 1;
+        }
+        Self::new(&counts, power)
+    }
+    pub fn len(&self) -> usize {
+        self.cumulative.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cumulative.is_empty()
+    }
+    /// The probability of drawing the index
+    pub fn probability(&self, index: usize) -> f64 {
+        let below = if index == 0 { 0. } else { self.cumulative[index - 1] };
+        (self.cumulative[index] - below) / self.cumulative[self.len() - 1]
+    }
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        let x = rng.gen::<f64>() * self.cumulative[self.len() - 1];
+        self.cumulative.partition_point(|c| *c <= x).min(self.len() - 1)
+    }
+    /// `k` draws other than the excluded indices, e.g. the center and the context,
+    /// failing if an excluded index is out of the sampler or if they hold all the weight
+    pub fn sample_excluding<R: Rng>(&self, rng: &mut R, k: usize, exclude: &[usize]) -> Result<Vec<usize>, EasynnError> {
+        if let Some(i) = exclude.iter().find(|i| **i >= self.len()) {
+            return Err(EasynnError::invalid("sample_excluding", format!("{} is not an index below {}", i, self.len())));
+        }
+        // some index left of positive weight, repeated exclusions counting once
+        if !(0..self.len()).any(|i| !exclude.contains(&i) && self.probability(i) > 0.) {
+            return Err(EasynnError::invalid("sample_excluding", "only the excluded indices can be drawn"));
+        }
+        let mut drawn = Vec::with_capacity(k);
+        while drawn.len() < k {
+            let i = self.sample(rng);
+            if !exclude.contains(&i) {
+                drawn.push(i);
+            }
+        }
+        Ok(drawn)
+    }
+}
+
+/// The (center, context) pairs of the tokens at most `window` apart, in the order of the centers
+pub fn skipgram_pairs(tokens: &[usize], window: usize) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for (i, center) in tokens.iter().enumerate() {
+        let end = (i + window + 1).min(tokens.len());
+        for (j, context) in tokens.iter().enumerate().take(end).skip(i.saturating_sub(window)) {
+            if j != i {
+                pairs.push((*center, *context));
+            }
+        }
+    }
+    pairs
+}
+
+#[test]
+fn test_negative_sampler() {
+    let sampler = NegativeSampler::new(&[16, 0, 1], 0.5);
+    assert_eq!(sampler.len(), 3);
+    assert_eq!(sampler.probability(0), 0.8);
+    assert_eq!(sampler.probability(1), 0.);
+    let mut rng = rand::thread_rng();
+    let draws: Vec<usize> = (0..5000).map(|_| sampler.sample(&mut rng)).collect();
+    assert!(!draws.contains(&1));
+    let share = draws.iter().filter(|i| **i == 0).count() as f64 / 5000.;
+    assert!((share - 0.8).abs() < 0.05);
+    assert_eq!(sampler.sample_excluding(&mut rng, 10, &[0]).unwrap(), vec![2; 10]);
+    // a repeated exclusion counts once, the indices out of the sampler fail
+    let even = NegativeSampler::new(&[1, 1], 1.);
+    assert_eq!(even.sample_excluding(&mut rng, 3, &[0, 0]).unwrap(), vec![1; 3]);
+    assert!(even.sample_excluding(&mut rng, 3, &[0, 1]).is_err());
+    assert!(even.sample_excluding(&mut rng, 3, &[0, 2]).is_err());
+
+    let from_tokens = NegativeSampler::from_tokens(&[0, 0, 2, 0], 3, 1.);
+    assert_eq!(from_tokens.probability(0), 0.75);
+
+    assert_eq!(skipgram_pairs(&[1, 2, 3, 4], 2), vec![
+        (1, 2), (1, 3),
+        (2, 1), (2, 3), (2, 4),
+        (3, 1), (3, 2), (3, 4),
+        (4, 2), (4, 3),
+    ]);
+    assert!(skipgram_pairs(&[1], 3).is_empty());
+}
@@ -0,0 +1,114 @@
+//! Preprocessing helpers of the loaded datasets: one-hot encoding of class labels and
+//! per-feature normalization fitted on the training samples.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::{ one_hot, Normalizer, Scaling };
+//!     let labels = one_hot(&[2_f64, 0.], 3).unwrap();
+//!     assert_eq!(labels[0].as_slice(), [0., 0., 1.]);
+//!     let train = vec![Tensor::<f64>::new(sh!([2]), vec![0., 10.]), Tensor::new(sh!([2]), vec![2., 30.])];
+//!     let norm = Normalizer::fit(&train, Scaling::MinMax).unwrap();
+//!     assert_eq!(norm.transform(&train[1]).unwrap().as_slice(), [1., 1.]);
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// The one-hot vectors of `[classes]` of the class indices
+pub fn one_hot<T: NumT>(labels: &[T], classes: usize) -> Result<Vec<Tensor<T>>> {
+    let shape = Shape::new([classes]);
+    labels.iter().map(|x| match x.to_usize() {
+        Some(c) if c < classes && x.fract() == T::zero() => {
+            let mut t = Tensor::zeros(&shape);
+            t.flattened[c] = T::one();
+            Ok(t)
+        }
+        _ => Err(EasynnError::invalid("one_hot", format!("{} is not a class below {}", x, classes))),
+    }).collect()
+}
+
+/// The class index of each one-hot, or score, vector, that of the highest element
+pub fn argmax_labels<T: NumT>(vectors: &[Tensor<T>]) -> Vec<usize> {
+    vectors.iter().map(|v| (1..v.flattened.len()).fold(0, |best, i| if v.flattened[i] > v.flattened[best] { i } else { best })).collect()
+}
+
+/// How each feature is normalized
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Scaling {
+    /// Subtract the mean and divide by the standard deviation
+    ZScore,
+    /// Map the minimum to 0 and the maximum to 1
+    MinMax,
+}
+
+/// A per-feature affine normalization `(x - shift) / scale`, fitted on some samples,
+/// e.g. the training set, and applied to any sample of their shape
+#[derive(Debug, Clone, PartialEq)]
+pub struct Normalizer<T: NumT> {
+    pub shape: Shape,
+    pub shift: Vec<T>,
+    pub scale: Vec<T>,
+}
+
+impl<T: NumT> Normalizer<T> {
+    /// Fit on the samples, of the same shape. A constant feature is only shifted.
+    pub fn fit(samples: &[Tensor<T>], scaling: Scaling) -> Result<Self> {
+        let first = samples.first().ok_or_else(|| EasynnError::invalid("Normalizer::fit", "there are no samples"))?;
+        for s in samples {
+            check_shape("Normalizer::fit", &first.shape, &s.shape)?;
+        }
+        let n = T::from(samples.len()).unwrap();
+        let feature = |i: usize| samples.iter().map(move |s| s.flattened[i]);
+        let (shift, scale): (Vec<T>, Vec<T>) = (0..first.flattened.len()).map(|i| match scaling {
+            Scaling::ZScore => {
+                let mean = feature(i).sum::<T>() / n;
+                let var = feature(i).map(|x| (x - mean) * (x - mean)).sum::<T>() / n;
+                (mean, var.sqrt())
+            }
+            Scaling::MinMax => {
+                let min = feature(i).fold(T::infinity(), T::min);
+                let max = feature(i).fold(T::neg_infinity(), T::max);
+                (min, max - min)
+            }
+        }).map(|(shift, scale)| (shift, if scale > T::zero() { scale } else { T::one() })).unzip();
+        Ok(Normalizer { shape: first.shape.clone(), shift, scale })
+    }
+    pub fn transform(&self, sample: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("Normalizer::transform", &self.shape, &sample.shape)?;
+        let data = sample.flattened.iter().zip(self.shift.iter().zip(self.scale.iter())).map(|(x, (a, b))| (*x - *a) / *b).collect();
+        Ok(Tensor::new(&self.shape, data))
+    }
+    pub fn transform_all(&self, samples: &[Tensor<T>]) -> Result<Vec<Tensor<T>>> {
+        samples.iter().map(|s| self.transform(s)).collect()
+    }
+    /// Map a normalized sample back, e.g. a predicted regression target
+    pub fn inverse(&self, sample: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("Normalizer::inverse", &self.shape, &sample.shape)?;
+        let data = sample.flattened.iter().zip(self.shift.iter().zip(self.scale.iter())).map(|(x, (a, b))| *x * *b + *a).collect();
+        Ok(Tensor::new(&self.shape, data))
+    }
+}
+
+#[test]
+fn test_preprocess() {
+    let labels = one_hot(&[1_f32, 0., 1.], 2).unwrap();
+    assert_eq!(labels.iter().map(|t| t.as_slice().to_vec()).collect::<Vec<_>>(), [[0., 1.], [1., 0.], [0., 1.]]);
+    assert_eq!(argmax_labels(&labels), [1, 0, 1]);
+    for bad in [2., -1., 0.5] {
+        assert!(one_hot(&[bad], 2).is_err());
+    }
+
+    let samples: Vec<_> = [[1., 5.], [3., 5.], [5., 5.]].iter().map(|s| Tensor::new(&Shape::new([2]), s.to_vec())).collect();
+    let z = Normalizer::fit(&samples, Scaling::ZScore).unwrap();
+    let normalized = z.transform_all(&samples).unwrap();
+    let std = (8_f64 / 3.).sqrt();
+    assert!((normalized[0].as_slice()[0] + 2. / std).abs() < 1e-12);
+    // the constant feature is only shifted
+    assert_eq!(normalized[2].as_slice()[1], 0.);
+    let back = z.inverse(&normalized[0]).unwrap();
+    assert!(back.as_slice().iter().zip(samples[0].as_slice()).all(|(a, b)| (a - b).abs() < 1e-12));
+    let m = Normalizer::fit(&samples, Scaling::MinMax).unwrap();
+    assert_eq!(m.transform(&samples[1]).unwrap().as_slice(), [0.5, 0.]);
+    assert!(m.transform(&Tensor::zeros(&Shape::new([3]))).is_err());
+    assert!(Normalizer::<f64>::fit(&[], Scaling::MinMax).is_err());
+}
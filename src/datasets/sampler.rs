@@ -0,0 +1,107 @@
+//! Batch samplers, choosing which samples form each batch.
+//!
+//! `ClassBalancedSampler` builds every batch from a fixed count of classes with
+//! a fixed count of samples each, for contrastive and metric learning, or for
+//! training on a severely imbalanced dataset.
+//!
+//! ```rust
+//!     use easynn::datasets::{ ClassBalancedSampler, gather };
+//!     let labels = [0, 0, 0, 0, 0, 0, 1, 2];
+//!     let sampler = ClassBalancedSampler::new(&labels, 2, 2);
+//!     let mut rng = rand::thread_rng();
+//!     for batch in sampler.epoch(&mut rng) {
+//!         assert_eq!(batch.len(), 4);
+//!         let classes: Vec<usize> = batch.iter().map(|i| labels[*i]).collect();
+//!         assert_eq!(classes[0], classes[1]);
+//!         assert_eq!(classes[2], classes[3]);
+//!         assert!(classes[0] != classes[2]);
+//!     }
+//! ```
+
+use crate::tensor::*;
+
+use rand::Rng;
+use rand::seq::SliceRandom;
+use rand::seq::index::sample;
+
+#[derive(Debug, Clone)]
+pub struct ClassBalancedSampler {
+    /// The indices of the samples of each class, classes without samples are dropped
+    by_class: Vec<Vec<usize>>,
+    /// The count of distinct classes in a batch
+    pub classes_per_batch: usize,
+    /// The count of samples of each class in a batch,
+    /// drawn with replacement if the class is smaller
+    pub per_class: usize,
+}
+
+impl ClassBalancedSampler {
+    /// `labels[i]` is the class of sample i
+    pub fn new(labels: &[usize], classes_per_batch: usize, per_class: usize) -> Self {
+        let classes = labels.iter().max().map_or(0, |m| m + 1);
+        let mut by_class = vec![Vec::<usize>::new(); classes];
+        for (i, l) in labels.iter().enumerate() {
+            by_class[*l].push(i);
+        }
+        by_class.retain(|c| !c.is_empty());
+        if classes_per_batch == 0 || per_class == 0 || classes_per_batch > by_class.len() {
+            panic!("Cannot sample {} classes from {} classes!", classes_per_batch, by_class.len());
+        }
+        ClassBalancedSampler { by_class, classes_per_batch, per_class }
+    }
+
+    pub fn batch_size(&self) -> usize {
+        self.classes_per_batch * self.per_class
+    }
+
+    /// The count of batches covering as many samples as the dataset
+    pub fn batches_per_epoch(&self) -> usize {
+        let len = self.by_class.iter().map(|c| c.len()).sum::<usize>();
+        len.div_ceil(self.batch_size())
+    }
+
+    /// The indices of one batch, grouped by class
+    pub fn sample_batch<R: Rng>(&self, rng: &mut R) -> Vec<usize> {
+        let mut batch = Vec::<usize>::with_capacity(self.batch_size());
+        for c in sample(rng, self.by_class.len(), self.classes_per_batch) {
+            let members = &self.by_class[c];
+            if members.len() >= self.per_class {
+                batch.extend(sample(rng, members.len(), self.per_class).into_iter().map(|i| members[i]));
+            } else {
+                batch.extend((0..self.per_class).map(|_| *members.choose(rng).unwrap()));
+            }
+        }
+        batch
+    }
+
+    /// The batches of one epoch
+    pub fn epoch<R: Rng>(&self, rng: &mut R) -> Vec<Vec<usize>> {
+        (0..self.batches_per_epoch()).map(|_| self.sample_batch(rng)).collect()
+    }
+}
+
+/// Collect the samples at the given indices, e.g. to pass a sampled batch to `train_once`
+pub fn gather<T: NumT>(samples: &[Tensor<T>], indices: &[usize]) -> Vec<Tensor<T>> {
+    indices.iter().map(|i| samples[*i].clone()).collect()
+}
+
+#[test]
+fn test_class_balanced_sampler() {
+    let labels = [2, 2, 2, 0, 2, 2, 2, 2];
+    let sampler = ClassBalancedSampler::new(&labels, 2, 3);
+    assert_eq!(sampler.batch_size(), 6);
+    assert_eq!(sampler.batches_per_epoch(), 2);
+    let mut rng = rand::thread_rng();
+    let batch = sampler.sample_batch(&mut rng);
+    let mut classes: Vec<usize> = batch.iter().map(|i| labels[*i]).collect();
+    classes.sort();
+    assert_eq!(classes, vec![0, 0, 0, 2, 2, 2]);
+    // the majority class is drawn without replacement
+    let mut majority: Vec<usize> = batch.iter().copied().filter(|i| labels[*i] == 2).collect();
+    majority.sort();
+    majority.dedup();
+    assert_eq!(majority.len(), 3);
+
+    let truths: Vec<_> = labels.iter().map(|l| Tensor::<f64>::new(&Shape::new([1]), vec![*l as f64])).collect();
+    assert_eq!(gather(&truths, &[3, 0])[0].get([0]), 0.);
+}
@@ -0,0 +1,179 @@
+//! Sliding windows over a long time series, for forecasting.
+//!
+//! The series is `[len]` or `[len, features]`. Sample i takes the `window` steps
+//! starting at `i * stride` as the input, and the following `horizon` steps as the truth.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::{ WindowedDataset, WindowNorm };
+//!     let series = Tensor::<f64>::new(sh!([10]), (0..10).map(|x| x as f64).collect());
+//!     let mut ds = WindowedDataset::new(&series, 4, 2, 1).unwrap();
+//!     ds.normalization = WindowNorm::ZScore;
+//!     let (inputs, truths) = ds.to_vecs();
+//!     assert_eq!(inputs.len(), 5);
+//!     assert_eq!(truths[0].get_shape(), sh!([2]));
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// How each window is normalized, per feature, using the statistics of the input window.
+/// The truth is normalized by the same statistics.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WindowNorm {
+    No,
+    /// Subtract the mean and divide by the standard deviation
+    ZScore,
+    /// Map the minimum to 0 and the maximum to 1
+    MinMax,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowedDataset<T: NumT> {
+    series: Tensor<T>,
+    /// The count of steps in an input
+    pub window: usize,
+    /// The count of steps to forecast
+    pub horizon: usize,
+    /// The steps between the starts of two samples
+    pub stride: usize,
+    pub normalization: WindowNorm,
+    /// The weight of each sample, e.g. for importance weighting, all 1 if not set
+    pub sample_weights: Option<Vec<T>>,
+}
+
+impl<T: NumT> WindowedDataset<T> {
+    pub fn new(series: &Tensor<T>, window: usize, horizon: usize, stride: usize) -> Result<Self> {
+        let rank = series.shape.rank();
+        if (rank != 1 && rank != 2) || window == 0 || horizon == 0 || stride == 0 || series.shape[0] < window + horizon {
+            let message = format!("no window {} and horizon {} of stride {} over the series {:?}", window, horizon, stride, series.shape.dims());
+            return Err(EasynnError::invalid("WindowedDataset::new", message));
+        }
+        Ok(WindowedDataset::<T> { series: series.clone(), window, horizon, stride, normalization: WindowNorm::No, sample_weights: None })
+    }
+
+    fn features(&self) -> usize {
+        if self.series.shape.rank() == 1 { 1 } else { self.series.shape[1] }
+    }
+
+    /// The shape of `steps` steps
+    fn steps_shape(&self, steps: usize) -> Shape {
+        if self.series.shape.rank() == 1 { Shape::new([steps]) } else { Shape::new([steps, self.features()]) }
+    }
+
+    /// The count of samples
+    pub fn len(&self) -> usize {
+        (self.series.shape[0] - self.window - self.horizon) / self.stride + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The (offset, scale) of each feature of sample i, normalized = (x - offset) / scale
+    pub fn stats(&self, i: usize) -> Vec<(T, T)> {
+        let f = self.features();
+        let start = i * self.stride * f;
+        let input = &self.series.flattened[start..start + self.window * f];
+        (0..f).map(|j| {
+            let col = input.iter().skip(j).step_by(f);
+            let scale_or_one = |s: T| if s > T::zero() { s } else { T::one() };
+            match self.normalization {
+                WindowNorm::No => (T::zero(), T::one()),
+                WindowNorm::ZScore => {
+                    let n = T::from(self.window).unwrap();
+                    let mean = col.clone().copied().sum::<T>() / n;
+                    let var = col.map(|x| (*x - mean) * (*x - mean)).sum::<T>() / n;
+                    (mean, scale_or_one(var.sqrt()))
+                },
+                WindowNorm::MinMax => {
+                    let min = col.clone().fold(T::infinity(), |m, x| m.min(*x));
+                    let max = col.fold(T::neg_infinity(), |m, x| m.max(*x));
+                    (min, scale_or_one(max - min))
+                },
+            }
+        }).collect()
+    }
+
+    /// The (input, truth) pair of sample i
+    pub fn get(&self, i: usize) -> (Tensor<T>, Tensor<T>) {
+        if i >= self.len() {
+            panic!("Window index out of bond!");
+        }
+        let f = self.features();
+        let stats = self.stats(i);
+        let normalize = |from: usize, steps: usize| -> Vec<T> {
+            self.series.flattened[from * f..(from + steps) * f].iter().enumerate()
+                .map(|(k, x)| (*x - stats[k % f].0) / stats[k % f].1)
+                .collect()
+        };
+        let start = i * self.stride;
+        (
+            Tensor::new(&self.steps_shape(self.window), normalize(start, self.window)),
+            Tensor::new(&self.steps_shape(self.horizon), normalize(start + self.window, self.horizon)),
+        )
+    }
+
+    /// Map a normalized prediction of sample i back to the scale of the series
+    pub fn denormalize(&self, i: usize, prediction: &Tensor<T>) -> Tensor<T> {
+        let f = self.features();
+        let stats = self.stats(i);
+        let data = prediction.flattened.iter().enumerate()
+            .map(|(k, x)| *x * stats[k % f].1 + stats[k % f].0)
+            .collect();
+        Tensor::new(&prediction.shape, data)
+    }
+
+    /// The weight of sample i
+    pub fn weight(&self, i: usize) -> T {
+        match &self.sample_weights {
+            Some(w) => {
+                if w.len() != self.len() {
+                    panic!("The count of sample weights does not match the count of windows!");
+                }
+                w[i]
+            },
+            None => T::one(),
+        }
+    }
+
+    /// All the samples, as the inputs and the truths to train a model with
+    pub fn to_vecs(&self) -> (Vec<Tensor<T>>, Vec<Tensor<T>>) {
+        (0..self.len()).map(|i| self.get(i)).unzip()
+    }
+
+    /// The weight of each sample, to train a model with along with `to_vecs`
+    pub fn weights(&self) -> Vec<T> {
+        (0..self.len()).map(|i| self.weight(i)).collect()
+    }
+}
+
+#[test]
+fn test_windowed_dataset() {
+    let series = Tensor::<f64>::new(&Shape::new([10]), (0..10).map(|x| x as f64).collect());
+    let ds = WindowedDataset::new(&series, 3, 2, 2).unwrap();
+    assert_eq!(ds.len(), 3);
+    let (input, truth) = ds.get(2);
+    assert_eq!(input, Tensor::new(&Shape::new([3]), vec![4., 5., 6.]));
+    assert_eq!(truth, Tensor::new(&Shape::new([2]), vec![7., 8.]));
+    assert!(WindowedDataset::new(&series, 8, 3, 1).is_err());
+    assert_eq!(ds.weights(), vec![1.; 3]);
+
+    // two features, normalized per feature
+    let series = Tensor::<f64>::new(&Shape::new([4, 2]), vec![
+        1., 10.,
+        3., 10.,
+        5., 20.,
+        7., 40.,
+    ]);
+    let mut ds = WindowedDataset::new(&series, 2, 1, 1).unwrap();
+    ds.normalization = WindowNorm::MinMax;
+    let (input, truth) = ds.get(0);
+    assert_eq!(input, Tensor::new(&Shape::new([2, 2]), vec![0., 0., 1., 0.]));
+    assert_eq!(truth, Tensor::new(&Shape::new([1, 2]), vec![2., 10.]));
+    assert_eq!(ds.denormalize(0, &truth), Tensor::new(&Shape::new([1, 2]), vec![5., 20.]));
+
+    ds.normalization = WindowNorm::ZScore;
+    let (input, _) = ds.get(1);
+    assert_eq!(input, Tensor::new(&Shape::new([2, 2]), vec![-1., -1., 1., 1.]));
+}
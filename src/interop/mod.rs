@@ -0,0 +1,5 @@
+//! The interop module, exchanging models with other frameworks.
+//!
+
+#[cfg(feature = "onnx")]
+pub mod onnx;
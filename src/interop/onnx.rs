@@ -0,0 +1,527 @@
+//! Export of models to [ONNX](https://onnx.ai/), enabled by the `onnx` feature.
+//!
+//! The graph has one input `input` and one output `output`, both with a leading
+//! batch dimension `N` before the shapes of the model. It targets opset 13,
+//! with parameters stored as `FLOAT` for `f32` models and `DOUBLE` for `f64` ones.
+//!
+//! The layers are exported from their `Layer::record`:
+//!
+//!  - `Dense`: `Flatten` (for inputs of rank over 1), `Gemm`, then `Reshape` (for outputs of rank over 1)
+//!  - `Conv2D`: `Conv`
+//!  - `MaxPool2D`, `AvgPool2D`: `MaxPool`, `AveragePool`
+//!  - `ZeroPad2D`, `Crop2D`: `Pad`, `Slice`
+//!  - `Softmax`: `Softmax` over the last axis
+//!  - `Dropout`: `Identity`, as at inference
+//!  - `Reshape`, `Flatten`, `Permute`: `Reshape`, `Flatten`, `Transpose` keeping the batch axis first
+//!  - `MeanOverTime`, `MaxOverTime`: `ReduceMean`, `ReduceMax` over the time axis
+//!  - `ActivationLayer`: its activation alone, `Identity` for no activation
+//!  - `Skip`: the shortcut and the body from the same input, then `Add` or `Concat` over the channels
+//!
+//! followed by `Sigmoid`, `Tanh`, `Relu`, `LeakyRelu`, `Elu` or `Softplus` for the activation,
+//! or `Sigmoid` then `Mul` by its input for Swish.
+//! Other layers, GELU and custom activations fail the export with `ErrorKind::InvalidData`.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::interop::onnx::*;
+//!     let mut model = Sequential::<f32>::new(Loss::MeanSquare);
+//!     model.add(Dense::new(sh!([4]), sh!([8]), Activation::Relu));
+//!     model.add(Dense::new(sh!([8]), sh!([2]), Activation::Sigmoid));
+//!     let mut buf = Vec::new();
+//!     write_onnx(&model, &mut buf).unwrap();
+//!     assert!(!buf.is_empty());
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::{ Config, LayerRecord, ensure, invalid };
+use crate::models::sequential::Sequential;
+
+use std::fs::File;
+use std::io::{ BufWriter, Result, Write };
+use std::path::Path;
+
+pub const IR_VERSION: u64 = 7;
+pub const OPSET_VERSION: u64 = 13;
+
+/// A protobuf message being encoded
+#[derive(Default)]
+struct Message(Vec<u8>);
+
+impl Message {
+    fn varint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.0.push(x as u8 | 0x80);
+            x >>= 7;
+        }
+        self.0.push(x as u8);
+    }
+    fn int(&mut self, field: u32, x: i64) -> &mut Self {
+        self.varint((field as u64) << 3);
+        self.varint(x as u64);
+        self
+    }
+    fn float(&mut self, field: u32, x: f32) -> &mut Self {
+        self.varint((field as u64) << 3 | 5);
+        self.0.extend_from_slice(&x.to_le_bytes());
+        self
+    }
+    fn bytes(&mut self, field: u32, b: &[u8]) -> &mut Self {
+        self.varint((field as u64) << 3 | 2);
+        self.varint(b.len() as u64);
+        self.0.extend_from_slice(b);
+        self
+    }
+    fn string(&mut self, field: u32, s: &str) -> &mut Self {
+        self.bytes(field, s.as_bytes())
+    }
+    fn message(&mut self, field: u32, m: &Message) -> &mut Self {
+        self.bytes(field, &m.0)
+    }
+}
+
+// The element types of TensorProto.DataType
+const FLOAT: i64 = 1;
+const INT64: i64 = 7;
+const DOUBLE: i64 = 11;
+
+fn tensor_proto(name: &str, dims: &[usize], data_type: i64, raw: Vec<u8>) -> Message {
+    let mut t = Message::default();
+    for d in dims {
+        t.int(1, *d as i64);
+    }
+    t.int(2, data_type).string(8, name).bytes(9, &raw);
+    t
+}
+
+/// The value info of a tensor of shape `[N, dims...]`
+fn value_info(name: &str, data_type: i64, dims: &[usize]) -> Message {
+    let mut shape = Message::default();
+    let mut batch = Message::default();
+    batch.string(2, "N");
+    shape.message(1, &batch);
+    for d in dims {
+        let mut dim = Message::default();
+        dim.int(1, *d as i64);
+        shape.message(1, &dim);
+    }
+    let mut tensor_type = Message::default();
+    tensor_type.int(1, data_type).message(2, &shape);
+    let mut ty = Message::default();
+    ty.message(1, &tensor_type);
+    let mut info = Message::default();
+    info.string(1, name).message(2, &ty);
+    info
+}
+
+enum Attr {
+    Int(i64),
+    Ints(Vec<usize>),
+    Float(f32),
+    Str(&'static str),
+}
+
+struct Node {
+    op: &'static str,
+    inputs: Vec<String>,
+    output: String,
+    attrs: Vec<(&'static str, Attr)>,
+}
+
+impl Node {
+    fn encode(&self, name: &str) -> Message {
+        let mut m = Message::default();
+        for i in &self.inputs {
+            m.string(1, i);
+        }
+        m.string(2, &self.output).string(3, name).string(4, self.op);
+        for (n, a) in &self.attrs {
+            let mut attr = Message::default();
+            attr.string(1, n);
+            match a {
+                Attr::Float(f) => attr.float(2, *f).int(20, 1),
+                Attr::Int(i) => attr.int(3, *i).int(20, 2),
+                Attr::Str(v) => attr.string(4, v).int(20, 3),
+                Attr::Ints(v) => {
+                    for i in v {
+                        attr.int(8, *i as i64);
+                    }
+                    attr.int(20, 7)
+                },
+            };
+            m.message(5, &attr);
+        }
+        m
+    }
+}
+
+/// The graph being built, tracking the name of the current output
+struct Graph {
+    data_type: i64,
+    nodes: Vec<Node>,
+    initializers: Vec<Message>,
+    current: String,
+}
+
+impl Graph {
+    fn node(&mut self, op: &'static str, extra: &[String], attrs: Vec<(&'static str, Attr)>) {
+        let output = format!("{}_{}", op.to_lowercase(), self.nodes.len());
+        let mut inputs = vec![std::mem::replace(&mut self.current, output.clone())];
+        inputs.extend_from_slice(extra);
+        self.nodes.push(Node { op, inputs, output, attrs });
+    }
+    /// Add an initializer of the parameters, returning its name
+    fn parameter<T: NumT>(&mut self, name: String, dims: &[usize], data: &[T]) -> String {
+        let raw = if self.data_type == FLOAT {
+            data.iter().flat_map(|x| x.to_f32().unwrap().to_le_bytes()).collect()
+        } else {
+            data.iter().flat_map(|x| x.to_f64().unwrap().to_le_bytes()).collect()
+        };
+        self.initializers.push(tensor_proto(&name, dims, self.data_type, raw));
+        name
+    }
+    /// Add an `INT64` initializer, returning its name
+    fn int64s(&mut self, name: String, data: &[i64]) -> String {
+        let raw = data.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.initializers.push(tensor_proto(&name, &[data.len()], INT64, raw));
+        name
+    }
+    fn floats(&mut self, name: String, data: &[f32]) -> String {
+        let raw = data.iter().flat_map(|x| x.to_le_bytes()).collect();
+        self.initializers.push(tensor_proto(&name, &[data.len()], FLOAT, raw));
+        name
+    }
+    fn activation<T: NumT>(&mut self, act: &Activation<T>) -> Result<()> {
+        match act {
+            Activation::No => (),
+            Activation::Sigmoid => self.node("Sigmoid", &[], vec![]),
+            Activation::Tanh => self.node("Tanh", &[], vec![]),
+            Activation::Relu => self.node("Relu", &[], vec![]),
+            Activation::LeakyRelu(a) => self.node("LeakyRelu", &[], vec![("alpha", Attr::Float(a.to_f32().unwrap()))]),
+            Activation::Elu(a) => self.node("Elu", &[], vec![("alpha", Attr::Float(a.to_f32().unwrap()))]),
+            Activation::Softplus => self.node("Softplus", &[], vec![]),
+            Activation::Swish => {
+                let x = self.current.clone();
+                self.node("Sigmoid", &[], vec![]);
+                self.node("Mul", &[x], vec![]);
+            },
+            Activation::Gelu => return Err(invalid("GELU cannot be exported to ONNX of opset 13")),
+            Activation::Custom(c) => return Err(invalid(&format!("the custom activation {} cannot be exported to ONNX", c.name()))),
+        }
+        Ok(())
+    }
+
+    /// Add the nodes of the layer `l`, its initializers named after it
+    fn layer<T: NumT>(&mut self, l: &str, record: &LayerRecord<T>) -> Result<()> {
+        let mut c = Config(record.config.iter());
+        let window = |kernel: (usize, usize), stride: (usize, usize)| vec![
+            ("kernel_shape", Attr::Ints(vec![kernel.0, kernel.1])),
+            ("strides", Attr::Ints(vec![stride.0, stride.1])),
+        ];
+        match record.kind.as_str() {
+            "dense" => {
+                let (i_shape, o_shape) = (c.shape()?, c.shape()?);
+                ensure(record.parameters.len() == 2, "wrong count of parameters")?;
+                if i_shape.rank() > 1 {
+                    self.node("Flatten", &[], vec![("axis", Attr::Int(1))]);
+                }
+                let w = self.parameter(format!("dense{}_weight", l), &[o_shape.size(), i_shape.size()], &record.parameters[0]);
+                let b = self.parameter(format!("dense{}_bias", l), &[o_shape.size()], &record.parameters[1]);
+                self.node("Gemm", &[w, b], vec![("transB", Attr::Int(1))]);
+                if o_shape.rank() > 1 {
+                    let dims: Vec<i64> = std::iter::once(0).chain(o_shape.dims().iter().map(|d| *d as i64)).collect();
+                    let shape = self.int64s(format!("dense{}_shape", l), &dims);
+                    self.node("Reshape", &[shape], vec![]);
+                }
+            },
+            "conv2d" => {
+                let i_shape = c.shape()?;
+                let (out_channels, kernel, stride, (t, b, left, r)) = (c.value()?, c.pair()?, c.pair()?, c.quad()?);
+                ensure(record.parameters.len() == 2, "wrong count of parameters")?;
+                let w = self.parameter(format!("conv{}_weight", l), &[out_channels, i_shape[0], kernel.0, kernel.1], &record.parameters[0]);
+                let bias = self.parameter(format!("conv{}_bias", l), &[out_channels], &record.parameters[1]);
+                let mut attrs = window(kernel, stride);
+                attrs.push(("pads", Attr::Ints(vec![t, left, b, r])));
+                self.node("Conv", &[w, bias], attrs);
+            },
+            "max_pool2d" | "avg_pool2d" => {
+                let (_, kernel, stride) = (c.shape()?, c.pair()?, c.pair()?);
+                let op = if record.kind == "max_pool2d" { "MaxPool" } else { "AveragePool" };
+                self.node(op, &[], window(kernel, stride));
+            },
+            "zero_pad2d" => {
+                let (i_shape, (t, b, left, r)) = (c.shape()?, c.quad()?);
+                // The begins then the ends of the batch axis and each input axis
+                let rank = i_shape.rank() + 1;
+                let mut pads = vec![0; 2 * rank];
+                pads[rank - 2] = t as i64;
+                pads[rank - 1] = left as i64;
+                pads[2 * rank - 2] = b as i64;
+                pads[2 * rank - 1] = r as i64;
+                let pads = self.int64s(format!("pad{}_pads", l), &pads);
+                self.node("Pad", &[pads], vec![]);
+            },
+            "crop2d" => {
+                let (i_shape, (t, b, left, r)) = (c.shape()?, c.quad()?);
+                let rank = i_shape.rank() as i64;
+                let (h, w) = (i_shape[i_shape.rank() - 2], i_shape[i_shape.rank() - 1]);
+                let starts = self.int64s(format!("crop{}_starts", l), &[t as i64, left as i64]);
+                let ends = self.int64s(format!("crop{}_ends", l), &[(h - b) as i64, (w - r) as i64]);
+                let axes = self.int64s(format!("crop{}_axes", l), &[rank - 1, rank]);
+                self.node("Slice", &[starts, ends, axes], vec![]);
+            },
+            "softmax" => self.node("Softmax", &[], vec![("axis", Attr::Int(-1))]),
+            "dropout" => self.node("Identity", &[], vec![]),
+            "reshape" => {
+                let (_, o_shape) = (c.shape()?, c.shape()?);
+                let dims: Vec<i64> = std::iter::once(0).chain(o_shape.dims().iter().map(|d| *d as i64)).collect();
+                let shape = self.int64s(format!("reshape{}_shape", l), &dims);
+                self.node("Reshape", &[shape], vec![]);
+            },
+            "permute" => {
+                let rank = c.shape()?.rank();
+                let axes = (0..rank).map(|_| c.value().map(|a| a + 1)).collect::<Result<Vec<_>>>()?;
+                let perm = std::iter::once(0).chain(axes).collect();
+                self.node("Transpose", &[], vec![("perm", Attr::Ints(perm))]);
+            },
+            "global_avg_pool2d" => {
+                self.node("GlobalAveragePool", &[], vec![]);
+                self.node("Flatten", &[], vec![("axis", Attr::Int(1))]);
+            },
+            "batch_norm" => {
+                // The momentum, the epsilon and the running statistics
+                let channels = c.shape()?[0];
+                let values = &record.floats;
+                ensure(values.len() == 2 + 2 * channels, "wrong count of values")?;
+                ensure(record.parameters.len() == 2, "wrong count of parameters")?;
+                let (eps, mean, var) = (values[1], &values[2..2 + channels], &values[2 + channels..]);
+                let scale = self.parameter(format!("bn{}_scale", l), &[channels], &record.parameters[0]);
+                let bias = self.parameter(format!("bn{}_bias", l), &[channels], &record.parameters[1]);
+                let mean = self.parameter(format!("bn{}_mean", l), &[channels], mean);
+                let var = self.parameter(format!("bn{}_var", l), &[channels], var);
+                self.node("BatchNormalization", &[scale, bias, mean, var], vec![("epsilon", Attr::Float(eps as f32))]);
+            },
+            "up_sample2d" => {
+                let (_, (fh, fw)) = (c.shape()?, c.pair()?);
+                let scales = self.floats(format!("up{}_scales", l), &[1., 1., fh as f32, fw as f32]);
+                // No region of interest, and the source pixel of x is floor(x / factor)
+                self.node("Resize", &[String::new(), scales], vec![
+                    ("mode", Attr::Str("nearest")),
+                    ("coordinate_transformation_mode", Attr::Str("asymmetric")),
+                    ("nearest_mode", Attr::Str("floor")),
+                ]);
+            },
+            "mean_over_time" | "max_over_time" => {
+                let op = if record.kind == "mean_over_time" { "ReduceMean" } else { "ReduceMax" };
+                self.node(op, &[], vec![("axes", Attr::Ints(vec![1])), ("keepdims", Attr::Int(0))]);
+            },
+            "flatten" => self.node("Flatten", &[], vec![("axis", Attr::Int(1))]),
+            "skip" => {
+                let (_, merge, shortcut) = (c.shape()?, c.value()?, c.value()?);
+                ensure(shortcut <= record.inner.len(), "invalid Skip")?;
+                // The inner layer i of the block l is named l_i, both chains starting from the input
+                let input = self.current.clone();
+                for (i, inner) in record.inner[..shortcut].iter().enumerate() {
+                    self.layer(&format!("{}_{}", l, i), inner)?;
+                }
+                let s = std::mem::replace(&mut self.current, input);
+                for (i, inner) in record.inner.iter().enumerate().skip(shortcut) {
+                    self.layer(&format!("{}_{}", l, i), inner)?;
+                }
+                if merge == 0 {
+                    self.node("Add", &[s], vec![]);
+                } else {
+                    // The shortcut output first
+                    let b = std::mem::replace(&mut self.current, s);
+                    self.node("Concat", &[b], vec![("axis", Attr::Int(1))]);
+                }
+            },
+            // An `Identity` for no activation, so that every layer adds a node
+            "activation" if matches!(record.activation, Activation::No) => self.node("Identity", &[], vec![]),
+            "activation" => (),
+            kind => return Err(invalid(&format!("the layer kind {} cannot be exported to ONNX", kind))),
+        }
+        self.activation(&record.activation)
+    }
+}
+
+/// Write the ONNX model of the layers applied in order
+pub fn write_onnx_layers<T: NumT, W: Write>(layers: &[&dyn Layer<T>], w: &mut W) -> Result<()> {
+    let data_type = if std::mem::size_of::<T>() == 4 { FLOAT } else { DOUBLE };
+    let (first, last) = match (layers.first(), layers.last()) {
+        (Some(f), Some(l)) => (f.get_input_shape(), l.get_output_shape()),
+        _ => return Err(invalid("there are no layers to export")),
+    };
+    let mut graph = Graph { data_type, nodes: Vec::new(), initializers: Vec::new(), current: "input".to_string() };
+    for (l, layer) in layers.iter().enumerate() {
+        let record = layer.record().ok_or_else(|| invalid("the layer cannot be exported to ONNX"))?;
+        graph.layer(&l.to_string(), &record)?;
+    }
+    // Every layer adds at least one node
+    graph.nodes.last_mut().unwrap().output = "output".to_string();
+
+    let mut g = Message::default();
+    for (i, node) in graph.nodes.iter().enumerate() {
+        g.message(1, &node.encode(&format!("node{}", i)));
+    }
+    g.string(2, "easynn");
+    for init in &graph.initializers {
+        g.message(5, init);
+    }
+    g.message(11, &value_info("input", data_type, first.dims()));
+    g.message(12, &value_info("output", data_type, last.dims()));
+
+    let mut opset = Message::default();
+    opset.string(1, "").int(2, OPSET_VERSION as i64);
+    let mut model = Message::default();
+    model.int(1, IR_VERSION as i64)
+        .string(2, "easynn")
+        .string(3, env!("CARGO_PKG_VERSION"))
+        .message(7, &g)
+        .message(8, &opset);
+    w.write_all(&model.0)
+}
+
+/// Write the ONNX model of the sequential model
+pub fn write_onnx<T: NumT, W: Write>(model: &Sequential<T>, w: &mut W) -> Result<()> {
+    let layers: Vec<&dyn Layer<T>> = model.layers().iter().map(|l| l.as_ref()).collect();
+    write_onnx_layers(&layers, w)
+}
+
+/// Save the ONNX model of the sequential model to the file
+pub fn save_onnx<T: NumT, P: AsRef<Path>>(model: &Sequential<T>, path: P) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_onnx(model, &mut w)?;
+    w.flush()
+}
+
+/// The (number, integer, bytes) of each field of a protobuf message,
+/// the integer of the varints and fixed32 or the bytes of the length-delimited
+#[cfg(test)]
+fn decode(mut b: &[u8]) -> Vec<(u64, u64, Vec<u8>)> {
+    fn varint(b: &mut &[u8]) -> u64 {
+        let (mut x, mut shift) = (0, 0);
+        loop {
+            let byte = b[0];
+            *b = &b[1..];
+            x |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte < 0x80 {
+                return x;
+            }
+        }
+    }
+    let mut ret = Vec::new();
+    while !b.is_empty() {
+        let key = varint(&mut b);
+        match key & 7 {
+            0 => ret.push((key >> 3, varint(&mut b), vec![])),
+            5 => {
+                ret.push((key >> 3, u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as u64, vec![]));
+                b = &b[4..];
+            },
+            2 => {
+                let len = varint(&mut b) as usize;
+                ret.push((key >> 3, 0, b[..len].to_vec()));
+                b = &b[len..];
+            },
+            _ => panic!("unexpected wire type"),
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_onnx() {
+    use crate::layers::{ dense::Dense, conv::Conv2D, pooling::MaxPool2D, padding::Crop2D, seq_pooling::AttentionPooling };
+    let mut model = Sequential::<f32>::new(crate::models::Loss::MeanSquare);
+    model.add(Conv2D::new(&Shape::new([1, 6, 6]), 2, (3, 3), (1, 1), Padding::Same, Activation::Relu));
+    model.add(MaxPool2D::new(&Shape::new([2, 6, 6]), (2, 2), (2, 2)));
+    model.add(Crop2D::new(&Shape::new([2, 3, 3]), (1, 0, 0, 1)));
+    model.add(Dense::new(&Shape::new([2, 2, 2]), &Shape::new([3, 2]), Activation::LeakyRelu(0.5)));
+    let mut buf = Vec::new();
+    write_onnx(&model, &mut buf).unwrap();
+
+    let fields = decode(&buf);
+    assert_eq!(fields[0].0, 1);
+    assert_eq!(fields[0].1, IR_VERSION);
+    let graph = fields.iter().find(|f| f.0 == 7).unwrap();
+    let graph = decode(&graph.2);
+    let nodes: Vec<_> = graph.iter().filter(|f| f.0 == 1).map(|f| decode(&f.2)).collect();
+    let op = |n: &Vec<(u64, u64, Vec<u8>)>| String::from_utf8(n.iter().find(|f| f.0 == 4).unwrap().2.clone()).unwrap();
+    let ops: Vec<_> = nodes.iter().map(op).collect();
+    assert_eq!(ops, ["Conv", "Relu", "MaxPool", "Slice", "Flatten", "Gemm", "Reshape", "LeakyRelu"]);
+    // The nodes are chained from the input to the output
+    let io = |n: &Vec<(u64, u64, Vec<u8>)>, field| String::from_utf8(n.iter().find(|f| f.0 == field).unwrap().2.clone()).unwrap();
+    assert_eq!(io(&nodes[0], 1), "input");
+    for pair in nodes.windows(2) {
+        assert_eq!(io(&pair[0], 2), io(&pair[1], 1));
+    }
+    assert_eq!(io(&nodes[7], 2), "output");
+    // Conv and Gemm weights and biases, the slice starts, ends and axes, and the reshape target
+    let inits: Vec<_> = graph.iter().filter(|f| f.0 == 5).map(|f| decode(&f.2)).collect();
+    assert_eq!(inits.len(), 8);
+    let conv_weight = &inits[0];
+    let dims: Vec<u64> = conv_weight.iter().filter(|f| f.0 == 1).map(|f| f.1).collect();
+    assert_eq!(dims, [2, 1, 3, 3]);
+    assert_eq!(conv_weight.iter().find(|f| f.0 == 9).unwrap().2.len(), 18 * 4);
+
+    // Nearest upsampling by flooring the coordinates
+    let mut up = Sequential::<f64>::new(crate::models::Loss::MeanSquare);
+    up.add(crate::layers::upsampling::UpSample2D::new(&Shape::new([2, 3, 3]), (2, 2)));
+    let mut buf = Vec::new();
+    write_onnx(&up, &mut buf).unwrap();
+    let graph = decode(&decode(&buf).iter().find(|f| f.0 == 7).unwrap().2);
+    let resize = decode(&graph.iter().find(|f| f.0 == 1).unwrap().2);
+    assert_eq!(op(&resize), "Resize");
+    assert_eq!(resize.iter().filter(|f| f.0 == 1).count(), 3);
+    assert_eq!(resize.iter().filter(|f| f.0 == 5).count(), 3);
+
+    // The permutation of the axes keeps the batch axis first
+    let mut permute = Sequential::<f64>::new(crate::models::Loss::MeanSquare);
+    permute.add(crate::layers::reshape::Permute::new(&Shape::new([2, 3, 4]), &[2, 0, 1]));
+    let mut buf = Vec::new();
+    write_onnx(&permute, &mut buf).unwrap();
+    let graph = decode(&decode(&buf).iter().find(|f| f.0 == 7).unwrap().2);
+    let transpose = decode(&graph.iter().find(|f| f.0 == 1).unwrap().2);
+    assert_eq!(op(&transpose), "Transpose");
+    let perm: Vec<u64> = decode(&transpose.iter().find(|f| f.0 == 5).unwrap().2).iter().filter(|f| f.0 == 8).map(|f| f.1).collect();
+    assert_eq!(perm, [0, 3, 1, 2]);
+
+    // Swish multiplies the sigmoid by the input of the activation
+    let mut swish = Sequential::<f64>::new(crate::models::Loss::MeanSquare);
+    swish.add(Dense::new(&Shape::new([3]), &Shape::new([2]), Activation::Swish));
+    let mut buf = Vec::new();
+    write_onnx(&swish, &mut buf).unwrap();
+    let graph = decode(&decode(&buf).iter().find(|f| f.0 == 7).unwrap().2);
+    let nodes: Vec<_> = graph.iter().filter(|f| f.0 == 1).map(|f| decode(&f.2)).collect();
+    assert_eq!(nodes.iter().map(op).collect::<Vec<_>>(), ["Gemm", "Sigmoid", "Mul"]);
+    let inputs: Vec<_> = nodes[2].iter().filter(|f| f.0 == 1).map(|f| String::from_utf8(f.2.clone()).unwrap()).collect();
+    assert_eq!(inputs, [io(&nodes[1], 2), io(&nodes[0], 2)]);
+
+    // An activation layer of no activation alone is an identity to the output
+    let mut identity = Sequential::<f64>::new(crate::models::Loss::MeanSquare);
+    identity.add(crate::layers::activation_layer::ActivationLayer::new(&Shape::new([3]), Activation::No));
+    let mut buf = Vec::new();
+    write_onnx(&identity, &mut buf).unwrap();
+    let graph = decode(&decode(&buf).iter().find(|f| f.0 == 7).unwrap().2);
+    let node = decode(&graph.iter().find(|f| f.0 == 1).unwrap().2);
+    assert_eq!(op(&node), "Identity");
+    assert_eq!((io(&node, 1), io(&node, 2)), ("input".to_string(), "output".to_string()));
+
+    // A skip concatenates the shortcut output, here the input, and the body output
+    let mut skip = Sequential::<f64>::new(crate::models::Loss::MeanSquare);
+    skip.add(crate::layers::skip::Skip::new(&Shape::new([2, 3, 3]), crate::layers::skip::Merge::Concat, Activation::Relu)
+        .then(Conv2D::new(&Shape::new([2, 3, 3]), 1, (1, 1), (1, 1), Padding::Valid, Activation::Tanh)));
+    let mut buf = Vec::new();
+    write_onnx(&skip, &mut buf).unwrap();
+    let graph = decode(&decode(&buf).iter().find(|f| f.0 == 7).unwrap().2);
+    let nodes: Vec<_> = graph.iter().filter(|f| f.0 == 1).map(|f| decode(&f.2)).collect();
+    assert_eq!(nodes.iter().map(op).collect::<Vec<_>>(), ["Conv", "Tanh", "Concat", "Relu"]);
+    let inputs: Vec<_> = nodes[2].iter().filter(|f| f.0 == 1).map(|f| String::from_utf8(f.2.clone()).unwrap()).collect();
+    assert_eq!(inputs, ["input".to_string(), io(&nodes[1], 2)]);
+
+    let mut unsupported = Sequential::<f64>::new(crate::models::Loss::MeanSquare);
+    unsupported.add(AttentionPooling::new(&Shape::new([4, 3])));
+    assert!(write_onnx(&unsupported, &mut Vec::new()).is_err());
+    assert!(write_onnx(&Sequential::<f64>::new(crate::models::Loss::MeanSquare), &mut Vec::new()).is_err());
+}
@@ -1,15 +1,90 @@
 //! The module that contains activation functions, e.g. sigmoid, relu, etc.
 //!
+//! Activations other than the built-in ones implement `ActivationFn` and are wrapped
+//! by `Activation::custom`, or given as closures to `Activation::from_fn`. Either
+//! registers the activation under its name for its element type, so that the models
+//! using it are saved and loaded like the others.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     // the softsign x / (1 + |x|)
+//!     let softsign = Activation::<f64>::from_fn("softsign",
+//!         |x| x / (1. + x.abs()),
+//!         |x| 1. / ((1. + x.abs()) * (1. + x.abs())));
+//!     assert_eq!(softsign.call(1.), 0.5);
+//!     assert_eq!(softsign.diff(-1.), 0.25);
+//!     assert!(Activation::<f64>::by_name("softsign").is_some());
+//! ```
 
 use crate::tensor::num::*;
 
+use std::any::{ Any, TypeId };
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{ OnceLock, RwLock };
+
 #[derive(Debug, Copy, Clone)]
 pub enum Activation<T: NumT> {
     No,
     Sigmoid,
     Tanh,
     Relu,
+    /// The ReLU of the slope over the negatives
     LeakyRelu(T),
+    /// The exponential linear unit of the scale `alpha`, `alpha (e^x - 1)` over the negatives
+    Elu(T),
+    /// The GELU of the tanh approximation
+    Gelu,
+    /// The Swish of beta 1, or SiLU, `x sigmoid(x)`
+    Swish,
+    /// `ln(1 + e^x)`, a smooth ReLU
+    Softplus,
+    /// A user-defined activation, see `Activation::custom`
+    Custom(&'static dyn ActivationFn<T>),
+}
+
+/// A user-defined activation function and its derivative
+pub trait ActivationFn<T: NumT>: Send + Sync {
+    /// The name it is registered and saved under, unique per element type
+    fn name(&self) -> &str;
+    fn call(&self, x: T) -> T;
+    fn diff(&self, x: T) -> T;
+}
+
+impl<T: NumT> fmt::Debug for dyn ActivationFn<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The activation of a pair of closures, for `Activation::from_fn`
+struct FnActivation<T, C, D> {
+    name: String,
+    call: C,
+    diff: D,
+    _t: std::marker::PhantomData<fn(T) -> T>,
+}
+
+impl<T: NumT, C, D> ActivationFn<T> for FnActivation<T, C, D>
+where C: Fn(T) -> T + Send + Sync, D: Fn(T) -> T + Send + Sync {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn call(&self, x: T) -> T {
+        (self.call)(x)
+    }
+    fn diff(&self, x: T) -> T {
+        (self.diff)(x)
+    }
+}
+
+/// The custom activations by the element type and the name, each a `&'static dyn ActivationFn` of its type
+type Customs = HashMap<(TypeId, String), Box<dyn Any + Send + Sync>>;
+
+static CUSTOMS: OnceLock<RwLock<Customs>> = OnceLock::new();
+
+fn customs() -> &'static RwLock<Customs> {
+    CUSTOMS.get_or_init(|| RwLock::new(HashMap::new()))
 }
 
 fn sigmoid<T: NumT>(x: T) -> T {
@@ -21,9 +96,7 @@ fn dsigmoid<T: NumT>(x: T) -> T {
 }
 
 fn tanh<T: NumT>(x: T) -> T {
-    let ex = x.exp();
-    let nex = (-x).exp();
-    (ex - nex) / (ex + nex)
+    x.tanh()
 }
 
 fn dtanh<T: NumT>(x: T) -> T {
@@ -40,11 +113,55 @@ fn drelu<T: NumT>(x: T) -> T {
 }
 
 fn leaky_relu<T: NumT>(a: T, x: T) -> T {
-    if x < T::zero() { T::zero() } else { a * T::one() }
+    if x < T::zero() { a * x } else { x }
 }
 
 fn dleaky_relu<T: NumT>(a: T, x: T) -> T {
-    if x < T::zero() { T::zero() } else { a }
+    if x < T::zero() { a } else { T::one() }
+}
+
+fn elu<T: NumT>(a: T, x: T) -> T {
+    if x < T::zero() { a * x.exp_m1() } else { x }
+}
+
+fn delu<T: NumT>(a: T, x: T) -> T {
+    if x < T::zero() { a * x.exp() } else { T::one() }
+}
+
+/// sqrt(2 / pi) and the cubic coefficient of the tanh approximation
+fn gelu_consts<T: NumT>() -> (T, T) {
+    (T::from(0.7978845608028654).unwrap(), T::from(0.044715).unwrap())
+}
+
+fn gelu<T: NumT>(x: T) -> T {
+    let (k, c) = gelu_consts::<T>();
+    let half = T::from(0.5).unwrap();
+    half * x * (T::one() + (k * (x + c * x * x * x)).tanh())
+}
+
+fn dgelu<T: NumT>(x: T) -> T {
+    let (k, c) = gelu_consts::<T>();
+    let half = T::from(0.5).unwrap();
+    let th = (k * (x + c * x * x * x)).tanh();
+    half * (T::one() + th) + half * x * (T::one() - th * th) * k * (T::one() + T::from(3).unwrap() * c * x * x)
+}
+
+fn swish<T: NumT>(x: T) -> T {
+    x * sigmoid(x)
+}
+
+fn dswish<T: NumT>(x: T) -> T {
+    let s = sigmoid(x);
+    s + x * s * (T::one() - s)
+}
+
+/// max(x, 0) + ln(1 + e^-|x|), not overflowing for large x
+fn softplus<T: NumT>(x: T) -> T {
+    x.max(T::zero()) + (-x.abs()).exp().ln_1p()
+}
+
+fn dsoftplus<T: NumT>(x: T) -> T {
+    sigmoid(x)
 }
 
 use Activation::*;
@@ -57,9 +174,30 @@ impl<T: NumT> Activation<T> {
             Tanh => tanh::<T>(x),
             Relu => relu::<T>(x),
             LeakyRelu(a) => leaky_relu::<T>(*a, x),
+            Elu(a) => elu::<T>(*a, x),
+            Gelu => gelu::<T>(x),
+            Swish => swish::<T>(x),
+            Softplus => softplus::<T>(x),
+            Custom(c) => c.call(x),
             // _ => T::zero(),
         }
     }
+    /// The same activation over another element type, its parameters mapped by `f`,
+    /// None for a custom activation not registered for that type
+    pub fn map<U: NumT, F: Fn(T) -> U>(&self, f: F) -> Option<Activation<U>> {
+        Some(match self {
+            No => Activation::No,
+            Sigmoid => Activation::Sigmoid,
+            Tanh => Activation::Tanh,
+            Relu => Activation::Relu,
+            LeakyRelu(a) => Activation::LeakyRelu(f(*a)),
+            Elu(a) => Activation::Elu(f(*a)),
+            Gelu => Activation::Gelu,
+            Swish => Activation::Swish,
+            Softplus => Activation::Softplus,
+            Custom(c) => Activation::by_name(c.name())?,
+        })
+    }
     pub fn diff(&self, x: T) -> T {
         match self {
             No => T::one(),
@@ -67,7 +205,90 @@ impl<T: NumT> Activation<T> {
             Tanh => dtanh::<T>(x),
             Relu => drelu::<T>(x),
             LeakyRelu(a) => dleaky_relu::<T>(*a, x),
+            Elu(a) => delu::<T>(*a, x),
+            Gelu => dgelu::<T>(x),
+            Swish => dswish::<T>(x),
+            Softplus => dsoftplus::<T>(x),
+            Custom(c) => c.diff(x),
             // _ => T::zero(),
         }
     }
+
+    /// The custom activation of `f`, registered under its name for the element type,
+    /// replacing the one registered before, if any.
+    /// The activation lives as long as the program, as models copy it freely.
+    pub fn custom<F: ActivationFn<T> + 'static>(f: F) -> Self {
+        let f: &'static dyn ActivationFn<T> = Box::leak(Box::new(f));
+        customs().write().unwrap().insert((TypeId::of::<T>(), f.name().to_string()), Box::new(f));
+        Custom(f)
+    }
+    /// The custom activation of the closures computing it and its derivative, see `custom`
+    pub fn from_fn<C, D>(name: &str, call: C, diff: D) -> Self
+    where C: Fn(T) -> T + Send + Sync + 'static, D: Fn(T) -> T + Send + Sync + 'static {
+        Self::custom(FnActivation { name: name.to_string(), call, diff, _t: std::marker::PhantomData })
+    }
+    /// The custom activation registered under the name for the element type
+    pub fn by_name(name: &str) -> Option<Self> {
+        customs().read().unwrap().get(&(TypeId::of::<T>(), name.to_string()))
+            .and_then(|f| f.downcast_ref::<&'static dyn ActivationFn<T>>()).map(|f| Custom(*f))
+    }
+}
+
+#[test]
+fn test_diff() {
+    // the derivatives against central differences, away from the kinks at 0
+    let acts = [Sigmoid, Tanh, Relu, LeakyRelu(0.1), Elu(1.5), Gelu, Swish, Softplus];
+    let h = 1e-6;
+    for act in acts.iter() {
+        for i in -40..=40 {
+            let x = i as f64 * 0.15 + 0.01;
+            let numeric = (act.call(x + h) - act.call(x - h)) / (2. * h);
+            assert!((act.diff(x) - numeric).abs() < 1e-6, "{:?} at {}: {} vs {}", act, x, act.diff(x), numeric);
+        }
+    }
+    assert_eq!(LeakyRelu(0.1).call(-2.), -0.2);
+    assert_eq!(LeakyRelu(0.1).call(3.), 3.);
+    assert_eq!(Elu(2.).call(f64::NEG_INFINITY), -2.);
+    assert!((Gelu.call(1.0_f64) - 0.8411919906082768).abs() < 1e-12);
+    assert_eq!(Swish.call(0.), 0.);
+    assert_eq!(Softplus.call(1000.), 1000.);
+    assert!((Softplus.call(0.) - 2_f64.ln()).abs() < 1e-15);
+    assert_eq!(Tanh.call(1000.), 1.);
+}
+
+#[test]
+fn test_custom_activation() {
+    use crate::prelude::*;
+
+    struct Cube;
+    impl<T: NumT> ActivationFn<T> for Cube {
+        fn name(&self) -> &str {
+            "test_cube"
+        }
+        fn call(&self, x: T) -> T {
+            x * x * x
+        }
+        fn diff(&self, x: T) -> T {
+            T::from(3).unwrap() * x * x
+        }
+    }
+    assert!(Activation::<f64>::by_name("test_cube").is_none());
+    let cube = Activation::<f64>::custom(Cube);
+    assert_eq!(cube.call(2.), 8.);
+    assert_eq!(cube.diff(2.), 12.);
+    assert_eq!(format!("{:?}", cube), "Custom(test_cube)");
+    // registered per element type
+    assert!(cube.map(|a| a as f32).is_none());
+    Activation::<f32>::custom(Cube);
+    assert_eq!(cube.map(|a| a as f32).unwrap().call(-2.), -8.);
+
+    // a model of the custom activation is saved and loaded
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([2]), &Shape::new([2]), cube));
+    let mut buf = Vec::new();
+    nn.write_to(&mut buf).unwrap();
+    let loaded = Sequential::<f64>::read_from(&mut buf.as_slice()).unwrap();
+    let x = Tensor::new(&Shape::new([2]), vec![0.5, -1.]);
+    assert_eq!(loaded.predict(&x).unwrap(), nn.predict(&x).unwrap());
+    assert_eq!(loaded.layers()[0].get_activation().call(3.), 27.);
 }
\ No newline at end of file
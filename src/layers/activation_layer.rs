@@ -0,0 +1,120 @@
+//! An activation as a layer of its own, applied element by element, so that models
+//! are composed as e.g. `Dense -> Relu -> Dense -> Softmax` with the dense layers
+//! left unactivated (`Activation::No`).
+//!
+//! Its output z is its input and its activated output a is the activation of it,
+//! like the other layers: the delta passed back is multiplied by the derivative of the
+//! activation of the layer before, which is `Activation::No` in such compositions.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([2]), sh!([4]), Activation::No));
+//!     nn.add(ActivationLayer::new(sh!([4]), Activation::Relu));
+//!     nn.add(Dense::new(sh!([4]), sh!([1]), Activation::No));
+//!     assert!(nn.predict(&Tensor::new(sh!([2]), vec![1., -1.])).is_ok());
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+#[derive(Debug)]
+pub struct ActivationLayer<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) activation: Activation<T>,
+}
+
+impl<T: NumT> ActivationLayer<T> {
+    pub fn new(i_shape: &Shape, act: Activation<T>) -> Self {
+        ActivationLayer { input_shape: i_shape.clone(), output_shape: i_shape.clone(), activation: act }
+    }
+}
+
+impl<T: NumT> Layer<T> for ActivationLayer<T> {
+    fn get_activation(&self) -> Activation<T> {
+        self.activation
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.input_shape.clone()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.output_shape.clone()
+    }
+    fn get_weight_count(&self) -> usize {
+        0
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("activate", &self.output_shape, &output.shape)?;
+        Ok(output.map(|x| self.activation.call(x)))
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, _a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        check_len("add_weight_delta_to", 0, cum_dw.len())?;
+        check_shape("add_weight_delta_to", &delta.shape, &cum_db.shape)?;
+        Ok(())
+    }
+    fn descend(&mut self, _rate: T, dw: &[T], _db: &Tensor<T>) -> Result<()> {
+        check_len("descend", 0, dw.len())?;
+        Ok(())
+    }
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("activation", self.activation).shape(&self.input_shape))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        Ok(if activate { input.map(|x| self.activation.call(x)) } else { input.clone() })
+    }
+    fn forward_batch(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        batch_len("forward_batch", &input.shape, &self.input_shape)?;
+        Ok(if activate { input.map(|x| self.activation.call(x)) } else { input.clone() })
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let mut lst_delta = delta.clone();
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+    fn backpropagate_batch(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        let n = batch_len("backpropagate_batch", &delta.shape, &self.output_shape)?;
+        check_shape("backpropagate_batch", &self.input_shape.batched(n), &z_lst.shape)?;
+        let mut lst_delta = delta.clone();
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[test]
+fn test_activation_layer() {
+    use crate::models::{ Model, sequential::Sequential, losses::Loss };
+    use crate::layers::dense::Dense;
+    let shape = Shape::new([3]);
+    let relu = ActivationLayer::new(&shape, Activation::Relu);
+    let x = Tensor::<f64>::new(&shape, vec![-1., 0.5, 2.]);
+    assert_eq!(relu.forward_propagate(&x, false).unwrap(), x);
+    assert_eq!(relu.forward_propagate(&x, true).unwrap().as_slice(), &[0., 0.5, 2.]);
+    let delta = Tensor::new(&shape, vec![1., 2., 3.]);
+    assert_eq!(relu.backpropagate_delta(&delta, &x, &Activation::Relu).unwrap().as_slice(), &[0., 2., 3.]);
+
+    // Dense -> Tanh -> Dense predicts and trains as the dense layer activated by tanh
+    let mut fused = Sequential::<f64>::new(Loss::MeanSquare);
+    fused.add(Dense::with_init(&Shape::new([2]), &shape, Activation::Tanh, crate::layers::init::Initializer::XavierUniform, 3));
+    fused.add(Dense::with_init(&shape, &Shape::new([1]), Activation::No, crate::layers::init::Initializer::XavierUniform, 4));
+    let mut split = Sequential::<f64>::new(Loss::MeanSquare);
+    split.add(Dense::with_init(&Shape::new([2]), &shape, Activation::No, crate::layers::init::Initializer::XavierUniform, 3));
+    split.add(ActivationLayer::new(&shape, Activation::Tanh));
+    split.add(Dense::with_init(&shape, &Shape::new([1]), Activation::No, crate::layers::init::Initializer::XavierUniform, 4));
+    let inputs = vec![Tensor::new(&Shape::new([2]), vec![0.5, -0.3]), Tensor::new(&Shape::new([2]), vec![-1., 0.8])];
+    let outputs = vec![Tensor::new(&Shape::new([1]), vec![0.2]), Tensor::new(&Shape::new([1]), vec![-0.4])];
+    for _ in 0..3 {
+        fused.train_once(&inputs, &outputs, 2, 0.1, false);
+        split.train_once(&inputs, &outputs, 2, 0.1, false);
+    }
+    for x in inputs.iter() {
+        let (a, b) = (fused.predict(x).unwrap().get([0]), split.predict(x).unwrap().get([0]));
+        assert!((a - b).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,412 @@
+//! Batch normalization over the first axis, the channels: each channel is normalized by
+//! a mean and a variance, then scaled by `gamma` and shifted by `beta`, both trained.
+//!
+//! The batched training (`Sequential::train_once_batched`) normalizes by the statistics of
+//! the batch, through the whole batch in the backward pass. Every other pass normalizes
+//! by the running statistics, a moving average of the statistics seen while training,
+//! updated after each batch in `Layer::finish_batch`. Out of the training mode,
+//! see `Layer::set_training`, the statistics are frozen and every pass uses them.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let bn = BatchNorm::<f64>::new(sh!([2, 1, 2]), Activation::No);
+//!     let x = Tensor::new(sh!([2, 1, 2]), vec![1., 3., -1., 1.]);
+//!     // initially the identity, up to the epsilon
+//!     let y = bn.forward_propagate(&x, true).unwrap();
+//!     assert!((y.get([0, 0, 1]) - 3.).abs() < 1e-4 && (y.get([1, 0, 0]) + 1.).abs() < 1e-4);
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+use std::sync::Mutex;
+
+/// The momentum of the running statistics by default
+pub const BATCH_NORM_MOMENTUM: f64 = 0.1;
+/// The epsilon added to the variances by default
+pub const BATCH_NORM_EPS: f64 = 1e-5;
+
+/// The sums and the squared sums of each channel and their count, since the last batch
+#[derive(Debug)]
+struct Pending<T> {
+    sum: Vec<T>,
+    sum_sq: Vec<T>,
+    count: usize,
+}
+
+impl<T> Default for Pending<T> {
+    fn default() -> Self {
+        Pending { sum: Vec::new(), sum_sq: Vec::new(), count: 0 }
+    }
+}
+
+#[derive(Debug)]
+pub struct BatchNorm<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) gamma: Vec<T>,
+    pub(crate) beta: Vec<T>,
+    pub(crate) running_mean: Vec<T>,
+    pub(crate) running_var: Vec<T>,
+    pub(crate) momentum: T,
+    pub(crate) eps: T,
+    pub(crate) activation: Activation<T>,
+    pub(crate) training: bool,
+    pending: Mutex<Pending<T>>,
+}
+
+impl<T: NumT> BatchNorm<T> {
+    pub fn new(i_shape: &Shape, act: Activation<T>) -> Self {
+        BatchNorm::with_momentum(i_shape, T::from(BATCH_NORM_MOMENTUM).unwrap(), T::from(BATCH_NORM_EPS).unwrap(), act)
+    }
+    /// Like `new`, the running statistics moving by `momentum` after each batch
+    pub fn with_momentum(i_shape: &Shape, momentum: T, eps: T, act: Activation<T>) -> Self {
+        if i_shape.rank() == 0 || i_shape.size() == 0 {
+            panic!("Batch normalization needs a non-empty input!");
+        }
+        if momentum < T::zero() || momentum > T::one() || eps <= T::zero() {
+            panic!("The momentum should be in [0, 1] and the epsilon positive!");
+        }
+        let channels = i_shape[0];
+        BatchNorm {
+            input_shape: i_shape.clone(),
+            output_shape: i_shape.clone(),
+            gamma: vec![T::one(); channels],
+            beta: vec![T::zero(); channels],
+            running_mean: vec![T::zero(); channels],
+            running_var: vec![T::one(); channels],
+            momentum,
+            eps,
+            activation: act,
+            training: true,
+            pending: Mutex::new(Pending::default()),
+        }
+    }
+
+    fn channels(&self) -> usize {
+        self.input_shape[0]
+    }
+    /// The elements of a channel in a sample
+    fn map_len(&self) -> usize {
+        self.input_shape.size() / self.channels()
+    }
+
+    /// The mean and the variance of each channel over the samples of `x`
+    fn moments(&self, x: &[T]) -> (Vec<T>, Vec<T>) {
+        let (channels, map) = (self.channels(), self.map_len());
+        let mut mean = vec![T::zero(); channels];
+        let mut var = vec![T::zero(); channels];
+        let n = T::from(x.len() / channels).unwrap();
+        for (i, v) in x.iter().enumerate() {
+            mean[i / map % channels] += *v / n;
+        }
+        for (i, v) in x.iter().enumerate() {
+            let c = i / map % channels;
+            var[c] += (*v - mean[c]) * (*v - mean[c]) / n;
+        }
+        (mean, var)
+    }
+    /// Add the statistics of the samples of `x` to those of the batch, while training
+    fn accumulate(&self, x: &[T], mean: &[T], var: &[T]) {
+        if !self.training {
+            return;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.sum.is_empty() {
+            pending.sum = vec![T::zero(); self.channels()];
+            pending.sum_sq = vec![T::zero(); self.channels()];
+        }
+        let count = x.len() / self.channels();
+        let n = T::from(count).unwrap();
+        for c in 0..self.channels() {
+            pending.sum[c] += mean[c] * n;
+            pending.sum_sq[c] += (var[c] + mean[c] * mean[c]) * n;
+        }
+        pending.count += count;
+    }
+    /// The normalized `x`, each element of channel `c` being `(x - mean[c]) / sqrt(var[c] + eps)`
+    fn normalized(&self, x: &[T], mean: &[T], var: &[T]) -> Vec<T> {
+        let (channels, map) = (self.channels(), self.map_len());
+        x.iter().enumerate().map(|(i, v)| {
+            let c = i / map % channels;
+            (*v - mean[c]) / (var[c] + self.eps).sqrt()
+        }).collect()
+    }
+    /// `gamma * x_hat + beta` of each element
+    fn affine(&self, x_hat: &[T]) -> Vec<T> {
+        let (channels, map) = (self.channels(), self.map_len());
+        x_hat.iter().enumerate().map(|(i, x)| {
+            let c = i / map % channels;
+            self.gamma[c] * *x + self.beta[c]
+        }).collect()
+    }
+    /// Add the deltas of `gamma`, the sums of `delta * x_hat` of each channel, and of `beta`,
+    /// the deltas summed over the samples
+    fn add_deltas(&self, delta: &[T], x_hat: &[T], cum_dw: &mut [T], cum_db: &mut Tensor<T>) {
+        let (channels, map) = (self.channels(), self.map_len());
+        for (i, (d, x)) in delta.iter().zip(x_hat.iter()).enumerate() {
+            cum_dw[i / map % channels] += *d * *x;
+        }
+        for d in delta.chunks(self.input_shape.size()) {
+            cum_db.flattened.iter_mut().zip(d.iter()).for_each(|(db, d)| *db += *d);
+        }
+    }
+}
+
+impl<T: NumT> Layer<T> for BatchNorm<T> {
+    fn get_activation(&self) -> Activation<T> {
+        self.activation
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.input_shape.clone()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.output_shape.clone()
+    }
+    fn get_weight_count(&self) -> usize {
+        self.gamma.len()
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let mut output = self.affine(&self.normalized(&input.flattened, &self.running_mean, &self.running_var));
+        if activate {
+            output.iter_mut().for_each(|o| *o = self.activation.call(*o));
+        }
+        Ok(Tensor::new(&self.output_shape, output))
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("activate", &self.output_shape, &output.shape)?;
+        Ok(output.map(|x| self.activation.call(x)))
+    }
+    fn forward_train(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        let z = self.forward_propagate(input, false)?;
+        // a sample is normalized by the running statistics, only recording its own
+        if self.training {
+            let (mean, var) = self.moments(&input.flattened);
+            self.accumulate(&input.flattened, &mean, &var);
+        }
+        let a = z.map(|x| self.activation.call(x));
+        Ok((z, a))
+    }
+    fn forward_train_batch(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        let n = batch_len("forward_train_batch", &input.shape, &self.input_shape)?;
+        if !self.training {
+            let z = Tensor::stack(&self.output_shape, &input.unstack().iter().map(|x| self.forward_propagate(x, false)).collect::<Result<Vec<_>>>()?)?;
+            let a = z.map(|x| self.activation.call(x));
+            return Ok((z, a));
+        }
+        let (mean, var) = self.moments(&input.flattened);
+        self.accumulate(&input.flattened, &mean, &var);
+        let z = Tensor::new(&self.output_shape.batched(n), self.affine(&self.normalized(&input.flattened, &mean, &var)));
+        let a = z.map(|x| self.activation.call(x));
+        Ok((z, a))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        // through the running statistics, a scaling of each channel
+        let map = self.map_len();
+        let mut lst_delta = delta.clone();
+        for (i, d) in lst_delta.flattened.iter_mut().enumerate() {
+            let c = i / map;
+            *d *= self.gamma[c] / (self.running_var[c] + self.eps).sqrt();
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+    fn backpropagate_batch(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        let n = batch_len("backpropagate_batch", &delta.shape, &self.output_shape)?;
+        check_shape("backpropagate_batch", &self.input_shape.batched(n), &z_lst.shape)?;
+        if !self.training {
+            let deltas = delta.unstack().iter().zip(z_lst.unstack().iter())
+                .map(|(d, z)| self.backpropagate_delta(d, z, sigma_lst))
+                .collect::<Result<Vec<_>>>()?;
+            return Tensor::stack(&self.input_shape, &deltas);
+        }
+        // d x = gamma / sqrt(var + eps) * (d - mean(d) - x_hat * mean(d * x_hat)) over each channel
+        let input: Vec<T> = z_lst.flattened.iter().map(|z| sigma_lst.call(*z)).collect();
+        let (mean, var) = self.moments(&input);
+        let x_hat = self.normalized(&input, &mean, &var);
+        let (channels, map) = (self.channels(), self.map_len());
+        let count = T::from(n * map).unwrap();
+        let mut mean_d = vec![T::zero(); channels];
+        let mut mean_dx = vec![T::zero(); channels];
+        for (i, (d, x)) in delta.flattened.iter().zip(x_hat.iter()).enumerate() {
+            let c = i / map % channels;
+            mean_d[c] += *d / count;
+            mean_dx[c] += *d * *x / count;
+        }
+        let mut lst_delta = Tensor::new(&z_lst.shape, delta.flattened.iter().zip(x_hat.iter()).enumerate().map(|(i, (d, x))| {
+            let c = i / map % channels;
+            self.gamma[c] / (var[c] + self.eps).sqrt() * (*d - mean_d[c] - *x * mean_dx[c])
+        }).collect());
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        check_shape("add_weight_delta_to", &self.output_shape, &delta.shape)?;
+        check_shape("add_weight_delta_to", &self.input_shape, &a_lst.shape)?;
+        check_len("add_weight_delta_to", self.gamma.len(), cum_dw.len())?;
+        check_shape("add_weight_delta_to", &self.output_shape, &cum_db.shape)?;
+        let x_hat = self.normalized(&a_lst.flattened, &self.running_mean, &self.running_var);
+        self.add_deltas(&delta.flattened, &x_hat, cum_dw, cum_db);
+        Ok(())
+    }
+    fn add_weight_delta_batch_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        let n = batch_len("add_weight_delta_batch_to", &delta.shape, &self.output_shape)?;
+        check_shape("add_weight_delta_batch_to", &self.input_shape.batched(n), &a_lst.shape)?;
+        check_len("add_weight_delta_batch_to", self.gamma.len(), cum_dw.len())?;
+        check_shape("add_weight_delta_batch_to", &self.output_shape, &cum_db.shape)?;
+        let x_hat = if self.training {
+            let (mean, var) = self.moments(&a_lst.flattened);
+            self.normalized(&a_lst.flattened, &mean, &var)
+        } else {
+            self.normalized(&a_lst.flattened, &self.running_mean, &self.running_var)
+        };
+        self.add_deltas(&delta.flattened, &x_hat, cum_dw, cum_db);
+        Ok(())
+    }
+    fn descend(&mut self, rate: T, dw: &[T], db: &Tensor<T>) -> Result<()> {
+        check_shape("descend", &self.output_shape, &db.shape)?;
+        check_len("descend", self.gamma.len(), dw.len())?;
+        let grads = self.gradients(dw, db);
+        self.gamma.iter_mut().zip(grads[0].iter()).for_each(|(g, d)| *g -= rate * *d);
+        self.beta.iter_mut().zip(grads[1].iter()).for_each(|(b, d)| *b -= rate * *d);
+        Ok(())
+    }
+    fn parameters(&self) -> Vec<&[T]> {
+        vec![&self.gamma, &self.beta]
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        vec![&mut self.gamma, &mut self.beta]
+    }
+    /// The running mean then the running variance
+    fn state(&self) -> Vec<&[T]> {
+        vec![&self.running_mean, &self.running_var]
+    }
+    fn state_mut(&mut self) -> Vec<&mut [T]> {
+        vec![&mut self.running_mean, &mut self.running_var]
+    }
+    fn gradients(&self, dw: &[T], db: &Tensor<T>) -> Vec<Vec<T>> {
+        vec![dw.to_vec(), db.flattened.chunks(self.map_len()).map(|d| d.iter().copied().sum()).collect()]
+    }
+    /// The scales and the shifts are not penalized
+    fn regularized(&self) -> Vec<bool> {
+        vec![false; 2]
+    }
+    fn passes_units(&self) -> bool {
+        true
+    }
+    fn pass_units(&mut self, map: &[usize]) -> Result<Vec<usize>> {
+        check_unit_map("pass_units", map, self.channels())?;
+        for v in [&mut self.gamma, &mut self.beta, &mut self.running_mean, &mut self.running_var] {
+            *v = map.iter().map(|k| v[*k]).collect();
+        }
+        self.input_shape = with_units(&self.input_shape, map.len());
+        self.output_shape = self.input_shape.clone();
+        *self.pending.get_mut().unwrap() = Pending::default();
+        Ok(map.to_vec())
+    }
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+        *self.pending.get_mut().unwrap() = Pending::default();
+    }
+    fn finish_batch(&mut self) {
+        let pending = std::mem::take(self.pending.get_mut().unwrap());
+        if pending.count == 0 {
+            return;
+        }
+        let (m, n) = (self.momentum, T::from(pending.count).unwrap());
+        for c in 0..self.channels() {
+            let mean = pending.sum[c] / n;
+            let var = (pending.sum_sq[c] / n - mean * mean).max(T::zero());
+            self.running_mean[c] = (T::one() - m) * self.running_mean[c] + m * mean;
+            self.running_var[c] = (T::one() - m) * self.running_var[c] + m * var;
+        }
+    }
+    fn discard_batch(&mut self) {
+        *self.pending.get_mut().unwrap() = Pending::default();
+    }
+    fn record(&self) -> Option<LayerRecord<T>> {
+        // the momentum, the epsilon and the running statistics
+        let floats: Vec<f64> = [self.momentum, self.eps].iter()
+            .chain(self.running_mean.iter())
+            .chain(self.running_var.iter())
+            .map(|x| x.to_f64().unwrap())
+            .collect();
+        Some(LayerRecord::new("batch_norm", self.activation).shape(&self.input_shape).floats(&floats).parameters_of(self))
+    }
+}
+
+#[test]
+fn test_batch_norm() {
+    let shape = Shape::new([2, 3]);
+    let mut bn = BatchNorm::<f64>::new(&shape, Activation::No);
+    bn.gamma = vec![1.5, -0.5];
+    bn.beta = vec![0.2, 1.];
+    let samples: Vec<_> = (0..4).map(|i| Tensor::new(&shape, (0..6).map(|j| ((i * 6 + j) * 7 % 11) as f64 / 3.).collect())).collect();
+    let batch = Tensor::stack(&shape, &samples).unwrap();
+
+    // each channel of the batch is normalized then scaled and shifted
+    let (z, a) = bn.forward_train_batch(&batch).unwrap();
+    assert_eq!(z, a);
+    for c in 0..2 {
+        let xs: Vec<f64> = z.flattened.chunks(3).skip(c).step_by(2).flatten().copied().collect();
+        let mean = xs.iter().sum::<f64>() / 12.;
+        let var = xs.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / 12.;
+        assert!((mean - bn.beta[c]).abs() < 1e-12);
+        assert!((var - bn.gamma[c] * bn.gamma[c]).abs() < 1e-4);
+    }
+
+    // compare the batch gradients with finite differences of L = sum(c * y)
+    let coef = Tensor::new(&z.shape, (0..24).map(|x| ((x * 5 % 9) as f64 - 4.) / 4.).collect());
+    let loss = |bn: &BatchNorm<f64>, x: &Tensor<f64>| -> f64 {
+        bn.forward_train_batch(x).unwrap().0.flattened.iter().zip(coef.flattened.iter()).map(|(y, k)| y * k).sum()
+    };
+    let eps = 1e-6;
+    let back = bn.backpropagate_batch(&coef, &batch, &Activation::No).unwrap();
+    for i in 0..batch.flattened.len() {
+        let (mut plus, mut minus) = (batch.clone(), batch.clone());
+        plus.flattened[i] += eps;
+        minus.flattened[i] -= eps;
+        let num = (loss(&bn, &plus) - loss(&bn, &minus)) / (2. * eps);
+        assert!((back.flattened[i] - num).abs() < 1e-6);
+    }
+    let mut dw = vec![0.; 2];
+    let mut db = Tensor::zeros(&shape);
+    bn.add_weight_delta_batch_to(&coef, &batch, &mut dw, &mut db).unwrap();
+    let grads = bn.gradients(&dw, &db);
+    for (p, grad) in grads.iter().enumerate() {
+        for (c, g) in grad.iter().enumerate() {
+            bn.parameters_mut()[p][c] += eps;
+            let plus = loss(&bn, &batch);
+            bn.parameters_mut()[p][c] -= 2. * eps;
+            let minus = loss(&bn, &batch);
+            bn.parameters_mut()[p][c] += eps;
+            assert!((g - (plus - minus) / (2. * eps)).abs() < 1e-6);
+        }
+    }
+
+    // the running statistics move towards those of the batches after each
+    let (mean, var) = bn.moments(&batch.flattened);
+    // switching the mode drops the statistics recorded above
+    bn.set_training(true);
+    bn.forward_train_batch(&batch).unwrap();
+    bn.finish_batch();
+    for c in 0..2 {
+        assert!((bn.running_mean[c] - 0.1 * mean[c]).abs() < 1e-12);
+        assert!((bn.running_var[c] - (0.9 + 0.1 * var[c])).abs() < 1e-12);
+    }
+
+    // out of training, the batches are normalized like the samples
+    bn.set_training(false);
+    let (z, _) = bn.forward_train_batch(&batch).unwrap();
+    assert_eq!(z.unstack()[1], bn.forward_propagate(&samples[1], false).unwrap());
+    bn.finish_batch();
+    assert!((bn.running_mean[0] - 0.1 * mean[0]).abs() < 1e-12);
+
+    // the record keeps the running statistics
+    let loaded = bn.record().unwrap().into_layer().unwrap();
+    assert_eq!(loaded.forward_propagate(&samples[2], true).unwrap(), bn.forward_propagate(&samples[2], true).unwrap());
+}
@@ -0,0 +1,309 @@
+//! Experimental binary and ternary weight layers, for extremely constrained devices.
+//!
+//! `Binarized` wraps a `Dense` or a `Conv2D` layer whose weights are quantized per output
+//! unit: `alpha * sign(w)` for the binary weights (XNOR-Net), or `alpha` times -1, 0 or 1
+//! for the ternary weights, 0 below `TERNARY_THRESHOLD` times the mean magnitude (TWN),
+//! `alpha` being the mean magnitude of the nonzero weights of the unit.
+//! The passes run with the quantized weights, while the real weights behind them, clipped
+//! into `[-1, 1]`, are the parameters trained by the gradients of the quantized ones,
+//! the straight-through estimator, and quantized again after each update.
+//!
+//! `PackedBinaryDense` runs the inference of a binary dense layer on inputs binarized
+//! as well, the signs packed into bits so that the products are XNOR and popcount.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     use easynn::layers::binary::{ Binarized, WeightQuantization, PackedBinaryDense };
+//!     let layer = Binarized::<f64>::new(Dense::new(sh!([64]), sh!([4]), Activation::No), WeightQuantization::Binary);
+//!     // every weight of a unit is of the same magnitude
+//!     let w = layer.quantized_weights();
+//!     assert!(w[..64].iter().all(|x| x.abs() == w[0].abs()));
+//!     let packed = PackedBinaryDense::new(&layer).unwrap();
+//!     let x = Tensor::new(sh!([64]), (0..64).map(|i| if i % 3 == 0 { 1. } else { -1. }).collect());
+//!     let (a, b) = (packed.forward(&x).unwrap(), layer.forward_propagate(&x, true).unwrap());
+//!     assert!(a.as_slice().iter().zip(b.as_slice()).all(|(a, b)| (a - b).abs() < 1e-9));
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+/// The threshold of the ternary weights, as a fraction of the mean magnitude of the unit
+pub const TERNARY_THRESHOLD: f64 = 0.7;
+
+/// How the weights are quantized
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WeightQuantization {
+    /// `alpha * sign(w)`, `sign(0)` being 1
+    Binary,
+    /// `alpha * sign(w)` above the threshold, 0 otherwise
+    Ternary,
+}
+
+/// The quantized weights, each of the `units` consecutive chunks scaled on its own
+pub fn quantize<T: NumT>(weights: &[T], units: usize, mode: WeightQuantization) -> Vec<T> {
+    weights.chunks(weights.len() / units.max(1)).flat_map(|w| {
+        let mean = w.iter().map(|x| x.abs()).sum::<T>() / T::from(w.len()).unwrap();
+        let threshold = match mode {
+            WeightQuantization::Binary => T::neg_infinity(),
+            WeightQuantization::Ternary => T::from(TERNARY_THRESHOLD).unwrap() * mean,
+        };
+        let kept: Vec<T> = w.iter().filter(|x| x.abs() > threshold).map(|x| x.abs()).collect();
+        let alpha = match kept.len() {
+            0 => T::zero(),
+            n => kept.into_iter().sum::<T>() / T::from(n).unwrap(),
+        };
+        w.iter().map(move |x| match x.abs() > threshold {
+            true if *x < T::zero() => -alpha,
+            true => alpha,
+            false => T::zero(),
+        })
+    }).collect()
+}
+
+/// The units of a `Dense` or a `Conv2D`, whose weights are quantized together: one per bias,
+/// the outputs of a `Dense` of any shape, the output channels of a `Conv2D`
+pub(crate) fn units_of<T: NumT>(layer: &dyn Layer<T>) -> usize {
+    layer.parameters().get(1).map_or(1, |b| b.len())
+}
+
+/// A layer of quantized weights, the first parameter of the wrapped layer
+pub struct Binarized<T: NumT> {
+    pub(crate) inner: Box<dyn Layer<T>>,
+    /// The real weights, in `[-1, 1]`
+    pub(crate) latent: Vec<T>,
+    pub(crate) mode: WeightQuantization,
+}
+
+impl<T: NumT> Binarized<T> {
+    /// Quantize the weights of the layer, a `Dense` or a `Conv2D`, from its current ones
+    pub fn new<L: Layer<T> + 'static>(layer: L, mode: WeightQuantization) -> Self {
+        Self::from_boxed(Box::new(layer), mode)
+    }
+    pub(crate) fn from_boxed(layer: Box<dyn Layer<T>>, mode: WeightQuantization) -> Self {
+        let latent = match layer.parameters().first() {
+            Some(w) => w.to_vec(),
+            None => panic!("Binarized needs a layer of weights!"),
+        };
+        let mut b = Binarized { inner: layer, latent, mode };
+        b.settle();
+        b
+    }
+    pub fn mode(&self) -> WeightQuantization {
+        self.mode
+    }
+    /// The quantized weights used by the passes
+    pub fn quantized_weights(&self) -> &[T] {
+        self.inner.parameters()[0]
+    }
+    /// Clip the real weights and quantize them into the wrapped layer
+    fn settle(&mut self) {
+        self.latent.iter_mut().for_each(|w| *w = w.max(-T::one()).min(T::one()));
+        let q = quantize(&self.latent, units_of(self.inner.as_ref()), self.mode);
+        self.inner.parameters_mut()[0].copy_from_slice(&q);
+    }
+}
+
+impl<T: NumT> Layer<T> for Binarized<T> {
+    fn get_activation(&self) -> Activation<T> {
+        self.inner.get_activation()
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.inner.get_input_shape()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.inner.get_output_shape()
+    }
+    fn get_weight_count(&self) -> usize {
+        self.inner.get_weight_count()
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        self.inner.forward_propagate(input, activate)
+    }
+    fn forward_propagate_into(&self, input: &Tensor<T>, output: &mut Tensor<T>) -> Result<()> {
+        self.inner.forward_propagate_into(input, output)
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        self.inner.activate(output)
+    }
+    fn forward_train(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        self.inner.forward_train(input)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        self.inner.backpropagate_delta(delta, z_lst, sigma_lst)
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        self.inner.add_weight_delta_to(delta, a_lst, cum_dw, cum_db)
+    }
+    /// The real weights descend by the gradients of the quantized ones
+    fn descend(&mut self, rate: T, dw: &[T], db: &Tensor<T>) -> Result<()> {
+        let grads = self.inner.gradients(dw, db);
+        self.inner.descend(rate, dw, db)?;
+        self.latent.iter_mut().zip(grads[0].iter()).for_each(|(w, g)| *w -= rate * *g);
+        self.settle();
+        Ok(())
+    }
+    fn forward_batch(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        self.inner.forward_batch(input, activate)
+    }
+    fn forward_train_batch(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        self.inner.forward_train_batch(input)
+    }
+    fn backpropagate_batch(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        self.inner.backpropagate_batch(delta, z_lst, sigma_lst)
+    }
+    fn add_weight_delta_batch_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        self.inner.add_weight_delta_batch_to(delta, a_lst, cum_dw, cum_db)
+    }
+
+    /// The real weights then the other parameters of the wrapped layer
+    fn parameters(&self) -> Vec<&[T]> {
+        let mut params = self.inner.parameters();
+        params[0] = &self.latent;
+        params
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        let mut params = self.inner.parameters_mut();
+        params[0] = &mut self.latent;
+        params
+    }
+    fn parameter_names(&self) -> Vec<String> {
+        self.inner.parameter_names()
+    }
+    fn regularized(&self) -> Vec<bool> {
+        self.inner.regularized()
+    }
+    fn gradients(&self, dw: &[T], db: &Tensor<T>) -> Vec<Vec<T>> {
+        self.inner.gradients(dw, db)
+    }
+    fn set_training(&mut self, training: bool) {
+        self.inner.set_training(training);
+    }
+    /// Quantize again after an update of the parameters, e.g. by an optimizer
+    fn finish_batch(&mut self) {
+        self.settle();
+        self.inner.finish_batch();
+    }
+    fn discard_batch(&mut self) {
+        self.inner.discard_batch();
+    }
+    fn name(&self) -> String {
+        format!("binarized_{}", self.inner.name())
+    }
+    /// The record of the wrapped layer holding the real weights, and a configuration of
+    /// the quantization (0 binary, 1 ternary)
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let mut inner = self.inner.record()?;
+        *inner.parameters.first_mut()? = self.latent.clone();
+        let mode = match self.mode { WeightQuantization::Binary => 0, WeightQuantization::Ternary => 1 };
+        Some(LayerRecord::new("binarized", self.get_activation()).values(&[mode]).inner(vec![inner]))
+    }
+}
+
+/// The signs of the values packed into bits, 1 for a negative value, 64 values a word
+pub fn pack_signs<T: NumT>(values: &[T]) -> Vec<u64> {
+    values.chunks(64).map(|c| c.iter().enumerate().fold(0, |bits, (i, x)| bits | (((*x < T::zero()) as u64) << i))).collect()
+}
+
+/// The dot product of two vectors of `len` signs packed by `pack_signs`: the count of
+/// the equal signs (XNOR) less the count of the different ones (popcount of XOR)
+pub fn xnor_dot(a: &[u64], b: &[u64], len: usize) -> i64 {
+    let different: u32 = a.iter().zip(b.iter()).map(|(x, y)| (x ^ y).count_ones()).sum();
+    len as i64 - 2 * different as i64
+}
+
+/// The inference of a binary `Dense` layer of `[units]` on binarized inputs: each input is
+/// approximated by `beta * sign(x)`, `beta` being its mean magnitude, so that each output
+/// is `alpha * beta * xnor_dot + bias`, exact for inputs of a single magnitude
+#[derive(Debug, Clone)]
+pub struct PackedBinaryDense<T: NumT> {
+    input_shape: Shape,
+    output_shape: Shape,
+    /// The packed signs of the weights, a row of words per output
+    rows: Vec<Vec<u64>>,
+    alpha: Vec<T>,
+    bias: Vec<T>,
+    activation: Activation<T>,
+}
+
+impl<T: NumT> PackedBinaryDense<T> {
+    pub fn new(layer: &Binarized<T>) -> Result<Self> {
+        if layer.mode != WeightQuantization::Binary || layer.inner.name() != "dense" {
+            return Err(EasynnError::invalid("PackedBinaryDense::new", "only the binary Dense layers can be packed"));
+        }
+        let (input_shape, output_shape) = (layer.get_input_shape(), layer.get_output_shape());
+        let params = layer.inner.parameters();
+        let rows = params[0].chunks(input_shape.size());
+        Ok(PackedBinaryDense {
+            alpha: rows.clone().map(|r| r[0].abs()).collect(),
+            rows: rows.map(pack_signs).collect(),
+            bias: params[1].to_vec(),
+            activation: layer.get_activation(),
+            input_shape,
+            output_shape,
+        })
+    }
+    /// The activated outputs
+    pub fn forward(&self, input: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("PackedBinaryDense::forward", &self.input_shape, &input.shape)?;
+        let n = input.flattened.len();
+        let beta = input.flattened.iter().map(|x| x.abs()).sum::<T>() / T::from(n).unwrap();
+        let x = pack_signs(&input.flattened);
+        let output = self.rows.iter().zip(self.alpha.iter().zip(self.bias.iter()))
+            .map(|(row, (a, b))| self.activation.call(*a * beta * T::from(xnor_dot(row, &x, n)).unwrap() + *b))
+            .collect();
+        Ok(Tensor::new(&self.output_shape, output))
+    }
+}
+
+#[test]
+fn test_binary_weights() {
+    use crate::layers::dense::Dense;
+    use crate::layers::conv::Conv2D;
+    use crate::models::{ Model, sequential::Sequential, losses::Loss };
+    let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-12);
+    assert!(close(&quantize(&[0.5, -0.3, 0.2, 0.], 2, WeightQuantization::Binary), &[0.4, -0.4, 0.1, 0.1]));
+    assert!(close(&quantize(&[0.9, -0.1, 0.5, -0.6], 1, WeightQuantization::Ternary), &[2. / 3., 0., 2. / 3., -2. / 3.]));
+    assert_eq!(xnor_dot(&pack_signs(&[1., -1., 2.]), &pack_signs(&[1., 1., 3.]), 3), 1);
+
+    // learning a linearly separable task through the quantized weights
+    let mut nn = Sequential::<f64>::new(Loss::BinaryCrossEntropy);
+    nn.add(Binarized::new(Dense::new(&Shape::new([2]), &Shape::new([1]), Activation::Sigmoid), WeightQuantization::Binary));
+    let inputs: Vec<_> = (0..16).map(|i| Tensor::new(&Shape::new([2]), vec![(i % 4) as f64 - 1.5, (i / 4) as f64 - 1.6])).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(&Shape::new([1]), vec![(x.get([0]) + x.get([1]) > 0.) as u8 as f64])).collect();
+    for _ in 0..100 {
+        nn.train_once(&inputs, &truths, 4, 0.5, false);
+    }
+    let correct = inputs.iter().zip(truths.iter()).filter(|(x, y)| (nn.predict(x).unwrap().get([0]) > 0.5) == (y.get([0]) > 0.5)).count();
+    assert_eq!(correct, 16);
+    let w = nn.layers()[0].parameters()[0].to_vec();
+    assert!(w.iter().all(|x| x.abs() <= 1.));
+    assert_eq!(nn.parameter_names()[0], "binarized_dense0.weight");
+
+    // the ternary filters of a convolution, the optimizer updating the real weights
+    let mut conv = Binarized::<f64>::new(Conv2D::new(&Shape::new([1, 3, 3]), 2, (2, 2), (1, 1), crate::tensor::shape::Padding::Valid, Activation::No), WeightQuantization::Ternary);
+    let before = conv.latent.clone();
+    conv.parameters_mut()[0][0] += 0.5;
+    conv.finish_batch();
+    assert_eq!(conv.latent[0], (before[0] + 0.5).min(1.));
+    assert!(conv.quantized_weights().chunks(4).all(|f| f.iter().filter(|x| **x != 0.).all(|x| x.abs() == f.iter().map(|x| x.abs()).fold(0., f64::max))));
+    assert!(PackedBinaryDense::new(&conv).is_err());
+
+    // each output of a dense layer of a multi-dim output is a unit
+    let grid = Binarized::<f64>::new(Dense::new(&Shape::new([3]), &Shape::new([2, 2]), Activation::No), WeightQuantization::Binary);
+    assert!(grid.quantized_weights().chunks(3).all(|u| u.iter().all(|x| x.abs() == u[0].abs())));
+    assert_eq!(grid.quantized_weights(), quantize(&grid.latent, 4, WeightQuantization::Binary));
+
+    // saved with the real weights, quantized again when loaded
+    let mut buf = Vec::new();
+    nn.write_to(&mut buf).unwrap();
+    let loaded = Sequential::<f64>::read_from(&mut buf.as_slice()).unwrap();
+    assert_eq!(loaded.layers()[0].parameters(), nn.layers()[0].parameters());
+    assert!(inputs.iter().all(|x| loaded.predict(x).unwrap() == nn.predict(x).unwrap()));
+    let record = conv.record().unwrap();
+    assert_eq!(record.inner[0].parameters[0], conv.latent);
+    let layer = record.into_layer().unwrap();
+    let x = Tensor::new(&Shape::new([1, 3, 3]), (0..9).map(|i| i as f64 / 9.).collect());
+    assert_eq!(layer.forward_propagate(&x, true).unwrap(), conv.forward_propagate(&x, true).unwrap());
+}
@@ -0,0 +1,517 @@
+//! The 2D convolution layer, working on `[channels, height, width]` inputs.
+//!
+//! Each kernel splits its work into chunks processed in parallel, see `parallel`:
+//! the forward pass and the weight deltas are chunked by output channel,
+//! and the backward pass by input channel, so no two threads write the same element.
+//!
+//! Kernels of at least `FFT_MIN_KERNEL_AREA` elements are applied in the forward pass
+//! through the FFT instead, whose cost does not grow with the kernel size, when it takes
+//! fewer operations for the channels and the image size of the layer.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let conv = Conv2D::<f64>::new(sh!([1, 28, 28]), 8, (3, 3), (1, 1), Padding::Same, Activation::Relu);
+//!     assert_eq!(conv.get_output_shape(), Shape::new([8, 28, 28]));
+//! ```
+
+use crate::layers::*;
+use crate::layers::activation::*;
+
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+use std::any::TypeId;
+
+use crate::layers::tune::{ choose_threads, determine_thread };
+use crate::parallel;
+use crate::layers::init::Initializer;
+use crate::layers::record::LayerRecord;
+use crate::tensor::check_allocation;
+
+/// The kernel area from which the forward pass may go through the FFT
+pub const FFT_MIN_KERNEL_AREA: usize = 64;
+
+/// Weights are arranged as `[out_channel, in_channel, kernel_h, kernel_w]`,
+/// with one bias per output channel
+#[derive(Debug)]
+pub struct Conv2D<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) kernel: (usize, usize),
+    pub(crate) stride: (usize, usize),
+    /// The padding resolved into (top, bottom, left, right)
+    pub(crate) padding: (usize, usize, usize, usize),
+    pub(crate) weight: Vec<T>,
+    pub(crate) bias: Vec<T>,
+    pub(crate) activation: Activation<T>,
+}
+
+impl<T: NumT> Conv2D<T> {
+    /// The input shape is `[in_channels, height, width]`,
+    /// the output is `[out_channels, out_height, out_width]`
+    pub fn new(i_shape: &Shape, out_channels: usize, kernel: (usize, usize), stride: (usize, usize), padding: Padding, act: Activation<T>) -> Self {
+        match Self::try_new(i_shape, out_channels, kernel, stride, padding, act) {
+            Ok(layer) => layer,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `new`, checking that the kernel fits, that the weight and output counts
+    /// do not overflow and that the weights are under the allocation cap
+    pub fn try_new(i_shape: &Shape, out_channels: usize, kernel: (usize, usize), stride: (usize, usize), padding: Padding, act: Activation<T>) -> std::result::Result<Self, EasynnError> {
+        if i_shape.rank() != 3 || out_channels == 0 {
+            return Err(EasynnError::invalid("Conv2D::try_new", "a [channels, height, width] input and at least one output channel are needed"));
+        }
+        let window = i_shape.window_output(kernel, stride, padding)?;
+        let output_shape = Shape::new([out_channels, window[1], window[2]]);
+        output_shape.checked_size()?;
+        let dims = [out_channels, i_shape[0], kernel.0, kernel.1];
+        let wlen = dims.iter().try_fold(1_usize, |n, d| n.checked_mul(*d)).ok_or_else(|| EasynnError::SizeOverflow(dims.to_vec()))?;
+        check_allocation::<T>(wlen)?;
+        let mut rng = rand::thread_rng();
+        Ok(Conv2D::<T> {
+            input_shape: i_shape.clone(),
+            output_shape,
+            kernel,
+            stride,
+            padding: padding.amounts((i_shape[1], i_shape[2]), kernel, stride),
+            weight: (0..wlen).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect(),
+            bias: (0..out_channels).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect(),
+            activation: act,
+        })
+    }
+
+    /// Like `new`, with the weights drawn by the initializer from a RNG of the seed, and zero biases
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_init(i_shape: &Shape, out_channels: usize, kernel: (usize, usize), stride: (usize, usize), padding: Padding, act: Activation<T>, init: Initializer<T>, seed: u64) -> Self {
+        let mut conv = Self::new(i_shape, out_channels, kernel, stride, padding, act);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let area = kernel.0 * kernel.1;
+        conv.weight = init.sample(conv.weight.len(), i_shape[0] * area, out_channels * area, &mut rng);
+        conv.bias = vec![T::zero(); out_channels];
+        conv
+    }
+
+    fn in_channels(&self) -> usize {
+        self.input_shape[0]
+    }
+
+    fn out_channels(&self) -> usize {
+        self.output_shape[0]
+    }
+
+    /// The weights of one output channel
+    fn filter_len(&self) -> usize {
+        self.in_channels() * self.kernel.0 * self.kernel.1
+    }
+
+    /// The position in one input map read by output `(oy, ox)` at kernel `(ky, kx)`,
+    /// None if it falls on the padding
+    fn input_pos(&self, oy: usize, ox: usize, ky: usize, kx: usize) -> Option<usize> {
+        let (h, w) = (self.input_shape[1], self.input_shape[2]);
+        let y = (oy * self.stride.0 + ky).checked_sub(self.padding.0)?;
+        let x = (ox * self.stride.1 + kx).checked_sub(self.padding.2)?;
+        if y < h && x < w { Some(y * w + x) } else { None }
+    }
+
+    /// The forward kernel, writing the output into `output` using `threads` chunks
+    fn forward_into(&self, input: &Tensor<T>, output: &mut [T], threads: usize, activate: bool) {
+        let omap = self.output_shape[1] * self.output_shape[2];
+        let imap = self.input_shape[1] * self.input_shape[2];
+        let ow = self.output_shape[2];
+        let (kh, kw) = self.kernel;
+        let flen = self.filter_len();
+        let ch_per_chunk = self.out_channels().div_ceil(threads);
+        parallel::chunks_mut(flen * output.len(), output, ch_per_chunk * omap, |i, o_chk| {
+            for (j, o_map) in o_chk.chunks_mut(omap).enumerate() {
+                let co = i * ch_per_chunk + j;
+                let filter = &self.weight[co * flen..(co + 1) * flen];
+                for (p, o) in o_map.iter_mut().enumerate() {
+                    let (oy, ox) = (p / ow, p % ow);
+                    *o = self.bias[co];
+                    for ky in 0..kh {
+                        for kx in 0..kw {
+                            if let Some(pos) = self.input_pos(oy, ox, ky, kx) {
+                                for c in 0..self.in_channels() {
+                                    *o += filter[(c * kh + ky) * kw + kx] * input.flattened[c * imap + pos];
+                                }
+                            }
+                        }
+                    }
+                    if activate {
+                        *o = self.activation.call(*o);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Whether the forward pass goes through the FFT, computed in f64, so only for f32 and f64,
+    /// for large kernels when it takes fewer operations than the direct pass
+    fn use_fft(&self) -> bool {
+        let real = TypeId::of::<T>() == TypeId::of::<f32>() || TypeId::of::<T>() == TypeId::of::<f64>();
+        let (direct, fft) = self.forward_costs();
+        real && self.kernel.0 * self.kernel.1 >= FFT_MIN_KERNEL_AREA && fft < direct
+    }
+
+    /// The size of the transforms, at least that of the padded input so nothing read wraps around
+    fn fft_size(&self) -> (usize, usize) {
+        let (pt, pb, pl, pr) = self.padding;
+        ((self.input_shape[1] + pt + pb).next_power_of_two(), (self.input_shape[2] + pl + pr).next_power_of_two())
+    }
+
+    /// The multiply-adds of the forward pass, directly and through the FFT,
+    /// every filter being transformed at each pass
+    fn forward_costs(&self) -> (f64, f64) {
+        let (nh, nw) = self.fft_size();
+        let plane = (nh * nw) as f64;
+        let (ci, co) = (self.in_channels() as f64, self.out_channels() as f64);
+        let direct = co * ci * (self.kernel.0 * self.kernel.1 * self.output_shape[1] * self.output_shape[2]) as f64;
+        // a transform of n points takes about 2 n log2(n), a complex product 4
+        let fft = (ci + co * ci + co) * 2. * plane * plane.log2() + co * ci * 4. * plane;
+        (direct, fft)
+    }
+
+    /// The forward pass through the FFT: each output map is the inverse transform of
+    /// the sum over input channels of `X_c * conj(W_c)`, the cross-correlation, read at the strides.
+    /// Each output channel transforms its filters one at a time, holding a plane of them at most.
+    fn forward_fft_into(&self, input: &Tensor<T>, output: &mut [T], activate: bool) {
+        let (h, w) = (self.input_shape[1], self.input_shape[2]);
+        let (pt, _, pl, _) = self.padding;
+        let (nh, nw) = self.fft_size();
+        let (kh, kw) = self.kernel;
+        let (ci, co) = (self.in_channels(), self.out_channels());
+        let zero = Complex::new(0., 0.);
+
+        let mut x = vec![zero; ci * nh * nw];
+        for c in 0..ci {
+            for y in 0..h {
+                for xx in 0..w {
+                    x[(c * nh + y + pt) * nw + xx + pl].re = input.flattened[(c * h + y) * w + xx].to_f64().unwrap();
+                }
+            }
+        }
+        let x = Tensor::new(&Shape::new([ci, nh, nw]), x).fft2().unwrap();
+
+        let plane = nh * nw;
+        let ow = self.output_shape[2];
+        parallel::chunks_mut(co * ci * plane, output, self.output_shape[1] * ow, |o, o_map| {
+            let mut acc = vec![zero; plane];
+            for (c, filter) in self.weight[o * ci * kh * kw..(o + 1) * ci * kh * kw].chunks(kh * kw).enumerate() {
+                let mut f = vec![zero; plane];
+                for ky in 0..kh {
+                    for kx in 0..kw {
+                        f[ky * nw + kx].re = filter[ky * kw + kx].to_f64().unwrap();
+                    }
+                }
+                let f = Tensor::new(&Shape::new([nh, nw]), f).fft2().unwrap();
+                let xs = &x.flattened[c * plane..(c + 1) * plane];
+                for ((a, xv), fv) in acc.iter_mut().zip(xs.iter()).zip(f.flattened.iter()) {
+                    *a += xv * fv.conj();
+                }
+            }
+            let corr = Tensor::new(&Shape::new([nh, nw]), acc).ifft2().unwrap();
+            for (p, out) in o_map.iter_mut().enumerate() {
+                let (oy, ox) = (p / ow * self.stride.0, p % ow * self.stride.1);
+                *out = self.bias[o] + T::from(corr.flattened[oy * nw + ox].re).unwrap();
+                if activate {
+                    *out = self.activation.call(*out);
+                }
+            }
+        });
+    }
+
+    /// The kernel scattering the deltas back through the weights into `prod`,
+    /// one input map per chunk item
+    fn weight_delta_prod_into(&self, delta: &Tensor<T>, prod: &mut [T], threads: usize) {
+        let omap = self.output_shape[1] * self.output_shape[2];
+        let imap = self.input_shape[1] * self.input_shape[2];
+        let ow = self.output_shape[2];
+        let (kh, kw) = self.kernel;
+        let flen = self.filter_len();
+        let ch_per_chunk = self.in_channels().div_ceil(threads);
+        parallel::chunks_mut(flen * delta.flattened.len(), prod, ch_per_chunk * imap, |i, p_chk| {
+            p_chk.iter_mut().for_each(|p| *p = T::zero());
+            for (j, p_map) in p_chk.chunks_mut(imap).enumerate() {
+                let c = i * ch_per_chunk + j;
+                for co in 0..self.out_channels() {
+                    let d_map = &delta.flattened[co * omap..(co + 1) * omap];
+                    for (p, &d) in d_map.iter().enumerate() {
+                        let (oy, ox) = (p / ow, p % ow);
+                        for ky in 0..kh {
+                            for kx in 0..kw {
+                                if let Some(pos) = self.input_pos(oy, ox, ky, kx) {
+                                    p_map[pos] += self.weight[co * flen + (c * kh + ky) * kw + kx] * d;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl<T: NumT> Layer<T> for Conv2D<T> {
+    fn get_activation(&self) -> Activation<T> {
+        self.activation
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.input_shape.clone()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.output_shape.clone()
+    }
+    fn get_weight_count(&self) -> usize {
+        self.weight.len()
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let mut output = Tensor::<T>::zeros(&self.output_shape);
+        if self.use_fft() {
+            self.forward_fft_into(input, &mut output.flattened, activate);
+            return Ok(output);
+        }
+        let olen = output.flattened.len();
+        let threads = choose_threads::<T, _>("conv2d_forward", (self.filter_len(), olen), |t| {
+            self.forward_into(input, &mut output.flattened, t, activate);
+        }).min(self.out_channels());
+        self.forward_into(input, &mut output.flattened, threads, activate);
+        Ok(output)
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("activate", &self.output_shape, &output.shape)?;
+        let mut act_vec = vec![T::zero(); output.shape.size()];
+        parallel::zip_mut(&mut act_vec, &output.flattened, |a, o| {
+            *a = self.activation.call(*o);
+        });
+        Ok(Tensor::<T>::new(&self.output_shape, act_vec))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        let threads = choose_threads::<T, _>("conv2d_backpropagate", (self.filter_len(), delta.flattened.len()), |t| {
+            self.weight_delta_prod_into(delta, &mut lst_delta.flattened, t);
+        }).min(self.in_channels());
+        self.weight_delta_prod_into(delta, &mut lst_delta.flattened, threads);
+
+        // dot product sigma-1(z^l) and w^Td^{l+1}
+        parallel::zip_mut(&mut lst_delta.flattened, &z_lst.flattened, |d, z| {
+            *d *= sigma_lst.diff(*z);
+        });
+        Ok(lst_delta)
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        check_shape("add_weight_delta_to", &self.output_shape, &delta.shape)?;
+        check_shape("add_weight_delta_to", &self.input_shape, &a_lst.shape)?;
+        check_len("add_weight_delta_to", self.weight.len(), cum_dw.len())?;
+        check_shape("add_weight_delta_to", &self.output_shape, &cum_db.shape)?;
+        let omap = self.output_shape[1] * self.output_shape[2];
+        let imap = self.input_shape[1] * self.input_shape[2];
+        let ow = self.output_shape[2];
+        let (kh, kw) = self.kernel;
+        let flen = self.filter_len();
+        let threads = determine_thread(cum_dw.len() * omap).min(self.out_channels());
+        let ch_per_chunk = self.out_channels().div_ceil(threads);
+        parallel::chunks_mut(cum_dw.len() * omap, cum_dw, ch_per_chunk * flen, |i, w_chk| {
+            for (j, filter) in w_chk.chunks_mut(flen).enumerate() {
+                let co = i * ch_per_chunk + j;
+                // Do filter += d[co] correlated with a
+                for (p, &d) in delta.flattened[co * omap..(co + 1) * omap].iter().enumerate() {
+                    let (oy, ox) = (p / ow, p % ow);
+                    for ky in 0..kh {
+                        for kx in 0..kw {
+                            if let Some(pos) = self.input_pos(oy, ox, ky, kx) {
+                                for c in 0..self.in_channels() {
+                                    filter[(c * kh + ky) * kw + kx] += d * a_lst.flattened[c * imap + pos];
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+        // add delta to cum_db, summed per channel when descending
+        parallel::zip_mut(&mut cum_db.flattened, &delta.flattened, |db, d| {
+            *db += *d;
+        });
+        Ok(())
+    }
+    fn descend(&mut self, rate: T, dw: &[T], db: &Tensor<T>) -> Result<()> {
+        check_shape("descend", &self.output_shape, &db.shape)?;
+        check_len("descend", self.weight.len(), dw.len())?;
+        // do weight update
+        parallel::zip_mut(&mut self.weight, dw, |wi, dwi| {
+            *wi -= rate * *dwi;
+        });
+
+        // do bias update, each bias is shared by a whole output map
+        let omap = self.output_shape[1] * self.output_shape[2];
+        parallel::for_each_mut(&mut self.bias, |co, bi| {
+            *bi -= rate * db.flattened[co * omap..(co + 1) * omap].iter().copied().sum::<T>();
+        });
+        Ok(())
+    }
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let (t, b, l, r) = self.padding;
+        let values = [self.out_channels(), self.kernel.0, self.kernel.1, self.stride.0, self.stride.1, t, b, l, r];
+        Some(LayerRecord::new("conv2d", self.activation).shape(&self.input_shape).values(&values).parameters_of(self))
+    }
+    fn parameters(&self) -> Vec<&[T]> {
+        vec![&self.weight, &self.bias]
+    }
+    fn gradients(&self, dw: &[T], db: &Tensor<T>) -> Vec<Vec<T>> {
+        let omap = self.output_shape[1] * self.output_shape[2];
+        vec![dw.to_vec(), db.flattened.chunks(omap).map(|d| d.iter().copied().sum()).collect()]
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        vec![&mut self.weight, &mut self.bias]
+    }
+    /// The units are the channels: the output map selects the filters, the input map their channels
+    fn supports_remap(&self) -> bool {
+        true
+    }
+    fn remap_units(&mut self, input_map: Option<&[usize]>, output_map: Option<&[usize]>) -> Result<()> {
+        check_unit_map("remap_units", input_map.unwrap_or_default(), self.in_channels())?;
+        check_unit_map("remap_units", output_map.unwrap_or_default(), self.out_channels())?;
+        if let Some(map) = output_map {
+            let flen = self.filter_len();
+            self.weight = map.iter().flat_map(|k| self.weight[k * flen..(k + 1) * flen].iter().copied()).collect();
+            self.bias = map.iter().map(|k| self.bias[*k]).collect();
+            self.output_shape = with_units(&self.output_shape, map.len());
+        }
+        if let Some(map) = input_map {
+            let mut copies = vec![0; self.in_channels()];
+            map.iter().for_each(|k| copies[*k] += 1);
+            let (area, copies) = (self.kernel.0 * self.kernel.1, &copies);
+            self.weight = self.weight.chunks(self.filter_len()).flat_map(|filter| map.iter().flat_map(move |k| {
+                filter[k * area..(k + 1) * area].iter().map(move |w| *w / T::from(copies[*k]).unwrap())
+            })).collect();
+            self.input_shape = with_units(&self.input_shape, map.len());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_conv2d_forward() {
+    // one 3x3 input map, two 2x2 filters, stride 1, valid
+    let mut conv = Conv2D::<f64>::new(&Shape::new([1, 3, 3]), 2, (2, 2), (1, 1), Padding::Valid, Activation::No);
+    conv.weight = vec![
+        1., 0., 0., 1.,
+        0., 1., 1., 0.,
+    ];
+    conv.bias = vec![0., 10.];
+    let input = Tensor::new(&Shape::new([1, 3, 3]), vec![
+        1., 2., 3.,
+        4., 5., 6.,
+        7., 8., 9.,
+    ]);
+    let output = conv.forward_propagate(&input, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([2, 2, 2]), vec![
+        6., 8., 12., 14.,
+        16., 18., 22., 24.,
+    ]));
+
+    // same padding with stride 2 reads zeros outside
+    let mut conv = Conv2D::<f64>::new(&Shape::new([1, 3, 3]), 1, (3, 3), (2, 2), Padding::Same, Activation::No);
+    conv.weight = vec![1.; 9];
+    conv.bias = vec![0.];
+    let output = conv.forward_propagate(&input, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([1, 2, 2]), vec![12., 16., 24., 28.]));
+}
+
+#[test]
+fn test_conv2d_fft() {
+    // the FFT path agrees with the direct kernel, with strides and uneven padding
+    // the FFT is taken for large kernels over many outputs only
+    for (i_shape, kernel, stride, padding, use_fft) in [
+        ([2, 9, 7], (4, 3), (1, 1), Padding::Valid, false),
+        ([3, 10, 11], (5, 4), (2, 3), Padding::Same, false),
+        ([1, 20, 20], (9, 9), (1, 2), Padding::Explicit(1, 2, 3, 0), false),
+        ([4, 32, 32], (15, 15), (1, 1), Padding::Same, true),
+    ] {
+        let i_shape = Shape::new(i_shape);
+        let conv = Conv2D::<f64>::new(&i_shape, 3, kernel, stride, padding, Activation::Tanh);
+        let input = Tensor::new(&i_shape, (0..i_shape.size()).map(|x| ((x * 7 % 13) as f64 - 6.) / 6.).collect());
+        let mut direct = Tensor::zeros(&conv.output_shape);
+        conv.forward_into(&input, &mut direct.flattened, 2, true);
+        let mut fft = Tensor::zeros(&conv.output_shape);
+        conv.forward_fft_into(&input, &mut fft.flattened, true);
+        crate::assert_tensor_eq!(direct, fft, 0., 1e-9);
+        assert_eq!(conv.use_fft(), use_fft);
+        assert_eq!(conv.forward_propagate(&input, true).unwrap().flattened.len(), direct.flattened.len());
+    }
+}
+
+#[test]
+fn test_conv2d_gradients() {
+    // compare the analytic gradients with finite differences of L = sum(c * y)
+    let conv = Conv2D::<f64>::new(&Shape::new([2, 4, 5]), 3, (3, 2), (2, 1), Padding::Same, Activation::No);
+    let input = Tensor::new(&Shape::new([2, 4, 5]), (0..40).map(|x| ((x * 7 % 11) as f64 - 5.) / 5.).collect());
+    let out_shape = conv.get_output_shape();
+    let coef = Tensor::new(&out_shape, (0..out_shape.size()).map(|x| ((x * 3 % 7) as f64 - 3.) / 3.).collect());
+    let loss = |c: &Conv2D<f64>, x: &Tensor<f64>| -> f64 {
+        c.forward_propagate(x, true).unwrap().flattened.iter().zip(coef.flattened.iter()).map(|(y, k)| y * k).sum()
+    };
+    let eps = 1e-6;
+
+    let d_input = conv.backpropagate_delta(&coef, &input, &Activation::No).unwrap();
+    for i in 0..input.flattened.len() {
+        let (mut plus, mut minus) = (input.clone(), input.clone());
+        plus.flattened[i] += eps;
+        minus.flattened[i] -= eps;
+        let num = (loss(&conv, &plus) - loss(&conv, &minus)) / (2. * eps);
+        assert!((d_input.flattened[i] - num).abs() < 1e-6);
+    }
+
+    let mut dw = vec![0.; conv.get_weight_count()];
+    let mut db = Tensor::zeros(&out_shape);
+    conv.add_weight_delta_to(&coef, &input, &mut dw, &mut db).unwrap();
+    let perturbed = |i: usize, e: f64| {
+        let mut c = Conv2D::new(&Shape::new([2, 4, 5]), 3, (3, 2), (2, 1), Padding::Same, Activation::No);
+        c.weight = conv.weight.clone();
+        c.bias = conv.bias.clone();
+        c.weight[i] += e;
+        c
+    };
+    for (i, g) in dw.iter().enumerate() {
+        let num = (loss(&perturbed(i, eps), &input) - loss(&perturbed(i, -eps), &input)) / (2. * eps);
+        assert!((g - num).abs() < 1e-6);
+    }
+
+    // descending on the bias sums the deltas of each map
+    let mut conv = conv;
+    let before = conv.bias.clone();
+    conv.descend(1., &vec![0.; dw.len()], &db).unwrap();
+    let omap = out_shape[1] * out_shape[2];
+    for (co, (b, b0)) in conv.bias.iter().zip(before.iter()).enumerate() {
+        let sum: f64 = coef.flattened[co * omap..(co + 1) * omap].iter().sum();
+        assert!((b0 - b - sum).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_conv2d_learns() {
+    use crate::prelude::*;
+
+    // detect whether the bright pixel is in the left or the right half
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Conv2D::new(sh!([1, 4, 4]), 2, (2, 2), (2, 2), Padding::Valid, Activation::Tanh));
+    nn.add(Dense::new(sh!([2, 2, 2]), sh!([1]), Activation::No));
+    let mut inputs = Vec::new();
+    let mut truths = Vec::new();
+    for p in 0..16 {
+        let mut x = vec![0.; 16];
+        x[p] = 1.;
+        inputs.push(Tensor::new(sh!([1, 4, 4]), x));
+        truths.push(Tensor::new(sh!([1]), vec![if p % 4 < 2 { 0. } else { 1. }]));
+    }
+    let before = nn.evaluate(&inputs, &truths);
+    for _ in 0..300 {
+        nn.train_once(&inputs, &truths, 4, 0.2, false);
+    }
+    assert!(nn.evaluate(&inputs, &truths) < before / 4.);
+}
@@ -0,0 +1,240 @@
+//! Linear-chain conditional random field (CRF) for sequence tagging.
+//!
+//! The CRF sits on top of an encoder producing emission scores of shape
+//! `[seq_len, tags]`. It scores a tag sequence y by
+//! `start[y_0] + sum_t emission[t][y_t] + sum_t trans[y_{t-1}][y_t] + end[y_last]`.
+//! Training minimizes the negative log likelihood, whose gradient w.r.t. the
+//! emissions can be fed back to the encoder.
+
+use crate::layers::*;
+use crate::tensor::num::log_add;
+
+use rand::Rng;
+
+/// The gradients of the negative log likelihood
+#[derive(Debug, Clone)]
+pub struct CrfGradients<T: NumT> {
+    /// w.r.t. the emissions, to be backpropagated to the encoder
+    pub emissions: Tensor<T>,
+    pub transitions: Vec<T>,
+    pub start: Vec<T>,
+    pub end: Vec<T>,
+}
+
+#[derive(Debug)]
+pub struct Crf<T: NumT> {
+    pub(crate) tags: usize,
+    /// `transitions[i * tags + j]` scores tag i followed by tag j
+    pub(crate) transitions: Vec<T>,
+    pub(crate) start: Vec<T>,
+    pub(crate) end: Vec<T>,
+}
+
+impl<T: NumT> Crf<T> {
+    pub fn new(tags: usize) -> Self {
+        let mut rng = rand::thread_rng();
+        let mut init = |n: usize| -> Vec<T> {
+            (0..n).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect()
+        };
+        Crf::<T> {
+            tags,
+            transitions: init(tags * tags),
+            start: init(tags),
+            end: init(tags),
+        }
+    }
+
+    fn check(&self, emissions: &Tensor<T>) -> Result<usize> {
+        if emissions.shape.rank() != 2 || emissions.shape[1] != self.tags || emissions.shape[0] == 0 {
+            let len = emissions.shape.dims().first().copied().unwrap_or(0).max(1);
+            return Err(EasynnError::mismatch("emissions", &Shape::new([len, self.tags]), &emissions.shape));
+        }
+        Ok(emissions.shape[0])
+    }
+
+    fn trans(&self, i: usize, j: usize) -> T {
+        self.transitions[i * self.tags + j]
+    }
+
+    /// The most likely tag sequence
+    pub fn viterbi(&self, emissions: &Tensor<T>) -> Result<Vec<usize>> {
+        let len = self.check(emissions)?;
+        let k = self.tags;
+        let e = &emissions.flattened;
+        let mut score: Vec<T> = (0..k).map(|j| self.start[j] + e[j]).collect();
+        let mut back = vec![vec![0_usize; k]; len];
+        for (t, back_t) in back.iter_mut().enumerate().skip(1) {
+            let mut next = vec![T::zero(); k];
+            for (j, n) in next.iter_mut().enumerate() {
+                let (best_i, best) = (0..k).map(|i| (i, score[i] + self.trans(i, j)))
+                    .fold((0, T::neg_infinity()), |b, c| if c.1 > b.1 { c } else { b });
+                back_t[j] = best_i;
+                *n = best + e[t * k + j];
+            }
+            score = next;
+        }
+        let mut last = (0..k).fold(0, |b, j| if score[j] + self.end[j] > score[b] + self.end[b] { j } else { b });
+        let mut path = vec![last; len];
+        for t in (1..len).rev() {
+            last = back[t][last];
+            path[t - 1] = last;
+        }
+        Ok(path)
+    }
+
+    /// The unnormalized score of a tag sequence
+    fn score(&self, emissions: &Tensor<T>, tags: &[usize]) -> T {
+        let k = self.tags;
+        let mut s = self.start[tags[0]] + self.end[tags[tags.len() - 1]];
+        for (t, &y) in tags.iter().enumerate() {
+            s += emissions.flattened[t * k + y];
+            if t > 0 {
+                s += self.trans(tags[t - 1], y);
+            }
+        }
+        s
+    }
+
+    /// The forward and backward log potentials, and the log partition function
+    fn forward_backward(&self, emissions: &Tensor<T>, len: usize) -> (Vec<Vec<T>>, Vec<Vec<T>>, T) {
+        let k = self.tags;
+        let e = &emissions.flattened;
+        let ninf = T::neg_infinity();
+        let mut alpha = vec![vec![ninf; k]; len];
+        for j in 0..k {
+            alpha[0][j] = self.start[j] + e[j];
+        }
+        for t in 1..len {
+            for j in 0..k {
+                let a = (0..k).fold(ninf, |a, i| log_add(a, alpha[t - 1][i] + self.trans(i, j)));
+                alpha[t][j] = a + e[t * k + j];
+            }
+        }
+        let mut beta = vec![vec![ninf; k]; len];
+        beta[len - 1].copy_from_slice(&self.end);
+        for t in (0..len - 1).rev() {
+            for i in 0..k {
+                beta[t][i] = (0..k).fold(ninf, |b, j| log_add(b, self.trans(i, j) + e[(t + 1) * k + j] + beta[t + 1][j]));
+            }
+        }
+        let ln_z = (0..k).fold(ninf, |z, j| log_add(z, alpha[len - 1][j] + self.end[j]));
+        (alpha, beta, ln_z)
+    }
+
+    fn check_tags(&self, len: usize, tags: &[usize]) -> Result<()> {
+        check_len("tags", len, tags.len())?;
+        if let Some(y) = tags.iter().find(|&&y| y >= self.tags) {
+            return Err(EasynnError::invalid("tags", format!("the tag {} is not among the {} tags", y, self.tags)));
+        }
+        Ok(())
+    }
+
+    /// The negative log likelihood of the tags given the emissions
+    pub fn neg_log_likelihood(&self, emissions: &Tensor<T>, tags: &[usize]) -> Result<T> {
+        let len = self.check(emissions)?;
+        self.check_tags(len, tags)?;
+        let (_, _, ln_z) = self.forward_backward(emissions, len);
+        Ok(ln_z - self.score(emissions, tags))
+    }
+
+    /// The gradients of the negative log likelihood, i.e. the expected counts
+    /// under the model minus the observed counts
+    pub fn gradients(&self, emissions: &Tensor<T>, tags: &[usize]) -> Result<CrfGradients<T>> {
+        let len = self.check(emissions)?;
+        self.check_tags(len, tags)?;
+        let k = self.tags;
+        let e = &emissions.flattened;
+        let (alpha, beta, ln_z) = self.forward_backward(emissions, len);
+        let mut grads = CrfGradients {
+            emissions: Tensor::<T>::zeros(&emissions.shape),
+            transitions: vec![T::zero(); k * k],
+            start: vec![T::zero(); k],
+            end: vec![T::zero(); k],
+        };
+        for t in 0..len {
+            for j in 0..k {
+                let marginal = (alpha[t][j] + beta[t][j] - ln_z).exp();
+                grads.emissions.flattened[t * k + j] = marginal;
+                if t == 0 {
+                    grads.start[j] = marginal;
+                }
+                if t == len - 1 {
+                    grads.end[j] = marginal;
+                }
+                if t > 0 {
+                    for (i, a) in alpha[t - 1].iter().enumerate() {
+                        grads.transitions[i * k + j] += (*a + self.trans(i, j) + e[t * k + j] + beta[t][j] - ln_z).exp();
+                    }
+                }
+            }
+        }
+        for (t, &y) in tags.iter().enumerate() {
+            grads.emissions.flattened[t * k + y] -= T::one();
+            if t > 0 {
+                grads.transitions[tags[t - 1] * k + y] -= T::one();
+            }
+        }
+        grads.start[tags[0]] -= T::one();
+        grads.end[tags[len - 1]] -= T::one();
+        Ok(grads)
+    }
+
+    /// Do the learning of the transition, start and end scores
+    pub fn descend(&mut self, rate: T, grads: &CrfGradients<T>) -> Result<()> {
+        check_len("descend", self.transitions.len(), grads.transitions.len())?;
+        check_len("descend", self.tags, grads.start.len())?;
+        check_len("descend", self.tags, grads.end.len())?;
+        let params = self.transitions.iter_mut().chain(self.start.iter_mut()).chain(self.end.iter_mut());
+        let dparams = grads.transitions.iter().chain(grads.start.iter()).chain(grads.end.iter());
+        for (p, dp) in params.zip(dparams) {
+            *p -= rate * *dp;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crf() {
+    let mut crf = Crf::<f64>::new(2);
+    crf.transitions = vec![0.5, -0.3, 0.2, 0.8];
+    crf.start = vec![0.1, -0.3];
+    crf.end = vec![-0.4, 0.3];
+    let emissions = Tensor::<f64>::new(&Shape::new([3, 2]), vec![
+        1., 0.2,
+        0.3, 0.4,
+        -0.5, 0.9,
+    ]);
+    let tags = [0, 1, 1];
+
+    // brute force over the 8 tag sequences
+    let seqs: Vec<[usize; 3]> = (0..8_usize).map(|s| [s / 4, s / 2 % 2, s % 2]).collect();
+    let ln_z = seqs.iter().map(|y| crf.score(&emissions, y).exp()).sum::<f64>().ln();
+    let nll = crf.neg_log_likelihood(&emissions, &tags).unwrap();
+    assert!((nll - (ln_z - crf.score(&emissions, &tags))).abs() < 1e-12);
+    let best = seqs.iter().max_by(|a, b| crf.score(&emissions, *a).partial_cmp(&crf.score(&emissions, *b)).unwrap()).unwrap();
+    assert_eq!(crf.viterbi(&emissions).unwrap(), best.to_vec());
+
+    // compare the gradients with finite differences
+    let grads = crf.gradients(&emissions, &tags).unwrap();
+    let eps = 1e-6;
+    for i in 0..6 {
+        let (mut ep, mut em) = (emissions.clone(), emissions.clone());
+        ep.flattened[i] += eps;
+        em.flattened[i] -= eps;
+        let num = (crf.neg_log_likelihood(&ep, &tags).unwrap() - crf.neg_log_likelihood(&em, &tags).unwrap()) / (2. * eps);
+        assert!((num - grads.emissions.flattened[i]).abs() < 1e-6, "expected {}, got {}", num, grads.emissions.flattened[i]);
+    }
+    for i in 0..4 {
+        let orig = crf.transitions[i];
+        crf.transitions[i] = orig + eps;
+        let lp = crf.neg_log_likelihood(&emissions, &tags).unwrap();
+        crf.transitions[i] = orig - eps;
+        let lm = crf.neg_log_likelihood(&emissions, &tags).unwrap();
+        crf.transitions[i] = orig;
+        assert!(((lp - lm) / (2. * eps) - grads.transitions[i]).abs() < 1e-6);
+    }
+
+    // learning lowers the loss
+    crf.descend(0.1, &grads).unwrap();
+    assert!(crf.neg_log_likelihood(&emissions, &tags).unwrap() < nll);
+}
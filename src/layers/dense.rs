@@ -1,28 +1,14 @@
 use crate::layers::*;
 use crate::layers::activation::*;
 
-extern crate crossbeam;
-extern crate num_cpus;
-extern crate rayon;
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
 
-use rayon::prelude::*;
-use rand::Rng;
-
-use std::cmp;
-
-/// This is used to determine how much threads to spawn.
-/// 
-/// The length argument is the count of total works (e.g. mul counts)
-///
-/// Need to consider SIMD.
-fn determine_thread(len: usize) -> usize {
-    const FALL_BACK_SIZE: usize = 256;
-    let ncpu = num_cpus::get();
-    if len / ncpu < FALL_BACK_SIZE {
-        return 1;
-    }
-    cmp::min(ncpu, len / FALL_BACK_SIZE)
-}
+use crate::linalg::{ gemm, axpy, MatRef };
+use crate::parallel;
+use crate::tensor::check_allocation;
+use crate::layers::init::Initializer;
+use crate::layers::record::LayerRecord;
 
 /// Weight are arranged in flattened style:
 /// every i^th consecutive (input size) items are the weight
@@ -34,8 +20,8 @@ fn determine_thread(len: usize) -> usize {
 ///  - weight: `[21~01,21~02,21~11,21~12;22~01,22~02,22~11,22~12;...;...]`
 ///  - bias: `[21,22;31;32]`
 ///
-/// When processed in parallel, each output coordinates a `mult`,
-/// and each `chunk` includes many `mult`.
+/// The products of the passes, single or batched, are computed by `linalg::gemm`,
+/// with the weight as an `[outputs, inputs]` matrix.
 /// 
 #[derive(Debug)]
 pub struct Dense<T: NumT> {
@@ -46,31 +32,103 @@ pub struct Dense<T: NumT> {
     pub(crate) activation: Activation<T>,
 }
 
+/// Helpers like `slice_iter(w, len, j)` is implemented to access the weight slice j,
+/// containing len(== input length) elements
+macro_rules! slice_iter {
+    ($w: expr, $len: expr, $j: expr) => {
+        $w[$j*$len..($j+1)*$len].iter()
+    }
+}
+
 impl<T: NumT> Dense<T> {
+    /// Panics if the weights cannot be allocated, see `try_new`
     pub fn new(i_shape: &Shape, o_shape: &Shape, act: Activation<T>) -> Self {
-        let ilen = i_shape.size();
-        let olen = o_shape.size();
+        match Self::try_new(i_shape, o_shape, act) {
+            Ok(layer) => layer,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Create a layer, checking that the weight count does not overflow
+    /// and that the weights are under the allocation cap
+    pub fn try_new(i_shape: &Shape, o_shape: &Shape, act: Activation<T>) -> std::result::Result<Self, EasynnError> {
         let mut rng = rand::thread_rng();
-        Dense::<T> {
+        Self::try_build(i_shape, o_shape, act, |wlen, _, _| {
+            (0..wlen).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect()
+        }, true)
+    }
+
+    /// Create a layer of weights drawn by the initializer from a RNG of the seed,
+    /// and zero biases. Panics like `new`
+    pub fn with_init(i_shape: &Shape, o_shape: &Shape, act: Activation<T>, init: Initializer<T>, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let built = Self::try_build(i_shape, o_shape, act, |wlen, fan_in, fan_out| {
+            init.sample(wlen, fan_in, fan_out, &mut rng)
+        }, false);
+        match built {
+            Ok(layer) => layer,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// The checked construction, `weights(count, fan_in, fan_out)` drawing the weights,
+    /// and the bias drawn with them if `random_bias`, otherwise zeros
+    fn try_build<F: FnMut(usize, usize, usize) -> Vec<T>>(i_shape: &Shape, o_shape: &Shape, act: Activation<T>, mut weights: F, random_bias: bool) -> std::result::Result<Self, EasynnError> {
+        let ilen = i_shape.checked_size()?;
+        let olen = o_shape.checked_size()?;
+        let wlen = ilen.checked_mul(olen).ok_or_else(|| EasynnError::SizeOverflow(vec![ilen, olen]))?;
+        check_allocation::<T>(wlen)?;
+        Ok(Dense::<T> {
             input_shape: i_shape.clone(),
             output_shape: o_shape.clone(),
-            weight: (0..ilen*olen).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect(),
-            bias: (0..olen).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect(),
+            weight: weights(wlen, ilen, olen),
+            bias: if random_bias { weights(olen, ilen, olen) } else { vec![T::zero(); olen] },
             activation: act,
+        })
+    }
+
+    /// An identity layer of `[units]` without activation, to deepen a trained model
+    /// without changing its function
+    pub fn identity(units: usize) -> Self {
+        let mut weight = vec![T::zero(); units * units];
+        for i in 0..units {
+            weight[i * units + i] = T::one();
+        }
+        Dense::<T> {
+            input_shape: Shape::new([units]),
+            output_shape: Shape::new([units]),
+            weight,
+            bias: vec![T::zero(); units],
+            activation: Activation::No,
         }
     }
-}
 
-/// Helpers like `slice_iter(w, len, j)` is implemented to access the weight slice j,
-/// containing len(== input length) elements
-macro_rules! slice_iter {
-    ($w: expr, $len: expr, $j: expr) => {
-        $w[$j*$len..($j+1)*$len].into_iter()
+    /// The weight as a `[outputs, inputs]` matrix
+    fn weight_mat(&self) -> MatRef<'_, T> {
+        MatRef::new(&self.weight, self.output_shape.size(), self.input_shape.size())
     }
-}
-macro_rules! slice_iter_mut {
-    ($w: expr, $len: expr, $j: expr) => {
-        $w[$j*$len..($j+1)*$len].iter_mut()
+    /// z = W x + b of a batch of `n` rows into `z`
+    fn affine_into(&self, input: &[T], n: usize, z: &mut [T]) -> Result<()> {
+        parallel::chunks_mut(z.len(), z, self.bias.len(), |_, row| row.copy_from_slice(&self.bias));
+        gemm(MatRef::new(input, n, self.input_shape.size()), self.weight_mat().t(), T::one(), z)
+    }
+    /// W^T d of a batch of `n` rows, times sigma-1(z^l)
+    fn delta_back(&self, delta: &[T], n: usize, z_lst: &[T], sigma_lst: &Activation<T>) -> Result<Vec<T>> {
+        let mut lst_delta = vec![T::zero(); z_lst.len()];
+        gemm(MatRef::new(delta, n, self.output_shape.size()), self.weight_mat(), T::zero(), &mut lst_delta)?;
+        parallel::zip_mut(&mut lst_delta, z_lst, |d, z| {
+            *d *= sigma_lst.diff(*z);
+        });
+        Ok(lst_delta)
+    }
+    /// dW += D^T A and db += the sum of the rows of D, of a batch of `n` rows
+    fn weight_delta_into(&self, delta: &[T], a_lst: &[T], n: usize, cum_dw: &mut [T], cum_db: &mut [T]) -> Result<()> {
+        let (ilen, olen) = (self.input_shape.size(), self.output_shape.size());
+        gemm(MatRef::new(delta, n, olen).t(), MatRef::new(a_lst, n, ilen), T::one(), cum_dw)?;
+        parallel::for_each_mut(cum_db, |j, db| {
+            *db += delta.iter().skip(j).step_by(olen).copied().sum::<T>();
+        });
+        Ok(())
     }
 }
 
@@ -89,143 +147,116 @@ impl<T: NumT> Layer<T> for Dense<T> {
     }
 
     fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
-        if input.shape != self.input_shape {
-            return Err(ShapeMismatchError);
-        }
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
         let mut output = Tensor::<T>::zeros(&self.output_shape);
-        let olen = output.flattened.len();
-        let ilen = input.flattened.len();
-
-        let threads = determine_thread(ilen * olen);
-        let mults_per_chunk = olen / threads + 1;
-        {
-            let o_chunks = output.flattened.chunks_mut(mults_per_chunk);
-            let w_chunks = self.weight.chunks(mults_per_chunk * ilen);
-            crossbeam::scope(|spawner| {
-                for (i, (o_chk, w_chk)) in o_chunks.zip(w_chunks).enumerate() {
-                    spawner.spawn(move |_| {
-                        for (j, o) in o_chk.into_iter().enumerate() {
-                            *o = self.bias[i*mults_per_chunk + j];
-                            // Do o = input.dot(w_chk[j])
-                            for (k, &w) in slice_iter!(w_chk, ilen, j).enumerate() {
-                                *o += w * input.flattened[k];
-                            }
-                            if activate {
-                                *o = self.activation.call(*o);
-                            }
-                        }
-                    });
-                }
-            }).unwrap(); 
+        self.affine_into(&input.flattened, 1, &mut output.flattened)?;
+        if activate {
+            parallel::for_each_mut(&mut output.flattened, |_, o| *o = self.activation.call(*o));
         }
         Ok(output)
     }
+    fn forward_propagate_into(&self, input: &Tensor<T>, output: &mut Tensor<T>) -> Result<()> {
+        check_shape("forward_propagate_into", &self.input_shape, &input.shape)?;
+        check_shape("forward_propagate_into", &self.output_shape, &output.shape)?;
+        self.affine_into(&input.flattened, 1, &mut output.flattened)?;
+        parallel::for_each_mut(&mut output.flattened, |_, o| *o = self.activation.call(*o));
+        Ok(())
+    }
     fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
-        if output.shape != self.output_shape {
-            return Err(ShapeMismatchError);
-        }
+        check_shape("activate", &self.output_shape, &output.shape)?;
         let mut act_vec = vec![T::zero(); output.shape.size()];
-        act_vec.par_iter_mut().zip(output.flattened.par_iter()).for_each(|(a, o)| {
+        parallel::zip_mut(&mut act_vec, &output.flattened, |a, o| {
             *a = self.activation.call(*o);
         });
         Ok(Tensor::<T>::new(&self.output_shape, act_vec))
     }
+    fn forward_train(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        check_shape("forward_train", &self.input_shape, &input.shape)?;
+        let z = self.forward_propagate(input, false)?;
+        let a = z.map(|z| self.activation.call(z));
+        Ok((z, a))
+    }
     fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
-        if delta.shape != self.output_shape || z_lst.shape != self.input_shape {
-            return Err(ShapeMismatchError);
-        }
-        let ilen = self.input_shape.size();
-        let dlen = delta.flattened.len();
-
-        // calculate products of weight and delta, to be sumed
-        let mut prod = vec![T::zero(); self.weight.len()];
-        let threads = determine_thread(ilen * dlen);
-        let mults_per_chunk = dlen / threads + 1;
-        {
-            let d_chunks = delta.flattened.chunks(mults_per_chunk); // delta chunk
-            let w_chunks = self.weight.chunks(mults_per_chunk * ilen); // weight chunk
-            let p_chunks = prod.chunks_mut(mults_per_chunk * ilen); // prod chunk
-            crossbeam::scope(|spawner| {
-                for ((w_chk, p_chk), d_chk) in w_chunks.zip(p_chunks).zip(d_chunks) {
-                    spawner.spawn(move |_| {
-                        for (j, &d) in d_chk.into_iter().enumerate() {
-                            // p[j] = w[j] * delta 
-                            for (&w, p) in slice_iter!(w_chk, ilen, j).zip(slice_iter_mut!(p_chk, ilen, j)) {
-                                *p = w * d;
-                            }
-                        }
-                    });
-                }
-            }).unwrap(); 
-        }
-
-        // add those slices back
-        let sum_prod = prod.par_chunks_mut(ilen).reduce_with(
-            |s1, s2| {
-                let len = s1.len();
-                for i in 0..len {
-                    let s = s1[i] + s2[i];
-                    s1[i] = s; s2[i] = s;
-                }
-                s1
-            }
-        ).unwrap();
-
-        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
-        lst_delta.flattened = sum_prod.to_vec();
-        
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
         // dot product sigma-1(z^l) and w^Td^{l+1}
-        lst_delta.flattened.par_iter_mut().zip(z_lst.flattened.par_iter()).for_each(|(d, z)| {
-            *d *= sigma_lst.diff(*z);
-        });
-
-        Ok(lst_delta)
+        let lst_delta = self.delta_back(&delta.flattened, 1, &z_lst.flattened, sigma_lst)?;
+        Ok(Tensor::<T> { flattened: lst_delta, shape: self.input_shape.clone() })
     }
     fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
-        if cum_dw.len() != delta.shape.size() * a_lst.shape.size() || cum_db.shape != delta.shape {
-            return Err(ShapeMismatchError);
-        }
+        check_len("add_weight_delta_to", delta.shape.size() * a_lst.shape.size(), cum_dw.len())?;
+        check_shape("add_weight_delta_to", &delta.shape, &cum_db.shape)?;
+        check_shape("add_weight_delta_to", &self.input_shape, &a_lst.shape)?;
         // compute d dot a^T
-        let dlen = delta.flattened.len();
-        let alen = a_lst.flattened.len();
-        let threads = determine_thread(dlen * alen);
-        let d_per_chunk = dlen / threads + 1;
-        {
-            let d_chunks = delta.flattened.chunks(d_per_chunk);
-            let w_chunks = cum_dw.chunks_mut(d_per_chunk * alen);
-            crossbeam::scope(|spawner| {
-                for (d_chk, w_chk) in d_chunks.zip(w_chunks) {
-                    spawner.spawn(move |_| {
-                        for (j, d) in d_chk.into_iter().enumerate() {
-                            // Do w_chk[j] += a * d[j]
-                            for (k, w) in slice_iter_mut!(w_chk, alen, j).enumerate() {
-                                *w += *d * a_lst.flattened[k];
-                            }
-                        }
-                    });
-                }
-            }).unwrap(); 
+        self.weight_delta_into(&delta.flattened, &a_lst.flattened, 1, cum_dw, &mut cum_db.flattened)
+    }
+    fn forward_batch(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        let n = batch_len("forward_batch", &input.shape, &self.input_shape)?;
+        // the batched matmul, one output row per sample
+        let mut output = Tensor::<T>::zeros(&self.output_shape.batched(n));
+        self.affine_into(&input.flattened, n, &mut output.flattened)?;
+        if activate {
+            parallel::for_each_mut(&mut output.flattened, |_, o| *o = self.activation.call(*o));
         }
-        // add delta to cum_db
-        cum_db.flattened.par_iter_mut().zip(delta.flattened.par_iter()).for_each(|(db, d)| {
-            *db += *d;
-        });
-        Ok(())
+        Ok(output)
+    }
+    fn backpropagate_batch(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        let n = batch_len("backpropagate_batch", &delta.shape, &self.output_shape)?;
+        check_shape("backpropagate_batch", &self.input_shape.batched(n), &z_lst.shape)?;
+        // L = D W, then dot product sigma-1(z^l)
+        let lst_delta = self.delta_back(&delta.flattened, n, &z_lst.flattened, sigma_lst)?;
+        Ok(Tensor::<T> { flattened: lst_delta, shape: z_lst.shape.clone() })
+    }
+    fn add_weight_delta_batch_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        let n = batch_len("add_weight_delta_batch_to", &delta.shape, &self.output_shape)?;
+        check_shape("add_weight_delta_batch_to", &self.input_shape.batched(n), &a_lst.shape)?;
+        check_len("add_weight_delta_batch_to", self.weight.len(), cum_dw.len())?;
+        check_shape("add_weight_delta_batch_to", &self.output_shape, &cum_db.shape)?;
+        // w_j += sum over the batch of d_bj * a_b, and b_j += sum of d_bj
+        self.weight_delta_into(&delta.flattened, &a_lst.flattened, n, cum_dw, &mut cum_db.flattened)
+    }
+    fn descend(&mut self, rate: T, dw: &[T], db: &Tensor<T>) -> Result<()> {
+        check_shape("descend", &self.output_shape, &db.shape)?;
+        check_len("descend", self.weight.len(), dw.len())?;
+        axpy(-rate, dw, &mut self.weight)?;
+        axpy(-rate, &db.flattened, &mut self.bias)
     }
-    fn descend(&mut self, rate: T, dw: &Vec<T>, db: &Tensor<T>) -> Result<()> {
-        if db.shape != self.output_shape || dw.len() != self.weight.len() {
-            return Err(ShapeMismatchError);
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("dense", self.activation).shape(&self.input_shape).shape(&self.output_shape).parameters_of(self))
+    }
+    fn parameters(&self) -> Vec<&[T]> {
+        vec![&self.weight, &self.bias]
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        vec![&mut self.weight, &mut self.bias]
+    }
+    fn supports_remap(&self) -> bool {
+        self.input_shape.rank() == 1 && self.output_shape.rank() == 1
+    }
+    fn remap_units(&mut self, input_map: Option<&[usize]>, output_map: Option<&[usize]>) -> Result<()> {
+        let (ilen, olen) = (self.input_shape.size(), self.output_shape.size());
+        if !self.supports_remap() {
+            return Err(EasynnError::invalid("remap_units", "only the Dense layers of [units] inputs and outputs can remap their units"));
+        }
+        if input_map.is_some_and(|m| m.iter().any(|k| *k >= ilen)) || output_map.is_some_and(|m| m.iter().any(|k| *k >= olen)) {
+            return Err(EasynnError::invalid("remap_units", "the maps refer to units out of the layer"));
+        }
+        if let Some(map) = output_map {
+            self.weight = map.iter().flat_map(|k| slice_iter!(self.weight, ilen, *k).copied()).collect();
+            self.bias = map.iter().map(|k| self.bias[*k]).collect();
+            self.output_shape = Shape::new([map.len()]);
+        }
+        if let Some(map) = input_map {
+            let mut copies = vec![0; ilen];
+            map.iter().for_each(|k| copies[*k] += 1);
+            let olen = self.output_shape.size();
+            let copies = &copies;
+            self.weight = (0..olen).flat_map(|o| {
+                let row = &self.weight[o * ilen..(o + 1) * ilen];
+                map.iter().map(move |k| row[*k] / T::from(copies[*k]).unwrap())
+            }).collect();
+            self.input_shape = Shape::new([map.len()]);
         }
-        // do weight update
-        self.weight.par_iter_mut().zip(dw.par_iter()).for_each(|(wi, dwi)| {
-            *wi -= rate * *dwi;
-        });
-
-        // do bias update
-        self.bias.par_iter_mut().zip(db.flattened.par_iter()).for_each(|(bi, dbi)| {
-            *bi -= rate * *dbi;
-        });
-
         Ok(())
     }
 }
@@ -272,6 +303,24 @@ fn test_dense_activate() {
     assert_eq!(l.activate(&output).unwrap(), answer);
 }
 
+#[test]
+fn test_dense_forward_train() {
+    let input = Tensor::<f64>::new(&Shape::new([3]), vec![1., -2., 0.5]);
+    let l = Dense::<f64> {
+        input_shape: Shape::new([3]),
+        output_shape: Shape::new([2]),
+        weight: vec![
+            2., 1., -1.,
+            -1., 0., 4.,
+        ],
+        bias: vec![-5., 1.],
+        activation: Activation::<f64>::Relu,
+    };
+    let (z, a) = l.forward_train(&input).unwrap();
+    assert_eq!(z, l.forward_propagate(&input, false).unwrap());
+    assert_eq!(a, l.activate(&z).unwrap());
+}
+
 #[test]
 fn test_dense_backpropagate() {
     let lst_a = Tensor::<f64>::new(&Shape::new([2, 3]), vec![
@@ -322,19 +371,8 @@ fn test_dense_descend() {
         1.-0.7, 0.-4.9, 0.-5.6, -2.+1.4, 1.-2.1, 0.-3.5,
     ];
     let b_ans = vec![-5.-0.1, -1.-0.7];
-    let eps = 1e-8;
-    for (w, upd) in w_ans.into_iter().zip(l.weight.into_iter()) {
-        assert!(
-            (w - upd).abs() < eps,
-            "expected {}, got {}", w, upd
-        );
-    }
-    for (b, upd) in b_ans.into_iter().zip(l.bias.into_iter()) {
-        assert!(
-            (b - upd).abs() < eps,
-            "expected {}, got {}", b, upd
-        );
-    }
+    crate::assert_tensor_eq!(Tensor::new(&Shape::new([12]), l.weight), Tensor::new(&Shape::new([12]), w_ans), 0., 1e-8);
+    crate::assert_tensor_eq!(Tensor::new(&Shape::new([2]), l.bias), Tensor::new(&Shape::new([2]), b_ans), 0., 1e-8);
 }
 
 #[test]
@@ -366,4 +404,26 @@ fn test_add_weight_delta_to() {
     ];
     assert_eq!(cum_dw, ans_da_lst);
     assert_eq!(cum_db, delta);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_dense_try_new() {
+    let huge = Shape::new([usize::MAX / 2]);
+    assert!(matches!(Dense::<f64>::try_new(&huge, &Shape::new([4]), Activation::No), Err(EasynnError::SizeOverflow(_))));
+    // the allocation cap is tested in tests/allocation_limit.rs, its own process
+    assert!(Dense::<f64>::try_new(&Shape::new([3]), &Shape::new([2]), Activation::No).is_ok());
+}
+
+#[test]
+fn test_dense_with_init() {
+    let (i, o) = (Shape::new([300]), Shape::new([100]));
+    let a = Dense::<f64>::with_init(&i, &o, Activation::Relu, Initializer::XavierUniform, 1);
+    let b = Dense::<f64>::with_init(&i, &o, Activation::Relu, Initializer::XavierUniform, 1);
+    let c = Dense::<f64>::with_init(&i, &o, Activation::Relu, Initializer::XavierUniform, 2);
+    assert_eq!(a.weight, b.weight);
+    assert!(a.weight != c.weight);
+    assert_eq!(a.bias, vec![0.; 100]);
+    // the limit is sqrt(6 / (300 + 100)) = 0.1225
+    let max = a.weight.iter().fold(0_f64, |m, w| m.max(w.abs()));
+    assert!(max <= 0.1225 && max > 0.12);
+}
@@ -0,0 +1,151 @@
+//! Inverted dropout: while training, each input is zeroed with the probability `rate`
+//! and the others are scaled by `1 / (1 - rate)`, so that the inference needs no rescaling
+//! and is the identity.
+//!
+//! The training passes (`forward_train`) drop, the inference (`forward_propagate`) never does.
+//! Out of the training mode, see `Layer::set_training`, the training passes do not drop either.
+//!
+//! The mask of a sample is hashed from the seed and the input, so that the backward pass
+//! recovers it from `sigma(z_lst)` like the other layers recover their forward pass.
+//! The mask depends on the exact bits of the input, so two passes rounding the previous layer
+//! differently (e.g. the per-sample and the batched kernels) may drop different units.
+//! The seed changes after each batch, in `Layer::finish_batch`.
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+#[derive(Debug)]
+pub struct Dropout<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) rate: T,
+    pub(crate) training: bool,
+    pub(crate) seed: u64,
+}
+
+/// The SplitMix64 finalizer
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl<T: NumT> Dropout<T> {
+    /// Drop with the probability `rate`, in `[0, 1)`, and a random seed
+    pub fn new(i_shape: &Shape, rate: T) -> Self {
+        Dropout::with_seed(i_shape, rate, rand::random())
+    }
+    /// Like `new`, drawing the masks from the seed
+    pub fn with_seed(i_shape: &Shape, rate: T, seed: u64) -> Self {
+        if rate < T::zero() || rate >= T::one() {
+            panic!("The dropout rate should be in [0, 1)!");
+        }
+        Dropout { input_shape: i_shape.clone(), output_shape: i_shape.clone(), rate, training: true, seed }
+    }
+
+    /// The multiplier of each input, 0 if dropped and `1 / (1 - rate)` otherwise
+    fn mask(&self, input: &[T]) -> Vec<T> {
+        let sample = input.iter().fold(self.seed, |h, x| mix(h ^ x.to_f64().unwrap().to_bits()));
+        let (rate, scale) = (self.rate.to_f64().unwrap(), T::one() / (T::one() - self.rate));
+        (0..input.len()).map(|i| {
+            let u = (mix(sample ^ mix(i as u64)) >> 11) as f64 / (1_u64 << 53) as f64;
+            if u < rate { T::zero() } else { scale }
+        }).collect()
+    }
+    fn dropping(&self) -> bool {
+        self.training && self.rate > T::zero()
+    }
+}
+
+impl<T: NumT> Layer<T> for Dropout<T> {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("dropout", Activation::No).shape(&self.input_shape).floats(&[self.rate.to_f64().unwrap()]))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        Ok(input.clone())
+    }
+    fn forward_train(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        let mut output = self.forward_propagate(input, false)?;
+        if self.dropping() {
+            for (o, m) in output.flattened.iter_mut().zip(self.mask(&input.flattened)) {
+                *o *= m;
+            }
+        }
+        Ok((output.clone(), output))
+    }
+    fn forward_train_batch(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        batch_len("forward_train_batch", &input.shape, &self.input_shape)?;
+        let mut output = input.clone();
+        if self.dropping() {
+            for sample in output.flattened.chunks_mut(self.input_shape.size()) {
+                let mask = self.mask(sample);
+                for (o, m) in sample.iter_mut().zip(mask) {
+                    *o *= m;
+                }
+            }
+        }
+        Ok((output.clone(), output))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let mut lst_delta = delta.clone();
+        if self.dropping() {
+            let a_lst: Vec<T> = z_lst.flattened.iter().map(|z| sigma_lst.call(*z)).collect();
+            for (d, m) in lst_delta.flattened.iter_mut().zip(self.mask(&a_lst)) {
+                *d *= m;
+            }
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+    fn finish_batch(&mut self) {
+        self.seed = mix(self.seed);
+    }
+}
+
+#[test]
+fn test_dropout() {
+    let shape = Shape::new([1000]);
+    let mut dropout = Dropout::<f64>::with_seed(&shape, 0.25, 7);
+    let input = Tensor::new(&shape, (0..1000).map(|i| 1. + i as f64 / 1000.).collect());
+    assert_eq!(dropout.forward_propagate(&input, true).unwrap(), input);
+
+    // a quarter dropped, the rest scaled by 4 / 3
+    let (z, a) = dropout.forward_train(&input).unwrap();
+    assert_eq!(z, a);
+    let dropped = a.flattened.iter().filter(|x| **x == 0.).count();
+    assert!((200..300).contains(&dropped), "dropped {}", dropped);
+    for (o, i) in a.flattened.iter().zip(input.flattened.iter()) {
+        assert!(*o == 0. || (*o - i * 4. / 3.).abs() < 1e-12);
+    }
+    // the same mask for the same sample, recovered by the backward pass through the last activation
+    assert_eq!(dropout.forward_train(&input).unwrap().1, a);
+    let batch = Tensor::stack(&shape, &[input.clone(), input.clone()]).unwrap();
+    assert_eq!(dropout.forward_train_batch(&batch).unwrap().1.unstack()[1], a);
+    let z_lst = Tensor::new(&shape, (0..1000).map(|i| (i as f64 + 0.5) / 500. - 1.).collect());
+    let a_lst = z_lst.map(|z| Activation::Tanh.call(z));
+    let a_tanh = dropout.forward_train(&a_lst).unwrap().1;
+    let delta = Tensor::new(&shape, vec![1.; 1000]);
+    let back = dropout.backpropagate_delta(&delta, &z_lst, &Activation::Tanh).unwrap();
+    for ((b, o), z) in back.flattened.iter().zip(a_tanh.flattened.iter()).zip(z_lst.flattened.iter()) {
+        let expected = if *o == 0. { 0. } else { 4. / 3. * Activation::Tanh.diff(*z) };
+        assert!((b - expected).abs() < 1e-12);
+    }
+
+    // a new mask after the batch, none out of training
+    dropout.finish_batch();
+    assert_ne!(dropout.forward_train(&input).unwrap().1, a);
+    dropout.set_training(false);
+    assert_eq!(dropout.forward_train(&input).unwrap().1, input);
+    assert_eq!(dropout.backpropagate_delta(&delta, &input, &Activation::No).unwrap(), delta);
+}
@@ -0,0 +1,196 @@
+//! The embedding layer, mapping integer indices, e.g. tokens or categories, to learned
+//! dense vectors: an input of shape `[..]` holding indices below `vocab` gives an output
+//! of shape `[.., dim]`, each index replaced by its row of the `[vocab, dim]` table.
+//!
+//! The indices are given as the elements of the input, which must be whole numbers.
+//! The input is not differentiable, so the layer comes first in a model. `descend`
+//! updates only the rows of the indices seen since the last update.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let emb = Embedding::<f32>::new(sh!([3]), 100, 8);
+//!     let tokens = Tensor::new(sh!([3]), vec![5., 42., 5.]);
+//!     let vectors = emb.forward_propagate(&tokens, true).unwrap();
+//!     assert_eq!(vectors.get_shape(), &Shape::new([3, 8]));
+//! ```
+
+use crate::layers::*;
+use crate::layers::init::Initializer;
+use crate::layers::record::LayerRecord;
+use crate::tensor::check_allocation;
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+#[derive(Debug)]
+pub struct Embedding<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) vocab: usize,
+    pub(crate) dim: usize,
+    /// Row i is the vector of index i
+    pub(crate) table: Vec<T>,
+    /// The rows given weight deltas since the last `descend`
+    seen: Mutex<BTreeSet<usize>>,
+}
+
+impl<T: NumT> Embedding<T> {
+    /// An embedding of `vocab` indices into vectors of `dim`, drawn uniformly in `[-0.05, 0.05]`
+    pub fn new(i_shape: &Shape, vocab: usize, dim: usize) -> Self {
+        match Self::try_new(i_shape, vocab, dim) {
+            Ok(layer) => layer,
+            Err(e) => panic!("{}", e),
+        }
+    }
+    /// Like `new`, checking that the table and the output do not overflow
+    /// and that the table is under the allocation cap
+    pub fn try_new(i_shape: &Shape, vocab: usize, dim: usize) -> std::result::Result<Self, EasynnError> {
+        if vocab == 0 || dim == 0 {
+            return Err(EasynnError::invalid("Embedding::try_new", "a vocabulary and a dimension are needed"));
+        }
+        let len = vocab.checked_mul(dim).ok_or_else(|| EasynnError::SizeOverflow(vec![vocab, dim]))?;
+        let size = i_shape.checked_size()?;
+        size.checked_mul(dim).ok_or_else(|| EasynnError::SizeOverflow(vec![size, dim]))?;
+        check_allocation::<T>(len)?;
+        let init = Initializer::Uniform(T::from(-0.05).unwrap(), T::from(0.05).unwrap());
+        let table = init.sample(len, vocab, dim, &mut rand::thread_rng());
+        Ok(Self::from_table(i_shape, vocab, dim, table))
+    }
+    /// An embedding of the given `[vocab, dim]` table, e.g. pretrained vectors
+    pub fn from_table(i_shape: &Shape, vocab: usize, dim: usize, table: Vec<T>) -> Self {
+        if vocab == 0 || dim == 0 {
+            panic!("Embedding needs a vocabulary and a dimension!");
+        }
+        if vocab.checked_mul(dim) != Some(table.len()) {
+            panic!("Embedding needs a table of vocab * dim elements!");
+        }
+        let mut o_dims = i_shape.dims().to_vec();
+        o_dims.push(dim);
+        Embedding::<T> {
+            input_shape: i_shape.clone(),
+            output_shape: Shape::from_slice(&o_dims),
+            vocab,
+            dim,
+            table,
+            seen: Mutex::new(BTreeSet::new()),
+        }
+    }
+    /// The vector of the index
+    pub fn vector(&self, index: usize) -> &[T] {
+        &self.table[index * self.dim..(index + 1) * self.dim]
+    }
+
+    /// The index held by an element of the input
+    fn index(&self, op: &'static str, x: T) -> Result<usize> {
+        match x.to_usize() {
+            Some(i) if i < self.vocab && x.fract() == T::zero() => Ok(i),
+            _ => Err(EasynnError::invalid(op, format!("{} is not an index below {}", x, self.vocab))),
+        }
+    }
+}
+
+impl<T: NumT> Layer<T> for Embedding<T> {
+    fn get_activation(&self) -> Activation<T> {
+        Activation::No
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.input_shape.clone()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.output_shape.clone()
+    }
+    fn get_weight_count(&self) -> usize {
+        self.table.len()
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let mut flattened = Vec::with_capacity(self.output_shape.size());
+        for x in input.flattened.iter() {
+            flattened.extend_from_slice(self.vector(self.index("forward_propagate", *x)?));
+        }
+        Ok(Tensor::<T> { flattened, shape: self.output_shape.clone() })
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("activate", &self.output_shape, &output.shape)?;
+        Ok(output.clone())
+    }
+    /// The indices are not differentiable, their delta is zero
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, _sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        Ok(Tensor::zeros(&self.input_shape))
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        check_shape("add_weight_delta_to", &self.output_shape, &delta.shape)?;
+        check_shape("add_weight_delta_to", &self.input_shape, &a_lst.shape)?;
+        check_len("add_weight_delta_to", self.table.len(), cum_dw.len())?;
+        check_shape("add_weight_delta_to", &delta.shape, &cum_db.shape)?;
+        let mut seen = self.seen.lock().unwrap();
+        for (x, d_row) in a_lst.flattened.iter().zip(delta.flattened.chunks(self.dim)) {
+            let i = self.index("add_weight_delta_to", *x)?;
+            for (w, d) in cum_dw[i * self.dim..(i + 1) * self.dim].iter_mut().zip(d_row) {
+                *w += *d;
+            }
+            seen.insert(i);
+        }
+        Ok(())
+    }
+    fn descend(&mut self, rate: T, dw: &[T], _db: &Tensor<T>) -> Result<()> {
+        check_len("descend", self.table.len(), dw.len())?;
+        let dim = self.dim;
+        let mut rows = std::mem::take(self.seen.get_mut().unwrap());
+        if rows.is_empty() {
+            // the deltas were not added by this layer, the rows are those of any delta
+            rows = dw.chunks(dim).enumerate().filter(|(_, d)| d.iter().any(|x| *x != T::zero())).map(|(i, _)| i).collect();
+        }
+        for i in rows {
+            for (w, d) in self.table[i * dim..(i + 1) * dim].iter_mut().zip(&dw[i * dim..(i + 1) * dim]) {
+                *w -= rate * *d;
+            }
+        }
+        Ok(())
+    }
+
+    fn parameters(&self) -> Vec<&[T]> {
+        vec![&self.table]
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        vec![&mut self.table]
+    }
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("embedding", Activation::No).shape(&self.input_shape).values(&[self.vocab, self.dim]).parameters_of(self))
+    }
+}
+
+#[test]
+fn test_embedding() {
+    let table: Vec<f64> = (0..8).map(|x| x as f64).collect();
+    let mut emb = Embedding::from_table(&Shape::new([3]), 4, 2, table.clone());
+    let input = Tensor::new(&Shape::new([3]), vec![2., 0., 2.]);
+    let output = emb.forward_propagate(&input, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([3, 2]), vec![4., 5., 0., 1., 4., 5.]));
+    for bad in [4., -1., 0.5, f64::NAN] {
+        let e = emb.forward_propagate(&Tensor::new(&Shape::new([3]), vec![0., bad, 1.]), true).unwrap_err();
+        assert!(matches!(e, EasynnError::InvalidArgument { op: "forward_propagate", .. }));
+    }
+    assert_eq!(emb.backpropagate_delta(&output, &input, &Activation::No).unwrap(), Tensor::zeros(&Shape::new([3])));
+
+    // the deltas of a repeated index add up, only the seen rows move
+    let delta = Tensor::new(&Shape::new([3, 2]), vec![1., 1., 2., 2., 3., 3.]);
+    let mut dw = vec![0.; 8];
+    let mut db = Tensor::zeros(&Shape::new([3, 2]));
+    emb.add_weight_delta_to(&delta, &input, &mut dw, &mut db).unwrap();
+    assert_eq!(dw, [2., 2., 0., 0., 4., 4., 0., 0.]);
+    dw[2] = 100.;
+    emb.descend(0.5, &dw, &db).unwrap();
+    assert_eq!(emb.table, [-1., 0., 2., 3., 2., 3., 6., 7.]);
+    // without the rows seen, the rows of any delta move
+    emb.descend(0.5, &[0., 0., 2., 0., 0., 0., 0., 0.], &db).unwrap();
+    assert_eq!(emb.vector(1), [1., 3.]);
+
+    let rebuilt = Layer::<f64>::record(&emb).unwrap().into_layer().unwrap();
+    assert_eq!(rebuilt.forward_propagate(&input, true).unwrap(), emb.forward_propagate(&input, true).unwrap());
+    assert_eq!(rebuilt.name(), "embedding");
+}
@@ -0,0 +1,98 @@
+//! Weight initialization strategies, given the fan-in and the fan-out of a layer.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::{ Layer, init::Initializer };
+//!     // reproducible He initialization for a ReLU layer
+//!     let a = Dense::<f32>::with_init(sh!([64]), sh!([32]), Activation::Relu, Initializer::HeNormal, 42);
+//!     let b = Dense::<f32>::with_init(sh!([64]), sh!([32]), Activation::Relu, Initializer::HeNormal, 42);
+//!     assert_eq!(a.parameters(), b.parameters());
+//! ```
+
+use crate::tensor::*;
+
+use rand::Rng;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Initializer<T: NumT> {
+    Zeros,
+    Constant(T),
+    /// Uniform in `[low, high)`
+    Uniform(T, T),
+    Normal { mean: T, std: T },
+    /// Glorot: uniform in `±sqrt(6 / (fan_in + fan_out))`, for tanh and sigmoid
+    XavierUniform,
+    /// Glorot: normal of std `sqrt(2 / (fan_in + fan_out))`
+    XavierNormal,
+    /// Kaiming: uniform in `±sqrt(6 / fan_in)`, for ReLU
+    HeUniform,
+    /// Kaiming: normal of std `sqrt(2 / fan_in)`
+    HeNormal,
+}
+
+/// A standard normal sample by the Box-Muller transform
+fn standard_normal<R: Rng>(rng: &mut R) -> f64 {
+    let u1 = 1. - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2. * u1.ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos()
+}
+
+impl<T: NumT> Initializer<T> {
+    /// Draw `count` values for a layer of the fan-in and fan-out
+    pub fn sample<R: Rng>(&self, count: usize, fan_in: usize, fan_out: usize, rng: &mut R) -> Vec<T> {
+        let (fan_in, fan_out) = (fan_in.max(1) as f64, fan_out.max(1) as f64);
+        let f = |x: T| x.to_f64().unwrap();
+        // either uniform in [low, high) or normal of (mean, std)
+        let (uniform, a, b) = match *self {
+            Initializer::Zeros => return vec![T::zero(); count],
+            Initializer::Constant(c) => return vec![c; count],
+            Initializer::Uniform(low, high) => (true, f(low), f(high)),
+            Initializer::Normal { mean, std } => (false, f(mean), f(std)),
+            Initializer::XavierUniform => {
+                let limit = (6. / (fan_in + fan_out)).sqrt();
+                (true, -limit, limit)
+            },
+            Initializer::XavierNormal => (false, 0., (2. / (fan_in + fan_out)).sqrt()),
+            Initializer::HeUniform => {
+                let limit = (6. / fan_in).sqrt();
+                (true, -limit, limit)
+            },
+            Initializer::HeNormal => (false, 0., (2. / fan_in).sqrt()),
+        };
+        (0..count).map(|_| {
+            let x = match uniform {
+                true if a < b => rng.gen_range(a..b),
+                true => a,
+                false => a + b * standard_normal(rng),
+            };
+            T::from(x).unwrap()
+        }).collect()
+    }
+}
+
+#[test]
+fn test_initializers() {
+    use rand::SeedableRng;
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let n = 20000;
+    let stats = |v: &[f64]| {
+        let mean = v.iter().sum::<f64>() / v.len() as f64;
+        (mean, (v.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / v.len() as f64).sqrt())
+    };
+
+    let he = Initializer::<f64>::HeNormal.sample(n, 50, 10, &mut rng);
+    let (mean, std) = stats(&he);
+    assert!(mean.abs() < 0.01 && (std - 0.2).abs() < 0.01);
+
+    let xavier = Initializer::<f64>::XavierUniform.sample(n, 20, 4, &mut rng);
+    assert!(xavier.iter().all(|x| x.abs() <= 0.5));
+    assert!((stats(&xavier).1 - 0.5 / 3_f64.sqrt()).abs() < 0.01);
+
+    let normal = Initializer::Normal { mean: 3., std: 0.5 }.sample(n, 1, 1, &mut rng);
+    let (mean, std) = stats(&normal);
+    assert!((mean - 3.).abs() < 0.02 && (std - 0.5).abs() < 0.02);
+
+    assert!(Initializer::Uniform(2., 4.).sample(100, 1, 1, &mut rng).iter().all(|x| (2. ..4.).contains(x)));
+    assert_eq!(Initializer::Constant(0.5).sample(3, 1, 1, &mut rng), vec![0.5; 3]);
+    assert_eq!(Initializer::<f64>::Zeros.sample(2, 1, 1, &mut rng), vec![0.; 2]);
+}
@@ -1,14 +1,34 @@
 //! The layers module
 
 pub mod dense;
+pub mod conv;
+pub mod init;
+pub mod dropout;
+pub mod batch_norm;
+pub mod pooling;
+pub mod softmax;
 pub mod activation;
+pub mod activation_layer;
+pub mod padding;
+pub mod reshape;
+pub mod upsampling;
+pub mod skip;
+pub mod seq_pooling;
+pub mod crf;
+pub mod embedding;
+pub mod binary;
+pub mod tune;
+pub mod record;
+pub mod registry;
 pub use activation::*;
 
 pub use crate::tensor::*;
-pub use crate::tensor::error::ShapeMismatchError;
-pub type Result<T> = std::result::Result<T, ShapeMismatchError>;
+pub use crate::tensor::error::EasynnError;
+use record::LayerRecord;
+pub type Result<T> = std::result::Result<T, EasynnError>;
 
-pub trait Layer<T: NumT> {
+/// Layers are `Send + Sync` so that models can be trained on other threads
+pub trait Layer<T: NumT>: Send + Sync {
     /// Getter method to specify a field `activation`
     fn get_activation(&self) -> Activation<T>;
     /// Getter method to specify a field `input_shape`
@@ -26,9 +46,29 @@ pub trait Layer<T: NumT> {
     /// Doing activation here is generally faster than doing that later
     fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>>;
 
+    /// Forward-propagate into a preallocated output tensor of the output shape,
+    /// always activated
+    /// 
+    /// Layers may override this to avoid allocating when inferring repeatedly
+    fn forward_propagate_into(&self, input: &Tensor<T>, output: &mut Tensor<T>) -> Result<()> {
+        check_shape("forward_propagate_into", &self.get_output_shape(), &output.shape)?;
+        *output = self.forward_propagate(input, true)?;
+        Ok(())
+    }
+
     /// This is used when training: should get both a^l and z^l
     fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>>;
 
+    /// Forward-propagate when training, returns both z^l and a^l
+    /// 
+    /// Layers may override this to fuse the bias add and the activation
+    /// into a single pass over memory
+    fn forward_train(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        let z = self.forward_propagate(input, false)?;
+        let a = self.activate(&z)?;
+        Ok((z, a))
+    }
+
     /// Backpropagate takes the delta of output and calculates the delta of the input
     /// 
     /// It relys on the output z of the last layer and the activation of the last layer
@@ -39,5 +79,224 @@ pub trait Layer<T: NumT> {
     fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()>;
 
     /// Do the learning of each layer
-    fn descend(&mut self, rate: T, dw: &Vec<T>, db: &Tensor<T>) -> Result<()>;
-}
\ No newline at end of file
+    fn descend(&mut self, rate: T, dw: &[T], db: &Tensor<T>) -> Result<()>;
+
+    /// Forward-propagate a batch of inputs stacked as `[batch, ..input_shape]`
+    /// into `[batch, ..output_shape]`, see `Tensor::stack`
+    ///
+    /// Layers may override this to process the whole batch at once
+    fn forward_batch(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        batch_len("forward_batch", &input.shape, &self.get_input_shape())?;
+        let outputs = input.unstack().iter().map(|x| self.forward_propagate(x, activate)).collect::<Result<Vec<_>>>()?;
+        Tensor::stack(&self.get_output_shape(), &outputs)
+    }
+
+    /// Forward-propagate a batch when training, returns both z^l and a^l of the batch
+    ///
+    /// Layers may override this like `forward_train`
+    fn forward_train_batch(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        let z = self.forward_batch(input, false)?;
+        let act = self.get_activation();
+        let a = z.map(|x| act.call(x));
+        Ok((z, a))
+    }
+
+    /// Backpropagate the deltas of a batch, `[batch, ..output_shape]`,
+    /// given the outputs z of the last layer for the batch
+    fn backpropagate_batch(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        let n = batch_len("backpropagate_batch", &delta.shape, &self.get_output_shape())?;
+        check_shape("backpropagate_batch", &self.get_input_shape().batched(n), &z_lst.shape)?;
+        let deltas = delta.unstack().iter().zip(z_lst.unstack().iter())
+            .map(|(d, z)| self.backpropagate_delta(d, z, sigma_lst))
+            .collect::<Result<Vec<_>>>()?;
+        Tensor::stack(&self.get_input_shape(), &deltas)
+    }
+
+    /// Add the weight deltas of a batch, summed over the samples
+    fn add_weight_delta_batch_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        let n = batch_len("add_weight_delta_batch_to", &delta.shape, &self.get_output_shape())?;
+        check_shape("add_weight_delta_batch_to", &self.get_input_shape().batched(n), &a_lst.shape)?;
+        for (d, a) in delta.unstack().iter().zip(a_lst.unstack().iter()) {
+            self.add_weight_delta_to(d, a, cum_dw, cum_db)?;
+        }
+        Ok(())
+    }
+
+    /// The trainable parameters, in the order of the weights then the bias
+    fn parameters(&self) -> Vec<&[T]> {
+        Vec::new()
+    }
+    /// The trainable parameters, mutable, in the same order as `parameters`
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        Vec::new()
+    }
+    /// The buffers that training updates besides the parameters, e.g. the running statistics
+    /// of `BatchNorm`, which the snapshots of the models copy along
+    fn state(&self) -> Vec<&[T]> {
+        Vec::new()
+    }
+    /// The buffers of `state`, mutable, in the same order
+    fn state_mut(&mut self) -> Vec<&mut [T]> {
+        Vec::new()
+    }
+    /// The names of `parameters`, in the same order, `weight` then `bias` by default
+    fn parameter_names(&self) -> Vec<String> {
+        (0..self.parameters().len()).map(|i| match i {
+            0 => "weight".to_string(),
+            1 => "bias".to_string(),
+            i => format!("param{}", i),
+        }).collect()
+    }
+    /// The parameters with their names
+    fn named_parameters(&self) -> Vec<(String, &[T])> {
+        self.parameter_names().into_iter().zip(self.parameters()).collect()
+    }
+    /// Whether each of `parameters` is penalized by the regularization of the model,
+    /// the parameters named `weight` by default
+    fn regularized(&self) -> Vec<bool> {
+        self.parameter_names().iter().map(|n| n == "weight").collect()
+    }
+
+    /// The gradients of `parameters`, in the same order and layout,
+    /// from the deltas accumulated by `add_weight_delta_to`
+    fn gradients(&self, dw: &[T], db: &Tensor<T>) -> Vec<Vec<T>> {
+        // the weights then the bias, as far as the layer has them
+        [dw.to_vec(), db.flattened.clone()].into_iter().take(self.parameters().len()).collect()
+    }
+
+    /// Switch the training passes between the training and the evaluation behaviour,
+    /// for the layers that differ (e.g. `Dropout`), the inference is always in evaluation
+    fn set_training(&mut self, _training: bool) {}
+
+    /// Called by the models after the update of each training batch
+    fn finish_batch(&mut self) {}
+    /// Called by the models instead of `finish_batch` for a batch propagated but not trained,
+    /// to drop what it gathered, e.g. batch statistics
+    fn discard_batch(&mut self) {}
+
+    /// The record to save the layer and rebuild it, None if the layer cannot be saved
+    fn record(&self) -> Option<LayerRecord<T>> {
+        None
+    }
+    /// The name of the layer in errors, the kind of its record by default
+    fn name(&self) -> String {
+        self.record().map_or_else(|| "layer".to_string(), |r| r.kind)
+    }
+
+    /// Whether the layer supports `remap_units`, its units being the first axis of its input
+    /// and its output, e.g. the features of `[units]` or the channels of `[channels, height, width]`
+    fn supports_remap(&self) -> bool {
+        false
+    }
+    /// Rebuild the layer over duplicated or removed units, to widen or to prune a trained model:
+    /// output j becomes a copy of the old output `output_map[j]`, and input j is a copy of the
+    /// old input `input_map[j]`, the weights of an old input being split among its copies
+    fn remap_units(&mut self, _input_map: Option<&[usize]>, _output_map: Option<&[usize]>) -> Result<()> {
+        Err(EasynnError::invalid("remap_units", "the layer does not support remapping its units"))
+    }
+    /// Whether the layer supports `pass_units`, working on each unit of its input apart
+    fn passes_units(&self) -> bool {
+        false
+    }
+    /// Rebuild the layer over the input units of the map, like the input map of `remap_units`,
+    /// returning the map of its output units that follows
+    fn pass_units(&mut self, _map: &[usize]) -> Result<Vec<usize>> {
+        Err(EasynnError::invalid("pass_units", "the layer does not pass a remap of its units"))
+    }
+}
+
+/// Check that the map of `remap_units` or `pass_units` refers to units below `units`
+pub(crate) fn check_unit_map(op: &'static str, map: &[usize], units: usize) -> Result<()> {
+    match map.iter().find(|k| **k >= units) {
+        Some(k) => Err(EasynnError::invalid(op, format!("the map refers to unit {} out of {}", k, units))),
+        None => Ok(()),
+    }
+}
+
+/// The shape of `units` along its first axis
+pub(crate) fn with_units(shape: &Shape, units: usize) -> Shape {
+    let mut dims = shape.dims().to_vec();
+    dims[0] = units;
+    Shape::from_slice(&dims)
+}
+
+/// The names of the parameters of a chain of layers, `kind` and the count of the layers
+/// of that kind before, then the name within the layer, e.g. `dense0.weight`
+pub(crate) fn chain_parameter_names<'a, T: NumT + 'a>(layers: impl Iterator<Item = &'a Box<dyn Layer<T>>>) -> Vec<String> {
+    let mut seen = std::collections::HashMap::<String, usize>::new();
+    layers.flat_map(|l| {
+        let kind = l.name();
+        let count = seen.entry(kind.clone()).or_insert(0);
+        let prefix = format!("{}{}", kind, count);
+        *count += 1;
+        l.parameter_names().into_iter().map(move |n| format!("{}.{}", prefix, n))
+    }).collect()
+}
+
+/// Check that a batch given to the operation `op` is of the shape `[n, ..item_shape]`, returning n
+pub(crate) fn batch_len(op: &'static str, batch: &Shape, item_shape: &Shape) -> Result<usize> {
+    let n = batch.dims().first().copied().unwrap_or(0);
+    check_shape(op, &item_shape.batched(n), batch)?;
+    Ok(n)
+}
+
+/// Attach the index and the name of a layer of a model to its errors
+pub(crate) fn at_layer<T: NumT>(index: usize, layer: &dyn Layer<T>) -> impl FnOnce(EasynnError) -> EasynnError + '_ {
+    move |e| EasynnError::Layer { index, name: layer.name(), source: Box::new(e) }
+}
+
+/// Multiply the passed through delta by sigma'(z) of the last layer
+pub(crate) fn apply_diff_lst<T: NumT>(delta: &mut Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) {
+    for (d, z) in delta.flattened.iter_mut().zip(z_lst.flattened.iter()) {
+        *d *= sigma_lst.diff(*z);
+    }
+}
+
+/// The methods shared by the layers without weights
+macro_rules! impl_weightless {
+    () => {
+        fn get_activation(&self) -> Activation<T> {
+            Activation::No
+        }
+        fn get_input_shape(&self) -> Shape {
+            self.input_shape.clone()
+        }
+        fn get_output_shape(&self) -> Shape {
+            self.output_shape.clone()
+        }
+        fn get_weight_count(&self) -> usize {
+            0
+        }
+        fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+            check_shape("activate", &self.output_shape, &output.shape)?;
+            Ok(output.clone())
+        }
+        fn add_weight_delta_to(&self, delta: &Tensor<T>, _a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+            check_len("add_weight_delta_to", 0, cum_dw.len())?;
+            check_shape("add_weight_delta_to", &delta.shape, &cum_db.shape)?;
+            Ok(())
+        }
+        fn descend(&mut self, _rate: T, dw: &[T], _db: &Tensor<T>) -> Result<()> {
+            check_len("descend", 0, dw.len())?;
+            Ok(())
+        }
+    };
+}
+pub(crate) use impl_weightless;
+
+/// Implement `pass_units` for a weightless layer working on each channel apart,
+/// the first axis of its input and its output
+macro_rules! impl_unit_pass {
+    () => {
+        fn passes_units(&self) -> bool {
+            true
+        }
+        fn pass_units(&mut self, map: &[usize]) -> Result<Vec<usize>> {
+            check_unit_map("pass_units", map, self.input_shape[0])?;
+            self.input_shape = with_units(&self.input_shape, map.len());
+            self.output_shape = with_units(&self.output_shape, map.len());
+            Ok(map.to_vec())
+        }
+    };
+}
+pub(crate) use impl_unit_pass;
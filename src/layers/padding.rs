@@ -0,0 +1,205 @@
+//! Zero padding and cropping layers, working on the last two dimentions
+//! (height and width) of a tensor, e.g. `[channel, height, width]`.
+//!
+//! Both have no weights, the deltas are passed through by the inverse operation.
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+use std::ops::Range;
+
+/// The ranges of each row of the inner window in the flattened outer tensor,
+/// paired with the ranges of the same rows in the flattened inner tensor.
+/// The window starts at `(top, left)` of the last two dimentions of the outer one.
+fn window_rows(maps: usize, outer_hw: (usize, usize), inner_hw: (usize, usize), top: usize, left: usize) -> Vec<(Range<usize>, Range<usize>)> {
+    let (oh, ow) = outer_hw;
+    let (ih, iw) = inner_hw;
+    let mut rows = Vec::with_capacity(maps * ih);
+    for m in 0..maps {
+        for r in 0..ih {
+            let o_start = m * oh * ow + (r + top) * ow + left;
+            let i_start = m * ih * iw + r * iw;
+            rows.push((o_start..o_start + iw, i_start..i_start + iw));
+        }
+    }
+    rows
+}
+
+/// The (height, width) of a shape, the last two dimentions
+fn hw(shape: &Shape) -> (usize, usize) {
+    let rank = shape.rank();
+    (shape[rank - 2], shape[rank - 1])
+}
+
+/// The count of 2D maps in a shape, i.e. the product of the leading dimentions
+fn maps(shape: &Shape) -> usize {
+    let (h, w) = hw(shape);
+    shape.size() / (h * w)
+}
+
+/// The shape with the last two dimentions replaced
+fn with_hw(shape: &Shape, h: usize, w: usize) -> Shape {
+    let mut dims = shape.dims().to_vec();
+    let rank = dims.len();
+    dims[rank - 2] = h;
+    dims[rank - 1] = w;
+    Shape::from_slice(&dims)
+}
+
+/// Pad the input with zeros on the (top, bottom, left, right) borders
+#[derive(Debug)]
+pub struct ZeroPad2D {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) padding: (usize, usize, usize, usize),
+}
+
+impl ZeroPad2D {
+    /// The input should be at least of rank 2, the paddings are (top, bottom, left, right)
+    pub fn new(i_shape: &Shape, padding: (usize, usize, usize, usize)) -> Self {
+        if i_shape.rank() < 2 {
+            panic!("ZeroPad2D needs an input of at least rank 2!");
+        }
+        let (h, w) = hw(i_shape);
+        let (top, bottom, left, right) = padding;
+        ZeroPad2D {
+            input_shape: i_shape.clone(),
+            output_shape: with_hw(i_shape, h + top + bottom, w + left + right),
+            padding,
+        }
+    }
+}
+
+/// Crop the (top, bottom, left, right) borders of the input
+#[derive(Debug)]
+pub struct Crop2D {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) cropping: (usize, usize, usize, usize),
+}
+
+impl Crop2D {
+    /// The input should be at least of rank 2, the croppings are (top, bottom, left, right)
+    pub fn new(i_shape: &Shape, cropping: (usize, usize, usize, usize)) -> Self {
+        if i_shape.rank() < 2 {
+            panic!("Crop2D needs an input of at least rank 2!");
+        }
+        let (h, w) = hw(i_shape);
+        let (top, bottom, left, right) = cropping;
+        if top + bottom >= h || left + right >= w {
+            panic!("Crop2D crops away the whole input!");
+        }
+        Crop2D {
+            input_shape: i_shape.clone(),
+            output_shape: with_hw(i_shape, h - top - bottom, w - left - right),
+            cropping,
+        }
+    }
+}
+
+impl<T: NumT> Layer<T> for ZeroPad2D {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let (t, b, l, r) = self.padding;
+        Some(LayerRecord::new("zero_pad2d", Activation::No).shape(&self.input_shape).values(&[t, b, l, r]))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let mut output = Tensor::<T>::zeros(&self.output_shape);
+        let (top, _, left, _) = self.padding;
+        for (o, i) in window_rows(maps(&self.input_shape), hw(&self.output_shape), hw(&self.input_shape), top, left) {
+            output.flattened[o].copy_from_slice(&input.flattened[i]);
+        }
+        Ok(output)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        // the deltas of the padded zeros are dropped
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        let (top, _, left, _) = self.padding;
+        for (o, i) in window_rows(maps(&self.input_shape), hw(&self.output_shape), hw(&self.input_shape), top, left) {
+            lst_delta.flattened[i].copy_from_slice(&delta.flattened[o]);
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+impl<T: NumT> Layer<T> for Crop2D {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let (t, b, l, r) = self.cropping;
+        Some(LayerRecord::new("crop2d", Activation::No).shape(&self.input_shape).values(&[t, b, l, r]))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let mut output = Tensor::<T>::zeros(&self.output_shape);
+        let (top, _, left, _) = self.cropping;
+        for (i, o) in window_rows(maps(&self.input_shape), hw(&self.input_shape), hw(&self.output_shape), top, left) {
+            output.flattened[o].copy_from_slice(&input.flattened[i]);
+        }
+        Ok(output)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        // the cropped away elements get zero deltas
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        let (top, _, left, _) = self.cropping;
+        for (i, o) in window_rows(maps(&self.input_shape), hw(&self.input_shape), hw(&self.output_shape), top, left) {
+            lst_delta.flattened[i].copy_from_slice(&delta.flattened[o]);
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[test]
+fn test_zero_pad_2d() {
+    let input = Tensor::<f64>::new(&Shape::new([2, 2, 2]), vec![
+        1., 2.,
+        3., 4.,
+
+        5., 6.,
+        7., 8.,
+    ]);
+    let l = ZeroPad2D::new(&Shape::new([2, 2, 2]), (1, 0, 0, 2));
+    let output = Tensor::<f64>::new(&Shape::new([2, 3, 4]), vec![
+        0., 0., 0., 0.,
+        1., 2., 0., 0.,
+        3., 4., 0., 0.,
+
+        0., 0., 0., 0.,
+        5., 6., 0., 0.,
+        7., 8., 0., 0.,
+    ]);
+    assert_eq!(l.forward_propagate(&input, true).unwrap(), output);
+    let z_lst = Tensor::<f64>::ones(&Shape::new([2, 2, 2]));
+    assert_eq!(l.backpropagate_delta(&output, &z_lst, &Activation::No).unwrap(), input);
+}
+
+#[test]
+fn test_crop_2d() {
+    let input = Tensor::<f64>::new(&Shape::new([3, 4]), vec![
+        1., 2., 3., 4.,
+        5., 6., 7., 8.,
+        9., 10., 11., 12.,
+    ]);
+    let l = Crop2D::new(&Shape::new([3, 4]), (1, 1, 1, 0));
+    let output = Tensor::<f64>::new(&Shape::new([1, 3]), vec![6., 7., 8.]);
+    assert_eq!(l.forward_propagate(&input, true).unwrap(), output);
+    let delta = Tensor::<f64>::new(&Shape::new([3, 4]), vec![
+        0., 0., 0., 0.,
+        0., 6., 7., 8.,
+        0., 0., 0., 0.,
+    ]);
+    let z_lst = Tensor::<f64>::ones(&Shape::new([3, 4]));
+    assert_eq!(l.backpropagate_delta(&output, &z_lst, &Activation::Relu).unwrap(), delta);
+}
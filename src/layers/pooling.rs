@@ -0,0 +1,222 @@
+//! Pooling layers over `[channels, height, width]` inputs, downsampling each map
+//! by taking the maximum or the average of each window.
+//!
+//! The windows are not padded: pad the input beforehand with `ZeroPad2D` if needed.
+//! `GlobalAvgPool2D` averages each whole map, collapsing `[channels, height, width]` to `[channels]`.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let pool = MaxPool2D::new(sh!([8, 28, 28]), (2, 2), (2, 2));
+//!     assert_eq!(Layer::<f32>::get_output_shape(&pool), Shape::new([8, 14, 14]));
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+/// Check the input and the windows, returning the output shape
+fn pool_output(i_shape: &Shape, kernel: (usize, usize), stride: (usize, usize)) -> Shape {
+    if i_shape.rank() != 3 {
+        panic!("Pooling needs a [channels, height, width] input!");
+    }
+    match i_shape.window_output(kernel, stride, Padding::Valid) {
+        Ok(s) => s,
+        Err(_) => panic!("The pooling window does not fit in the input!"),
+    }
+}
+
+/// The positions in the flattened input covered by the window of output position `p`
+fn window(i_shape: &Shape, o_shape: &Shape, kernel: (usize, usize), stride: (usize, usize), p: usize) -> impl Iterator<Item = usize> {
+    let (h, w) = (i_shape[1], i_shape[2]);
+    let (oh, ow) = (o_shape[1], o_shape[2]);
+    let (c, oy, ox) = (p / (oh * ow), p / ow % oh, p % ow);
+    let base = c * h * w + oy * stride.0 * w + ox * stride.1;
+    (0..kernel.0).flat_map(move |ky| (0..kernel.1).map(move |kx| base + ky * w + kx))
+}
+
+/// Take the maximum of each window
+#[derive(Debug)]
+pub struct MaxPool2D {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) kernel: (usize, usize),
+    pub(crate) stride: (usize, usize),
+}
+
+impl MaxPool2D {
+    pub fn new(i_shape: &Shape, kernel: (usize, usize), stride: (usize, usize)) -> Self {
+        MaxPool2D { input_shape: i_shape.clone(), output_shape: pool_output(i_shape, kernel, stride), kernel, stride }
+    }
+
+    /// The position of the maximum of each window
+    fn argmax<T: NumT>(&self, input: &[T]) -> Vec<usize> {
+        (0..self.output_shape.size()).map(|p| {
+            window(&self.input_shape, &self.output_shape, self.kernel, self.stride, p)
+                .reduce(|m, i| if input[i] > input[m] { i } else { m })
+                .unwrap()
+        }).collect()
+    }
+}
+
+/// Take the average of each window
+#[derive(Debug)]
+pub struct AvgPool2D {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) kernel: (usize, usize),
+    pub(crate) stride: (usize, usize),
+}
+
+impl AvgPool2D {
+    pub fn new(i_shape: &Shape, kernel: (usize, usize), stride: (usize, usize)) -> Self {
+        AvgPool2D { input_shape: i_shape.clone(), output_shape: pool_output(i_shape, kernel, stride), kernel, stride }
+    }
+}
+
+/// Take the average of each map
+#[derive(Debug)]
+pub struct GlobalAvgPool2D {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+}
+
+impl GlobalAvgPool2D {
+    pub fn new(i_shape: &Shape) -> Self {
+        if i_shape.rank() != 3 || i_shape.size() == 0 {
+            panic!("Pooling needs a [channels, height, width] input!");
+        }
+        GlobalAvgPool2D { input_shape: i_shape.clone(), output_shape: Shape::new([i_shape[0]]) }
+    }
+}
+
+impl<T: NumT> Layer<T> for MaxPool2D {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let values = [self.kernel.0, self.kernel.1, self.stride.0, self.stride.1];
+        Some(LayerRecord::new("max_pool2d", Activation::No).shape(&self.input_shape).values(&values))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let output = self.argmax(&input.flattened).into_iter().map(|i| input.flattened[i]).collect();
+        Ok(Tensor::<T>::new(&self.output_shape, output))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        // route each delta to the position holding the maximum of the last activation,
+        // summing where overlapping windows share it
+        let a_lst: Vec<T> = z_lst.flattened.iter().map(|z| sigma_lst.call(*z)).collect();
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for (i, dl) in self.argmax(&a_lst).into_iter().zip(delta.flattened.iter()) {
+            lst_delta.flattened[i] += *dl;
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+impl<T: NumT> Layer<T> for AvgPool2D {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let values = [self.kernel.0, self.kernel.1, self.stride.0, self.stride.1];
+        Some(LayerRecord::new("avg_pool2d", Activation::No).shape(&self.input_shape).values(&values))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let area = T::from(self.kernel.0 * self.kernel.1).unwrap();
+        let output = (0..self.output_shape.size()).map(|p| {
+            window(&self.input_shape, &self.output_shape, self.kernel, self.stride, p)
+                .map(|i| input.flattened[i])
+                .sum::<T>() / area
+        }).collect();
+        Ok(Tensor::<T>::new(&self.output_shape, output))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let area = T::from(self.kernel.0 * self.kernel.1).unwrap();
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for (p, dl) in delta.flattened.iter().enumerate() {
+            for i in window(&self.input_shape, &self.output_shape, self.kernel, self.stride, p) {
+                lst_delta.flattened[i] += *dl / area;
+            }
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+impl<T: NumT> Layer<T> for GlobalAvgPool2D {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("global_avg_pool2d", Activation::No).shape(&self.input_shape))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let map = self.input_shape[1] * self.input_shape[2];
+        let area = T::from(map).unwrap();
+        let output = input.flattened.chunks(map).map(|m| m.iter().copied().sum::<T>() / area).collect();
+        Ok(Tensor::<T>::new(&self.output_shape, output))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let map = self.input_shape[1] * self.input_shape[2];
+        let area = T::from(map).unwrap();
+        let mut lst_delta = Tensor::<T>::new(&self.input_shape, delta.flattened.iter().flat_map(|d| std::iter::repeat_n(*d / area, map)).collect());
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[test]
+fn test_pooling() {
+    let shape = Shape::new([1, 4, 4]);
+    let input = Tensor::<f64>::new(&shape, vec![
+        1., 2., 5., 0.,
+        3., 4., 1., 1.,
+        0., 0., 2., 2.,
+        -1., 8., 2., 6.,
+    ]);
+    let max = MaxPool2D::new(&shape, (2, 2), (2, 2));
+    let avg = AvgPool2D::new(&shape, (2, 2), (2, 2));
+    assert_eq!(max.forward_propagate(&input, true).unwrap(), Tensor::new(&Shape::new([1, 2, 2]), vec![4., 5., 8., 6.]));
+    assert_eq!(avg.forward_propagate(&input, true).unwrap(), Tensor::new(&Shape::new([1, 2, 2]), vec![2.5, 1.75, 1.75, 3.]));
+
+    let delta = Tensor::<f64>::new(&Shape::new([1, 2, 2]), vec![1., 2., 3., 4.]);
+    assert_eq!(max.backpropagate_delta(&delta, &input, &Activation::No).unwrap(), Tensor::new(&shape, vec![
+        0., 0., 2., 0.,
+        0., 1., 0., 0.,
+        0., 0., 0., 0.,
+        0., 3., 0., 4.,
+    ]));
+    assert_eq!(avg.backpropagate_delta(&delta, &input, &Activation::No).unwrap(), Tensor::new(&shape, vec![
+        0.25, 0.25, 0.5, 0.5,
+        0.25, 0.25, 0.5, 0.5,
+        0.75, 0.75, 1., 1.,
+        0.75, 0.75, 1., 1.,
+    ]));
+
+    // overlapping windows sharing a maximum sum their deltas
+    let max = MaxPool2D::new(&shape, (3, 3), (1, 1));
+    let out = Tensor::<f64>::new(&Shape::new([1, 2, 2]), vec![1., 1., 1., 1.]);
+    let back = max.backpropagate_delta(&out, &input, &Activation::No).unwrap();
+    assert_eq!(back.flattened[2], 2.);
+    assert_eq!(back.flattened[13], 2.);
+    assert_eq!(back.flattened.iter().sum::<f64>(), 4.);
+
+    let global = GlobalAvgPool2D::new(&Shape::new([2, 1, 2]));
+    let input = Tensor::<f64>::new(&Shape::new([2, 1, 2]), vec![1., 3., -2., 0.]);
+    assert_eq!(global.forward_propagate(&input, true).unwrap(), Tensor::new(&Shape::new([2]), vec![2., -1.]));
+    let back = global.backpropagate_delta(&Tensor::new(&Shape::new([2]), vec![1., 4.]), &input, &Activation::No).unwrap();
+    assert_eq!(back, Tensor::new(&Shape::new([2, 1, 2]), vec![0.5, 0.5, 2., 2.]));
+}
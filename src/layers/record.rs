@@ -0,0 +1,454 @@
+//! Records of layers, holding what is needed to rebuild a layer: its kind,
+//! its configuration as integers and as reals, its activation and its parameters.
+//!
+//! A record is written in little endian as:
+//!
+//!  - the kind: `u32` byte length, then UTF-8
+//!  - the configuration: `u32` count, then each as `u64`, shapes being their rank then the dims;
+//!    before version 3 of the files, rates and statistics followed the shape as the bits of their `f64`
+//!  - the activation: `u8` tag (0 no, 1 sigmoid, 2 tanh, 3 relu, 4 leaky relu, 5 custom,
+//!    6 elu, 7 gelu, 8 swish, 9 softplus), then its `f64` parameter,
+//!    then for a custom activation its name as `u32` byte length and UTF-8
+//!  - the parameters: `u32` count, then each as its `u64` length and the elements,
+//!    as `f32` or `f64` following the width given by the container
+//!  - the inner records: `u32` count, then each record, from version 2 of the files
+//!  - the real configuration, e.g. rates and statistics: `u32` count, then each as `f64`,
+//!    from version 3 of the files
+//!
+//! The layers made of other layers, e.g. `Skip`, record them as inner records, which
+//! hold their own parameters.
+//!
+//! See `models::serialize` for the files of whole models.
+
+use crate::layers::*;
+use crate::layers::dense::Dense;
+use crate::layers::conv::Conv2D;
+use crate::layers::pooling::{ MaxPool2D, AvgPool2D, GlobalAvgPool2D };
+use crate::layers::padding::{ ZeroPad2D, Crop2D };
+use crate::layers::seq_pooling::{ MeanOverTime, MaxOverTime, AttentionPooling };
+use crate::layers::softmax::Softmax;
+use crate::layers::dropout::Dropout;
+use crate::layers::upsampling::UpSample2D;
+use crate::layers::reshape::{ Reshape, Flatten, Permute };
+use crate::layers::batch_norm::BatchNorm;
+use crate::layers::embedding::Embedding;
+use crate::layers::activation_layer::ActivationLayer;
+use crate::layers::skip::{ Skip, Merge };
+use crate::layers::binary::{ Binarized, WeightQuantization, units_of };
+use crate::layers::registry;
+
+use std::io::{ Error, ErrorKind, Read, Write };
+
+#[derive(Debug, Clone)]
+pub struct LayerRecord<T: NumT> {
+    pub kind: String,
+    pub config: Vec<usize>,
+    pub activation: Activation<T>,
+    /// As listed by `Layer::parameters`, less those of the inner records
+    pub parameters: Vec<Vec<T>>,
+    /// The records of the layers within, in the order of their parameters
+    pub inner: Vec<LayerRecord<T>>,
+    /// The real values of the configuration, e.g. rates, kept as `f64` on every target
+    pub floats: Vec<f64>,
+}
+
+/// The deepest nesting of inner records read
+const MAX_DEPTH: usize = 64;
+
+/// The kinds whose real values were in the configuration before version 3
+const LEGACY_FLOAT_KINDS: &[&str] = &["dropout", "batch_norm"];
+
+pub(crate) fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid easynn data: {}", msg))
+}
+
+pub(crate) fn write_u8<W: Write>(w: &mut W, x: u8) -> std::io::Result<()> {
+    w.write_all(&[x])
+}
+pub(crate) fn write_u32<W: Write>(w: &mut W, x: usize) -> std::io::Result<()> {
+    let x = u32::try_from(x).map_err(|_| invalid("a count exceeds u32"))?;
+    w.write_all(&x.to_le_bytes())
+}
+pub(crate) fn write_u64<W: Write>(w: &mut W, x: usize) -> std::io::Result<()> {
+    w.write_all(&(x as u64).to_le_bytes())
+}
+pub(crate) fn write_f64<W: Write>(w: &mut W, x: f64) -> std::io::Result<()> {
+    w.write_all(&x.to_le_bytes())
+}
+pub(crate) fn read_u8<R: Read>(r: &mut R) -> std::io::Result<u8> {
+    let mut b = [0; 1];
+    r.read_exact(&mut b)?;
+    Ok(b[0])
+}
+pub(crate) fn read_u32<R: Read>(r: &mut R) -> std::io::Result<usize> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b) as usize)
+}
+pub(crate) fn read_u64<R: Read>(r: &mut R) -> std::io::Result<usize> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    usize::try_from(u64::from_le_bytes(b)).map_err(|_| invalid("a length exceeds usize"))
+}
+pub(crate) fn read_f64<R: Read>(r: &mut R) -> std::io::Result<f64> {
+    let mut b = [0; 8];
+    r.read_exact(&mut b)?;
+    Ok(f64::from_le_bytes(b))
+}
+
+/// Read `len` elements of the width (4 or 8 bytes), without trusting `len` for the allocation
+fn read_elements<R: Read, T: NumT>(r: &mut R, len: usize, width: u8) -> std::io::Result<Vec<T>> {
+    let mut v = Vec::new();
+    for _ in 0..len {
+        let x = match width {
+            4 => {
+                let mut b = [0; 4];
+                r.read_exact(&mut b)?;
+                f32::from_le_bytes(b) as f64
+            },
+            _ => read_f64(r)?,
+        };
+        v.push(T::from(x).ok_or_else(|| invalid("an element does not fit the type"))?);
+    }
+    Ok(v)
+}
+
+impl<T: NumT> LayerRecord<T> {
+    pub fn new(kind: &str, activation: Activation<T>) -> Self {
+        LayerRecord { kind: kind.to_string(), config: Vec::new(), activation, parameters: Vec::new(), inner: Vec::new(), floats: Vec::new() }
+    }
+    /// Append the shape to the configuration, as its rank then the dims
+    pub fn shape(mut self, shape: &Shape) -> Self {
+        self.config.push(shape.rank());
+        self.config.extend_from_slice(shape.dims());
+        self
+    }
+    pub fn values(mut self, values: &[usize]) -> Self {
+        self.config.extend_from_slice(values);
+        self
+    }
+    /// Append real values, e.g. rates, to the configuration
+    pub fn floats(mut self, values: &[f64]) -> Self {
+        self.floats.extend_from_slice(values);
+        self
+    }
+    /// Copy the parameters of the layer
+    pub fn parameters_of(mut self, layer: &dyn Layer<T>) -> Self {
+        self.parameters = layer.parameters().iter().map(|p| p.to_vec()).collect();
+        self
+    }
+    /// Append the records of the layers within
+    pub fn inner(mut self, records: Vec<LayerRecord<T>>) -> Self {
+        self.inner.extend(records);
+        self
+    }
+    /// The parameters, then those of the inner records in order, as listed by `Layer::parameters`
+    pub fn all_parameters(&self) -> Vec<&[T]> {
+        let own = self.parameters.iter().map(|p| p.as_slice());
+        own.chain(self.inner.iter().flat_map(|r| r.all_parameters())).collect()
+    }
+
+    pub fn write_to<W: Write>(&self, w: &mut W, width: u8) -> std::io::Result<()> {
+        write_u32(w, self.kind.len())?;
+        w.write_all(self.kind.as_bytes())?;
+        write_u32(w, self.config.len())?;
+        for c in &self.config {
+            write_u64(w, *c)?;
+        }
+        let (tag, param) = match self.activation {
+            Activation::No => (0, 0.),
+            Activation::Sigmoid => (1, 0.),
+            Activation::Tanh => (2, 0.),
+            Activation::Relu => (3, 0.),
+            Activation::LeakyRelu(a) => (4, a.to_f64().unwrap()),
+            Activation::Custom(_) => (5, 0.),
+            Activation::Elu(a) => (6, a.to_f64().unwrap()),
+            Activation::Gelu => (7, 0.),
+            Activation::Swish => (8, 0.),
+            Activation::Softplus => (9, 0.),
+        };
+        write_u8(w, tag)?;
+        write_f64(w, param)?;
+        if let Activation::Custom(c) = self.activation {
+            write_u32(w, c.name().len())?;
+            w.write_all(c.name().as_bytes())?;
+        }
+        write_u32(w, self.parameters.len())?;
+        for p in &self.parameters {
+            write_u64(w, p.len())?;
+            for x in p {
+                let x = x.to_f64().unwrap();
+                match width {
+                    4 => w.write_all(&(x as f32).to_le_bytes())?,
+                    _ => write_f64(w, x)?,
+                }
+            }
+        }
+        write_u32(w, self.inner.len())?;
+        self.inner.iter().try_for_each(|i| i.write_to(w, width))?;
+        write_u32(w, self.floats.len())?;
+        self.floats.iter().try_for_each(|x| write_f64(w, *x))
+    }
+
+    /// Read a record of the file format `version`
+    pub fn read_from<R: Read>(r: &mut R, width: u8, version: usize) -> std::io::Result<Self> {
+        Self::read_nested(r, width, version, 0)
+    }
+    fn read_nested<R: Read>(r: &mut R, width: u8, version: usize, depth: usize) -> std::io::Result<Self> {
+        ensure(depth <= MAX_DEPTH, "the layers are nested too deep")?;
+        let len = read_u32(r)?;
+        let mut kind = Vec::new();
+        r.take(len as u64).read_to_end(&mut kind)?;
+        let kind = String::from_utf8(kind).map_err(|_| invalid("the layer kind is not UTF-8"))?;
+        let count = read_u32(r)?;
+        let mut config = Vec::new();
+        for _ in 0..count {
+            let mut b = [0; 8];
+            r.read_exact(&mut b)?;
+            config.push(u64::from_le_bytes(b));
+        }
+        // Before version 3 the real values followed the shape as the bits of their f64
+        let mut floats = Vec::new();
+        if version < 3 && LEGACY_FLOAT_KINDS.contains(&kind.as_str()) {
+            let after_shape = config.first().and_then(|rank| usize::try_from(*rank).ok()).and_then(|rank| rank.checked_add(1));
+            let at = after_shape.filter(|at| *at <= config.len()).ok_or_else(|| invalid("the layer configuration is too short"))?;
+            floats = config.split_off(at).into_iter().map(f64::from_bits).collect();
+        }
+        let config = config.into_iter().map(|x| usize::try_from(x).map_err(|_| invalid("a length exceeds usize")))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let tag = read_u8(r)?;
+        let param = T::from(read_f64(r)?).ok_or_else(|| invalid("the activation parameter does not fit the type"))?;
+        let activation = match tag {
+            0 => Activation::No,
+            1 => Activation::Sigmoid,
+            2 => Activation::Tanh,
+            3 => Activation::Relu,
+            4 => Activation::LeakyRelu(param),
+            5 => {
+                let len = read_u32(r)?;
+                let mut name = Vec::new();
+                r.take(len as u64).read_to_end(&mut name)?;
+                let name = String::from_utf8(name).map_err(|_| invalid("the activation name is not UTF-8"))?;
+                Activation::by_name(&name).ok_or_else(|| invalid(&format!("unregistered activation {}", name)))?
+            },
+            6 => Activation::Elu(param),
+            7 => Activation::Gelu,
+            8 => Activation::Swish,
+            9 => Activation::Softplus,
+            _ => return Err(invalid("unknown activation")),
+        };
+        let count = read_u32(r)?;
+        let mut parameters = Vec::new();
+        for _ in 0..count {
+            let len = read_u64(r)?;
+            parameters.push(read_elements(r, len, width)?);
+        }
+        let mut inner = Vec::new();
+        if version >= 2 {
+            for _ in 0..read_u32(r)? {
+                inner.push(Self::read_nested(r, width, version, depth + 1)?);
+            }
+        }
+        if version >= 3 {
+            floats = (0..read_u32(r)?).map(|_| read_f64(r)).collect::<std::io::Result<Vec<_>>>()?;
+        }
+        Ok(LayerRecord { kind, config, activation, parameters, inner, floats })
+    }
+}
+
+/// A cursor over the configuration
+/// A reader of the configuration of a record, failing with `ErrorKind::InvalidData` past its end
+pub struct Config<'a>(pub(crate) std::slice::Iter<'a, usize>);
+
+impl<'a> Config<'a> {
+    pub fn new(config: &'a [usize]) -> Self {
+        Config(config.iter())
+    }
+    pub fn value(&mut self) -> std::io::Result<usize> {
+        self.0.next().copied().ok_or_else(|| invalid("the layer configuration is too short"))
+    }
+    pub fn pair(&mut self) -> std::io::Result<(usize, usize)> {
+        Ok((self.value()?, self.value()?))
+    }
+    pub fn quad(&mut self) -> std::io::Result<(usize, usize, usize, usize)> {
+        Ok((self.value()?, self.value()?, self.value()?, self.value()?))
+    }
+    /// A shape appended by `LayerRecord::shape`
+    pub fn shape(&mut self) -> std::io::Result<Shape> {
+        let rank = self.value()?;
+        let dims = (0..rank).map(|_| self.value()).collect::<std::io::Result<Vec<_>>>()?;
+        let shape = Shape::from_slice(&dims);
+        shape.checked_size().map_err(|_| invalid("the shape overflows"))?;
+        Ok(shape)
+    }
+}
+
+/// A reader of the real values of a record, failing with `ErrorKind::InvalidData` past their end
+pub struct Floats<'a>(pub(crate) std::slice::Iter<'a, f64>);
+
+impl<'a> Floats<'a> {
+    pub fn new(floats: &'a [f64]) -> Self {
+        Floats(floats.iter())
+    }
+    pub fn value(&mut self) -> std::io::Result<f64> {
+        self.0.next().copied().ok_or_else(|| invalid("the layer values are too short"))
+    }
+}
+
+/// The kinds rebuilt by `LayerRecord::into_layer` itself
+pub const BUILTIN_KINDS: &[&str] = &[
+    "dense", "conv2d", "max_pool2d", "avg_pool2d", "zero_pad2d", "crop2d", "softmax", "mean_over_time", "max_over_time",
+    "attention_pooling", "dropout", "up_sample2d", "reshape", "permute", "global_avg_pool2d", "batch_norm", "embedding",
+    "activation", "flatten", "skip", "binarized",
+];
+
+pub(crate) fn ensure(cond: bool, msg: &str) -> std::io::Result<()> {
+    if cond { Ok(()) } else { Err(invalid(msg)) }
+}
+
+impl<T: NumT + 'static> LayerRecord<T> {
+    /// Rebuild the layer, checking the configuration and the parameter lengths.
+    /// Kinds other than the built-in ones are built by the builders of `registry`.
+    pub fn into_layer(self) -> std::io::Result<Box<dyn Layer<T>>> {
+        self.load()
+    }
+    fn load(&self) -> std::io::Result<Box<dyn Layer<T>>> {
+        let mut layer = self.build()?;
+        let saved = self.all_parameters();
+        let mut params = layer.parameters_mut();
+        ensure(params.len() == saved.len(), "wrong count of parameters")?;
+        for (p, saved) in params.iter_mut().zip(saved.iter()) {
+            ensure(p.len() == saved.len(), "wrong length of parameters")?;
+            p.copy_from_slice(saved);
+        }
+        Ok(layer)
+    }
+
+    /// Build the layer of the configuration, with freshly initialized parameters,
+    /// the inner layers being rebuilt with theirs
+    pub(crate) fn build(&self) -> std::io::Result<Box<dyn Layer<T>>> {
+        let (mut c, mut f) = (Config(self.config.iter()), Floats(self.floats.iter()));
+        let layer: Box<dyn Layer<T>> = match self.kind.as_str() {
+            "dense" => {
+                let (i_shape, o_shape) = (c.shape()?, c.shape()?);
+                let layer = Dense::try_new(&i_shape, &o_shape, self.activation).map_err(|e| invalid(&e.to_string()))?;
+                Box::new(layer)
+            },
+            "conv2d" => {
+                let i_shape = c.shape()?;
+                let (out_channels, kernel, stride, (t, b, l, r)) = (c.value()?, c.pair()?, c.pair()?, c.quad()?);
+                let padding = Padding::Explicit(t, b, l, r);
+                let layer = Conv2D::try_new(&i_shape, out_channels, kernel, stride, padding, self.activation).map_err(|e| invalid(&e.to_string()))?;
+                Box::new(layer)
+            },
+            "max_pool2d" | "avg_pool2d" => {
+                let (i_shape, kernel, stride) = (c.shape()?, c.pair()?, c.pair()?);
+                ensure(i_shape.rank() == 3 && i_shape.window_output(kernel, stride, Padding::Valid).is_ok(), "invalid pooling")?;
+                if self.kind == "max_pool2d" {
+                    Box::new(MaxPool2D::new(&i_shape, kernel, stride))
+                } else {
+                    Box::new(AvgPool2D::new(&i_shape, kernel, stride))
+                }
+            },
+            "zero_pad2d" | "crop2d" => {
+                let (i_shape, (t, b, l, r)) = (c.shape()?, c.quad()?);
+                ensure(i_shape.rank() >= 2, "invalid padding or cropping")?;
+                if self.kind == "zero_pad2d" {
+                    Box::new(ZeroPad2D::new(&i_shape, (t, b, l, r)))
+                } else {
+                    let (h, w) = (i_shape[i_shape.rank() - 2], i_shape[i_shape.rank() - 1]);
+                    ensure(t + b < h && l + r < w, "invalid cropping")?;
+                    Box::new(Crop2D::new(&i_shape, (t, b, l, r)))
+                }
+            },
+            "softmax" => {
+                let i_shape = c.shape()?;
+                ensure(i_shape.rank() > 0 && i_shape.size() > 0, "invalid Softmax")?;
+                Box::new(Softmax::new(&i_shape))
+            },
+            "mean_over_time" | "max_over_time" | "attention_pooling" => {
+                let i_shape = c.shape()?;
+                ensure(i_shape.rank() == 2 && i_shape.size() > 0, "invalid sequence pooling")?;
+                match self.kind.as_str() {
+                    "mean_over_time" => Box::new(MeanOverTime::new(&i_shape)),
+                    "max_over_time" => Box::new(MaxOverTime::new(&i_shape)),
+                    _ => Box::new(AttentionPooling::<T>::new(&i_shape)),
+                }
+            },
+            "dropout" => {
+                let (i_shape, rate) = (c.shape()?, f.value()?);
+                ensure((0. ..1.).contains(&rate), "invalid dropout rate")?;
+                Box::new(Dropout::new(&i_shape, T::from(rate).unwrap()))
+            },
+            "up_sample2d" => {
+                let (i_shape, factor) = (c.shape()?, c.pair()?);
+                ensure(i_shape.rank() == 3 && factor.0 > 0 && factor.1 > 0, "invalid upsampling")?;
+                ensure(i_shape.size().checked_mul(factor.0).and_then(|s| s.checked_mul(factor.1)).is_some(), "the upsampling overflows")?;
+                Box::new(UpSample2D::new(&i_shape, factor))
+            },
+            "reshape" => {
+                let (i_shape, o_shape) = (c.shape()?, c.shape()?);
+                ensure(i_shape.size() == o_shape.size(), "invalid Reshape")?;
+                Box::new(Reshape::new(&i_shape, &o_shape))
+            },
+            "flatten" => Box::new(Flatten::new(&c.shape()?)),
+            "permute" => {
+                let i_shape = c.shape()?;
+                let axes = (0..i_shape.rank()).map(|_| c.value()).collect::<std::io::Result<Vec<_>>>()?;
+                let mut sorted = axes.clone();
+                sorted.sort_unstable();
+                ensure(sorted.iter().enumerate().all(|(i, a)| i == *a), "invalid Permute")?;
+                Box::new(Permute::new(&i_shape, &axes))
+            },
+            "global_avg_pool2d" => {
+                let i_shape = c.shape()?;
+                ensure(i_shape.rank() == 3 && i_shape.size() > 0, "invalid global pooling")?;
+                Box::new(GlobalAvgPool2D::new(&i_shape))
+            },
+            "batch_norm" => {
+                let i_shape = c.shape()?;
+                ensure(i_shape.rank() > 0 && i_shape.size() > 0, "invalid BatchNorm")?;
+                let (momentum, eps) = (f.value()?, f.value()?);
+                ensure((0. ..=1.).contains(&momentum) && eps > 0., "invalid BatchNorm")?;
+                let mut layer = BatchNorm::with_momentum(&i_shape, T::from(momentum).unwrap(), T::from(eps).unwrap(), self.activation);
+                for i in 0..2 * i_shape[0] {
+                    let x = T::from(f.value()?).ok_or_else(|| invalid("a statistic does not fit the type"))?;
+                    if i < i_shape[0] { layer.running_mean[i] = x } else { layer.running_var[i - i_shape[0]] = x }
+                }
+                Box::new(layer)
+            },
+            "embedding" => {
+                let (i_shape, vocab, dim) = (c.shape()?, c.value()?, c.value()?);
+                Box::new(Embedding::try_new(&i_shape, vocab, dim).map_err(|e| invalid(&e.to_string()))?)
+            },
+            "activation" => {
+                let i_shape = c.shape()?;
+                Box::new(ActivationLayer::new(&i_shape, self.activation))
+            },
+            "skip" => {
+                let (i_shape, merge, shortcut) = (c.shape()?, c.value()?, c.value()?);
+                ensure(merge < 2 && shortcut <= self.inner.len(), "invalid Skip")?;
+                let mut layers = self.inner.iter().map(|r| r.load()).collect::<std::io::Result<Vec<_>>>()?;
+                let body = layers.split_off(shortcut);
+                let merge = if merge == 0 { Merge::Add } else { Merge::Concat };
+                let layer = Skip::from_chains(&i_shape, merge, self.activation, layers, body).map_err(|e| invalid(&e.to_string()))?;
+                Box::new(layer)
+            },
+            "binarized" => {
+                let mode = match c.value()? {
+                    0 => WeightQuantization::Binary,
+                    1 => WeightQuantization::Ternary,
+                    _ => return Err(invalid("unknown weight quantization")),
+                };
+                ensure(self.inner.len() == 1, "invalid Binarized")?;
+                let layer = self.inner[0].load()?;
+                let units = units_of(layer.as_ref());
+                ensure(layer.parameters().first().is_some_and(|w| units > 0 && w.len() >= units), "invalid Binarized")?;
+                Box::new(Binarized::from_boxed(layer, mode))
+            },
+            kind => return registry::build(self)?.ok_or_else(|| invalid(&format!("unknown layer kind {}", kind))),
+        };
+        ensure(c.0.next().is_none() && f.0.next().is_none(), "the layer configuration is too long")?;
+        Ok(layer)
+    }
+}
@@ -0,0 +1,176 @@
+//! The registry of layer kinds, building layers by the name of their kind: the built-in
+//! kinds of `LayerRecord::into_layer`, and the custom layers registered by the user.
+//!
+//! A custom layer returns a `LayerRecord` of its own kind from `Layer::record`, and
+//! its builder rebuilds it from such a record with fresh parameters; the parameters
+//! of a saved layer are then copied in. A layer type implementing `FromRecord` is
+//! registered by `register_layer`, other builders by `register`. Once registered, the
+//! layer is saved and loaded with models like the built-in ones, `create_layer` builds
+//! it from a configuration, and `Sequential::summary` names it by its kind.
+//! Builders are registered per element type, as `Layer<f32>` and `Layer<f64>` differ.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::{ Layer, registry };
+//!     // a configuration-driven layer: the kind, the shapes and the activation
+//!     let config = [1, 4, 1, 2];
+//!     let dense = registry::create_layer::<f32>("dense", &config, Activation::Relu).unwrap();
+//!     assert_eq!(dense.get_output_shape(), Shape::new([2]));
+//!     assert!(registry::kinds::<f32>().contains(&"dense".to_string()));
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::{ LayerRecord, BUILTIN_KINDS };
+
+use std::any::{ Any, TypeId };
+use std::collections::HashMap;
+use std::sync::{ Arc, OnceLock, RwLock };
+
+/// Build a layer of the configuration and the activation of the record, with fresh parameters
+pub type LayerBuilder<T> = Arc<dyn Fn(&LayerRecord<T>) -> std::io::Result<Box<dyn Layer<T>>> + Send + Sync>;
+
+/// The builders by the element type and the kind, each a boxed `LayerBuilder` of its type
+type Builders = HashMap<(TypeId, String), Box<dyn Any + Send + Sync>>;
+
+static BUILDERS: OnceLock<RwLock<Builders>> = OnceLock::new();
+
+fn builders() -> &'static RwLock<Builders> {
+    BUILDERS.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// A layer type rebuilt from its records, for `register_layer`
+pub trait FromRecord<T: NumT>: Layer<T> + Sized + 'static {
+    /// The layer of the configuration and the activation of the record, with fresh
+    /// parameters, those of the record being copied in afterwards
+    fn from_record(record: &LayerRecord<T>) -> std::io::Result<Self>;
+}
+
+/// Register the layer type under the kind of its records, e.g.
+/// `register_layer::<MyLayer<f32>, f32>("my_layer")`
+pub fn register_layer<L: FromRecord<T>, T: NumT>(kind: &str) -> Result<()> {
+    register(kind, |r: &LayerRecord<T>| Ok(Box::new(L::from_record(r)?) as Box<dyn Layer<T>>))
+}
+
+/// Register the builder of the kind, replacing the one registered before, if any
+pub fn register<T: NumT, F>(kind: &str, builder: F) -> Result<()>
+where F: Fn(&LayerRecord<T>) -> std::io::Result<Box<dyn Layer<T>>> + Send + Sync + 'static {
+    if BUILTIN_KINDS.contains(&kind) {
+        return Err(EasynnError::invalid("register", format!("{} is a built-in layer kind", kind)));
+    }
+    let builder: LayerBuilder<T> = Arc::new(builder);
+    builders().write().unwrap().insert((TypeId::of::<T>(), kind.to_string()), Box::new(builder));
+    Ok(())
+}
+
+/// Remove the builder of the kind, returning whether there was one
+pub fn unregister<T: NumT>(kind: &str) -> bool {
+    builders().write().unwrap().remove(&(TypeId::of::<T>(), kind.to_string())).is_some()
+}
+
+/// The built-in kinds then the registered ones of the element type, sorted
+pub fn kinds<T: NumT>() -> Vec<String> {
+    let mut custom: Vec<String> = builders().read().unwrap().keys()
+        .filter(|(t, _)| *t == TypeId::of::<T>()).map(|(_, k)| k.clone()).collect();
+    custom.sort();
+    BUILTIN_KINDS.iter().map(|k| k.to_string()).chain(custom).collect()
+}
+
+/// Build the layer of the record by the registered builder of its kind, None if unregistered
+pub(crate) fn build<T: NumT>(record: &LayerRecord<T>) -> std::io::Result<Option<Box<dyn Layer<T>>>> {
+    let builder = builders().read().unwrap().get(&(TypeId::of::<T>(), record.kind.clone()))
+        .and_then(|b| b.downcast_ref::<LayerBuilder<T>>()).cloned();
+    builder.map(|b| b(record)).transpose()
+}
+
+/// Build a layer of the kind by name from its configuration, as appended to a `LayerRecord`,
+/// with freshly initialized parameters. The kinds also configured by real values, e.g. `dropout`,
+/// are built from a record holding them by `LayerRecord::into_layer`.
+pub fn create_layer<T: NumT>(kind: &str, config: &[usize], activation: Activation<T>) -> std::io::Result<Box<dyn Layer<T>>> {
+    let mut record = LayerRecord::new(kind, activation);
+    record.config = config.to_vec();
+    record.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layers::record::Config;
+    use crate::models::Model;
+    use crate::models::sequential::Sequential;
+    use crate::models::losses::Loss;
+
+    /// Scales the input by a factor, a parameter
+    struct Scale {
+        shape: Shape,
+        factor: Vec<f64>,
+    }
+
+    impl Layer<f64> for Scale {
+        fn get_activation(&self) -> Activation<f64> {
+            Activation::No
+        }
+        fn get_input_shape(&self) -> Shape {
+            self.shape.clone()
+        }
+        fn get_output_shape(&self) -> Shape {
+            self.shape.clone()
+        }
+        fn get_weight_count(&self) -> usize {
+            1
+        }
+        fn activate(&self, output: &Tensor<f64>) -> Result<Tensor<f64>> {
+            Ok(output.clone())
+        }
+        fn add_weight_delta_to(&self, _delta: &Tensor<f64>, _a_lst: &Tensor<f64>, _cum_dw: &mut Vec<f64>, _cum_db: &mut Tensor<f64>) -> Result<()> {
+            Ok(())
+        }
+        fn descend(&mut self, _rate: f64, _dw: &[f64], _db: &Tensor<f64>) -> Result<()> {
+            Ok(())
+        }
+        fn forward_propagate(&self, input: &Tensor<f64>, _activate: bool) -> Result<Tensor<f64>> {
+            Ok(input.map(|x| x * self.factor[0]))
+        }
+        fn backpropagate_delta(&self, delta: &Tensor<f64>, _z_lst: &Tensor<f64>, _sigma_lst: &Activation<f64>) -> Result<Tensor<f64>> {
+            Ok(delta.map(|d| d * self.factor[0]))
+        }
+        fn parameters(&self) -> Vec<&[f64]> {
+            vec![&self.factor]
+        }
+        fn parameters_mut(&mut self) -> Vec<&mut [f64]> {
+            vec![&mut self.factor]
+        }
+        fn record(&self) -> Option<LayerRecord<f64>> {
+            Some(LayerRecord::new("test_scale", Activation::No).shape(&self.shape).parameters_of(self))
+        }
+    }
+
+    impl FromRecord<f64> for Scale {
+        fn from_record(record: &LayerRecord<f64>) -> std::io::Result<Self> {
+            Ok(Scale { shape: Config::new(&record.config).shape()?, factor: vec![1.] })
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        assert!(create_layer::<f64>("test_scale", &[1, 3], Activation::No).is_err());
+        register_layer::<Scale, _>("test_scale").unwrap();
+        assert!(kinds::<f64>().contains(&"test_scale".to_string()));
+        assert!(!kinds::<f32>().contains(&"test_scale".to_string()));
+        assert!(register("dense", |r: &LayerRecord<f64>| LayerRecord::new("dense", r.activation).into_layer()).is_err());
+
+        // a model of the custom layer is saved and loaded
+        let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+        nn.add(Scale { shape: Shape::new([3]), factor: vec![2.5] });
+        let mut buf = Vec::new();
+        nn.write_to(&mut buf).unwrap();
+        let loaded = Sequential::<f64>::read_from(&mut buf.as_slice()).unwrap();
+        let x = Tensor::new(&Shape::new([3]), vec![1., 2., 3.]);
+        assert_eq!(loaded.predict(&x).unwrap().as_slice(), &[2.5, 5., 7.5]);
+        assert!(loaded.summary().lines().nth(1).unwrap().starts_with("0   test_scale"));
+        let fresh = create_layer::<f64>("test_scale", &[1, 3], Activation::No).unwrap();
+        assert_eq!(fresh.parameters(), [&[1.][..]]);
+
+        assert!(unregister::<f64>("test_scale"));
+        assert!(Sequential::<f64>::read_from(&mut buf.as_slice()).is_err());
+    }
+}
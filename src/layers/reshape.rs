@@ -0,0 +1,201 @@
+//! Layers rearranging the elements without weights: `Reshape` to another shape of the
+//! same size, `Flatten` to rank 1, e.g. from a feature map of a convolution to a dense
+//! head, and `Permute` of the axes, e.g. to swap the tokens and the channels of
+//! `[tokens, channels]` features.
+//!
+//! The deltas are passed through by the inverse rearrangement.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let flat = Reshape::new(sh!([8, 4, 4]), sh!([8, 1, 16]));
+//!     let swap = Permute::new(&Layer::<f32>::get_output_shape(&flat), &[2, 1, 0]);
+//!     assert_eq!(Layer::<f32>::get_output_shape(&swap), Shape::new([16, 1, 8]));
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+#[derive(Debug)]
+pub struct Reshape {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+}
+
+impl Reshape {
+    /// The shapes should be of the same size
+    pub fn new(i_shape: &Shape, o_shape: &Shape) -> Self {
+        if i_shape.size() != o_shape.size() {
+            panic!("Reshape needs shapes of the same size!");
+        }
+        Reshape { input_shape: i_shape.clone(), output_shape: o_shape.clone() }
+    }
+}
+
+impl<T: NumT> Layer<T> for Reshape {
+    impl_weightless!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("reshape", Activation::No).shape(&self.input_shape).shape(&self.output_shape))
+    }
+    /// Only flattening passes the units, each input unit becoming a block of output units
+    fn passes_units(&self) -> bool {
+        self.output_shape.rank() == 1
+    }
+    fn pass_units(&mut self, map: &[usize]) -> Result<Vec<usize>> {
+        if !Layer::<T>::passes_units(self) {
+            return Err(EasynnError::invalid("pass_units", "only the Reshape layers flattening their input pass their units"));
+        }
+        check_unit_map("pass_units", map, self.input_shape[0])?;
+        let block = self.input_shape.size() / self.input_shape[0];
+        self.input_shape = with_units(&self.input_shape, map.len());
+        self.output_shape = Shape::new([map.len() * block]);
+        Ok(map.iter().flat_map(|k| k * block..(k + 1) * block).collect())
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        input.reshape(&self.output_shape)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let mut lst_delta = delta.reshape(&self.input_shape)?;
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+/// Flatten the input into `[size]`, keeping the order of the elements
+#[derive(Debug)]
+pub struct Flatten {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+}
+
+impl Flatten {
+    pub fn new(i_shape: &Shape) -> Self {
+        Flatten { input_shape: i_shape.clone(), output_shape: Shape::new([i_shape.size()]) }
+    }
+}
+
+impl<T: NumT> Layer<T> for Flatten {
+    impl_weightless!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("flatten", Activation::No).shape(&self.input_shape))
+    }
+    /// Each input unit becomes a block of output units
+    fn passes_units(&self) -> bool {
+        self.input_shape.rank() > 0
+    }
+    fn pass_units(&mut self, map: &[usize]) -> Result<Vec<usize>> {
+        if !Layer::<T>::passes_units(self) {
+            return Err(EasynnError::invalid("pass_units", "a Flatten of a scalar has no units to pass"));
+        }
+        check_unit_map("pass_units", map, self.input_shape[0])?;
+        let block = self.input_shape.size() / self.input_shape[0];
+        self.input_shape = with_units(&self.input_shape, map.len());
+        self.output_shape = Shape::new([map.len() * block]);
+        Ok(map.iter().flat_map(|k| k * block..(k + 1) * block).collect())
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        input.reshape(&self.output_shape)
+    }
+    fn forward_batch(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        let n = batch_len("forward_batch", &input.shape, &self.input_shape)?;
+        input.reshape(&self.output_shape.batched(n))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let mut lst_delta = delta.reshape(&self.input_shape)?;
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[derive(Debug)]
+pub struct Permute {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    /// Axis `i` of the output is axis `axes[i]` of the input
+    pub(crate) axes: Vec<usize>,
+    /// The inverse permutation, for the deltas
+    inverse: Vec<usize>,
+}
+
+impl Permute {
+    /// The axes should be a permutation of the axes of the input, see `Tensor::permute`
+    pub fn new(i_shape: &Shape, axes: &[usize]) -> Self {
+        if axes.len() != i_shape.rank() {
+            panic!("Permute needs as many axes as the rank of the input!");
+        }
+        let mut inverse = vec![usize::MAX; axes.len()];
+        for (i, a) in axes.iter().enumerate() {
+            if *a >= i_shape.rank() || inverse[*a] != usize::MAX {
+                panic!("Permute needs a permutation of the axes of the input!");
+            }
+            inverse[*a] = i;
+        }
+        let output_shape = Shape::from_slice(&axes.iter().map(|a| i_shape[*a]).collect::<Vec<_>>());
+        Permute { input_shape: i_shape.clone(), output_shape, axes: axes.to_vec(), inverse }
+    }
+}
+
+impl<T: NumT> Layer<T> for Permute {
+    impl_weightless!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("permute", Activation::No).shape(&self.input_shape).values(&self.axes))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        input.permute(&self.axes)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let mut lst_delta = delta.permute(&self.inverse)?;
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[test]
+fn test_reshape_permute() {
+    let input = Tensor::new(&Shape::new([2, 3]), vec![1., 2., 3., 4., 5., 6.]);
+    let reshape = Reshape::new(&Shape::new([2, 3]), &Shape::new([3, 1, 2]));
+    let output = reshape.forward_propagate(&input, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([3, 1, 2]), vec![1., 2., 3., 4., 5., 6.]));
+    let back = reshape.backpropagate_delta(&output, &input, &Activation::Relu).unwrap();
+    assert_eq!(back, input);
+
+    let flatten = Flatten::new(&Shape::new([1, 2, 3]));
+    let image = Tensor::new(&Shape::new([1, 2, 3]), vec![1., 2., 3., 4., 5., 6.]);
+    let output = flatten.forward_propagate(&image, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([6]), vec![1., 2., 3., 4., 5., 6.]));
+    let z = Tensor::new(&Shape::new([1, 2, 3]), vec![1., -1., 1., -1., 1., -1.]);
+    let back = flatten.backpropagate_delta(&output, &z, &Activation::Relu).unwrap();
+    assert_eq!(back, Tensor::new(&Shape::new([1, 2, 3]), vec![1., 0., 3., 0., 5., 0.]));
+    let batch = Tensor::stack(&Shape::new([1, 2, 3]), &[image.clone(), image]).unwrap();
+    assert_eq!(flatten.forward_batch(&batch, true).unwrap().shape, Shape::new([2, 6]));
+
+    let permute = Permute::new(&Shape::new([2, 3]), &[1, 0]);
+    let output = permute.forward_propagate(&input, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([3, 2]), vec![1., 4., 2., 5., 3., 6.]));
+    let z = Tensor::new(&Shape::new([2, 3]), vec![1., -1., 1., -1., 1., -1.]);
+    let back = permute.backpropagate_delta(&output, &z, &Activation::Relu).unwrap();
+    assert_eq!(back, Tensor::new(&Shape::new([2, 3]), vec![1., 0., 3., 0., 5., 0.]));
+
+    // a cyclic permutation of rank 3 is undone by its inverse
+    let permute = Permute::new(&Shape::new([2, 3, 4]), &[1, 2, 0]);
+    assert_eq!(permute.inverse, [2, 0, 1]);
+    let record = Layer::<f64>::record(&permute).unwrap();
+    assert_eq!(record.config, [3, 2, 3, 4, 1, 2, 0]);
+    let rebuilt = record.into_layer().unwrap();
+    assert_eq!(rebuilt.get_output_shape(), Shape::new([3, 4, 2]));
+}
@@ -0,0 +1,307 @@
+//! Pooling layers over sequences, collapsing an input of `[seq_len, d]` into `[d]`,
+//! used as the readout of sequence models.
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+use rand::Rng;
+
+/// Check that the shape is `[seq_len, d]`, returning `(seq_len, d)`
+fn seq_dims(i_shape: &Shape) -> (usize, usize) {
+    if i_shape.rank() != 2 || i_shape.size() == 0 {
+        panic!("Sequence pooling needs a non-empty input of shape [seq_len, d]!");
+    }
+    (i_shape[0], i_shape[1])
+}
+
+/// Average the input over time
+#[derive(Debug)]
+pub struct MeanOverTime {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+}
+
+impl MeanOverTime {
+    pub fn new(i_shape: &Shape) -> Self {
+        let (_, d) = seq_dims(i_shape);
+        MeanOverTime { input_shape: i_shape.clone(), output_shape: Shape::new([d]) }
+    }
+}
+
+/// Take the maximum of each feature over time
+#[derive(Debug)]
+pub struct MaxOverTime {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+}
+
+impl MaxOverTime {
+    pub fn new(i_shape: &Shape) -> Self {
+        let (_, d) = seq_dims(i_shape);
+        MaxOverTime { input_shape: i_shape.clone(), output_shape: Shape::new([d]) }
+    }
+}
+
+impl<T: NumT> Layer<T> for MeanOverTime {
+    impl_weightless!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("mean_over_time", Activation::No).shape(&self.input_shape))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let (len, d) = (self.input_shape[0], self.input_shape[1]);
+        let mut output = Tensor::<T>::zeros(&self.output_shape);
+        for x_t in input.flattened.chunks(d) {
+            for (o, x) in output.flattened.iter_mut().zip(x_t.iter()) {
+                *o += *x;
+            }
+        }
+        let len_t = T::from(len).unwrap();
+        output.flattened.iter_mut().for_each(|o| { *o /= len_t; });
+        Ok(output)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let (len, d) = (self.input_shape[0], self.input_shape[1]);
+        let len_t = T::from(len).unwrap();
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for d_t in lst_delta.flattened.chunks_mut(d) {
+            for (dt, dl) in d_t.iter_mut().zip(delta.flattened.iter()) {
+                *dt = *dl / len_t;
+            }
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+impl<T: NumT> Layer<T> for MaxOverTime {
+    impl_weightless!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("max_over_time", Activation::No).shape(&self.input_shape))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let d = self.input_shape[1];
+        let mut output = Tensor::<T>::new(&self.output_shape, input.flattened[..d].to_vec());
+        for x_t in input.flattened.chunks(d).skip(1) {
+            for (o, x) in output.flattened.iter_mut().zip(x_t.iter()) {
+                if *x > *o {
+                    *o = *x;
+                }
+            }
+        }
+        Ok(output)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        // route each delta to the step holding the maximum of the last activation
+        let d = self.input_shape[1];
+        let a_lst: Vec<T> = z_lst.flattened.iter().map(|z| sigma_lst.call(*z)).collect();
+        let mut argmax = vec![0_usize; d];
+        for (t, a_t) in a_lst.chunks(d).enumerate().skip(1) {
+            for (j, a) in a_t.iter().enumerate() {
+                if *a > a_lst[argmax[j] * d + j] {
+                    argmax[j] = t;
+                }
+            }
+        }
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for (j, (t, dl)) in argmax.iter().zip(delta.flattened.iter()).enumerate() {
+            lst_delta.flattened[t * d + j] = *dl;
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+/// Learned attention pooling: each step t gets a score `s_t = x_t . w`,
+/// and the output is the softmax(s) weighted sum of the steps
+#[derive(Debug)]
+pub struct AttentionPooling<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) weight: Vec<T>,
+}
+
+impl<T: NumT> AttentionPooling<T> {
+    pub fn new(i_shape: &Shape) -> Self {
+        let (_, d) = seq_dims(i_shape);
+        let mut rng = rand::thread_rng();
+        AttentionPooling::<T> {
+            input_shape: i_shape.clone(),
+            output_shape: Shape::new([d]),
+            weight: (0..d).map(|_| T::from(rng.gen_range(-0.1..=0.1)).unwrap()).collect(),
+        }
+    }
+
+    /// The attention of each step on the input
+    fn attention(&self, x: &[T]) -> Vec<T> {
+        let d = self.input_shape[1];
+        let scores: Vec<T> = x.chunks(d).map(|x_t| {
+            x_t.iter().zip(self.weight.iter()).map(|(x, w)| *x * *w).sum()
+        }).collect();
+        let max = scores.iter().fold(T::neg_infinity(), |m, s| m.max(*s));
+        let exps: Vec<T> = scores.iter().map(|s| (*s - max).exp()).collect();
+        let sum: T = exps.iter().copied().sum();
+        exps.into_iter().map(|e| e / sum).collect()
+    }
+
+    /// The gradients of the loss w.r.t. the scores, i.e. `alpha_t (g_t - sum_u alpha_u g_u)`
+    /// where `g_t = delta . x_t`
+    fn score_grads(&self, x: &[T], alpha: &[T], delta: &[T]) -> Vec<T> {
+        let d = self.input_shape[1];
+        let g: Vec<T> = x.chunks(d).map(|x_t| {
+            x_t.iter().zip(delta.iter()).map(|(x, dl)| *x * *dl).sum()
+        }).collect();
+        let g_mean: T = alpha.iter().zip(g.iter()).map(|(a, g)| *a * *g).sum();
+        alpha.iter().zip(g.iter()).map(|(a, g)| *a * (*g - g_mean)).collect()
+    }
+}
+
+impl<T: NumT> Layer<T> for AttentionPooling<T> {
+    fn get_activation(&self) -> Activation<T> {
+        Activation::No
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.input_shape.clone()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.output_shape.clone()
+    }
+    fn get_weight_count(&self) -> usize {
+        self.weight.len()
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let d = self.input_shape[1];
+        let alpha = self.attention(&input.flattened);
+        let mut output = Tensor::<T>::zeros(&self.output_shape);
+        for (x_t, a) in input.flattened.chunks(d).zip(alpha.iter()) {
+            for (o, x) in output.flattened.iter_mut().zip(x_t.iter()) {
+                *o += *a * *x;
+            }
+        }
+        Ok(output)
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("activate", &self.output_shape, &output.shape)?;
+        Ok(output.clone())
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let d = self.input_shape[1];
+        let x: Vec<T> = z_lst.flattened.iter().map(|z| sigma_lst.call(*z)).collect();
+        let alpha = self.attention(&x);
+        let ds = self.score_grads(&x, &alpha, &delta.flattened);
+        // d x_t = alpha_t delta + ds_t w
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for ((d_t, a), s) in lst_delta.flattened.chunks_mut(d).zip(alpha.iter()).zip(ds.iter()) {
+            for ((dt, dl), w) in d_t.iter_mut().zip(delta.flattened.iter()).zip(self.weight.iter()) {
+                *dt = *a * *dl + *s * *w;
+            }
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        check_len("add_weight_delta_to", self.weight.len(), cum_dw.len())?;
+        check_shape("add_weight_delta_to", &delta.shape, &cum_db.shape)?;
+        check_shape("add_weight_delta_to", &self.input_shape, &a_lst.shape)?;
+        // dw = sum_t ds_t x_t, there is no bias
+        let d = self.input_shape[1];
+        let alpha = self.attention(&a_lst.flattened);
+        let ds = self.score_grads(&a_lst.flattened, &alpha, &delta.flattened);
+        for (x_t, s) in a_lst.flattened.chunks(d).zip(ds.iter()) {
+            for (w, x) in cum_dw.iter_mut().zip(x_t.iter()) {
+                *w += *s * *x;
+            }
+        }
+        Ok(())
+    }
+    fn descend(&mut self, rate: T, dw: &[T], _db: &Tensor<T>) -> Result<()> {
+        check_len("descend", self.weight.len(), dw.len())?;
+        for (w, dwi) in self.weight.iter_mut().zip(dw.iter()) {
+            *w -= rate * *dwi;
+        }
+        Ok(())
+    }
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("attention_pooling", Activation::No).shape(&self.input_shape).parameters_of(self))
+    }
+    fn parameters(&self) -> Vec<&[T]> {
+        vec![&self.weight]
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        vec![&mut self.weight]
+    }
+}
+
+#[test]
+fn test_mean_max_over_time() {
+    let input = Tensor::<f64>::new(&Shape::new([3, 2]), vec![
+        1., -4.,
+        3., 2.,
+        -1., 5.,
+    ]);
+    let mean = MeanOverTime::new(&Shape::new([3, 2]));
+    let max = MaxOverTime::new(&Shape::new([3, 2]));
+    assert_eq!(mean.forward_propagate(&input, true).unwrap(), Tensor::new(&Shape::new([2]), vec![1., 1.]));
+    assert_eq!(max.forward_propagate(&input, true).unwrap(), Tensor::new(&Shape::new([2]), vec![3., 5.]));
+
+    let delta = Tensor::<f64>::new(&Shape::new([2]), vec![3., 6.]);
+    assert_eq!(
+        mean.backpropagate_delta(&delta, &input, &Activation::No).unwrap(),
+        Tensor::new(&Shape::new([3, 2]), vec![1., 2., 1., 2., 1., 2.])
+    );
+    assert_eq!(
+        max.backpropagate_delta(&delta, &input, &Activation::No).unwrap(),
+        Tensor::new(&Shape::new([3, 2]), vec![0., 0., 3., 0., 0., 6.])
+    );
+}
+
+#[test]
+fn test_attention_pooling_gradients() {
+    let shape = Shape::new([3, 2]);
+    let x = vec![0.5, -1., 2., 0.3, -0.7, 1.2];
+    let mut l = AttentionPooling::<f64>::new(&shape);
+    l.weight = vec![0.8, -0.4];
+    let delta = Tensor::<f64>::new(&Shape::new([2]), vec![1., -2.]);
+    // the loss is delta . output, so its gradient w.r.t. the output is delta
+    let loss = |l: &AttentionPooling<f64>, x: &Vec<f64>| {
+        let o = l.forward_propagate(&Tensor::new(&shape, x.clone()), true).unwrap();
+        o.flattened.iter().zip(delta.flattened.iter()).map(|(o, d)| o * d).sum::<f64>()
+    };
+    let eps = 1e-6;
+
+    let dx = l.backpropagate_delta(&delta, &Tensor::new(&shape, x.clone()), &Activation::No).unwrap();
+    for i in 0..x.len() {
+        let (mut xp, mut xm) = (x.clone(), x.clone());
+        xp[i] += eps;
+        xm[i] -= eps;
+        let num = (loss(&l, &xp) - loss(&l, &xm)) / (2. * eps);
+        assert!((num - dx.flattened[i]).abs() < 1e-6, "expected {}, got {}", num, dx.flattened[i]);
+    }
+
+    let mut dw = vec![0.; 2];
+    let mut db = Tensor::<f64>::zeros(&Shape::new([2]));
+    l.add_weight_delta_to(&delta, &Tensor::new(&shape, x.clone()), &mut dw, &mut db).unwrap();
+    for i in 0..2 {
+        let w = l.weight.clone();
+        l.weight[i] = w[i] + eps;
+        let lp = loss(&l, &x);
+        l.weight[i] = w[i] - eps;
+        let lm = loss(&l, &x);
+        l.weight = w;
+        let num = (lp - lm) / (2. * eps);
+        assert!((num - dw[i]).abs() < 1e-6, "expected {}, got {}", num, dw[i]);
+    }
+}
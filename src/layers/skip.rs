@@ -0,0 +1,475 @@
+//! Skip connections: a block running a body of layers beside a shortcut, by default the
+//! identity, and merging the two outputs by a sum (residual blocks) or by a concatenation
+//! over the first axis, the channels (the skips of U-Net). The activation of the block
+//! applies to the merged output.
+//!
+//! Blocks nest, as the body may hold other blocks. The inner layers are recomputed from
+//! the input in the backward pass, like the other layers recompute their forward pass,
+//! and the weight deltas of the block pack the weight and the bias deltas of each inner
+//! layer one after another, the shortcut first. Batches go through the batch passes of
+//! the inner layers.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let block = Skip::<f64>::new(sh!([4, 8, 8]), Merge::Add, Activation::Relu)
+//!         .then(Conv2D::new(sh!([4, 8, 8]), 4, (3, 3), (1, 1), Padding::Same, Activation::Relu))
+//!         .then(Conv2D::new(sh!([4, 8, 8]), 4, (3, 3), (1, 1), Padding::Same, Activation::No));
+//!     assert_eq!(block.get_output_shape(), Shape::new([4, 8, 8]));
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+use crate::models::Propagation;
+
+/// How the outputs of the shortcut and of the body are merged
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Merge {
+    /// The sum of two outputs of the same shape
+    Add,
+    /// The shortcut output followed by the body output over the first axis
+    Concat,
+}
+
+type Chain<T> = Vec<Box<dyn Layer<T>>>;
+
+pub struct Skip<T: NumT> {
+    pub(crate) input_shape: Shape,
+    pub(crate) shortcut: Chain<T>,
+    pub(crate) body: Chain<T>,
+    pub(crate) merge: Merge,
+    pub(crate) activation: Activation<T>,
+}
+
+/// The output shape of a chain, the input shape if empty
+fn chain_shape<T: NumT>(chain: &Chain<T>, input: &Shape) -> Shape {
+    chain.last().map_or(input.clone(), |l| l.get_output_shape())
+}
+
+/// Infer through a chain
+fn chain_predict<T: NumT>(chain: &Chain<T>, input: &Tensor<T>) -> Result<Tensor<T>> {
+    chain.iter().try_fold(input.clone(), |x, l| l.forward_propagate(&x, true))
+}
+
+/// Forward a chain for training, a sample or a batch, returning the z of each layer
+/// and the a of each layer with the input first
+fn chain_train<T: NumT>(chain: &Chain<T>, input: &Tensor<T>, batched: bool) -> Result<Propagation<T>> {
+    let mut z_l = Vec::with_capacity(chain.len());
+    let mut a_l = vec![input.clone()];
+    for layer in chain {
+        let x = a_l.last().unwrap();
+        let (z, a) = if batched { layer.forward_train_batch(x)? } else { layer.forward_train(x)? };
+        z_l.push(z);
+        a_l.push(a);
+    }
+    Ok((z_l, a_l))
+}
+
+/// Backpropagate through a layer, a sample or a batch
+fn backpropagate<T: NumT>(layer: &dyn Layer<T>, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>, batched: bool) -> Result<Tensor<T>> {
+    if batched {
+        layer.backpropagate_batch(delta, z_lst, sigma_lst)
+    } else {
+        layer.backpropagate_delta(delta, z_lst, sigma_lst)
+    }
+}
+
+/// The delta of the z of each layer of a chain, given the delta of the chain output
+fn chain_deltas<T: NumT>(chain: &Chain<T>, z_l: &[Tensor<T>], delta: &Tensor<T>, batched: bool) -> Result<Vec<Tensor<T>>> {
+    let mut d_lrev = Vec::with_capacity(chain.len());
+    if let Some(last) = chain.last() {
+        let mut d = delta.clone();
+        apply_diff_lst(&mut d, z_l.last().unwrap(), &last.get_activation());
+        d_lrev.push(d);
+        for i in (1..chain.len()).rev() {
+            let d = backpropagate(chain[i].as_ref(), d_lrev.last().unwrap(), &z_l[i - 1], &chain[i - 1].get_activation(), batched)?;
+            d_lrev.push(d);
+        }
+    }
+    d_lrev.reverse();
+    Ok(d_lrev)
+}
+
+/// The delta of the z of the last layer through a chain, given the chain output delta
+/// and the deltas of its layers
+fn chain_backpropagate<T: NumT>(chain: &Chain<T>, deltas: &[Tensor<T>], delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>, batched: bool) -> Result<Tensor<T>> {
+    match chain.first() {
+        Some(first) => backpropagate(first.as_ref(), &deltas[0], z_lst, sigma_lst, batched),
+        None => {
+            let mut lst_delta = delta.clone();
+            apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+            Ok(lst_delta)
+        }
+    }
+}
+
+/// The lengths of the packed weight and bias deltas of an inner layer
+fn packed_len<T: NumT>(layer: &dyn Layer<T>) -> (usize, usize) {
+    (layer.get_weight_count(), layer.get_output_shape().size())
+}
+
+impl<T: NumT> Skip<T> {
+    /// An identity shortcut and an empty body, to be filled with `then`
+    pub fn new(i_shape: &Shape, merge: Merge, act: Activation<T>) -> Self {
+        Skip { input_shape: i_shape.clone(), shortcut: Vec::new(), body: Vec::new(), merge, activation: act }
+    }
+    /// Append a layer to the body
+    pub fn then<L: 'static + Layer<T>>(mut self, layer: L) -> Self {
+        if layer.get_input_shape() != chain_shape(&self.body, &self.input_shape) {
+            panic!("The layer does not fit the body of the skip!");
+        }
+        self.body.push(Box::new(layer));
+        self
+    }
+    /// Append a layer to the shortcut, e.g. a projection when the body changes the shape
+    pub fn shortcut<L: 'static + Layer<T>>(mut self, layer: L) -> Self {
+        if layer.get_input_shape() != chain_shape(&self.shortcut, &self.input_shape) {
+            panic!("The layer does not fit the shortcut of the skip!");
+        }
+        self.shortcut.push(Box::new(layer));
+        self
+    }
+    /// The output shape of the body, the input shape while empty
+    pub fn body_output_shape(&self) -> Shape {
+        chain_shape(&self.body, &self.input_shape)
+    }
+    /// The block of the chains, failing unless their layers fit each other and the outputs merge
+    pub(crate) fn from_chains(i_shape: &Shape, merge: Merge, act: Activation<T>, shortcut: Chain<T>, body: Chain<T>) -> Result<Self> {
+        for chain in [&shortcut, &body] {
+            for (i, layer) in chain.iter().enumerate() {
+                let input = if i == 0 { i_shape.clone() } else { chain[i - 1].get_output_shape() };
+                check_shape("Skip::from_chains", &input, &layer.get_input_shape())?;
+            }
+        }
+        let skip = Skip { input_shape: i_shape.clone(), shortcut, body, merge, activation: act };
+        skip.check_merge()?;
+        Ok(skip)
+    }
+
+    /// The inner layers in the order of the packed deltas
+    fn inner(&self) -> impl Iterator<Item = &Box<dyn Layer<T>>> {
+        self.shortcut.iter().chain(self.body.iter())
+    }
+    fn inner_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Layer<T>>> {
+        self.shortcut.iter_mut().chain(self.body.iter_mut())
+    }
+
+    /// The count of samples of a sample or of a batch of the item shape, given to the operation `op`
+    fn samples(op: &'static str, x: &Shape, item: &Shape, batched: bool) -> Result<usize> {
+        match batched {
+            true => batch_len(op, x, item),
+            false => check_shape(op, item, x).map(|_| 1),
+        }
+    }
+    /// The shape of `n` samples, batched or not
+    fn shape_of(item: &Shape, n: usize, batched: bool) -> Shape {
+        if batched { item.batched(n) } else { item.clone() }
+    }
+
+    /// Check that the outputs of the shortcut and of the body can be merged
+    fn check_merge(&self) -> Result<()> {
+        let (s_shape, b_shape) = (chain_shape(&self.shortcut, &self.input_shape), chain_shape(&self.body, &self.input_shape));
+        match self.merge {
+            Merge::Add => check_shape("merge", &s_shape, &b_shape),
+            Merge::Concat => {
+                if s_shape.rank() == 0 || s_shape.rank() != b_shape.rank() || s_shape.dims()[1..] != b_shape.dims()[1..] {
+                    let message = format!("the shortcut {:?} and the body {:?} differ past the first axis", s_shape.dims(), b_shape.dims());
+                    return Err(EasynnError::invalid("merge", message));
+                }
+                Ok(())
+            }
+        }
+    }
+    /// Merge the shortcut and the body outputs of `n` samples
+    fn merged(&self, s: &Tensor<T>, b: &Tensor<T>, n: usize, batched: bool) -> Result<Tensor<T>> {
+        let (s_shape, b_shape) = (chain_shape(&self.shortcut, &self.input_shape), chain_shape(&self.body, &self.input_shape));
+        check_shape("merge", &Self::shape_of(&s_shape, n, batched), &s.shape)?;
+        check_shape("merge", &Self::shape_of(&b_shape, n, batched), &b.shape)?;
+        self.check_merge()?;
+        let merged = match self.merge {
+            Merge::Add => s.flattened.iter().zip(b.flattened.iter()).map(|(x, y)| *x + *y).collect(),
+            Merge::Concat => {
+                s.flattened.chunks(s_shape.size()).zip(b.flattened.chunks(b_shape.size()))
+                    .flat_map(|(x, y)| x.iter().chain(y.iter()).copied())
+                    .collect()
+            }
+        };
+        Ok(Tensor::new(&Self::shape_of(&self.merged_shape(), n, batched), merged))
+    }
+    /// The shape of the merged output
+    fn merged_shape(&self) -> Shape {
+        let s = chain_shape(&self.shortcut, &self.input_shape);
+        match self.merge {
+            Merge::Add => s,
+            Merge::Concat => {
+                let b = chain_shape(&self.body, &self.input_shape);
+                let mut dims = b.dims().to_vec();
+                if let Some(d) = dims.first_mut() {
+                    *d += s.dims().first().copied().unwrap_or(0);
+                }
+                Shape::from_slice(&dims)
+            }
+        }
+    }
+    /// Split the delta of the merged output of `n` samples into the deltas of the shortcut and of the body
+    fn split(&self, delta: &Tensor<T>, n: usize, batched: bool) -> (Tensor<T>, Tensor<T>) {
+        match self.merge {
+            Merge::Add => (delta.clone(), delta.clone()),
+            Merge::Concat => {
+                let (s_shape, b_shape) = (chain_shape(&self.shortcut, &self.input_shape), chain_shape(&self.body, &self.input_shape));
+                let (mut ds, mut db) = (Vec::with_capacity(n * s_shape.size()), Vec::with_capacity(n * b_shape.size()));
+                for d in delta.flattened.chunks(s_shape.size() + b_shape.size()) {
+                    ds.extend_from_slice(&d[..s_shape.size()]);
+                    db.extend_from_slice(&d[s_shape.size()..]);
+                }
+                (Tensor::new(&Self::shape_of(&s_shape, n, batched), ds), Tensor::new(&Self::shape_of(&b_shape, n, batched), db))
+            }
+        }
+    }
+
+    /// Forward a sample or a batch for training
+    fn train(&self, input: &Tensor<T>, batched: bool) -> Result<(Tensor<T>, Tensor<T>)> {
+        let n = Self::samples("forward_train", &input.shape, &self.input_shape, batched)?;
+        // train the inner layers, e.g. to drop in a body
+        let (_, a_s) = chain_train(&self.shortcut, input, batched)?;
+        let (_, a_b) = chain_train(&self.body, input, batched)?;
+        let z = self.merged(a_s.last().unwrap(), a_b.last().unwrap(), n, batched)?;
+        let a = z.map(|x| self.activation.call(x));
+        Ok((z, a))
+    }
+    /// Backpropagate a sample or a batch
+    fn backpropagate(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>, batched: bool) -> Result<Tensor<T>> {
+        let n = Self::samples("backpropagate_delta", &z_lst.shape, &self.input_shape, batched)?;
+        check_shape("backpropagate_delta", &Self::shape_of(&self.merged_shape(), n, batched), &delta.shape)?;
+        let input = z_lst.map(|z| sigma_lst.call(z));
+        let (ds, db) = self.split(delta, n, batched);
+        let mut lst_delta = Tensor::<T>::zeros(&z_lst.shape);
+        for (chain, d) in [(&self.shortcut, ds), (&self.body, db)] {
+            let (z_l, _) = chain_train(chain, &input, batched)?;
+            let deltas = chain_deltas(chain, &z_l, &d, batched)?;
+            let part = chain_backpropagate(chain, &deltas, &d, z_lst, sigma_lst, batched)?;
+            lst_delta.flattened.iter_mut().zip(part.flattened.iter()).for_each(|(x, p)| *x += *p);
+        }
+        Ok(lst_delta)
+    }
+    /// Add the weight deltas of a sample or of a batch
+    fn add_weight_deltas(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut [T], cum_db: &Tensor<T>, batched: bool) -> Result<()> {
+        let n = Self::samples("add_weight_delta_to", &a_lst.shape, &self.input_shape, batched)?;
+        check_shape("add_weight_delta_to", &Self::shape_of(&self.merged_shape(), n, batched), &delta.shape)?;
+        check_len("add_weight_delta_to", self.get_weight_count(), cum_dw.len())?;
+        check_shape("add_weight_delta_to", &self.merged_shape(), &cum_db.shape)?;
+        let (ds, db) = self.split(delta, n, batched);
+        let mut offset = 0;
+        for (chain, d) in [(&self.shortcut, ds), (&self.body, db)] {
+            let (z_l, a_l) = chain_train(chain, a_lst, batched)?;
+            let deltas = chain_deltas(chain, &z_l, &d, batched)?;
+            for ((layer, d), a) in chain.iter().zip(deltas.iter()).zip(a_l.iter()) {
+                let (w, b) = packed_len(layer.as_ref());
+                let mut dw = cum_dw[offset..offset + w].to_vec();
+                let mut db = Tensor::new(&layer.get_output_shape(), cum_dw[offset + w..offset + w + b].to_vec());
+                if batched {
+                    layer.add_weight_delta_batch_to(d, a, &mut dw, &mut db)?;
+                } else {
+                    layer.add_weight_delta_to(d, a, &mut dw, &mut db)?;
+                }
+                cum_dw[offset..offset + w].copy_from_slice(&dw);
+                cum_dw[offset + w..offset + w + b].copy_from_slice(&db.flattened);
+                offset += w + b;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: NumT> Layer<T> for Skip<T> {
+    fn get_activation(&self) -> Activation<T> {
+        self.activation
+    }
+    fn get_input_shape(&self) -> Shape {
+        self.input_shape.clone()
+    }
+    fn get_output_shape(&self) -> Shape {
+        self.merged_shape()
+    }
+    fn get_weight_count(&self) -> usize {
+        self.inner().map(|l| {
+            let (w, b) = packed_len(l.as_ref());
+            w + b
+        }).sum()
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let z = self.merged(&chain_predict(&self.shortcut, input)?, &chain_predict(&self.body, input)?, 1, false)?;
+        Ok(if activate { z.map(|x| self.activation.call(x)) } else { z })
+    }
+    fn activate(&self, output: &Tensor<T>) -> Result<Tensor<T>> {
+        check_shape("activate", &self.merged_shape(), &output.shape)?;
+        Ok(output.map(|x| self.activation.call(x)))
+    }
+    fn forward_train(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        self.train(input, false)
+    }
+    fn forward_train_batch(&self, input: &Tensor<T>) -> Result<(Tensor<T>, Tensor<T>)> {
+        self.train(input, true)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        self.backpropagate(delta, z_lst, sigma_lst, false)
+    }
+    fn backpropagate_batch(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        self.backpropagate(delta, z_lst, sigma_lst, true)
+    }
+    fn add_weight_delta_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        self.add_weight_deltas(delta, a_lst, cum_dw, cum_db, false)
+    }
+    fn add_weight_delta_batch_to(&self, delta: &Tensor<T>, a_lst: &Tensor<T>, cum_dw: &mut Vec<T>, cum_db: &mut Tensor<T>) -> Result<()> {
+        self.add_weight_deltas(delta, a_lst, cum_dw, cum_db, true)
+    }
+    fn name(&self) -> String {
+        "skip".to_string()
+    }
+    /// The records of the inner layers, the shortcut first, and a configuration of the
+    /// input shape, the merge (0 add, 1 concat) and the length of the shortcut
+    fn record(&self) -> Option<LayerRecord<T>> {
+        let inner = self.inner().map(|l| l.record()).collect::<Option<Vec<_>>>()?;
+        let merge = match self.merge { Merge::Add => 0, Merge::Concat => 1 };
+        Some(LayerRecord::new("skip", self.activation).shape(&self.input_shape).values(&[merge, self.shortcut.len()]).inner(inner))
+    }
+    fn descend(&mut self, rate: T, dw: &[T], db: &Tensor<T>) -> Result<()> {
+        check_len("descend", self.get_weight_count(), dw.len())?;
+        check_shape("descend", &self.merged_shape(), &db.shape)?;
+        let mut offset = 0;
+        for layer in self.inner_mut() {
+            let (w, b) = packed_len(layer.as_ref());
+            let db = Tensor::new(&layer.get_output_shape(), dw[offset + w..offset + w + b].to_vec());
+            layer.descend(rate, &dw[offset..offset + w], &db)?;
+            offset += w + b;
+        }
+        Ok(())
+    }
+    fn parameters(&self) -> Vec<&[T]> {
+        self.inner().flat_map(|l| l.parameters()).collect()
+    }
+    fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        self.inner_mut().flat_map(|l| l.parameters_mut()).collect()
+    }
+    fn state(&self) -> Vec<&[T]> {
+        self.inner().flat_map(|l| l.state()).collect()
+    }
+    fn state_mut(&mut self) -> Vec<&mut [T]> {
+        self.inner_mut().flat_map(|l| l.state_mut()).collect()
+    }
+    /// The names within the shortcut then the body, e.g. `body.dense1.weight`
+    fn parameter_names(&self) -> Vec<String> {
+        let shortcut = chain_parameter_names(self.shortcut.iter()).into_iter().map(|n| format!("shortcut.{}", n));
+        shortcut.chain(chain_parameter_names(self.body.iter()).into_iter().map(|n| format!("body.{}", n))).collect()
+    }
+    fn regularized(&self) -> Vec<bool> {
+        self.inner().flat_map(|l| l.regularized()).collect()
+    }
+    fn gradients(&self, dw: &[T], _db: &Tensor<T>) -> Vec<Vec<T>> {
+        let mut offset = 0;
+        self.inner().flat_map(|l| {
+            let (w, b) = packed_len(l.as_ref());
+            let db = Tensor::new(&l.get_output_shape(), dw[offset + w..offset + w + b].to_vec());
+            let grads = l.gradients(&dw[offset..offset + w], &db);
+            offset += w + b;
+            grads
+        }).collect()
+    }
+    fn set_training(&mut self, training: bool) {
+        self.inner_mut().for_each(|l| l.set_training(training));
+    }
+    fn finish_batch(&mut self) {
+        self.inner_mut().for_each(|l| l.finish_batch());
+    }
+    fn discard_batch(&mut self) {
+        self.inner_mut().for_each(|l| l.discard_batch());
+    }
+}
+
+#[test]
+fn test_skip_gradients() {
+    use crate::layers::dense::Dense;
+    use crate::layers::conv::Conv2D;
+
+    // compare the analytic gradients with finite differences of L = sum(c * y),
+    // for a residual block with a projection and a concatenation
+    let i_shape = Shape::new([2, 3, 3]);
+    let build = || -> Vec<Skip<f64>> {
+        vec![
+            Skip::new(&i_shape, Merge::Add, Activation::Tanh)
+                .shortcut(Conv2D::new(&i_shape, 3, (1, 1), (1, 1), Padding::Valid, Activation::No))
+                .then(Conv2D::new(&i_shape, 3, (3, 3), (1, 1), Padding::Same, Activation::Tanh))
+                .then(Conv2D::new(&Shape::new([3, 3, 3]), 3, (3, 3), (1, 1), Padding::Same, Activation::No)),
+            Skip::new(&i_shape, Merge::Concat, Activation::No)
+                .then(Conv2D::new(&i_shape, 1, (2, 2), (1, 1), Padding::Same, Activation::Sigmoid)),
+            Skip::new(&Shape::new([2, 3, 3]), Merge::Concat, Activation::No)
+                .then(Dense::new(&i_shape, &Shape::new([1, 3, 3]), Activation::Tanh)),
+        ]
+    };
+    let input = Tensor::new(&i_shape, (0..18).map(|x| ((x * 7 % 11) as f64 - 5.) / 5.).collect());
+    let eps = 1e-6;
+    for (n, mut skip) in build().into_iter().enumerate() {
+        let out_shape = skip.get_output_shape();
+        assert_eq!(out_shape[0], 3);
+        let coef = Tensor::new(&out_shape, (0..out_shape.size()).map(|x| ((x * 3 % 7) as f64 - 3.) / 3.).collect());
+        let loss = |s: &Skip<f64>, x: &Tensor<f64>| -> f64 {
+            s.forward_propagate(x, false).unwrap().flattened.iter().zip(coef.flattened.iter()).map(|(y, k)| y * k).sum()
+        };
+
+        // through a tanh of the last layer
+        let z_lst = input.clone();
+        let a_lst = z_lst.map(|z| z.tanh());
+        let d_input = skip.backpropagate_delta(&coef, &z_lst, &Activation::Tanh).unwrap();
+        for i in 0..input.flattened.len() {
+            let (mut plus, mut minus) = (z_lst.clone(), z_lst.clone());
+            plus.flattened[i] += eps;
+            minus.flattened[i] -= eps;
+            let num = (loss(&skip, &plus.map(|z| z.tanh())) - loss(&skip, &minus.map(|z| z.tanh()))) / (2. * eps);
+            assert!((d_input.flattened[i] - num).abs() < 1e-6, "block {} input {}", n, i);
+        }
+
+        // a batch goes through the batch passes, agreeing with the samples
+        let other = input.map(|x| 0.5 - x);
+        let a_batch = Tensor::stack(&i_shape, &[a_lst.clone(), other.map(|z| z.tanh())]).unwrap();
+        let (_, out) = skip.forward_train_batch(&a_batch).unwrap();
+        crate::assert_tensor_eq!(out.unstack()[1], skip.forward_train(&other.map(|z| z.tanh())).unwrap().1, 0., 1e-12);
+        let z_batch = Tensor::stack(&i_shape, &[z_lst.clone(), other.clone()]).unwrap();
+        let d_batch = Tensor::stack(&out_shape, &[coef.clone(), coef.clone()]).unwrap();
+        let back = skip.backpropagate_batch(&d_batch, &z_batch, &Activation::Tanh).unwrap().unstack();
+        crate::assert_tensor_eq!(back[0], d_input, 0., 1e-12);
+        crate::assert_tensor_eq!(back[1], skip.backpropagate_delta(&coef, &other, &Activation::Tanh).unwrap(), 0., 1e-12);
+
+        let mut dw = vec![0.; skip.get_weight_count()];
+        let mut db = Tensor::zeros(&out_shape);
+        skip.add_weight_delta_to(&coef, &a_lst, &mut dw, &mut db).unwrap();
+        let grads: Vec<f64> = skip.gradients(&dw, &db).concat();
+        let params: Vec<f64> = skip.parameters().concat();
+        assert_eq!(grads.len(), params.len());
+        fn shift(skip: &mut Skip<f64>, mut k: usize, e: f64) {
+            for p in skip.parameters_mut() {
+                if k < p.len() {
+                    p[k] += e;
+                    return;
+                }
+                k -= p.len();
+            }
+        }
+        for (i, g) in grads.iter().enumerate() {
+            shift(&mut skip, i, eps);
+            let plus = loss(&skip, &a_lst);
+            shift(&mut skip, i, -2. * eps);
+            let minus = loss(&skip, &a_lst);
+            shift(&mut skip, i, eps);
+            assert!((g - (plus - minus) / (2. * eps)).abs() < 1e-6, "block {} parameter {}", n, i);
+        }
+
+        // descending moves the parameters against the gradients
+        let before = params;
+        skip.descend(0.5, &dw, &db).unwrap();
+        for ((p, p0), g) in skip.parameters().concat().iter().zip(before.iter()).zip(grads.iter()) {
+            assert!((p0 - p - 0.5 * g).abs() < 1e-12);
+        }
+    }
+}
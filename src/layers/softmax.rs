@@ -0,0 +1,100 @@
+//! The softmax over the last axis of a tensor, e.g. the classes of `[classes]`
+//! or of each step of `[seq_len, classes]`.
+//!
+//! It is not an `Activation` since each output depends on the whole row,
+//! the backward pass applies the Jacobian `diag(s) - s s^T` of each row to the delta.
+//! To train a classifier, prefer leaving the logits unnormalized and using
+//! `Loss::SoftmaxCrossEntropy`, which fuses the two with a simpler and stabler gradient.
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+/// Normalize the last axis into probabilities
+#[derive(Debug)]
+pub struct Softmax {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+}
+
+impl Softmax {
+    pub fn new(i_shape: &Shape) -> Self {
+        if i_shape.rank() == 0 || i_shape.size() == 0 {
+            panic!("Softmax needs a non-empty input!");
+        }
+        Softmax { input_shape: i_shape.clone(), output_shape: i_shape.clone() }
+    }
+
+    fn classes(&self) -> usize {
+        self.input_shape[self.input_shape.rank() - 1]
+    }
+}
+
+/// The softmax of each row in place, subtracting the maximum so that exp does not overflow
+fn softmax_rows<T: NumT>(x: &mut [T], classes: usize) {
+    for row in x.chunks_mut(classes) {
+        let max = row.iter().fold(T::neg_infinity(), |m, v| m.max(*v));
+        row.iter_mut().for_each(|v| *v = (*v - max).exp());
+        let sum = row.iter().copied().sum::<T>();
+        row.iter_mut().for_each(|v| *v /= sum);
+    }
+}
+
+impl<T: NumT> Layer<T> for Softmax {
+    impl_weightless!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("softmax", Activation::No).shape(&self.input_shape))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let mut output = input.clone();
+        softmax_rows(&mut output.flattened, self.classes());
+        Ok(output)
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        let classes = self.classes();
+        let mut s: Vec<T> = z_lst.flattened.iter().map(|z| sigma_lst.call(*z)).collect();
+        softmax_rows(&mut s, classes);
+        // (diag(s) - s s^T) d = s * (d - s . d)
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for ((l, s), d) in lst_delta.flattened.chunks_mut(classes).zip(s.chunks(classes)).zip(delta.flattened.chunks(classes)) {
+            let dot = s.iter().zip(d.iter()).map(|(a, b)| *a * *b).sum::<T>();
+            for ((l, s), d) in l.iter_mut().zip(s.iter()).zip(d.iter()) {
+                *l = *s * (*d - dot);
+            }
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[test]
+fn test_softmax() {
+    let shape = Shape::new([2, 3]);
+    let softmax = Softmax::new(&shape);
+    let input = Tensor::<f64>::new(&shape, vec![1., 2., 3., 1000., 1000., -1000.]);
+    let output = softmax.forward_propagate(&input, true).unwrap();
+    let e = [1_f64.exp(), 2_f64.exp(), 3_f64.exp()];
+    let sum: f64 = e.iter().sum();
+    for (o, x) in output.flattened.iter().zip([e[0] / sum, e[1] / sum, e[2] / sum, 0.5, 0.5, 0.]) {
+        assert!((o - x).abs() < 1e-12);
+    }
+
+    // the Jacobian-vector product against finite differences of L = sum(c * softmax(x))
+    let x = Tensor::<f64>::new(&shape, vec![0.3, -1.2, 0.8, 2., 0.1, -0.5]);
+    let coef = Tensor::<f64>::new(&shape, vec![1., -2., 0.5, 3., 0., -1.]);
+    let loss = |x: &Tensor<f64>| -> f64 {
+        softmax.forward_propagate(x, true).unwrap().flattened.iter().zip(coef.flattened.iter()).map(|(y, c)| y * c).sum()
+    };
+    let grad = softmax.backpropagate_delta(&coef, &x, &Activation::No).unwrap();
+    let eps = 1e-6;
+    for i in 0..shape.size() {
+        let (mut plus, mut minus) = (x.clone(), x.clone());
+        plus.flattened[i] += eps;
+        minus.flattened[i] -= eps;
+        assert!((grad.flattened[i] - (loss(&plus) - loss(&minus)) / (2. * eps)).abs() < 1e-6);
+    }
+}
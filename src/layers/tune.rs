@@ -0,0 +1,169 @@
+//! Autotuning of the chunking used by the parallel layer kernels.
+//!
+//! By default the thread count of a kernel is guessed by [`determine_thread`].
+//! When tuning is enabled, the first call of a kernel on a given shape and element type
+//! benchmarks a few thread counts and caches the fastest one, e.g.:
+//!
+//! ```rust
+//!     use easynn::layers::tune;
+//!     tune::enable(true);
+//!     // ... run the model, the first pass of each shape gets tuned ...
+//!     let path = std::env::temp_dir().join("easynn_tune.txt");
+//!     tune::save(&path).unwrap();
+//!     // on the next run of the same machine
+//!     tune::load(&path).unwrap();
+//! ```
+
+use crate::parallel::{ current_threads, is_serial };
+
+use std::cmp;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::{ Mutex, OnceLock };
+use std::time::{ Duration, Instant };
+
+/// How many times each candidate is timed, the fastest run counts
+const BENCH_RUNS: usize = 3;
+
+/// The kernel name, the element type and the two dimentions of the work it does
+type TuneKey = (String, String, usize, usize);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static CACHE: OnceLock<Mutex<HashMap<TuneKey, usize>>> = OnceLock::new();
+
+fn cache() -> &'static Mutex<HashMap<TuneKey, usize>> {
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// This is used to determine how much threads to spawn.
+///
+/// The length argument is the count of total works (e.g. mul counts),
+/// below the size of `ParallelConfig::serial_below` the work is done by one thread.
+///
+/// Need to consider SIMD.
+pub(crate) fn determine_thread(len: usize) -> usize {
+    const FALL_BACK_SIZE: usize = 256;
+    let ncpu = current_threads();
+    if is_serial(len) || len / ncpu < FALL_BACK_SIZE {
+        return 1;
+    }
+    cmp::min(ncpu, len / FALL_BACK_SIZE)
+}
+
+/// Turn autotuning on or off, it is off by default
+pub fn enable(on: bool) {
+    ENABLED.store(on, Ordering::Relaxed);
+}
+
+/// Check whether autotuning is on
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Forget every tuned configuration
+pub fn clear() {
+    cache().lock().unwrap().clear();
+}
+
+/// The thread counts worth trying: powers of 2 up to the available threads,
+/// the available threads themselves and the heuristic guess
+fn candidates(guess: usize) -> Vec<usize> {
+    let ncpu = current_threads();
+    let mut cands = vec![1, guess, ncpu];
+    let mut t = 2;
+    while t < ncpu {
+        cands.push(t);
+        t *= 2;
+    }
+    cands.sort_unstable();
+    cands.dedup();
+    cands
+}
+
+/// Pick the thread count of the kernel `name` working on `dims` of elements `T`.
+///
+/// If tuning is off the heuristic is returned; otherwise the cached winner is
+/// returned, or `bench` is timed on every candidate thread count to find it.
+/// `bench` must be safe to run repeatedly, i.e. it must not accumulate.
+pub(crate) fn choose_threads<T, F: FnMut(usize)>(name: &str, dims: (usize, usize), mut bench: F) -> usize {
+    let guess = determine_thread(dims.0 * dims.1);
+    if !is_enabled() {
+        return guess;
+    }
+    let key = (name.to_string(), std::any::type_name::<T>().to_string(), dims.0, dims.1);
+    if let Some(&threads) = cache().lock().unwrap().get(&key) {
+        return threads;
+    }
+    let mut best = (guess, Duration::MAX);
+    for threads in candidates(guess) {
+        for _ in 0..BENCH_RUNS {
+            let start = Instant::now();
+            bench(threads);
+            let elapsed = start.elapsed();
+            if elapsed < best.1 {
+                best = (threads, elapsed);
+            }
+        }
+    }
+    cache().lock().unwrap().insert(key, best.0);
+    best.0
+}
+
+/// Save the tuned configurations to a text file,
+/// each line is `<kernel> <element type> <dim0> <dim1> <threads>`
+pub fn save<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let mut lines: Vec<String> = cache().lock().unwrap().iter()
+        .map(|((name, elem, d0, d1), threads)| format!("{} {} {} {} {}", name, elem, d0, d1, threads))
+        .collect();
+    lines.sort();
+    fs::write(path, lines.join("\n"))
+}
+
+/// Load the configurations saved by [`save`], merging them into the cache
+pub fn load<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let content = fs::read_to_string(path)?;
+    let mut loaded = Vec::<(TuneKey, usize)>::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let parse = |s: &str| s.parse::<usize>().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        if fields.len() != 5 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("malformed tuning line: {}", line)));
+        }
+        loaded.push(((fields[0].to_string(), fields[1].to_string(), parse(fields[2])?, parse(fields[3])?), parse(fields[4])?));
+    }
+    cache().lock().unwrap().extend(loaded);
+    Ok(())
+}
+
+#[test]
+fn test_tune_cache_roundtrip() {
+    // the thread count must not change meanwhile, and tuning is restored after
+    let _globals = crate::parallel::TEST_GLOBALS.lock().unwrap_or_else(|e| e.into_inner());
+    let was_enabled = is_enabled();
+    enable(true);
+    let mut runs = 0;
+    let threads = choose_threads::<f64, _>("test_kernel", (3, 7), |_| { runs += 1; });
+    assert!(runs > 0);
+    assert!(candidates(determine_thread(21)).contains(&threads));
+    // cached, the bench is not run again
+    let again = choose_threads::<f64, _>("test_kernel", (3, 7), |_| panic!("should be cached"));
+    assert_eq!(threads, again);
+    // another element type is tuned by itself
+    let mut runs = 0;
+    choose_threads::<f32, _>("test_kernel", (3, 7), |_| { runs += 1; });
+    assert!(runs > 0);
+    let key = |elem: &str| ("test_kernel".to_string(), elem.to_string(), 3, 7);
+
+    let path = std::env::temp_dir().join(format!("easynn_test_tune_cache_{}.txt", std::process::id()));
+    save(&path).unwrap();
+    cache().lock().unwrap().remove(&key("f64"));
+    load(&path).unwrap();
+    assert_eq!(cache().lock().unwrap().get(&key("f64")), Some(&threads));
+    assert!(cache().lock().unwrap().contains_key(&key("f32")));
+    fs::remove_file(path).unwrap();
+    cache().lock().unwrap().retain(|k, _| k.0 != "test_kernel");
+    enable(was_enabled);
+}
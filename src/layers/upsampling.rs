@@ -0,0 +1,82 @@
+//! Nearest-neighbour upsampling of `[channels, height, width]` inputs, repeating each
+//! element over a block of `factor` rows and columns, e.g. to undo a `MaxPool2D`.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::layers::Layer;
+//!     let up = UpSample2D::new(sh!([8, 14, 14]), (2, 2));
+//!     assert_eq!(Layer::<f32>::get_output_shape(&up), Shape::new([8, 28, 28]));
+//! ```
+
+use crate::layers::*;
+use crate::layers::record::LayerRecord;
+
+#[derive(Debug)]
+pub struct UpSample2D {
+    pub(crate) input_shape: Shape,
+    pub(crate) output_shape: Shape,
+    pub(crate) factor: (usize, usize),
+}
+
+impl UpSample2D {
+    pub fn new(i_shape: &Shape, factor: (usize, usize)) -> Self {
+        if i_shape.rank() != 3 {
+            panic!("Upsampling needs a [channels, height, width] input!");
+        }
+        if factor.0 == 0 || factor.1 == 0 {
+            panic!("The upsampling factor should be positive!");
+        }
+        let output_shape = Shape::new([i_shape[0], i_shape[1] * factor.0, i_shape[2] * factor.1]);
+        UpSample2D { input_shape: i_shape.clone(), output_shape, factor }
+    }
+
+    /// The position in the flattened input repeated at output position `p`
+    fn source(&self, p: usize) -> usize {
+        let (h, w) = (self.input_shape[1], self.input_shape[2]);
+        let (oh, ow) = (self.output_shape[1], self.output_shape[2]);
+        let (c, oy, ox) = (p / (oh * ow), p / ow % oh, p % ow);
+        c * h * w + oy / self.factor.0 * w + ox / self.factor.1
+    }
+}
+
+impl<T: NumT> Layer<T> for UpSample2D {
+    impl_weightless!();
+    impl_unit_pass!();
+
+    fn record(&self) -> Option<LayerRecord<T>> {
+        Some(LayerRecord::new("up_sample2d", Activation::No).shape(&self.input_shape).values(&[self.factor.0, self.factor.1]))
+    }
+
+    fn forward_propagate(&self, input: &Tensor<T>, _activate: bool) -> Result<Tensor<T>> {
+        check_shape("forward_propagate", &self.input_shape, &input.shape)?;
+        let output = (0..self.output_shape.size()).map(|p| input.flattened[self.source(p)]).collect();
+        Ok(Tensor::<T>::new(&self.output_shape, output))
+    }
+    fn backpropagate_delta(&self, delta: &Tensor<T>, z_lst: &Tensor<T>, sigma_lst: &Activation<T>) -> Result<Tensor<T>> {
+        check_shape("backpropagate_delta", &self.output_shape, &delta.shape)?;
+        check_shape("backpropagate_delta", &self.input_shape, &z_lst.shape)?;
+        // each input receives the sum of the deltas of its block
+        let mut lst_delta = Tensor::<T>::zeros(&self.input_shape);
+        for (p, dl) in delta.flattened.iter().enumerate() {
+            lst_delta.flattened[self.source(p)] += *dl;
+        }
+        apply_diff_lst(&mut lst_delta, z_lst, sigma_lst);
+        Ok(lst_delta)
+    }
+}
+
+#[test]
+fn test_up_sample2d() {
+    let up = UpSample2D::new(&Shape::new([2, 1, 2]), (2, 3));
+    let input = Tensor::new(&Shape::new([2, 1, 2]), vec![1., 2., 3., 4.]);
+    let output = up.forward_propagate(&input, true).unwrap();
+    assert_eq!(output, Tensor::new(&Shape::new([2, 2, 6]), vec![
+        1., 1., 1., 2., 2., 2.,
+        1., 1., 1., 2., 2., 2.,
+        3., 3., 3., 4., 4., 4.,
+        3., 3., 3., 4., 4., 4.,
+    ]));
+    let delta = Tensor::new(&Shape::new([2, 2, 6]), (0..24).map(|x| x as f64).collect());
+    let back = up.backpropagate_delta(&delta, &input, &Activation::No).unwrap();
+    assert_eq!(back, Tensor::new(&Shape::new([2, 1, 2]), vec![24., 42., 96., 114.]));
+}
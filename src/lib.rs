@@ -54,22 +54,52 @@
 //! 
 //! ## Supported models
 //!  - [x] `Sequential`: similar to [The Sequential model](https://www.tensorflow.org/guide/keras/sequential_model) of [Keras](https://keras.io/)
+//!  - [x] `zoo::unet`: the U-Net of segmentation
+//!  - [x] `zoo::resnet_small`: a small ResNet of classification
+//!  - [x] `zoo::mixer`: an MLP-Mixer of classification
+//!  - [x] `zoo::load_pretrained`: the small MNIST and CIFAR classifiers with cached, SHA-256 verified weights
 //!
 //! ## Supported layer types
 //!  - Primitive types:
 //!    - [x] `Dense`: fully connected layers
+//!    - [x] `ZeroPad2D`, `Crop2D`: zero padding and cropping of 2D feature maps
+//!    - [x] `Softmax`: the softmax over the last axis
+//!    - [x] `Dropout`: inverted dropout while training
+//!    - [x] `Reshape`, `Flatten`, `Permute`: rearrangements of the elements and the axes
+//!    - [x] `Skip`: skip connections around a body of layers, summed or concatenated
+//!    - [x] `BatchNorm`: batch normalization over the channels
+//!    - [x] `ActivationLayer`: an activation on its own, after unactivated layers
+//!  - Sequence types:
+//!    - [x] `MeanOverTime`, `MaxOverTime`, `AttentionPooling`: readouts collapsing `[seq_len, d]` to `[d]`
+//!    - [x] `Crf`: linear-chain CRF with Viterbi decoding for sequence tagging
+//!    - [x] `Embedding`: learned vectors of integer indices, e.g. tokens
 //!  - CNN types:
-//!    - [ ] `Conv`: the convolution layer
-//!    - [ ] `Pooling`: the pooling layer
+//!    - [x] `Conv2D`: the 2D convolution layer
+//!    - [x] `MaxPool2D`, `AvgPool2D`, `GlobalAvgPool2D`: the 2D pooling layers
+//!    - [x] `UpSample2D`: the nearest-neighbour 2D upsampling
 
 
 pub mod layers;
 pub mod models;
 pub mod tensor;
+pub mod audio;
+pub mod datasets;
+pub mod metrics;
+pub mod optim;
+pub mod linalg;
+pub mod parallel;
+pub mod autograd;
+pub mod vision;
+pub mod interop;
+pub mod cache;
+pub mod neighbors;
 
 pub mod prelude {
-    pub use crate::{ sh };
-    pub use crate::layers::{ dense::Dense, activation::Activation };
+    pub use crate::{ sh, assert_tensor_eq };
+    pub use crate::layers::{ dense::Dense, conv::Conv2D, pooling::{ MaxPool2D, AvgPool2D }, padding::{ ZeroPad2D, Crop2D }, softmax::Softmax, dropout::Dropout,
+        upsampling::UpSample2D, skip::{ Skip, Merge }, batch_norm::BatchNorm, pooling::GlobalAvgPool2D, reshape::{ Reshape, Flatten, Permute },
+        seq_pooling::{ MeanOverTime, MaxOverTime, AttentionPooling }, crf::Crf, embedding::Embedding, activation::{ Activation, ActivationFn },
+        activation_layer::ActivationLayer };
     pub use crate::models::{ Model, sequential::Sequential, losses::Loss };
-    pub use crate::tensor::{ shape::{ Shape }, Tensor };
+    pub use crate::tensor::{ shape::{ Shape, Padding }, Tensor };
 }
\ No newline at end of file
@@ -0,0 +1,287 @@
+//! Dense linear algebra kernels of the layers: `gemm`, the general matrix product,
+//! and `axpy`, the scaled vector sum.
+//!
+//! Matrices are borrowed as `MatRef`, a slice with a row and a column stride, so that
+//! a transpose is a view and not a copy. The product is tiled into blocks fitting in
+//! the cache, and row blocks of the result are computed in parallel, see `parallel`.
+//! Matrix-vector products, `n == 1` or `m == 1`, walk the matrix once without tiling.
+//!
+//! With the `blas` feature, `f32` and `f64` products are delegated to the
+//! `matrixmultiply` crate instead.
+//!
+//! ```rust
+//!     use easynn::linalg::{ gemm, MatRef };
+//!     let a = [1., 2., 3., 4., 5., 6.];
+//!     let b = [1., 0., 0., 1., 1., 1.];
+//!     let mut c = [0.; 4];
+//!     // [2, 3] x [3, 2]
+//!     gemm(MatRef::new(&a, 2, 3), MatRef::new(&b, 3, 2), 0., &mut c).unwrap();
+//!     assert_eq!(c, [4., 5., 10., 11.]);
+//!     // [2, 3] x [2, 3]^T, the transposed view of b
+//!     gemm(MatRef::new(&a, 2, 3), MatRef::new(&a, 2, 3).t(), 0., &mut c).unwrap();
+//!     assert_eq!(c, [14., 32., 32., 77.]);
+//! ```
+
+use crate::tensor::*;
+
+use crate::parallel;
+
+/// The rows of the result computed by a task
+const MC: usize = 64;
+/// The depth of a block, rows of B packed together
+const KC: usize = 256;
+/// The columns of a packed block of B
+const NC: usize = 512;
+/// The rows and the columns of the tile of the result kept in the registers
+const MR: usize = 4;
+const NR: usize = 8;
+
+/// A `rows` by `cols` matrix in a slice, element `(i, j)` at `i * rs + j * cs`
+#[derive(Debug, Clone, Copy)]
+pub struct MatRef<'a, T> {
+    pub data: &'a [T],
+    pub rows: usize,
+    pub cols: usize,
+    /// The row stride
+    pub rs: usize,
+    /// The column stride
+    pub cs: usize,
+}
+
+impl<'a, T: Copy> MatRef<'a, T> {
+    /// The row-major matrix
+    pub fn new(data: &'a [T], rows: usize, cols: usize) -> Self {
+        MatRef { data, rows, cols, rs: cols, cs: 1 }
+    }
+    /// The transposed view
+    pub fn t(self) -> Self {
+        MatRef { data: self.data, rows: self.cols, cols: self.rows, rs: self.cs, cs: self.rs }
+    }
+    #[inline]
+    fn at(&self, i: usize, j: usize) -> T {
+        self.data[i * self.rs + j * self.cs]
+    }
+    /// Check that the strides stay in the slice, without overflowing,
+    /// and fit in `isize` as the kernels of the `blas` feature take them
+    fn check(&self, op: &'static str) -> Result<(), EasynnError> {
+        if self.rs > isize::MAX as usize || self.cs > isize::MAX as usize {
+            return Err(EasynnError::invalid(op, format!("the strides ({}, {}) overflow isize", self.rs, self.cs)));
+        }
+        if self.rows == 0 || self.cols == 0 {
+            return Ok(());
+        }
+        let last = (self.rows - 1).checked_mul(self.rs)
+            .and_then(|r| (self.cols - 1).checked_mul(self.cs).and_then(|c| r.checked_add(c)));
+        match last {
+            Some(last) if last < self.data.len() => Ok(()),
+            _ => Err(EasynnError::invalid(op, format!("a {} by {} matrix does not fit in {} elements", self.rows, self.cols, self.data.len()))),
+        }
+    }
+}
+
+/// `c = beta * c + a * b` of a `[m, k]` by a `[k, n]` matrix, `c` being `[m, n]` row-major.
+/// A `beta` of zero overwrites `c`, even if it holds NaN.
+pub fn gemm<T: NumT>(a: MatRef<T>, b: MatRef<T>, beta: T, c: &mut [T]) -> Result<(), EasynnError> {
+    let (m, k, n) = (a.rows, a.cols, b.cols);
+    a.check("gemm")?;
+    b.check("gemm")?;
+    if b.rows != k {
+        return Err(EasynnError::mismatch("gemm", &Shape::new([k, n]), &Shape::new([b.rows, n])));
+    }
+    check_len("gemm", m.checked_mul(n).ok_or(EasynnError::SizeOverflow(vec![m, n]))?, c.len())?;
+    if beta == T::zero() {
+        parallel::for_each_mut(c, |_, x| *x = T::zero());
+    } else if beta != T::one() {
+        parallel::for_each_mut(c, |_, x| *x *= beta);
+    }
+    if m == 0 || n == 0 || k == 0 {
+        return Ok(());
+    }
+    #[cfg(feature = "blas")]
+    if blas::gemm(a, b, c) {
+        return Ok(());
+    }
+    if n == 1 {
+        gemv(a, b, c);
+    } else if m == 1 {
+        // c^T = b^T a^T
+        gemv(b.t(), a.t(), c);
+    } else {
+        gemm_blocked(a, b, c);
+    }
+    Ok(())
+}
+
+/// `c += a * x` of a `[k, 1]` matrix x, along the contiguous axis of a
+fn gemv<T: NumT>(a: MatRef<T>, x: MatRef<T>, c: &mut [T]) {
+    let (m, k) = (a.rows, a.cols);
+    if a.cs == 1 && x.rs == 1 {
+        // a dot product per row
+        let x = &x.data[..k];
+        parallel::chunks_mut(m * k, c, MC, |ic, chunk| {
+            for (r, ci) in chunk.iter_mut().enumerate() {
+                let i = ic * MC + r;
+                *ci += dot(&a.data[i * a.rs..i * a.rs + k], x);
+            }
+        });
+    } else if a.cs == 1 || a.rs != 1 {
+        parallel::chunks_mut(m * k, c, MC, |ic, chunk| {
+            for (r, ci) in chunk.iter_mut().enumerate() {
+                let i = ic * MC + r;
+                *ci += (0..k).map(|p| a.at(i, p) * x.at(p, 0)).sum::<T>();
+            }
+        });
+    } else {
+        // the columns of a scaled and summed, by chunks of rows
+        parallel::chunks_mut(m * k, c, NC, |ic, chunk| {
+            for p in 0..k {
+                let xp = x.at(p, 0);
+                for (r, ci) in chunk.iter_mut().enumerate() {
+                    *ci += a.at(ic * NC + r, p) * xp;
+                }
+            }
+        });
+    }
+}
+
+/// The dot product, summed in independent lanes so that it vectorizes
+fn dot<T: NumT>(x: &[T], y: &[T]) -> T {
+    const LANES: usize = 8;
+    let mut acc = [T::zero(); LANES];
+    let (xc, yc) = (x.chunks_exact(LANES), y.chunks_exact(LANES));
+    let tail: T = xc.remainder().iter().zip(yc.remainder()).map(|(a, b)| *a * *b).sum();
+    for (xs, ys) in xc.zip(yc) {
+        for l in 0..LANES {
+            acc[l] += xs[l] * ys[l];
+        }
+    }
+    acc.iter().copied().sum::<T>() + tail
+}
+
+/// Pack blocks of b in strips of `NR` columns, then multiply each strip by `MR` rows
+/// of a at a time, packed as well, summing into a tile small enough for the registers
+fn gemm_blocked<T: NumT>(a: MatRef<T>, b: MatRef<T>, c: &mut [T]) {
+    let (m, k, n) = (a.rows, a.cols, b.cols);
+    let mut packed = Vec::new();
+    for p0 in (0..k).step_by(KC) {
+        let kb = KC.min(k - p0);
+        for j0 in (0..n).step_by(NC) {
+            let nb = NC.min(n - j0);
+            let strips = nb.div_ceil(NR);
+            // strip s holds b[p0 + p][j0 + s * NR + l] at (s * kb + p) * NR + l, padded by zeros
+            packed.clear();
+            packed.resize(strips * kb * NR, T::zero());
+            for (s, strip) in packed.chunks_mut(kb * NR).enumerate() {
+                for (p, row) in strip.chunks_mut(NR).enumerate() {
+                    for (l, x) in row.iter_mut().enumerate().take(nb - s * NR) {
+                        *x = b.at(p0 + p, j0 + s * NR + l);
+                    }
+                }
+            }
+            let packed = &packed;
+            parallel::chunks_mut(m * kb * nb, c, MC * n, |ic, c_block| {
+                let mut a_packed = vec![T::zero(); kb * MR];
+                for (r, c_rows) in c_block.chunks_mut(MR * n).enumerate() {
+                    let (i0, mr) = (ic * MC + r * MR, c_rows.len() / n);
+                    for (p, col) in a_packed.chunks_mut(MR).enumerate() {
+                        for (q, x) in col.iter_mut().enumerate() {
+                            *x = if q < mr { a.at(i0 + q, p0 + p) } else { T::zero() };
+                        }
+                    }
+                    for (s, strip) in packed.chunks(kb * NR).enumerate() {
+                        let mut tile = [[T::zero(); NR]; MR];
+                        for p in 0..kb {
+                            let ap: &[T; MR] = a_packed[p * MR..(p + 1) * MR].try_into().unwrap();
+                            let bp: &[T; NR] = strip[p * NR..(p + 1) * NR].try_into().unwrap();
+                            for q in 0..MR {
+                                for l in 0..NR {
+                                    tile[q][l] += ap[q] * bp[l];
+                                }
+                            }
+                        }
+                        let j = j0 + s * NR;
+                        for (t, c_row) in tile.iter().zip(c_rows.chunks_mut(n)) {
+                            for (x, y) in c_row[j..].iter_mut().zip(t).take(nb - s * NR) {
+                                *x += *y;
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// `y += alpha * x`
+pub fn axpy<T: NumT>(alpha: T, x: &[T], y: &mut [T]) -> Result<(), EasynnError> {
+    check_len("axpy", y.len(), x.len())?;
+    parallel::zip_mut(y, x, |y, x| *y += alpha * *x);
+    Ok(())
+}
+
+#[cfg(feature = "blas")]
+mod blas {
+    use super::*;
+    use std::any::TypeId;
+
+    /// `c += a * b` by `matrixmultiply` if T is `f32` or `f64`, false otherwise
+    pub(super) fn gemm<T: NumT>(a: MatRef<T>, b: MatRef<T>, c: &mut [T]) -> bool {
+        let (m, k, n) = (a.rows, a.cols, b.cols);
+        let s = |x: usize| x as isize;
+        // SAFETY: T is the type it is cast to, the strides were checked against the slices
+        unsafe {
+            if TypeId::of::<T>() == TypeId::of::<f64>() {
+                matrixmultiply::dgemm(m, k, n, 1., a.data.as_ptr() as *const f64, s(a.rs), s(a.cs),
+                    b.data.as_ptr() as *const f64, s(b.rs), s(b.cs), 1., c.as_mut_ptr() as *mut f64, s(n), 1);
+                true
+            } else if TypeId::of::<T>() == TypeId::of::<f32>() {
+                matrixmultiply::sgemm(m, k, n, 1., a.data.as_ptr() as *const f32, s(a.rs), s(a.cs),
+                    b.data.as_ptr() as *const f32, s(b.rs), s(b.cs), 1., c.as_mut_ptr() as *mut f32, s(n), 1);
+                true
+            } else {
+                false
+            }
+        }
+    }
+}
+
+#[test]
+fn test_gemm() {
+    use rand::{ Rng, SeedableRng };
+    let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+    let naive = |a: MatRef<f64>, b: MatRef<f64>| -> Vec<f64> {
+        (0..a.rows).flat_map(|i| (0..b.cols).map(move |j| (0..a.cols).map(|p| a.at(i, p) * b.at(p, j)).sum())).collect()
+    };
+    // sizes across the block edges, vectors and outer products
+    for (m, k, n) in [(3, 4, 5), (70, 300, 520), (1, 600, 9), (600, 9, 1), (5, 1, 7), (1, 1, 1)] {
+        let a: Vec<f64> = (0..m * k).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let b: Vec<f64> = (0..k * n).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let views = [
+            (MatRef::new(&a, m, k), MatRef::new(&b, k, n)),
+            (MatRef::new(&a, k, m).t(), MatRef::new(&b, n, k).t()),
+        ];
+        for (va, vb) in views {
+            let expected = naive(va, vb);
+            let mut c = vec![f64::NAN; m * n];
+            gemm(va, vb, 0., &mut c).unwrap();
+            assert!(c.iter().zip(expected.iter()).all(|(x, y)| (x - y).abs() < 1e-9));
+            // beta scales what is already there
+            gemm(va, vb, 2., &mut c).unwrap();
+            assert!(c.iter().zip(expected.iter()).all(|(x, y)| (x - 3. * y).abs() < 1e-9));
+        }
+    }
+    let a = [1., 2., 3., 4.];
+    let mut c = [0.; 4];
+    assert!(gemm(MatRef::new(&a, 2, 2), MatRef::new(&a, 1, 4), 0., &mut c).is_err());
+    assert!(gemm(MatRef::new(&a, 2, 2), MatRef::new(&a, 2, 2), 0., &mut c[..3]).is_err());
+    assert!(gemm(MatRef::new(&a, 2, 3), MatRef::new(&a, 3, 2), 0., &mut c).is_err());
+    // strides wrapping around to the slice are rejected
+    let wrapping = MatRef { data: &a[..], rows: 3, cols: 1, rs: 1 << (usize::BITS - 1), cs: 1 };
+    assert!(gemm(wrapping, MatRef::new(&a, 1, 1), 0., &mut c[..3]).is_err());
+    let wrapping = MatRef { data: &a[..], rows: 3, cols: 2, rs: usize::MAX / 2, cs: usize::MAX / 2 };
+    assert!(gemm(MatRef::new(&a, 1, 3), wrapping, 0., &mut c[..2]).is_err());
+    let mut y = [1., 1., 1., 1.];
+    axpy(-2., &a, &mut y).unwrap();
+    assert_eq!(y, [-1., -3., -5., -7.]);
+    assert!(axpy(1., &a[..3], &mut y).is_err());
+}
@@ -0,0 +1,340 @@
+//! Streaming metrics of single-label classification: accuracy, top-k accuracy, precision,
+//! recall, F1 and the confusion matrix, accumulated batch by batch, e.g. over the batches
+//! of a validation set, then read by `Metric::result`.
+//!
+//! A prediction is the `[classes]` scores of a model, the class of the highest score
+//! being predicted, or for two classes a single probability of class 1. A truth is either
+//! one-hot over the classes or a single element holding the class index.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::metrics::{ Metric, Accuracy, F1, Average };
+//!     let preds = vec![Tensor::<f64>::new(sh!([3]), vec![0.1, 0.7, 0.2]), Tensor::new(sh!([3]), vec![0.5, 0.3, 0.2])];
+//!     let truths = vec![Tensor::<f64>::new(sh!([1]), vec![1.]), Tensor::new(sh!([1]), vec![2.])];
+//!     let mut accuracy = Accuracy::new();
+//!     let mut f1 = F1::new(3, Average::Macro);
+//!     for (p, t) in preds.chunks(1).zip(truths.chunks(1)) {
+//!         accuracy.update(p, t).unwrap();
+//!         f1.update(p, t).unwrap();
+//!     }
+//!     assert_eq!(accuracy.result(), 0.5);
+//!     assert!((f1.result() - 1. / 3.).abs() < 1e-12);
+//! ```
+
+use crate::tensor::*;
+use std::marker::PhantomData;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// A metric accumulated over batches of predictions and truths
+pub trait Metric<T: NumT> {
+    /// Accumulate a batch
+    fn update(&mut self, preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<()>;
+    /// The metric of every batch accumulated, 0 if none
+    fn result(&self) -> T;
+    /// Forget the accumulated batches, e.g. before the next epoch
+    fn reset(&mut self);
+}
+
+/// The predicted class of the scores
+fn predicted_class<T: NumT>(op: &'static str, pred: &Tensor<T>) -> Result<usize> {
+    match pred.flattened.len() {
+        0 => Err(EasynnError::invalid(op, "the prediction is empty")),
+        1 => Ok(usize::from(pred.flattened[0] > T::from(0.5).unwrap())),
+        _ => Ok((1..pred.flattened.len()).fold(0, |best, c| if pred.flattened[c] > pred.flattened[best] { c } else { best })),
+    }
+}
+
+/// The class of the truth, below `classes`
+fn true_class<T: NumT>(op: &'static str, truth: &Tensor<T>, classes: usize) -> Result<usize> {
+    let class = match truth.flattened.len() {
+        1 => {
+            let x = truth.flattened[0];
+            match x.to_usize() {
+                Some(c) if x.fract() == T::zero() => c,
+                _ => return Err(EasynnError::invalid(op, format!("{} is not a class index", x))),
+            }
+        }
+        n if n == classes => predicted_class(op, truth)?,
+        n => return Err(EasynnError::invalid(op, format!("a truth of {} elements is neither a class index nor one-hot over {} classes", n, classes))),
+    };
+    if class >= classes {
+        return Err(EasynnError::invalid(op, format!("the class {} is not below {}", class, classes)));
+    }
+    Ok(class)
+}
+
+/// The count of classes of the scores
+fn classes_of<T: NumT>(pred: &Tensor<T>) -> usize {
+    pred.flattened.len().max(2)
+}
+
+/// The (true, predicted) class of each pair
+fn pairs<T: NumT>(op: &'static str, preds: &[Tensor<T>], truths: &[Tensor<T>], classes: Option<usize>) -> Result<Vec<(usize, usize)>> {
+    check_len(op, preds.len(), truths.len())?;
+    preds.iter().zip(truths.iter()).map(|(p, t)| {
+        let n = classes_of(p);
+        if let Some(classes) = classes {
+            check_len(op, classes, n)?;
+        }
+        Ok((true_class(op, t, n)?, predicted_class(op, p)?))
+    }).collect()
+}
+
+fn ratio<T: NumT>(num: usize, den: usize) -> T {
+    if den == 0 { T::zero() } else { T::from(num).unwrap() / T::from(den).unwrap() }
+}
+
+/// The fraction of the predicted classes that are right
+#[derive(Debug, Clone)]
+pub struct Accuracy<T: NumT = f64> {
+    correct: usize,
+    total: usize,
+    _t: PhantomData<T>,
+}
+
+impl<T: NumT> Accuracy<T> {
+    pub fn new() -> Self {
+        Accuracy { correct: 0, total: 0, _t: PhantomData }
+    }
+}
+
+impl<T: NumT> Default for Accuracy<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: NumT> Metric<T> for Accuracy<T> {
+    fn update(&mut self, preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<()> {
+        let pairs = pairs("Accuracy::update", preds, truths, None)?;
+        self.correct += pairs.iter().filter(|(t, p)| t == p).count();
+        self.total += pairs.len();
+        Ok(())
+    }
+    fn result(&self) -> T {
+        ratio(self.correct, self.total)
+    }
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// The fraction of the samples whose true class is among the `k` highest scores
+#[derive(Debug, Clone)]
+pub struct TopK<T: NumT = f64> {
+    pub k: usize,
+    correct: usize,
+    total: usize,
+    _t: PhantomData<T>,
+}
+
+impl<T: NumT> TopK<T> {
+    pub fn new(k: usize) -> Self {
+        TopK { k, correct: 0, total: 0, _t: PhantomData }
+    }
+}
+
+impl<T: NumT> Metric<T> for TopK<T> {
+    fn update(&mut self, preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<()> {
+        check_len("TopK::update", preds.len(), truths.len())?;
+        for (p, t) in preds.iter().zip(truths.iter()) {
+            if p.flattened.len() < 2 {
+                return Err(EasynnError::invalid("TopK::update", "the prediction should be the scores of the classes"));
+            }
+            let class = true_class("TopK::update", t, p.flattened.len())?;
+            let score = p.flattened[class];
+            // the classes scored above, the ties before it counted above too
+            let above = p.flattened.iter().enumerate().filter(|(c, s)| **s > score || (**s == score && *c < class)).count();
+            self.correct += usize::from(above < self.k);
+        }
+        self.total += preds.len();
+        Ok(())
+    }
+    fn result(&self) -> T {
+        ratio(self.correct, self.total)
+    }
+    fn reset(&mut self) {
+        self.correct = 0;
+        self.total = 0;
+    }
+}
+
+/// The counts of each pair of the true class, the row, and the predicted class, the column
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfusionMatrix<T: NumT = f64> {
+    pub classes: usize,
+    counts: Vec<usize>,
+    _t: PhantomData<T>,
+}
+
+impl<T: NumT> ConfusionMatrix<T> {
+    pub fn new(classes: usize) -> Self {
+        ConfusionMatrix { classes, counts: vec![0; classes * classes], _t: PhantomData }
+    }
+    /// The count of the samples of the true class predicted as `pred`
+    pub fn count(&self, truth: usize, pred: usize) -> usize {
+        self.counts[truth * self.classes + pred]
+    }
+    /// The rows of the matrix
+    pub fn rows(&self) -> std::slice::Chunks<'_, usize> {
+        self.counts.chunks(self.classes)
+    }
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+    /// The (true positive, false positive, false negative) counts of the class
+    pub fn class_counts(&self, class: usize) -> (usize, usize, usize) {
+        let tp = self.count(class, class);
+        let predicted = (0..self.classes).map(|t| self.count(t, class)).sum::<usize>();
+        let actual = (0..self.classes).map(|p| self.count(class, p)).sum::<usize>();
+        (tp, predicted - tp, actual - tp)
+    }
+    /// `tp / (tp + fp)` of the class, 0 if never predicted
+    pub fn precision(&self, class: usize) -> T {
+        let (tp, fp, _) = self.class_counts(class);
+        ratio(tp, tp + fp)
+    }
+    /// `tp / (tp + fn)` of the class, 0 if never true
+    pub fn recall(&self, class: usize) -> T {
+        let (tp, _, fn_) = self.class_counts(class);
+        ratio(tp, tp + fn_)
+    }
+    /// `2 tp / (2 tp + fp + fn)` of the class, 0 if neither true nor predicted
+    pub fn f1(&self, class: usize) -> T {
+        let (tp, fp, fn_) = self.class_counts(class);
+        ratio(2 * tp, 2 * tp + fp + fn_)
+    }
+    /// The score averaged over the classes as asked, the macro average being over the
+    /// classes true or predicted at least once
+    pub fn average<F: Fn(&Self, usize) -> T>(&self, average: Average, score: F) -> T {
+        match average {
+            Average::Class(c) => score(self, c),
+            Average::Micro => ratio((0..self.classes).map(|c| self.count(c, c)).sum(), self.total()),
+            Average::Macro => {
+                let present: Vec<usize> = (0..self.classes).filter(|c| self.class_counts(*c) != (0, 0, 0)).collect();
+                if present.is_empty() {
+                    return T::zero();
+                }
+                present.iter().map(|c| score(self, *c)).sum::<T>() / T::from(present.len()).unwrap()
+            }
+            Average::Weighted => {
+                let total = self.total();
+                if total == 0 {
+                    return T::zero();
+                }
+                (0..self.classes).map(|c| {
+                    let (tp, _, fn_) = self.class_counts(c);
+                    score(self, c) * T::from(tp + fn_).unwrap()
+                }).sum::<T>() / T::from(total).unwrap()
+            }
+        }
+    }
+}
+
+impl<T: NumT> Metric<T> for ConfusionMatrix<T> {
+    fn update(&mut self, preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<()> {
+        for (t, p) in pairs("ConfusionMatrix::update", preds, truths, Some(self.classes))? {
+            self.counts[t * self.classes + p] += 1;
+        }
+        Ok(())
+    }
+    /// The accuracy
+    fn result(&self) -> T {
+        self.average(Average::Micro, |_, _| T::zero())
+    }
+    fn reset(&mut self) {
+        self.counts.iter_mut().for_each(|c| *c = 0);
+    }
+}
+
+/// How a score of each class is averaged into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Average {
+    /// Of the summed counts of all the classes, the accuracy for single-label classification
+    Micro,
+    /// The mean over the classes present
+    Macro,
+    /// The mean over the classes weighted by their true counts
+    Weighted,
+    /// Of a single class, e.g. the positive class 1 of a binary classification
+    Class(usize),
+}
+
+macro_rules! class_metric {
+    ($name:ident, $score:ident, $doc:expr) => {
+        #[doc = $doc]
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name<T: NumT = f64> {
+            pub average: Average,
+            pub matrix: ConfusionMatrix<T>,
+        }
+
+        impl<T: NumT> $name<T> {
+            pub fn new(classes: usize, average: Average) -> Self {
+                $name { average, matrix: ConfusionMatrix::new(classes) }
+            }
+        }
+
+        impl<T: NumT> Metric<T> for $name<T> {
+            fn update(&mut self, preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<()> {
+                self.matrix.update(preds, truths)
+            }
+            fn result(&self) -> T {
+                self.matrix.average(self.average, |m, c| m.$score(c))
+            }
+            fn reset(&mut self) {
+                self.matrix.reset();
+            }
+        }
+    };
+}
+
+class_metric!(Precision, precision, "The precision of the classes, averaged");
+class_metric!(Recall, recall, "The recall of the classes, averaged");
+class_metric!(F1, f1, "The F1 score of the classes, averaged");
+
+#[test]
+fn test_classification_metrics() {
+    let scores = |s: &[f64]| Tensor::new(&Shape::new([s.len()]), s.to_vec());
+    let index = |c: f64| Tensor::new(&Shape::new([1]), vec![c]);
+    // true 0 0 1 1 2, predicted 0 1 1 1 0
+    let preds = [
+        scores(&[0.8, 0.1, 0.1]), scores(&[0.3, 0.6, 0.1]), scores(&[0.1, 0.5, 0.4]),
+        scores(&[0.2, 0.7, 0.1]), scores(&[0.5, 0.1, 0.4]),
+    ];
+    let truths = [index(0.), scores(&[1., 0., 0.]), index(1.), index(1.), index(2.)];
+    let mut matrix = ConfusionMatrix::<f64>::new(3);
+    let mut accuracy = Accuracy::new();
+    let mut top2 = TopK::new(2);
+    let mut precision = Precision::new(3, Average::Macro);
+    let mut recall = Recall::new(3, Average::Class(1));
+    let mut f1 = F1::new(3, Average::Weighted);
+    for (p, t) in preds.chunks(2).zip(truths.chunks(2)) {
+        let metrics: [&mut dyn Metric<f64>; 6] = [&mut matrix, &mut accuracy, &mut top2, &mut precision, &mut recall, &mut f1];
+        for m in metrics {
+            m.update(p, t).unwrap();
+        }
+    }
+    assert_eq!(matrix.rows().collect::<Vec<_>>(), [[1, 1, 0], [0, 2, 0], [1, 0, 0]]);
+    assert_eq!(matrix.class_counts(0), (1, 1, 1));
+    assert_eq!(accuracy.result(), 0.6);
+    assert_eq!(matrix.result(), 0.6);
+    assert_eq!(top2.result(), 1.);
+    // precisions 1/2, 2/3, 0 over the 3 classes present
+    assert!((precision.result() - 7. / 18.).abs() < 1e-12);
+    assert_eq!(recall.result(), 1.);
+    // f1s 1/2, 4/5, 0 weighted by 2, 2, 1
+    assert!((f1.result() - 0.52).abs() < 1e-12);
+
+    // binary probabilities against 0/1 truths
+    let mut binary = F1::new(2, Average::Class(1));
+    binary.update(&[index(0.9), index(0.2), index(0.7)], &[index(1.), index(1.), index(0.)]).unwrap();
+    assert_eq!(binary.result(), 0.5);
+    binary.reset();
+    assert_eq!(binary.result(), 0.);
+
+    for bad in [index(3.), index(0.5), scores(&[0., 1.])] {
+        assert!(matrix.update(&preds[..1], &[bad]).is_err());
+    }
+    assert!(accuracy.update(&preds, &truths[..2]).is_err());
+}
@@ -0,0 +1,129 @@
+//! Mean average precision (mAP) of object detection.
+//!
+//! The detections of each class are ranked by score over all the images.
+//! Going down the ranking, a detection is a true positive if it overlaps an unmatched
+//! truth of its class in its image with an IoU of at least the threshold (the truth of
+//! the highest IoU is matched), otherwise a false positive. The average precision is
+//! the area under the precision-recall curve with the precision made monotone
+//! (the all-point interpolation of Pascal VOC), and the mAP averages it over the classes
+//! having truths and over the IoU thresholds.
+//!
+//! ```rust
+//!     use easynn::vision::BBox;
+//!     use easynn::metrics::{ Detection, mean_average_precision };
+//!     let truths = vec![vec![(BBox::new(0., 0., 10., 10.), 0)]];
+//!     let detections = vec![vec![
+//!         Detection { bbox: BBox::new(1., 0., 10., 10.), score: 0.9, class: 0 },
+//!         Detection { bbox: BBox::new(20., 20., 30., 30.), score: 0.8, class: 0 },
+//!     ]];
+//!     assert_eq!(mean_average_precision(&detections, &truths, &[0.5]).unwrap(), 1.);
+//!     assert_eq!(mean_average_precision(&detections, &truths, &[0.95]).unwrap(), 0.);
+//! ```
+
+use crate::tensor::*;
+use crate::vision::BBox;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// A predicted box with its confidence and class
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Detection<T: NumT> {
+    pub bbox: BBox<T>,
+    pub score: T,
+    pub class: usize,
+}
+
+/// The IoU thresholds of COCO, 0.5 to 0.95 by 0.05
+pub fn coco_thresholds<T: NumT>() -> Vec<T> {
+    (0..10).map(|i| T::from(0.5 + 0.05 * i as f64).unwrap()).collect()
+}
+
+/// The average precision of one class at the IoU threshold, None if the class has no truths.
+/// `detections` and `truths` hold the boxes of each image
+pub fn average_precision<T: NumT>(detections: &[Vec<Detection<T>>], truths: &[Vec<(BBox<T>, usize)>], class: usize, iou_threshold: T) -> Result<Option<T>> {
+    check_len("average_precision", truths.len(), detections.len())?;
+    let positives = truths.iter().flatten().filter(|t| t.1 == class).count();
+    if positives == 0 {
+        return Ok(None);
+    }
+    let mut ranked: Vec<(usize, &Detection<T>)> = detections.iter().enumerate()
+        .flat_map(|(img, d)| d.iter().filter(|d| d.class == class).map(move |d| (img, d)))
+        .collect();
+    ranked.sort_by(|a, b| b.1.score.partial_cmp(&a.1.score).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut matched: Vec<Vec<bool>> = truths.iter().map(|t| vec![false; t.len()]).collect();
+    // The (recall, precision) after each detection
+    let mut curve = Vec::with_capacity(ranked.len());
+    let mut tp = 0;
+    for (rank, (img, d)) in ranked.iter().enumerate() {
+        let best = truths[*img].iter().enumerate()
+            .filter(|(j, t)| t.1 == class && !matched[*img][*j])
+            .map(|(j, t)| (j, t.0.iou(&d.bbox)))
+            .filter(|(_, iou)| *iou >= iou_threshold)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        if let Some((j, _)) = best {
+            matched[*img][j] = true;
+            tp += 1;
+        }
+        curve.push((T::from(tp).unwrap() / T::from(positives).unwrap(), T::from(tp).unwrap() / T::from(rank + 1).unwrap()));
+    }
+    // Make the precision monotone from the right, then sum over the recall steps
+    let mut ap = T::zero();
+    let mut best = T::zero();
+    for i in (0..curve.len()).rev() {
+        best = best.max(curve[i].1);
+        let lst_recall = if i == 0 { T::zero() } else { curve[i - 1].0 };
+        ap += (curve[i].0 - lst_recall) * best;
+    }
+    Ok(Some(ap))
+}
+
+/// The mean of the average precisions over the classes having truths and over the IoU thresholds,
+/// failing if the image counts differ, there are no thresholds or there are no truths
+pub fn mean_average_precision<T: NumT>(detections: &[Vec<Detection<T>>], truths: &[Vec<(BBox<T>, usize)>], iou_thresholds: &[T]) -> Result<T> {
+    let classes = truths.iter().flatten().map(|t| t.1 + 1).max().unwrap_or(0);
+    if iou_thresholds.is_empty() || classes == 0 {
+        return Err(EasynnError::invalid("mean_average_precision", "there are no IoU thresholds or no classes"));
+    }
+    let mut sum = T::zero();
+    let mut count = 0;
+    for th in iou_thresholds {
+        for class in 0..classes {
+            if let Some(ap) = average_precision(detections, truths, class, *th)? {
+                sum += ap;
+                count += 1;
+            }
+        }
+    }
+    Ok(sum / T::from(count).unwrap())
+}
+
+#[test]
+fn test_mean_average_precision() {
+    let det = |x: f64, score, class| Detection { bbox: BBox::new(x, 0., x + 10., 10.), score, class };
+    let truths = vec![
+        vec![(BBox::new(0., 0., 10., 10.), 0), (BBox::new(50., 0., 60., 10.), 0)],
+        vec![(BBox::new(0., 0., 10., 10.), 1)],
+    ];
+    // Ranked: TP (recall 1/2, precision 1), FP (a duplicate), TP (recall 1, precision 2/3)
+    let detections = vec![
+        vec![det(0., 0.9, 0), det(1., 0.8, 0), det(51., 0.7, 0), det(0., 0.6, 1)],
+        vec![det(0., 0.5, 1)],
+    ];
+    let ap0 = average_precision(&detections, &truths, 0, 0.5).unwrap().unwrap();
+    assert!((ap0 - (0.5 + 0.5 * 2. / 3.)).abs() < 1e-12);
+    // The class 1 detection in image 0 is a false positive ranked first
+    let ap1 = average_precision(&detections, &truths, 1, 0.5).unwrap().unwrap();
+    assert!((ap1 - 0.5).abs() < 1e-12);
+    assert_eq!(average_precision(&detections, &truths, 2, 0.5).unwrap(), None);
+    let map = mean_average_precision(&detections, &truths, &[0.5]).unwrap();
+    assert!((map - (ap0 + ap1) / 2.).abs() < 1e-12);
+
+    // A shifted box fails the strict thresholds
+    let coco = mean_average_precision(&detections, &truths, &coco_thresholds()).unwrap();
+    assert!(coco < map && coco > 0.);
+    assert_eq!(coco_thresholds::<f64>().len(), 10);
+
+    assert!(mean_average_precision(&detections[..1], &truths, &[0.5]).is_err());
+    assert!(mean_average_precision(&detections, &truths, &[]).is_err());
+    assert!(mean_average_precision::<f64>(&[vec![]], &[vec![]], &[0.5]).is_err());
+}
@@ -0,0 +1,124 @@
+//! Metrics and walk-forward evaluation for time-series forecasting.
+//!
+//! Random splits leak the future into the training set, so forecasters are
+//! validated from rolling origins: at each origin the model only sees the past.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::metrics::walk_forward;
+//!     let series = Tensor::<f64>::new(sh!([12]), (0..12).map(|x| x as f64).collect());
+//!     // the naive forecaster repeats the last observation
+//!     let report = walk_forward(&series, 6, 2, 2, 1, |history, horizon| {
+//!         let last = history.get([history.get_shape()[0] - 1]);
+//!         Tensor::new(sh!([horizon]), vec![last; horizon])
+//!     }).unwrap();
+//!     assert_eq!(report.folds.len(), 3);
+//!     assert!((report.mase - 1.5).abs() < 1e-12);
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// The symmetric mean absolute percentage error, in percent (0 to 200)
+pub fn smape<T: NumT>(prediction: &Tensor<T>, truth: &Tensor<T>) -> Result<T> {
+    check_shape("smape", &truth.shape, &prediction.shape)?;
+    if truth.shape.size() == 0 {
+        return Err(EasynnError::invalid("smape", "the truth is empty"));
+    }
+    let two = T::one() + T::one();
+    let sum = prediction.flattened.iter().zip(truth.flattened.iter()).map(|(p, t)| {
+        let denom = p.abs() + t.abs();
+        if denom == T::zero() { T::zero() } else { two * (*p - *t).abs() / denom }
+    }).sum::<T>();
+    Ok(sum / T::from(truth.shape.size()).unwrap() * T::from(100).unwrap())
+}
+
+/// The mean absolute scaled error: the mean absolute error of the prediction,
+/// divided by that of the seasonal naive forecast (lag `season`) on the history,
+/// failing if that is zero, e.g. for a constant history
+pub fn mase<T: NumT>(prediction: &Tensor<T>, truth: &Tensor<T>, history: &Tensor<T>, season: usize) -> Result<T> {
+    check_shape("mase", &truth.shape, &prediction.shape)?;
+    if truth.shape.size() == 0 || season == 0 || history.flattened.len() <= season {
+        return Err(EasynnError::invalid("mase", format!("no season {} over a history of {}", season, history.flattened.len())));
+    }
+    let mae = prediction.flattened.iter().zip(truth.flattened.iter())
+        .map(|(p, t)| (*p - *t).abs()).sum::<T>() / T::from(truth.shape.size()).unwrap();
+    let h = &history.flattened;
+    let naive = h.iter().skip(season).zip(h.iter())
+        .map(|(x, x_lag)| (*x - *x_lag).abs()).sum::<T>() / T::from(h.len() - season).unwrap();
+    if naive == T::zero() {
+        return Err(EasynnError::invalid("mase", format!("the history repeats with the season {}, there is no scale", season)));
+    }
+    Ok(mae / naive)
+}
+
+/// The evaluation at one forecast origin
+#[derive(Debug, Clone)]
+pub struct ForecastFold<T: NumT> {
+    /// The count of observed steps
+    pub origin: usize,
+    pub prediction: Tensor<T>,
+    pub truth: Tensor<T>,
+    pub smape: T,
+    pub mase: T,
+}
+
+/// The walk-forward evaluation, with the metrics averaged over the folds
+#[derive(Debug, Clone)]
+pub struct WalkForwardReport<T: NumT> {
+    pub folds: Vec<ForecastFold<T>>,
+    pub smape: T,
+    pub mase: T,
+}
+
+/// Rolling-origin evaluation of a `[len]` series: starting with `initial` observed steps,
+/// `forecast(history, horizon)` predicts the next `horizon` steps, then the origin moves
+/// by `step`. The forecaster may refit on the history each time.
+pub fn walk_forward<T: NumT, F>(series: &Tensor<T>, initial: usize, horizon: usize, step: usize, season: usize, mut forecast: F) -> Result<WalkForwardReport<T>>
+where F: FnMut(&Tensor<T>, usize) -> Tensor<T> {
+    let len = series.flattened.len();
+    if series.shape.rank() != 1 || horizon == 0 || step == 0 || initial <= season || initial + horizon > len {
+        let message = format!("no folds of horizon {} by {} from {} over the series {:?}", horizon, step, initial, series.shape.dims());
+        return Err(EasynnError::invalid("walk_forward", message));
+    }
+    let mut folds = Vec::<ForecastFold<T>>::new();
+    let mut origin = initial;
+    while origin + horizon <= len {
+        let history = Tensor::new(&Shape::new([origin]), series.flattened[..origin].to_vec());
+        let truth = Tensor::new(&Shape::new([horizon]), series.flattened[origin..origin + horizon].to_vec());
+        let prediction = forecast(&history, horizon);
+        folds.push(ForecastFold {
+            origin,
+            smape: smape(&prediction, &truth)?,
+            mase: mase(&prediction, &truth, &history, season)?,
+            prediction,
+            truth,
+        });
+        origin += step;
+    }
+    let n = T::from(folds.len()).unwrap();
+    Ok(WalkForwardReport {
+        smape: folds.iter().map(|f| f.smape).sum::<T>() / n,
+        mase: folds.iter().map(|f| f.mase).sum::<T>() / n,
+        folds,
+    })
+}
+
+#[test]
+fn test_forecast_metrics() {
+    let truth = Tensor::<f64>::new(&Shape::new([3]), vec![1., 2., 0.]);
+    let pred = Tensor::<f64>::new(&Shape::new([3]), vec![3., 2., 0.]);
+    // 2 * 2 / 4 = 1, then 0 and 0 (both zero)
+    assert!((smape(&pred, &truth).unwrap() - 100. / 3.).abs() < 1e-12);
+
+    let history = Tensor::<f64>::new(&Shape::new([4]), vec![1., 3., 2., 6.]);
+    // the naive errors are 2, 1, 4, the prediction errors are 2, 0, 0
+    assert!((mase(&pred, &truth, &history, 1).unwrap() - (2. / 3.) / (7. / 3.)).abs() < 1e-12);
+    // seasonal naive with lag 2: errors 1, 3
+    assert!((mase(&pred, &truth, &history, 2).unwrap() - (2. / 3.) / 2.).abs() < 1e-12);
+    assert!(mase(&pred, &truth, &history, 4).is_err());
+    // a constant or seasonally constant history has no scale
+    let seasonal = Tensor::<f64>::new(&Shape::new([6]), vec![1., 5., 1., 5., 1., 5.]);
+    assert!(mase(&pred, &truth, &seasonal, 2).is_err());
+    assert!(mase(&pred, &truth, &seasonal, 1).is_ok());
+}
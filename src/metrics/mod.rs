@@ -0,0 +1,15 @@
+//! The metrics module, measuring the quality of predictions.
+//!
+
+pub mod forecast;
+pub use forecast::*;
+pub mod multilabel;
+pub use multilabel::*;
+pub mod detection;
+pub use detection::*;
+pub mod segmentation;
+pub use segmentation::*;
+pub mod classification;
+pub use classification::*;
+pub mod ranking;
+pub use ranking::*;
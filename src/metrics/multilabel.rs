@@ -0,0 +1,134 @@
+//! Metrics and threshold tuning for multi-label classification.
+//!
+//! A sample is a tensor with one entry per label. The scores are probabilities,
+//! e.g. the sigmoid of the logits of a model trained with `Loss::SigmoidCrossEntropy`,
+//! and the truths and the binarized predictions are 1 (positive) or 0 (negative).
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::metrics::{ tune_thresholds, binarize, micro_f1 };
+//!     let scores = vec![Tensor::<f64>::new(sh!([2]), vec![0.9, 0.6]), Tensor::new(sh!([2]), vec![0.2, 0.4])];
+//!     let truths = vec![Tensor::<f64>::new(sh!([2]), vec![1., 1.]), Tensor::new(sh!([2]), vec![0., 0.])];
+//!     let thresholds = tune_thresholds(&scores, &truths).unwrap();
+//!     let preds: Vec<_> = scores.iter().map(|s| binarize(s, &thresholds).unwrap()).collect();
+//!     assert_eq!(micro_f1(&preds, &truths).unwrap(), 1.);
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+fn positive<T: NumT>(x: T) -> bool {
+    x > T::from(0.5).unwrap()
+}
+
+/// Predict the labels whose scores are over their thresholds, one threshold per label
+pub fn binarize<T: NumT>(scores: &Tensor<T>, thresholds: &[T]) -> Result<Tensor<T>> {
+    check_len("binarize", scores.shape.size(), thresholds.len())?;
+    let data = scores.flattened.iter().zip(thresholds.iter())
+        .map(|(s, t)| if s > t { T::one() } else { T::zero() })
+        .collect();
+    Ok(Tensor::new(&scores.shape, data))
+}
+
+/// The (true positive, false positive, false negative) counts of each label
+fn counts<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<(usize, usize, usize)>> {
+    if preds.is_empty() {
+        return Err(EasynnError::invalid("counts", "there are no predictions"));
+    }
+    check_len("counts", preds.len(), truths.len())?;
+    let labels = truths[0].shape.size();
+    let mut ret = vec![(0, 0, 0); labels];
+    for (p, t) in preds.iter().zip(truths.iter()) {
+        check_shape("counts", &t.shape, &p.shape)?;
+        check_len("counts", labels, t.shape.size())?;
+        for (c, (p, t)) in ret.iter_mut().zip(p.flattened.iter().zip(t.flattened.iter())) {
+            match (positive(*p), positive(*t)) {
+                (true, true) => c.0 += 1,
+                (true, false) => c.1 += 1,
+                (false, true) => c.2 += 1,
+                _ => (),
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// The F1 score of the counts, 1 if there are neither positive truths nor positive predictions
+fn f1<T: NumT>((tp, fp, fn_): (usize, usize, usize)) -> T {
+    if tp + fp + fn_ == 0 {
+        return T::one();
+    }
+    T::from(2 * tp).unwrap() / T::from(2 * tp + fp + fn_).unwrap()
+}
+
+/// The fraction of samples whose labels are all predicted correctly
+pub fn subset_accuracy<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<T> {
+    counts(preds, truths)?;
+    let exact = preds.iter().zip(truths.iter()).filter(|(p, t)| {
+        p.flattened.iter().zip(t.flattened.iter()).all(|(p, t)| positive(*p) == positive(*t))
+    }).count();
+    Ok(T::from(exact).unwrap() / T::from(preds.len()).unwrap())
+}
+
+/// The F1 score of the counts summed over all the labels
+pub fn micro_f1<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<T> {
+    let total = counts(preds, truths)?.iter()
+        .fold((0, 0, 0), |a, c| (a.0 + c.0, a.1 + c.1, a.2 + c.2));
+    Ok(f1(total))
+}
+
+/// The mean of the F1 scores of each label
+pub fn macro_f1<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<T> {
+    let c = counts(preds, truths)?;
+    Ok(c.iter().map(|c| f1::<T>(*c)).sum::<T>() / T::from(c.len()).unwrap())
+}
+
+/// Choose the threshold of each label maximizing its F1 score on a validation set,
+/// among the midpoints between its distinct scores
+pub fn tune_thresholds<T: NumT>(scores: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<T>> {
+    counts(scores, truths)?;
+    let labels = truths[0].shape.size();
+    let two = T::one() + T::one();
+    Ok((0..labels).map(|l| {
+        let mut pairs: Vec<(T, bool)> = scores.iter().zip(truths.iter())
+            .map(|(s, t)| (s.flattened[l], positive(t.flattened[l])))
+            .collect();
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        // start by predicting every sample positive, then raise the threshold past each score
+        let mut tp = pairs.iter().filter(|p| p.1).count();
+        let mut fp = pairs.len() - tp;
+        let mut fn_ = 0;
+        let mut best = (f1::<T>((tp, fp, fn_)), pairs[0].0 - T::one());
+        for (i, (score, pos)) in pairs.iter().enumerate() {
+            if *pos { tp -= 1; fn_ += 1; } else { fp -= 1; }
+            if i + 1 < pairs.len() && pairs[i + 1].0 == *score {
+                continue;
+            }
+            let threshold = if i + 1 < pairs.len() { (*score + pairs[i + 1].0) / two } else { *score };
+            let score = f1::<T>((tp, fp, fn_));
+            if score > best.0 {
+                best = (score, threshold);
+            }
+        }
+        best.1
+    }).collect())
+}
+
+#[test]
+fn test_multilabel_metrics() {
+    let t = |v: [f64; 3]| Tensor::new(&Shape::new([3]), v.to_vec());
+    let truths = [t([1., 0., 1.]), t([0., 1., 0.]), t([1., 1., 0.])];
+    let preds = [t([1., 0., 1.]), t([1., 1., 0.]), t([1., 0., 0.])];
+    assert!((subset_accuracy(&preds, &truths).unwrap() - 1. / 3.).abs() < 1e-12);
+    // tp 4, fp 1, fn 1
+    assert!((micro_f1(&preds, &truths).unwrap() - 8. / 10.).abs() < 1e-12);
+    // labels: (2, 1, 0), (1, 0, 1), (1, 0, 0)
+    assert!((macro_f1(&preds, &truths).unwrap() - (0.8 + 2. / 3. + 1.) / 3.).abs() < 1e-12);
+
+    let scores = [t([0.8, 0.1, 0.3]), t([0.6, 0.7, 0.2]), t([0.9, 0.6, 0.1])];
+    let thresholds = tune_thresholds(&scores, &truths).unwrap();
+    assert_eq!(thresholds, vec![0.7, 0.35, 0.25]);
+    let tuned: Vec<_> = scores.iter().map(|s| binarize(s, &thresholds).unwrap()).collect();
+    assert_eq!(subset_accuracy(&tuned, &truths).unwrap(), 1.);
+    assert!(binarize(&scores[0], &[0.5]).is_err());
+}
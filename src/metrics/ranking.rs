@@ -0,0 +1,58 @@
+//! Metrics of top-k recommendation: the hit rate HR@k and the normalized discounted
+//! cumulative gain NDCG@k, averaged over the users.
+//!
+//! A ranking is the items recommended to a user, best first, and the relevant items are
+//! those held out for the user, e.g. the single last interaction of leave-one-out.
+//! Relevance is binary, an item at position p (from 0) gaining `1 / log2(p + 2)`.
+//!
+//! ```rust
+//!     use easynn::metrics::{ hit_rate_at_k, ndcg_at_k };
+//!     let rankings = vec![vec![3, 1, 2], vec![0, 2, 1]];
+//!     let relevant = vec![vec![1], vec![3]];
+//!     assert_eq!(hit_rate_at_k(&rankings, &relevant, 2).unwrap(), 0.5);
+//!     assert!((ndcg_at_k(&rankings, &relevant, 2).unwrap() - 0.5 / 3_f64.log2()).abs() < 1e-12);
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+fn check_rankings(op: &'static str, rankings: &[Vec<usize>], relevant: &[Vec<usize>], k: usize) -> Result<()> {
+    if rankings.is_empty() || k == 0 {
+        return Err(EasynnError::invalid(op, "there are no rankings or k is 0"));
+    }
+    check_len(op, rankings.len(), relevant.len())
+}
+
+/// The fraction of the users with a relevant item in the top `k` of their ranking
+pub fn hit_rate_at_k(rankings: &[Vec<usize>], relevant: &[Vec<usize>], k: usize) -> Result<f64> {
+    check_rankings("hit_rate_at_k", rankings, relevant, k)?;
+    let hits = rankings.iter().zip(relevant).filter(|(r, rel)| r.iter().take(k).any(|i| rel.contains(i))).count();
+    Ok(hits as f64 / rankings.len() as f64)
+}
+
+/// The mean over the users of the DCG of the top `k` over the DCG of the ideal ranking,
+/// 0 for the users without relevant items
+pub fn ndcg_at_k(rankings: &[Vec<usize>], relevant: &[Vec<usize>], k: usize) -> Result<f64> {
+    check_rankings("ndcg_at_k", rankings, relevant, k)?;
+    let gain = |p: usize| 1. / ((p + 2) as f64).log2();
+    let sum: f64 = rankings.iter().zip(relevant).map(|(r, rel)| {
+        let dcg: f64 = r.iter().take(k).enumerate().filter(|(_, i)| rel.contains(*i)).map(|(p, _)| gain(p)).sum();
+        let ideal: f64 = (0..rel.len().min(k)).map(gain).sum();
+        if ideal > 0. { dcg / ideal } else { 0. }
+    }).sum();
+    Ok(sum / rankings.len() as f64)
+}
+
+#[test]
+fn test_ranking() {
+    let rankings = vec![vec![5, 2, 7, 1], vec![4, 3, 0, 9], vec![8, 6, 2, 0]];
+    let relevant = vec![vec![2, 1], vec![4], vec![]];
+    assert_eq!(hit_rate_at_k(&rankings, &relevant, 1).unwrap(), 1. / 3.);
+    assert_eq!(hit_rate_at_k(&rankings, &relevant, 4).unwrap(), 2. / 3.);
+    // the first user gains 1 / log2(3) + 1 / log2(5) of the ideal 1 + 1 / log2(3)
+    let first = (1. / 3_f64.log2() + 1. / 5_f64.log2()) / (1. + 1. / 3_f64.log2());
+    assert!((ndcg_at_k(&rankings, &relevant, 4).unwrap() - (first + 1.) / 3.).abs() < 1e-12);
+    assert!((ndcg_at_k(&rankings, &relevant, 1).unwrap() - 1. / 3.).abs() < 1e-12);
+    assert!(hit_rate_at_k(&rankings, &relevant[..2], 1).is_err());
+    assert!(ndcg_at_k(&[], &[], 1).is_err());
+}
@@ -0,0 +1,107 @@
+//! Metrics of semantic segmentation: IoU (Jaccard) and Dice per class, and their macro averages.
+//!
+//! A prediction is the `[classes, ...]` scores of a model, e.g. the probabilities trained
+//! with `Loss::Dice`, and a truth is one-hot over the same first axis. Each pixel is
+//! assigned the class of the highest score, the counts are summed over all the samples,
+//! and the classes absent from both the predictions and the truths are skipped.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::metrics::{ class_iou, mean_iou };
+//!     let truth = Tensor::<f64>::new(sh!([2, 4]), vec![1., 1., 0., 0., 0., 0., 1., 1.]);
+//!     let pred = Tensor::<f64>::new(sh!([2, 4]), vec![0.9, 0.2, 0.3, 0.1, 0.1, 0.8, 0.7, 0.9]);
+//!     assert_eq!(class_iou(&[pred.clone()], &[truth.clone()]).unwrap(), vec![Some(0.5), Some(2. / 3.)]);
+//!     assert!((mean_iou(&[pred], &[truth]).unwrap() - 7. / 12.).abs() < 1e-12);
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// The class of the highest score of each pixel of a `[classes, ...]` tensor
+fn argmax_classes<T: NumT>(t: &Tensor<T>) -> Vec<usize> {
+    let classes = t.shape[0];
+    let len = t.shape.size() / classes;
+    (0..len).map(|p| {
+        (1..classes).fold(0, |best, c| if t.flattened[c * len + p] > t.flattened[best * len + p] { c } else { best })
+    }).collect()
+}
+
+/// The (true positive, false positive, false negative) counts of each class
+fn counts<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<(usize, usize, usize)>> {
+    if preds.is_empty() {
+        return Err(EasynnError::invalid("counts", "there are no predictions"));
+    }
+    check_len("counts", preds.len(), truths.len())?;
+    let shape = &truths[0].shape;
+    if shape.rank() < 2 || shape.size() == 0 {
+        return Err(EasynnError::invalid("counts", "the masks should be non-empty [classes, ...]"));
+    }
+    let mut ret = vec![(0, 0, 0); shape[0]];
+    for (p, t) in preds.iter().zip(truths.iter()) {
+        check_shape("counts", shape, &p.shape)?;
+        check_shape("counts", shape, &t.shape)?;
+        for (p, t) in argmax_classes(p).into_iter().zip(argmax_classes(t)) {
+            if p == t {
+                ret[p].0 += 1;
+            } else {
+                ret[p].1 += 1;
+                ret[t].2 += 1;
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// The mean of the classes present
+fn macro_average<T: NumT>(scores: &[Option<T>]) -> T {
+    let present: Vec<T> = scores.iter().flatten().copied().collect();
+    present.iter().copied().sum::<T>() / T::from(present.len()).unwrap()
+}
+
+/// The IoU `tp / (tp + fp + fn)` of each class, None if the class is absent
+pub fn class_iou<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<Option<T>>> {
+    Ok(counts(preds, truths)?.into_iter().map(|(tp, fp, fn_)| {
+        if tp + fp + fn_ == 0 { None } else { Some(T::from(tp).unwrap() / T::from(tp + fp + fn_).unwrap()) }
+    }).collect())
+}
+
+/// The Dice coefficient `2 tp / (2 tp + fp + fn)` of each class, None if the class is absent
+pub fn class_dice<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<Option<T>>> {
+    Ok(counts(preds, truths)?.into_iter().map(|(tp, fp, fn_)| {
+        if tp + fp + fn_ == 0 { None } else { Some(T::from(2 * tp).unwrap() / T::from(2 * tp + fp + fn_).unwrap()) }
+    }).collect())
+}
+
+/// The IoU macro-averaged over the classes present
+pub fn mean_iou<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<T> {
+    Ok(macro_average(&class_iou(preds, truths)?))
+}
+
+/// The Dice coefficient macro-averaged over the classes present
+pub fn mean_dice<T: NumT>(preds: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<T> {
+    Ok(macro_average(&class_dice(preds, truths)?))
+}
+
+#[test]
+fn test_segmentation_metrics() {
+    // 3 classes of 2x2 pixels, class 2 never appears
+    let shape = Shape::new([3, 2, 2]);
+    let truth = Tensor::<f64>::new(&shape, vec![1., 1., 0., 0., 0., 0., 1., 1., 0., 0., 0., 0.]);
+    let samples = [truth.clone()];
+    assert_eq!(class_iou(&samples, &samples).unwrap(), vec![Some(1.), Some(1.), None]);
+    assert_eq!(mean_dice(&samples, &samples).unwrap(), 1.);
+
+    // One pixel of class 1 predicted as 0 in the first sample, all right in the second
+    let pred = Tensor::<f64>::new(&shape, vec![0.6, 0.9, 0.7, 0.1, 0.3, 0.1, 0.2, 0.8, 0.1, 0., 0.1, 0.1]);
+    let preds = [pred, truth.clone()];
+    let truths = [truth.clone(), truth];
+    // class 0: tp 4, fp 1; class 1: tp 3, fn 1
+    let iou = class_iou(&preds, &truths).unwrap();
+    assert_eq!(iou, vec![Some(0.8), Some(0.75), None]);
+    assert!((mean_iou(&preds, &truths).unwrap() - 0.775).abs() < 1e-12);
+    let dice = class_dice(&preds, &truths).unwrap();
+    assert_eq!(dice, vec![Some(8. / 9.), Some(6. / 7.), None]);
+
+    assert!(mean_iou(&preds[..1], &truths).is_err());
+    assert!(mean_iou::<f64>(&[], &[]).is_err());
+}
@@ -0,0 +1,306 @@
+//! Callbacks observing, and possibly stopping, the training of `Sequential::fit_with_callbacks`.
+//!
+//! A `Callback` is called at the beginning and the end of each epoch and after each batch.
+//! Returning false from `on_batch_end` or `on_epoch_end` stops the training. The built-ins
+//! are `EarlyStopping` on the validation loss, `BestCheckpoint` saving the best model,
+//! and `ProgressReporter` printing the losses.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::callbacks::*;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+//!     let inputs = vec![Tensor::new(sh!([1]), vec![1.]); 4];
+//!     let truths = vec![Tensor::new(sh!([1]), vec![2.]); 4];
+//!     let mut stopping = EarlyStopping::new(3).restore_best(true);
+//!     let history = nn.fit_with_callbacks(&inputs, &truths, Some((&inputs, &truths)), 100, 2, 0.1, &mut [&mut stopping]);
+//!     assert!(history.len() <= 100);
+//!     assert!(history.last().unwrap().val_loss.is_some());
+//! ```
+
+use crate::models::*;
+use crate::models::control::Snapshot;
+use crate::models::sequential::Sequential;
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// The losses of a trained epoch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EpochLogs<T: NumT> {
+    pub epoch: usize,
+    /// The mean training loss of the batches
+    pub loss: T,
+    /// The loss on the validation set, if given
+    pub val_loss: Option<T>,
+}
+
+impl<T: NumT> EpochLogs<T> {
+    /// The loss the built-in callbacks monitor, the validation one if any
+    pub fn monitored(&self) -> T {
+        self.val_loss.unwrap_or(self.loss)
+    }
+}
+
+/// The observer of the training loop. Every method does nothing by default.
+pub trait Callback<T: NumT> {
+    fn on_epoch_begin(&mut self, _epoch: usize, _model: &Sequential<T>) {}
+    /// After each batch, with its index and mean loss. Returns whether the training goes on.
+    fn on_batch_end(&mut self, _batch: usize, _loss: T, _model: &Sequential<T>) -> bool {
+        true
+    }
+    /// Returns whether the training goes on
+    fn on_epoch_end(&mut self, _logs: &EpochLogs<T>, _model: &Sequential<T>) -> bool {
+        true
+    }
+    /// Once the training stops, e.g. to restore the best parameters
+    fn on_train_end(&mut self, _model: &mut Sequential<T>) {}
+}
+
+/// Whether the loss improves on the best by more than `min_delta`
+fn improves<T: NumT>(loss: T, best: Option<T>, min_delta: T) -> bool {
+    best.map_or(!loss.is_nan(), |b| loss < b - min_delta)
+}
+
+/// Stop the training once the monitored loss has not improved for `patience` epochs
+#[derive(Debug, Clone)]
+pub struct EarlyStopping<T: NumT> {
+    pub patience: usize,
+    /// The least decrease of the loss counted as an improvement
+    pub min_delta: T,
+    /// Whether the parameters of the best epoch are loaded back at the end
+    pub restore_best: bool,
+    best: Option<T>,
+    best_epoch: Option<usize>,
+    wait: usize,
+    snapshot: Option<Snapshot<T>>,
+    stopped_epoch: Option<usize>,
+}
+
+impl<T: NumT> EarlyStopping<T> {
+    pub fn new(patience: usize) -> Self {
+        EarlyStopping::<T> {
+            patience,
+            min_delta: T::zero(),
+            restore_best: false,
+            best: None,
+            best_epoch: None,
+            wait: 0,
+            snapshot: None,
+            stopped_epoch: None,
+        }
+    }
+    pub fn min_delta(mut self, min_delta: T) -> Self {
+        self.min_delta = min_delta;
+        self
+    }
+    pub fn restore_best(mut self, restore_best: bool) -> Self {
+        self.restore_best = restore_best;
+        self
+    }
+    /// The best monitored loss and its epoch
+    pub fn best(&self) -> Option<(usize, T)> {
+        self.best_epoch.zip(self.best)
+    }
+    /// The epoch the training was stopped at, if stopped
+    pub fn stopped_epoch(&self) -> Option<usize> {
+        self.stopped_epoch
+    }
+}
+
+impl<T: NumT> Callback<T> for EarlyStopping<T> {
+    fn on_epoch_end(&mut self, logs: &EpochLogs<T>, model: &Sequential<T>) -> bool {
+        let loss = logs.monitored();
+        if improves(loss, self.best, self.min_delta) {
+            self.best = Some(loss);
+            self.best_epoch = Some(logs.epoch);
+            self.wait = 0;
+            if self.restore_best {
+                self.snapshot = Some(model.snapshot());
+            }
+            return true;
+        }
+        self.wait += 1;
+        if self.wait >= self.patience {
+            self.stopped_epoch = Some(logs.epoch);
+            return false;
+        }
+        true
+    }
+    fn on_train_end(&mut self, model: &mut Sequential<T>) {
+        if let Some(snapshot) = self.snapshot.take() {
+            model.load_snapshot(&snapshot).expect("the snapshot is of the trained model");
+        }
+    }
+}
+
+/// Save the model by `Sequential::save` whenever the monitored loss improves
+#[derive(Debug, Clone)]
+pub struct BestCheckpoint<T: NumT> {
+    pub path: PathBuf,
+    best: Option<T>,
+    best_epoch: Option<usize>,
+    error: Option<Arc<io::Error>>,
+}
+
+impl<T: NumT> BestCheckpoint<T> {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        BestCheckpoint::<T> { path: path.into(), best: None, best_epoch: None, error: None }
+    }
+    /// The best monitored loss and its epoch, that of the saved model
+    pub fn best(&self) -> Option<(usize, T)> {
+        self.best_epoch.zip(self.best)
+    }
+    /// The error of the save that stopped the training, if any
+    pub fn last_error(&self) -> Option<&io::Error> {
+        self.error.as_deref()
+    }
+}
+
+impl<T: NumT> Callback<T> for BestCheckpoint<T> {
+    /// A checkpoint that cannot be saved stops the training, see `last_error`
+    fn on_epoch_end(&mut self, logs: &EpochLogs<T>, model: &Sequential<T>) -> bool {
+        let loss = logs.monitored();
+        if !improves(loss, self.best, T::zero()) {
+            return true;
+        }
+        if let Err(e) = model.save(&self.path) {
+            self.error = Some(Arc::new(e));
+            return false;
+        }
+        self.error = None;
+        self.best = Some(loss);
+        self.best_epoch = Some(logs.epoch);
+        true
+    }
+}
+
+/// Print the losses and the duration of each epoch, and every `every_batches` batches
+#[derive(Debug, Clone)]
+pub struct ProgressReporter {
+    pub epochs: usize,
+    /// 0 for no report of batches
+    pub every_batches: usize,
+    start: Option<Instant>,
+}
+
+impl ProgressReporter {
+    /// A reporter of a training of `epochs` epochs
+    pub fn new(epochs: usize) -> Self {
+        ProgressReporter { epochs, every_batches: 0, start: None }
+    }
+    pub fn every_batches(mut self, every_batches: usize) -> Self {
+        self.every_batches = every_batches;
+        self
+    }
+}
+
+impl<T: NumT> Callback<T> for ProgressReporter {
+    fn on_epoch_begin(&mut self, _epoch: usize, _model: &Sequential<T>) {
+        self.start = Some(Instant::now());
+    }
+    fn on_batch_end(&mut self, batch: usize, loss: T, _model: &Sequential<T>) -> bool {
+        if self.every_batches > 0 && (batch + 1).is_multiple_of(self.every_batches) {
+            println!("  batch {}: loss {}", batch + 1, loss);
+        }
+        true
+    }
+    fn on_epoch_end(&mut self, logs: &EpochLogs<T>, _model: &Sequential<T>) -> bool {
+        let secs = self.start.map_or(0., |s| s.elapsed().as_secs_f64());
+        match logs.val_loss {
+            Some(val_loss) => println!("Epoch {}/{}: loss {}, val_loss {} ({:.2}s)", logs.epoch + 1, self.epochs, logs.loss, val_loss, secs),
+            None => println!("Epoch {}/{}: loss {} ({:.2}s)", logs.epoch + 1, self.epochs, logs.loss, secs),
+        }
+        true
+    }
+}
+
+/// The inputs and the truths of a validation set
+pub type Dataset<'a, T> = (&'a [Tensor<T>], &'a [Tensor<T>]);
+
+/// Call every callback, not stopping at the first one returning false, and return whether all go on
+fn every<T: NumT, F: FnMut(&mut dyn Callback<T>) -> bool>(callbacks: &mut [&mut dyn Callback<T>], mut f: F) -> bool {
+    let mut go = true;
+    for c in callbacks.iter_mut() {
+        go &= f(&mut **c);
+    }
+    go
+}
+
+impl<T: NumT> Sequential<T> {
+    /// Trains the model like `fit`, calling the callbacks in order and evaluating the
+    /// validation set, if given, after each epoch. The training stops early when a
+    /// callback returns false. Returns the logs of each trained epoch.
+    #[allow(clippy::too_many_arguments)]
+    pub fn fit_with_callbacks(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], validation: Option<Dataset<'_, T>>, epochs: usize, batch_size: usize, learning_rate: T, callbacks: &mut [&mut dyn Callback<T>]) -> Vec<EpochLogs<T>> {
+        let mut history = Vec::new();
+        for epoch in 0..epochs {
+            callbacks.iter_mut().for_each(|c| c.on_epoch_begin(epoch, self));
+            let outcome = self.train_once_observed(inputs, truths, batch_size, learning_rate, false, &mut |batch, loss, model| {
+                every(callbacks, |c| c.on_batch_end(batch, loss, model))
+            });
+            if outcome.cancelled {
+                break;
+            }
+            let logs = EpochLogs::<T> {
+                epoch,
+                loss: outcome.loss,
+                val_loss: validation.map(|(x, y)| self.evaluate(x, y)),
+            };
+            history.push(logs);
+            if !every(callbacks, |c| c.on_epoch_end(&logs, self)) {
+                break;
+            }
+        }
+        callbacks.iter_mut().for_each(|c| c.on_train_end(self));
+        history
+    }
+}
+
+#[test]
+fn test_callbacks() {
+    use crate::layers::dense::Dense;
+    let inputs: Vec<_> = (0..8).map(|i| Tensor::new(&Shape::new([1]), vec![i as f64 / 8.])).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(&Shape::new([1]), vec![2. * x.as_slice()[0] + 1.])).collect();
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([1]), &Shape::new([1]), Activation::No));
+
+    // a validation set the model cannot fit stops the training after the patience
+    let noise: Vec<_> = truths.iter().map(|y| Tensor::new(&Shape::new([1]), vec![-y.as_slice()[0]])).collect();
+    let path = std::env::temp_dir().join(format!("easynn_callbacks_{}.eznn", std::process::id()));
+    let mut stopping = EarlyStopping::new(2).restore_best(true);
+    let mut checkpoint = BestCheckpoint::new(&path);
+    let history = nn.fit_with_callbacks(&inputs, &truths, Some((&inputs, &noise)), 50, 2, 0.1, &mut [&mut stopping, &mut checkpoint]);
+    let (best_epoch, best) = stopping.best().unwrap();
+    assert_eq!(stopping.stopped_epoch(), Some(history.len() - 1));
+    assert_eq!(history.len(), best_epoch + 3);
+    assert_eq!(checkpoint.best(), Some((best_epoch, best)));
+    assert!(checkpoint.last_error().is_none());
+    // the best parameters are restored, those of the checkpoint
+    assert_eq!(nn.evaluate(&inputs, &noise), best);
+    assert_eq!(Sequential::<f64>::load(&path).unwrap().snapshot(), nn.snapshot());
+    std::fs::remove_file(&path).unwrap();
+
+    // a callback stops in the middle of an epoch
+    struct Batches(usize);
+    impl Callback<f64> for Batches {
+        fn on_batch_end(&mut self, _batch: usize, loss: f64, _model: &Sequential<f64>) -> bool {
+            assert!(loss.is_finite());
+            self.0 += 1;
+            self.0 < 6
+        }
+    }
+    let mut batches = Batches(0);
+    let history = nn.fit_with_callbacks(&inputs, &truths, None, 10, 2, 0.1, &mut [&mut batches, &mut ProgressReporter::new(10)]);
+    assert_eq!((history.len(), batches.0), (1, 6));
+    assert_eq!(history[0].val_loss, None);
+
+    // a checkpoint that cannot be saved stops at the first epoch and keeps the error
+    let mut broken = BestCheckpoint::new(std::env::temp_dir().join("easynn_no_such_dir").join("model.eznn"));
+    let history = nn.fit_with_callbacks(&inputs, &truths, None, 10, 2, 0.1, &mut [&mut broken]);
+    assert_eq!(history.len(), 1);
+    assert_eq!(broken.best(), None);
+    assert_eq!(broken.last_error().unwrap().kind(), std::io::ErrorKind::NotFound);
+}
@@ -0,0 +1,255 @@
+//! Cooperative cancellation, pausing and snapshots of training.
+//!
+//! A `TrainingControl` is shared (e.g. in an `Arc`) between the training loop and
+//! a ctrl-C handler, a UI or an evaluation thread. The loop checks it between batches,
+//! so the model is always left, and snapshotted, at a batch boundary.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::control::TrainingControl;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+//!     let inputs = vec![Tensor::new(sh!([1]), vec![1.]); 4];
+//!     let control = TrainingControl::new();
+//!     control.cancel();
+//!     let outcome = nn.train_once_controlled(&inputs, &inputs, 1, 0.1, false, &control, |_| ());
+//!     assert!(outcome.cancelled);
+//!     assert_eq!(outcome.batches, 0);
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use std::sync::{ Condvar, Mutex };
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::time::Duration;
+
+/// A copy of the parameters and of the state of a model
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot<T: NumT> {
+    /// The parameters of each layer, as listed by `Layer::parameters`
+    pub parameters: Vec<Vec<Vec<T>>>,
+    /// The state of each layer, e.g. running statistics, as listed by `Layer::state`
+    pub state: Vec<Vec<Vec<T>>>,
+}
+
+/// Whether the buffers of each layer have the lengths of the copies
+fn fits<T: NumT>(buffers: Vec<Vec<&[T]>>, copies: &[Vec<Vec<T>>]) -> bool {
+    buffers.len() == copies.len() && buffers.iter().zip(copies.iter()).all(|(b, c)| {
+        b.len() == c.len() && b.iter().zip(c.iter()).all(|(b, c)| b.len() == c.len())
+    })
+}
+
+impl<T: NumT> Sequential<T> {
+    /// Copy the current parameters and state
+    pub fn snapshot(&self) -> Snapshot<T> {
+        let copy = |buffers: Vec<&[T]>| buffers.iter().map(|p| p.to_vec()).collect();
+        Snapshot::<T> {
+            parameters: self.layers().iter().map(|l| copy(l.parameters())).collect(),
+            state: self.layers().iter().map(|l| copy(l.state())).collect(),
+        }
+    }
+    /// Load the parameters and the state of a snapshot of a model of the same architecture
+    pub fn load_snapshot(&mut self, snapshot: &Snapshot<T>) -> Result<()> {
+        if !fits(self.layers().iter().map(|l| l.parameters()).collect(), &snapshot.parameters)
+            || !fits(self.layers().iter().map(|l| l.state()).collect(), &snapshot.state) {
+            return Err(EasynnError::invalid("load_snapshot", "the snapshot is of another architecture"));
+        }
+        for ((l, p), s) in self.layers_mut().iter_mut().zip(snapshot.parameters.iter()).zip(snapshot.state.iter()) {
+            for (x, p) in l.parameters_mut().into_iter().zip(p.iter()) {
+                x.copy_from_slice(p);
+            }
+            for (x, s) in l.state_mut().into_iter().zip(s.iter()) {
+                x.copy_from_slice(s);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct TrainingControl<T: NumT = f64> {
+    cancelled: AtomicBool,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    snapshot_requested: AtomicBool,
+    snapshot: Mutex<Option<Snapshot<T>>>,
+    snapshot_ready: Condvar,
+}
+
+impl<T: NumT> Default for TrainingControl<T> {
+    fn default() -> Self {
+        TrainingControl::<T> {
+            cancelled: AtomicBool::new(false),
+            paused: Mutex::new(false),
+            resumed: Condvar::new(),
+            snapshot_requested: AtomicBool::new(false),
+            snapshot: Mutex::new(None),
+            snapshot_ready: Condvar::new(),
+        }
+    }
+}
+
+impl<T: NumT> TrainingControl<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Ask the training loop for a snapshot at the next batch boundary,
+    /// and wait for it at most `timeout`. Training goes on meanwhile.
+    pub fn snapshot(&self, timeout: Duration) -> Option<Snapshot<T>> {
+        let mut slot = self.snapshot.lock().unwrap();
+        *slot = None;
+        self.snapshot_requested.store(true, Ordering::SeqCst);
+        let (mut slot, _) = self.snapshot_ready.wait_timeout_while(slot, timeout, |s| s.is_none()).unwrap();
+        self.snapshot_requested.store(false, Ordering::SeqCst);
+        slot.take()
+    }
+    /// Stop the training at the next batch boundary, also if paused
+    pub fn cancel(&self) {
+        // Under the lock, a trainer about to wait either sees the flag or gets woken up
+        let _paused = self.paused.lock().unwrap();
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.resumed.notify_all();
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+    /// Pause the training at the next batch boundary
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap() = true;
+    }
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap() = false;
+        self.resumed.notify_all();
+    }
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap()
+    }
+    /// Called by the training loop between batches. Serves a requested snapshot,
+    /// then if paused, `on_pause` is called once (e.g. to flush a checkpoint)
+    /// before waiting to be resumed or cancelled. Returns whether the training should go on.
+    pub(crate) fn proceed<F: FnOnce(), S: Fn() -> Snapshot<T>>(&self, on_pause: F, snapshot: S) -> bool {
+        if self.is_cancelled() {
+            return false;
+        }
+        if self.snapshot_requested.swap(false, Ordering::SeqCst) {
+            *self.snapshot.lock().unwrap() = Some(snapshot());
+            self.snapshot_ready.notify_all();
+        }
+        let mut paused = self.paused.lock().unwrap();
+        if *paused {
+            drop(paused);
+            on_pause();
+            paused = self.paused.lock().unwrap();
+            while *paused && !self.is_cancelled() {
+                paused = self.resumed.wait(paused).unwrap();
+            }
+        }
+        !self.is_cancelled()
+    }
+}
+
+/// The result of an epoch that may be cancelled
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EpochOutcome<T: NumT> {
+    /// The mean loss of the trained batches
+    pub loss: T,
+    /// The regularization penalty of the model at the end, see `Sequential::penalty`
+    pub penalty: T,
+    /// The count of trained batches
+    pub batches: usize,
+    pub cancelled: bool,
+}
+
+#[test]
+fn test_training_control() {
+    use crate::prelude::*;
+    use std::sync::Arc;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+    let inputs = vec![Tensor::new(sh!([1]), vec![1.]); 4];
+    let truths = vec![Tensor::new(sh!([1]), vec![2.]); 4];
+
+    let control = Arc::new(TrainingControl::<f64>::new());
+    let outcome = nn.train_once_controlled(&inputs, &truths, 1, 0.1, false, &control, |_| ());
+    assert_eq!((outcome.batches, outcome.cancelled), (4, false));
+
+    // pause before the first batch, the checkpoint sees the model, then resume from another thread
+    control.pause();
+    let resumer = Arc::clone(&control);
+    let mut checkpoints = 0;
+    let outcome = nn.train_once_controlled(&inputs, &truths, 2, 0.1, false, &control, |m| {
+        checkpoints += 1;
+        assert_eq!(m.len(), 1);
+        let r = Arc::clone(&resumer);
+        std::thread::spawn(move || r.resume());
+    });
+    assert_eq!(checkpoints, 1);
+    assert_eq!((outcome.batches, outcome.cancelled), (2, false));
+
+    // cancel while paused
+    control.pause();
+    let canceller = Arc::clone(&control);
+    let outcome = nn.train_once_controlled(&inputs, &truths, 1, 0.1, false, &control, |_| {
+        let c = Arc::clone(&canceller);
+        std::thread::spawn(move || c.cancel());
+    });
+    assert_eq!((outcome.batches, outcome.cancelled), (0, true));
+
+    // a cancel racing the start of the wait is not missed
+    for _ in 0..200 {
+        let control = Arc::new(TrainingControl::<f64>::new());
+        control.pause();
+        let canceller = Arc::clone(&control);
+        let outcome = nn.train_once_controlled(&inputs, &truths, 1, 0.1, false, &control, |_| {
+            let c = Arc::clone(&canceller);
+            std::thread::spawn(move || c.cancel());
+        });
+        assert!(outcome.cancelled);
+    }
+}
+
+#[test]
+fn test_training_snapshot() {
+    use crate::prelude::*;
+    use std::sync::Arc;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+    let inputs = vec![Tensor::new(sh!([1]), vec![1.]); 50];
+    let truths = vec![Tensor::new(sh!([1]), vec![2.]); 50];
+
+    let control = Arc::new(TrainingControl::<f64>::new());
+    let observer = Arc::clone(&control);
+    let handle = std::thread::spawn(move || observer.snapshot(Duration::from_secs(10)));
+    // wait until the snapshot is requested, then train
+    while !control.snapshot_requested.load(Ordering::SeqCst) {
+        std::thread::yield_now();
+    }
+    nn.train_once_controlled(&inputs, &truths, 1, 0.1, false, &control, |_| ());
+    let snapshot = handle.join().unwrap().unwrap();
+    assert_eq!(snapshot.parameters.len(), 1);
+    assert_eq!(snapshot.parameters[0][0].len(), 1);
+
+    // the snapshot serves a copy of the model
+    let mut copy = Sequential::<f64>::new(Loss::MeanSquare);
+    copy.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+    copy.load_snapshot(&nn.snapshot()).unwrap();
+    assert_eq!(copy.predict(&inputs[0]).unwrap(), nn.predict(&inputs[0]).unwrap());
+    let mut other = Sequential::<f64>::new(Loss::MeanSquare);
+    other.add(Dense::new(sh!([2]), sh!([1]), Activation::No));
+    assert!(other.load_snapshot(&snapshot).is_err());
+    // no training, no snapshot
+    assert!(control.snapshot(Duration::from_millis(10)).is_none());
+
+    // the running statistics of a batch normalization are restored too
+    let mut bn = Sequential::<f64>::new(Loss::MeanSquare);
+    bn.add(crate::layers::batch_norm::BatchNorm::new(sh!([2, 2]), Activation::No));
+    let x: Vec<_> = (0..4).map(|i| Tensor::new(sh!([2, 2]), vec![i as f64, 1., -(i as f64), 3.])).collect();
+    let start = bn.snapshot();
+    assert_eq!(start.state[0].len(), 2);
+    bn.train_once_batched(&x, &x, 2, 0.1, false);
+    assert_ne!(bn.snapshot().state, start.state);
+    bn.load_snapshot(&start).unwrap();
+    assert_eq!(bn.snapshot(), start);
+}
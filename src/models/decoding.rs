@@ -0,0 +1,131 @@
+//! Decoding utilities for models producing a token distribution per step.
+//!
+//! The model is given as a closure taking the tokens so far and returning the
+//! probabilities of the next token, as a tensor of shape `[vocab_size]`.
+
+use crate::tensor::*;
+
+/// A decoded sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hypothesis<T: NumT> {
+    /// The tokens, including the given prefix
+    pub tokens: Vec<usize>,
+    /// The sum of the log probabilities of the generated tokens
+    pub log_prob: T,
+    /// The length normalized log probability, used for ranking
+    pub score: T,
+}
+
+/// Beam search decoding
+#[derive(Debug, Copy, Clone)]
+pub struct BeamSearch {
+    /// How many hypotheses are kept at each step
+    pub beam_width: usize,
+    /// The maximum count of generated tokens
+    pub max_len: usize,
+    /// The end of sequence token, a hypothesis ending with it is finished
+    pub eos: Option<usize>,
+    /// The score is `log_prob / len^length_penalty`, 0 disables normalization
+    pub length_penalty: f64,
+    /// Stop as soon as `beam_width` hypotheses are finished
+    pub early_stopping: bool,
+}
+
+impl BeamSearch {
+    pub fn new(beam_width: usize, max_len: usize) -> Self {
+        if beam_width == 0 {
+            panic!("The beam width should be positive!");
+        }
+        BeamSearch { beam_width, max_len, eos: None, length_penalty: 0., early_stopping: false }
+    }
+
+    fn hypothesis<T: NumT>(&self, tokens: Vec<usize>, log_prob: T, prefix_len: usize) -> Hypothesis<T> {
+        let len = T::from(tokens.len() - prefix_len).unwrap();
+        let score = if len > T::zero() {
+            log_prob / len.powf(T::from(self.length_penalty).unwrap())
+        } else {
+            log_prob
+        };
+        Hypothesis { tokens, log_prob, score }
+    }
+
+    /// Decode after the prefix, returning at most `beam_width` hypotheses, the best first
+    pub fn search<T: NumT, F: FnMut(&[usize]) -> Tensor<T>>(&self, prefix: &[usize], mut step: F) -> Vec<Hypothesis<T>> {
+        let mut beams = vec![(prefix.to_vec(), T::zero())];
+        let mut finished = Vec::<Hypothesis<T>>::new();
+        for _ in 0..self.max_len {
+            let mut candidates = Vec::<(Vec<usize>, T)>::new();
+            for (tokens, log_prob) in &beams {
+                let probs = step(tokens);
+                for (tok, p) in probs.flattened.iter().enumerate() {
+                    if *p > T::zero() {
+                        let mut next = tokens.clone();
+                        next.push(tok);
+                        candidates.push((next, *log_prob + p.ln()));
+                    }
+                }
+            }
+            candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+            beams.clear();
+            for (rank, (tokens, log_prob)) in candidates.into_iter().enumerate() {
+                if beams.len() == self.beam_width {
+                    break;
+                }
+                if self.eos.is_some() && tokens.last() == self.eos.as_ref() {
+                    // only the finished ones ranking in the beam count
+                    if rank < self.beam_width {
+                        finished.push(self.hypothesis(tokens, log_prob, prefix.len()));
+                    }
+                } else {
+                    beams.push((tokens, log_prob));
+                }
+            }
+            if beams.is_empty() || (self.early_stopping && finished.len() >= self.beam_width) {
+                break;
+            }
+        }
+        if finished.len() < self.beam_width {
+            for (tokens, log_prob) in beams {
+                finished.push(self.hypothesis(tokens, log_prob, prefix.len()));
+            }
+        }
+        finished.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal));
+        finished.truncate(self.beam_width);
+        finished
+    }
+}
+
+#[test]
+fn test_beam_search() {
+    // token 0 is eos, greedy picks 1 first but 2 then eos is more likely
+    let step = |tokens: &[usize]| -> Tensor<f64> {
+        match tokens.last() {
+            Some(1) => Tensor::new(&Shape::new([3]), vec![0.3, 0.35, 0.35]),
+            Some(2) => Tensor::new(&Shape::new([3]), vec![0.9, 0.05, 0.05]),
+            _ => Tensor::new(&Shape::new([3]), vec![0., 0.6, 0.4]),
+        }
+    };
+    let mut greedy = BeamSearch::new(1, 2);
+    greedy.eos = Some(0);
+    assert_eq!(greedy.search(&[], step)[0].tokens[0], 1);
+
+    let mut beam = BeamSearch::new(2, 2);
+    beam.eos = Some(0);
+    let hyps = beam.search(&[], step);
+    assert_eq!(hyps[0].tokens, vec![2, 0]);
+    assert!((hyps[0].log_prob - (0.4_f64 * 0.9).ln()).abs() < 1e-12);
+    assert_eq!(hyps.len(), 2);
+
+    // normalizing by length favours the longer hypotheses
+    let mut normalized = BeamSearch::new(2, 3);
+    normalized.length_penalty = 1.;
+    let hyps = normalized.search(&[2], step);
+    assert!(hyps.iter().all(|h| h.tokens.len() == 4));
+    assert!((hyps[0].score - hyps[0].log_prob / 3.).abs() < 1e-12);
+
+    // NaN scores do not panic the ranking
+    normalized.length_penalty = f64::NAN;
+    let hyps = normalized.search(&[2], |_: &[usize]| Tensor::new(&Shape::new([2]), vec![f64::INFINITY, f64::INFINITY]));
+    assert_eq!(hyps.len(), 2);
+    assert!(hyps.iter().all(|h| h.score.is_nan()));
+}
@@ -0,0 +1,157 @@
+//! Concept-drift detection on a stream of losses.
+//!
+//! A detector is fed the loss of each step and signals when the stream has
+//! changed, i.e. when the model should be refreshed or the learning rate raised.
+//! `OnlineLearner::partial_fit_watched` does the latter on its own.
+//!
+//! ```rust
+//!     use easynn::models::drift::{ DriftDetector, PageHinkley };
+//!     let mut ph = PageHinkley::<f64>::new(0.005, 1.);
+//!     assert!((0..100).all(|_| !ph.update(0.1)));
+//!     assert!((0..100).any(|_| ph.update(0.5)));
+//! ```
+
+use crate::tensor::*;
+use std::collections::VecDeque;
+
+/// Called with the loss of each step, returns whether a drift is detected
+pub trait DriftDetector<T: NumT> {
+    fn update(&mut self, loss: T) -> bool;
+    /// Forget the stream seen, after the model has adapted to the drift
+    fn reset(&mut self);
+}
+
+/// The Page-Hinkley test for an increase of the mean loss
+#[derive(Debug, Clone)]
+pub struct PageHinkley<T: NumT> {
+    /// The magnitude of changes tolerated
+    pub delta: T,
+    /// The threshold of the cumulative deviation to signal a drift
+    pub lambda: T,
+    count: usize,
+    mean: T,
+    cum: T,
+    min_cum: T,
+}
+
+impl<T: NumT> PageHinkley<T> {
+    pub fn new(delta: T, lambda: T) -> Self {
+        PageHinkley::<T> { delta, lambda, count: 0, mean: T::zero(), cum: T::zero(), min_cum: T::zero() }
+    }
+}
+
+impl<T: NumT> DriftDetector<T> for PageHinkley<T> {
+    fn update(&mut self, loss: T) -> bool {
+        self.count += 1;
+        self.mean += (loss - self.mean) / T::from(self.count).unwrap();
+        self.cum += loss - self.mean - self.delta;
+        self.min_cum = self.min_cum.min(self.cum);
+        if self.cum - self.min_cum > self.lambda {
+            self.reset();
+            return true;
+        }
+        false
+    }
+    fn reset(&mut self) {
+        *self = PageHinkley::new(self.delta, self.lambda);
+    }
+}
+
+/// ADWIN, the adaptive window: the oldest part of the window is dropped when its mean
+/// differs from that of the newest part by more than the Hoeffding bound of confidence `delta`.
+/// The bound assumes the losses are within `[0, 1]`.
+#[derive(Debug, Clone)]
+pub struct Adwin<T: NumT> {
+    pub delta: T,
+    /// The longest window kept, bounding the cost of each update, at least 2 to compare two parts
+    pub max_window: usize,
+    window: VecDeque<T>,
+}
+
+impl<T: NumT> Adwin<T> {
+    pub fn new(delta: T, max_window: usize) -> Self {
+        if max_window < 2 {
+            panic!("Adwin needs a window of at least 2 losses!");
+        }
+        Adwin::<T> { delta, max_window, window: VecDeque::new() }
+    }
+    pub fn window_len(&self) -> usize {
+        self.window.len()
+    }
+    /// The mean of the window, the current estimate of the loss
+    pub fn mean(&self) -> T {
+        self.window.iter().copied().sum::<T>() / T::from(self.window.len().max(1)).unwrap()
+    }
+    /// The count of oldest items to drop, 0 if the window is consistent
+    fn cut(&self) -> usize {
+        let n = self.window.len();
+        if n < 2 {
+            return 0;
+        }
+        let total = self.window.iter().copied().sum::<T>();
+        let ln = (T::from(4 * n).unwrap() / self.delta).ln();
+        let two = T::one() + T::one();
+        let mut head = T::zero();
+        for (i, x) in self.window.iter().take(n - 1).enumerate() {
+            head += *x;
+            let (n0, n1) = (T::from(i + 1).unwrap(), T::from(n - i - 1).unwrap());
+            let m = T::one() / (T::one() / n0 + T::one() / n1);
+            let eps = (ln / (two * m)).sqrt();
+            if (head / n0 - (total - head) / n1).abs() > eps {
+                return i + 1;
+            }
+        }
+        0
+    }
+}
+
+impl<T: NumT> DriftDetector<T> for Adwin<T> {
+    fn update(&mut self, loss: T) -> bool {
+        self.window.push_back(loss);
+        if self.window.len() > self.max_window {
+            self.window.pop_front();
+        }
+        let mut drifted = false;
+        loop {
+            let cut = self.cut();
+            if cut == 0 {
+                break;
+            }
+            self.window.drain(..cut);
+            drifted = true;
+        }
+        drifted
+    }
+    fn reset(&mut self) {
+        self.window.clear();
+    }
+}
+
+#[test]
+fn test_drift_detectors() {
+    let mut ph = PageHinkley::<f64>::new(0.01, 2.);
+    for i in 0..500 {
+        assert!(!ph.update(0.2 + 0.05 * ((i % 7) as f64 - 3.) / 3.));
+    }
+    let detected = (0..100).position(|_| ph.update(0.6)).unwrap();
+    assert!(detected < 20);
+
+    let mut adwin = Adwin::<f64>::new(0.002, 1000);
+    for i in 0..300 {
+        assert!(!adwin.update(if i % 2 == 0 { 0.1 } else { 0.3 }));
+    }
+    assert_eq!(adwin.window_len(), 300);
+    let detected = (0..200).position(|_| adwin.update(0.9)).unwrap();
+    assert!(detected < 50);
+    // the old regime is dropped
+    assert!(adwin.window_len() < 100);
+    assert!(adwin.mean() > 0.5);
+    // a window shrunk below 2 losses has nothing to compare
+    for max_window in [0, 1] {
+        let mut small = Adwin::<f64>::new(0.002, 2);
+        small.max_window = max_window;
+        assert!((0..3).all(|_| !small.update(0.5)));
+        assert_eq!(small.window_len(), max_window);
+    }
+    assert!(std::panic::catch_unwind(|| Adwin::<f64>::new(0.002, 1)).is_err());
+}
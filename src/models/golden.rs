@@ -0,0 +1,159 @@
+//! Golden-file regression tests of models: the outputs of a model on stored inputs are
+//! compared with stored golden outputs, catching numerical changes of kernels or refactorings.
+//!
+//! A golden file is a `.npz` archive of the tensors `input_0`, `output_0`, `input_1`, ...
+//! `record_golden` writes it from the current outputs of a model, `check_golden` runs
+//! the model on the inputs and reports the outputs out of the tolerances. A test would
+//! check a model saved by `Sequential::save` with `assert_golden`. Running it with
+//! `EASYNN_BLESS=1` rewrites the golden file instead, after an intended change.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::golden::*;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([2]), sh!([3]), Activation::Tanh));
+//!     let inputs = vec![Tensor::new(sh!([2]), vec![0.5, -1.]), Tensor::new(sh!([2]), vec![2., 0.])];
+//!     let path = std::env::temp_dir().join("easynn_golden_doc.npz");
+//!     record_golden(&nn, &inputs, &path).unwrap();
+//!     assert!(check_golden(&nn, &path, 1e-9, 0.).unwrap().is_ok());
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use crate::tensor::npy::{ load_npz, save_npz };
+
+use std::fmt;
+use std::io::{ Error, ErrorKind, Result };
+use std::path::Path;
+
+/// A golden output the model does not reproduce
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenMismatch {
+    /// The index of the stored input
+    pub case: usize,
+    pub message: String,
+}
+
+/// The outcome of `check_golden`
+#[derive(Debug, Clone, PartialEq)]
+pub struct GoldenReport {
+    /// The count of stored inputs
+    pub cases: usize,
+    pub mismatches: Vec<GoldenMismatch>,
+}
+
+impl GoldenReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} golden outputs differ", self.mismatches.len(), self.cases)?;
+        for m in self.mismatches.iter() {
+            write!(f, "\n  case {}: {}", m.case, m.message)?;
+        }
+        Ok(())
+    }
+}
+
+fn predict<T: NumT + 'static>(model: &Sequential<T>, input: &Tensor<T>) -> Result<Tensor<T>> {
+    model.predict(input).map_err(Error::other)
+}
+
+/// Run the model on the inputs and store the inputs and the outputs as the golden file
+pub fn record_golden<T: NumT + 'static, P: AsRef<Path>>(model: &Sequential<T>, inputs: &[Tensor<T>], path: P) -> Result<()> {
+    let outputs = inputs.iter().map(|x| predict(model, x)).collect::<Result<Vec<_>>>()?;
+    let names: Vec<(String, String)> = (0..inputs.len()).map(|i| (format!("input_{}", i), format!("output_{}", i))).collect();
+    let mut tensors = Vec::new();
+    for ((input_name, output_name), (x, y)) in names.iter().zip(inputs.iter().zip(outputs.iter())) {
+        tensors.push((input_name.as_str(), x));
+        tensors.push((output_name.as_str(), y));
+    }
+    save_npz(&tensors, path)
+}
+
+/// Where the output differs from the golden one beyond `atol + rtol * |golden|`, if anywhere
+fn compare<T: NumT>(output: &Tensor<T>, golden: &Tensor<T>, rtol: T, atol: T) -> Option<String> {
+    if output.get_shape() != golden.get_shape() {
+        return Some(format!("the output is of shape {:?}, the golden one of {:?}", output.get_shape().dims(), golden.get_shape().dims()));
+    }
+    let (pos, diff) = output.as_slice().iter().zip(golden.as_slice()).map(|(a, b)| (*a - *b).abs() - rtol * b.abs())
+        .enumerate().fold((0, T::neg_infinity()), |best, (i, d)| if d > best.1 || (d.is_nan() && !best.1.is_nan()) { (i, d) } else { best });
+    if diff <= atol {
+        return None;
+    }
+    let (a, b) = (output.as_slice()[pos], golden.as_slice()[pos]);
+    Some(format!("element {} is {} but the golden one is {}, off by {} beyond the tolerance", pos, a, b, (a - b).abs()))
+}
+
+/// Run the model on the inputs of the golden file and compare the outputs with the golden ones
+pub fn check_golden<T: NumT + 'static, P: AsRef<Path>>(model: &Sequential<T>, path: P, rtol: T, atol: T) -> Result<GoldenReport> {
+    let tensors = load_npz::<T, _>(path)?;
+    let find = |name: String| tensors.iter().find(|(n, _)| *n == name).map(|(_, t)| t)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("the golden file has no {}", name)));
+    let cases = tensors.iter().filter(|(n, _)| n.starts_with("input_")).count();
+    let mut mismatches = Vec::new();
+    for case in 0..cases {
+        let (input, golden) = (find(format!("input_{}", case))?, find(format!("output_{}", case))?);
+        let message = match model.predict(input) {
+            Ok(output) => compare(&output, golden, rtol, atol),
+            Err(e) => Some(e.to_string()),
+        };
+        if let Some(message) = message {
+            mismatches.push(GoldenMismatch { case, message });
+        }
+    }
+    Ok(GoldenReport { cases, mismatches })
+}
+
+/// Check the model saved at `model_path` against the golden file, panicking with the
+/// report on a mismatch; with `EASYNN_BLESS` set, record the golden file instead
+pub fn assert_golden<T: NumT + 'static, P: AsRef<Path>, Q: AsRef<Path>>(model_path: P, golden_path: Q, rtol: T, atol: T) {
+    let model = Sequential::<T>::load(&model_path)
+        .unwrap_or_else(|e| panic!("cannot load the model {}: {}", model_path.as_ref().display(), e));
+    let golden_path = golden_path.as_ref();
+    if std::env::var_os("EASYNN_BLESS").is_some() {
+        let inputs = load_npz::<T, _>(golden_path).map(|tensors| tensors.into_iter()
+            .filter(|(n, _)| n.starts_with("input_")).map(|(_, t)| t).collect::<Vec<_>>())
+            .unwrap_or_else(|e| panic!("cannot read the inputs of {}: {}", golden_path.display(), e));
+        record_golden(&model, &inputs, golden_path).unwrap_or_else(|e| panic!("cannot bless {}: {}", golden_path.display(), e));
+        return;
+    }
+    let report = check_golden(&model, golden_path, rtol, atol)
+        .unwrap_or_else(|e| panic!("cannot read the golden file {}: {}", golden_path.display(), e));
+    if !report.is_ok() {
+        panic!("{} against {}: {}", model_path.as_ref().display(), golden_path.display(), report);
+    }
+}
+
+#[test]
+fn test_golden() {
+    use crate::layers::dense::Dense;
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([3]), &Shape::new([2]), Activation::Sigmoid));
+    let inputs: Vec<_> = (0..3).map(|i| Tensor::new(&Shape::new([3]), vec![i as f64, 1., -0.5])).collect();
+    let dir = std::env::temp_dir().join(format!("easynn_golden_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (model_path, golden_path) = (dir.join("model.eznn"), dir.join("golden.npz"));
+    nn.save(&model_path).unwrap();
+    record_golden(&nn, &inputs, &golden_path).unwrap();
+    let report = check_golden(&nn, &golden_path, 0., 0.).unwrap();
+    assert_eq!(report, GoldenReport { cases: 3, mismatches: vec![] });
+    assert_golden::<f64, _, _>(&model_path, &golden_path, 0., 0.);
+
+    // a drift of the bias shows in every case, within the tolerance or not
+    nn.layers_mut()[0].parameters_mut()[1][1] += 1e-6;
+    assert!(check_golden(&nn, &golden_path, 0., 1e-6).unwrap().is_ok());
+    let report = check_golden(&nn, &golden_path, 1e-9, 1e-9).unwrap();
+    assert_eq!(report.mismatches.iter().map(|m| m.case).collect::<Vec<_>>(), [0, 1, 2]);
+    assert!(report.mismatches[0].message.starts_with("element 1 is"));
+    assert!(report.to_string().starts_with("3 of 3 golden outputs differ\n  case 0: element 1"));
+
+    // a model of other outputs differs in shape
+    let mut other = Sequential::<f64>::new(Loss::MeanSquare);
+    other.add(Dense::new(&Shape::new([3]), &Shape::new([4]), Activation::No));
+    assert!(check_golden(&other, &golden_path, 1., 1.).unwrap().mismatches[2].message.contains("shape [4]"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
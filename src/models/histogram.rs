@@ -0,0 +1,113 @@
+//! Histograms of the parameters and the gradients of each layer, for dashboards.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([4]), sh!([2]), Activation::Relu));
+//!     let hist = nn.weight_histograms(10);
+//!     // 8 weights and 2 biases
+//!     assert_eq!(hist[0].weights.counts.iter().sum::<usize>(), 10);
+//!     assert!(hist[0].gradients.is_none());
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+
+/// The counts of values in `bins` equal-width bins between `min` and `max`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram<T: NumT> {
+    pub min: T,
+    pub max: T,
+    pub counts: Vec<usize>,
+}
+
+impl<T: NumT> Histogram<T> {
+    pub fn new<'a, I: Iterator<Item = &'a T> + Clone>(values: I, bins: usize) -> Self where T: 'a {
+        if bins == 0 {
+            panic!("A histogram needs at least one bin!");
+        }
+        let min = values.clone().fold(T::infinity(), |m, x| m.min(*x));
+        let max = values.clone().fold(T::neg_infinity(), |m, x| m.max(*x));
+        let mut counts = vec![0; bins];
+        if min > max {
+            return Histogram::<T> { min: T::zero(), max: T::zero(), counts };
+        }
+        let width = (max - min) / T::from(bins).unwrap();
+        for x in values {
+            let bin = if width > T::zero() { ((*x - min) / width).to_usize().unwrap_or(0) } else { 0 };
+            counts[bin.min(bins - 1)] += 1;
+        }
+        Histogram::<T> { min, max, counts }
+    }
+    /// The `bins + 1` edges of the bins
+    pub fn edges(&self) -> Vec<T> {
+        let bins = T::from(self.counts.len()).unwrap();
+        (0..=self.counts.len()).map(|i| self.min + (self.max - self.min) * T::from(i).unwrap() / bins).collect()
+    }
+    /// The counts as a `[bins]` tensor
+    pub fn to_tensor(&self) -> Tensor<T> {
+        Tensor::new(&Shape::new([self.counts.len()]), self.counts.iter().map(|c| T::from(*c).unwrap()).collect())
+    }
+}
+
+/// The histograms of a layer, over all its parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerHistograms<T: NumT> {
+    /// The index of the layer in the model
+    pub layer: usize,
+    pub weights: Histogram<T>,
+    /// The mean gradient over a batch, if a batch was given
+    pub gradients: Option<Histogram<T>>,
+}
+
+impl<T: NumT> Sequential<T> {
+    /// The histograms of the parameters of each layer with parameters
+    pub fn weight_histograms(&self, bins: usize) -> Vec<LayerHistograms<T>> {
+        self.layers().iter().enumerate().filter(|(_, l)| l.get_weight_count() > 0).map(|(i, l)| {
+            let params = l.parameters();
+            LayerHistograms::<T> {
+                layer: i,
+                weights: Histogram::new(params.iter().flat_map(|p| p.iter()), bins),
+                gradients: None,
+            }
+        }).collect()
+    }
+    /// The histograms of the parameters of each layer with parameters,
+    /// and of their mean gradient on the batch
+    pub fn weight_histograms_with_gradients(&self, bins: usize, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<LayerHistograms<T>>> {
+        let (mut cum_dw, mut cum_db) = self.accumulators();
+        for (input, truth) in inputs.iter().zip(truths.iter()) {
+            let (deltas, mut outputs) = self.propagate_sample(input, truth)?;
+            outputs.pop();
+            self.update_delta_da(&mut cum_dw, &mut cum_db, &deltas, &outputs);
+        }
+        let n = T::from(inputs.len().max(1)).unwrap();
+        let mut hist = self.weight_histograms(bins);
+        for h in &mut hist {
+            let grads: Vec<T> = cum_dw[h.layer].iter().chain(cum_db[h.layer].flattened.iter()).map(|g| *g / n).collect();
+            h.gradients = Some(Histogram::new(grads.iter(), bins));
+        }
+        Ok(hist)
+    }
+}
+
+#[test]
+fn test_histograms() {
+    use crate::prelude::*;
+
+    let h = Histogram::new([0., 1., 1.5, 4.].iter(), 4);
+    assert_eq!(h.counts, vec![1, 2, 0, 1]);
+    assert_eq!(h.edges(), vec![0., 1., 2., 3., 4.]);
+    assert_eq!(h.to_tensor().flattened, vec![1., 2., 0., 1.]);
+    assert_eq!(Histogram::new([2., 2.].iter(), 3).counts, vec![2, 0, 0]);
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(sh!([3]), sh!([1, 2]), Activation::Tanh));
+    nn.add(MeanOverTime::new(sh!([1, 2])));
+    let hist = nn.weight_histograms_with_gradients(
+        5, &[Tensor::new(sh!([3]), vec![1., 2., 3.])], &[Tensor::new(sh!([2]), vec![1., 1.])]
+    ).unwrap();
+    // the pooling layer has no parameters
+    assert_eq!(hist.len(), 1);
+    assert_eq!(hist[0].gradients.as_ref().unwrap().counts.iter().sum::<usize>(), 8);
+}
@@ -0,0 +1,52 @@
+//! Inference executor compiled from a model.
+//!
+//! The executor freezes the layer shapes and preallocates every intermediate
+//! output, so repeated predictions do not allocate.
+
+use crate::layers::*;
+
+/// An executor for repeated inference, created by `compile_for_inference`
+pub struct InferenceExecutor<'a, T: NumT> {
+    layers: Vec<&'a dyn Layer<T>>,
+    input_shape: Shape,
+    buffers: Vec<Tensor<T>>,
+}
+
+impl<'a, T: NumT> InferenceExecutor<'a, T> {
+    /// Freeze the shapes of the layers and preallocate the outputs of each of them,
+    /// the output shape of each layer must match the input shape of the next one
+    pub fn new(layers: Vec<&'a dyn Layer<T>>) -> Result<Self> {
+        let input_shape = match layers.first() {
+            Some(l) => l.get_input_shape(),
+            None => return Err(EasynnError::invalid("InferenceExecutor::new", "there are no layers")),
+        };
+        let mut buffers = Vec::<Tensor<T>>::new();
+        let mut last_shape = input_shape.clone();
+        for layer in &layers {
+            check_shape("InferenceExecutor::new", &last_shape, &layer.get_input_shape())?;
+            last_shape = layer.get_output_shape();
+            buffers.push(Tensor::<T>::zeros(&last_shape));
+        }
+        Ok(InferenceExecutor::<T> { layers, input_shape, buffers })
+    }
+
+    /// The frozen input shape
+    pub fn get_input_shape(&self) -> &Shape {
+        &self.input_shape
+    }
+
+    /// Run the layers on the input, the returned output is valid until the next run
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "inference", level = "trace", skip_all))]
+    pub fn run(&mut self, input: &Tensor<T>) -> Result<&Tensor<T>> {
+        check_shape("run", &self.input_shape, &input.shape)?;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let (done, rest) = self.buffers.split_at_mut(i);
+            let last_output = match done.last() {
+                Some(o) => o,
+                None => input,
+            };
+            layer.forward_propagate_into(last_output, &mut rest[0]).map_err(at_layer(i, *layer))?;
+        }
+        Ok(self.buffers.last().unwrap())
+    }
+}
@@ -0,0 +1,188 @@
+//! A synthetic load generator for capacity planning of model serving.
+//!
+//! Requests of random inputs arrive as a Poisson process of the given rate (open loop),
+//! or back to back (closed loop), and are served by a number of worker threads calling
+//! the handler. The latency of a request counts from its scheduled arrival, so that
+//! the time waiting in the queue of an overloaded server is included.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::loadgen::*;
+//!     let mut nn = Sequential::<f32>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([16]), sh!([4]), Activation::Relu));
+//!     let config = LoadConfig::new(sh!([16]), 200).rate(2000.).workers(2).batch_sizes(&[1, 8]);
+//!     let report = run_load(&config, |_, batch: &[Tensor<f32>]| {
+//!         for x in batch {
+//!             nn.predict(x).unwrap();
+//!         }
+//!     });
+//!     assert_eq!(report.requests, 200);
+//!     println!("{}", report);
+//! ```
+
+use crate::tensor::*;
+
+extern crate crossbeam;
+
+use rand::Rng;
+use std::fmt;
+use std::time::{ Duration, Instant };
+
+/// The load to generate
+#[derive(Debug, Clone)]
+pub struct LoadConfig {
+    /// The shape of each sample
+    pub input_shape: Shape,
+    /// The count of requests sent
+    pub requests: usize,
+    /// The mean arrivals per second, None to send each request once a worker is free
+    pub rate: Option<f64>,
+    /// The threads serving the requests
+    pub workers: usize,
+    /// The samples in a request are drawn uniformly from these sizes
+    pub batch_sizes: Vec<usize>,
+}
+
+impl LoadConfig {
+    /// A closed loop of single-sample requests on one worker
+    pub fn new(input_shape: &Shape, requests: usize) -> Self {
+        LoadConfig { input_shape: input_shape.clone(), requests, rate: None, workers: 1, batch_sizes: vec![1] }
+    }
+    pub fn rate(mut self, rate: f64) -> Self {
+        if rate.is_nan() || rate <= 0. {
+            panic!("The request rate should be positive!");
+        }
+        self.rate = Some(rate);
+        self
+    }
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+    pub fn batch_sizes(mut self, sizes: &[usize]) -> Self {
+        if sizes.is_empty() {
+            panic!("At least one batch size is needed!");
+        }
+        self.batch_sizes = sizes.to_vec();
+        self
+    }
+}
+
+/// The latencies of a load run, sorted ascending
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub requests: usize,
+    pub samples: usize,
+    /// From the first arrival to the last completion
+    pub elapsed: Duration,
+    pub latencies: Vec<Duration>,
+}
+
+impl LoadReport {
+    /// The latency under which `p` percent of the requests completed, by nearest rank
+    pub fn percentile(&self, p: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (p / 100. * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+    pub fn mean(&self) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        self.latencies.iter().sum::<Duration>() / self.latencies.len() as u32
+    }
+    /// The served requests per second
+    pub fn throughput(&self) -> f64 {
+        self.requests as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "requests:   {} ({} samples) in {:.3?}", self.requests, self.samples, self.elapsed)?;
+        writeln!(f, "throughput: {:.1} req/s", self.throughput())?;
+        writeln!(f, "latency:    mean {:.3?}, p50 {:.3?}, p90 {:.3?}, p99 {:.3?}, max {:.3?}",
+            self.mean(), self.percentile(50.), self.percentile(90.), self.percentile(99.), self.percentile(100.))?;
+        Ok(())
+    }
+}
+
+/// Drive the handler with the configured load, it is given the worker index and the batch of a request
+pub fn run_load<T, F>(config: &LoadConfig, handler: F) -> LoadReport
+where
+    T: NumT,
+    F: Fn(usize, &[Tensor<T>]) + Sync,
+{
+    let (tx, rx) = crossbeam::channel::bounded::<(Instant, Vec<Tensor<T>>)>(config.workers);
+    let (done_tx, done_rx) = crossbeam::channel::unbounded::<(Duration, usize)>();
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for worker in 0..config.workers {
+            let (rx, done_tx, handler) = (rx.clone(), done_tx.clone(), &handler);
+            scope.spawn(move || {
+                for (arrival, batch) in rx {
+                    handler(worker, &batch);
+                    done_tx.send((arrival.elapsed(), batch.len())).unwrap();
+                }
+            });
+        }
+        drop(done_tx);
+
+        let mut rng = rand::thread_rng();
+        let mut next = start;
+        for _ in 0..config.requests {
+            let size = config.batch_sizes[rng.gen_range(0..config.batch_sizes.len())];
+            let batch = (0..size).map(|_| {
+                Tensor::new(&config.input_shape, (0..config.input_shape.size()).map(|_| T::from(rng.gen_range(-1.0..1.0)).unwrap()).collect())
+            }).collect();
+            let arrival = match config.rate {
+                Some(rate) => {
+                    // exponential inter-arrival times
+                    next += Duration::from_secs_f64(-(1. - rng.gen::<f64>()).ln() / rate);
+                    std::thread::sleep(next.saturating_duration_since(Instant::now()));
+                    next
+                },
+                None => Instant::now(),
+            };
+            tx.send((arrival, batch)).unwrap();
+        }
+        drop(tx);
+    });
+    let elapsed = start.elapsed();
+    let mut latencies = Vec::with_capacity(config.requests);
+    let mut samples = 0;
+    for (latency, size) in done_rx {
+        latencies.push(latency);
+        samples += size;
+    }
+    latencies.sort();
+    LoadReport { requests: latencies.len(), samples, elapsed, latencies }
+}
+
+#[test]
+fn test_run_load() {
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    let served = AtomicUsize::new(0);
+    let config = LoadConfig::new(&Shape::new([3]), 50).workers(3).batch_sizes(&[2, 5]);
+    let report = run_load(&config, |worker, batch: &[Tensor<f64>]| {
+        assert!(worker < 3);
+        assert!(batch.len() == 2 || batch.len() == 5);
+        assert!(batch.iter().all(|x| x.get_shape() == &Shape::new([3])));
+        served.fetch_add(batch.len(), Ordering::Relaxed);
+        std::thread::sleep(Duration::from_micros(200));
+    });
+    assert_eq!(report.requests, 50);
+    assert_eq!(report.samples, served.load(Ordering::Relaxed));
+    assert!(report.percentile(50.) <= report.percentile(99.));
+    assert!(report.percentile(0.) >= Duration::from_micros(200));
+    assert_eq!(report.percentile(100.), *report.latencies.last().unwrap());
+
+    // an open loop at 1000 req/s takes about 20 ms for 20 requests
+    let config = LoadConfig::new(&Shape::new([1]), 20).rate(1000.);
+    let report = run_load(&config, |_, _: &[Tensor<f32>]| {});
+    assert_eq!(report.requests, 20);
+    assert!(report.elapsed > Duration::from_millis(2));
+}
@@ -2,17 +2,68 @@
 //!
 
 use crate::tensor::*;
-type Result<T> = std::result::Result<T, ShapeMismatchError>;
+use crate::tensor::num::log_add;
+use crate::layers::activation::Activation;
+use std::ops::Deref;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// The most quantile levels of a pinball loss
+pub const MAX_QUANTILES: usize = 16;
+
+/// The quantile levels of a pinball loss, held by value so that `Loss` stays `Copy`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Quantiles {
+    levels: [f64; MAX_QUANTILES],
+    len: usize,
+}
+
+impl Quantiles {
+    /// The levels, at most `MAX_QUANTILES` of them
+    pub fn new(levels: &[f64]) -> Result<Self> {
+        if levels.len() > MAX_QUANTILES {
+            return Err(EasynnError::invalid("Quantiles::new", format!("{} levels are more than {}", levels.len(), MAX_QUANTILES)));
+        }
+        let mut ret = Quantiles { levels: [0.; MAX_QUANTILES], len: levels.len() };
+        ret.levels[..levels.len()].copy_from_slice(levels);
+        Ok(ret)
+    }
+}
+
+impl Deref for Quantiles {
+    type Target = [f64];
+    fn deref(&self) -> &[f64] {
+        &self.levels[..self.len]
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 pub enum Loss {
     MeanSquare,
+    MeanAbsolute,
+    /// Binary cross-entropy of probabilities in `(0, 1)`, clamped away from 0 and 1,
+    /// prefer `SigmoidCrossEntropy` on logits for stability
+    BinaryCrossEntropy,
+    /// Categorical cross-entropy fused with the softmax over the last axis:
+    /// the output is the logits and the truth is the class probabilities, e.g. one-hot.
+    /// The loss is summed over the classes and averaged over the other axes
+    SoftmaxCrossEntropy,
+    /// Connectionist Temporal Classification with the given blank class,
+    /// the output is `[time, classes]` probabilities and the truth is the label sequence
+    Ctc(usize),
+    /// Pinball (quantile) loss of the given quantile levels, the output is `[..., quantiles]`
+    /// predicting each quantile of a truth of shape `[...]`, see `quantile_shape`
+    Pinball(Quantiles),
+    /// Binary cross-entropy of independent labels, the output is the logits
+    /// (apply the sigmoid to get the probabilities) and the truth is 0 or 1 per label
+    SigmoidCrossEntropy,
+    /// Soft Dice loss of segmentation: the output is the `[classes, ...]` probabilities
+    /// and the truth is one-hot over the first axis, the loss is 1 minus the Dice
+    /// coefficient of each class, macro-averaged
+    Dice,
 }
 
 fn mse<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
-    if output.shape != truth.shape {
-        return Err(ShapeMismatchError);
-    }
+    check_shape("mse", &truth.shape, &output.shape)?;
     let mut ret = T::zero();
     let len = T::from(output.shape.size()).unwrap();
     for (o, t) in output.flattened.iter().zip(truth.flattened.iter()) {
@@ -22,9 +73,7 @@ fn mse<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
 }
 
 fn dmse<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
-    if output.shape != truth.shape {
-        return Err(ShapeMismatchError);
-    }
+    check_shape("dmse", &truth.shape, &output.shape)?;
     let mut ret = Tensor::<T>::zeros(&truth.shape);
     let len = T::from(output.shape.size()).unwrap();
     for (r, (o, t)) in ret.flattened.iter_mut().zip(output.flattened.iter().zip(truth.flattened.iter())) {
@@ -33,17 +82,459 @@ fn dmse<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T
     Ok(ret)
 }
 
+fn mae<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
+    check_shape("mae", &truth.shape, &output.shape)?;
+    let len = T::from(output.shape.size()).unwrap();
+    Ok(output.flattened.iter().zip(truth.flattened.iter()).map(|(o, t)| (*o - *t).abs()).sum::<T>() / len)
+}
+
+fn dmae<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
+    check_shape("dmae", &truth.shape, &output.shape)?;
+    let len = T::from(output.shape.size()).unwrap();
+    let data = output.flattened.iter().zip(truth.flattened.iter()).map(|(o, t)| {
+        if o > t { T::one() / len } else if o < t { -T::one() / len } else { T::zero() }
+    }).collect();
+    Ok(Tensor::new(&output.shape, data))
+}
+
+/// Keep probabilities away from 0 and 1 so that the logarithms stay finite
+fn clamp_prob<T: NumT>(p: T) -> T {
+    let eps = T::from(1e-7).unwrap();
+    p.max(eps).min(T::one() - eps)
+}
+
+fn bce<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
+    check_shape("bce", &truth.shape, &output.shape)?;
+    let len = T::from(output.shape.size()).unwrap();
+    Ok(-output.flattened.iter().zip(truth.flattened.iter()).map(|(p, y)| {
+        let p = clamp_prob(*p);
+        *y * p.ln() + (T::one() - *y) * (T::one() - p).ln()
+    }).sum::<T>() / len)
+}
+
+fn dbce<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
+    check_shape("dbce", &truth.shape, &output.shape)?;
+    let len = T::from(output.shape.size()).unwrap();
+    let data = output.flattened.iter().zip(truth.flattened.iter()).map(|(p, y)| {
+        let p = clamp_prob(*p);
+        (p - *y) / (p * (T::one() - p)) / len
+    }).collect();
+    Ok(Tensor::new(&output.shape, data))
+}
+
+/// The log-softmax of each row over the last axis, and the count of rows
+fn log_softmax<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<(Vec<T>, usize)> {
+    let rank = output.shape.rank();
+    check_shape("log_softmax", &truth.shape, &output.shape)?;
+    if rank == 0 || output.shape[rank - 1] == 0 {
+        return Err(EasynnError::invalid("log_softmax", "the output has no classes on its last axis"));
+    }
+    let classes = output.shape[rank - 1];
+    let mut ret = Vec::with_capacity(output.flattened.len());
+    for z in output.flattened.chunks(classes) {
+        let max = z.iter().fold(T::neg_infinity(), |m, x| m.max(*x));
+        let ln_sum = max + z.iter().map(|x| (*x - max).exp()).sum::<T>().ln();
+        ret.extend(z.iter().map(|x| *x - ln_sum));
+    }
+    Ok((ret, output.flattened.len() / classes))
+}
+
+fn softmax_ce<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
+    let (ln_p, rows) = log_softmax(output, truth)?;
+    Ok(-ln_p.iter().zip(truth.flattened.iter()).map(|(l, y)| *l * *y).sum::<T>() / T::from(rows).unwrap())
+}
+
+fn dsoftmax_ce<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
+    let (ln_p, rows) = log_softmax(output, truth)?;
+    let rows = T::from(rows).unwrap();
+    let classes = output.shape[output.shape.rank() - 1];
+    // d/dz_k = (p_k sum_j y_j - y_k), which is p - y for probability truths
+    let mut data = Vec::with_capacity(ln_p.len());
+    for (l, y) in ln_p.chunks(classes).zip(truth.flattened.chunks(classes)) {
+        let y_sum = y.iter().copied().sum::<T>();
+        data.extend(l.iter().zip(y.iter()).map(|(l, y)| (l.exp() * y_sum - *y) / rows));
+    }
+    Ok(Tensor::new(&output.shape, data))
+}
+
+fn sigmoid_bce<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
+    check_shape("sigmoid_bce", &truth.shape, &output.shape)?;
+    let len = T::from(output.shape.size()).unwrap();
+    // ln(1 + e^z) - y z, stable for large |z|
+    Ok(output.flattened.iter().zip(truth.flattened.iter()).map(|(z, y)| {
+        z.max(T::zero()) - *z * *y + (-z.abs()).exp().ln_1p()
+    }).sum::<T>() / len)
+}
+
+fn dsigmoid_bce<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
+    check_shape("dsigmoid_bce", &truth.shape, &output.shape)?;
+    let len = T::from(output.shape.size()).unwrap();
+    let data = output.flattened.iter().zip(truth.flattened.iter())
+        .map(|(z, y)| (Activation::<T>::Sigmoid.call(*z) - *y) / len)
+        .collect();
+    Ok(Tensor::new(&output.shape, data))
+}
+
+/// The CTC forward-backward pass, returning ln p(labels | output),
+/// and ln(alpha_t(s) beta_t(s) / y_t(s)) for each time t and position s in the blank-extended labels,
+/// where beta excludes the output at t
+fn ctc_forward_backward<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>, blank: usize) -> Result<(T, Vec<usize>, Vec<Vec<T>>)> {
+    if output.shape.rank() != 2 || truth.shape.rank() != 1 {
+        return Err(EasynnError::invalid("ctc", "the output should be [times, classes] and the truth [labels]"));
+    }
+    let (times, classes) = (output.shape[0], output.shape[1]);
+    if times == 0 || blank >= classes {
+        return Err(EasynnError::invalid("ctc", format!("no blank {} among the {} classes of {} times", blank, classes, times)));
+    }
+    // the labels interleaved with blanks: [blank, l1, blank, l2, ..., blank]
+    let mut ext = vec![blank];
+    for l in truth.flattened.iter() {
+        // the labels are whole class indices, not truncated
+        match l.to_usize() {
+            Some(c) if *l == l.trunc() && c < classes && c != blank => { ext.push(c); ext.push(blank); },
+            _ => return Err(EasynnError::invalid("ctc", "the labels should be whole classes other than the blank")),
+        }
+    }
+    let slen = ext.len();
+    let ln_y = |t: usize, s: usize| output.flattened[t * classes + ext[s]].ln();
+    // a skip from s-2 to s is allowed between different labels
+    let can_skip = |s: usize| s >= 2 && ext[s] != blank && ext[s] != ext[s - 2];
+
+    let ninf = T::neg_infinity();
+    // alpha before emitting y at t, alpha_t(s) / y_t(s) without dividing by a zero y
+    let mut lpre = vec![vec![ninf; slen]; times];
+    let mut la = vec![vec![ninf; slen]; times];
+    for s in 0..slen.min(2) {
+        lpre[0][s] = T::zero();
+        la[0][s] = ln_y(0, s);
+    }
+    for t in 1..times {
+        for s in 0..slen {
+            let mut a = la[t - 1][s];
+            if s >= 1 {
+                a = log_add(a, la[t - 1][s - 1]);
+            }
+            if can_skip(s) {
+                a = log_add(a, la[t - 1][s - 2]);
+            }
+            lpre[t][s] = a;
+            la[t][s] = a + ln_y(t, s);
+        }
+    }
+    let mut ln_p = la[times - 1][slen - 1];
+    if slen > 1 {
+        ln_p = log_add(ln_p, la[times - 1][slen - 2]);
+    }
+
+    let mut lb = vec![vec![ninf; slen]; times];
+    lb[times - 1][slen - 1] = T::zero();
+    if slen > 1 {
+        lb[times - 1][slen - 2] = T::zero();
+    }
+    for t in (0..times - 1).rev() {
+        for s in 0..slen {
+            let mut b = lb[t + 1][s] + ln_y(t + 1, s);
+            if s + 1 < slen {
+                b = log_add(b, lb[t + 1][s + 1] + ln_y(t + 1, s + 1));
+            }
+            if s + 2 < slen && can_skip(s + 2) {
+                b = log_add(b, lb[t + 1][s + 2] + ln_y(t + 1, s + 2));
+            }
+            lb[t][s] = b;
+        }
+    }
+    let ln_ab = lpre.iter().zip(lb.iter()).map(|(a_t, b_t)| {
+        a_t.iter().zip(b_t.iter()).map(|(a, b)| *a + *b).collect()
+    }).collect();
+    Ok((ln_p, ext, ln_ab))
+}
+
+fn ctc<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>, blank: usize) -> Result<T> {
+    let (ln_p, _, _) = ctc_forward_backward(output, truth, blank)?;
+    Ok(-ln_p)
+}
+
+fn dctc<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>, blank: usize) -> Result<Tensor::<T>> {
+    let (ln_p, ext, ln_ab) = ctc_forward_backward(output, truth, blank)?;
+    let mut ret = Tensor::<T>::zeros(&output.shape);
+    // impossible alignments give no gradient
+    if ln_p == T::neg_infinity() {
+        return Ok(ret);
+    }
+    let classes = output.shape[1];
+    // dL/dy_tk = -sum_{s: ext[s] = k} alpha_t(s) beta_t(s) / (p y_tk),
+    // alpha_t(s) / y_tk being taken before the emission so that a zero y gives no NaN
+    for (t, ab_t) in ln_ab.iter().enumerate() {
+        for (s, ab) in ab_t.iter().enumerate() {
+            ret.flattened[t * classes + ext[s]] -= (*ab - ln_p).exp();
+        }
+    }
+    Ok(ret)
+}
+
+/// The output shape of a head predicting `quantiles` quantiles of a truth of `truth_shape`
+pub fn quantile_shape(truth_shape: &Shape, quantiles: usize) -> Shape {
+    let mut dims = truth_shape.dims().to_vec();
+    dims.push(quantiles);
+    Shape::from_slice(&dims)
+}
+
+/// Split the output of a quantile head into one tensor per quantile, each of the truth shape
+pub fn split_quantiles<T: NumT>(output: &Tensor::<T>, quantiles: usize) -> Result<Vec<Tensor::<T>>> {
+    let rank = output.shape.rank();
+    if rank == 0 || output.shape[rank - 1] != quantiles {
+        return Err(EasynnError::invalid("split_quantiles", format!("the last axis of {:?} is not of the {} quantiles", output.shape.dims(), quantiles)));
+    }
+    let truth_shape = Shape::from_slice(&output.shape.dims()[..rank - 1]);
+    Ok((0..quantiles).map(|q| {
+        Tensor::new(&truth_shape, output.flattened.iter().skip(q).step_by(quantiles).copied().collect())
+    }).collect())
+}
+
+fn check_pinball<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>, quantiles: &[f64]) -> Result<()> {
+    if quantiles.is_empty() {
+        return Err(EasynnError::invalid("pinball", "there are no quantiles"));
+    }
+    check_shape("pinball", &quantile_shape(&truth.shape, quantiles.len()), &output.shape)?;
+    Ok(())
+}
+
+fn pinball<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>, quantiles: &[f64]) -> Result<T> {
+    check_pinball(output, truth, quantiles)?;
+    let q = quantiles.len();
+    let mut ret = T::zero();
+    for (i, o) in output.flattened.iter().enumerate() {
+        let tau = T::from(quantiles[i % q]).unwrap();
+        let err = truth.flattened[i / q] - *o;
+        ret += (tau * err).max((tau - T::one()) * err);
+    }
+    Ok(ret / T::from(output.shape.size()).unwrap())
+}
+
+fn dpinball<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>, quantiles: &[f64]) -> Result<Tensor::<T>> {
+    check_pinball(output, truth, quantiles)?;
+    let q = quantiles.len();
+    let len = T::from(output.shape.size()).unwrap();
+    let mut ret = Tensor::<T>::zeros(&output.shape);
+    for (i, (r, o)) in ret.flattened.iter_mut().zip(output.flattened.iter()).enumerate() {
+        let tau = T::from(quantiles[i % q]).unwrap();
+        let t = truth.flattened[i / q];
+        if t > *o {
+            *r = -tau / len;
+        } else if t < *o {
+            *r = (T::one() - tau) / len;
+        }
+    }
+    Ok(ret)
+}
+
+/// Keep the Dice coefficient of classes absent from both the output and the truth at 1
+const DICE_SMOOTH: f64 = 1e-6;
+
+/// The (intersection, sum) of each class of the `[classes, ...]` output and truth
+fn dice_terms<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Vec<(T, T)>> {
+    check_shape("dice", &truth.shape, &output.shape)?;
+    if output.shape.rank() < 2 || output.shape.size() == 0 {
+        return Err(EasynnError::invalid("dice", "the output should be a non-empty [classes, ...]"));
+    }
+    let len = output.shape.size() / output.shape[0];
+    Ok(output.flattened.chunks(len).zip(truth.flattened.chunks(len)).map(|(o, t)| {
+        let inter = o.iter().zip(t.iter()).map(|(o, t)| *o * *t).sum::<T>();
+        (inter, o.iter().copied().sum::<T>() + t.iter().copied().sum::<T>())
+    }).collect())
+}
+
+fn dice<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
+    let terms = dice_terms(output, truth)?;
+    let (two, smooth) = (T::one() + T::one(), T::from(DICE_SMOOTH).unwrap());
+    let coef = terms.iter().map(|(i, s)| (two * *i + smooth) / (*s + smooth)).sum::<T>();
+    Ok(T::one() - coef / T::from(terms.len()).unwrap())
+}
+
+fn ddice<T: NumT>(output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
+    let terms = dice_terms(output, truth)?;
+    let (two, smooth) = (T::one() + T::one(), T::from(DICE_SMOOTH).unwrap());
+    let classes = T::from(terms.len()).unwrap();
+    let len = output.shape.size() / output.shape[0];
+    let mut ret = Tensor::<T>::zeros(&output.shape);
+    for ((r, t), (i, s)) in ret.flattened.chunks_mut(len).zip(truth.flattened.chunks(len)).zip(terms.iter()) {
+        let (num, den) = (two * *i + smooth, *s + smooth);
+        // d/do_k of num / den is (2 t_k den - num) / den^2
+        for (r, t) in r.iter_mut().zip(t.iter()) {
+            *r = -(two * *t * den - num) / (den * den) / classes;
+        }
+    }
+    Ok(ret)
+}
+
 impl Loss {
     pub fn call<T: NumT>(&self, output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<T> {
         match self {
             Loss::MeanSquare => mse::<T>(output, truth),
+            Loss::MeanAbsolute => mae::<T>(output, truth),
+            Loss::BinaryCrossEntropy => bce::<T>(output, truth),
+            Loss::SoftmaxCrossEntropy => softmax_ce::<T>(output, truth),
+            Loss::Ctc(blank) => ctc::<T>(output, truth, *blank),
+            Loss::Pinball(quantiles) => pinball::<T>(output, truth, quantiles),
+            Loss::SigmoidCrossEntropy => sigmoid_bce::<T>(output, truth),
+            Loss::Dice => dice::<T>(output, truth),
             // _ => T::zero(),
         }
     }
     pub fn diff<T: NumT>(&self, output: &Tensor::<T>, truth: &Tensor::<T>) -> Result<Tensor::<T>> {
         match self {
             Loss::MeanSquare => dmse::<T>(output, truth),
+            Loss::MeanAbsolute => dmae::<T>(output, truth),
+            Loss::BinaryCrossEntropy => dbce::<T>(output, truth),
+            Loss::SoftmaxCrossEntropy => dsoftmax_ce::<T>(output, truth),
+            Loss::Ctc(blank) => dctc::<T>(output, truth, *blank),
+            Loss::Pinball(quantiles) => dpinball::<T>(output, truth, quantiles),
+            Loss::SigmoidCrossEntropy => dsigmoid_bce::<T>(output, truth),
+            Loss::Dice => ddice::<T>(output, truth),
             // _ => T::zero(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_ctc() {
+    // 3 time steps of 3 classes, class 0 is the blank
+    let output = Tensor::<f64>::new(&Shape::new([3, 3]), vec![
+        0.5, 0.3, 0.2,
+        0.2, 0.5, 0.3,
+        0.4, 0.1, 0.5,
+    ]);
+    let truth = Tensor::<f64>::new(&Shape::new([2]), vec![1., 2.]);
+    // brute force over all paths collapsing to [1, 2]
+    let mut p = 0.;
+    for path in 0..27_usize {
+        let toks = [path / 9, path / 3 % 3, path % 3];
+        let mut collapsed = Vec::<usize>::new();
+        for (i, &k) in toks.iter().enumerate() {
+            if k != 0 && (i == 0 || toks[i - 1] != k) {
+                collapsed.push(k);
+            }
+        }
+        if collapsed == vec![1, 2] {
+            p += (0..3).map(|t| output.get([t, toks[t]])).product::<f64>();
+        }
+    }
+    let loss = Loss::Ctc(0);
+    assert!((loss.call(&output, &truth).unwrap() + p.ln()).abs() < 1e-12);
+
+    let grad = loss.diff(&output, &truth).unwrap();
+    let eps = 1e-7;
+    for i in 0..9 {
+        let (mut op, mut om) = (output.clone(), output.clone());
+        op.flattened[i] += eps;
+        om.flattened[i] -= eps;
+        let num = (loss.call(&op, &truth).unwrap() - loss.call(&om, &truth).unwrap()) / (2. * eps);
+        assert!((num - grad.flattened[i]).abs() < 1e-5, "expected {}, got {}", num, grad.flattened[i]);
+    }
+    // labels equal to the blank are invalid
+    assert!(loss.call(&output, &Tensor::new(&Shape::new([1]), vec![0.])).is_err());
+    // so are the labels that are not whole
+    assert!(loss.call(&output, &Tensor::new(&Shape::new([2]), vec![1.5, 2.])).is_err());
+    assert!(loss.diff(&output, &Tensor::new(&Shape::new([2]), vec![1., 2.0001])).is_err());
+
+    // a zero probability on a feasible alignment still has a finite gradient
+    let output = Tensor::<f64>::new(&Shape::new([3, 3]), vec![
+        0.5, 0.5, 0.0,
+        0.2, 0.5, 0.3,
+        0.4, 0.1, 0.5,
+    ]);
+    let grad = loss.diff(&output, &truth).unwrap();
+    assert!(grad.flattened.iter().all(|g| g.is_finite()));
+    for i in 0..9 {
+        let mut op = output.clone();
+        op.flattened[i] += eps;
+        let num = (loss.call(&op, &truth).unwrap() - loss.call(&output, &truth).unwrap()) / eps;
+        assert!((num - grad.flattened[i]).abs() < 1e-4, "expected {}, got {}", num, grad.flattened[i]);
+    }
+}
+
+#[test]
+fn test_pinball() {
+    let loss = Loss::Pinball(Quantiles::new(&[0.1, 0.9]).unwrap());
+    let truth = Tensor::<f64>::new(&Shape::new([2]), vec![1., 2.]);
+    let output = Tensor::<f64>::new(&quantile_shape(&truth.shape, 2), vec![
+        0., 2.,
+        3., 2.,
+    ]);
+    // 0.1 * 1, 0.1 * 1, 0.9 * 1, 0
+    assert!((loss.call(&output, &truth).unwrap() - 1.1 / 4.).abs() < 1e-12);
+    let grad = loss.diff(&output, &truth).unwrap();
+    assert_eq!(grad.flattened, vec![-0.1 / 4., (1. - 0.9) / 4., (1. - 0.1) / 4., 0.]);
+    let split = split_quantiles(&output, 2).unwrap();
+    assert_eq!(split[1], Tensor::new(&Shape::new([2]), vec![2., 2.]));
+    assert!(loss.call(&truth, &truth).is_err());
+    assert!(Quantiles::new(&[0.5; MAX_QUANTILES + 1]).is_err());
+}
+
+#[test]
+fn test_sigmoid_cross_entropy() {
+    let loss = Loss::SigmoidCrossEntropy;
+    let output = Tensor::<f64>::new(&Shape::new([3]), vec![0., 2., -40.]);
+    let truth = Tensor::<f64>::new(&Shape::new([3]), vec![1., 0., 0.]);
+    let expected = (2f64.ln() + (1. + 2f64.exp()).ln() + (1. + (-40f64).exp()).ln()) / 3.;
+    assert!((loss.call(&output, &truth).unwrap() - expected).abs() < 1e-12);
+    let grad = loss.diff(&output, &truth).unwrap();
+    let eps = 1e-6;
+    for i in 0..3 {
+        let (mut plus, mut minus) = (output.clone(), output.clone());
+        plus.flattened[i] += eps;
+        minus.flattened[i] -= eps;
+        let num = (loss.call(&plus, &truth).unwrap() - loss.call(&minus, &truth).unwrap()) / (2. * eps);
+        assert!((grad.flattened[i] - num).abs() < 1e-8);
+    }
+}
+
+#[test]
+fn test_basic_losses() {
+    let shape = Shape::new([2, 3]);
+    let output = Tensor::<f64>::new(&shape, vec![0.2, 0.7, 0.4, 0.9, 0.1, 0.5]);
+    let truth = Tensor::<f64>::new(&shape, vec![0., 1., 0., 1., 0., 0.]);
+    assert!((Loss::MeanAbsolute.call(&output, &truth).unwrap() - 1.6 / 6.).abs() < 1e-12);
+    // one-hot rows: the loss is the mean of -ln softmax(z)_label
+    let ln_sm = |z: &[f64], k: usize| z[k] - z.iter().map(|x| x.exp()).sum::<f64>().ln();
+    let expected = -(ln_sm(&[0.2, 0.7, 0.4], 1) + ln_sm(&[0.9, 0.1, 0.5], 0)) / 2.;
+    assert!((Loss::SoftmaxCrossEntropy.call(&output, &truth).unwrap() - expected).abs() < 1e-12);
+    // large logits stay finite
+    let big = Tensor::<f64>::new(&Shape::new([2]), vec![1000., -1000.]);
+    let one_hot = Tensor::<f64>::new(&Shape::new([2]), vec![0., 1.]);
+    assert!((Loss::SoftmaxCrossEntropy.call(&big, &one_hot).unwrap() - 2000.).abs() < 1e-9);
+
+    // the gradients against finite differences
+    let eps = 1e-6;
+    for loss in [Loss::MeanAbsolute, Loss::BinaryCrossEntropy, Loss::SoftmaxCrossEntropy] {
+        let diff = loss.diff(&output, &truth).unwrap();
+        for i in 0..shape.size() {
+            let (mut plus, mut minus) = (output.clone(), output.clone());
+            plus.flattened[i] += eps;
+            minus.flattened[i] -= eps;
+            let num = (loss.call(&plus, &truth).unwrap() - loss.call(&minus, &truth).unwrap()) / (2. * eps);
+            assert!((diff.flattened[i] - num).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn test_dice() {
+    let loss = Loss::Dice;
+    // 2 classes of 3 pixels
+    let truth = Tensor::<f64>::new(&Shape::new([2, 3]), vec![1., 0., 0., 0., 1., 1.]);
+    assert!(loss.call(&truth, &truth).unwrap().abs() < 1e-9);
+    let output = Tensor::<f64>::new(&Shape::new([2, 3]), vec![0.8, 0.3, 0.1, 0.2, 0.7, 0.9]);
+    let expected = 1. - (1.6 / 2.2 + 3.2 / 3.8) / 2.;
+    assert!((loss.call(&output, &truth).unwrap() - expected).abs() < 1e-6);
+    let grad = loss.diff(&output, &truth).unwrap();
+    let eps = 1e-6;
+    for i in 0..6 {
+        let (mut plus, mut minus) = (output.clone(), output.clone());
+        plus.flattened[i] += eps;
+        minus.flattened[i] -= eps;
+        let num = (loss.call(&plus, &truth).unwrap() - loss.call(&minus, &truth).unwrap()) / (2. * eps);
+        assert!((grad.flattened[i] - num).abs() < 1e-8);
+    }
+    assert!(loss.call(&Tensor::<f64>::zeros(&Shape::new([3])), &Tensor::zeros(&Shape::new([3]))).is_err());
+}
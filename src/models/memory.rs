@@ -0,0 +1,100 @@
+//! Estimation of the memory a model takes, to size the batch and the hidden
+//! dimensions before running out of memory.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     let mut nn = Sequential::<f32>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([784]), sh!([128]), Activation::Relu));
+//!     nn.add(Dense::new(sh!([128]), sh!([10]), Activation::No));
+//!     // plain SGD keeps no optimizer state
+//!     let report = nn.memory_report(32, 0);
+//!     assert_eq!(report.parameter_bytes, (784 * 128 + 128 + 128 * 10 + 10) * 4);
+//!     println!("{}", report);
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use std::fmt;
+use std::mem::size_of;
+
+/// The estimated bytes of each part of the memory of a model
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryReport {
+    /// The trainable parameters
+    pub parameter_bytes: usize,
+    /// The accumulators of the weight and bias deltas while training
+    pub gradient_bytes: usize,
+    /// The inputs, outputs (z and a) and deltas of every layer, for a whole batch held at once
+    pub activation_bytes: usize,
+    /// The state kept by the optimizer, e.g. 2 slots per parameter for Adam
+    pub optimizer_bytes: usize,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> usize {
+        self.parameter_bytes + self.gradient_bytes + self.activation_bytes + self.optimizer_bytes
+    }
+}
+
+/// Bytes in a human readable unit
+fn human(bytes: usize) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit + 1 < units.len() {
+        size /= 1024.;
+        unit += 1;
+    }
+    if unit == 0 { format!("{} B", bytes) } else { format!("{:.2} {}", size, units[unit]) }
+}
+
+impl fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "parameters:  {}", human(self.parameter_bytes))?;
+        writeln!(f, "gradients:   {}", human(self.gradient_bytes))?;
+        writeln!(f, "activations: {}", human(self.activation_bytes))?;
+        writeln!(f, "optimizer:   {}", human(self.optimizer_bytes))?;
+        write!(f, "total:       {}", human(self.total()))
+    }
+}
+
+impl<T: NumT> Sequential<T> {
+    /// Estimate the memory to train on batches of `batch_size` samples held at once,
+    /// with an optimizer keeping `optimizer_slots` values per parameter.
+    /// `train_once` propagates one sample at a time, needing the activations of a batch of 1.
+    pub fn memory_report(&self, batch_size: usize, optimizer_slots: usize) -> MemoryReport {
+        let elem = size_of::<T>();
+        let params = self.layers().iter()
+            .map(|l| l.parameters().iter().map(|p| p.len()).sum::<usize>())
+            .sum::<usize>();
+        let grads = self.layers().iter()
+            .map(|l| l.get_weight_count() + l.get_output_shape().size())
+            .sum::<usize>();
+        let input = self.layers().first().map_or(0, |l| l.get_input_shape().size());
+        // z, a and delta of each layer
+        let per_sample = input + 3 * self.layers().iter().map(|l| l.get_output_shape().size()).sum::<usize>();
+        MemoryReport {
+            parameter_bytes: params * elem,
+            gradient_bytes: grads * elem,
+            activation_bytes: batch_size * per_sample * elem,
+            optimizer_bytes: optimizer_slots * params * elem,
+        }
+    }
+}
+
+#[test]
+fn test_memory_report() {
+    use crate::prelude::*;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(sh!([4]), sh!([3]), Activation::Relu));
+    nn.add(MaxOverTime::new(sh!([3, 1])));
+    let report = nn.memory_report(2, 2);
+    assert_eq!(report.parameter_bytes, 15 * 8);
+    assert_eq!(report.gradient_bytes, (15 + 1) * 8);
+    assert_eq!(report.activation_bytes, 2 * (4 + 3 * (3 + 1)) * 8);
+    assert_eq!(report.optimizer_bytes, 2 * 15 * 8);
+    assert_eq!(report.total(), (15 + 16 + 32 + 30) * 8);
+    assert_eq!(human(1536), "1.50 KiB");
+    assert_eq!(human(12), "12 B");
+}
@@ -2,27 +2,81 @@
 //! 
 
 pub mod sequential;
+pub mod inference;
+pub mod decoding;
+pub mod multitask;
+pub mod online;
+pub mod drift;
+pub mod pretrain;
+pub mod histogram;
+pub mod control;
+pub mod callbacks;
+pub mod pruning;
+pub mod regularization;
+pub mod parallel;
+pub mod memory;
+pub mod loadgen;
+pub mod tiling;
+pub mod serialize;
+pub mod golden;
+pub mod zoo;
+pub mod skipgram;
+pub mod recommender;
 
 pub mod losses;
 
 use crate::layers::*;
 pub use losses::*;
 
+/// The deltas of each layer and the outputs of each layer of a propagated sample
+pub type Propagation<T> = (Vec<Tensor<T>>, Vec<Tensor<T>>);
+
 pub trait Model<T: NumT>  {
     /// Calculate the output according to the input
     fn predict(&self, input: &Tensor<T>) -> Result<Tensor<T>>;
     /// Forward propagate and backward propagate using a input-output pair
     /// 
     /// Returns (the delta of each layer and the output for each layer)
-    fn propagate_sample(&self, input: &Tensor<T>, truth: &Tensor<T>) -> Result<(Vec<Tensor<T>>, Vec<Tensor<T>>)>;
+    fn propagate_sample(&self, input: &Tensor<T>, truth: &Tensor<T>) -> Result<Propagation<T>>;
     /// Add back the delta of each layer to cum_delta
     /// and add back (d dot a^T) of each layer to cum_da_lst
-    fn update_delta_da(&self, cum_dw: &mut Vec<Vec<T>>, cum_db: &mut Vec<Tensor<T>>, delta: &Vec<Tensor<T>>, a_lst: &Vec<Tensor<T>>);
+    fn update_delta_da(&self, cum_dw: &mut [Vec<T>], cum_db: &mut [Tensor<T>], delta: &[Tensor<T>], a_lst: &[Tensor<T>]);
     /// Descend
-    fn descend(&mut self, rate: T, dw: &Vec<Vec<T>>, db: &Vec<Tensor<T>>);
+    fn descend(&mut self, rate: T, dw: &[Vec<T>], db: &[Tensor<T>]);
     
     /// Evaluate the model and return the loss
-    fn evaluate(&self, inputs: &Vec<Tensor<T>>, truths: &Vec<Tensor<T>>) -> T;
+    fn evaluate(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> T;
+    /// Evaluate the model and return the mean loss weighted by the sample weights,
+    /// one weight per sample, failing if they do not sum to a positive weight
+    fn evaluate_weighted(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>], weights: &[T]) -> Result<T> {
+        check_len("evaluate_weighted", inputs.len(), weights.len())?;
+        let mut sum = T::zero();
+        let mut total = T::zero();
+        for ((input, truth), w) in inputs.chunks(1).zip(truths.chunks(1)).zip(weights.iter()) {
+            sum += *w * self.evaluate(input, truth);
+            total += *w;
+        }
+        if total <= T::zero() {
+            return Err(EasynnError::invalid("evaluate_weighted", "the weights do not sum to a positive weight"));
+        }
+        Ok(sum / total)
+    }
     /// Trains the model given the dataset by an epoch and return the loss
-    fn train_once(&mut self, inputs: &Vec<Tensor<T>>, truths: &Vec<Tensor<T>>, batch_size: usize, learning_rate: T, verbose: bool) -> T;
+    fn train_once(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, learning_rate: T, verbose: bool) -> T;
+    /// Trains the model by an epoch, scaling the gradient and the loss of each sample by its weight,
+    /// and return the weighted mean loss
+    ///
+    /// The default fails, a model has to implement it to take sample weights
+    fn train_once_weighted(&mut self, inputs: &[Tensor<T>], _truths: &[Tensor<T>], weights: &[T], _batch_size: usize, _learning_rate: T, _verbose: bool) -> Result<T> {
+        check_len("train_once_weighted", inputs.len(), weights.len())?;
+        Err(EasynnError::invalid("train_once_weighted", "the model does not take sample weights"))
+    }
+    /// Trains the model for a number of epochs and return the loss of each epoch
+    fn fit(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], epochs: usize, batch_size: usize, learning_rate: T, verbose: bool) -> Vec<T> {
+        (0..epochs).map(|_| self.train_once(inputs, truths, batch_size, learning_rate, verbose)).collect()
+    }
+    /// Descend once on a batch of streaming data and return its mean loss
+    fn partial_fit(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], learning_rate: T) -> T {
+        self.train_once(inputs, truths, inputs.len().max(1), learning_rate, false)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,176 @@
+//! Multi-task model: a shared trunk feeding several heads, each with its own loss.
+//!
+//! The heads are `Sequential` models taking the output of the trunk as input.
+//! Training minimizes the weighted sum of the losses of the heads, and the
+//! loss of each task is reported separately.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::multitask::MultiTask;
+//!     let mut trunk = Sequential::<f64>::new(Loss::MeanSquare);
+//!     trunk.add(Dense::new(sh!([2]), sh!([4]), Activation::Tanh));
+//!     let mut mt = MultiTask::new(trunk);
+//!     for _ in 0..2 {
+//!         let mut head = Sequential::<f64>::new(Loss::MeanSquare);
+//!         head.add(Dense::new(sh!([4]), sh!([1]), Activation::No));
+//!         mt.add_head(head, 0.5);
+//!     }
+//!     let inputs = vec![Tensor::new(sh!([2]), vec![1., -1.])];
+//!     let truths = vec![vec![Tensor::new(sh!([1]), vec![1.]), Tensor::new(sh!([1]), vec![-1.])]];
+//!     let report = mt.train_once(&inputs, &truths, 1, 0.1, false);
+//!     assert_eq!(report.tasks.len(), 2);
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+
+pub struct MultiTask<T: NumT> {
+    /// The shared layers, the loss of the trunk is not used
+    pub trunk: Sequential<T>,
+    heads: Vec<Sequential<T>>,
+    /// The weight of the loss of each head
+    pub weights: Vec<T>,
+}
+
+/// The losses of a multi-task model
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiTaskLoss<T: NumT> {
+    /// The weighted sum of the task losses
+    pub total: T,
+    /// The loss of each task
+    pub tasks: Vec<T>,
+}
+
+impl<T: NumT> MultiTaskLoss<T> {
+    fn new(tasks: Vec<T>, weights: &[T]) -> Self {
+        MultiTaskLoss::<T> {
+            total: tasks.iter().zip(weights.iter()).map(|(l, w)| *l * *w).sum(),
+            tasks,
+        }
+    }
+}
+
+impl<T: NumT> MultiTask<T> {
+    /// The trunk should have at least one layer
+    pub fn new(trunk: Sequential<T>) -> Self {
+        if trunk.layers().is_empty() {
+            panic!("The trunk of a multi-task model has no layer!");
+        }
+        MultiTask::<T> { trunk, heads: Vec::new(), weights: Vec::new() }
+    }
+    /// Add a head trained with its own loss, weighted by `weight`
+    pub fn add_head(&mut self, head: Sequential<T>, weight: T) {
+        self.heads.push(head);
+        self.weights.push(weight);
+    }
+    pub fn heads(&self) -> &[Sequential<T>] {
+        &self.heads
+    }
+    pub fn heads_mut(&mut self) -> &mut [Sequential<T>] {
+        &mut self.heads
+    }
+    /// The output of each head
+    pub fn predict(&self, input: &Tensor<T>) -> Result<Vec<Tensor<T>>> {
+        let feature = self.trunk.predict(input)?;
+        self.heads.iter().map(|h| h.predict(&feature)).collect()
+    }
+    /// The loss of each task on one sample
+    fn task_losses(&self, input: &Tensor<T>, truths: &[Tensor<T>]) -> Result<Vec<T>> {
+        check_len("task_losses", self.heads.len(), truths.len())?;
+        self.predict(input)?.iter().zip(self.heads.iter().zip(truths.iter()))
+            .map(|(pred, (h, truth))| h.loss.call(pred, truth))
+            .collect()
+    }
+    /// Evaluate the model, `truths[i]` holds the truth of each task for `inputs[i]`
+    pub fn evaluate(&self, inputs: &[Tensor<T>], truths: &[Vec<Tensor<T>>]) -> MultiTaskLoss<T> {
+        let mut sum = vec![T::zero(); self.heads.len()];
+        for (input, truth) in inputs.iter().zip(truths.iter()) {
+            for (s, l) in sum.iter_mut().zip(self.task_losses(input, truth).unwrap()) {
+                *s += l;
+            }
+        }
+        let n = T::from(inputs.len()).unwrap();
+        MultiTaskLoss::new(sum.into_iter().map(|s| s / n).collect(), &self.weights)
+    }
+    /// Trains the model by an epoch and return the mean losses,
+    /// `truths[i]` holds the truth of each task for `inputs[i]`
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "train_epoch_multitask", skip_all, fields(samples = inputs.len(), tasks = self.heads.len())))]
+    pub fn train_once(&mut self, inputs: &[Tensor<T>], truths: &[Vec<Tensor<T>>], batch_size: usize, learning_rate: T, verbose: bool) -> MultiTaskLoss<T> {
+        let (mut trunk_dw, mut trunk_db) = self.trunk.accumulators();
+        let mut head_acc: Vec<_> = self.heads.iter().map(|h| h.accumulators()).collect();
+        let mut sum = vec![T::zero(); self.heads.len()];
+        let trunk_last = self.trunk.layers().last().unwrap().get_activation();
+
+        for (i, (in_batch, tr_batch)) in inputs.chunks(batch_size).zip(truths.chunks(batch_size)).enumerate() {
+            let bsize_t = T::from(in_batch.len()).unwrap();
+            for (dw, db) in trunk_dw.iter_mut().zip(trunk_db.iter_mut()).chain(
+                head_acc.iter_mut().flat_map(|(dw, db)| dw.iter_mut().zip(db.iter_mut()))
+            ) {
+                dw.iter_mut().for_each(|x| *x = T::zero());
+                db.flattened.iter_mut().for_each(|x| *x = T::zero());
+            }
+            for (input, truth) in in_batch.iter().zip(tr_batch.iter()) {
+                assert_eq!(truth.len(), self.heads.len(), "One truth per task is expected!");
+                let (z_l, a_lst) = self.trunk.forward_train_all(input).unwrap();
+                let feature = a_lst.last().unwrap();
+                let mut trunk_delta = Tensor::<T>::zeros(feature.get_shape());
+                for ((head, weight), ((t, s), (dw, db))) in self.heads.iter().zip(self.weights.iter()).zip(
+                    truth.iter().zip(sum.iter_mut()).zip(head_acc.iter_mut())
+                ) {
+                    let (mut deltas, mut outputs) = head.propagate_sample(feature, t).unwrap();
+                    *s += head.loss.call(&outputs.pop().unwrap(), t).unwrap();
+                    for d in &mut deltas {
+                        d.flattened.iter_mut().for_each(|x| *x *= *weight);
+                    }
+                    head.update_delta_da(dw, db, &deltas, &outputs);
+                    let back = head.layers()[0].backpropagate_delta(&deltas[0], z_l.last().unwrap(), &trunk_last).unwrap();
+                    for (x, b) in trunk_delta.flattened.iter_mut().zip(back.flattened.iter()) {
+                        *x += *b;
+                    }
+                }
+                let deltas = self.trunk.backpropagate_from(trunk_delta, &z_l);
+                self.trunk.update_delta_da(&mut trunk_dw, &mut trunk_db, &deltas, &a_lst[..a_lst.len() - 1]);
+            }
+            self.trunk.descend(learning_rate / bsize_t, &trunk_dw, &trunk_db);
+            for (head, (dw, db)) in self.heads.iter_mut().zip(head_acc.iter()) {
+                head.descend(learning_rate / bsize_t, dw, db);
+            }
+            if verbose {
+                println!("Trainning batch {} ... Ok", i);
+            }
+        }
+        let n = T::from(inputs.len()).unwrap();
+        MultiTaskLoss::new(sum.into_iter().map(|s| s / n).collect(), &self.weights)
+    }
+}
+
+#[test]
+fn test_multitask() {
+    use crate::prelude::*;
+
+    let mut trunk = Sequential::<f64>::new(Loss::MeanSquare);
+    trunk.add(Dense::new(sh!([2]), sh!([8]), Activation::Tanh));
+    let mut mt = MultiTask::new(trunk);
+    // the sum and the difference of the inputs
+    for _ in 0..2 {
+        let mut head = Sequential::<f64>::new(Loss::MeanSquare);
+        head.add(Dense::new(sh!([8]), sh!([1]), Activation::No));
+        mt.add_head(head, 1.);
+    }
+    let inputs: Vec<_> = [(0.1, 0.2), (-0.3, 0.1), (0.4, -0.2), (-0.1, -0.3)].iter()
+        .map(|(a, b)| Tensor::new(sh!([2]), vec![*a, *b])).collect();
+    let truths: Vec<Vec<_>> = inputs.iter().map(|i| {
+        let (a, b) = (i.get([0]), i.get([1]));
+        vec![Tensor::new(sh!([1]), vec![a + b]), Tensor::new(sh!([1]), vec![a - b])]
+    }).collect();
+
+    let before = mt.evaluate(&inputs, &truths);
+    for _ in 0..300 {
+        mt.train_once(&inputs, &truths, 2, 0.1, false);
+    }
+    let after = mt.evaluate(&inputs, &truths);
+    assert_eq!(after.tasks.len(), 2);
+    assert!(after.tasks[0] < before.tasks[0] && after.tasks[1] < before.tasks[1]);
+    assert!((after.total - after.tasks[0] - after.tasks[1]).abs() < 1e-12);
+    assert_eq!(mt.predict(&inputs[0]).unwrap().len(), 2);
+}
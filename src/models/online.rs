@@ -0,0 +1,125 @@
+//! Online learning: training on an unbounded stream of batches, one step each.
+//!
+//! There is no epoch in a stream, so the learning rate decays with the count
+//! of steps taken, following a `StreamDecay` schedule.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::online::{ OnlineLearner, StreamDecay };
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+//!     let mut learner = OnlineLearner::new(nn, 0.5, StreamDecay::InverseTime(0.01));
+//!     for x in (0..100).map(|x| x as f64 / 100.) {
+//!         learner.partial_fit(&[Tensor::new(sh!([1]), vec![x])], &[Tensor::new(sh!([1]), vec![2. * x])]);
+//!     }
+//!     assert_eq!(learner.steps(), 100);
+//! ```
+
+use crate::models::*;
+use crate::models::drift::DriftDetector;
+
+/// How the learning rate decays with the step t (from 0)
+#[derive(Debug, Copy, Clone)]
+pub enum StreamDecay<T: NumT> {
+    Constant,
+    /// `rate / (1 + k t)`
+    InverseTime(T),
+    /// `rate / sqrt(1 + t)`
+    InverseSqrt,
+    /// `rate * gamma^t`, but not lower than `rate * floor`
+    Exponential { gamma: T, floor: T },
+}
+
+impl<T: NumT> StreamDecay<T> {
+    /// The learning rate at step t
+    pub fn rate(&self, base: T, step: usize) -> T {
+        let t = T::from(step).unwrap();
+        match self {
+            StreamDecay::Constant => base,
+            StreamDecay::InverseTime(k) => base / (T::one() + *k * t),
+            StreamDecay::InverseSqrt => base / (T::one() + t).sqrt(),
+            StreamDecay::Exponential { gamma, floor } => base * gamma.powf(t).max(*floor),
+        }
+    }
+}
+
+/// A model trained by `partial_fit` on a stream, keeping the count of steps taken
+pub struct OnlineLearner<T: NumT, M: Model<T>> {
+    pub model: M,
+    /// The learning rate of the first step
+    pub base_rate: T,
+    pub decay: StreamDecay<T>,
+    steps: usize,
+}
+
+impl<T: NumT, M: Model<T>> OnlineLearner<T, M> {
+    pub fn new(model: M, base_rate: T, decay: StreamDecay<T>) -> Self {
+        OnlineLearner::<T, M> { model, base_rate, decay, steps: 0 }
+    }
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+    /// The learning rate of the next step
+    pub fn learning_rate(&self) -> T {
+        self.decay.rate(self.base_rate, self.steps)
+    }
+    /// Restart the schedule, e.g. when the stream has drifted
+    pub fn reset_schedule(&mut self) {
+        self.steps = 0;
+    }
+    /// Descend once on the batch and return its mean loss before the step
+    pub fn partial_fit(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> T {
+        let loss = self.model.partial_fit(inputs, truths, self.learning_rate());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(step = self.steps, learning_rate = ?self.learning_rate(), loss = ?loss, "partial fit");
+        self.steps += 1;
+        loss
+    }
+    /// Like `partial_fit`, feeding the loss to the drift detector.
+    /// On a drift the schedule restarts, raising the learning rate back to the base rate.
+    /// Returns the loss and whether a drift was detected.
+    pub fn partial_fit_watched<D: DriftDetector<T>>(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], detector: &mut D) -> (T, bool) {
+        let loss = self.partial_fit(inputs, truths);
+        let drifted = detector.update(loss);
+        if drifted {
+            #[cfg(feature = "tracing")]
+            tracing::info!(step = self.steps, "drift detected, restarting the schedule");
+            self.reset_schedule();
+        }
+        (loss, drifted)
+    }
+}
+
+#[test]
+fn test_online_learner() {
+    use crate::prelude::*;
+
+    assert_eq!(StreamDecay::InverseTime(0.5).rate(1., 2), 0.5);
+    assert_eq!(StreamDecay::InverseSqrt.rate(1., 3), 0.5);
+    assert_eq!(StreamDecay::Exponential { gamma: 0.5, floor: 0.2 }.rate(1., 1), 0.5);
+    assert_eq!(StreamDecay::Exponential { gamma: 0.5, floor: 0.2 }.rate(1., 5), 0.2);
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+    let mut learner = OnlineLearner::new(nn, 0.5, StreamDecay::InverseSqrt);
+    let mut last = 0.;
+    for i in 0..2000 {
+        let x = (i % 10) as f64 / 10.;
+        last = learner.partial_fit(&[Tensor::new(sh!([1]), vec![x])], &[Tensor::new(sh!([1]), vec![1. - x])]);
+    }
+    assert!(last < 1e-4);
+    assert!(learner.learning_rate() < 0.5 / 40.);
+    learner.reset_schedule();
+    assert_eq!(learner.learning_rate(), 0.5);
+
+    // the target flips, the detector restarts the schedule
+    let mut ph = crate::models::drift::PageHinkley::new(0.001, 0.05);
+    let mut drifted = false;
+    for i in 0..200 {
+        let x = (i % 10) as f64 / 10.;
+        let input = [Tensor::new(sh!([1]), vec![x])];
+        let truth = [Tensor::new(sh!([1]), vec![if i < 100 { 1. - x } else { x }])];
+        drifted |= learner.partial_fit_watched(&input, &truth, &mut ph).1 && i >= 100;
+    }
+    assert!(drifted);
+}
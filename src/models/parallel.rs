@@ -0,0 +1,144 @@
+//! Training independent models side by side, e.g. an ensemble or a hyperparameter sweep.
+//!
+//! Each model is trained on its own thread inside a bounded rayon pool, whose threads
+//! may be pinned to a disjoint subset of cores (on Linux). The layer kernels respect
+//! the size of the pool they run in. The dataset is simply borrowed by every model.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::parallel::ParallelTraining;
+//!     let inputs = vec![Tensor::new(sh!([1]), vec![1.]); 4];
+//!     let truths = vec![Tensor::new(sh!([1]), vec![2.]); 4];
+//!     let mut models: Vec<Sequential<f64>> = (0..3).map(|_| {
+//!         let mut nn = Sequential::new(Loss::MeanSquare);
+//!         nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+//!         nn
+//!     }).collect();
+//!     // a learning rate per model
+//!     let rates = [0.01, 0.05, 0.1];
+//!     let losses = ParallelTraining::new(models.len()).train(&mut models, |k, nn| {
+//!         (0..10).map(|_| nn.train_once(&inputs, &truths, 2, rates[k], false)).last().unwrap()
+//!     }).unwrap();
+//!     assert_eq!(losses.len(), 3);
+//! ```
+
+extern crate num_cpus;
+extern crate rayon;
+
+use crate::tensor::EasynnError;
+
+use std::io;
+use std::thread;
+
+#[derive(Debug, Copy, Clone)]
+pub struct ParallelTraining {
+    /// The size of the thread pool of each model
+    pub threads_per_model: usize,
+    /// Pin the threads of model k to the allowed cores from the `k * threads_per_model`-th,
+    /// wrapping around, only on Linux
+    pub pin_cores: bool,
+}
+
+impl ParallelTraining {
+    /// Share the cpus evenly among `models` models, without pinning
+    pub fn new(models: usize) -> Self {
+        ParallelTraining { threads_per_model: (num_cpus::get() / models.max(1)).max(1), pin_cores: false }
+    }
+
+    /// Run `train(k, model)` for every model k concurrently, returning the results in order,
+    /// failing before any training if the threads cannot be pinned
+    pub fn train<M, R, F>(&self, models: &mut [M], train: F) -> Result<Vec<R>, EasynnError>
+    where M: Send, R: Send, F: Fn(usize, &mut M) -> R + Sync {
+        let threads = self.threads_per_model.max(1);
+        let cores = if self.pin_cores { allowed_cores().map_err(pin_error)? } else { Vec::new() };
+        let (train, cores) = (&train, &cores);
+        thread::scope(|s| {
+            let handles: Vec<_> = models.iter_mut().enumerate().map(|(k, model)| {
+                s.spawn(move || {
+                    let pool = rayon::ThreadPoolBuilder::new()
+                        .num_threads(threads)
+                        .build()
+                        .expect("Failed to build the thread pool of a model!");
+                    if !cores.is_empty() {
+                        pool.broadcast(|c| pin_to_core(cores[(k * threads + c.index()) % cores.len()]))
+                            .into_iter().collect::<io::Result<()>>().map_err(pin_error)?;
+                    }
+                    Ok(pool.install(|| train(k, model)))
+                })
+            }).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+}
+
+fn pin_error(e: io::Error) -> EasynnError {
+    EasynnError::invalid("ParallelTraining::train", format!("cannot pin the threads: {}", e))
+}
+
+/// The cores the current thread may run on
+#[cfg(target_os = "linux")]
+fn allowed_cores() -> io::Result<Vec<usize>> {
+    // SAFETY: the set is zero-initialized and only written by sched_getaffinity
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize).filter(|c| libc::CPU_ISSET(*c, &set)).collect())
+    }
+}
+
+/// Pin the current thread to a core
+#[cfg(target_os = "linux")]
+fn pin_to_core(core: usize) -> io::Result<()> {
+    // SAFETY: the set is zero-initialized and only written by the libc macros
+    unsafe {
+        let mut set = std::mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cores() -> io::Result<Vec<usize>> {
+    Ok(Vec::new())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_core(_core: usize) -> io::Result<()> {
+    Ok(())
+}
+
+#[test]
+fn test_parallel_training() {
+    use crate::prelude::*;
+
+    let inputs: Vec<_> = (0..8).map(|i| Tensor::new(sh!([2]), vec![i as f64 / 8., 1.])).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(sh!([1]), vec![x.get([0]) * 3.])).collect();
+    let mut models: Vec<Sequential<f64>> = (0..4).map(|_| {
+        let mut nn = Sequential::new(Loss::MeanSquare);
+        nn.add(Dense::new(sh!([2]), sh!([1]), Activation::No));
+        nn
+    }).collect();
+    let config = ParallelTraining { threads_per_model: 2, pin_cores: true };
+    let pools = config.train(&mut models, |_, nn| {
+        for _ in 0..300 {
+            nn.train_once(&inputs, &truths, 4, 0.2, false);
+        }
+        rayon::current_num_threads()
+    }).unwrap();
+    assert_eq!(pools, vec![2; 4]);
+    for nn in &models {
+        assert!(nn.evaluate(&inputs, &truths) < 1e-2);
+    }
+    // each thread is pinned to one of the cores allowed to the caller
+    #[cfg(target_os = "linux")]
+    {
+        let allowed = allowed_cores().unwrap();
+        let pinned = config.train(&mut models, |_, _| allowed_cores().unwrap()).unwrap();
+        assert!(pinned.iter().all(|c| c.len() == 1 && allowed.contains(&c[0])));
+    }
+}
@@ -0,0 +1,105 @@
+//! Greedy layer-wise pretraining, for very small datasets.
+//!
+//! Every hidden layer is first trained as the encoder of an autoencoder
+//! reconstructing its own input, from the first layer up, then the whole stack
+//! is fine-tuned on the labelled samples.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::pretrain::{ greedy_pretrain, PretrainConfig };
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([4]), sh!([3]), Activation::Tanh));
+//!     nn.add(Dense::new(sh!([3]), sh!([1]), Activation::No));
+//!     let inputs = vec![Tensor::new(sh!([4]), vec![1., 0., 1., 0.]), Tensor::new(sh!([4]), vec![0., 1., 0., 1.])];
+//!     let truths = vec![Tensor::new(sh!([1]), vec![1.]), Tensor::new(sh!([1]), vec![0.])];
+//!     let report = greedy_pretrain(&mut nn, &inputs, &truths, &PretrainConfig::new(10, 10, 0.1));
+//!     assert_eq!(report.reconstruction.len(), 1);
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use crate::layers::dense::Dense;
+
+#[derive(Debug, Copy, Clone)]
+pub struct PretrainConfig<T: NumT> {
+    /// The epochs to train each autoencoder
+    pub pretrain_epochs: usize,
+    /// The epochs to fine-tune the whole stack
+    pub finetune_epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: T,
+    pub verbose: bool,
+}
+
+impl<T: NumT> PretrainConfig<T> {
+    pub fn new(pretrain_epochs: usize, finetune_epochs: usize, learning_rate: T) -> Self {
+        PretrainConfig::<T> { pretrain_epochs, finetune_epochs, batch_size: 1, learning_rate, verbose: false }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PretrainReport<T: NumT> {
+    /// The final reconstruction loss of each hidden layer
+    pub reconstruction: Vec<T>,
+    /// The loss of the last fine-tuning epoch
+    pub finetune_loss: T,
+}
+
+/// Pretrain all the layers of the model but the output layer, each as the encoder
+/// of an autoencoder with a linear decoder, then fine-tune the model
+#[cfg_attr(feature = "tracing", tracing::instrument(skip_all, fields(layers = model.len())))]
+pub fn greedy_pretrain<T: NumT + 'static>(model: &mut Sequential<T>, inputs: &[Tensor<T>], truths: &[Tensor<T>], config: &PretrainConfig<T>) -> PretrainReport<T> {
+    let hidden = model.len().saturating_sub(1);
+    let mut features = inputs.to_vec();
+    let mut reconstruction = Vec::<T>::new();
+    for i in 0..hidden {
+        // move the layer into an autoencoder and back
+        let encoder = model.layers_mut().remove(i);
+        let mut ae = Sequential::<T>::new(Loss::MeanSquare);
+        let (i_shape, o_shape) = (encoder.get_input_shape(), encoder.get_output_shape());
+        ae.layers_mut().push(encoder);
+        ae.add(Dense::new(&o_shape, &i_shape, Activation::No));
+        let mut loss = T::zero();
+        for epoch in 0..config.pretrain_epochs {
+            loss = ae.train_once(&features, &features, config.batch_size, config.learning_rate, false);
+            if config.verbose {
+                println!("[Pretrain layer {} epoch {}] Reconstruction loss: {}", i, epoch, loss);
+            }
+        }
+        reconstruction.push(loss);
+        let encoder = ae.layers_mut().remove(0);
+        features = features.iter().map(|f| encoder.forward_propagate(f, true).unwrap()).collect();
+        model.layers_mut().insert(i, encoder);
+    }
+    let mut finetune_loss = T::zero();
+    for epoch in 0..config.finetune_epochs {
+        finetune_loss = model.train_once(inputs, truths, config.batch_size, config.learning_rate, false);
+        if config.verbose {
+            println!("[Fine-tune epoch {}] Loss: {}", epoch, finetune_loss);
+        }
+    }
+    PretrainReport::<T> { reconstruction, finetune_loss }
+}
+
+#[test]
+fn test_greedy_pretrain() {
+    use crate::prelude::*;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(sh!([4]), sh!([4]), Activation::No));
+    nn.add(Dense::new(sh!([4]), sh!([2]), Activation::Tanh));
+    nn.add(Dense::new(sh!([2]), sh!([1]), Activation::No));
+    // the samples lie on a plane, which the autoencoders can learn
+    let inputs: Vec<_> = (0..8).map(|i| {
+        let (a, b) = ((i % 4) as f64 / 4., (i / 4) as f64 / 2.);
+        Tensor::new(sh!([4]), vec![a, b, a + b, a - b])
+    }).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(sh!([1]), vec![x.get([2])])).collect();
+    let before = nn.evaluate(&inputs, &truths);
+    let report = greedy_pretrain(&mut nn, &inputs, &truths, &PretrainConfig::new(300, 200, 0.05));
+    assert_eq!(report.reconstruction.len(), 2);
+    assert!(report.reconstruction[0] < 1e-2);
+    assert_eq!(nn.len(), 3);
+    assert_eq!(nn.layers()[1].get_output_shape(), Shape::new([2]));
+    assert!(nn.evaluate(&inputs, &truths) < before);
+}
@@ -0,0 +1,329 @@
+//! Magnitude pruning, the lottery-ticket search of sparse trainable subnetworks and the
+//! structured pruning of whole units.
+//!
+//! A `PruningMask` keeps or prunes each element of the prunable parameters, by default the
+//! weights of the dense, convolution and embedding layers, never the biases. Pruned
+//! weights are zeroed by `PruningMask::apply`, and stay zero when trained by
+//! `Sequential::train_once_masked`.
+//!
+//! `LotteryTicket::search` runs iterative magnitude pruning: from the initial weights,
+//! it trains, prunes the smallest remaining weights, rewinds the others to their initial
+//! values and repeats, reporting the loss and the accuracy of each sparsity.
+//!
+//! Structured pruning removes whole units instead, the neurons of dense layers or the
+//! filters of convolutions, shrinking the layer and the inputs of the next layer of
+//! weights, through the layers working on each unit apart in between (e.g. `BatchNorm`,
+//! the pooling or a flattening `Reshape`), for a smaller and faster model.
+//! `Sequential::prune_smallest_units` removes the units of the smallest weights.
+//! The snapshots and the optimizer states of the model no longer fit it after that.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::pruning::*;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([4]), sh!([1]), Activation::No));
+//!     let inputs: Vec<_> = (0..8).map(|i| Tensor::new(sh!([4]), vec![i as f64 / 8., 1., 0., -1.])).collect();
+//!     let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(sh!([1]), vec![2. * x.get([0])])).collect();
+//!     let search = LotteryTicket { rounds: 3, ..LotteryTicket::new(50, 4, 0.1) };
+//!     let (mask, report) = search.search(&mut nn, (&inputs, &truths), (&inputs, &truths));
+//!     assert_eq!(report.len(), 3);
+//!     assert!(mask.sparsity() > 0.3);
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use crate::metrics::{ Accuracy, Metric };
+
+/// The kinds of layers whose weights, their first parameter, are prunable by default
+pub const PRUNABLE_KINDS: &[&str] = &["dense", "conv2d", "embedding"];
+
+/// Which elements of the parameters of a model are kept, in the layout of `Snapshot`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PruningMask {
+    /// For each layer and each of its parameters, whether each element is kept
+    pub keep: Vec<Vec<Vec<bool>>>,
+    /// For each layer and each of its parameters, whether it may be pruned
+    pub prunable: Vec<Vec<bool>>,
+}
+
+impl PruningMask {
+    /// Keep everything, the weights of the `PRUNABLE_KINDS` layers prunable
+    pub fn new<T: NumT>(model: &Sequential<T>) -> Self {
+        Self::with_prunable(model, |layer, slot| slot == 0 && PRUNABLE_KINDS.contains(&layer.name().as_str()))
+    }
+    /// Keep everything, the parameters of the slots for which `prunable(layer, slot)` prunable
+    pub fn with_prunable<T: NumT, F: Fn(&dyn Layer<T>, usize) -> bool>(model: &Sequential<T>, prunable: F) -> Self {
+        let layers = model.layers();
+        PruningMask {
+            keep: layers.iter().map(|l| l.parameters().iter().map(|p| vec![true; p.len()]).collect()).collect(),
+            prunable: layers.iter().map(|l| (0..l.parameters().len()).map(|s| prunable(l.as_ref(), s)).collect()).collect(),
+        }
+    }
+    /// The keep flags of the prunable parameters
+    fn prunable_flags(&self) -> impl Iterator<Item = &bool> {
+        self.keep.iter().zip(self.prunable.iter())
+            .flat_map(|(l, p)| l.iter().zip(p.iter()).filter(|(_, p)| **p).flat_map(|(k, _)| k.iter()))
+    }
+    /// The count of prunable elements
+    pub fn prunable_count(&self) -> usize {
+        self.prunable_flags().count()
+    }
+    /// The count of pruned elements
+    pub fn pruned_count(&self) -> usize {
+        self.prunable_flags().filter(|k| !**k).count()
+    }
+    /// The fraction of the prunable elements pruned
+    pub fn sparsity(&self) -> f64 {
+        self.pruned_count() as f64 / self.prunable_count().max(1) as f64
+    }
+    /// Whether the mask is of the parameters of the model
+    fn fits<T: NumT>(&self, model: &Sequential<T>) -> bool {
+        self.keep.len() == model.len() && model.layers().iter().zip(self.keep.iter()).all(|(l, k)| {
+            let p = l.parameters();
+            p.len() == k.len() && p.iter().zip(k.iter()).all(|(p, k)| p.len() == k.len())
+        })
+    }
+    /// Zero the pruned elements of the model
+    pub fn apply<T: NumT>(&self, model: &mut Sequential<T>) -> Result<()> {
+        if !self.fits(model) {
+            return Err(EasynnError::invalid("PruningMask::apply", "the mask is of another architecture"));
+        }
+        for (l, k) in model.layers_mut().iter_mut().zip(self.keep.iter()) {
+            for (p, k) in l.parameters_mut().into_iter().zip(k.iter()) {
+                p.iter_mut().zip(k.iter()).filter(|(_, k)| !**k).for_each(|(x, _)| *x = T::zero());
+            }
+        }
+        Ok(())
+    }
+    /// Prune the `fraction` of the remaining prunable elements of the smallest magnitude
+    /// in the model, across all the layers, and return the count pruned
+    pub fn prune_magnitude<T: NumT>(&mut self, model: &Sequential<T>, fraction: f64) -> Result<usize> {
+        if !self.fits(model) {
+            return Err(EasynnError::invalid("PruningMask::prune_magnitude", "the mask is of another architecture"));
+        }
+        if !(0. ..=1.).contains(&fraction) {
+            return Err(EasynnError::invalid("PruningMask::prune_magnitude", format!("the fraction {} is not in [0, 1]", fraction)));
+        }
+        // (magnitude, layer, slot, element) of the remaining prunable elements
+        let mut remaining: Vec<(T, usize, usize, usize)> = Vec::new();
+        for (li, (l, keep)) in model.layers().iter().zip(self.keep.iter()).enumerate() {
+            for (si, (p, k)) in l.parameters().iter().zip(keep.iter()).enumerate().filter(|(si, _)| self.prunable[li][*si]) {
+                remaining.extend(p.iter().zip(k.iter()).enumerate().filter(|(_, (_, k))| **k).map(|(e, (x, _))| (x.abs(), li, si, e)));
+            }
+        }
+        let count = (remaining.len() as f64 * fraction).round() as usize;
+        remaining.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        for (_, l, s, e) in remaining.into_iter().take(count) {
+            self.keep[l][s][e] = false;
+        }
+        Ok(count)
+    }
+}
+
+impl<T: NumT> Sequential<T> {
+    /// Trains the model by an epoch like `train_once`, zeroing the pruned weights after
+    /// each batch so that they stay pruned, and return the mean loss of the batches
+    pub fn train_once_masked(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, learning_rate: T, mask: &PruningMask) -> Result<T> {
+        mask.apply(self)?;
+        let mut loss = T::zero();
+        let mut batches = 0;
+        for (x, y) in inputs.chunks(batch_size).zip(truths.chunks(batch_size)) {
+            loss += self.train_once(x, y, batch_size, learning_rate, false);
+            mask.apply(self)?;
+            batches += 1;
+        }
+        Ok(loss / T::from(batches.max(1)).unwrap())
+    }
+
+    /// The L2 norm of the weights of each output unit of layer `index`,
+    /// e.g. of each neuron of a dense layer or each filter of a convolution
+    pub fn unit_norms(&self, index: usize) -> Result<Vec<T>> {
+        let layer = match self.layers().get(index) {
+            Some(l) if l.supports_remap() => l,
+            _ => return Err(EasynnError::invalid("unit_norms", format!("layer {} has no units of weights", index))),
+        };
+        let params = layer.parameters();
+        let units = layer.get_output_shape()[0];
+        Ok(params[0].chunks(params[0].len() / units).map(|w| w.iter().map(|x| *x * *x).sum::<T>().sqrt()).collect())
+    }
+    /// Keep only the output units `keep` of layer `index`, in increasing order, removing the
+    /// others with their inputs to the next layer of weights
+    pub fn prune_units(&mut self, index: usize, keep: &[usize]) -> Result<()> {
+        if keep.is_empty() || keep.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(EasynnError::invalid("prune_units", "the kept units should be some distinct units in increasing order"));
+        }
+        self.remap_output_units("prune_units", index, keep)
+    }
+    /// Remove the `count` output units of layer `index` of the smallest `unit_norms`,
+    /// returning the indices of the kept units
+    pub fn prune_smallest_units(&mut self, index: usize, count: usize) -> Result<Vec<usize>> {
+        let norms = self.unit_norms(index)?;
+        if count >= norms.len() {
+            return Err(EasynnError::invalid("prune_smallest_units", format!("cannot remove {} of the {} units", count, norms.len())));
+        }
+        let mut order: Vec<usize> = (0..norms.len()).collect();
+        order.sort_by(|a, b| norms[*a].partial_cmp(&norms[*b]).unwrap_or(std::cmp::Ordering::Equal));
+        let mut keep = order.split_off(count);
+        keep.sort();
+        self.prune_units(index, &keep)?;
+        Ok(keep)
+    }
+}
+
+/// The outcome of a round of `LotteryTicket::search`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LotteryRound<T: NumT> {
+    pub round: usize,
+    /// The fraction of the prunable weights pruned when trained
+    pub sparsity: f64,
+    /// The mean training loss of the last epoch
+    pub train_loss: T,
+    /// The loss on the validation set
+    pub val_loss: T,
+    /// The accuracy on the validation set, if the truths are classes, see `metrics::Accuracy`
+    pub accuracy: Option<T>,
+}
+
+/// Iterative magnitude pruning with rewinding to the initial weights
+#[derive(Debug, Clone)]
+pub struct LotteryTicket<T: NumT> {
+    /// The count of trainings, the first dense
+    pub rounds: usize,
+    /// The fraction of the remaining weights pruned after each training, 0.2 by default
+    pub prune_fraction: f64,
+    pub epochs: usize,
+    pub batch_size: usize,
+    pub learning_rate: T,
+}
+
+impl<T: NumT> LotteryTicket<T> {
+    /// 5 rounds, each training `epochs` epochs and then pruning 20% of the remaining weights
+    pub fn new(epochs: usize, batch_size: usize, learning_rate: T) -> Self {
+        LotteryTicket { rounds: 5, prune_fraction: 0.2, epochs, batch_size, learning_rate }
+    }
+
+    /// Search the winning ticket of the model from its current, initial weights: each
+    /// round rewinds the kept weights to them, trains, reports and prunes but the last.
+    /// The model is left trained at the last mask, which is returned with the report.
+    pub fn search(&self, model: &mut Sequential<T>, train: (&[Tensor<T>], &[Tensor<T>]), validation: (&[Tensor<T>], &[Tensor<T>])) -> (PruningMask, Vec<LotteryRound<T>>) {
+        let initial = model.snapshot();
+        let mut mask = PruningMask::new(model);
+        let mut report = Vec::with_capacity(self.rounds);
+        for round in 0..self.rounds {
+            model.load_snapshot(&initial).unwrap();
+            let mut train_loss = T::zero();
+            for _ in 0..self.epochs {
+                train_loss = model.train_once_masked(train.0, train.1, self.batch_size, self.learning_rate, &mask).unwrap();
+            }
+            let mut accuracy = Accuracy::<T>::new();
+            let preds: Vec<_> = validation.0.iter().map(|x| model.predict(x).unwrap()).collect();
+            report.push(LotteryRound {
+                round,
+                sparsity: mask.sparsity(),
+                train_loss,
+                val_loss: model.evaluate(validation.0, validation.1),
+                accuracy: accuracy.update(&preds, validation.1).ok().map(|_| accuracy.result()),
+            });
+            if round + 1 < self.rounds {
+                mask.prune_magnitude(model, self.prune_fraction).unwrap();
+            }
+        }
+        (mask, report)
+    }
+}
+
+#[test]
+fn test_pruning() {
+    use crate::layers::dense::Dense;
+    use crate::layers::batch_norm::BatchNorm;
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([2]), &Shape::new([3]), Activation::No));
+    nn.add(BatchNorm::new(&Shape::new([3]), Activation::No));
+    nn.layers_mut()[0].parameters_mut()[0].copy_from_slice(&[0.5, -0.1, 0.3, -0.6, 0.2, 0.05]);
+    let mut mask = PruningMask::new(&nn);
+    assert_eq!(mask.prunable, [vec![true, false], vec![false, false]]);
+    assert_eq!((mask.prunable_count(), mask.sparsity()), (6, 0.));
+    // the 3 smallest weights by magnitude, then half of the 3 remaining
+    assert_eq!(mask.prune_magnitude(&nn, 0.5).unwrap(), 3);
+    assert_eq!(mask.keep[0][0], [true, false, true, true, false, false]);
+    mask.apply(&mut nn).unwrap();
+    assert_eq!(nn.layers()[0].parameters()[0], [0.5, 0., 0.3, -0.6, 0., 0.]);
+    assert_eq!(mask.prune_magnitude(&nn, 0.5).unwrap(), 2);
+    assert_eq!(mask.keep[0][0], [false, false, false, true, false, false]);
+    assert!((mask.sparsity() - 5. / 6.).abs() < 1e-12);
+    assert!(mask.prune_magnitude(&nn, 1.5).is_err());
+
+    // the pruned weights stay zero through training
+    let inputs: Vec<_> = (0..6).map(|i| Tensor::new(&Shape::new([2]), vec![i as f64, 1.])).collect();
+    let mut model = Sequential::<f64>::new(Loss::MeanSquare);
+    model.add(Dense::new(&Shape::new([2]), &Shape::new([2]), Activation::No));
+    let mut mask = PruningMask::new(&model);
+    mask.keep[0][0][1] = false;
+    model.train_once_masked(&inputs, &inputs, 2, 0.01, &mask).unwrap();
+    assert_eq!(model.layers()[0].parameters()[0][1], 0.);
+    assert!(mask.apply(&mut nn).is_err());
+
+    // the rounds rewind to the initial weights, pruning 20% each
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(&Shape::new([2]), [1., 0.].iter().map(|c| c * x.get([0]) / 5.).collect())).collect();
+    let initial = model.snapshot();
+    let search = LotteryTicket { rounds: 3, ..LotteryTicket::new(20, 3, 0.02) };
+    let (mask, report) = search.search(&mut model, (&inputs, &truths), (&inputs, &truths));
+    assert_eq!(report.iter().map(|r| r.round).collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(report[1].sparsity, 0.25);
+    assert_eq!(mask.pruned_count(), 2);
+    assert!(report.iter().all(|r| r.val_loss.is_finite() && r.accuracy.is_some()));
+    // the kept weights were trained from the initial ones, the pruned zeroed
+    let trained = model.snapshot();
+    for ((w, w0), k) in trained.parameters[0][0].iter().zip(initial.parameters[0][0].iter()).zip(mask.keep[0][0].iter()) {
+        assert!(if *k { w != w0 } else { *w == 0. });
+    }
+}
+
+#[test]
+fn test_structured_pruning() {
+    use crate::layers::dense::Dense;
+    use crate::layers::conv::Conv2D;
+    use crate::layers::batch_norm::BatchNorm;
+    use crate::layers::pooling::MaxPool2D;
+    use crate::layers::reshape::Reshape;
+    let close = |a: &Tensor<f64>, b: &Tensor<f64>| a.as_slice().iter().zip(b.as_slice()).all(|(x, y)| (x - y).abs() < 1e-12);
+
+    // the units of zero weights and bias output 0 through ReLU and the batch norm, so that
+    // removing them keeps the function
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&Shape::new([3]), &Shape::new([4]), Activation::Relu));
+    nn.add(BatchNorm::new(&Shape::new([4]), Activation::No));
+    nn.add(Dense::new(&Shape::new([4]), &Shape::new([2]), Activation::No));
+    for unit in [1, 3] {
+        nn.layers_mut()[0].parameters_mut()[0][unit * 3..(unit + 1) * 3].fill(0.);
+        nn.layers_mut()[0].parameters_mut()[1][unit] = 0.;
+    }
+    let input = Tensor::new(&Shape::new([3]), vec![0.5, -1., 2.]);
+    let before = nn.predict(&input).unwrap();
+    assert_eq!(nn.unit_norms(0).unwrap()[3], 0.);
+    assert_eq!(nn.prune_smallest_units(0, 2).unwrap(), [0, 2]);
+    assert_eq!(nn.layers()[1].get_input_shape(), Shape::new([2]));
+    assert_eq!(nn.layers()[2].get_weight_count(), 4);
+    assert!(close(&nn.predict(&input).unwrap(), &before));
+    assert!(nn.prune_units(0, &[1, 0]).is_err());
+    assert!(nn.prune_units(0, &[2]).is_err());
+    assert!(nn.prune_smallest_units(0, 2).is_err());
+    assert!(nn.prune_units(2, &[0]).is_err());
+
+    // a filter, through the pooling and the flattening
+    let mut cnn = Sequential::<f64>::new(Loss::MeanSquare);
+    cnn.add(Conv2D::new(&Shape::new([2, 4, 4]), 3, (3, 3), (1, 1), Padding::Same, Activation::Relu));
+    cnn.add(MaxPool2D::new(&Shape::new([3, 4, 4]), (2, 2), (2, 2)));
+    cnn.add(Reshape::new(&Shape::new([3, 2, 2]), &Shape::new([12])));
+    cnn.add(Dense::new(&Shape::new([12]), &Shape::new([2]), Activation::No));
+    cnn.layers_mut()[0].parameters_mut()[0][18..36].fill(0.);
+    cnn.layers_mut()[0].parameters_mut()[1][1] = 0.;
+    let input = Tensor::new(&Shape::new([2, 4, 4]), (0..32).map(|i| (i as f64 * 0.7).sin()).collect());
+    let before = cnn.predict(&input).unwrap();
+    let count = |m: &Sequential<f64>| m.parameters().iter().map(|p| p.len()).sum::<usize>();
+    let weights = count(&cnn);
+    assert_eq!(cnn.prune_smallest_units(0, 1).unwrap(), [0, 2]);
+    assert_eq!(cnn.layers()[2].get_output_shape(), Shape::new([8]));
+    assert_eq!(count(&cnn), weights - 18 - 1 - 4 * 2);
+    assert!(close(&cnn.predict(&input).unwrap(), &before));
+}
@@ -0,0 +1,194 @@
+//! Matrix factorization for recommendation from implicit feedback, e.g. clicks or purchases.
+//!
+//! The score of item i for user u is `b + b_u + b_i + p_u . q_i`, the user and the item
+//! vectors being the rows of two `Embedding` tables. It is trained by BPR (Bayesian
+//! personalized ranking): for a user, an item interacted with should score above an
+//! item not interacted with, the loss of such a triple being
+//! `-ln sigmoid(x_ui - x_uj)` plus the L2 penalty of the parameters involved.
+//! The recommendations are evaluated by `metrics::hit_rate_at_k` and `metrics::ndcg_at_k`.
+//!
+//! ```rust
+//!     use easynn::models::recommender::*;
+//!     use easynn::metrics::hit_rate_at_k;
+//!     let interactions = [(0, 0), (0, 1), (1, 2), (1, 3)];
+//!     let mut mf = MatrixFactorization::<f64>::new(2, 4, 8);
+//!     let mut rng = rand::thread_rng();
+//!     for _ in 0..100 {
+//!         let triples = sample_bpr_triples(&interactions, 4, &mut rng);
+//!         mf.train_bpr(&triples, 0.1, 0.001).unwrap();
+//!     }
+//!     let rankings = vec![mf.recommend(0, 2, &[]).unwrap(), mf.recommend(1, 2, &[]).unwrap()];
+//!     assert!(hit_rate_at_k(&rankings, &[vec![0, 1], vec![2, 3]], 2).unwrap() > 0.);
+//! ```
+
+use crate::layers::*;
+use crate::layers::embedding::Embedding;
+
+use rand::Rng;
+
+use std::collections::HashSet;
+
+fn sigmoid<T: NumT>(x: T) -> T {
+    T::one() / (T::one() + (-x).exp())
+}
+
+/// The user and item vectors and biases, see the module
+#[derive(Debug)]
+pub struct MatrixFactorization<T: NumT> {
+    pub users: Embedding<T>,
+    pub items: Embedding<T>,
+    pub user_bias: Vec<T>,
+    pub item_bias: Vec<T>,
+    pub global_bias: T,
+}
+
+impl<T: NumT> MatrixFactorization<T> {
+    /// The factorization of `users` users and `items` items into vectors of `dim`,
+    /// drawn like `Embedding::new`, the biases zero
+    pub fn new(users: usize, items: usize, dim: usize) -> Self {
+        MatrixFactorization {
+            users: Embedding::new(&Shape::new([1]), users, dim),
+            items: Embedding::new(&Shape::new([1]), items, dim),
+            user_bias: vec![T::zero(); users],
+            item_bias: vec![T::zero(); items],
+            global_bias: T::zero(),
+        }
+    }
+    pub fn user_count(&self) -> usize {
+        self.users.vocab
+    }
+    pub fn item_count(&self) -> usize {
+        self.items.vocab
+    }
+
+    fn check(&self, op: &'static str, user: usize, items: &[usize]) -> Result<()> {
+        if user >= self.user_count() {
+            return Err(EasynnError::invalid(op, format!("no user {} of {}", user, self.user_count())));
+        }
+        match items.iter().find(|i| **i >= self.item_count()) {
+            Some(i) => Err(EasynnError::invalid(op, format!("no item {} of {}", i, self.item_count()))),
+            None => Ok(()),
+        }
+    }
+    fn score_unchecked(&self, user: usize, item: usize) -> T {
+        let dot = self.users.vector(user).iter().zip(self.items.vector(item)).map(|(p, q)| *p * *q).sum::<T>();
+        self.global_bias + self.user_bias[user] + self.item_bias[item] + dot
+    }
+
+    /// The predicted preference of the user for the item
+    pub fn score(&self, user: usize, item: usize) -> Result<T> {
+        self.check("score", user, &[item])?;
+        Ok(self.score_unchecked(user, item))
+    }
+
+    /// Descend by SGD on each (user, positive item, negative item) triple in order,
+    /// with the L2 penalty `reg` of the vectors and the item biases, returning the mean
+    /// BPR loss without the penalty
+    pub fn train_bpr(&mut self, triples: &[(usize, usize, usize)], rate: T, reg: T) -> Result<T> {
+        let dim = self.users.dim;
+        let mut sum = T::zero();
+        for (u, i, j) in triples.iter().copied() {
+            self.check("train_bpr", u, &[i, j])?;
+            // the global and the user biases cancel in the difference
+            let x = self.score_unchecked(u, i) - self.score_unchecked(u, j);
+            // -ln sigmoid(x), not overflowing
+            sum += (-x).max(T::zero()) + (-x.abs()).exp().ln_1p();
+            // d/dx -ln sigmoid(x)
+            let g = sigmoid(x) - T::one();
+            let p: Vec<T> = self.users.vector(u).to_vec();
+            let (qi, qj): (Vec<T>, Vec<T>) = (self.items.vector(i).to_vec(), self.items.vector(j).to_vec());
+            for (k, ((p, qi), qj)) in p.iter().zip(qi.iter()).zip(qj.iter()).enumerate() {
+                self.users.table[u * dim + k] -= rate * (g * (*qi - *qj) + reg * *p);
+                self.items.table[i * dim + k] -= rate * (g * *p + reg * *qi);
+                self.items.table[j * dim + k] -= rate * (-g * *p + reg * *qj);
+            }
+            let (bi, bj) = (self.item_bias[i], self.item_bias[j]);
+            self.item_bias[i] -= rate * (g + reg * bi);
+            self.item_bias[j] -= rate * (-g + reg * bj);
+        }
+        Ok(sum / T::from(triples.len().max(1)).unwrap())
+    }
+
+    /// The `k` best scored items for the user, best first, other than the excluded ones,
+    /// e.g. those seen in training; fewer if there are not enough items
+    pub fn recommend(&self, user: usize, k: usize, exclude: &[usize]) -> Result<Vec<usize>> {
+        self.check("recommend", user, exclude)?;
+        let exclude: HashSet<usize> = exclude.iter().copied().collect();
+        let mut scored: Vec<(usize, T)> = (0..self.item_count()).filter(|i| !exclude.contains(i))
+            .map(|i| (i, self.score_unchecked(user, i))).collect();
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal).then(a.0.cmp(&b.0)));
+        Ok(scored.into_iter().take(k).map(|(i, _)| i).collect())
+    }
+}
+
+/// One (user, positive, negative) triple of each interaction, the negative drawn
+/// uniformly among the `items` items the user has not interacted with; the users
+/// having interacted with every item are skipped
+pub fn sample_bpr_triples<R: Rng>(interactions: &[(usize, usize)], items: usize, rng: &mut R) -> Vec<(usize, usize, usize)> {
+    let seen: HashSet<(usize, usize)> = interactions.iter().copied().collect();
+    let mut per_user = std::collections::HashMap::<usize, usize>::new();
+    for (u, _) in seen.iter() {
+        *per_user.entry(*u).or_insert(0) += 1;
+    }
+    interactions.iter().filter(|(u, _)| per_user[u] < items).map(|(u, i)| {
+        let j = loop {
+            let j = rng.gen_range(0..items);
+            if !seen.contains(&(*u, j)) {
+                break j;
+            }
+        };
+        (*u, *i, j)
+    }).collect()
+}
+
+#[test]
+fn test_matrix_factorization() {
+    // two groups of users, each liking its own half of the items
+    let (users, items) = (8, 10);
+    let half = |u: usize| if u < users / 2 { 0 } else { items / 2 };
+    // hold out one item of the half of each user, liked by the others of its group
+    let held_out: Vec<Vec<usize>> = (0..users).map(|u| vec![half(u) + u % (users / 2)]).collect();
+    let interactions: Vec<(usize, usize)> = (0..users).flat_map(|u| {
+        (half(u)..half(u) + items / 2).filter(|i| *i != held_out[u][0]).map(|i| (u, i)).collect::<Vec<_>>()
+    }).collect();
+
+    let mut mf = MatrixFactorization::<f64>::new(users, items, 4);
+    let mut rng = rand::thread_rng();
+    let first = mf.train_bpr(&sample_bpr_triples(&interactions, items, &mut rng), 0.1, 0.001).unwrap();
+    let mut last = first;
+    for _ in 0..300 {
+        last = mf.train_bpr(&sample_bpr_triples(&interactions, items, &mut rng), 0.1, 0.001).unwrap();
+    }
+    assert!(last < first);
+    let rankings: Vec<Vec<usize>> = (0..users).map(|u| {
+        let seen: Vec<usize> = interactions.iter().filter(|(v, _)| *v == u).map(|(_, i)| *i).collect();
+        mf.recommend(u, 3, &seen).unwrap()
+    }).collect();
+    assert!(rankings.iter().all(|r| r.len() == 3));
+    assert!(crate::metrics::hit_rate_at_k(&rankings, &held_out, 1).unwrap() > 0.8);
+    assert!(crate::metrics::ndcg_at_k(&rankings, &held_out, 3).unwrap() > 0.8);
+
+    assert!(mf.score(users, 0).is_err());
+    assert!(mf.train_bpr(&[(0, 0, items)], 0.1, 0.).is_err());
+    // a user of every item has no negative to draw
+    assert!(sample_bpr_triples(&[(0, 0), (0, 1)], 2, &mut rng).is_empty());
+}
+
+#[test]
+fn test_bpr_step() {
+    // one user and two items of known vectors
+    let mut mf = MatrixFactorization::<f64>::new(1, 2, 2);
+    mf.users.table = vec![1., 0.5];
+    mf.items.table = vec![0.2, -0.4, 0.6, 0.2];
+    mf.item_bias = vec![0.1, -0.1];
+    let x = mf.score(0, 0).unwrap() - mf.score(0, 1).unwrap();
+    assert!((x - -0.5).abs() < 1e-12);
+    let loss = mf.train_bpr(&[(0, 0, 1)], 0.5, 0.).unwrap();
+    assert!((loss - (1. + x.exp()).ln() + x).abs() < 1e-12);
+    // without the penalty each bias moves by the rate times sigmoid(-x)
+    let step = 0.5 / (1. + x.exp());
+    assert!((mf.item_bias[0] - (0.1 + step)).abs() < 1e-12);
+    assert!((mf.item_bias[1] - (-0.1 - step)).abs() < 1e-12);
+    assert!(mf.score(0, 0).unwrap() - mf.score(0, 1).unwrap() > x);
+    assert_eq!(mf.train_bpr(&[], 0.5, 0.).unwrap(), 0.);
+}
@@ -0,0 +1,96 @@
+//! L1 and L2 penalties of the weights, added to the loss of a model to limit overfitting.
+//!
+//! The penalty of a weight `w` is `l1 * |w| + l2 / 2 * w^2`, summed over the weights the
+//! layers regularize, see `Layer::regularized`, by default those named `weight` and not
+//! the biases nor the scales of the normalizations. The penalty is minimized along with
+//! the loss: its gradient is applied after each descent by the learning rate, and added
+//! to the gradients given to an optimizer. For L2 it is the weight decay of SGD.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::regularization::Regularization;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([2]), sh!([1]), Activation::No));
+//!     nn.regularization = Regularization::l2(1e-3);
+//!     let inputs = vec![Tensor::new(sh!([2]), vec![1., 0.])];
+//!     let truths = vec![Tensor::new(sh!([1]), vec![2.])];
+//!     let outcome = nn.train_once_controlled(&inputs, &truths, 1, 0.1, false, &Default::default(), |_| {});
+//!     assert_eq!(outcome.penalty, nn.penalty());
+//! ```
+
+use crate::tensor::*;
+
+/// The strengths of the L1 and the L2 penalties, none by default
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Regularization<T: NumT> {
+    pub l1: T,
+    pub l2: T,
+}
+
+impl<T: NumT> Default for Regularization<T> {
+    fn default() -> Self {
+        Regularization::none()
+    }
+}
+
+impl<T: NumT> Regularization<T> {
+    pub fn none() -> Self {
+        Regularization { l1: T::zero(), l2: T::zero() }
+    }
+    /// The L1 penalty (lasso), driving the small weights to zero
+    pub fn l1(l1: T) -> Self {
+        Regularization { l1, l2: T::zero() }
+    }
+    /// The L2 penalty, or weight decay
+    pub fn l2(l2: T) -> Self {
+        Regularization { l1: T::zero(), l2 }
+    }
+    /// Both penalties (elastic net)
+    pub fn elastic_net(l1: T, l2: T) -> Self {
+        Regularization { l1, l2 }
+    }
+    pub fn is_none(&self) -> bool {
+        self.l1 == T::zero() && self.l2 == T::zero()
+    }
+    /// The penalty of the weights
+    pub fn penalty(&self, weights: &[T]) -> T {
+        let half = T::from(0.5).unwrap();
+        weights.iter().map(|w| self.l1 * w.abs() + half * self.l2 * *w * *w).sum()
+    }
+    /// The derivative of the penalty of a weight, taking 0 as the slope of `|w|` at 0
+    pub fn gradient(&self, w: T) -> T {
+        let sign = if w > T::zero() { T::one() } else if w < T::zero() { -T::one() } else { T::zero() };
+        self.l1 * sign + self.l2 * w
+    }
+}
+
+#[test]
+fn test_regularization() {
+    use crate::prelude::*;
+    let r = Regularization::<f64>::elastic_net(0.1, 2.);
+    assert!((r.penalty(&[1., -2., 0.]) - (0.3 + 5.)).abs() < 1e-12);
+    assert_eq!([r.gradient(-2.), r.gradient(0.), r.gradient(0.5)], [-4.1, 0., 1.1]);
+    assert!(Regularization::<f32>::default().is_none());
+
+    // the weights shrink, not the biases nor the batch norm
+    let s = Shape::new([2]);
+    let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-12);
+    assert_eq!(crate::layers::Layer::<f64>::regularized(&BatchNorm::new(&s, Activation::No)), [false, false]);
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&s, &s, Activation::No));
+    nn.layers_mut()[0].parameters_mut()[0].copy_from_slice(&[1., -1., 0.5, 2.]);
+    nn.layers_mut()[0].parameters_mut()[1].copy_from_slice(&[3., 3.]);
+    assert_eq!(nn.penalty(), 0.);
+    nn.regularization = Regularization::l2(0.5);
+    assert!((nn.penalty() - 0.25 * 6.25).abs() < 1e-12);
+    // no data gradient of the weights at a zero input
+    let x = vec![Tensor::new(&s, vec![0., 0.])];
+    nn.train_once(&x, &x, 1, 0.1, false);
+    assert!(close(nn.parameter("dense0.weight").unwrap(), &[0.95, -0.95, 0.475, 1.9]));
+
+    // the L1 penalty added to the gradients given to an optimizer
+    nn.regularization = Regularization::l1(0.5);
+    nn.layers_mut()[0].parameters_mut()[0].copy_from_slice(&[1., -1., 0.5, 0.]);
+    nn.train_once_optimized(&x, &x, 1, &mut crate::optim::Sgd::new(0.1), false);
+    assert!(close(nn.parameter("dense0.weight").unwrap(), &[0.95, -0.95, 0.45, 0.]));
+}
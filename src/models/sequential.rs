@@ -2,15 +2,44 @@
 
 use crate::models::*;
 extern crate itertools;
-extern crate rayon;
-use rayon::prelude::*;
 use itertools::Itertools;
+use rand::Rng;
 
 use crate::layers::*;
+use crate::parallel;
+use crate::models::inference::InferenceExecutor;
+use crate::models::control::{ TrainingControl, EpochOutcome };
+use crate::models::regularization::Regularization;
+use crate::optim::{ Optimizer, Sam, GradientClipping };
+
+/// How the parameters descend after each batch, the plain SGD of `Layer::descend` or an optimizer
+enum Update<'a, T: NumT> {
+    Rate(T),
+    Optimizer(&'a mut dyn Optimizer<T>),
+}
+
+impl<T: NumT> Update<'_, T> {
+    #[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+    fn learning_rate(&self) -> T {
+        match self {
+            Update::Rate(r) => *r,
+            Update::Optimizer(o) => o.learning_rate(),
+        }
+    }
+}
+
+/// The control checked between batches, and the callback when paused
+type Control<'a, T> = (&'a TrainingControl<T>, &'a mut dyn FnMut(&Sequential<T>));
+/// The observer of the index and the mean loss of each trained batch, stopping the epoch when false
+type OnBatch<'a, T> = &'a mut dyn FnMut(usize, T, &Sequential<T>) -> bool;
 
 pub struct Sequential<T: NumT> {
     seq: Vec<Box<dyn Layer<T>>>,
     pub loss: Loss,
+    /// The penalty of the weights minimized with the loss when training, none by default
+    pub regularization: Regularization<T>,
+    /// How the gradients are clipped before each update when training, none by default
+    pub clipping: GradientClipping<T>,
 }
 
 impl<T: NumT> Sequential<T> {
@@ -18,123 +47,527 @@ impl<T: NumT> Sequential<T> {
         Sequential::<T> {
             seq: Vec::<Box<dyn Layer<T>>>::new(),
             loss: l,
+            regularization: Regularization::none(),
+            clipping: GradientClipping::none(),
         }
     }
     pub fn add<L: 'static + Layer<T>>(&mut self, layer: L) {
         self.seq.push(Box::new(layer));
     }
-}
-
-impl<T: NumT> Model<T> for Sequential<T> {
-    fn predict(&self, input: &Tensor<T>) -> Result<Tensor<T>> {
-        let mut last_output: Box<Tensor<T>>;
-        let mut output: Box<Tensor<T>> = Box::new((*input).clone());
-        for layer in &self.seq {
-            last_output = output;
-            output = Box::new(layer.forward_propagate(&last_output, true).unwrap());
-        }
-        Ok(*output)
+    pub(crate) fn layers_mut(&mut self) -> &mut Vec<Box<dyn Layer<T>>> {
+        &mut self.seq
     }
-    fn propagate_sample(&self, input: &Tensor<T>, truth: &Tensor<T>) -> Result<(Vec<Tensor<T>>, Vec<Tensor<T>>)> {
-        // forward propagate
+    /// Forward propagate for training, returning the z and the a of each layer,
+    /// with the input as the first a
+    pub(crate) fn forward_train_all(&self, input: &Tensor<T>) -> Result<Propagation<T>> {
         let mut a_lst = Vec::<Tensor<T>>::new();
         let mut z_l = Vec::<Tensor<T>>::new();
         a_lst.push((*input).clone());
-        for layer in &self.seq {
-            let z_now = layer.forward_propagate(&a_lst.last().unwrap(), false)?;
+        for (l, layer) in self.seq.iter().enumerate() {
+            let (z_now, a_now) = layer.forward_train(a_lst.last().unwrap()).map_err(at_layer(l, layer.as_ref()))?;
             z_l.push(z_now);
-            a_lst.push(layer.activate(&z_l.last().unwrap())?);
+            a_lst.push(a_now);
         }
-        let mut d_lrev = Vec::<Tensor<T>>::new();
-        d_lrev.push(self.loss.diff(&a_lst.last().unwrap(), truth)?);
+        Ok((z_l, a_lst))
+    }
+    /// Backward propagate the delta of the last layer, returning the delta of each layer
+    pub(crate) fn backpropagate_from(&self, last_delta: Tensor<T>, z_l: &[Tensor<T>]) -> Vec<Tensor<T>> {
+        let mut d_lrev = vec![last_delta];
         let mut z_lst_iter = z_l.iter().rev();
         z_lst_iter.next().unwrap();
-        // backward propagate
-        for ((layer, layer_lst), zlst) in self.seq.iter().rev().tuple_windows().zip(z_lst_iter) {
+        for (k, ((layer, layer_lst), zlst)) in self.seq.iter().rev().tuple_windows().zip(z_lst_iter).enumerate() {
             d_lrev.push(
                 layer.backpropagate_delta(
-                    &d_lrev.last().unwrap(), zlst, &layer_lst.get_activation()
-                ).unwrap()
+                    d_lrev.last().unwrap(), zlst, &layer_lst.get_activation()
+                ).map_err(at_layer(self.seq.len() - 1 - k, layer.as_ref())).unwrap()
             );
         }
         d_lrev.reverse();
-        Ok((d_lrev, a_lst))
+        d_lrev
+    }
+    /// Zeroed accumulators of the weight and bias deltas of each layer
+    pub(crate) fn accumulators(&self) -> (Vec<Vec<T>>, Vec<Tensor<T>>) {
+        self.seq.iter().map(|layer| (
+            vec![T::zero(); layer.get_weight_count()],
+            Tensor::<T>::zeros(&layer.get_output_shape()),
+        )).unzip()
+    }
+    /// The gradient of the mean loss of the samples by each parameter, listed like
+    /// `Layer::parameters` layer after layer
+    pub fn loss_gradients(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<Vec<T>>> {
+        Ok(self.loss_and_gradients(inputs, truths)?.1)
+    }
+    /// The trainable parameters of every layer, listed like `Layer::parameters` layer after layer
+    pub fn parameters(&self) -> Vec<&[T]> {
+        self.seq.iter().flat_map(|l| l.parameters()).collect()
+    }
+    /// The trainable parameters, mutable, in the same order as `parameters`
+    pub fn parameters_mut(&mut self) -> Vec<&mut [T]> {
+        self.seq.iter_mut().flat_map(|l| l.parameters_mut()).collect()
+    }
+    /// The names of `parameters`, the kind of the layer numbered among the layers of that
+    /// kind, then the name within the layer, e.g. `dense0.weight`, `batch_norm0.bias`, `dense1.weight`
+    pub fn parameter_names(&self) -> Vec<String> {
+        chain_parameter_names(self.seq.iter())
+    }
+    /// The parameters with their names, see `parameter_names`
+    pub fn named_parameters(&self) -> Vec<(String, &[T])> {
+        self.parameter_names().into_iter().zip(self.parameters()).collect()
+    }
+    /// The parameters with their names, mutable
+    pub fn named_parameters_mut(&mut self) -> Vec<(String, &mut [T])> {
+        self.parameter_names().into_iter().zip(self.parameters_mut()).collect()
+    }
+    /// The parameter of the name, see `parameter_names`
+    pub fn parameter(&self, name: &str) -> Option<&[T]> {
+        self.named_parameters().into_iter().find(|(n, _)| n == name).map(|(_, p)| p)
+    }
+    /// The gradients of `loss_gradients` with the names of their parameters
+    pub fn named_gradients(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<Vec<(String, Vec<T>)>> {
+        Ok(self.parameter_names().into_iter().zip(self.loss_gradients(inputs, truths)?).collect())
+    }
+    /// The mean loss of the samples and its gradients, see `loss_gradients`
+    fn loss_and_gradients(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> Result<(T, Vec<Vec<T>>)> {
+        let (mut cum_dw, mut cum_db) = self.accumulators();
+        let mut tot_loss = T::zero();
+        for (input, truth) in inputs.iter().zip(truths.iter()) {
+            let (deltas, a_lst) = self.propagate_sample(input, truth)?;
+            tot_loss += self.loss.call(a_lst.last().unwrap(), truth)?;
+            self.update_delta_da(&mut cum_dw, &mut cum_db, &deltas, &a_lst);
+        }
+        let scale = T::one() / T::from(inputs.len().max(1)).unwrap();
+        let grads = self.seq.iter().zip(cum_dw.iter().zip(cum_db.iter()))
+            .flat_map(|(layer, (dw, db))| layer.gradients(dw, db))
+            .map(|mut g| { g.iter_mut().for_each(|x| *x *= scale); g })
+            .collect();
+        Ok((tot_loss * scale, grads))
+    }
+    /// Add `alpha * v` to the parameters, `v` being listed like the gradients of `loss_gradients`
+    pub fn add_to_parameters(&mut self, alpha: T, v: &[Vec<T>]) {
+        let params = self.seq.iter_mut().flat_map(|layer| layer.parameters_mut()).collect::<Vec<_>>();
+        for (param, vi) in params.into_iter().zip(v.iter()) {
+            param.iter_mut().zip(vi.iter()).for_each(|(p, x)| *p += alpha * *x);
+        }
+    }
+    /// Let the optimizer update every parameter by its gradient, listed like `loss_gradients`,
+    /// clipped by `clipping` then plus the gradient of the regularization penalty
+    pub fn step(&mut self, optimizer: &mut dyn Optimizer<T>, grads: &[Vec<T>]) {
+        let clipped;
+        let grads = match self.clipping.is_none() {
+            true => grads,
+            false => {
+                let mut g = grads.to_vec();
+                self.clipping.clip(&mut g);
+                clipped = g;
+                &clipped
+            }
+        };
+        let reg = self.regularization;
+        let regularized = self.regularized();
+        let params = self.seq.iter_mut().flat_map(|layer| layer.parameters_mut()).collect::<Vec<_>>();
+        for (slot, ((param, grad), r)) in params.into_iter().zip(grads.iter()).zip(regularized).enumerate() {
+            if r && !reg.is_none() {
+                let grad: Vec<T> = grad.iter().zip(param.iter()).map(|(g, p)| *g + reg.gradient(*p)).collect();
+                optimizer.update(slot, param, &grad);
+            } else {
+                optimizer.update(slot, param, grad);
+            }
+        }
+        optimizer.finish_step();
+        self.seq.iter_mut().for_each(|layer| layer.finish_batch());
+    }
+    /// Whether each of `parameters` is penalized by the regularization, see `Layer::regularized`
+    fn regularized(&self) -> Vec<bool> {
+        self.seq.iter().flat_map(|layer| layer.regularized()).collect()
+    }
+    /// The regularization penalty of the weights, to be added to the loss
+    pub fn penalty(&self) -> T {
+        if self.regularization.is_none() {
+            return T::zero();
+        }
+        self.seq.iter().flat_map(|layer| layer.parameters().into_iter().zip(layer.regularized()))
+            .filter(|(_, r)| *r)
+            .map(|(p, _)| self.regularization.penalty(p))
+            .sum()
+    }
+    /// The penalty for the verbose training, if regularized
+    fn penalty_report(&self) -> String {
+        match self.regularization.is_none() {
+            true => String::new(),
+            false => format!(", Penalty: {}", self.penalty()),
+        }
+    }
+    /// Descend the regularized parameters by the gradient of the penalty
+    fn regularize(&mut self, rate: T) {
+        let reg = self.regularization;
+        if reg.is_none() {
+            return;
+        }
+        for layer in self.seq.iter_mut() {
+            let regularized = layer.regularized();
+            for (param, _) in layer.parameters_mut().into_iter().zip(regularized).filter(|(_, r)| *r) {
+                param.iter_mut().for_each(|p| *p -= rate * reg.gradient(*p));
+            }
+        }
+    }
+    /// A table of the layers, the name, the output shape and the parameter count of each
+    pub fn summary(&self) -> String {
+        let row = |i: &str, name: &str, shape: &str, params: &str| format!("{:<4}{:<24}{:<20}{:>12}", i, name, shape, params);
+        let mut lines = vec![row("#", "Layer", "Output shape", "Params")];
+        let mut total = 0;
+        for (i, layer) in self.seq.iter().enumerate() {
+            let params = layer.parameters().iter().map(|p| p.len()).sum::<usize>();
+            total += params;
+            lines.push(row(&i.to_string(), &layer.name(), &layer.get_output_shape().to_string(), &params.to_string()));
+        }
+        lines.push(format!("Total params: {}", total));
+        lines.join("\n")
+    }
+    pub(crate) fn layers(&self) -> &[Box<dyn Layer<T>>] {
+        &self.seq
     }
-    fn update_delta_da(&self, cum_dw: &mut Vec<Vec<T>>, cum_db: &mut Vec<Tensor<T>>, delta: &Vec<Tensor<T>>, a_lst: &Vec<Tensor<T>>) {
+    /// Switch the training passes of every layer between the training and the evaluation
+    /// behaviour, see `Layer::set_training`
+    pub fn set_training(&mut self, training: bool) {
+        self.seq.iter_mut().for_each(|layer| layer.set_training(training));
+    }
+    /// The count of layers
+    pub fn len(&self) -> usize {
+        self.seq.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.seq.is_empty()
+    }
+    /// Check that the output of layer `i - 1` fits the input of layer i (if both exist)
+    fn check_fit(&self, op: &'static str, lst: Option<&dyn Layer<T>>, next: Option<&dyn Layer<T>>) -> Result<()> {
+        match (lst, next) {
+            (Some(l), Some(n)) => check_shape(op, &l.get_output_shape(), &n.get_input_shape()),
+            _ => Ok(()),
+        }
+    }
+    /// Insert a layer before layer `index`, keeping the trained weights of the others
+    pub fn insert<L: 'static + Layer<T>>(&mut self, index: usize, layer: L) -> Result<()> {
+        if index > self.seq.len() {
+            return Err(EasynnError::invalid("insert", format!("no layer {} to insert before", index)));
+        }
+        self.check_fit("insert", index.checked_sub(1).map(|i| self.seq[i].as_ref()), Some(&layer))?;
+        self.check_fit("insert", Some(&layer), self.seq.get(index).map(|l| l.as_ref()))?;
+        self.seq.insert(index, Box::new(layer));
+        Ok(())
+    }
+    /// Remove layer `index` if its neighbours fit each other, returning it
+    pub fn remove(&mut self, index: usize) -> Result<Box<dyn Layer<T>>> {
+        if index >= self.seq.len() {
+            return Err(EasynnError::invalid("remove", format!("no layer {} to remove", index)));
+        }
+        self.check_fit("remove", index.checked_sub(1).map(|i| self.seq[i].as_ref()), self.seq.get(index + 1).map(|l| l.as_ref()))?;
+        Ok(self.seq.remove(index))
+    }
+    /// Widen the output units of layer `index`, see `Layer::remap_units`, to `width` units
+    /// (Net2WiderNet): the new units copy random old units and the next layer of weights
+    /// splits its weights among the copies, so the model computes the same function
+    pub fn widen(&mut self, index: usize, width: usize) -> Result<()> {
+        let units = self.seq.get(index).map_or(0, |l| l.get_output_shape()[0]);
+        if width < units {
+            return Err(EasynnError::invalid("widen", format!("the width {} is less than the {} units", width, units)));
+        }
+        let mut rng = rand::thread_rng();
+        let map: Vec<usize> = (0..width).map(|j| if j < units { j } else { rng.gen_range(0..units) }).collect();
+        self.remap_output_units("widen", index, &map)
+    }
+    /// Remap the output units of layer `index` by the map, then the input units of the next
+    /// layer supporting `remap_units`, through the layers passing the units in between
+    pub(crate) fn remap_output_units(&mut self, op: &'static str, index: usize, map: &[usize]) -> Result<()> {
+        let next = (index + 1..self.seq.len()).find(|j| !self.seq[*j].passes_units());
+        let next = match next {
+            Some(next) if self.seq[index].supports_remap() && self.seq[next].supports_remap() => next,
+            _ => return Err(EasynnError::invalid(op, format!("layer {} and the next layer of weights cannot remap their units", index))),
+        };
+        self.seq[index].remap_units(None, Some(map)).map_err(at_layer(index, self.seq[index].as_ref()))?;
+        let mut map = map.to_vec();
+        for l in index + 1..next {
+            map = self.seq[l].pass_units(&map).map_err(at_layer(l, self.seq[l].as_ref()))?;
+        }
+        self.seq[next].remap_units(Some(&map), None).map_err(at_layer(next, self.seq[next].as_ref()))
+    }
+    /// Freeze the model for inference, preallocating all intermediate outputs
+    pub fn compile_for_inference(&self) -> Result<InferenceExecutor<'_, T>> {
+        InferenceExecutor::new(self.seq.iter().map(|l| l.as_ref()).collect())
+    }
+}
+
+impl<T: NumT> Model<T> for Sequential<T> {
+    fn predict(&self, input: &Tensor<T>) -> Result<Tensor<T>> {
+        let mut last_output: Box<Tensor<T>>;
+        let mut output: Box<Tensor<T>> = Box::new((*input).clone());
+        for (l, layer) in self.seq.iter().enumerate() {
+            last_output = output;
+            output = Box::new(layer.forward_propagate(&last_output, true).map_err(at_layer(l, layer.as_ref()))?);
+        }
+        Ok(*output)
+    }
+    fn propagate_sample(&self, input: &Tensor<T>, truth: &Tensor<T>) -> Result<Propagation<T>> {
+        let (z_l, a_lst) = self.forward_train_all(input)?;
+        let d_l = self.backpropagate_from(self.loss.diff(a_lst.last().unwrap(), truth)?, &z_l);
+        Ok((d_l, a_lst))
+    }
+    fn update_delta_da(&self, cum_dw: &mut [Vec<T>], cum_db: &mut [Tensor<T>], delta: &[Tensor<T>], a_lst: &[Tensor<T>]) {
         // assert_eq!(cum_dw.len(), cum_db.len());
         // assert_eq!(delta.len(), a_lst.len());
         // assert_eq!(delta.len(), cum_db.len());
-        for (layer, ((d, alst), (cumdw, cumdb))) in self.seq.iter().zip(
+        for (l, (layer, ((d, alst), (cumdw, cumdb)))) in self.seq.iter().zip(
             delta.iter().zip(a_lst.iter()).zip(
                 cum_dw.iter_mut().zip(cum_db.iter_mut())
             )
-        ) {
-            layer.add_weight_delta_to(d, alst, cumdw, cumdb).unwrap();
+        ).enumerate() {
+            layer.add_weight_delta_to(d, alst, cumdw, cumdb).map_err(at_layer(l, layer.as_ref())).unwrap();
         }
     }
-    fn descend(&mut self, rate: T, dw: &Vec<Vec<T>>, db: &Vec<Tensor<T>>) {
+    fn descend(&mut self, rate: T, dw: &[Vec<T>], db: &[Tensor<T>]) {
         // assert_eq!(dw.len(), self.seq.len());
         // assert_eq!(db.len(), self.seq.len());
-        for (layer, (dwi, dbi)) in self.seq.iter_mut().zip(dw.iter().zip(db.iter())) {
-            layer.descend(rate, dwi, dbi).unwrap();
+        for (l, (layer, (dwi, dbi))) in self.seq.iter_mut().zip(dw.iter().zip(db.iter())).enumerate() {
+            layer.descend(rate, dwi, dbi).map_err(at_layer(l, layer.as_ref())).unwrap();
+            layer.finish_batch();
         }
     }
-    fn evaluate(&self, inputs: &Vec<Tensor<T>>, truths: &Vec<Tensor<T>>) -> T {
+    fn evaluate(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>]) -> T {
+        self.evaluate_with(inputs, truths, None).unwrap()
+    }
+    fn evaluate_weighted(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>], weights: &[T]) -> Result<T> {
+        self.evaluate_with(inputs, truths, Some(weights))
+    }
+    fn train_once(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, learning_rate: T, verbose: bool) -> T {
+        self.train_with(inputs, truths, None, batch_size, Update::Rate(learning_rate), verbose, None, None).loss
+    }
+    fn train_once_weighted(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], weights: &[T], batch_size: usize, learning_rate: T, verbose: bool) -> Result<T> {
+        check_len("train_once_weighted", inputs.len(), weights.len())?;
+        Ok(self.train_with(inputs, truths, Some(weights), batch_size, Update::Rate(learning_rate), verbose, None, None).loss)
+    }
+}
+
+impl<T: NumT> Sequential<T> {
+    /// Trains the model by an epoch like `train_once`, checking the control between batches.
+    /// When paused, `on_pause` is called with the model, e.g. to save a checkpoint,
+    /// then the training waits to be resumed. When cancelled, the epoch stops.
+    /// Requested snapshots are taken between batches too.
+    #[allow(clippy::too_many_arguments)]
+    pub fn train_once_controlled<F: FnMut(&Self)>(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, learning_rate: T, verbose: bool, control: &TrainingControl<T>, mut on_pause: F) -> EpochOutcome<T> {
+        self.train_with(inputs, truths, None, batch_size, Update::Rate(learning_rate), verbose, Some((control, &mut on_pause)), None)
+    }
+    /// Trains the model by an epoch like `train_once`, calling `on_batch` after each batch
+    /// with its index and mean loss. The epoch stops, as cancelled, when it returns false.
+    pub(crate) fn train_once_observed(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, learning_rate: T, verbose: bool, on_batch: OnBatch<'_, T>) -> EpochOutcome<T> {
+        self.train_with(inputs, truths, None, batch_size, Update::Rate(learning_rate), verbose, None, Some(on_batch))
+    }
+    /// Trains the model by an epoch like `train_once`, the optimizer updating the parameters
+    /// from the mean gradients of each batch
+    pub fn train_once_optimized(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, optimizer: &mut dyn Optimizer<T>, verbose: bool) -> T {
+        self.train_with(inputs, truths, None, batch_size, Update::Optimizer(optimizer), verbose, None, None).loss
+    }
+    /// Trains the model by an epoch with sharpness-aware minimization, see `optim::Sam`,
+    /// and return the mean loss before each step
+    pub fn train_once_sam<O: Optimizer<T>>(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, sam: &mut Sam<T, O>, verbose: bool) -> T {
         let mut avg_loss = T::zero();
-        for (input, truth) in inputs.iter().zip(truths.iter()) {
+        let mut tot_batches = 0;
+        for (i, (in_batch, tr_batch)) in inputs.chunks(batch_size).zip(truths.chunks(batch_size)).enumerate() {
+            // the gradient at the worst parameters nearby, applied to the current ones
+            let (loss, grads) = self.loss_and_gradients(in_batch, tr_batch).unwrap();
+            let e = sam.perturbation(&grads);
+            self.add_to_parameters(T::one(), &e);
+            let sharp_grads = self.loss_gradients(in_batch, tr_batch);
+            self.add_to_parameters(-T::one(), &e);
+            self.step(&mut sam.base, &sharp_grads.unwrap());
+            if verbose {
+                println!("Trainning batch {} ... Ok, Mean loss ({:?}): {}{}", i, self.loss, loss, self.penalty_report());
+            }
+            avg_loss += loss;
+            tot_batches += 1;
+        }
+        avg_loss / T::from(tot_batches.max(1)).unwrap()
+    }
+    /// Predict a batch of inputs stacked as `[batch, ..input_shape]`, see `Tensor::stack`
+    pub fn predict_batch(&self, inputs: &Tensor<T>) -> Result<Tensor<T>> {
+        let mut output = inputs.clone();
+        for (l, layer) in self.seq.iter().enumerate() {
+            output = layer.forward_batch(&output, true).map_err(at_layer(l, layer.as_ref()))?;
+        }
+        Ok(output)
+    }
+    /// Trains the model by an epoch like `train_once`, but propagating each batch at once
+    /// through `Layer::forward_batch` and the batched backward pass, then descending by
+    /// the gradients averaged over the batch.
+    /// The layers are assumed to activate pointwise by `get_activation`, as all of the crate do.
+    pub fn train_once_batched(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], batch_size: usize, learning_rate: T, verbose: bool) -> T {
+        let (mut cum_dw, mut cum_db) = self.accumulators();
+        let input_shape = self.seq[0].get_input_shape();
+        let output_shape = self.seq.last().unwrap().get_output_shape();
+        let mut avg_loss = T::zero();
+        let mut tot_batches = 0;
+        for (i, (in_batch, tr_batch)) in inputs.chunks(batch_size).zip(truths.chunks(batch_size)).enumerate() {
+            if verbose {
+                print!("Trainning batch {} ... ", i);
+            }
+            // forward, keeping the z and the a of each layer for the whole batch
+            let mut a_lst = vec![Tensor::stack(&input_shape, in_batch).unwrap()];
+            let mut z_l = Vec::with_capacity(self.seq.len());
+            for (l, layer) in self.seq.iter().enumerate() {
+                let (z, a) = layer.forward_train_batch(a_lst.last().unwrap()).map_err(at_layer(l, layer.as_ref())).unwrap();
+                a_lst.push(a);
+                z_l.push(z);
+            }
+            let mut tot_loss = T::zero();
+            let mut diffs = Vec::with_capacity(in_batch.len());
+            for (result, truth) in a_lst.last().unwrap().unstack().iter().zip(tr_batch.iter()) {
+                tot_loss += self.loss.call(result, truth).unwrap();
+                diffs.push(self.loss.diff(result, truth).unwrap());
+            }
+            // backward
+            let mut deltas = vec![Tensor::stack(&output_shape, &diffs).unwrap()];
+            for l in (1..self.seq.len()).rev() {
+                let delta = self.seq[l].backpropagate_batch(deltas.last().unwrap(), &z_l[l - 1], &self.seq[l - 1].get_activation())
+                    .map_err(at_layer(l, self.seq[l].as_ref())).unwrap();
+                deltas.push(delta);
+            }
+            deltas.reverse();
+            for (l, layer) in self.seq.iter().enumerate() {
+                cum_dw[l].iter_mut().for_each(|x| *x = T::zero());
+                cum_db[l].apply(|_| T::zero());
+                layer.add_weight_delta_batch_to(&deltas[l], &a_lst[l], &mut cum_dw[l], &mut cum_db[l]).map_err(at_layer(l, layer.as_ref())).unwrap();
+            }
+            let bsize_t = T::from(in_batch.len()).unwrap();
+            self.regularize(learning_rate);
+            self.descend_rate(learning_rate, bsize_t, &cum_dw, &cum_db);
+            if verbose {
+                println!("Ok, Mean loss ({:?}): {}{}", self.loss, tot_loss / bsize_t, self.penalty_report());
+            }
+            avg_loss += tot_loss / bsize_t;
+            tot_batches += 1;
+        }
+        avg_loss / T::from(tot_batches.max(1)).unwrap()
+    }
+    /// The gradients of every parameter from the accumulated deltas scaled by `scale`
+    fn scaled_gradients(&self, scale: T, dw: &[Vec<T>], db: &[Tensor<T>]) -> Vec<Vec<T>> {
+        self.seq.iter().zip(dw.iter().zip(db.iter()))
+            .flat_map(|(layer, (dwi, dbi))| layer.gradients(dwi, dbi))
+            .map(|mut g| { g.iter_mut().for_each(|x| *x *= scale); g })
+            .collect()
+    }
+    /// Let the optimizer update every parameter from the accumulated deltas scaled by `scale`
+    fn descend_optimized(&mut self, optimizer: &mut dyn Optimizer<T>, scale: T, dw: &[Vec<T>], db: &[Tensor<T>]) {
+        let grads = self.scaled_gradients(scale, dw, db);
+        self.step(optimizer, &grads);
+    }
+    /// Descend by the plain SGD of `rate` from the deltas accumulated over a batch of total
+    /// weight `batch`, through the gradients of the parameters when they are clipped
+    fn descend_rate(&mut self, rate: T, batch: T, dw: &[Vec<T>], db: &[Tensor<T>]) {
+        if self.clipping.is_none() {
+            return self.descend(rate / batch, dw, db);
+        }
+        let mut grads = self.scaled_gradients(T::one() / batch, dw, db);
+        self.clipping.clip(&mut grads);
+        self.add_to_parameters(-rate, &grads);
+        self.seq.iter_mut().for_each(|layer| layer.finish_batch());
+    }
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "evaluate", skip_all, fields(samples = inputs.len())))]
+    fn evaluate_with(&self, inputs: &[Tensor<T>], truths: &[Tensor<T>], weights: Option<&[T]>) -> Result<T> {
+        if let Some(w) = weights {
+            check_len("evaluate_weighted", inputs.len(), w.len())?;
+        }
+        let mut avg_loss = T::zero();
+        let mut tot_weight = T::zero();
+        for (i, (input, truth)) in inputs.iter().zip(truths.iter()).enumerate() {
+            let w = weights.map_or(T::one(), |w| w[i]);
             let pred = self.predict(input).unwrap();
-            avg_loss += self.loss.call(&pred, &truth).unwrap();
+            avg_loss += w * self.loss.call(&pred, truth).unwrap();
+            tot_weight += w;
         }
-        avg_loss / T::from(inputs.len()).unwrap()
+        if tot_weight <= T::zero() {
+            return Err(EasynnError::invalid("evaluate_weighted", "the weights do not sum to a positive weight"));
+        }
+        Ok(avg_loss / tot_weight)
     }
-    fn train_once(&mut self, inputs: &Vec<Tensor<T>>, truths: &Vec<Tensor<T>>, batch_size: usize, learning_rate: T, verbose: bool) -> T {
-        // prepare the intermediate accumulators
-        let mut cum_dw = Vec::<Vec<T>>::new();
-        let mut cum_db = Vec::<Tensor<T>>::new();
-        for layer in &self.seq {
-            cum_dw.push(vec![T::zero(); layer.get_weight_count()]);
-            cum_db.push(Tensor::<T>::zeros(&layer.get_output_shape()));
+    #[cfg_attr(feature = "tracing", tracing::instrument(name = "train_epoch", skip_all, fields(samples = inputs.len(), batch_size, learning_rate = ?update.learning_rate())))]
+    #[allow(clippy::too_many_arguments)]
+    fn train_with(&mut self, inputs: &[Tensor<T>], truths: &[Tensor<T>], weights: Option<&[T]>, batch_size: usize, mut update: Update<'_, T>, verbose: bool, mut control: Option<Control<'_, T>>, mut on_batch: Option<OnBatch<'_, T>>) -> EpochOutcome<T> {
+        if let Some(w) = weights {
+            assert_eq!(w.len(), inputs.len(), "One weight per sample is expected!");
         }
+        // prepare the intermediate accumulators
+        let (mut cum_dw, mut cum_db) = self.accumulators();
 
         // assert_eq!(inputs.len(), truths.len());
         let in_batches = inputs.chunks(batch_size);
         let tr_batches = truths.chunks(batch_size);
         let mut avg_loss = T::zero();
-        let tot_batches = in_batches.len();
-        for (i, (in_batch, tr_batch)) in in_batches.into_iter().zip(tr_batches.into_iter()).enumerate() {
+        let mut tot_batches = 0;
+        for (i, (in_batch, tr_batch)) in in_batches.zip(tr_batches).enumerate() {
+            if let Some((c, on_pause)) = &mut control {
+                if !c.proceed(|| on_pause(self), || self.snapshot()) {
+                    return EpochOutcome { loss: avg_loss / T::from(tot_batches.max(1)).unwrap(), penalty: self.penalty(), batches: tot_batches, cancelled: true };
+                }
+            }
             let mut tot_loss = T::zero();
             if verbose {
                 print!("Trainning batch {} ... ", i);
             }
-            // batch size
-            let batch_size = in_batch.len();
-            let bsize_t = T::from(batch_size).unwrap();
+            // the total weight of the batch, its size if unweighted
+            let mut bsize_t = T::zero();
             // clear the cumulators
             for cum_dw_l in &mut cum_dw {
-                cum_dw_l.par_iter_mut().for_each(|cdw| { *cdw = T::zero(); });
+                parallel::for_each_mut(cum_dw_l, |_, cdw| { *cdw = T::zero(); });
             }
             for cum_db_l in &mut cum_db {
-                cum_db_l.flattened.par_iter_mut().for_each(|cdb| { *cdb = T::zero(); });
+                parallel::for_each_mut(&mut cum_db_l.flattened, |_, cdb| { *cdb = T::zero(); });
             }
             // train for a batch
-            for (input, truth) in in_batch.into_iter().zip(tr_batch.into_iter()) {
-                let (deltas, mut interoutputs) = self.propagate_sample(input, truth).unwrap();
+            for (j, (input, truth)) in in_batch.iter().zip(tr_batch.iter()).enumerate() {
+                let w = weights.map_or(T::one(), |w| w[i * batch_size + j]);
+                let (mut deltas, mut interoutputs) = self.propagate_sample(input, truth).unwrap();
                 let result = interoutputs.pop().unwrap();
                 // print!("Input: {:?}; Truth: {:?}; Result: {:?}", input.flattened, truth.flattened, result.flattened);
                 // println!("; dL: {:?}", deltas.last().unwrap().flattened);
-                tot_loss += self.loss.call(&result, truth).unwrap();
+                tot_loss += w * self.loss.call(&result, truth).unwrap();
+                bsize_t += w;
+                if weights.is_some() {
+                    for d in &mut deltas {
+                        d.flattened.iter_mut().for_each(|x| *x *= w);
+                    }
+                }
                 self.update_delta_da(&mut cum_dw, &mut cum_db, &deltas, &interoutputs);
             }
+            if bsize_t <= T::zero() {
+                // nothing to learn from a batch of zero weights, nor to count in the mean loss
+                self.seq.iter_mut().for_each(|layer| layer.discard_batch());
+                if verbose {
+                    println!("Skipped, no weight");
+                }
+                continue;
+            }
+            tot_batches += 1;
 
             // descend
-            self.descend(learning_rate / bsize_t, &cum_dw, &cum_db);
+            match &mut update {
+                Update::Rate(rate) => {
+                    self.regularize(*rate);
+                    self.descend_rate(*rate, bsize_t, &cum_dw, &cum_db);
+                }
+                Update::Optimizer(optimizer) => self.descend_optimized(*optimizer, T::one() / bsize_t, &cum_dw, &cum_db),
+            }
 
             if verbose {
-                println!("Ok, Mean loss ({:?}): {}", self.loss, tot_loss / bsize_t);
+                println!("Ok, Mean loss ({:?}): {}{}", self.loss, tot_loss / bsize_t, self.penalty_report());
             }
+            #[cfg(feature = "tracing")]
+            tracing::debug!(batch = i, loss = ?(tot_loss / bsize_t), "trained batch");
             avg_loss += tot_loss / bsize_t;
+            if let Some(on_batch) = &mut on_batch {
+                if !on_batch(i, tot_loss / bsize_t, self) {
+                    return EpochOutcome { loss: avg_loss / T::from(tot_batches).unwrap(), penalty: self.penalty(), batches: tot_batches, cancelled: true };
+                }
+            }
         }
-        avg_loss / T::from(tot_batches).unwrap()
+        EpochOutcome { loss: avg_loss / T::from(tot_batches.max(1)).unwrap(), penalty: self.penalty(), batches: tot_batches, cancelled: false }
     }
 }
 
@@ -151,7 +584,6 @@ fn test_sequential_predict() {
     let output = Tensor::<f64>::new(&o_shape, vec![70., 70.]);
 
     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
-    use crate::layers::activation::Activation::*;
 
     let l1 = crate::layers::dense::Dense::<f64> {
         input_shape: i_shape.clone(),
@@ -174,11 +606,196 @@ fn test_sequential_predict() {
     assert_eq!(nn.predict(&input).unwrap(), output);
 }
 
+#[test]
+fn test_sequential_fit() {
+    use crate::prelude::*;
+
+    // learn y = 2x - 1
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::<f64>::new(sh!([1]), sh!([1]), Activation::No));
+    let inputs: Vec<_> = (0..8).map(|i| Tensor::new(sh!([1]), vec![i as f64 / 4.])).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(sh!([1]), vec![2. * x.get([0]) - 1.])).collect();
+    let history = nn.fit(&inputs, &truths, 300, 4, 0.2, false);
+    assert_eq!(history.len(), 300);
+    assert!(history[299] < history[0] && history[299] < 1e-6);
+}
+
+#[test]
+fn test_sequential_optimized() {
+    use crate::prelude::*;
+    use crate::optim::*;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Conv2D::new(sh!([1, 3, 3]), 2, (2, 2), (1, 1), Padding::Valid, Activation::Tanh));
+    nn.add(Dense::new(sh!([2, 2, 2]), sh!([2]), Activation::No));
+    let inputs: Vec<_> = (0..6).map(|i| Tensor::new(sh!([1, 3, 3]), (0..9).map(|j| ((i * 7 + j * j) % 5) as f64 / 4.).collect())).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(sh!([2]), vec![x.as_slice().iter().sum::<f64>() / 9., x.get([0, 1, 1])])).collect();
+
+    // plain SGD through the optimizer takes the same steps as train_once
+    let start = nn.snapshot();
+    nn.train_once(&inputs, &truths, 4, 0.1, false);
+    let plain = nn.snapshot();
+    nn.load_snapshot(&start).unwrap();
+    nn.train_once_optimized(&inputs, &truths, 4, &mut Sgd::new(0.1), false);
+    for (a, b) in plain.parameters.iter().flatten().flatten().zip(nn.snapshot().parameters.iter().flatten().flatten()) {
+        assert!((a - b).abs() < 1e-12);
+    }
+
+    let before = nn.evaluate(&inputs, &truths);
+    let mut adam = Adam::new(0.01);
+    for _ in 0..300 {
+        nn.train_once_optimized(&inputs, &truths, 3, &mut adam, false);
+    }
+    assert!(nn.evaluate(&inputs, &truths) < before / 4.);
+
+    // SAM of radius 0 is its base optimizer, and it reports the loss before each step
+    let trained = nn.snapshot();
+    nn.train_once_optimized(&inputs, &truths, 4, &mut Sgd::new(0.1), false);
+    let plain = nn.snapshot();
+    nn.load_snapshot(&trained).unwrap();
+    let mut sam = Sam { base: Sgd::new(0.1), rho: 0. };
+    let loss = nn.train_once_sam(&inputs, &truths, 6, &mut sam, false);
+    nn.load_snapshot(&trained).unwrap();
+    assert!((loss - nn.evaluate(&inputs, &truths)).abs() < 1e-12);
+    nn.train_once_sam(&inputs, &truths, 4, &mut sam, false);
+    for (a, b) in plain.parameters.iter().flatten().flatten().zip(nn.snapshot().parameters.iter().flatten().flatten()) {
+        assert!((a - b).abs() < 1e-12);
+    }
+    nn.load_snapshot(&start).unwrap();
+    let before = nn.evaluate(&inputs, &truths);
+    let mut sam = Sam::new(Adam::new(0.01));
+    for _ in 0..100 {
+        nn.train_once_sam(&inputs, &truths, 3, &mut sam, false);
+    }
+    assert!(nn.evaluate(&inputs, &truths) < before);
+}
+
+#[test]
+fn test_sequential_batched() {
+    use crate::prelude::*;
+
+    // the batched layers (Dense) and the per-sample fallback (Conv2D) take the same steps as train_once
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Conv2D::new(sh!([1, 3, 3]), 2, (2, 2), (1, 1), Padding::Valid, Activation::Tanh));
+    nn.add(Dense::new(sh!([2, 2, 2]), sh!([3]), Activation::Sigmoid));
+    nn.add(Dense::new(sh!([3]), sh!([2]), Activation::No));
+    let inputs: Vec<_> = (0..5).map(|i| Tensor::new(sh!([1, 3, 3]), (0..9).map(|j| ((i * 7 + j * j) % 5) as f64 / 4.).collect())).collect();
+    let truths: Vec<_> = (0..5).map(|i| Tensor::new(sh!([2]), vec![i as f64 / 5., 1. - i as f64 / 5.])).collect();
+
+    let batch = Tensor::stack(sh!([1, 3, 3]), &inputs).unwrap();
+    let predicted = nn.predict_batch(&batch).unwrap().unstack();
+    for (x, p) in inputs.iter().zip(predicted.iter()) {
+        assert!(nn.predict(x).unwrap().as_slice().iter().zip(p.as_slice()).all(|(a, b)| (a - b).abs() < 1e-12));
+    }
+
+    let start = nn.snapshot();
+    let loss = nn.train_once(&inputs, &truths, 2, 0.3, false);
+    let plain = nn.snapshot();
+    nn.load_snapshot(&start).unwrap();
+    let batched_loss = nn.train_once_batched(&inputs, &truths, 2, 0.3, false);
+    assert!((loss - batched_loss).abs() < 1e-12);
+    for (a, b) in plain.parameters.iter().flatten().flatten().zip(nn.snapshot().parameters.iter().flatten().flatten()) {
+        assert!((a - b).abs() < 1e-12);
+    }
+}
+
+#[test]
+fn test_sequential_weighted() {
+    use crate::prelude::*;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::<f64>::new(sh!([1]), sh!([1]), Activation::No));
+    // two contradicting samples, the heavier one wins
+    let inputs = [Tensor::new(sh!([1]), vec![1.]), Tensor::new(sh!([1]), vec![1.])];
+    let truths = [Tensor::new(sh!([1]), vec![0.]), Tensor::new(sh!([1]), vec![1.])];
+    let weights = [1., 3.];
+    for _ in 0..500 {
+        nn.train_once_weighted(&inputs, &truths, &weights, 2, 0.1, false).unwrap();
+    }
+    assert!((nn.predict(&inputs[0]).unwrap().get([0]) - 0.75).abs() < 1e-6);
+    // (1 * 0.75^2 + 3 * 0.25^2) / 4
+    assert!((nn.evaluate_weighted(&inputs, &truths, &weights).unwrap() - 0.1875).abs() < 1e-6);
+    assert!((nn.evaluate(&inputs, &truths) - 0.3125).abs() < 1e-6);
+    assert_eq!(nn.evaluate_weighted(&inputs, &truths, &weights[..1]),
+        Err(EasynnError::mismatch("evaluate_weighted", &Shape::new([2]), &Shape::new([1]))));
+    assert!(nn.evaluate_weighted(&inputs, &truths, &[0., 0.]).is_err());
+    assert!(nn.train_once_weighted(&inputs, &truths, &weights[..1], 2, 0.1, false).is_err());
+
+    // a batch of zero weight is neither trained nor counted, nor gathers batch statistics
+    let mut bn = Sequential::<f64>::new(Loss::MeanSquare);
+    bn.add(crate::layers::batch_norm::BatchNorm::new(sh!([1, 2]), Activation::No));
+    let mut alone = Sequential::<f64>::new(Loss::MeanSquare);
+    alone.add(crate::layers::batch_norm::BatchNorm::new(sh!([1, 2]), Activation::No));
+    let x = [Tensor::new(sh!([1, 2]), vec![5., 9.]), Tensor::new(sh!([1, 2]), vec![1., 2.])];
+    let y = [Tensor::new(sh!([1, 2]), vec![0., 1.]), Tensor::new(sh!([1, 2]), vec![1., 0.])];
+    let loss = bn.train_once_weighted(&x, &y, &[0., 1.], 1, 0.1, false).unwrap();
+    assert_eq!(loss, alone.train_once_weighted(&x[1..], &y[1..], &[1.], 1, 0.1, false).unwrap());
+    assert_eq!(bn.snapshot(), alone.snapshot());
+}
+
+#[test]
+fn test_sequential_surgery() {
+    use crate::prelude::*;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::<f64>::new(sh!([3]), sh!([4]), Activation::Tanh));
+    nn.add(Dense::<f64>::new(sh!([4]), sh!([2]), Activation::Sigmoid));
+    let input = Tensor::new(sh!([3]), vec![0.5, -1., 2.]);
+    let before = nn.predict(&input).unwrap();
+    let close = |a: &Tensor<f64>, b: &Tensor<f64>| a.flattened.iter().zip(b.flattened.iter()).all(|(x, y)| (x - y).abs() < 1e-12);
+
+    nn.widen(0, 9).unwrap();
+    assert_eq!(nn.layers()[0].get_output_shape(), Shape::new([9]));
+    assert!(close(&nn.predict(&input).unwrap(), &before));
+    assert!(nn.widen(0, 5).is_err());
+    assert!(nn.widen(1, 5).is_err());
+
+    nn.insert(1, Dense::identity(9)).unwrap();
+    assert_eq!(nn.len(), 3);
+    assert!(close(&nn.predict(&input).unwrap(), &before));
+    assert!(nn.insert(1, Dense::identity(4)).is_err());
+    assert!(nn.remove(3).is_err());
+    nn.remove(1).unwrap();
+    assert!(close(&nn.predict(&input).unwrap(), &before));
+}
+
+#[test]
+fn test_sequential_compile_for_inference() {
+    use crate::prelude::*;
+
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::<f64>::new(sh!([2, 3]), sh!([4]), Activation::Relu));
+    nn.add(Dense::<f64>::new(sh!([4]), sh!([2]), Activation::Sigmoid));
+
+    let inputs = [
+        Tensor::new(sh!([2, 3]), vec![1., 7., 8., -2., 3., 5.]),
+        Tensor::new(sh!([2, 3]), vec![0., -1., 2., 0.5, 0., -3.]),
+    ];
+    let expected: Vec<Tensor<f64>> = inputs.iter().map(|i| nn.predict(i).unwrap()).collect();
+    let mut exe = nn.compile_for_inference().unwrap();
+    for (input, exp) in inputs.iter().zip(expected.iter()) {
+        assert_eq!(exe.run(input).unwrap(), exp);
+    }
+    assert!(exe.run(&Tensor::new(sh!([6]), vec![0.; 6])).is_err());
+
+    let mut bad = Sequential::<f64>::new(Loss::MeanSquare);
+    bad.add(Dense::<f64>::new(sh!([2]), sh!([3]), Activation::Relu));
+    bad.add(Dense::<f64>::new(sh!([4]), sh!([1]), Activation::Relu));
+    assert!(bad.compile_for_inference().is_err());
+    // the error tells the layer and the shapes
+    let err = bad.predict(&Tensor::new(sh!([2]), vec![1., 2.])).unwrap_err();
+    assert_eq!(err, EasynnError::Layer {
+        index: 1,
+        name: "dense".to_string(),
+        source: Box::new(EasynnError::mismatch("forward_propagate", sh!([4]), sh!([3]))),
+    });
+    assert_eq!(err.to_string(), "Layer 1 (dense): Tensor shape mismatch in forward_propagate: expected [4] but got [3]!");
+}
+
 /// This test is to test if it can learn the 1 bit xor function
 #[test]
 fn test_sequential_xor1() {
     use crate::prelude::*;
-    use rand::Rng;
 
     // create the network and add 2 layers
     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
@@ -211,7 +828,71 @@ fn test_sequential_xor1() {
     // evaluate the model
     let mut result = [0.; 4];
     for (n, input) in inputs.iter().enumerate() {
-        result[n] = nn.predict(&input).unwrap().get([0]).round();
+        result[n] = nn.predict(input).unwrap().get([0]).round();
     }
     assert_eq!(result.to_vec(), outputs.iter().map(|t| t.flattened[0]).collect::<Vec<f64>>());
-}
\ No newline at end of file
+}
+#[test]
+fn test_sequential_dropout() {
+    use crate::prelude::*;
+
+    // the same seed drops the same units, and the seed moves on after each batch
+    let build = || {
+        let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+        nn.add(Dense::new(sh!([3]), sh!([6]), Activation::Tanh));
+        nn.add(Dropout::with_seed(sh!([6]), 0.5, 11));
+        nn.add(Dense::new(sh!([6]), sh!([2]), Activation::No));
+        nn
+    };
+    let (mut plain, mut again) = (build(), build());
+    again.load_snapshot(&plain.snapshot()).unwrap();
+    let inputs: Vec<_> = (0..6).map(|i| Tensor::new(sh!([3]), vec![i as f64 / 6., 1. - i as f64 / 3., 0.5])).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| Tensor::new(sh!([2]), vec![x.get([0]), x.get([1]) * 0.5])).collect();
+    for _ in 0..3 {
+        let loss = plain.train_once_batched(&inputs, &truths, 4, 0.2, false);
+        assert_eq!(loss, again.train_once_batched(&inputs, &truths, 4, 0.2, false));
+    }
+    assert_eq!(plain.snapshot().parameters, again.snapshot().parameters);
+    let first = plain.train_once(&inputs, &truths, 6, 0.1, false);
+    let mut last = first;
+    for _ in 0..200 {
+        last = plain.train_once(&inputs, &truths, 6, 0.1, false);
+        assert!(last.is_finite());
+    }
+    assert!(last < first);
+
+    // the inference never drops, and the evaluation mode trains without dropping
+    let x = &inputs[1];
+    assert_eq!(plain.predict(x).unwrap(), plain.predict(x).unwrap());
+    plain.set_training(false);
+    let (_, a_lst) = plain.forward_train_all(x).unwrap();
+    assert_eq!(a_lst[2], a_lst[1]);
+}
+
+#[test]
+fn test_sequential_named_parameters() {
+    use crate::layers::{ dense::Dense, batch_norm::BatchNorm, skip::{ Skip, Merge } };
+    let s = Shape::new([2]);
+    let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+    nn.add(Dense::new(&s, &s, Activation::No));
+    nn.add(BatchNorm::new(&s, Activation::No));
+    nn.add(Dense::new(&s, &s, Activation::Tanh));
+    nn.add(Skip::new(&s, Merge::Add, Activation::No).then(Dense::new(&s, &s, Activation::Relu)).shortcut(Dense::new(&s, &s, Activation::No)));
+    assert_eq!(nn.parameter_names(), [
+        "dense0.weight", "dense0.bias", "batch_norm0.weight", "batch_norm0.bias", "dense1.weight", "dense1.bias",
+        "skip0.shortcut.dense0.weight", "skip0.shortcut.dense0.bias", "skip0.body.dense0.weight", "skip0.body.dense0.bias",
+    ]);
+    assert_eq!(nn.parameters().len(), 10);
+    for (name, p) in nn.named_parameters_mut() {
+        if name == "dense1.bias" {
+            p.fill(0.5);
+        }
+    }
+    assert_eq!(nn.parameter("dense1.bias").unwrap(), [0.5, 0.5]);
+    assert_eq!(nn.layers()[2].named_parameters()[1], ("bias".to_string(), &[0.5, 0.5][..]));
+    assert!(nn.parameter("dense2.weight").is_none());
+    let x = vec![Tensor::new(&s, vec![1., -1.])];
+    let grads = nn.named_gradients(&x, &x).unwrap();
+    assert_eq!(grads[4].0, "dense1.weight");
+    assert_eq!(grads[4].1.len(), 4);
+}
@@ -0,0 +1,259 @@
+//! Saving and loading of layers and sequential models in a versioned binary format.
+//!
+//! A file is written in little endian as:
+//!
+//!  - the magic `EZNN`, then the `u32` format version
+//!  - the content: `u8` 0 for a `Sequential` model, 1 for a single layer
+//!  - the element width: `u8` 4 for `f32` parameters, 8 for `f64`
+//!  - for a model, the loss: `u8` tag (0 mean square, 1 mean absolute, 2 binary cross-entropy,
+//!    3 softmax cross-entropy, 4 sigmoid cross-entropy, 5 CTC then its `u64` blank,
+//!    6 pinball then its `u32` count, at most `MAX_QUANTILES`, and `f64` levels, 7 Dice),
+//!    then the `u32` count of layers
+//!  - the layer records, see `layers::record`
+//!
+//! Files of older versions keep loading, files of newer versions are rejected.
+//! Parameters saved with one element width load into either type.
+//!
+//!     use easynn::prelude::*;
+//!     use easynn::models::serialize::*;
+//!     let mut model = Sequential::<f32>::new(Loss::MeanSquare);
+//!     model.add(Dense::new(sh!([2]), sh!([1]), Activation::Sigmoid));
+//!     let mut buf = Vec::new();
+//!     model.write_to(&mut buf).unwrap();
+//!     let loaded = Sequential::<f32>::read_from(&mut buf.as_slice()).unwrap();
+//!     let x = Tensor::new(sh!([2]), vec![0.5, -1.]);
+//!     assert_eq!(model.predict(&x).unwrap(), loaded.predict(&x).unwrap());
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use crate::layers::record::*;
+
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Read, Result, Write };
+use std::path::Path;
+
+pub const MAGIC: &[u8; 4] = b"EZNN";
+/// The version written, the versions up to it are read.
+/// Version 2 adds the inner records of the layers made of other layers,
+/// version 3 the real values of the records instead of their bits in the configuration.
+pub const FORMAT_VERSION: usize = 3;
+
+const CONTENT_MODEL: u8 = 0;
+const CONTENT_LAYER: u8 = 1;
+
+fn width_of<T: NumT>() -> u8 {
+    std::mem::size_of::<T>() as u8
+}
+
+fn write_header<W: Write>(w: &mut W, content: u8, width: u8) -> Result<()> {
+    w.write_all(MAGIC)?;
+    write_u32(w, FORMAT_VERSION)?;
+    write_u8(w, content)?;
+    write_u8(w, width)
+}
+
+/// Check the header of the content, returning the element width and the version
+fn read_header<R: Read>(r: &mut R, content: u8) -> Result<(u8, usize)> {
+    let mut magic = [0; 4];
+    r.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not an easynn file"));
+    }
+    let version = read_u32(r)?;
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(invalid(&format!("unsupported format version {}", version)));
+    }
+    if read_u8(r)? != content {
+        return Err(invalid("unexpected content"));
+    }
+    match read_u8(r)? {
+        w @ (4 | 8) => Ok((w, version)),
+        _ => Err(invalid("unsupported element width")),
+    }
+}
+
+fn write_loss<W: Write>(w: &mut W, loss: &Loss) -> Result<()> {
+    match loss {
+        Loss::MeanSquare => write_u8(w, 0),
+        Loss::MeanAbsolute => write_u8(w, 1),
+        Loss::BinaryCrossEntropy => write_u8(w, 2),
+        Loss::SoftmaxCrossEntropy => write_u8(w, 3),
+        Loss::SigmoidCrossEntropy => write_u8(w, 4),
+        Loss::Ctc(blank) => {
+            write_u8(w, 5)?;
+            write_u64(w, *blank)
+        },
+        Loss::Pinball(quantiles) => {
+            write_u8(w, 6)?;
+            write_u32(w, quantiles.len())?;
+            quantiles.iter().try_for_each(|q| write_f64(w, *q))
+        },
+        Loss::Dice => write_u8(w, 7),
+    }
+}
+
+/// Read the loss
+fn read_loss<R: Read>(r: &mut R) -> Result<Loss> {
+    Ok(match read_u8(r)? {
+        0 => Loss::MeanSquare,
+        1 => Loss::MeanAbsolute,
+        2 => Loss::BinaryCrossEntropy,
+        3 => Loss::SoftmaxCrossEntropy,
+        4 => Loss::SigmoidCrossEntropy,
+        5 => Loss::Ctc(read_u64(r)?),
+        6 => {
+            let count = read_u32(r)?;
+            ensure(count <= MAX_QUANTILES, "too many quantile levels")?;
+            let levels = (0..count).map(|_| read_f64(r)).collect::<Result<Vec<_>>>()?;
+            Loss::Pinball(Quantiles::new(&levels).map_err(|e| invalid(&e.to_string()))?)
+        },
+        7 => Loss::Dice,
+        _ => return Err(invalid("unknown loss")),
+    })
+}
+
+fn record_of<T: NumT>(layer: &dyn Layer<T>) -> Result<LayerRecord<T>> {
+    layer.record().ok_or_else(|| invalid("the layer cannot be saved"))
+}
+
+/// Write a single layer
+pub fn write_layer<T: NumT, W: Write>(layer: &dyn Layer<T>, w: &mut W) -> Result<()> {
+    let record = record_of(layer)?;
+    write_header(w, CONTENT_LAYER, width_of::<T>())?;
+    record.write_to(w, width_of::<T>())
+}
+
+/// Read a single layer
+pub fn read_layer<T: NumT + 'static, R: Read>(r: &mut R) -> Result<Box<dyn Layer<T>>> {
+    let (width, version) = read_header(r, CONTENT_LAYER)?;
+    LayerRecord::<T>::read_from(r, width, version)?.into_layer()
+}
+
+/// Save a single layer to the file
+pub fn save_layer<T: NumT, P: AsRef<Path>>(layer: &dyn Layer<T>, path: P) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_layer(layer, &mut w)?;
+    w.flush()
+}
+
+/// Load a single layer from the file
+pub fn load_layer<T: NumT + 'static, P: AsRef<Path>>(path: P) -> Result<Box<dyn Layer<T>>> {
+    read_layer(&mut BufReader::new(File::open(path)?))
+}
+
+impl<T: NumT + 'static> Sequential<T> {
+    /// Write the loss and the layers with their parameters,
+    /// failing if some layer cannot be saved
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<()> {
+        let records = self.layers().iter().map(|l| record_of(l.as_ref())).collect::<Result<Vec<_>>>()?;
+        write_header(w, CONTENT_MODEL, width_of::<T>())?;
+        write_loss(w, &self.loss)?;
+        write_u32(w, records.len())?;
+        records.iter().try_for_each(|r| r.write_to(w, width_of::<T>()))
+    }
+    /// Read a model written by `write_to`, checking the layers fit each other
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Self> {
+        let (width, version) = read_header(r, CONTENT_MODEL)?;
+        let mut model = Sequential::new(read_loss(r)?);
+        let count = read_u32(r)?;
+        for _ in 0..count {
+            let layer = LayerRecord::<T>::read_from(r, width, version)?.into_layer()?;
+            if let Some(lst) = model.layers().last() {
+                if lst.get_output_shape() != layer.get_input_shape() {
+                    return Err(invalid("the layers do not fit each other"));
+                }
+            }
+            model.layers_mut().push(layer);
+        }
+        Ok(model)
+    }
+    /// Save the model to the file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_to(&mut w)?;
+        w.flush()
+    }
+    /// Load a model saved by `save`
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+}
+
+#[test]
+fn test_serialize() {
+    use crate::layers::conv::*;
+    use crate::layers::pooling::MaxPool2D;
+    use crate::layers::softmax::Softmax;
+    use crate::layers::dense::Dense;
+    let mut model = Sequential::<f64>::new(Loss::Pinball(Quantiles::new(&[0.1, 0.9]).unwrap()));
+    model.add(Conv2D::new(&Shape::new([1, 6, 6]), 2, (3, 3), (1, 1), Padding::Same, Activation::LeakyRelu(0.1)));
+    model.add(MaxPool2D::new(&Shape::new([2, 6, 6]), (2, 2), (2, 2)));
+    model.add(Dense::new(&Shape::new([2, 3, 3]), &Shape::new([4]), Activation::Tanh));
+    model.add(Softmax::new(&Shape::new([4])));
+    let x = Tensor::new(&Shape::new([1, 6, 6]), (0..36).map(|i| (i as f64 * 0.37).sin()).collect());
+
+    let mut buf = Vec::new();
+    model.write_to(&mut buf).unwrap();
+    let loaded = Sequential::<f64>::read_from(&mut buf.as_slice()).unwrap();
+    assert_eq!(loaded.len(), 4);
+    assert!(matches!(loaded.loss, Loss::Pinball(q) if *q == [0.1, 0.9]));
+    assert_eq!(model.predict(&x).unwrap(), loaded.predict(&x).unwrap());
+
+    // f64 parameters load into f32 models
+    let narrowed = Sequential::<f32>::read_from(&mut buf.as_slice()).unwrap();
+    let y = narrowed.predict(&Tensor::new(&Shape::new([1, 6, 6]), x.flattened.iter().map(|v| *v as f32).collect())).unwrap();
+    for (a, b) in y.flattened.iter().zip(model.predict(&x).unwrap().flattened.iter()) {
+        assert!((*a as f64 - b).abs() < 1e-5);
+    }
+
+    // A single layer through a file
+    let dense = Dense::<f32>::new(&Shape::new([3]), &Shape::new([2]), Activation::Relu);
+    let path = std::env::temp_dir().join(format!("easynn_layer_{}.bin", std::process::id()));
+    save_layer(&dense, &path).unwrap();
+    let layer = load_layer::<f32, _>(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let v = Tensor::new(&Shape::new([3]), vec![1., -2., 0.5]);
+    assert_eq!(dense.forward_propagate(&v, true).unwrap(), layer.forward_propagate(&v, true).unwrap());
+
+    // The records of version 2 end without the count of real values, those of version 1
+    // without the count of inner records either
+    let mut current = Vec::new();
+    write_layer(&dense, &mut current).unwrap();
+    for (version, cut) in [(2, 4), (1, 8)] {
+        let mut older = current[..current.len() - cut].to_vec();
+        older[4] = version;
+        let layer = read_layer::<f32, _>(&mut older.as_slice()).unwrap();
+        assert_eq!(dense.forward_propagate(&v, true).unwrap(), layer.forward_propagate(&v, true).unwrap());
+    }
+    // and the real values, e.g. a dropout rate or running statistics, were the bits of their f64 after the shape
+    use crate::layers::dropout::Dropout;
+    use crate::layers::batch_norm::BatchNorm;
+    let mut bn = BatchNorm::<f64>::new(&Shape::new([2, 3]), Activation::No);
+    bn.running_mean = vec![0.5, -1.];
+    for layer in [&Dropout::<f64>::new(&Shape::new([3]), 0.25) as &dyn Layer<f64>, &bn] {
+        let mut record = layer.record().unwrap();
+        let floats = std::mem::take(&mut record.floats);
+        record.config.extend(floats.iter().map(|x| x.to_bits() as usize));
+        let mut older = Vec::new();
+        write_layer(layer, &mut older).unwrap();
+        older.truncate(10);
+        record.write_to(&mut older, 8).unwrap();
+        older.truncate(older.len() - 4);
+        older[4] = 2;
+        let loaded = read_layer::<f64, _>(&mut older.as_slice()).unwrap();
+        assert_eq!(loaded.record().unwrap().floats, floats);
+    }
+
+    // The configurations of layers too large to build are rejected
+    let huge = LayerRecord::<f64>::new("conv2d", Activation::No).shape(&Shape::new([4, 3, 3])).values(&[usize::MAX / 2, 1, 1, 1, 1, 0, 0, 0, 0]);
+    assert!(huge.into_layer().is_err());
+    let wide = LayerRecord::<f64>::new("conv2d", Activation::No).shape(&Shape::new([1, 3, 3])).values(&[usize::MAX / 4, 1, 1, 1, 1, 0, 0, 0, 0]);
+    assert!(wide.into_layer().is_err());
+
+    // Newer versions, truncation and the wrong content are rejected
+    let mut newer = buf.clone();
+    newer[4] = FORMAT_VERSION as u8 + 1;
+    assert!(Sequential::<f64>::read_from(&mut newer.as_slice()).is_err());
+    assert!(Sequential::<f64>::read_from(&mut &buf[..buf.len() - 3]).is_err());
+    assert!(read_layer::<f64, _>(&mut buf.as_slice()).is_err());
+}
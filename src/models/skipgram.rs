@@ -0,0 +1,197 @@
+//! Skip-gram with negative sampling (SGNS), the word2vec training of embeddings.
+//!
+//! A center token is embedded by the input table and each context token by the output
+//! table, both `Embedding` layers. The loss of a (center, context) pair and its negatives
+//! drawn by a `NegativeSampler` is
+//! `-ln sigmoid(u_context . v_center) - sum of ln sigmoid(-u_negative . v_center)`,
+//! so that the centers come near their contexts and away from random tokens.
+//! The learned input table is then taken by `into_embedding` to start a model.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::datasets::{ NegativeSampler, skipgram_pairs };
+//!     use easynn::models::skipgram::SkipGram;
+//!     let tokens = [0, 1, 2, 0, 1, 2, 0, 1, 2];
+//!     let sampler = NegativeSampler::from_tokens(&tokens, 3, 0.75);
+//!     let mut sgns = SkipGram::<f64>::new(3, 8);
+//!     let mut rng = rand::thread_rng();
+//!     let pairs = skipgram_pairs(&tokens, 1);
+//!     let loss = sgns.train_pairs(&pairs, &sampler, 2, 0.05, &mut rng).unwrap();
+//!     assert!(loss > 0.);
+//!     let emb = sgns.into_embedding(sh!([4]));
+//!     assert_eq!(emb.vector(1).len(), 8);
+//! ```
+
+use crate::layers::*;
+use crate::layers::embedding::Embedding;
+use crate::datasets::NegativeSampler;
+
+use rand::Rng;
+
+/// ln sigmoid(x), not overflowing
+fn ln_sigmoid<T: NumT>(x: T) -> T {
+    -((-x).max(T::zero()) + (-x.abs()).exp().ln_1p())
+}
+
+fn sigmoid<T: NumT>(x: T) -> T {
+    T::one() / (T::one() + (-x).exp())
+}
+
+fn dot<T: NumT>(a: &[T], b: &[T]) -> T {
+    a.iter().zip(b).map(|(x, y)| *x * *y).sum()
+}
+
+/// The SGNS loss of a pair and its gradients with respect to each vector
+#[derive(Debug, Clone, PartialEq)]
+pub struct SgnsGrad<T: NumT> {
+    pub loss: T,
+    pub center: Vec<T>,
+    pub context: Vec<T>,
+    pub negatives: Vec<Vec<T>>,
+}
+
+/// The SGNS loss of the center vector, the context vector and the negative vectors,
+/// with its gradients with respect to the center, the context and each negative
+pub fn sgns_loss<T: NumT>(center: &[T], context: &[T], negatives: &[&[T]]) -> Result<SgnsGrad<T>> {
+    check_len("sgns_loss", center.len(), context.len())?;
+    for n in negatives {
+        check_len("sgns_loss", center.len(), n.len())?;
+    }
+    // d/dx -ln sigmoid(x) = sigmoid(x) - 1, d/dx -ln sigmoid(-x) = sigmoid(x)
+    let pos = dot(context, center);
+    let mut loss = -ln_sigmoid(pos);
+    let g = sigmoid(pos) - T::one();
+    let mut d_center: Vec<T> = context.iter().map(|u| g * *u).collect();
+    let d_context: Vec<T> = center.iter().map(|v| g * *v).collect();
+    let mut d_negatives = Vec::with_capacity(negatives.len());
+    for n in negatives {
+        let neg = dot(n, center);
+        loss -= ln_sigmoid(-neg);
+        let g = sigmoid(neg);
+        d_center.iter_mut().zip(n.iter()).for_each(|(d, u)| *d += g * *u);
+        d_negatives.push(center.iter().map(|v| g * *v).collect());
+    }
+    Ok(SgnsGrad { loss, center: d_center, context: d_context, negatives: d_negatives })
+}
+
+/// The input and the output tables of a skip-gram model, see the module
+#[derive(Debug)]
+pub struct SkipGram<T: NumT> {
+    pub input: Embedding<T>,
+    pub output: Embedding<T>,
+}
+
+impl<T: NumT> SkipGram<T> {
+    /// The tables of `vocab` tokens into `dim`, the input drawn uniformly in
+    /// `[-0.5 / dim, 0.5 / dim]` and the output zero, as in word2vec
+    pub fn new(vocab: usize, dim: usize) -> Self {
+        let half = 0.5 / dim as f64;
+        let mut rng = rand::thread_rng();
+        let table = (0..vocab * dim).map(|_| T::from(rng.gen_range(-half..=half)).unwrap()).collect();
+        SkipGram {
+            input: Embedding::from_table(&Shape::new([1]), vocab, dim, table),
+            output: Embedding::from_table(&Shape::new([1]), vocab, dim, vec![T::zero(); vocab * dim]),
+        }
+    }
+    pub fn vocab(&self) -> usize {
+        self.input.vocab
+    }
+    pub fn dim(&self) -> usize {
+        self.input.dim
+    }
+
+    /// Descend on the pair and the given negatives by plain SGD, returning its loss
+    pub fn train_pair(&mut self, center: usize, context: usize, negatives: &[usize], rate: T) -> Result<T> {
+        let vocab = self.vocab();
+        if let Some(i) = [center, context].iter().chain(negatives).find(|i| **i >= vocab) {
+            return Err(EasynnError::invalid("train_pair", format!("{} is not a token below {}", i, vocab)));
+        }
+        let negative_rows: Vec<&[T]> = negatives.iter().map(|n| self.output.vector(*n)).collect();
+        let grad = sgns_loss(self.input.vector(center), self.output.vector(context), &negative_rows)?;
+        let dim = self.dim();
+        let step = |table: &mut [T], row: usize, d: &[T]| {
+            table[row * dim..(row + 1) * dim].iter_mut().zip(d).for_each(|(w, d)| *w -= rate * *d);
+        };
+        step(&mut self.input.table, center, &grad.center);
+        step(&mut self.output.table, context, &grad.context);
+        for (n, d) in negatives.iter().zip(grad.negatives.iter()) {
+            step(&mut self.output.table, *n, d);
+        }
+        Ok(grad.loss)
+    }
+
+    /// Descend on each pair with `k` negatives drawn other than its tokens, in order,
+    /// returning the mean loss
+    pub fn train_pairs<R: Rng>(&mut self, pairs: &[(usize, usize)], sampler: &NegativeSampler, k: usize, rate: T, rng: &mut R) -> Result<T> {
+        check_len("train_pairs", self.vocab(), sampler.len())?;
+        let mut sum = T::zero();
+        for (center, context) in pairs {
+            let negatives = sampler.sample_excluding(rng, k, &[*center, *context])?;
+            sum += self.train_pair(*center, *context, &negatives, rate)?;
+        }
+        Ok(sum / T::from(pairs.len().max(1)).unwrap())
+    }
+
+    /// The cosine similarity of the input vectors of two tokens
+    pub fn similarity(&self, a: usize, b: usize) -> T {
+        let (a, b) = (self.input.vector(a), self.input.vector(b));
+        let norms = dot(a, a).sqrt() * dot(b, b).sqrt();
+        if norms == T::zero() { T::zero() } else { dot(a, b) / norms }
+    }
+
+    /// The learned input table as an `Embedding` of inputs of the shape
+    pub fn into_embedding(self, i_shape: &Shape) -> Embedding<T> {
+        Embedding::from_table(i_shape, self.input.vocab, self.input.dim, self.input.table)
+    }
+}
+
+#[test]
+fn test_skipgram() {
+    // the gradients against central differences
+    let center = [0.3, -0.2, 0.5];
+    let context = [0.1, 0.4, -0.3];
+    let negs = [[-0.2, 0.1, 0.6], [0.5, 0.5, -0.1]];
+    let negatives: Vec<&[f64]> = negs.iter().map(|n| &n[..]).collect();
+    let grad = sgns_loss(&center, &context, &negatives).unwrap();
+    let f = |c: &[f64], u: &[f64], n: &[&[f64]]| sgns_loss(c, u, n).unwrap().loss;
+    assert!((grad.loss - f(&center, &context, &negatives)).abs() < 1e-15);
+    let h = 1e-6;
+    for (i, dc) in grad.center.iter().enumerate() {
+        let (mut plus, mut minus) = (center, center);
+        plus[i] += h;
+        minus[i] -= h;
+        assert!((dc - (f(&plus, &context, &negatives) - f(&minus, &context, &negatives)) / (2. * h)).abs() < 1e-8);
+        let (mut plus, mut minus) = (context, context);
+        plus[i] += h;
+        minus[i] -= h;
+        assert!((grad.context[i] - (f(&center, &plus, &negatives) - f(&center, &minus, &negatives)) / (2. * h)).abs() < 1e-8);
+        let (mut plus, mut minus) = (negs[1], negs[1]);
+        plus[i] += h;
+        minus[i] -= h;
+        let numeric = (f(&center, &context, &[&negs[0][..], &plus[..]]) - f(&center, &context, &[&negs[0][..], &minus[..]])) / (2. * h);
+        assert!((grad.negatives[1][i] - numeric).abs() < 1e-8);
+    }
+    assert!(sgns_loss(&center, &[0.; 2], &[]).is_err());
+
+    // tokens 0 and 1 share their contexts 2 and 3, token 4 has the contexts 5 and 6
+    let sentences = [[0, 2, 3], [1, 2, 3], [4, 5, 6]];
+    let pairs: Vec<(usize, usize)> = sentences.iter().flat_map(|s| crate::datasets::skipgram_pairs(s, 2)).collect();
+    let sampler = NegativeSampler::new(&[1; 7], 0.75);
+    let mut sgns = SkipGram::<f64>::new(7, 8);
+    let mut rng = rand::thread_rng();
+    let first = sgns.train_pairs(&pairs, &sampler, 3, 0.2, &mut rng).unwrap();
+    let mut last = first;
+    for _ in 0..300 {
+        last = sgns.train_pairs(&pairs, &sampler, 3, 0.2, &mut rng).unwrap();
+    }
+    assert!(last < first);
+    assert!(sgns.similarity(0, 1) > sgns.similarity(0, 4));
+    assert!(sgns.train_pair(0, 7, &[], 0.1).is_err());
+    // a repeated token of half the weight still has negatives, an unknown token fails
+    let skewed = NegativeSampler::new(&[6, 1, 1, 1, 1, 1, 1], 1.);
+    assert!(sgns.train_pairs(&[(0, 0)], &skewed, 3, 0.1, &mut rng).is_ok());
+    assert!(sgns.train_pairs(&[(0, 7)], &skewed, 3, 0.1, &mut rng).is_err());
+
+    let emb = sgns.into_embedding(&Shape::new([2]));
+    assert_eq!(Layer::<f64>::get_output_shape(&emb), Shape::new([2, 8]));
+}
@@ -0,0 +1,123 @@
+//! Tiling of images larger than the input of a model: the image is cut into overlapping
+//! patches, the model runs on each patch, and the outputs are stitched back,
+//! averaging where patches overlap.
+//!
+//! The patches cover the whole image, the last row and column of patches being
+//! shifted to end at the border when the stride does not divide the image.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::tiling::*;
+//!     // a per-pixel model on 4x4 patches of 2 channels, mapping to 1 channel
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Conv2D::new(sh!([2, 4, 4]), 1, (3, 3), (1, 1), Padding::Same, Activation::Sigmoid));
+//!     let image = Tensor::new(sh!([2, 10, 13]), vec![0.5; 260]);
+//!     let mask = predict_tiled(&nn, &image, (4, 4), (3, 3)).unwrap();
+//!     assert_eq!(mask.get_shape(), sh!([1, 10, 13]));
+//! ```
+
+use crate::models::*;
+
+/// The start positions of the windows of `size` along `len`, `stride` apart, the last ending at `len`
+fn starts(len: usize, size: usize, stride: usize) -> Vec<usize> {
+    let mut s: Vec<usize> = (0..=len - size).step_by(stride).collect();
+    if *s.last().unwrap() != len - size {
+        s.push(len - size);
+    }
+    s
+}
+
+/// A patch and its top left corner `(y, x)` in the image
+pub type Patch<T> = ((usize, usize), Tensor<T>);
+
+/// Cut a `[channels, height, width]` image into `[channels, patch.0, patch.1]` patches
+pub fn extract_patches<T: NumT>(image: &Tensor<T>, patch: (usize, usize), stride: (usize, usize)) -> Result<Vec<Patch<T>>> {
+    if image.shape.rank() != 3 || patch.0 == 0 || patch.1 == 0 || stride.0 == 0 || stride.1 == 0
+        || patch.0 > image.shape[1] || patch.1 > image.shape[2] {
+        let message = format!("no patches {:?} of stride {:?} in the image {:?}", patch, stride, image.shape.dims());
+        return Err(EasynnError::invalid("extract_patches", message));
+    }
+    let (c, h, w) = (image.shape[0], image.shape[1], image.shape[2]);
+    let p_shape = Shape::new([c, patch.0, patch.1]);
+    let mut patches = Vec::new();
+    for &y in &starts(h, patch.0, stride.0) {
+        for &x in &starts(w, patch.1, stride.1) {
+            let mut data = Vec::with_capacity(p_shape.size());
+            for ch in 0..c {
+                for row in y..y + patch.0 {
+                    let start = (ch * h + row) * w + x;
+                    data.extend_from_slice(&image.flattened[start..start + patch.1]);
+                }
+            }
+            patches.push(((y, x), Tensor::new(&p_shape, data)));
+        }
+    }
+    Ok(patches)
+}
+
+/// Stitch `[channels, patch_h, patch_w]` outputs placed at their corners into
+/// a `[channels, height, width]` tensor, averaging the overlaps
+pub fn stitch_patches<T: NumT>(patches: &[Patch<T>], height: usize, width: usize) -> Result<Tensor<T>> {
+    let p_shape = match patches.first() {
+        Some((_, p)) if p.shape.rank() == 3 => p.shape.clone(),
+        _ => return Err(EasynnError::invalid("stitch_patches", "the patches should be non-empty and of rank 3")),
+    };
+    let (c, ph, pw) = (p_shape[0], p_shape[1], p_shape[2]);
+    let mut sum = Tensor::<T>::zeros(&Shape::new([c, height, width]));
+    let mut count = vec![0_usize; height * width];
+    for ((y, x), p) in patches {
+        check_shape("stitch_patches", &p_shape, &p.shape)?;
+        if y + ph > height || x + pw > width {
+            return Err(EasynnError::invalid("stitch_patches", format!("the patch at {:?} exceeds the image", (y, x))));
+        }
+        for row in 0..ph {
+            for col in 0..pw {
+                count[(y + row) * width + x + col] += 1;
+                for ch in 0..c {
+                    sum.flattened[(ch * height + y + row) * width + x + col] += p.flattened[(ch * ph + row) * pw + col];
+                }
+            }
+        }
+    }
+    if count.contains(&0) {
+        return Err(EasynnError::invalid("stitch_patches", "part of the image is not covered"));
+    }
+    for (i, s) in sum.flattened.iter_mut().enumerate() {
+        *s /= T::from(count[i % (height * width)]).unwrap();
+    }
+    Ok(sum)
+}
+
+/// Run a model mapping `[c_in, patch.0, patch.1]` to `[c_out, patch.0, patch.1]`
+/// over a larger `[c_in, height, width]` image, returning the stitched `[c_out, height, width]`
+pub fn predict_tiled<T: NumT, M: Model<T>>(model: &M, image: &Tensor<T>, patch: (usize, usize), stride: (usize, usize)) -> Result<Tensor<T>> {
+    let outputs = extract_patches(image, patch, stride)?.into_iter()
+        .map(|(at, p)| Ok((at, model.predict(&p)?)))
+        .collect::<Result<Vec<_>>>()?;
+    for (_, o) in &outputs {
+        let channels = o.shape.dims().first().copied().unwrap_or(0);
+        check_shape("predict_tiled", &Shape::new([channels, patch.0, patch.1]), &o.shape)?;
+    }
+    stitch_patches(&outputs, image.shape[1], image.shape[2])
+}
+
+#[test]
+fn test_tiling() {
+    let image = Tensor::<f64>::new(&Shape::new([2, 5, 7]), (0..70).map(|x| x as f64).collect());
+    let patches = extract_patches(&image, (3, 4), (2, 2)).unwrap();
+    // rows start at 0, 2, and columns at 0, 2, 3 (shifted to end at the border)
+    let corners: Vec<_> = patches.iter().map(|(at, _)| *at).collect();
+    assert_eq!(corners, vec![(0, 0), (0, 2), (0, 3), (2, 0), (2, 2), (2, 3)]);
+    let (_, p) = &patches[4];
+    assert_eq!(p.get([0, 0, 0]), image.get([0, 2, 2]));
+    assert_eq!(p.get([1, 2, 3]), image.get([1, 4, 5]));
+    // stitching unchanged patches gives the image back, overlaps averaging equal values
+    assert_eq!(stitch_patches(&patches, 5, 7).unwrap(), image);
+
+    // overlaps are averaged
+    let ones = ((0, 0), Tensor::<f64>::ones(&Shape::new([1, 2, 2])));
+    let threes = ((0, 1), Tensor::<f64>::new(&Shape::new([1, 2, 2]), vec![3.; 4]));
+    assert_eq!(stitch_patches(&[ones.clone(), threes], 2, 3).unwrap().as_slice(), &[1., 2., 3., 1., 2., 3.]);
+    assert!(stitch_patches(&[ones], 2, 3).is_err());
+    assert!(extract_patches(&image, (6, 4), (1, 1)).is_err());
+}
@@ -0,0 +1,450 @@
+//! Constructors of reference models, ready to train.
+//!
+//! `unet` builds the U-Net of segmentation: each level of the encoder runs two 3x3
+//! convolutions then halves the size with a `MaxPool2D`, and each level of the decoder
+//! doubles it back with an `UpSample2D` and a 3x3 convolution, concatenates the features
+//! of the encoder at the same level through a `Skip`, and runs two 3x3 convolutions.
+//! A 1x1 convolution with a sigmoid gives the probability of each class at each pixel,
+//! trained with `Loss::Dice`.
+//!
+//! `resnet_small` builds a small ResNet of classification: a 3x3 convolution, then three
+//! stages of `residual_block`s of 16, 32 and 64 channels, the last two halving the size,
+//! a `GlobalAvgPool2D` and a `Dense` giving the logits, trained with
+//! `Loss::SoftmaxCrossEntropy`. The convolutions are batch normalized, so train it with
+//! `Sequential::train_once_batched` and switch it out of the training mode to infer.
+//!
+//! `mixer` builds an MLP-Mixer of classification, free of spatial convolutions: the image is
+//! cut into patches embedded by a shared linear map, then each `mixer_block` mixes the
+//! features across the patches (tokens) and across the channels with two MLPs, each summed
+//! with its input. The MLPs apply to each row by 1x1 convolutions, `Permute` swapping the
+//! tokens and the channels around the token mixing. A `GlobalAvgPool2D` over the tokens and
+//! a `Dense` give the logits, trained with `Loss::SoftmaxCrossEntropy`.
+//!
+//! `ARCHITECTURES` lists the small classifiers built by name with `build`: `mnist_mlp` and
+//! `mnist_cnn` over `[1, 28, 28]` digits, and `cifar_cnn` over `[3, 32, 32]` images.
+//! `load_pretrained` loads their trained weights, saved by `Sequential::save` and
+//! described by `PretrainedWeights`. The file is downloaded into a cache directory and
+//! checked against its SHA-256 first, see the `cache` module.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::models::zoo::{ unet, resnet_small, mixer };
+//!     let nn = unet::<f32>(sh!([3, 32, 32]), 2, 8, 2);
+//!     let mask = nn.predict(&Tensor::zeros(sh!([3, 32, 32]))).unwrap();
+//!     assert_eq!(mask.get_shape(), sh!([2, 32, 32]));
+//!     let nn = resnet_small::<f32>(sh!([3, 32, 32]), 10);
+//!     assert_eq!(nn.predict(&Tensor::zeros(sh!([3, 32, 32]))).unwrap().get_shape(), sh!([10]));
+//!     let nn = mixer::<f32>(sh!([3, 32, 32]), 8, 16, 2, 10);
+//!     assert_eq!(nn.predict(&Tensor::zeros(sh!([3, 32, 32]))).unwrap().get_shape(), sh!([10]));
+//! ```
+
+use crate::models::*;
+use crate::models::sequential::Sequential;
+use crate::layers::conv::Conv2D;
+use crate::layers::dense::Dense;
+use crate::layers::pooling::{ MaxPool2D, GlobalAvgPool2D };
+use crate::layers::batch_norm::BatchNorm;
+use crate::layers::upsampling::UpSample2D;
+use crate::layers::reshape::{ Reshape, Permute };
+
+use crate::cache::{ self, Artifact };
+
+use std::fs;
+use std::io::{ Error, ErrorKind };
+use std::path::Path;
+use crate::layers::skip::{ Skip, Merge };
+
+/// A 3x3 convolution keeping the size, with a ReLU
+fn conv3x3<T: NumT>(i_shape: &Shape, channels: usize) -> Conv2D<T> {
+    Conv2D::new(i_shape, channels, (3, 3), (1, 1), Padding::Same, Activation::Relu)
+}
+
+/// Two 3x3 convolutions appended to a body
+fn double_conv<T: NumT + 'static>(skip: Skip<T>, channels: usize) -> Skip<T> {
+    let first = conv3x3(&skip.body_output_shape(), channels);
+    let second = conv3x3(&first.get_output_shape(), channels);
+    skip.then(first).then(second)
+}
+
+/// The concatenation of the encoder features `[channels, h, w]` of a level with the decoded
+/// features of the levels below, giving `[2 channels, h, w]`
+fn unet_level<T: NumT + 'static>(i_shape: &Shape, level: usize, depth: usize) -> Skip<T> {
+    let channels = i_shape[0];
+    let pool = MaxPool2D::new(i_shape, (2, 2), (2, 2));
+    let mut skip = Skip::new(i_shape, Merge::Concat, Activation::No);
+    let down = Layer::<T>::get_output_shape(&pool);
+    skip = double_conv(skip.then(pool), 2 * channels);
+    if level + 1 < depth {
+        let below = unet_level(&Shape::new([2 * channels, down[1], down[2]]), level + 1, depth);
+        skip = double_conv(skip.then(below), 2 * channels);
+    }
+    let up = UpSample2D::new(&skip.body_output_shape(), (2, 2));
+    let up_conv = conv3x3(&Layer::<T>::get_output_shape(&up), channels);
+    skip.then(up).then(up_conv)
+}
+
+/// The U-Net over `[in_channels, height, width]` inputs, halving the size `depth` times,
+/// with `base_channels` channels at the top level doubling at each level below,
+/// and `out_channels` probabilities at each pixel
+///
+/// The height and the width should be divisible by `2^depth`
+pub fn unet<T: NumT + 'static>(i_shape: &Shape, depth: usize, base_channels: usize, out_channels: usize) -> Sequential<T> {
+    if i_shape.rank() != 3 || base_channels == 0 || out_channels == 0 {
+        panic!("U-Net needs a [channels, height, width] input and positive channels!");
+    }
+    let scale = 1_usize.checked_shl(depth as u32).unwrap_or(0);
+    if scale == 0 || !i_shape[1].is_multiple_of(scale) || !i_shape[2].is_multiple_of(scale) {
+        panic!("The height and the width should be divisible by 2^depth!");
+    }
+    let (h, w) = (i_shape[1], i_shape[2]);
+    let mut nn = Sequential::new(Loss::Dice);
+    nn.add(conv3x3::<T>(i_shape, base_channels));
+    nn.add(conv3x3::<T>(&Shape::new([base_channels, h, w]), base_channels));
+    if depth > 0 {
+        nn.add(unet_level::<T>(&Shape::new([base_channels, h, w]), 0, depth));
+        nn.add(conv3x3::<T>(&Shape::new([2 * base_channels, h, w]), base_channels));
+        nn.add(conv3x3::<T>(&Shape::new([base_channels, h, w]), base_channels));
+    }
+    nn.add(Conv2D::new(&Shape::new([base_channels, h, w]), out_channels, (1, 1), (1, 1), Padding::Valid, Activation::Sigmoid));
+    nn
+}
+
+/// The basic block of ResNet, see `residual_block`
+pub type ResidualBlock<T> = Skip<T>;
+
+/// A convolution without activation followed by a batch normalization
+fn conv_bn<T: NumT + 'static>(i_shape: &Shape, channels: usize, kernel: usize, stride: usize, act: Activation<T>) -> (Conv2D<T>, BatchNorm<T>) {
+    let conv = Conv2D::new(i_shape, channels, (kernel, kernel), (stride, stride), Padding::Same, Activation::No);
+    let bn = BatchNorm::new(&conv.get_output_shape(), act);
+    (conv, bn)
+}
+
+/// The basic block of ResNet over `[channels, h, w]` inputs: two batch normalized 3x3
+/// convolutions to `channels`, the first striding by `stride`, summed with the input and
+/// followed by a ReLU. When the shape changes, the input goes through a batch normalized
+/// 1x1 convolution of the same stride
+pub fn residual_block<T: NumT + 'static>(i_shape: &Shape, channels: usize, stride: usize) -> ResidualBlock<T> {
+    if i_shape.rank() != 3 || channels == 0 || stride == 0 {
+        panic!("A residual block needs a [channels, height, width] input and positive channels and stride!");
+    }
+    let (conv1, bn1) = conv_bn(i_shape, channels, 3, stride, Activation::Relu);
+    let (conv2, bn2) = conv_bn(&conv1.get_output_shape(), channels, 3, 1, Activation::No);
+    let mut block = Skip::new(i_shape, Merge::Add, Activation::Relu).then(conv1).then(bn1).then(conv2).then(bn2);
+    if block.body_output_shape() != *i_shape {
+        let (proj, bn) = conv_bn(i_shape, channels, 1, stride, Activation::No);
+        block = block.shortcut(proj).shortcut(bn);
+    }
+    block
+}
+
+/// A small ResNet over `[channels, height, width]` inputs giving the logits of `classes`
+pub fn resnet_small<T: NumT + 'static>(i_shape: &Shape, classes: usize) -> Sequential<T> {
+    if i_shape.rank() != 3 || classes == 0 {
+        panic!("ResNet needs a [channels, height, width] input and positive classes!");
+    }
+    let mut nn = Sequential::new(Loss::SoftmaxCrossEntropy);
+    let (conv, bn) = conv_bn::<T>(i_shape, 16, 3, 1, Activation::Relu);
+    let mut shape = conv.get_output_shape();
+    nn.add(conv);
+    nn.add(bn);
+    for (channels, stride) in [(16, 1), (32, 2), (64, 2)] {
+        let block = residual_block::<T>(&shape, channels, stride);
+        shape = block.get_output_shape();
+        nn.add(block);
+    }
+    nn.add(GlobalAvgPool2D::new(&shape));
+    nn.add(Dense::new(&Shape::new([64]), &Shape::new([classes]), Activation::No));
+    nn
+}
+
+/// A 1x1 convolution, applying the same dense map to the channels of each position
+fn pointwise<T: NumT>(i_shape: &Shape, channels: usize, act: Activation<T>) -> Conv2D<T> {
+    Conv2D::new(i_shape, channels, (1, 1), (1, 1), Padding::Valid, act)
+}
+
+/// The block of MLP-Mixer over `[channels, h, w]` features of `h * w` tokens: the token
+/// mixing, an MLP of `token_hidden` ReLU units over the tokens of each channel, then the
+/// channel mixing, an MLP of `channel_hidden` ReLU units over the channels of each token,
+/// each summed with its input
+pub fn mixer_block<T: NumT + 'static>(i_shape: &Shape, token_hidden: usize, channel_hidden: usize) -> (Skip<T>, Skip<T>) {
+    if i_shape.rank() != 3 || token_hidden == 0 || channel_hidden == 0 {
+        panic!("A mixer block needs a [channels, height, width] input and positive hidden units!");
+    }
+    let (channels, tokens) = (i_shape[0], i_shape[1] * i_shape[2]);
+    let rows = Shape::new([channels, 1, tokens]);
+    let columns = Shape::new([tokens, 1, channels]);
+    let token_mixing = Skip::new(i_shape, Merge::Add, Activation::No)
+        .then(Reshape::new(i_shape, &rows))
+        .then(Permute::new(&rows, &[2, 1, 0]))
+        .then(pointwise(&columns, token_hidden, Activation::Relu))
+        .then(pointwise(&Shape::new([token_hidden, 1, channels]), tokens, Activation::No))
+        .then(Permute::new(&columns, &[2, 1, 0]))
+        .then(Reshape::new(&rows, i_shape));
+    let channel_mixing = Skip::new(i_shape, Merge::Add, Activation::No)
+        .then(pointwise(i_shape, channel_hidden, Activation::Relu))
+        .then(pointwise(&Shape::new([channel_hidden, i_shape[1], i_shape[2]]), channels, Activation::No));
+    (token_mixing, channel_mixing)
+}
+
+/// An MLP-Mixer over `[channels, height, width]` inputs giving the logits of `classes`,
+/// embedding the `patch` x `patch` patches to `channels` and mixing them by `blocks`
+/// `mixer_block`s of twice the tokens and four times the channels as hidden units
+///
+/// The layer normalizations of the original model are left out
+pub fn mixer<T: NumT + 'static>(i_shape: &Shape, patch: usize, channels: usize, blocks: usize, classes: usize) -> Sequential<T> {
+    if i_shape.rank() != 3 || channels == 0 || classes == 0 {
+        panic!("MLP-Mixer needs a [channels, height, width] input and positive channels and classes!");
+    }
+    if patch == 0 || i_shape[1] < patch || i_shape[2] < patch {
+        panic!("The patches should be positive and fit the input!");
+    }
+    let mut nn = Sequential::new(Loss::SoftmaxCrossEntropy);
+    let embed = Conv2D::new(i_shape, channels, (patch, patch), (patch, patch), Padding::Valid, Activation::No);
+    let shape = embed.get_output_shape();
+    nn.add(embed);
+    for _ in 0..blocks {
+        let (token_mixing, channel_mixing) = mixer_block::<T>(&shape, 2 * shape[1] * shape[2], 4 * channels);
+        nn.add(token_mixing);
+        nn.add(channel_mixing);
+    }
+    nn.add(GlobalAvgPool2D::new(&shape));
+    nn.add(Dense::new(&Shape::new([channels]), &Shape::new([classes]), Activation::No));
+    nn
+}
+
+/// A built-in architecture, see `build`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Architecture {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub input: &'static [usize],
+    pub classes: usize,
+}
+
+pub const ARCHITECTURES: &[Architecture] = &[
+    Architecture { name: "mnist_mlp", description: "a 128-unit MLP of MNIST digits", input: &[1, 28, 28], classes: 10 },
+    Architecture { name: "mnist_cnn", description: "two convolutions and a Dense of MNIST digits", input: &[1, 28, 28], classes: 10 },
+    Architecture { name: "cifar_cnn", description: "three convolutions and a Dense of CIFAR-10 images", input: &[3, 32, 32], classes: 10 },
+];
+
+/// The MLP of MNIST: a `Dense` of 128 ReLU units and a `Dense` giving the logits
+pub fn mnist_mlp<T: NumT + 'static>() -> Sequential<T> {
+    let mut nn = Sequential::new(Loss::SoftmaxCrossEntropy);
+    nn.add(Dense::new(&Shape::new([1, 28, 28]), &Shape::new([128]), Activation::Relu));
+    nn.add(Dense::new(&Shape::new([128]), &Shape::new([10]), Activation::No));
+    nn
+}
+
+/// Convolutions of `channels` each halved by a `MaxPool2D`, then a `Dense` giving the logits
+fn small_cnn<T: NumT + 'static>(i_shape: &Shape, channels: &[usize], classes: usize) -> Sequential<T> {
+    let mut nn = Sequential::new(Loss::SoftmaxCrossEntropy);
+    let mut shape = i_shape.clone();
+    for c in channels {
+        let conv = conv3x3::<T>(&shape, *c);
+        let pool = MaxPool2D::new(&conv.get_output_shape(), (2, 2), (2, 2));
+        shape = Layer::<T>::get_output_shape(&pool);
+        nn.add(conv);
+        nn.add(pool);
+    }
+    nn.add(Dense::new(&shape, &Shape::new([classes]), Activation::No));
+    nn
+}
+
+/// The CNN of MNIST: 3x3 convolutions of 8 and 16 channels, each pooled, and a `Dense`
+pub fn mnist_cnn<T: NumT + 'static>() -> Sequential<T> {
+    small_cnn(&Shape::new([1, 28, 28]), &[8, 16], 10)
+}
+
+/// The CNN of CIFAR-10: 3x3 convolutions of 16, 32 and 64 channels, each pooled, and a `Dense`
+pub fn cifar_cnn<T: NumT + 'static>() -> Sequential<T> {
+    small_cnn(&Shape::new([3, 32, 32]), &[16, 32, 64], 10)
+}
+
+/// The untrained model of the architecture listed in `ARCHITECTURES`
+pub fn build<T: NumT + 'static>(name: &str) -> Option<Sequential<T>> {
+    match name {
+        "mnist_mlp" => Some(mnist_mlp()),
+        "mnist_cnn" => Some(mnist_cnn()),
+        "cifar_cnn" => Some(cifar_cnn()),
+        _ => None,
+    }
+}
+
+/// A file of trained weights of a built-in architecture, saved by `Sequential::save`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PretrainedWeights {
+    /// The name in `ARCHITECTURES`
+    pub architecture: String,
+    pub url: String,
+    /// The SHA-256 of the file, in hexadecimal
+    pub sha256: String,
+}
+
+impl PretrainedWeights {
+    /// The file in the cache, named by the architecture and the hash
+    pub fn artifact(&self) -> Artifact {
+        let hash: String = self.sha256.chars().take(16).collect();
+        let name = format!("{}-{}.eznn", self.architecture, hash.to_lowercase());
+        Artifact::new(&name, &self.url, &self.sha256)
+    }
+}
+
+/// Load the pretrained model, downloading and verifying the weights unless already
+/// in the cache directory, see `cache::fetch_in`, and checking that the layers are
+/// those of the architecture. `cache::cache_dir()` is the configured cache directory.
+pub fn load_pretrained<T: NumT + 'static>(weights: &PretrainedWeights, cache_dir: &Path) -> std::io::Result<Sequential<T>> {
+    let reference = build::<T>(&weights.architecture)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, format!("unknown architecture {}", weights.architecture)))?;
+    let data = fs::read(cache::fetch_in(&weights.artifact(), cache_dir)?)?;
+    let model = Sequential::<T>::read_from(&mut data.as_slice())?;
+    let shapes = |m: &Sequential<T>| m.layers().iter().map(|l| (l.get_input_shape(), l.get_output_shape())).collect::<Vec<_>>();
+    if shapes(&model) != shapes(&reference) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("the weights do not fit the architecture {}", weights.architecture)));
+    }
+    Ok(model)
+}
+
+#[test]
+fn test_unet() {
+    let nn = unet::<f64>(&Shape::new([2, 8, 12]), 2, 2, 3);
+    // two convolutions, the skip of the first level, two convolutions and the output
+    assert_eq!(nn.len(), 6);
+    assert_eq!(nn.layers()[2].get_output_shape(), Shape::new([4, 8, 12]));
+    assert_eq!(nn.predict(&Tensor::zeros(&Shape::new([2, 8, 12]))).unwrap().get_shape(), &Shape::new([3, 8, 12]));
+    assert_eq!(unet::<f64>(&Shape::new([1, 3, 3]), 0, 2, 1).len(), 3);
+
+    // learn to segment the bright pixels
+    let mut nn = unet::<f64>(&Shape::new([1, 4, 4]), 1, 2, 1);
+    let inputs: Vec<_> = (0..8).map(|i| Tensor::new(&Shape::new([1, 4, 4]), (0..16).map(|p| ((p * 5 + i * 3) % 7) as f64 / 6.).collect())).collect();
+    let truths: Vec<_> = inputs.iter().map(|x| x.map(|v| if v > 0.5 { 1. } else { 0. })).collect();
+    let before = nn.evaluate(&inputs, &truths);
+    let mut adam = crate::optim::Adam::new(0.01);
+    for _ in 0..30 {
+        nn.train_once_optimized(&inputs, &truths, 4, &mut adam, false);
+    }
+    assert!(nn.evaluate(&inputs, &truths) < before);
+}
+
+#[test]
+fn test_resnet_small() {
+    // the identity shortcut when the shape is kept, a projection otherwise
+    let block = residual_block::<f64>(&Shape::new([4, 6, 6]), 4, 1);
+    assert!(block.shortcut.is_empty());
+    let block = residual_block::<f64>(&Shape::new([4, 6, 6]), 8, 2);
+    assert_eq!(block.shortcut.len(), 2);
+    assert_eq!(block.get_output_shape(), Shape::new([8, 3, 3]));
+
+    let mut nn = resnet_small::<f64>(&Shape::new([1, 4, 4]), 2);
+    assert_eq!(nn.len(), 7);
+    assert_eq!(nn.layers()[4].get_output_shape(), Shape::new([64, 1, 1]));
+
+    // learn whether the left or the right half is bright
+    let inputs: Vec<_> = (0..8).map(|i| Tensor::new(&Shape::new([1, 4, 4]), (0..16).map(|p| {
+        let bright = (p % 4 < 2) == (i % 2 == 0);
+        (if bright { 1. } else { 0. }) + ((p * 3 + i) % 5) as f64 / 10.
+    }).collect())).collect();
+    let truths: Vec<_> = (0..8).map(|i| Tensor::new(&Shape::new([2]), if i % 2 == 0 { vec![1., 0.] } else { vec![0., 1.] })).collect();
+    let mut losses = Vec::new();
+    for _ in 0..10 {
+        losses.push(nn.train_once_batched(&inputs, &truths, 8, 0.05, false));
+    }
+    assert!(losses.iter().all(|l| l.is_finite()));
+    assert!(losses[9] < losses[0]);
+    nn.set_training(false);
+    assert!(nn.evaluate(&inputs, &truths).is_finite());
+}
+
+#[test]
+fn test_mixer() {
+    // the token mixing of a block mixes the columns of each channel and not the channels
+    let (token_mixing, channel_mixing) = mixer_block::<f64>(&Shape::new([2, 2, 2]), 3, 5);
+    assert_eq!(token_mixing.body_output_shape(), Shape::new([2, 2, 2]));
+    assert_eq!(token_mixing.body[2].get_input_shape(), Shape::new([4, 1, 2]));
+    let count = |s: &Skip<f64>| s.parameters().iter().map(|p| p.len()).sum::<usize>();
+    assert_eq!(count(&token_mixing) + count(&channel_mixing), (4 * 3 + 3) + (3 * 4 + 4) + (2 * 5 + 5) + (5 * 2 + 2));
+    let input = Tensor::new(&Shape::new([2, 2, 2]), vec![1., 2., 3., 4., 5., 6., 7., 8.]);
+    let changed = Tensor::new(&Shape::new([2, 2, 2]), vec![4., 3., 2., 1., 5., 6., 7., 8.]);
+    let (y, y_changed) = (token_mixing.forward_propagate(&input, true).unwrap(), token_mixing.forward_propagate(&changed, true).unwrap());
+    assert_eq!(y.as_slice()[4..], y_changed.as_slice()[4..]);
+    assert_ne!(y.as_slice()[..4], y_changed.as_slice()[..4]);
+
+    let mut nn = mixer::<f64>(&Shape::new([1, 4, 4]), 2, 4, 1, 2);
+    assert_eq!(nn.len(), 5);
+    assert_eq!(nn.layers()[1].get_input_shape(), Shape::new([4, 2, 2]));
+
+    // learn whether the left or the right half is bright
+    let inputs: Vec<_> = (0..8).map(|i| Tensor::new(&Shape::new([1, 4, 4]), (0..16).map(|p| {
+        let bright = (p % 4 < 2) == (i % 2 == 0);
+        (if bright { 1. } else { 0. }) + ((p * 3 + i) % 5) as f64 / 10.
+    }).collect())).collect();
+    let truths: Vec<_> = (0..8).map(|i| Tensor::new(&Shape::new([2]), if i % 2 == 0 { vec![1., 0.] } else { vec![0., 1.] })).collect();
+    let before = nn.evaluate(&inputs, &truths);
+    let mut adam = crate::optim::Adam::new(0.01);
+    for _ in 0..30 {
+        nn.train_once_optimized(&inputs, &truths, 4, &mut adam, false);
+    }
+    assert!(nn.evaluate(&inputs, &truths) < before);
+}
+
+#[test]
+fn test_zoo_serialize() {
+    // the models of skip connections save and load with the inner layers
+    let models = [
+        (unet::<f64>(&Shape::new([1, 4, 4]), 1, 2, 1), Shape::new([1, 4, 4])),
+        (resnet_small::<f64>(&Shape::new([1, 4, 4]), 2), Shape::new([1, 4, 4])),
+        (mixer::<f64>(&Shape::new([1, 4, 4]), 2, 4, 1, 2), Shape::new([1, 4, 4])),
+    ];
+    for (mut nn, i_shape) in models {
+        let x = Tensor::new(&i_shape, (0..i_shape.size()).map(|p| (p as f64 * 0.7).sin()).collect());
+        // move the running statistics of the batch normalizations away from their start
+        nn.train_once_batched(&[x.clone(), x.map(|v| 1. - v)], &[nn.predict(&x).unwrap(), nn.predict(&x).unwrap()], 2, 0.01, false);
+        nn.set_training(false);
+        let mut buf = Vec::new();
+        nn.write_to(&mut buf).unwrap();
+        let mut loaded = Sequential::<f64>::read_from(&mut buf.as_slice()).unwrap();
+        loaded.set_training(false);
+        assert_eq!(loaded.len(), nn.len());
+        assert_eq!(loaded.predict(&x).unwrap(), nn.predict(&x).unwrap());
+        assert_eq!(loaded.parameter_names(), nn.parameter_names());
+    }
+}
+
+#[test]
+fn test_pretrained() {
+    for arch in ARCHITECTURES {
+        let nn = build::<f32>(arch.name).unwrap();
+        let output = nn.predict(&Tensor::zeros(&Shape::from_slice(arch.input))).unwrap();
+        assert_eq!(output.get_shape(), &Shape::new([arch.classes]));
+    }
+    assert!(build::<f32>("unknown").is_none());
+
+    // publish the weights of a model as a file, then load them through the cache
+    let dir = std::env::temp_dir().join(format!("easynn_zoo_{}", std::process::id()));
+    let (source, cache_dir) = (dir.join("weights.eznn"), dir.join("cache"));
+    fs::create_dir_all(&dir).unwrap();
+    let trained = mnist_mlp::<f64>();
+    trained.save(&source).unwrap();
+    let mut weights = PretrainedWeights {
+        architecture: "mnist_mlp".to_string(),
+        url: format!("file://{}", source.display()),
+        sha256: cache::sha256_hex(&fs::read(&source).unwrap()),
+    };
+    let x = Tensor::new(&Shape::new([1, 28, 28]), (0..784).map(|i| (i % 7) as f64 / 7.).collect());
+    let loaded = load_pretrained::<f64>(&weights, &cache_dir).unwrap();
+    assert_eq!(loaded.predict(&x).unwrap(), trained.predict(&x).unwrap());
+    // the cached file serves without the source
+    fs::remove_file(&source).unwrap();
+    assert!(weights.artifact().path_in(&cache_dir).exists());
+    assert_eq!(load_pretrained::<f64>(&weights, &cache_dir).unwrap().predict(&x).unwrap(), trained.predict(&x).unwrap());
+
+    // a wrong hash is rejected and an unknown architecture is not found
+    weights.sha256 = cache::sha256_hex(b"other");
+    assert_eq!(load_pretrained::<f64>(&weights, &cache_dir).err().unwrap().kind(), ErrorKind::NotFound);
+    mnist_cnn::<f64>().save(&source).unwrap();
+    assert_eq!(load_pretrained::<f64>(&weights, &cache_dir).err().unwrap().kind(), ErrorKind::InvalidData);
+    weights.sha256 = cache::sha256_hex(&fs::read(&source).unwrap());
+    assert_eq!(load_pretrained::<f64>(&weights, &cache_dir).err().unwrap().kind(), ErrorKind::InvalidData);
+    weights.architecture = "unknown".to_string();
+    assert_eq!(load_pretrained::<f64>(&weights, &cache_dir).err().unwrap().kind(), ErrorKind::NotFound);
+    fs::remove_dir_all(&dir).unwrap();
+}
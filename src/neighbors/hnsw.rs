@@ -0,0 +1,311 @@
+//! An approximate nearest neighbor index of embeddings: the HNSW graph of
+//! [Malkov and Yashunin](https://arxiv.org/abs/1603.09320).
+//!
+//! Each embedding is a node linked to its near nodes on its level and on every level
+//! below, the levels being drawn at random with exponentially fewer nodes above.
+//! A search descends greedily from the top level, then explores the `ef` nearest
+//! candidates of level 0: a larger `ef` finds the true neighbors more often, slower.
+//! The nodes are numbered in their order of insertion, e.g. the rows of a matrix.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::neighbors::*;
+//!     let points = Tensor::<f32>::new(sh!([4, 2]), vec![0., 0., 0., 1., 5., 5., 6., 5.]);
+//!     let mut index = Hnsw::from_embeddings(&points, Metric::Euclidean, HnswConfig::default()).unwrap();
+//!     let found = index.search(&Tensor::new(sh!([2]), vec![5., 4.]), 2).unwrap();
+//!     assert_eq!(found.iter().map(|n| n.index).collect::<Vec<_>>(), [2, 3]);
+//!     assert_eq!(index.insert(&Tensor::new(sh!([2]), vec![5., 4.])).unwrap(), 4);
+//! ```
+
+use crate::tensor::*;
+use crate::parallel;
+use crate::neighbors::knn::{ Metric, Neighbor, by_distance, cosine, norm };
+
+use rand::{ Rng, SeedableRng };
+use rand::rngs::StdRng;
+
+use std::cmp::{ Ordering, Reverse };
+use std::collections::{ BinaryHeap, HashSet };
+
+type Result<T> = std::result::Result<T, EasynnError>;
+
+#[derive(Debug, Clone)]
+pub struct HnswConfig {
+    /// The links of a node on each level above 0, twice as many on level 0
+    pub m: usize,
+    /// The candidates explored when inserting
+    pub ef_construction: usize,
+    /// The candidates explored when searching, at least the count of neighbors requested
+    pub ef_search: usize,
+    /// The seed of the levels drawn
+    pub seed: u64,
+}
+
+impl Default for HnswConfig {
+    fn default() -> Self {
+        HnswConfig { m: 16, ef_construction: 200, ef_search: 64, seed: 0 }
+    }
+}
+
+/// A neighbor ordered by `by_distance`, for the heaps of the search
+#[derive(Debug, Copy, Clone)]
+struct Near<T: NumT>(Neighbor<T>);
+
+impl<T: NumT> PartialEq for Near<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<T: NumT> Eq for Near<T> { }
+impl<T: NumT> PartialOrd for Near<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<T: NumT> Ord for Near<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        by_distance(&self.0, &other.0)
+    }
+}
+
+/// The HNSW index, see the module
+#[derive(Debug, Clone)]
+pub struct Hnsw<T: NumT> {
+    dim: usize,
+    metric: Metric,
+    config: HnswConfig,
+    /// The embeddings, row by row
+    data: Vec<T>,
+    /// Their norms, for the cosine distance
+    norms: Vec<T>,
+    /// The links of each node on each of its levels
+    links: Vec<Vec<Vec<usize>>>,
+    /// The node of the top level, where searches start
+    entry: Option<usize>,
+    rng: StdRng,
+}
+
+impl<T: NumT> Hnsw<T> {
+    /// An empty index of embeddings of `dim` elements
+    pub fn new(dim: usize, metric: Metric, config: HnswConfig) -> Result<Self> {
+        if config.m < 2 || config.ef_construction == 0 {
+            return Err(EasynnError::invalid("Hnsw::new", "m should be at least 2 and ef_construction positive"));
+        }
+        let rng = StdRng::seed_from_u64(config.seed);
+        Ok(Hnsw { dim, metric, config, data: Vec::new(), norms: Vec::new(), links: Vec::new(), entry: None, rng })
+    }
+    /// The index of the rows of the `[n, d]` embeddings, numbered as the rows
+    pub fn from_embeddings(embeddings: &Tensor<T>, metric: Metric, config: HnswConfig) -> Result<Self> {
+        if embeddings.shape.rank() != 2 {
+            return Err(EasynnError::invalid("Hnsw::from_embeddings", "the embeddings should be [n, d]"));
+        }
+        let dim = embeddings.shape[1];
+        let mut index = Self::new(dim, metric, config)?;
+        for row in 0..embeddings.shape[0] {
+            index.insert_slice(&embeddings.flattened[row * dim..(row + 1) * dim]);
+        }
+        Ok(index)
+    }
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+    /// Set the candidates explored when searching
+    pub fn set_ef_search(&mut self, ef: usize) {
+        self.config.ef_search = ef;
+    }
+
+    fn row(&self, node: usize) -> &[T] {
+        &self.data[node * self.dim..(node + 1) * self.dim]
+    }
+    /// The distance of the query, of the norm given, to the node
+    fn distance(&self, query: &[T], query_norm: T, node: usize) -> T {
+        match self.metric {
+            Metric::Euclidean => self.metric.distance(query, self.row(node)),
+            Metric::Cosine => cosine(query, self.row(node), query_norm, self.norms[node]),
+        }
+    }
+    fn near(&self, query: &[T], query_norm: T, node: usize) -> Near<T> {
+        Near(Neighbor { index: node, distance: self.distance(query, query_norm, node) })
+    }
+    fn max_links(&self, level: usize) -> usize {
+        if level == 0 { 2 * self.config.m } else { self.config.m }
+    }
+
+    /// The `ef` nearest nodes of the level reached from the entries, nearest first
+    fn search_level(&self, query: &[T], query_norm: T, entries: &[Near<T>], ef: usize, level: usize) -> Vec<Near<T>> {
+        let mut visited: HashSet<usize> = entries.iter().map(|e| e.0.index).collect();
+        let mut candidates: BinaryHeap<Reverse<Near<T>>> = entries.iter().map(|e| Reverse(*e)).collect();
+        let mut found: BinaryHeap<Near<T>> = entries.iter().copied().collect();
+        while let Some(Reverse(c)) = candidates.pop() {
+            if found.len() >= ef && c > *found.peek().unwrap() {
+                break;
+            }
+            for &next in &self.links[c.0.index][level] {
+                if !visited.insert(next) {
+                    continue;
+                }
+                let n = self.near(query, query_norm, next);
+                if found.len() < ef || n < *found.peek().unwrap() {
+                    candidates.push(Reverse(n));
+                    found.push(n);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+
+    /// Keep up to `m` of the candidates, nearest first, each nearer to the base than to
+    /// the ones kept before, so that the links spread in every direction
+    fn select(&self, candidates: &[Near<T>], m: usize) -> Vec<usize> {
+        let mut kept: Vec<usize> = Vec::with_capacity(m);
+        for c in candidates {
+            if kept.len() == m {
+                break;
+            }
+            let row = self.row(c.0.index);
+            let row_norm = if self.metric == Metric::Cosine { self.norms[c.0.index] } else { T::zero() };
+            if kept.iter().all(|k| c.0.distance < self.distance(row, row_norm, *k)) {
+                kept.push(c.0.index);
+            }
+        }
+        kept
+    }
+
+    fn insert_slice(&mut self, embedding: &[T]) -> usize {
+        let node = self.links.len();
+        let query_norm = norm(embedding);
+        self.data.extend_from_slice(embedding);
+        self.norms.push(query_norm);
+        // P(level >= l) = m^-l
+        let u: f64 = self.rng.gen_range(f64::EPSILON..1.);
+        let level = (-u.ln() / (self.config.m as f64).ln()) as usize;
+        self.links.push(vec![Vec::new(); level + 1]);
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => {
+                self.entry = Some(node);
+                return node;
+            },
+        };
+        let top = self.links[entry].len() - 1;
+        let mut entries = vec![self.near(embedding, query_norm, entry)];
+        for l in (level + 1..=top).rev() {
+            entries = self.search_level(embedding, query_norm, &entries, 1, l);
+        }
+        for l in (0..=level.min(top)).rev() {
+            entries = self.search_level(embedding, query_norm, &entries, self.config.ef_construction, l);
+            let selected = self.select(&entries, self.config.m);
+            for &other in &selected {
+                self.links[other][l].push(node);
+                if self.links[other][l].len() > self.max_links(l) {
+                    // shrink the links of the other node to its nearest spread ones
+                    let base = self.row(other).to_vec();
+                    let base_norm = self.norms[other];
+                    let mut linked: Vec<Near<T>> = self.links[other][l].iter().map(|n| self.near(&base, base_norm, *n)).collect();
+                    linked.sort();
+                    let shrunk = self.select(&linked, self.max_links(l));
+                    self.links[other][l] = shrunk;
+                }
+            }
+            self.links[node][l] = selected;
+        }
+        if level > top {
+            self.entry = Some(node);
+        }
+        node
+    }
+
+    fn check_query(&self, op: &'static str, t: &Tensor<T>) -> Result<()> {
+        check_shape(op, &Shape::new([self.dim]), &t.shape)
+    }
+
+    /// Insert the `[d]` embedding, returning its node
+    pub fn insert(&mut self, embedding: &Tensor<T>) -> Result<usize> {
+        self.check_query("insert", embedding)?;
+        Ok(self.insert_slice(&embedding.flattened))
+    }
+
+    fn search_slice(&self, query: &[T], k: usize) -> Vec<Neighbor<T>> {
+        let entry = match self.entry {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+        let query_norm = norm(query);
+        let mut entries = vec![self.near(query, query_norm, entry)];
+        for l in (1..self.links[entry].len()).rev() {
+            entries = self.search_level(query, query_norm, &entries, 1, l);
+        }
+        let mut found = self.search_level(query, query_norm, &entries, self.config.ef_search.max(k), 0);
+        found.truncate(k);
+        found.into_iter().map(|n| n.0).collect()
+    }
+
+    /// The approximate `k` nearest nodes of the `[d]` query, nearest first,
+    /// fewer if the index holds fewer
+    pub fn search(&self, query: &Tensor<T>, k: usize) -> Result<Vec<Neighbor<T>>> {
+        self.check_query("search", query)?;
+        Ok(self.search_slice(&query.flattened, k))
+    }
+
+    /// The searches of each row of the `[q, d]` queries, in parallel
+    pub fn search_batch(&self, queries: &Tensor<T>, k: usize) -> Result<Vec<Vec<Neighbor<T>>>> {
+        check_shape("search_batch", &Shape::new([queries.shape.dims().first().copied().unwrap_or(0), self.dim]), &queries.shape)?;
+        let q = queries.shape[0];
+        let mut found = vec![Vec::new(); q];
+        let work = q * self.config.ef_search.max(k) * self.config.m * self.dim;
+        parallel::chunks_mut(work, &mut found, 1, |i, slot| {
+            slot[0] = self.search_slice(&queries.flattened[i * self.dim..(i + 1) * self.dim], k);
+        });
+        Ok(found)
+    }
+}
+
+#[test]
+fn test_hnsw() {
+    use crate::neighbors::knn::knn_search;
+    // clustered points in 8 dimensions
+    let mut rng = StdRng::seed_from_u64(7);
+    let (n, d) = (600, 8);
+    let centers: Vec<f64> = (0..10 * d).map(|_| rng.gen_range(-5.0..5.)).collect();
+    let data: Vec<f64> = (0..n).flat_map(|i| {
+        let c = (i % 10) * d;
+        (0..d).map(|j| centers[c + j] + rng.gen_range(-1.0..1.)).collect::<Vec<_>>()
+    }).collect();
+    let points = Tensor::new(&Shape::new([n, d]), data);
+    let queries = Tensor::new(&Shape::new([50, d]), points.flattened[..50 * d].iter().map(|x| x + 0.05).collect());
+
+    for metric in [Metric::Euclidean, Metric::Cosine] {
+        let config = HnswConfig { m: 8, ef_construction: 64, ef_search: 100, seed: 1 };
+        let index = Hnsw::from_embeddings(&points, metric, config).unwrap();
+        assert_eq!(index.len(), n);
+        let exact = knn_search(&points, &queries, 10, metric).unwrap();
+        let approx = index.search_batch(&queries, 10).unwrap();
+        let mut hits = 0;
+        for (e, a) in exact.iter().zip(approx.iter()) {
+            assert_eq!(a.len(), 10);
+            assert!(a.windows(2).all(|w| w[0].distance <= w[1].distance));
+            hits += a.iter().filter(|x| e.iter().any(|y| y.index == x.index)).count();
+        }
+        assert!(hits as f64 / 500. > 0.9, "recall of {:?}: {}", metric, hits as f64 / 500.);
+    }
+
+    // the inserted embeddings are found, the searches of fewer nodes return them all
+    let mut index = Hnsw::new(2, Metric::Euclidean, HnswConfig::default()).unwrap();
+    assert!(index.search(&Tensor::new(&Shape::new([2]), vec![0., 0.]), 3).unwrap().is_empty());
+    for (i, p) in [[0., 0.], [1., 0.], [0., 1.]].iter().enumerate() {
+        assert_eq!(index.insert(&Tensor::new(&Shape::new([2]), p.to_vec())).unwrap(), i);
+    }
+    let found = index.search(&Tensor::new(&Shape::new([2]), vec![0.9, 0.2]), 5).unwrap();
+    assert_eq!(found.iter().map(|n| n.index).collect::<Vec<_>>(), [1, 0, 2]);
+    assert!(index.insert(&Tensor::new(&Shape::new([3]), vec![0.; 3])).is_err());
+    assert!(Hnsw::<f64>::new(2, Metric::Cosine, HnswConfig { m: 1, ..HnswConfig::default() }).is_err());
+}
@@ -0,0 +1,194 @@
+//! Brute-force k-nearest-neighbor search over the rows of a `[n, d]` matrix of embeddings,
+//! the queries being searched in parallel under the crate-wide `ParallelConfig`.
+//!
+//! The neighbors of a query are ordered by their distance, ties going to the lower row,
+//! and `Knn` classifies queries by the majority label of their neighbors, e.g. to
+//! evaluate learned embeddings by retrieval.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::neighbors::*;
+//!     let points = Tensor::<f32>::new(sh!([4, 2]), vec![0., 0., 0., 1., 5., 5., 6., 5.]);
+//!     let queries = Tensor::<f32>::new(sh!([1, 2]), vec![5., 4.]);
+//!     let found = knn_search(&points, &queries, 2, Metric::Euclidean).unwrap();
+//!     assert_eq!(found[0].iter().map(|n| n.index).collect::<Vec<_>>(), [2, 3]);
+//!     let knn = Knn::new(points, vec![0, 0, 1, 1], Metric::Cosine).unwrap();
+//!     assert_eq!(knn.classify(&queries, 3).unwrap(), [1]);
+//! ```
+
+use crate::tensor::*;
+use crate::parallel;
+
+use std::cmp::Ordering;
+
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// The distance between embeddings
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Metric {
+    Euclidean,
+    /// One minus the cosine similarity, from 0 to 2, the zero vector being at 1 from all
+    Cosine,
+}
+
+impl Metric {
+    pub fn distance<T: NumT>(&self, a: &[T], b: &[T]) -> T {
+        match self {
+            Metric::Euclidean => a.iter().zip(b).map(|(x, y)| (*x - *y) * (*x - *y)).sum::<T>().sqrt(),
+            Metric::Cosine => cosine(a, b, norm(a), norm(b)),
+        }
+    }
+}
+
+pub(crate) fn norm<T: NumT>(a: &[T]) -> T {
+    a.iter().map(|x| *x * *x).sum::<T>().sqrt()
+}
+
+/// The cosine distance of the vectors of the given norms
+pub(crate) fn cosine<T: NumT>(a: &[T], b: &[T], norm_a: T, norm_b: T) -> T {
+    if norm_a == T::zero() || norm_b == T::zero() {
+        return T::one();
+    }
+    T::one() - a.iter().zip(b).map(|(x, y)| *x * *y).sum::<T>() / (norm_a * norm_b)
+}
+
+/// A neighbor found: its row and its distance to the query
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Neighbor<T: NumT> {
+    pub index: usize,
+    pub distance: T,
+}
+
+/// Order by the distance then the row, NaN compared as equal
+pub(crate) fn by_distance<T: NumT>(a: &Neighbor<T>, b: &Neighbor<T>) -> Ordering {
+    a.distance.partial_cmp(&b.distance).unwrap_or(Ordering::Equal).then(a.index.cmp(&b.index))
+}
+
+/// Check that the tensor is a matrix of `d` columns, or any if None, returning its (rows, columns)
+fn check_matrix<T: NumT>(op: &'static str, t: &Tensor<T>, d: Option<usize>) -> Result<(usize, usize)> {
+    if t.shape.rank() != 2 {
+        return Err(EasynnError::invalid(op, format!("the embeddings should be [n, d], not of rank {}", t.shape.rank())));
+    }
+    if let Some(d) = d {
+        check_shape(op, &Shape::new([t.shape[0], d]), &t.shape)?;
+    }
+    Ok((t.shape[0], t.shape[1]))
+}
+
+/// The `k` nearest rows of `embeddings` (`[n, d]`) to each row of `queries` (`[q, d]`),
+/// nearest first
+pub fn knn_search<T: NumT>(embeddings: &Tensor<T>, queries: &Tensor<T>, k: usize, metric: Metric) -> Result<Vec<Vec<Neighbor<T>>>> {
+    let (n, d) = check_matrix("knn_search", embeddings, None)?;
+    let (q, _) = check_matrix("knn_search", queries, Some(d))?;
+    if k > n {
+        return Err(EasynnError::invalid("knn_search", format!("{} neighbors requested of {} embeddings", k, n)));
+    }
+    let norms: Vec<T> = match metric {
+        Metric::Euclidean => Vec::new(),
+        Metric::Cosine => (0..n).map(|j| norm(&embeddings.flattened[j * d..(j + 1) * d])).collect(),
+    };
+    let mut found = vec![Vec::new(); q];
+    parallel::chunks_mut(q * n * d, &mut found, 1, |i, slot| {
+        let query = &queries.flattened[i * d..(i + 1) * d];
+        let query_norm = norm(query);
+        let mut all: Vec<Neighbor<T>> = (0..n).map(|j| {
+            let row = &embeddings.flattened[j * d..(j + 1) * d];
+            let distance = match metric {
+                Metric::Euclidean => metric.distance(query, row),
+                Metric::Cosine => cosine(query, row, query_norm, norms[j]),
+            };
+            Neighbor { index: j, distance }
+        }).collect();
+        if k < n && k > 0 {
+            all.select_nth_unstable_by(k - 1, by_distance);
+        }
+        all.truncate(k);
+        all.sort_by(by_distance);
+        slot[0] = all;
+    });
+    Ok(found)
+}
+
+/// A k-NN classifier of labelled embeddings
+#[derive(Debug, Clone)]
+pub struct Knn<T: NumT> {
+    embeddings: Tensor<T>,
+    labels: Vec<usize>,
+    metric: Metric,
+}
+
+impl<T: NumT> Knn<T> {
+    /// The classifier of the `[n, d]` embeddings, labelled row by row
+    pub fn new(embeddings: Tensor<T>, labels: Vec<usize>, metric: Metric) -> Result<Self> {
+        let (n, _) = check_matrix("Knn::new", &embeddings, None)?;
+        check_len("Knn::new", n, labels.len())?;
+        Ok(Knn { embeddings, labels, metric })
+    }
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+    /// The `k` nearest embeddings of each row of the `[q, d]` queries
+    pub fn search(&self, queries: &Tensor<T>, k: usize) -> Result<Vec<Vec<Neighbor<T>>>> {
+        knn_search(&self.embeddings, queries, k, self.metric)
+    }
+    /// The most frequent label among the `k` nearest embeddings of each query,
+    /// ties going to the label of the nearer neighbor
+    pub fn classify(&self, queries: &Tensor<T>, k: usize) -> Result<Vec<usize>> {
+        if k == 0 {
+            return Err(EasynnError::invalid("classify", "no neighbors to vote"));
+        }
+        Ok(self.search(queries, k)?.iter().map(|neighbors| {
+            let labels: Vec<usize> = neighbors.iter().map(|n| self.labels[n.index]).collect();
+            // the first label, in the order of distance, reaching the highest count
+            let count = |l: &usize| labels.iter().filter(|x| *x == l).count();
+            let best = labels.iter().map(count).max().unwrap_or(0);
+            *labels.iter().find(|l| count(l) == best).unwrap()
+        }).collect())
+    }
+    /// The fraction of the queries classified as their labels
+    pub fn accuracy(&self, queries: &Tensor<T>, labels: &[usize], k: usize) -> Result<f64> {
+        let predicted = self.classify(queries, k)?;
+        check_len("accuracy", predicted.len(), labels.len())?;
+        if predicted.is_empty() {
+            return Err(EasynnError::invalid("accuracy", "there are no queries"));
+        }
+        Ok(predicted.iter().zip(labels).filter(|(p, l)| p == l).count() as f64 / predicted.len() as f64)
+    }
+}
+
+#[test]
+fn test_knn() {
+    let points = Tensor::<f64>::new(&Shape::new([5, 2]), vec![
+        1., 0.,
+        0., 1.,
+        2., 0.1,
+        -1., 0.,
+        0., 0.,
+    ]);
+    let queries = Tensor::new(&Shape::new([2, 2]), vec![3., 0., 0., 0.5]);
+    let found = knn_search(&points, &queries, 3, Metric::Euclidean).unwrap();
+    assert_eq!(found[0].iter().map(|n| n.index).collect::<Vec<_>>(), [2, 0, 4]);
+    assert!((found[0][0].distance - 1.01_f64.sqrt()).abs() < 1e-12);
+    // 1 and 4 are tied, the lower row first
+    assert_eq!(found[1].iter().map(|n| n.index).collect::<Vec<_>>(), [1, 4, 0]);
+
+    // by the angle, the zero vector being at 1 from all
+    let found = knn_search(&points, &queries, 5, Metric::Cosine).unwrap();
+    assert_eq!(found[0].iter().map(|n| n.index).collect::<Vec<_>>(), [0, 2, 1, 4, 3]);
+    assert_eq!(found[0][0].distance, 0.);
+    assert_eq!(found[0][4].distance, 2.);
+
+    let knn = Knn::new(points.clone(), vec![0, 1, 0, 2, 1], Metric::Euclidean).unwrap();
+    assert_eq!(knn.classify(&queries, 3).unwrap(), [0, 1]);
+    assert_eq!(knn.accuracy(&queries, &[0, 2], 1).unwrap(), 0.5);
+    // a tie of labels goes to the nearer neighbor
+    let distinct = Knn::new(points.clone(), vec![0, 1, 2, 3, 4], Metric::Euclidean).unwrap();
+    assert_eq!(distinct.classify(&queries, 3).unwrap(), [2, 1]);
+
+    assert!(knn_search(&points, &queries, 6, Metric::Euclidean).is_err());
+    assert!(knn_search(&points, &Tensor::new(&Shape::new([1, 3]), vec![0.; 3]), 1, Metric::Euclidean).is_err());
+    assert!(Knn::new(points, vec![0], Metric::Cosine).is_err());
+}
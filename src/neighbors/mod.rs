@@ -0,0 +1,7 @@
+//! The neighbors module, searching the nearest embeddings exactly or approximately, e.g. for retrieval.
+//!
+
+pub mod knn;
+pub use knn::*;
+pub mod hnsw;
+pub use hnsw::*;
@@ -0,0 +1,535 @@
+//! Optimizers updating the parameters from their gradients, keeping a state per parameter.
+//!
+//! Layers list their parameters with `Layer::parameters_mut` and the matching gradients
+//! with `Layer::gradients`, so any optimizer works with any layer.
+//! `Layer::descend` remains the plain SGD used by `train_once`.
+//!
+//! Exploding gradients are tamed by `clip_grad_norm`, rescaling them to a bound of their
+//! global norm across all the layers, or `clip_grad_value`, clamping each of them.
+//! `Sequential::clipping` applies either before every update when training.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     use easynn::optim::Adam;
+//!     let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+//!     nn.add(Dense::new(sh!([1]), sh!([1]), Activation::No));
+//!     let inputs = vec![Tensor::new(sh!([1]), vec![1.]); 4];
+//!     let truths = vec![Tensor::new(sh!([1]), vec![3.]); 4];
+//!     let mut adam = Adam::new(0.1);
+//!     for _ in 0..200 {
+//!         nn.train_once_optimized(&inputs, &truths, 4, &mut adam, false);
+//!     }
+//!     assert!(nn.evaluate(&inputs, &truths) < 1e-4);
+//! ```
+
+use crate::tensor::*;
+use crate::layers::init::Initializer;
+
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+pub trait Optimizer<T: NumT> {
+    /// The base learning rate
+    fn learning_rate(&self) -> T;
+    /// Update the parameter numbered `slot` by its gradient, the state of each slot is kept apart
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]);
+    /// Called once every parameter was updated in a step
+    fn finish_step(&mut self) {}
+}
+
+/// The state of the slot, zeros of the parameter length when first used
+fn state<T: NumT>(states: &mut Vec<Vec<T>>, slot: usize, len: usize) -> &mut Vec<T> {
+    if states.len() <= slot {
+        states.resize(slot + 1, Vec::new());
+    }
+    if states[slot].len() != len {
+        states[slot] = vec![T::zero(); len];
+    }
+    &mut states[slot]
+}
+
+/// Stochastic gradient descent with momentum, `v = momentum * v + g; p -= rate * v`
+#[derive(Debug, Clone)]
+pub struct Sgd<T: NumT> {
+    pub learning_rate: T,
+    pub momentum: T,
+    velocity: Vec<Vec<T>>,
+}
+
+impl<T: NumT> Sgd<T> {
+    pub fn new(learning_rate: T) -> Self {
+        Sgd::with_momentum(learning_rate, T::zero())
+    }
+    pub fn with_momentum(learning_rate: T, momentum: T) -> Self {
+        Sgd { learning_rate, momentum, velocity: Vec::new() }
+    }
+}
+
+impl<T: NumT> Optimizer<T> for Sgd<T> {
+    fn learning_rate(&self) -> T {
+        self.learning_rate
+    }
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]) {
+        if self.momentum == T::zero() {
+            param.iter_mut().zip(grad.iter()).for_each(|(p, g)| *p -= self.learning_rate * *g);
+            return;
+        }
+        let v = state(&mut self.velocity, slot, param.len());
+        for ((p, g), v) in param.iter_mut().zip(grad.iter()).zip(v.iter_mut()) {
+            *v = self.momentum * *v + *g;
+            *p -= self.learning_rate * *v;
+        }
+    }
+}
+
+/// RMSProp, dividing the rate by a running root mean square of the gradients
+#[derive(Debug, Clone)]
+pub struct RmsProp<T: NumT> {
+    pub learning_rate: T,
+    /// The decay of the running mean, 0.9 by default
+    pub rho: T,
+    pub epsilon: T,
+    square: Vec<Vec<T>>,
+}
+
+impl<T: NumT> RmsProp<T> {
+    pub fn new(learning_rate: T) -> Self {
+        RmsProp { learning_rate, rho: T::from(0.9).unwrap(), epsilon: T::from(1e-8).unwrap(), square: Vec::new() }
+    }
+}
+
+impl<T: NumT> Optimizer<T> for RmsProp<T> {
+    fn learning_rate(&self) -> T {
+        self.learning_rate
+    }
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]) {
+        let s = state(&mut self.square, slot, param.len());
+        for ((p, g), s) in param.iter_mut().zip(grad.iter()).zip(s.iter_mut()) {
+            *s = self.rho * *s + (T::one() - self.rho) * *g * *g;
+            *p -= self.learning_rate * *g / (s.sqrt() + self.epsilon);
+        }
+    }
+}
+
+/// Adam, with bias-corrected running means of the gradients and their squares
+#[derive(Debug, Clone)]
+pub struct Adam<T: NumT> {
+    pub learning_rate: T,
+    /// 0.9 by default
+    pub beta1: T,
+    /// 0.999 by default
+    pub beta2: T,
+    pub epsilon: T,
+    steps: i32,
+    m: Vec<Vec<T>>,
+    v: Vec<Vec<T>>,
+}
+
+impl<T: NumT> Adam<T> {
+    pub fn new(learning_rate: T) -> Self {
+        Adam {
+            learning_rate,
+            beta1: T::from(0.9).unwrap(),
+            beta2: T::from(0.999).unwrap(),
+            epsilon: T::from(1e-8).unwrap(),
+            steps: 0,
+            m: Vec::new(),
+            v: Vec::new(),
+        }
+    }
+}
+
+impl<T: NumT> Optimizer<T> for Adam<T> {
+    fn learning_rate(&self) -> T {
+        self.learning_rate
+    }
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]) {
+        let t = self.steps.saturating_add(1);
+        let c1 = T::one() - self.beta1.powi(t);
+        let c2 = T::one() - self.beta2.powi(t);
+        let (b1, b2) = (self.beta1, self.beta2);
+        state(&mut self.m, slot, param.len());
+        state(&mut self.v, slot, param.len());
+        for (((p, g), m), v) in param.iter_mut().zip(grad.iter()).zip(self.m[slot].iter_mut()).zip(self.v[slot].iter_mut()) {
+            *m = b1 * *m + (T::one() - b1) * *g;
+            *v = b2 * *v + (T::one() - b2) * *g * *g;
+            *p -= self.learning_rate * (*m / c1) / ((*v / c2).sqrt() + self.epsilon);
+        }
+    }
+    fn finish_step(&mut self) {
+        self.steps = self.steps.saturating_add(1);
+    }
+}
+
+/// Lion, stepping by the sign of an interpolation of the momentum and the gradient:
+/// `p -= rate * (sign(beta1 * m + (1 - beta1) * g) + weight_decay * p); m = beta2 * m + (1 - beta2) * g`.
+/// Each parameter moves by the rate, so it takes a rate about 10 times smaller than Adam.
+#[derive(Debug, Clone)]
+pub struct Lion<T: NumT> {
+    pub learning_rate: T,
+    /// 0.9 by default
+    pub beta1: T,
+    /// 0.99 by default
+    pub beta2: T,
+    /// The decoupled weight decay, 0 by default
+    pub weight_decay: T,
+    m: Vec<Vec<T>>,
+}
+
+impl<T: NumT> Lion<T> {
+    pub fn new(learning_rate: T) -> Self {
+        Lion { learning_rate, beta1: T::from(0.9).unwrap(), beta2: T::from(0.99).unwrap(), weight_decay: T::zero(), m: Vec::new() }
+    }
+}
+
+impl<T: NumT> Optimizer<T> for Lion<T> {
+    fn learning_rate(&self) -> T {
+        self.learning_rate
+    }
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]) {
+        let (b1, b2) = (self.beta1, self.beta2);
+        let m = state(&mut self.m, slot, param.len());
+        for ((p, g), m) in param.iter_mut().zip(grad.iter()).zip(m.iter_mut()) {
+            let c = b1 * *m + (T::one() - b1) * *g;
+            let sign = if c == T::zero() { T::zero() } else { c.signum() };
+            *p -= self.learning_rate * (sign + self.weight_decay * *p);
+            *m = b2 * *m + (T::one() - b2) * *g;
+        }
+    }
+}
+
+/// LAMB, the Adam update with a decoupled weight decay scaled for each parameter, e.g.
+/// the weights of a layer, by the trust ratio `|p| / |update|`, so that every layer moves
+/// by about the rate relative to its norm. Parameters or updates of zero norm take a ratio of 1.
+#[derive(Debug, Clone)]
+pub struct Lamb<T: NumT> {
+    pub learning_rate: T,
+    /// 0.9 by default
+    pub beta1: T,
+    /// 0.999 by default
+    pub beta2: T,
+    /// 1e-6 by default
+    pub epsilon: T,
+    /// 0 by default
+    pub weight_decay: T,
+    steps: i32,
+    m: Vec<Vec<T>>,
+    v: Vec<Vec<T>>,
+}
+
+impl<T: NumT> Lamb<T> {
+    pub fn new(learning_rate: T) -> Self {
+        Lamb {
+            learning_rate,
+            beta1: T::from(0.9).unwrap(),
+            beta2: T::from(0.999).unwrap(),
+            epsilon: T::from(1e-6).unwrap(),
+            weight_decay: T::zero(),
+            steps: 0,
+            m: Vec::new(),
+            v: Vec::new(),
+        }
+    }
+}
+
+impl<T: NumT> Optimizer<T> for Lamb<T> {
+    fn learning_rate(&self) -> T {
+        self.learning_rate
+    }
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]) {
+        let t = self.steps.saturating_add(1);
+        let c1 = T::one() - self.beta1.powi(t);
+        let c2 = T::one() - self.beta2.powi(t);
+        let (b1, b2) = (self.beta1, self.beta2);
+        state(&mut self.m, slot, param.len());
+        state(&mut self.v, slot, param.len());
+        let update: Vec<T> = param.iter().zip(grad.iter()).zip(self.m[slot].iter_mut()).zip(self.v[slot].iter_mut()).map(|(((p, g), m), v)| {
+            *m = b1 * *m + (T::one() - b1) * *g;
+            *v = b2 * *v + (T::one() - b2) * *g * *g;
+            (*m / c1) / ((*v / c2).sqrt() + self.epsilon) + self.weight_decay * *p
+        }).collect();
+        let p_norm = param.iter().map(|p| *p * *p).sum::<T>().sqrt();
+        let u_norm = update.iter().map(|u| *u * *u).sum::<T>().sqrt();
+        let ratio = if p_norm > T::zero() && u_norm > T::zero() { p_norm / u_norm } else { T::one() };
+        param.iter_mut().zip(update).for_each(|(p, u)| *p -= self.learning_rate * ratio * u);
+    }
+    fn finish_step(&mut self) {
+        self.steps = self.steps.saturating_add(1);
+    }
+}
+
+/// Gaussian noise added to the gradients before a base optimizer, of the variance
+/// `eta / (1 + t)^gamma` annealed with the step `t`, helping to escape poor regions early
+#[derive(Debug, Clone)]
+pub struct GradientNoise<T: NumT, O: Optimizer<T>> {
+    pub base: O,
+    /// The initial variance
+    pub eta: T,
+    /// The decay of the variance, 0.55 by default
+    pub gamma: T,
+    steps: i32,
+    rng: StdRng,
+}
+
+impl<T: NumT, O: Optimizer<T>> GradientNoise<T, O> {
+    pub fn new(base: O, eta: T) -> Self {
+        Self::with_seed(base, eta, rand::random())
+    }
+    /// Like `new`, drawing the noise from the seed
+    pub fn with_seed(base: O, eta: T, seed: u64) -> Self {
+        GradientNoise { base, eta, gamma: T::from(0.55).unwrap(), steps: 0, rng: StdRng::seed_from_u64(seed) }
+    }
+    /// The standard deviation of the noise of the current step
+    pub fn std(&self) -> T {
+        (self.eta / (T::one() + T::from(self.steps).unwrap()).powf(self.gamma)).sqrt()
+    }
+}
+
+impl<T: NumT, O: Optimizer<T>> Optimizer<T> for GradientNoise<T, O> {
+    fn learning_rate(&self) -> T {
+        self.base.learning_rate()
+    }
+    fn update(&mut self, slot: usize, param: &mut [T], grad: &[T]) {
+        let noise = Initializer::Normal { mean: T::zero(), std: self.std() }.sample(grad.len(), 1, 1, &mut self.rng);
+        let noisy: Vec<T> = grad.iter().zip(noise).map(|(g, n)| *g + n).collect();
+        self.base.update(slot, param, &noisy);
+    }
+    fn finish_step(&mut self) {
+        self.steps = self.steps.saturating_add(1);
+        self.base.finish_step();
+    }
+}
+
+/// Stochastic gradient Langevin dynamics: `p -= rate * g + sqrt(2 * rate * temperature) * n`
+/// of standard normal `n`, so that at a small rate the parameters are samples of the
+/// distribution `exp(-loss / temperature)` rather than converging to a minimum.
+/// The gradients being of the mean loss, a temperature of `1 / samples` samples the
+/// Bayesian posterior of the model; averaging the predictions of the parameters visited
+/// after a burn-in gives the posterior predictive.
+#[derive(Debug, Clone)]
+pub struct Sgld<T: NumT> {
+    pub learning_rate: T,
+    pub temperature: T,
+    rng: StdRng,
+}
+
+impl<T: NumT> Sgld<T> {
+    pub fn new(learning_rate: T, temperature: T) -> Self {
+        Self::with_seed(learning_rate, temperature, rand::random())
+    }
+    /// Like `new`, drawing the noise from the seed
+    pub fn with_seed(learning_rate: T, temperature: T, seed: u64) -> Self {
+        Sgld { learning_rate, temperature, rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl<T: NumT> Optimizer<T> for Sgld<T> {
+    fn learning_rate(&self) -> T {
+        self.learning_rate
+    }
+    fn update(&mut self, _slot: usize, param: &mut [T], grad: &[T]) {
+        let std = (T::from(2).unwrap() * self.learning_rate * self.temperature).sqrt();
+        let noise = Initializer::Normal { mean: T::zero(), std }.sample(param.len(), 1, 1, &mut self.rng);
+        for ((p, g), n) in param.iter_mut().zip(grad.iter()).zip(noise) {
+            *p -= self.learning_rate * *g + n;
+        }
+    }
+}
+
+/// Sharpness-aware minimization over a base optimizer: each step takes the gradient again
+/// at the parameters moved by `rho` along the normalized gradient, towards the worst loss
+/// nearby, then the base optimizer updates the unmoved parameters by that gradient.
+/// The two gradients of a step are taken by `Sequential::train_once_sam`.
+#[derive(Debug, Clone)]
+pub struct Sam<T: NumT, O: Optimizer<T>> {
+    pub base: O,
+    /// The radius of the neighbourhood, 0.05 by default
+    pub rho: T,
+}
+
+impl<T: NumT, O: Optimizer<T>> Sam<T, O> {
+    pub fn new(base: O) -> Self {
+        Sam { base, rho: T::from(0.05).unwrap() }
+    }
+    /// The move of the parameters of the gradients, `rho * g / |g|`
+    pub fn perturbation(&self, grads: &[Vec<T>]) -> Vec<Vec<T>> {
+        let norm = grad_norm(grads);
+        let scale = if norm > T::zero() { self.rho / norm } else { T::zero() };
+        grads.iter().map(|g| g.iter().map(|x| *x * scale).collect()).collect()
+    }
+}
+
+/// The global L2 norm of the gradients of every parameter
+pub fn grad_norm<T: NumT>(grads: &[Vec<T>]) -> T {
+    grads.iter().flatten().map(|g| *g * *g).sum::<T>().sqrt()
+}
+
+/// Rescale the gradients so that their global norm is at most `max_norm`,
+/// returning their norm before
+pub fn clip_grad_norm<T: NumT>(grads: &mut [Vec<T>], max_norm: T) -> T {
+    let norm = grad_norm(grads);
+    if norm > max_norm {
+        let scale = max_norm / norm;
+        grads.iter_mut().flatten().for_each(|g| *g *= scale);
+    }
+    norm
+}
+
+/// Clamp each gradient into `[-max_value, max_value]`
+pub fn clip_grad_value<T: NumT>(grads: &mut [Vec<T>], max_value: T) {
+    grads.iter_mut().flatten().for_each(|g| *g = g.max(-max_value).min(max_value));
+}
+
+/// How the gradients of each training step are clipped before the update, none by default
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GradientClipping<T: NumT> {
+    /// The bound of the global norm, see `clip_grad_norm`
+    pub max_norm: Option<T>,
+    /// The bound of each gradient, see `clip_grad_value`, applied before the norm
+    pub max_value: Option<T>,
+}
+
+impl<T: NumT> Default for GradientClipping<T> {
+    fn default() -> Self {
+        GradientClipping::none()
+    }
+}
+
+impl<T: NumT> GradientClipping<T> {
+    pub fn none() -> Self {
+        GradientClipping { max_norm: None, max_value: None }
+    }
+    pub fn by_norm(max_norm: T) -> Self {
+        GradientClipping { max_norm: Some(max_norm), max_value: None }
+    }
+    pub fn by_value(max_value: T) -> Self {
+        GradientClipping { max_norm: None, max_value: Some(max_value) }
+    }
+    pub fn is_none(&self) -> bool {
+        self.max_norm.is_none() && self.max_value.is_none()
+    }
+    /// Clip the gradients of every parameter
+    pub fn clip(&self, grads: &mut [Vec<T>]) {
+        if let Some(v) = self.max_value {
+            clip_grad_value(grads, v);
+        }
+        if let Some(n) = self.max_norm {
+            clip_grad_norm(grads, n);
+        }
+    }
+}
+
+#[test]
+fn test_optimizers() {
+    // minimize (p - 3)^2 from 0
+    let mut optimizers: Vec<Box<dyn Optimizer<f64>>> = vec![
+        Box::new(Sgd::new(0.1)),
+        Box::new(Sgd::with_momentum(0.05, 0.9)),
+        Box::new(RmsProp::new(0.05)),
+        Box::new(Adam::new(0.1)),
+        Box::new(Lion::new(0.003)),
+    ];
+    for opt in optimizers.iter_mut() {
+        let mut p = [0.];
+        for _ in 0..2000 {
+            let g = [2. * (p[0] - 3.)];
+            opt.update(0, &mut p, &g);
+            opt.finish_step();
+        }
+        assert!((p[0] - 3.).abs() < 1e-2);
+    }
+
+    // the first Adam step moves each parameter by about the rate, whatever the gradient scale
+    let mut adam = Adam::new(0.1);
+    let mut p = [0_f64, 0.];
+    adam.update(0, &mut p, &[1000., -0.001]);
+    assert!((p[0] + 0.1).abs() < 1e-6 && (p[1] - 0.1).abs() < 1e-4);
+
+    // a Lion step moves each parameter by the rate, a LAMB step the parameter by the rate of its norm
+    let mut lion = Lion::new(0.1);
+    let mut p = [0_f64, 1., 2.];
+    lion.update(0, &mut p, &[1000., -0.001, 0.]);
+    assert_eq!(p, [-0.1, 1.1, 2.]);
+    let mut lamb = Lamb::new(0.1);
+    let mut p = [3_f64, 4.];
+    lamb.update(0, &mut p, &[1., 1.]);
+    let moved = ((p[0] - 3.).powi(2) + (p[1] - 4.).powi(2)).sqrt();
+    assert!((moved - 0.5).abs() < 1e-6);
+    // its steps are relative to the parameter, from 1 to 3
+    let mut lamb = Lamb::new(0.001);
+    let mut p = [1_f64];
+    for _ in 0..2000 {
+        let g = [2. * (p[0] - 3.)];
+        lamb.update(0, &mut p, &g);
+        lamb.finish_step();
+    }
+    assert!((p[0] - 3.).abs() < 1e-2);
+
+    // annealed gradient noise still converges, and without noise is the base optimizer
+    let mut noisy = GradientNoise::with_seed(Sgd::new(0.1), 0.1, 1);
+    let mut p = [0_f64];
+    for _ in 0..2000 {
+        let g = [2. * (p[0] - 3.)];
+        noisy.update(0, &mut p, &g);
+        noisy.finish_step();
+    }
+    assert!((p[0] - 3.).abs() < 5e-2);
+    assert!((noisy.std() - (0.1 / 2001_f64.powf(0.55)).sqrt()).abs() < 1e-12);
+    let mut quiet = GradientNoise::with_seed(Sgd::new(0.1), 0., 1);
+    let mut p = [1_f64];
+    quiet.update(0, &mut p, &[2.]);
+    assert_eq!(p, [0.8]);
+
+    // SGLD samples exp(-(p - 3)^2 / T), the normal of mean 3 and variance T / 2
+    let mut sgld = Sgld::with_seed(0.01, 0.5, 7);
+    let mut p = [0_f64];
+    let mut samples = Vec::new();
+    for i in 0..40000 {
+        let g = [2. * (p[0] - 3.)];
+        sgld.update(0, &mut p, &g);
+        if i >= 1000 {
+            samples.push(p[0]);
+        }
+    }
+    let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+    let var = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f64>() / samples.len() as f64;
+    assert!((mean - 3.).abs() < 0.1 && (var - 0.25).abs() < 0.05);
+
+    let sam = Sam::new(Sgd::<f64>::new(0.1));
+    let e = sam.perturbation(&[vec![3., 0.], vec![4.]]);
+    assert!((e[0][0] - 0.03).abs() < 1e-12 && e[0][1] == 0. && (e[1][0] - 0.04).abs() < 1e-12);
+    assert_eq!(sam.perturbation(&[vec![0.; 2]]), [vec![0.; 2]]);
+}
+
+#[test]
+fn test_gradient_clipping() {
+    use crate::prelude::*;
+    let mut grads = vec![vec![3., -40.], vec![0.]];
+    clip_grad_value(&mut grads, 10.);
+    assert_eq!(grads, [vec![3., -10.], vec![0.]]);
+    assert!((clip_grad_norm(&mut grads, 5.) - 109_f64.sqrt()).abs() < 1e-12);
+    assert!((grad_norm(&grads) - 5.).abs() < 1e-12);
+    assert_eq!(clip_grad_norm(&mut grads, 10.), grad_norm(&grads));
+
+    // an exploding gradient moves the parameters by at most the bound times the rate
+    let s = Shape::new([1]);
+    let (x, y) = (vec![Tensor::new(&s, vec![100.])], vec![Tensor::new(&s, vec![1e4])]);
+    let moved = |clipping: GradientClipping<f64>, optimized: bool| {
+        let mut nn = Sequential::<f64>::new(Loss::MeanSquare);
+        nn.add(Dense::new(&s, &s, Activation::No));
+        nn.clipping = clipping;
+        let before = nn.parameters().concat();
+        match optimized {
+            true => { nn.train_once_optimized(&x, &y, 1, &mut Sgd::new(0.5), false); }
+            false => { nn.train_once(&x, &y, 1, 0.5, false); }
+        }
+        nn.parameters().concat().iter().zip(before).map(|(a, b)| a - b).collect::<Vec<_>>()
+    };
+    for optimized in [false, true] {
+        let step = moved(GradientClipping::by_norm(1.), optimized);
+        assert!((grad_norm(&[step]) - 0.5).abs() < 1e-9);
+        let step = moved(GradientClipping::by_value(0.2), optimized);
+        assert!(step.iter().all(|d| (d - 0.1).abs() < 1e-9));
+    }
+    assert!(grad_norm(&[moved(GradientClipping::none(), false)]) > 1e3);
+}
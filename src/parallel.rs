@@ -0,0 +1,211 @@
+//! The parallelism of the kernels of tensors, layers and linear algebra, set crate-wide
+//! by a `ParallelConfig`: the thread count, the size of an operation below which it runs
+//! serially on the calling thread, and optionally the rayon pool to run in.
+//!
+//! By default the kernels use every cpu through the global rayon pool, and operations
+//! below 4096 elements run serially. `ParallelConfig::serial()` keeps every kernel on
+//! the calling thread, e.g. in embedded contexts or inside the workers of a server.
+//! A kernel called from a rayon pool, e.g. of `ParallelTraining`, stays in that pool.
+//!
+//! ```rust
+//!     use easynn::parallel::*;
+//!     set_parallel_config(ParallelConfig::serial()).unwrap();
+//!     assert_eq!(current_threads(), 1);
+//!     // two threads of a pool of its own, operations of less than 1024 elements serial
+//!     set_parallel_config(ParallelConfig::with_threads(2).serial_below(1024)).unwrap();
+//!     assert_eq!(current_threads(), 2);
+//!     set_parallel_config(ParallelConfig::default()).unwrap();
+//! ```
+
+extern crate num_cpus;
+extern crate rayon;
+
+use crate::tensor::EasynnError;
+
+use rayon::prelude::*;
+use rayon::ThreadPool;
+
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, RwLock };
+
+/// The default size of an operation below which it runs serially
+pub const DEFAULT_SERIAL_BELOW: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct ParallelConfig {
+    /// The threads of the kernels, 0 for every cpu
+    pub threads: usize,
+    /// The element count, or the multiply count of products, below which an operation
+    /// runs serially on the calling thread
+    pub serial_below: usize,
+    /// The rayon pool to run the kernels in, instead of the global one
+    pub pool: Option<Arc<ThreadPool>>,
+}
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        ParallelConfig { threads: 0, serial_below: DEFAULT_SERIAL_BELOW, pool: None }
+    }
+}
+
+impl ParallelConfig {
+    /// Every kernel on the calling thread
+    pub fn serial() -> Self {
+        Self::with_threads(1)
+    }
+    /// The kernels in a pool of `threads` threads, built by `set_parallel_config`
+    pub fn with_threads(threads: usize) -> Self {
+        ParallelConfig { threads, ..Self::default() }
+    }
+    /// The kernels in the given pool, e.g. one shared with the rest of the application
+    pub fn with_pool(pool: Arc<ThreadPool>) -> Self {
+        ParallelConfig { threads: pool.current_num_threads(), pool: Some(pool), ..Self::default() }
+    }
+    /// Set the size below which operations run serially
+    pub fn serial_below(mut self, size: usize) -> Self {
+        self.serial_below = size;
+        self
+    }
+}
+
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+static SERIAL_BELOW: AtomicUsize = AtomicUsize::new(DEFAULT_SERIAL_BELOW);
+static POOL: RwLock<Option<Arc<ThreadPool>>> = RwLock::new(None);
+
+/// Held by the tests that change the crate-wide configurations or depend on them
+#[cfg(test)]
+pub(crate) static TEST_GLOBALS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Apply the configuration to every kernel called from now on. A thread count other than
+/// 0 or 1 without a pool builds a pool of that many threads, which may fail.
+pub fn set_parallel_config(config: ParallelConfig) -> Result<(), EasynnError> {
+    let pool = match config.pool {
+        Some(pool) => Some(pool),
+        None if config.threads > 1 => Some(Arc::new(rayon::ThreadPoolBuilder::new().num_threads(config.threads).build()
+            .map_err(|e| EasynnError::invalid("set_parallel_config", e.to_string()))?)),
+        None => None,
+    };
+    *POOL.write().unwrap() = pool;
+    THREADS.store(config.threads, Ordering::Relaxed);
+    SERIAL_BELOW.store(config.serial_below, Ordering::Relaxed);
+    Ok(())
+}
+
+/// The configuration in effect
+pub fn parallel_config() -> ParallelConfig {
+    ParallelConfig {
+        threads: THREADS.load(Ordering::Relaxed),
+        serial_below: SERIAL_BELOW.load(Ordering::Relaxed),
+        pool: POOL.read().unwrap().clone(),
+    }
+}
+
+/// The threads a kernel called here may use: 1 in the serial mode, else the size of the
+/// rayon pool it runs in, so that models trained side by side in bounded pools do not
+/// oversubscribe the cpus, else the configured count
+pub fn current_threads() -> usize {
+    match THREADS.load(Ordering::Relaxed) {
+        1 => 1,
+        _ if rayon::current_thread_index().is_some() => rayon::current_num_threads(),
+        0 => num_cpus::get(),
+        threads => threads,
+    }
+}
+
+/// Whether an operation of the size runs serially
+pub(crate) fn is_serial(size: usize) -> bool {
+    size < SERIAL_BELOW.load(Ordering::Relaxed) || current_threads() == 1
+}
+
+/// The smallest piece an operation is split into
+fn min_len() -> usize {
+    SERIAL_BELOW.load(Ordering::Relaxed).max(1)
+}
+
+/// Run the parallel kernel in the configured pool, unless already in a rayon pool
+fn install<R: Send, F: FnOnce() -> R + Send>(f: F) -> R {
+    if rayon::current_thread_index().is_none() {
+        if let Some(pool) = POOL.read().unwrap().clone() {
+            return pool.install(f);
+        }
+    }
+    f()
+}
+
+/// `f(i, a[i])` for every element
+pub(crate) fn for_each_mut<A: Send, F: Fn(usize, &mut A) + Sync + Send>(a: &mut [A], f: F) {
+    if is_serial(a.len()) {
+        a.iter_mut().enumerate().for_each(|(i, x)| f(i, x));
+    } else {
+        install(|| a.par_iter_mut().with_min_len(min_len()).enumerate().for_each(|(i, x)| f(i, x)));
+    }
+}
+
+/// `f(a[i], b[i])` for every pair of elements
+pub(crate) fn zip_mut<A: Send, B: Sync, F: Fn(&mut A, &B) + Sync + Send>(a: &mut [A], b: &[B], f: F) {
+    if is_serial(a.len()) {
+        a.iter_mut().zip(b).for_each(|(x, y)| f(x, y));
+    } else {
+        install(|| a.par_iter_mut().zip(b.par_iter()).with_min_len(min_len()).for_each(|(x, y)| f(x, y)));
+    }
+}
+
+/// The `f(a[i])` of every element
+pub(crate) fn map<A: Sync, R: Send, F: Fn(&A) -> R + Sync + Send>(a: &[A], f: F) -> Vec<R> {
+    if is_serial(a.len()) {
+        a.iter().map(&f).collect()
+    } else {
+        install(|| a.par_iter().with_min_len(min_len()).map(&f).collect())
+    }
+}
+
+/// The `f(a[i], b[i])` of every pair of elements
+pub(crate) fn zip_map<A: Sync, B: Sync, R: Send, F: Fn(&A, &B) -> R + Sync + Send>(a: &[A], b: &[B], f: F) -> Vec<R> {
+    if is_serial(a.len()) {
+        a.iter().zip(b).map(|(x, y)| f(x, y)).collect()
+    } else {
+        install(|| a.par_iter().zip(b.par_iter()).with_min_len(min_len()).map(|(x, y)| f(x, y)).collect())
+    }
+}
+
+/// `f(i, chunk)` for the chunks of `chunk` elements, a serial loop if the `work` of the
+/// whole operation is small or there is a single chunk
+pub(crate) fn chunks_mut<A: Send, F: Fn(usize, &mut [A]) + Sync + Send>(work: usize, a: &mut [A], chunk: usize, f: F) {
+    if is_serial(work) || a.len() <= chunk {
+        a.chunks_mut(chunk).enumerate().for_each(|(i, c)| f(i, c));
+    } else {
+        install(|| a.par_chunks_mut(chunk).enumerate().for_each(|(i, c)| f(i, c)));
+    }
+}
+
+#[test]
+fn test_parallel_config() {
+    let _globals = TEST_GLOBALS.lock().unwrap_or_else(|e| e.into_inner());
+    let saved = parallel_config();
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new().num_threads(3).build().unwrap());
+    let in_pool = |i: usize, x: &mut usize| *x = i + rayon::current_thread_index().map_or(0, |_| 1000);
+    for (config, threads, pooled) in [
+        (ParallelConfig::serial(), 1, false),
+        (ParallelConfig::with_pool(pool.clone()).serial_below(8), 3, true),
+        (ParallelConfig::default().serial_below(8), num_cpus::get(), num_cpus::get() > 1),
+    ] {
+        set_parallel_config(config).unwrap();
+        assert_eq!(current_threads(), threads);
+        // the small operations stay on the calling thread
+        let mut small = vec![0; 4];
+        for_each_mut(&mut small, in_pool);
+        assert_eq!(small, [0, 1, 2, 3]);
+        let mut large = vec![0; 64];
+        for_each_mut(&mut large, in_pool);
+        assert_eq!(large[5], if pooled { 1005 } else { 5 });
+        assert_eq!(zip_map(&large, &small, |x, y| x + y).len(), 4);
+        assert_eq!(map(&large, |x| x % 1000), (0..64).collect::<Vec<_>>());
+        chunks_mut(1 << 20, &mut large, 16, |i, c| c.iter_mut().for_each(|x| *x = i));
+        assert_eq!(large[63], 3);
+    }
+    // a kernel of a pool stays in it
+    set_parallel_config(ParallelConfig::with_threads(2)).unwrap();
+    assert_eq!(pool.install(current_threads), 3);
+    assert!(parallel_config().pool.is_some());
+    set_parallel_config(saved).unwrap();
+}
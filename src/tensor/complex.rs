@@ -0,0 +1,68 @@
+//! Complex tensors, e.g. the spectra of signals, converted from and into
+//! real tensors to feed the networks.
+//!
+//! ```rust
+//!     use easynn::tensor::{ Tensor, Shape, Complex };
+//!     let z = Tensor::new(&Shape::new([2]), vec![Complex::new(3_f64, 4.), Complex::new(0., 1.)]);
+//!     let w = &z * &z.conj();
+//!     assert_eq!(w.re().as_slice(), &[25., 1.]);
+//!     assert_eq!(z.norm().as_slice(), &[5., 1.]);
+//! ```
+
+pub use num_complex::Complex;
+
+use crate::tensor::*;
+
+impl<T: NumT> Tensor<Complex<T>> {
+    /// The complex tensor of zero imaginary parts
+    pub fn from_real(re: &Tensor<T>) -> Self {
+        Tensor::new(&re.shape, re.flattened.iter().map(|r| Complex::new(*r, T::zero())).collect())
+    }
+    /// The complex tensor of the real and the imaginary parts, of the same shape
+    pub fn from_parts(re: &Tensor<T>, im: &Tensor<T>) -> std::result::Result<Self, EasynnError> {
+        check_shape("from_parts", &re.shape, &im.shape)?;
+        Ok(Tensor::new(&re.shape, re.flattened.iter().zip(im.flattened.iter()).map(|(r, i)| Complex::new(*r, *i)).collect()))
+    }
+    /// The real parts
+    pub fn re(&self) -> Tensor<T> {
+        Tensor::new(&self.shape, self.flattened.iter().map(|z| z.re).collect())
+    }
+    /// The imaginary parts
+    pub fn im(&self) -> Tensor<T> {
+        Tensor::new(&self.shape, self.flattened.iter().map(|z| z.im).collect())
+    }
+    /// The magnitudes
+    pub fn norm(&self) -> Tensor<T> {
+        Tensor::new(&self.shape, self.flattened.iter().map(|z| z.norm()).collect())
+    }
+    /// The phases, in `(-pi, pi]`
+    pub fn arg(&self) -> Tensor<T> {
+        Tensor::new(&self.shape, self.flattened.iter().map(|z| z.arg()).collect())
+    }
+    /// The complex conjugates
+    pub fn conj(&self) -> Self {
+        self.map(|z| z.conj())
+    }
+}
+
+#[test]
+fn test_complex_tensor() {
+    let re = Tensor::<f32>::new(&Shape::new([2, 2]), vec![1., 0., -1., 2.]);
+    let im = Tensor::<f32>::new(&Shape::new([2, 2]), vec![0., 1., 0., -2.]);
+    let z = Tensor::from_parts(&re, &im).unwrap();
+    assert_eq!(z.re(), re);
+    assert_eq!(z.im(), im);
+    assert_eq!(z.conj().im().as_slice(), &[0., -1., 0., 2.]);
+    assert_eq!(Tensor::from_real(&re).im(), Tensor::zeros(&Shape::new([2, 2])));
+    assert!(Tensor::from_parts(&re, &Tensor::zeros(&Shape::new([4]))).is_err());
+
+    // (i) * (i) = -1, and the matrix product
+    let i = Tensor::new(&Shape::new([1, 1]), vec![Complex::new(0_f32, 1.)]);
+    assert_eq!((&i * &i).re().as_slice(), &[-1.]);
+    let prod = z.matmul(&z).unwrap();
+    // [[1, i], [-1, 2-2i]]^2 = [[1 - i, i + i(2-2i)], [-1 - 2 + 2i, -i + (2-2i)^2]]
+    assert_eq!(prod.as_slice(), &[
+        Complex::new(1., -1.), Complex::new(2., 3.),
+        Complex::new(-3., 2.), Complex::new(0., -9.),
+    ]);
+}
@@ -1,7 +1,9 @@
-/// Errors that may occur while manipulating tensors
+//! Errors that may occur while manipulating tensors
 
 use std::fmt;
 
+use crate::tensor::shape::Shape;
+
 #[derive(Debug, Clone)]
 pub struct OutOfBondError;
 
@@ -11,11 +13,92 @@ impl fmt::Display for OutOfBondError {
     }
 }
 
-#[derive(Debug, Clone)]
-pub struct ShapeMismatchError;
+impl std::error::Error for OutOfBondError { }
+
+/// Errors of the shapes, the arguments and the resources of tensors, layers and models
+#[derive(Debug, Clone, PartialEq)]
+pub enum EasynnError {
+    /// A tensor given to the operation `op` is not of the `expected` shape,
+    /// lengths of buffers being compared as shapes of rank 1
+    ShapeMismatch { op: &'static str, expected: Shape, actual: Shape },
+    /// A tensor or an argument given to the operation `op` is invalid for the reason of `message`
+    InvalidArgument { op: &'static str, message: String },
+    /// The error `source` of the layer at `index` of a model, named by the kind of its record
+    Layer { index: usize, name: String, source: Box<EasynnError> },
+    /// The element count of the shape does not fit in `usize`
+    SizeOverflow(Vec<usize>),
+    /// The allocation of `requested` bytes exceeds the cap of `limit` bytes,
+    /// see `tensor::set_allocation_limit`
+    ResourceLimit { requested: usize, limit: usize },
+    /// The JSON of a tensor is invalid at the byte `position`
+    InvalidJson { position: usize, message: String },
+}
 
-impl fmt::Display for ShapeMismatchError {
+impl EasynnError {
+    pub(crate) fn mismatch(op: &'static str, expected: &Shape, actual: &Shape) -> Self {
+        EasynnError::ShapeMismatch { op, expected: expected.clone(), actual: actual.clone() }
+    }
+    pub(crate) fn invalid<S: Into<String>>(op: &'static str, message: S) -> Self {
+        EasynnError::InvalidArgument { op, message: message.into() }
+    }
+    /// The error itself, below the layers of the models it went through
+    pub fn root(&self) -> &EasynnError {
+        match self {
+            EasynnError::Layer { source, .. } => source.root(),
+            e => e,
+        }
+    }
+}
+
+/// Check that a tensor given to the operation `op` is of the expected shape
+pub(crate) fn check_shape(op: &'static str, expected: &Shape, actual: &Shape) -> Result<(), EasynnError> {
+    if expected != actual {
+        return Err(EasynnError::mismatch(op, expected, actual));
+    }
+    Ok(())
+}
+
+/// Check that a buffer given to the operation `op` is of the expected length
+pub(crate) fn check_len(op: &'static str, expected: usize, actual: usize) -> Result<(), EasynnError> {
+    if expected != actual {
+        return Err(EasynnError::mismatch(op, &Shape::new([expected]), &Shape::new([actual])));
+    }
+    Ok(())
+}
+
+impl fmt::Display for EasynnError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Tensor shape mismatch!")
+        match self {
+            EasynnError::ShapeMismatch { op, expected, actual } =>
+                write!(f, "Tensor shape mismatch in {}: expected {:?} but got {:?}!", op, expected.dims(), actual.dims()),
+            EasynnError::InvalidArgument { op, message } => write!(f, "Invalid argument of {}: {}!", op, message),
+            EasynnError::Layer { index, name, source } => write!(f, "Layer {} ({}): {}", index, name, source),
+            EasynnError::SizeOverflow(dims) => write!(f, "The size of shape {:?} overflows!", dims),
+            EasynnError::ResourceLimit { requested, limit } =>
+                write!(f, "Allocating {} bytes exceeds the limit of {} bytes!", requested, limit),
+            EasynnError::InvalidJson { position, message } =>
+                write!(f, "Invalid tensor JSON at byte {}: {}!", position, message),
+        }
+    }
+}
+
+impl std::error::Error for EasynnError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EasynnError::Layer { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_error() {
+    let e = check_shape("forward_propagate", &Shape::new([2, 3]), &Shape::new([3, 2])).unwrap_err();
+    assert_eq!(e.to_string(), "Tensor shape mismatch in forward_propagate: expected [2, 3] but got [3, 2]!");
+    let e = EasynnError::Layer { index: 1, name: "dense".to_string(), source: Box::new(e) };
+    assert_eq!(e.to_string(), "Layer 1 (dense): Tensor shape mismatch in forward_propagate: expected [2, 3] but got [3, 2]!");
+    assert!(matches!(e.root(), EasynnError::ShapeMismatch { op: "forward_propagate", .. }));
+    assert!(std::error::Error::source(&e).is_some());
+    assert_eq!(check_len("descend", 4, 5), Err(EasynnError::mismatch("descend", &Shape::new([4]), &Shape::new([5]))));
+    assert!(check_len("descend", 4, 4).is_ok());
+}
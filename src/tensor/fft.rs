@@ -0,0 +1,185 @@
+//! The fast Fourier transform of complex tensors, radix-2 for power of 2 lengths
+//! and the naive DFT otherwise, computed in f64.
+//!
+//! `fft` transforms along the last axis, each row separately, and `fft2` along the last two.
+//! The inverses are normalized by `1 / n`.
+//!
+//! ```rust
+//!     use easynn::tensor::{ Tensor, Shape, Complex };
+//!     let x = Tensor::<Complex<f64>>::from_real(&Tensor::new(&Shape::new([4]), vec![1., 2., 3., 4.]));
+//!     let spectrum = x.fft().unwrap();
+//!     assert_eq!(spectrum.get([0]), Complex::new(10., 0.));
+//!     let back = spectrum.ifft().unwrap().re();
+//!     assert!(back.as_slice().iter().zip([1., 2., 3., 4.]).all(|(a, b)| (a - b).abs() < 1e-12));
+//! ```
+
+use crate::tensor::*;
+
+use std::f64::consts::PI;
+
+type Result<T> = std::result::Result<T, EasynnError>;
+
+/// In-place DFT of (re, im), radix-2 if the length is a power of 2, otherwise naive
+pub(crate) fn dft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    if !n.is_power_of_two() {
+        let (src_re, src_im) = (re.to_vec(), im.to_vec());
+        for k in 0..n {
+            let (mut sr, mut si) = (0., 0.);
+            for j in 0..n {
+                let ang = -2. * PI * (k * j % n) as f64 / n as f64;
+                sr += src_re[j] * ang.cos() - src_im[j] * ang.sin();
+                si += src_re[j] * ang.sin() + src_im[j] * ang.cos();
+            }
+            re[k] = sr;
+            im[k] = si;
+        }
+        return;
+    }
+    // bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let ang = -2. * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = ((ang * k as f64).cos(), (ang * k as f64).sin());
+                let (a, b) = (start + k, start + k + len / 2);
+                let (xr, xi) = (re[b] * wr - im[b] * wi, re[b] * wi + im[b] * wr);
+                re[b] = re[a] - xr;
+                im[b] = im[a] - xi;
+                re[a] += xr;
+                im[a] += xi;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Transform every line along the axis of length `n` whose elements are `stride` apart
+fn transform_axis(x: &mut [Complex<f64>], n: usize, stride: usize, inverse: bool) {
+    let (mut re, mut im) = (vec![0.; n], vec![0.; n]);
+    // the inverse is the conjugate of the transform of the conjugate, over n
+    let sign = if inverse { -1. } else { 1. };
+    let scale = if inverse { 1. / n as f64 } else { 1. };
+    for block in x.chunks_mut(n * stride) {
+        for offset in 0..stride {
+            for i in 0..n {
+                re[i] = block[i * stride + offset].re;
+                im[i] = sign * block[i * stride + offset].im;
+            }
+            dft(&mut re, &mut im);
+            for i in 0..n {
+                block[i * stride + offset] = Complex::new(re[i] * scale, sign * im[i] * scale);
+            }
+        }
+    }
+}
+
+impl<T: NumT> Tensor<Complex<T>> {
+    /// Transform along the trailing `axes` axes
+    fn transform(&self, axes: usize, inverse: bool) -> Result<Self> {
+        let rank = self.shape.rank();
+        if rank < axes || self.flattened.is_empty() {
+            return Err(EasynnError::invalid("fft", format!("the shape {:?} has no {} axes to transform", self.shape.dims(), axes)));
+        }
+        let mut x: Vec<Complex<f64>> = self.flattened.iter()
+            .map(|z| Complex::new(z.re.to_f64().unwrap(), z.im.to_f64().unwrap()))
+            .collect();
+        let mut stride = 1;
+        for axis in (rank - axes..rank).rev() {
+            transform_axis(&mut x, self.shape[axis], stride, inverse);
+            stride *= self.shape[axis];
+        }
+        Ok(Tensor::new(&self.shape, x.into_iter().map(|z| Complex::new(T::from(z.re).unwrap(), T::from(z.im).unwrap())).collect()))
+    }
+    /// The FFT along the last axis
+    pub fn fft(&self) -> Result<Self> {
+        self.transform(1, false)
+    }
+    /// The inverse FFT along the last axis
+    pub fn ifft(&self) -> Result<Self> {
+        self.transform(1, true)
+    }
+    /// The 2D FFT over the last two axes
+    pub fn fft2(&self) -> Result<Self> {
+        self.transform(2, false)
+    }
+    /// The inverse 2D FFT over the last two axes
+    pub fn ifft2(&self) -> Result<Self> {
+        self.transform(2, true)
+    }
+}
+
+/// The full linear convolution of two rank-1 tensors through the FFT,
+/// of length `signal + kernel - 1`, faster than the direct sum for long kernels
+pub fn fft_convolve<T: NumT>(signal: &Tensor<T>, kernel: &Tensor<T>) -> Result<Tensor<T>> {
+    if signal.shape.rank() != 1 || kernel.shape.rank() != 1 || signal.flattened.is_empty() || kernel.flattened.is_empty() {
+        return Err(EasynnError::invalid("fft_convolve", "the signal and the kernel should be non-empty rank-1 tensors"));
+    }
+    let len = signal.flattened.len() + kernel.flattened.len() - 1;
+    let n = len.next_power_of_two();
+    let padded = |t: &Tensor<T>| {
+        let mut v = vec![Complex::new(T::zero(), T::zero()); n];
+        v.iter_mut().zip(t.flattened.iter()).for_each(|(z, x)| z.re = *x);
+        Tensor::new(&Shape::new([n]), v)
+    };
+    let product = &padded(signal).fft()? * &padded(kernel).fft()?;
+    let mut out = product.ifft()?.re();
+    out.flattened.truncate(len);
+    out.shape = Shape::new([len]);
+    Ok(out)
+}
+
+#[test]
+fn test_dft() {
+    for n in [8_usize, 6] {
+        let x: Vec<f64> = (0..n).map(|i| (i as f64 * 0.7).sin() + 0.1 * i as f64).collect();
+        let (mut re, mut im) = (x.clone(), vec![0.; n]);
+        dft(&mut re, &mut im);
+        for k in 0..n {
+            let ang = |j: usize| -2. * PI * (k * j) as f64 / n as f64;
+            let er: f64 = x.iter().enumerate().map(|(j, v)| v * ang(j).cos()).sum();
+            let ei: f64 = x.iter().enumerate().map(|(j, v)| v * ang(j).sin()).sum();
+            assert!((re[k] - er).abs() < 1e-9 && (im[k] - ei).abs() < 1e-9);
+        }
+    }
+}
+
+#[test]
+fn test_fft() {
+    let shape = Shape::new([2, 3, 4]);
+    let x = Tensor::<Complex<f64>>::new(&shape, (0..24).map(|i| Complex::new((i as f64 * 0.7).sin(), (i as f64).cos())).collect());
+    let close = |a: &Tensor<Complex<f64>>, b: &Tensor<Complex<f64>>| {
+        a.as_slice().iter().zip(b.as_slice().iter()).all(|(p, q)| (p - q).norm() < 1e-9)
+    };
+    assert!(close(&x.fft().unwrap().ifft().unwrap(), &x));
+    assert!(close(&x.fft2().unwrap().ifft2().unwrap(), &x));
+
+    // the 2D transform of an impulse is constant
+    let mut impulse = Tensor::<Complex<f64>>::zeros(&Shape::new([3, 4]));
+    impulse.set([1, 2], Complex::new(1., 0.));
+    let spectrum = impulse.fft2().unwrap().norm();
+    assert!(spectrum.as_slice().iter().all(|m| (m - 1.).abs() < 1e-12));
+    assert!(Tensor::<Complex<f64>>::zeros(&Shape::new([4])).fft2().is_err());
+
+    let signal = Tensor::<f32>::new(&Shape::new([4]), vec![1., 2., 3., 4.]);
+    let kernel = Tensor::<f32>::new(&Shape::new([3]), vec![1., 0., -1.]);
+    let conv = fft_convolve(&signal, &kernel).unwrap();
+    assert_eq!(conv.get_shape(), &Shape::new([6]));
+    for (c, e) in conv.as_slice().iter().zip([1., 2., 2., 2., -3., -4.]) {
+        assert!((c - e).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,187 @@
+//! Reading and writing tensors as JSON of the schema `{"shape": [2, 3], "data": [1, 2, 3, 4, 5, 6]}`,
+//! the data being flattened in row-major order.
+//!
+//! Non-finite values are written as `null`, which reads back as NaN.
+//!
+//! ```rust
+//!     use easynn::tensor::{ Tensor, Shape };
+//!     let t = Tensor::<f32>::new(&Shape::new([2, 2]), vec![1., 0.5, -2., 3.25]);
+//!     let json = t.to_json();
+//!     assert_eq!(json, r#"{"shape":[2,2],"data":[1,0.5,-2,3.25]}"#);
+//!     assert_eq!(Tensor::<f32>::from_json(&json).unwrap(), t);
+//!     let err = Tensor::<f32>::from_json(r#"{"shape": [3], "data": [1, 2]}"#).unwrap_err();
+//!     assert_eq!(err.to_string(), "Invalid tensor JSON at byte 30: the shape [3] needs 3 values but the data has 2!");
+//! ```
+
+use crate::tensor::*;
+
+/// A cursor over the JSON text
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error<R>(&self, message: String) -> std::result::Result<R, EasynnError> {
+        Err(EasynnError::InvalidJson { position: self.pos, message })
+    }
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.text[self.pos..].chars().next()
+    }
+    fn expect(&mut self, c: char) -> std::result::Result<(), EasynnError> {
+        match self.peek() {
+            Some(p) if p == c => {
+                self.pos += c.len_utf8();
+                Ok(())
+            },
+            Some(p) => self.error(format!("expected '{}' but found '{}'", c, p)),
+            None => self.error(format!("expected '{}' but the text ended", c)),
+        }
+    }
+    /// A string without escapes, which is all the keys need
+    fn string(&mut self) -> std::result::Result<&'a str, EasynnError> {
+        self.expect('"')?;
+        let rest = &self.text[self.pos..];
+        match rest.find(['"', '\\']) {
+            Some(end) if rest[end..].starts_with('"') => {
+                self.pos += end + 1;
+                Ok(&rest[..end])
+            },
+            Some(_) => self.error("escapes in keys are not supported".to_string()),
+            None => self.error("unterminated string".to_string()),
+        }
+    }
+    /// A number, or null for NaN
+    fn number(&mut self) -> std::result::Result<f64, EasynnError> {
+        self.skip_whitespace();
+        let rest = &self.text[self.pos..];
+        if rest.starts_with("null") {
+            self.pos += 4;
+            return Ok(f64::NAN);
+        }
+        let len = rest.find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')).unwrap_or(rest.len());
+        match rest[..len].parse::<f64>() {
+            Ok(x) if len > 0 && !rest.starts_with('+') => {
+                self.pos += len;
+                Ok(x)
+            },
+            _ => self.error(format!("expected a number but found '{}'", &rest[..len.max(1).min(rest.len())])),
+        }
+    }
+    /// An array of numbers
+    fn array(&mut self) -> std::result::Result<Vec<f64>, EasynnError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(values);
+        }
+        loop {
+            values.push(self.number()?);
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                Some(']') => {
+                    self.pos += 1;
+                    return Ok(values);
+                },
+                _ => return self.error("expected ',' or ']' in the array".to_string()),
+            }
+        }
+    }
+}
+
+impl<T: NumT> Tensor<T> {
+    /// Read a tensor from `{"shape": [...], "data": [...]}`, reporting where and why it is invalid
+    pub fn from_json(text: &str) -> std::result::Result<Self, EasynnError> {
+        let mut parser = Parser { text, pos: 0 };
+        let (mut shape, mut data) = (None, None);
+        parser.expect('{')?;
+        loop {
+            let key = parser.string()?;
+            parser.expect(':')?;
+            let value = parser.array()?;
+            let slot = match key {
+                "shape" => &mut shape,
+                "data" => &mut data,
+                _ => return parser.error(format!("unknown key \"{}\", expected \"shape\" and \"data\"", key)),
+            };
+            if slot.replace(value).is_some() {
+                return parser.error(format!("duplicated key \"{}\"", key));
+            }
+            match parser.peek() {
+                Some(',') => parser.pos += 1,
+                Some('}') => {
+                    parser.pos += 1;
+                    break;
+                },
+                _ => return parser.error("expected ',' or '}' in the object".to_string()),
+            }
+        }
+        if parser.peek().is_some() {
+            return parser.error("trailing characters after the object".to_string());
+        }
+        let (shape, data) = match (shape, data) {
+            (Some(s), Some(d)) => (s, d),
+            (None, _) => return parser.error("missing key \"shape\"".to_string()),
+            (_, None) => return parser.error("missing key \"data\"".to_string()),
+        };
+        let mut dims = Vec::with_capacity(shape.len());
+        for d in shape {
+            if d < 0. || d.fract() != 0. || d > usize::MAX as f64 {
+                return parser.error(format!("the shape has {} which is not a dimension", d));
+            }
+            dims.push(d as usize);
+        }
+        let shape = Shape::from_slice(&dims);
+        let size = shape.checked_size()?;
+        if size != data.len() {
+            return parser.error(format!("the shape {:?} needs {} values but the data has {}", dims, size, data.len()));
+        }
+        let mut flattened = Vec::with_capacity(size);
+        for x in data {
+            match T::from(x) {
+                Some(v) => flattened.push(v),
+                None => return parser.error(format!("{} does not fit in the element type", x)),
+            }
+        }
+        Ok(Tensor { shape, flattened })
+    }
+
+    /// Write the tensor as `{"shape": [...], "data": [...]}` without spaces
+    pub fn to_json(&self) -> String {
+        let dims: Vec<String> = self.shape.dims().iter().map(|d| d.to_string()).collect();
+        let data: Vec<String> = self.flattened.iter()
+            .map(|x| if x.is_finite() { x.to_string() } else { "null".to_string() })
+            .collect();
+        format!("{{\"shape\":[{}],\"data\":[{}]}}", dims.join(","), data.join(","))
+    }
+}
+
+#[test]
+fn test_tensor_json() {
+    let t = Tensor::<f64>::new(&Shape::new([3, 1]), vec![0.1, -1e-300, f64::INFINITY]);
+    let back = Tensor::<f64>::from_json(&t.to_json()).unwrap();
+    assert_eq!(back.as_slice()[..2], t.as_slice()[..2]);
+    assert!(back.as_slice()[2].is_nan());
+
+    let spaced = " {\n  \"data\" : [ 1 , 2.5e1 ] ,\n  \"shape\" : [ 2 ]\n} ";
+    assert_eq!(Tensor::<f32>::from_json(spaced).unwrap().as_slice(), &[1., 25.]);
+    assert_eq!(Tensor::<f32>::from_json(r#"{"shape":[0,2],"data":[]}"#).unwrap().get_shape(), &Shape::new([0, 2]));
+
+    let message = |text: &str| Tensor::<f32>::from_json(text).unwrap_err().to_string();
+    assert!(message(r#"{"shape":[2]}"#).contains("missing key \"data\""));
+    assert!(message(r#"{"shape":[2],"data":[1,2],"dtype":[]}"#).contains("unknown key \"dtype\""));
+    assert!(message(r#"{"shape":[1.5],"data":[1]}"#).contains("1.5 which is not a dimension"));
+    assert!(message(r#"{"shape":[2],"data":[1,"a"]}"#).contains("at byte 23: expected a number"));
+    assert!(message(r#"{"shape":[1],"data":[1]} x"#).contains("trailing"));
+    assert!(message(r#"{"shape":[1],"data":[1"#).contains("expected ',' or ']'"));
+    assert!(matches!(
+        Tensor::<f32>::from_json(r#"{"shape":[4294967296,4294967296,4294967296],"data":[]}"#),
+        Err(EasynnError::SizeOverflow(_))
+    ));
+}
@@ -7,11 +7,25 @@ pub use shape::*;
 pub use std::ops::{ Add, Mul };
 
 pub mod num;
-pub use num::NumT;
+pub use num::{ NumT, ScalarT };
+
+pub mod complex;
+pub use complex::Complex;
+
+pub mod ops;
+pub mod select;
+
+pub mod fft;
+pub mod json;
+pub mod npy;
+pub use fft::fft_convolve;
 
 /// Tensor: a generic describing a tensor with the element type T.
+///
+/// The storage is private so that its length always equals the shape size,
+/// it is reached through `as_slice` and `as_mut_slice`.
 #[derive(Debug, Clone, PartialEq)]
-pub struct Tensor<T: NumT> {
+pub struct Tensor<T: ScalarT> {
     pub(crate) shape: Shape,
     pub(crate) flattened: Vec<T>,
 }
@@ -20,21 +34,51 @@ pub struct Tensor<T: NumT> {
 pub type TensorIndex<const RANK: usize> = [usize; RANK];
 
 pub mod error;
-pub use error::{ OutOfBondError, ShapeMismatchError };
+pub use error::{ OutOfBondError, EasynnError };
+pub(crate) use error::{ check_shape, check_len };
 type Result<T> = std::result::Result<T, OutOfBondError>;
 
-impl<T: NumT> Tensor<T> {
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+/// The cap of a single allocation in bytes, 0 for no cap
+static ALLOCATION_LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Cap the bytes of a single tensor or parameter allocation, `None` to remove the cap.
+/// Allocations over the cap fail with `EasynnError::ResourceLimit` instead of running out of memory.
+pub fn set_allocation_limit(bytes: Option<usize>) {
+    ALLOCATION_LIMIT.store(bytes.unwrap_or(0), Ordering::Relaxed);
+}
+
+pub fn allocation_limit() -> Option<usize> {
+    match ALLOCATION_LIMIT.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Check that `count` elements of T can be allocated under the cap
+pub(crate) fn check_allocation<T>(count: usize) -> std::result::Result<(), EasynnError> {
+    let elem = std::mem::size_of::<T>();
+    let requested = count.checked_mul(elem).ok_or(EasynnError::SizeOverflow(vec![count, elem]))?;
+    match allocation_limit() {
+        Some(limit) if requested > limit => Err(EasynnError::ResourceLimit { requested, limit }),
+        _ => Ok(()),
+    }
+}
+
+impl<T: ScalarT> Tensor<T> {
     pub(crate) fn index2pos<const RANK: usize>(&self, at: TensorIndex<RANK>) -> Result<usize> {
         let mut pos: usize = 0;
-        for dimention in 0..RANK {
-            if at[dimention] >= self.shape[dimention] {
+        for (dimention, &i) in at.iter().enumerate() {
+            if i >= self.shape[dimention] {
                 return Err(OutOfBondError);
             }
             pos *= self.shape[dimention];
-            pos += at[dimention];
+            pos += i;
         }
         Ok(pos)
     }
+    #[allow(dead_code)]
     pub(crate) fn pos2index<const RANK: usize>(&self, mut pos: usize) -> Result<TensorIndex<RANK>> {
         if pos > self.flattened.len() {
             return Err(OutOfBondError);
@@ -47,14 +91,25 @@ impl<T: NumT> Tensor<T> {
         Ok(ind)
     }
 
+    /// Panics if the length of the data is not the shape size, see `try_new`
     pub fn new(shape: &Shape, flattened: Vec<T>) -> Self {
-        if flattened.len() != shape.size() {
-            panic!("Shape mismatch!")
+        match Self::try_new(shape, flattened) {
+            Ok(t) => t,
+            Err(_) => panic!("Shape mismatch!"),
         }
-        Tensor {
-            flattened: flattened,
+    }
+    /// Create a tensor of the flattened data, if its length is the shape size
+    pub fn try_new(shape: &Shape, flattened: Vec<T>) -> std::result::Result<Self, EasynnError> {
+        check_len("try_new", shape.checked_size()?, flattened.len())?;
+        Ok(Tensor {
+            flattened,
             shape: shape.clone(),
-        }
+        })
+    }
+    /// Zeros checking that the size does not overflow and is under the allocation cap
+    pub fn try_zeros(shape: &Shape) -> std::result::Result<Self, EasynnError> {
+        check_allocation::<T>(shape.checked_size()?)?;
+        Ok(Self::zeros(shape))
     }
     pub fn zeros(shape: &Shape) -> Self {
         Tensor::<T> { flattened: vec![T::zero(); shape.size()], shape: shape.clone(), }
@@ -73,4 +128,66 @@ impl<T: NumT> Tensor<T> {
     pub fn get_shape(&self) -> &Shape {
         &self.shape
     }
+    /// The flattened elements, in row-major order
+    pub fn as_slice(&self) -> &[T] {
+        &self.flattened
+    }
+    /// The flattened elements, mutable in place but not resizable
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        &mut self.flattened
+    }
+    /// Take the flattened elements
+    pub fn into_vec(self) -> Vec<T> {
+        self.flattened
+    }
+    /// A tensor of the same shape with f applied to each element
+    pub fn map<F: FnMut(T) -> T>(&self, f: F) -> Self {
+        Tensor::<T> { flattened: self.flattened.iter().copied().map(f).collect(), shape: self.shape.clone() }
+    }
+    /// Apply f to each element in place
+    pub fn apply<F: FnMut(T) -> T>(&mut self, mut f: F) {
+        self.flattened.iter_mut().for_each(|x| *x = f(*x));
+    }
+    /// Stack tensors of the item shape along a new leading batch dimension
+    pub fn stack(item_shape: &Shape, tensors: &[Tensor<T>]) -> std::result::Result<Self, EasynnError> {
+        for t in tensors {
+            check_shape("stack", item_shape, &t.shape)?;
+        }
+        Ok(Tensor::<T> {
+            flattened: tensors.iter().flat_map(|t| t.flattened.iter().copied()).collect(),
+            shape: item_shape.batched(tensors.len()),
+        })
+    }
+    /// Split along the leading dimension, the inverse of `stack`
+    pub fn unstack(&self) -> Vec<Tensor<T>> {
+        if self.shape.rank() == 0 {
+            return Vec::new();
+        }
+        let item_shape = Shape::from_slice(&self.shape.dims()[1..]);
+        let size = item_shape.size();
+        (0..self.shape[0]).map(|i| Tensor::<T> {
+            flattened: self.flattened[i * size..(i + 1) * size].to_vec(),
+            shape: item_shape.clone(),
+        }).collect()
+    }
+}
+
+#[test]
+fn test_tensor_accessors() {
+    let shape = Shape::new([2, 2]);
+    assert!(Tensor::<f64>::try_new(&shape, vec![1., 2., 3.]).is_err());
+    let mut t = Tensor::<f64>::try_new(&shape, vec![1., 2., 3., 4.]).unwrap();
+    assert_eq!(t.as_slice(), &[1., 2., 3., 4.]);
+    t.as_mut_slice()[1] = 5.;
+    assert_eq!(t.get([0, 1]), 5.);
+    assert_eq!(t.map(|x| x * 2.).as_slice(), &[2., 10., 6., 8.]);
+    t.apply(|x| x - 1.);
+    assert_eq!(t.into_vec(), vec![0., 4., 2., 3.]);
+
+    let rows = [Tensor::<f64>::new(&Shape::new([2]), vec![1., 2.]), Tensor::new(&Shape::new([2]), vec![3., 4.])];
+    let batch = Tensor::stack(&Shape::new([2]), &rows).unwrap();
+    assert_eq!(batch, Tensor::new(&Shape::new([2, 2]), vec![1., 2., 3., 4.]));
+    assert_eq!(batch.unstack(), rows.to_vec());
+    assert_eq!(Tensor::<f64>::stack(&Shape::new([2]), &[]).unwrap().get_shape(), &Shape::new([0, 2]));
+    assert!(Tensor::stack(&Shape::new([3]), &rows).is_err());
 }
\ No newline at end of file
@@ -0,0 +1,371 @@
+//! Reading and writing tensors in the NumPy `.npy` format, and `.npz` archives of them.
+//!
+//! Tensors are written as little-endian `<f4` or `<f8` following the element type.
+//! Any boolean, integer or float dtype of either byte order is read, converted into
+//! the element type, and arrays in Fortran order are reordered to row-major.
+//! Archives are read if their entries are stored, as by `numpy.savez`, but not
+//! compressed, as by `numpy.savez_compressed`.
+//!
+//! ```rust
+//!     use easynn::tensor::{ Tensor, Shape };
+//!     let t = Tensor::<f64>::new(&Shape::new([2, 3]), vec![0., 1., 2., 3., 4., 5.]);
+//!     let mut buf = Vec::new();
+//!     t.write_npy(&mut buf).unwrap();
+//!     assert_eq!(&buf[..6], b"\x93NUMPY");
+//!     assert_eq!(Tensor::<f64>::read_npy(&mut buf.as_slice()).unwrap(), t);
+//! ```
+
+use crate::tensor::*;
+
+use std::fs::File;
+use std::io::{ BufReader, BufWriter, Error, ErrorKind, Read, Result, Write };
+use std::path::Path;
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("invalid NPY: {}", msg))
+}
+
+/// The dtype of the header, e.g. `<f8`
+struct Dtype {
+    big_endian: bool,
+    kind: u8,
+    size: usize,
+}
+
+impl Dtype {
+    fn parse(descr: &str) -> Result<Self> {
+        let b = descr.as_bytes();
+        if b.len() < 3 {
+            return Err(invalid("bad dtype"));
+        }
+        let size: usize = descr[2..].parse().map_err(|_| invalid("bad dtype"))?;
+        let ok = match b[1] {
+            b'b' | b'i' | b'u' => matches!(size, 1 | 2 | 4 | 8),
+            b'f' => matches!(size, 4 | 8),
+            _ => false,
+        };
+        if !ok || !matches!(b[0], b'<' | b'>' | b'|' | b'=') {
+            return Err(invalid(&format!("unsupported dtype {}", descr)));
+        }
+        Ok(Dtype { big_endian: b[0] == b'>', kind: b[1], size })
+    }
+    fn value(&self, bytes: &[u8]) -> f64 {
+        let mut b = [0; 8];
+        if self.big_endian {
+            b[..self.size].iter_mut().zip(bytes.iter().rev()).for_each(|(d, s)| *d = *s);
+        } else {
+            b[..self.size].copy_from_slice(bytes);
+        }
+        match (self.kind, self.size) {
+            (b'f', 4) => f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            (b'f', _) => f64::from_le_bytes(b),
+            (b'u', _) | (b'b', _) => u64::from_le_bytes(b) as f64,
+            // sign-extend the integer from its size
+            (_, size) => {
+                let shift = 64 - 8 * size as u32;
+                ((i64::from_le_bytes(b) << shift) >> shift) as f64
+            },
+        }
+    }
+}
+
+/// The value of `key` in the header dict, up to the next top-level comma or brace
+fn header_value<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let quoted = [format!("'{}'", key), format!("\"{}\"", key)];
+    let start = quoted.iter().find_map(|q| header.find(q.as_str()).map(|p| p + q.len()))
+        .ok_or_else(|| invalid(&format!("the header has no {}", key)))?;
+    let rest = header[start..].trim_start().strip_prefix(':').ok_or_else(|| invalid("bad header"))?.trim_start();
+    let end = if rest.starts_with('(') {
+        rest.find(')').map(|p| p + 1)
+    } else {
+        rest.find([',', '}'])
+    }.ok_or_else(|| invalid("bad header"))?;
+    Ok(rest[..end].trim())
+}
+
+/// The row-major position of each element stored in Fortran order
+fn fortran_positions(dims: &[usize]) -> Vec<usize> {
+    let size: usize = dims.iter().product();
+    let mut strides = vec![1; dims.len()];
+    for i in (0..dims.len().saturating_sub(1)).rev() {
+        strides[i] = strides[i + 1] * dims[i + 1];
+    }
+    (0..size).map(|mut f| {
+        dims.iter().zip(strides.iter()).map(|(d, s)| {
+            let i = f % d;
+            f /= d;
+            i * s
+        }).sum()
+    }).collect()
+}
+
+impl<T: NumT> Tensor<T> {
+    /// Read a tensor in the `.npy` format
+    pub fn read_npy<R: Read>(r: &mut R) -> Result<Self> {
+        let mut pre = [0; 8];
+        r.read_exact(&mut pre)?;
+        if &pre[..6] != MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        let len = match pre[6] {
+            1 => {
+                let mut b = [0; 2];
+                r.read_exact(&mut b)?;
+                u16::from_le_bytes(b) as usize
+            },
+            2 | 3 => {
+                let mut b = [0; 4];
+                r.read_exact(&mut b)?;
+                u32::from_le_bytes(b) as usize
+            },
+            v => return Err(invalid(&format!("unsupported version {}", v))),
+        };
+        let mut header = Vec::new();
+        r.take(len as u64).read_to_end(&mut header)?;
+        let header = String::from_utf8(header).map_err(|_| invalid("the header is not UTF-8"))?;
+
+        let descr = header_value(&header, "descr")?;
+        let dtype = Dtype::parse(descr.trim_matches(|c| c == '\'' || c == '"'))?;
+        let fortran = match header_value(&header, "fortran_order")? {
+            "True" => true,
+            "False" => false,
+            _ => return Err(invalid("bad fortran_order")),
+        };
+        let shape = header_value(&header, "shape")?;
+        let dims = shape.trim_start_matches('(').trim_end_matches(')').split(',')
+            .map(|d| d.trim()).filter(|d| !d.is_empty())
+            .map(|d| d.parse::<usize>().map_err(|_| invalid("bad shape")))
+            .collect::<Result<Vec<_>>>()?;
+        let shape = Shape::from_slice(&dims);
+        let size = shape.checked_size().map_err(|_| invalid("the shape overflows"))?;
+        check_allocation::<T>(size).map_err(|e| invalid(&e.to_string()))?;
+
+        let mut data = Vec::new();
+        let mut bytes = [0; 8];
+        for _ in 0..size {
+            r.read_exact(&mut bytes[..dtype.size])?;
+            data.push(T::from(dtype.value(&bytes[..dtype.size])).ok_or_else(|| invalid("a value does not fit the type"))?);
+        }
+        if fortran {
+            let mut ordered = vec![T::zero(); size];
+            for (x, p) in data.into_iter().zip(fortran_positions(&dims)) {
+                ordered[p] = x;
+            }
+            data = ordered;
+        }
+        Ok(Tensor { shape, flattened: data })
+    }
+
+    /// Write the tensor in the `.npy` format (version 1.0)
+    pub fn write_npy<W: Write>(&self, w: &mut W) -> Result<()> {
+        let width = std::mem::size_of::<T>();
+        let dims = self.shape.dims().iter().map(|d| format!("{},", d)).collect::<Vec<_>>().join(" ");
+        let dims = if self.shape.rank() == 1 { dims } else { dims.trim_end_matches(',').to_string() };
+        let mut header = format!("{{'descr': '<f{}', 'fortran_order': False, 'shape': ({}), }}", width, dims);
+        // pad with spaces so that the data starts 64-byte aligned, ending with a newline
+        let total = MAGIC.len() + 4 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - total % 64) % 64));
+        header.push('\n');
+        let len = u16::try_from(header.len()).map_err(|_| invalid("the header is too long"))?;
+        w.write_all(MAGIC)?;
+        w.write_all(&[1, 0])?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(header.as_bytes())?;
+        for x in &self.flattened {
+            match width {
+                4 => w.write_all(&x.to_f32().unwrap().to_le_bytes())?,
+                _ => w.write_all(&x.to_f64().unwrap().to_le_bytes())?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the `.npy` file
+    pub fn from_npy<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read_npy(&mut BufReader::new(File::open(path)?))
+    }
+
+    /// Write the `.npy` file
+    pub fn to_npy<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_npy(&mut w)?;
+        w.flush()
+    }
+}
+
+/// The CRC-32 of the zip entries
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0_u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn u16_at(b: &[u8], pos: usize) -> Result<usize> {
+    b.get(pos..pos + 2).map(|s| u16::from_le_bytes([s[0], s[1]]) as usize).ok_or_else(|| invalid("truncated archive"))
+}
+
+fn u32_at(b: &[u8], pos: usize) -> Result<usize> {
+    b.get(pos..pos + 4).map(|s| u32::from_le_bytes([s[0], s[1], s[2], s[3]]) as usize).ok_or_else(|| invalid("truncated archive"))
+}
+
+fn u64_at(b: &[u8], pos: usize) -> Result<usize> {
+    let s = b.get(pos..pos + 8).ok_or_else(|| invalid("truncated archive"))?;
+    usize::try_from(u64::from_le_bytes(s.try_into().unwrap())).map_err(|_| invalid("the archive is too large"))
+}
+
+/// Read the named tensors of a `.npz` archive, the names without the `.npy` suffix
+pub fn read_npz<T: NumT, R: Read>(r: &mut R) -> Result<Vec<(String, Tensor<T>)>> {
+    let mut zip = Vec::new();
+    r.read_to_end(&mut zip)?;
+    // the end of central directory record, followed by a comment of at most 64 KiB
+    let eocd = (0..zip.len().saturating_sub(21)).rev().take(1 << 16)
+        .find(|p| zip[*p..].starts_with(&[0x50, 0x4b, 0x05, 0x06]))
+        .ok_or_else(|| invalid("not a zip archive"))?;
+    let count = u16_at(&zip, eocd + 10)?;
+    let mut pos = u32_at(&zip, eocd + 16)?;
+    let mut ret = Vec::with_capacity(count);
+    for _ in 0..count {
+        if !zip.get(pos..).is_some_and(|b| b.starts_with(&[0x50, 0x4b, 0x01, 0x02])) {
+            return Err(invalid("bad central directory"));
+        }
+        let method = u16_at(&zip, pos + 10)?;
+        let (mut size, name_len, extra_len, comment_len) = (u32_at(&zip, pos + 20)?, u16_at(&zip, pos + 28)?, u16_at(&zip, pos + 30)?, u16_at(&zip, pos + 32)?);
+        let mut offset = u32_at(&zip, pos + 42)?;
+        let name = zip.get(pos + 46..pos + 46 + name_len).ok_or_else(|| invalid("truncated archive"))?;
+        let name = String::from_utf8_lossy(name).to_string();
+        // the zip64 extra field holds the sizes and the offset that overflow 32 bits, in order
+        let mut extra = pos + 46 + name_len;
+        let extra_end = extra + extra_len;
+        while extra + 4 <= extra_end {
+            let (id, len) = (u16_at(&zip, extra)?, u16_at(&zip, extra + 2)?);
+            if id == 1 {
+                let mut field = extra + 4;
+                if u32_at(&zip, pos + 24)? == 0xffff_ffff {
+                    field += 8;
+                }
+                if size == 0xffff_ffff {
+                    size = u64_at(&zip, field)?;
+                    field += 8;
+                }
+                if offset == 0xffff_ffff {
+                    offset = u64_at(&zip, field)?;
+                }
+            }
+            extra += 4 + len;
+        }
+        if method != 0 {
+            return Err(invalid(&format!("the entry {} is compressed", name)));
+        }
+        if !zip.get(offset..).is_some_and(|b| b.starts_with(&[0x50, 0x4b, 0x03, 0x04])) {
+            return Err(invalid("bad local header"));
+        }
+        let data = offset + 30 + u16_at(&zip, offset + 26)? + u16_at(&zip, offset + 28)?;
+        let mut entry = zip.get(data..data + size).ok_or_else(|| invalid("truncated archive"))?;
+        let tensor = Tensor::read_npy(&mut entry)?;
+        ret.push((name.strip_suffix(".npy").unwrap_or(&name).to_string(), tensor));
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(ret)
+}
+
+/// Write the named tensors as a `.npz` archive of stored entries, adding the `.npy` suffix
+pub fn write_npz<T: NumT, W: Write>(tensors: &[(&str, &Tensor<T>)], w: &mut W) -> Result<()> {
+    let too_large = || invalid("the archive is too large");
+    let mut local = Vec::new();
+    let mut central = Vec::new();
+    for (name, tensor) in tensors {
+        let name = format!("{}.npy", name);
+        let mut data = Vec::new();
+        tensor.write_npy(&mut data)?;
+        let offset = u32::try_from(local.len()).map_err(|_| too_large())?;
+        let size = u32::try_from(data.len()).map_err(|_| too_large())?;
+        // version 2.0, no flags, stored, zero time and date
+        let mut fields = Vec::new();
+        fields.extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        fields.extend_from_slice(&crc32(&data).to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&[0, 0]);
+
+        local.extend_from_slice(&[0x50, 0x4b, 0x03, 0x04]);
+        local.extend_from_slice(&fields);
+        local.extend_from_slice(name.as_bytes());
+        local.extend_from_slice(&data);
+
+        central.extend_from_slice(&[0x50, 0x4b, 0x01, 0x02, 20, 0]);
+        central.extend_from_slice(&fields);
+        // no comment, disk 0, no attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+    let count = u16::try_from(tensors.len()).map_err(|_| too_large())?;
+    let central_len = u32::try_from(central.len()).map_err(|_| too_large())?;
+    let central_offset = u32::try_from(local.len()).map_err(|_| too_large())?;
+    w.write_all(&local)?;
+    w.write_all(&central)?;
+    w.write_all(&[0x50, 0x4b, 0x05, 0x06, 0, 0, 0, 0])?;
+    w.write_all(&count.to_le_bytes())?;
+    w.write_all(&count.to_le_bytes())?;
+    w.write_all(&central_len.to_le_bytes())?;
+    w.write_all(&central_offset.to_le_bytes())?;
+    w.write_all(&[0, 0])
+}
+
+/// Load the named tensors of the `.npz` file
+pub fn load_npz<T: NumT, P: AsRef<Path>>(path: P) -> Result<Vec<(String, Tensor<T>)>> {
+    read_npz(&mut BufReader::new(File::open(path)?))
+}
+
+/// Save the named tensors as the `.npz` file
+pub fn save_npz<T: NumT, P: AsRef<Path>>(tensors: &[(&str, &Tensor<T>)], path: P) -> Result<()> {
+    let mut w = BufWriter::new(File::create(path)?);
+    write_npz(tensors, &mut w)?;
+    w.flush()
+}
+
+#[test]
+fn test_npy() {
+    // as written by numpy.save of np.asfortranarray(np.arange(6, dtype='>i2').reshape(3, 2))
+    let mut file = b"\x93NUMPY\x01\x00\x76\x00{'descr': '>i2', 'fortran_order': True, 'shape': (3, 2), }".to_vec();
+    file.resize(10 + 0x76 - 1, b' ');
+    file.push(b'\n');
+    file.extend_from_slice(&[0, 0, 0, 2, 0, 4, 0, 1, 0, 3, 0, 5]);
+    let t = Tensor::<f32>::read_npy(&mut file.as_slice()).unwrap();
+    assert_eq!(t, Tensor::new(&Shape::new([3, 2]), vec![0., 1., 2., 3., 4., 5.]));
+
+    // f32 round trip with a 1-tuple shape, the header is 64-byte aligned
+    let v = Tensor::<f32>::new(&Shape::new([3]), vec![1.5, -2., 0.25]);
+    let mut buf = Vec::new();
+    v.write_npy(&mut buf).unwrap();
+    assert_eq!((10 + u16::from_le_bytes([buf[8], buf[9]]) as usize) % 64, 0);
+    assert!(String::from_utf8_lossy(&buf).contains("'descr': '<f4', 'fortran_order': False, 'shape': (3,), }"));
+    assert_eq!(Tensor::<f64>::read_npy(&mut buf.as_slice()).unwrap(), Tensor::new(&Shape::new([3]), vec![1.5, -2., 0.25]));
+    let path = std::env::temp_dir().join(format!("easynn_{}.npy", std::process::id()));
+    v.to_npy(&path).unwrap();
+    assert_eq!(Tensor::<f32>::from_npy(&path).unwrap(), v);
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(Tensor::<f32>::read_npy(&mut &buf[..buf.len() - 1]).is_err());
+    let complex = String::from_utf8_lossy(&buf).replace("<f4", "<c8");
+    assert!(Tensor::<f32>::read_npy(&mut complex.as_bytes()).is_err());
+
+    // CRC-32 of "123456789"
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    let m = Tensor::<f64>::new(&Shape::new([2, 2]), vec![1., 2., 3., 4.]);
+    let mut zip = Vec::new();
+    write_npz(&[("weight", &m), ("bias", &Tensor::new(&Shape::new([2]), vec![0.5, -0.5]))], &mut zip).unwrap();
+    let read = read_npz::<f64, _>(&mut zip.as_slice()).unwrap();
+    assert_eq!(read.len(), 2);
+    assert_eq!(read[0], ("weight".to_string(), m));
+    assert_eq!(read[1].0, "bias");
+    assert!(read_npz::<f64, _>(&mut &zip[..zip.len() - 30]).is_err());
+}
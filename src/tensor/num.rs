@@ -1,5 +1,5 @@
-/// NumT trait is implemented for the numeric types that
-/// are accepted to be contained in a tensor.
+//! NumT trait is implemented for the numeric types that
+//! are accepted to be contained in a tensor.
 
 extern crate num_traits;
 use num_traits::{ NumOps, NumAssignOps, Float };
@@ -10,9 +10,17 @@ use std::marker::{ Send, Sync };
 use std::fmt::{ Debug, Display };
 
 pub trait NumT:
-    PartialEq + PartialOrd + Zero + One + NumOps + NumAssignOps
-    + Copy + Send + Sync + Debug + Display + Neg + Float
-    + std::iter::Sum
+    ScalarT + PartialOrd + NumAssignOps + Display + Neg + Float + 'static
+{ }
+
+/// ScalarT is what a tensor may contain: NumT, but also the complex numbers
+/// of NumT, supporting the arithmetic ops but neither ordering nor activations.
+pub trait ScalarT:
+    PartialEq + Zero + One + NumOps + Copy + Send + Sync + Debug + std::iter::Sum
+{ }
+
+impl<T> ScalarT for T
+where T: PartialEq + Zero + One + NumOps + Copy + Send + Sync + Debug + std::iter::Sum
 { }
 
 macro_rules! trait_impl {
@@ -21,4 +29,16 @@ macro_rules! trait_impl {
     )*)
 }
 
-trait_impl!(NumT for f32 f64);
\ No newline at end of file
+trait_impl!(NumT for f32 f64);
+
+/// ln(e^a + e^b), where either may be -inf
+pub(crate) fn log_add<T: NumT>(a: T, b: T) -> T {
+    if a == T::neg_infinity() {
+        return b;
+    }
+    if b == T::neg_infinity() {
+        return a;
+    }
+    let m = a.max(b);
+    m + ((a - m).exp() + (b - m).exp()).ln()
+}
@@ -0,0 +1,302 @@
+//! Element-wise arithmetic, the matrix product, and the reshaping, the flattening, the
+//! transposition and the permutation of axes, for any `ScalarT` including complex numbers.
+//! They return new tensors, only `into_shape` reuses the elements of the tensor it takes.
+//!
+//! The element-wise ops `+ - * /` take tensors or scalars, by value or by reference.
+//! Two tensors are broadcast NumPy-style: their shapes are aligned on the last axes, and
+//! an axis of length 1 or a missing leading axis is repeated along the other tensor, e.g.
+//! `[2, 3] + [3]` adds the row to both rows. The ops panic on incompatible shapes, as
+//! indexing out of bond does, while `zip_with` returns the error.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     let x = Tensor::<f32>::new(sh!([2, 3]), vec![1., 2., 3., 4., 5., 6.]);
+//!     let mean = Tensor::new(sh!([3]), vec![2.5, 3.5, 4.5]);
+//!     let centered = (&x - &mean) * 2.;
+//!     assert_eq!(centered.as_slice(), &[-3., -3., -3., 3., 3., 3.]);
+//! ```
+
+use crate::tensor::*;
+
+use std::ops::{ Sub, Div };
+
+use crate::parallel;
+
+macro_rules! impl_elementwise {
+    ($op: ident, $method: ident) => {
+        impl<'a, T: ScalarT> $op<&'a Tensor<T>> for &'a Tensor<T> {
+            type Output = Tensor<T>;
+            fn $method(self, rhs: &'a Tensor<T>) -> Tensor<T> {
+                match self.broadcast(stringify!($method), rhs, $op::$method) {
+                    Ok(t) => t,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        }
+        impl<T: ScalarT> $op<Tensor<T>> for Tensor<T> {
+            type Output = Tensor<T>;
+            fn $method(self, rhs: Tensor<T>) -> Tensor<T> {
+                $op::$method(&self, &rhs)
+            }
+        }
+        impl<'a, T: ScalarT> $op<&'a Tensor<T>> for Tensor<T> {
+            type Output = Tensor<T>;
+            fn $method(self, rhs: &'a Tensor<T>) -> Tensor<T> {
+                $op::$method(&self, rhs)
+            }
+        }
+        impl<'a, T: ScalarT> $op<T> for &'a Tensor<T> {
+            type Output = Tensor<T>;
+            fn $method(self, rhs: T) -> Tensor<T> {
+                Tensor::<T> {
+                    flattened: parallel::map(&self.flattened, |a| $op::$method(*a, rhs)),
+                    shape: self.shape.clone(),
+                }
+            }
+        }
+        impl<T: ScalarT> $op<T> for Tensor<T> {
+            type Output = Tensor<T>;
+            fn $method(mut self, rhs: T) -> Tensor<T> {
+                parallel::for_each_mut(&mut self.flattened, |_, a| *a = $op::$method(*a, rhs));
+                self
+            }
+        }
+    }
+}
+
+impl_elementwise!(Add, add);
+impl_elementwise!(Sub, sub);
+impl_elementwise!(Mul, mul);
+impl_elementwise!(Div, div);
+
+impl Shape {
+    /// The shape of the element-wise ops of tensors of the two shapes, see the module
+    pub fn broadcast(&self, rhs: &Shape) -> std::result::Result<Shape, EasynnError> {
+        let rank = self.rank().max(rhs.rank());
+        // The dims aligned on the last axis, the missing leading ones being 1
+        let dim = |s: &Shape, i: usize| if i + s.rank() < rank { 1 } else { s[i + s.rank() - rank] };
+        let mut dims = Vec::with_capacity(rank);
+        for i in 0..rank {
+            match (dim(self, i), dim(rhs, i)) {
+                (a, b) if a == b || b == 1 => dims.push(a),
+                (1, b) => dims.push(b),
+                _ => return Err(EasynnError::mismatch("broadcast", self, rhs)),
+            }
+        }
+        Ok(Shape::from_slice(&dims))
+    }
+}
+
+impl<T: ScalarT> Tensor<T> {
+    /// The strides of the tensor walked in the broadcast shape, 0 along the repeated axes
+    fn broadcast_strides(&self, shape: &Shape) -> Vec<usize> {
+        let offset = shape.rank() - self.shape.rank();
+        let mut strides = vec![0; shape.rank()];
+        let mut stride = 1;
+        for i in (0..self.shape.rank()).rev() {
+            if self.shape[i] != 1 {
+                strides[i + offset] = stride;
+            }
+            stride *= self.shape[i];
+        }
+        strides
+    }
+
+    fn broadcast<F>(&self, op: &'static str, rhs: &Tensor<T>, f: F) -> std::result::Result<Tensor<T>, EasynnError>
+    where F: Fn(T, T) -> T + Sync {
+        if self.shape == rhs.shape {
+            return Ok(Tensor::<T> {
+                flattened: parallel::zip_map(&self.flattened, &rhs.flattened, |a, b| f(*a, *b)),
+                shape: self.shape.clone(),
+            });
+        }
+        let shape = self.shape.broadcast(&rhs.shape).map_err(|_| EasynnError::mismatch(op, &self.shape, &rhs.shape))?;
+        check_allocation::<T>(shape.checked_size()?)?;
+        let (lhs_strides, rhs_strides) = (self.broadcast_strides(&shape), rhs.broadcast_strides(&shape));
+        let dims = shape.dims();
+        let mut flattened = vec![T::zero(); shape.size()];
+        parallel::for_each_mut(&mut flattened, |mut pos, o| {
+            let (mut l, mut r) = (0, 0);
+            for i in (0..dims.len()).rev() {
+                let at = pos % dims[i];
+                pos /= dims[i];
+                l += at * lhs_strides[i];
+                r += at * rhs_strides[i];
+            }
+            *o = f(self.flattened[l], rhs.flattened[r]);
+        });
+        Ok(Tensor::<T> { flattened, shape })
+    }
+
+    /// Combine the elements of the tensors broadcast to a common shape, see the module
+    pub fn zip_with<F>(&self, rhs: &Tensor<T>, f: F) -> std::result::Result<Tensor<T>, EasynnError>
+    where F: Fn(T, T) -> T + Sync {
+        self.broadcast("zip_with", rhs, f)
+    }
+
+    /// The matrix product of `[m, k]` and `[k, n]` tensors
+    pub fn matmul(&self, rhs: &Tensor<T>) -> std::result::Result<Tensor<T>, EasynnError> {
+        if self.shape.rank() != 2 {
+            return Err(EasynnError::invalid("matmul", format!("the shape {:?} is not of a matrix", self.shape.dims())));
+        }
+        if rhs.shape.rank() != 2 || self.shape[1] != rhs.shape[0] {
+            let cols = if rhs.shape.rank() == 2 { rhs.shape[1] } else { 1 };
+            return Err(EasynnError::mismatch("matmul", &Shape::new([self.shape[1], cols]), &rhs.shape));
+        }
+        let (m, k, n) = (self.shape[0], self.shape[1], rhs.shape[1]);
+        let mut out = vec![T::zero(); m * n];
+        for (i, row) in out.chunks_mut(n).enumerate() {
+            for (p, &a) in self.flattened[i * k..(i + 1) * k].iter().enumerate() {
+                for (o, &b) in row.iter_mut().zip(rhs.flattened[p * n..(p + 1) * n].iter()) {
+                    *o = *o + a * b;
+                }
+            }
+        }
+        Ok(Tensor::<T> { flattened: out, shape: Shape::new([m, n]) })
+    }
+
+    /// The same elements in the same order under a shape of the same size
+    pub fn reshape(&self, shape: &Shape) -> std::result::Result<Tensor<T>, EasynnError> {
+        Tensor::try_new(shape, self.flattened.clone())
+    }
+
+    /// `reshape` taking the tensor, so that the elements are not copied
+    pub fn into_shape(self, shape: &Shape) -> std::result::Result<Tensor<T>, EasynnError> {
+        Tensor::try_new(shape, self.flattened)
+    }
+
+    /// The elements as a tensor of rank 1, e.g. to feed conv outputs into a `Dense`
+    pub fn flatten(&self) -> Tensor<T> {
+        Tensor::<T> { flattened: self.flattened.clone(), shape: Shape::new([self.flattened.len()]) }
+    }
+
+    /// Reverse the axes, the transpose of a matrix
+    pub fn transpose(&self) -> Tensor<T> {
+        let axes: Vec<usize> = (0..self.shape.rank()).rev().collect();
+        match self.permute(&axes) {
+            Ok(t) => t,
+            Err(e) => unreachable!("{}", e),
+        }
+    }
+
+    /// Permute the axes: axis `i` of the result is axis `axes[i]` of the tensor,
+    /// e.g. `[1, 0]` transposes a matrix
+    pub fn permute(&self, axes: &[usize]) -> std::result::Result<Tensor<T>, EasynnError> {
+        let rank = self.shape.rank();
+        let mut seen = vec![false; rank];
+        if axes.len() != rank || axes.iter().any(|a| *a >= rank || std::mem::replace(&mut seen[*a], true)) {
+            return Err(EasynnError::invalid("permute", format!("{:?} is not a permutation of the {} axes", axes, rank)));
+        }
+        let dims = self.shape.dims();
+        let out_dims: Vec<usize> = axes.iter().map(|a| dims[*a]).collect();
+        // The stride in the tensor of each axis of the result
+        let mut strides = vec![1; rank];
+        for i in (0..rank.saturating_sub(1)).rev() {
+            strides[i] = strides[i + 1] * dims[i + 1];
+        }
+        let out_strides: Vec<usize> = axes.iter().map(|a| strides[*a]).collect();
+        let mut index = vec![0; rank];
+        let mut flattened = Vec::with_capacity(self.flattened.len());
+        for _ in 0..self.flattened.len() {
+            flattened.push(self.flattened[index.iter().zip(out_strides.iter()).map(|(i, s)| i * s).sum::<usize>()]);
+            // Count in the result order, the last axis fastest
+            for i in (0..rank).rev() {
+                index[i] += 1;
+                if index[i] < out_dims[i] {
+                    break;
+                }
+                index[i] = 0;
+            }
+        }
+        Ok(Tensor::<T> { flattened, shape: Shape::from_slice(&out_dims) })
+    }
+}
+
+impl<T: NumT> Tensor<T> {
+    /// Whether the tensors are of the same shape and every element of the tensor is within
+    /// `atol + rtol * |other|` of the element of the other, as `numpy.allclose`
+    pub fn approx_eq(&self, other: &Tensor<T>, rtol: T, atol: T) -> bool {
+        self.shape == other.shape
+            && self.flattened.iter().zip(other.flattened.iter()).all(|(a, b)| (*a - *b).abs() <= atol + rtol * b.abs())
+    }
+}
+
+/// Assert that two tensors are equal up to tolerances, see `Tensor::approx_eq`.
+/// The relative and the absolute tolerances default to `1e-5` and `1e-8`.
+///
+/// ```rust
+///     use easynn::prelude::*;
+///     let a = Tensor::<f64>::new(sh!([2]), vec![0.1 + 0.2, 1.]);
+///     assert_tensor_eq!(a, Tensor::new(sh!([2]), vec![0.3, 1.]));
+///     assert_tensor_eq!(a, Tensor::new(sh!([2]), vec![0.31, 1.01]), 0., 0.02);
+/// ```
+#[macro_export]
+macro_rules! assert_tensor_eq {
+    ($left: expr, $right: expr) => {
+        $crate::assert_tensor_eq!($left, $right, 1e-5, 1e-8)
+    };
+    ($left: expr, $right: expr, $rtol: expr, $atol: expr) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !left.approx_eq(right, $rtol, $atol) {
+                    panic!("assertion `left ≈ right` failed (rtol {}, atol {})\n  left: {:?}\n right: {:?}", $rtol, $atol, left, right);
+                }
+            }
+        }
+    };
+}
+
+#[test]
+fn test_tensor_ops() {
+    let a = Tensor::<f64>::new(&Shape::new([2, 2]), vec![1., 2., 3., 4.]);
+    let b = Tensor::<f64>::new(&Shape::new([2, 2]), vec![5., 6., 7., 8.]);
+    assert_eq!((&a + &b).as_slice(), &[6., 8., 10., 12.]);
+    assert_eq!((&b - &a).as_slice(), &[4., 4., 4., 4.]);
+    assert_eq!((&a * &b).as_slice(), &[5., 12., 21., 32.]);
+    assert_eq!((&b / &a).as_slice(), &[5., 3., 7. / 3., 2.]);
+    assert_eq!((a.clone() + 1.).as_slice(), &[2., 3., 4., 5.]);
+    assert_eq!((&a / 2.).as_slice(), &[0.5, 1., 1.5, 2.]);
+    assert_eq!((a.clone() - &b) * (b.clone() - a.clone()), Tensor::new(&Shape::new([2, 2]), vec![-16.; 4]));
+    assert_eq!(a.matmul(&b).unwrap().as_slice(), &[19., 22., 43., 50.]);
+
+    // a row, a column and a scalar tensor broadcast along the missing axes
+    let row = Tensor::<f64>::new(&Shape::new([2]), vec![10., 20.]);
+    let col = Tensor::<f64>::new(&Shape::new([2, 1]), vec![1., 2.]);
+    assert_eq!((&a + &row).as_slice(), &[11., 22., 13., 24.]);
+    assert_eq!((&row - &a).as_slice(), &[9., 18., 7., 16.]);
+    assert_eq!((&a * &col).as_slice(), &[1., 2., 6., 8.]);
+    let outer = &col * &row;
+    assert_eq!((outer.get_shape(), outer.as_slice()), (&Shape::new([2, 2]), &[10., 20., 20., 40.][..]));
+    let grid = Tensor::<f64>::ones(&Shape::new([3, 1, 2])) + Tensor::ones(&Shape::new([4, 1]));
+    assert_eq!(grid, Tensor::new(&Shape::new([3, 4, 2]), vec![2.; 24]));
+    assert_eq!(&a + &Tensor::new(&Shape::new([]), vec![1.]), a.clone() + 1.);
+    assert_eq!(Shape::new([2, 3]).broadcast(&Shape::new([1])), Ok(Shape::new([2, 3])));
+    let e = a.zip_with(&Tensor::zeros(&Shape::new([3])), f64::max).unwrap_err();
+    assert_eq!(e, EasynnError::mismatch("zip_with", &Shape::new([2, 2]), &Shape::new([3])));
+    assert_eq!(a.zip_with(&row, f64::max).unwrap().as_slice(), &[10., 20., 10., 20.]);
+    assert!(a.matmul(&Tensor::zeros(&Shape::new([3, 1]))).is_err());
+
+    let t = Tensor::<f64>::new(&Shape::new([2, 3, 4]), (0..24).map(|x| x as f64).collect());
+    assert_eq!(a.permute(&[1, 0]).unwrap().as_slice(), &[1., 3., 2., 4.]);
+    let p = t.permute(&[2, 0, 1]).unwrap();
+    assert_eq!(p.get_shape(), &Shape::new([4, 2, 3]));
+    assert_eq!(p.get([3, 1, 2]), t.get([1, 2, 3]));
+    assert_eq!(p.permute(&[1, 2, 0]).unwrap(), t);
+    assert!(t.permute(&[0, 0, 1]).is_err());
+    assert!(t.permute(&[1, 0]).is_err());
+    let r = t.reshape(&Shape::new([6, 4])).unwrap();
+    assert_eq!((r.get_shape(), r.as_slice()), (&Shape::new([6, 4]), t.as_slice()));
+    assert!(t.reshape(&Shape::new([5, 5])).is_err());
+
+    let close = a.map(|x| x * (1. + 1e-7));
+    assert!(close.approx_eq(&a, 1e-6, 0.) && !close.approx_eq(&a, 1e-8, 0.));
+    assert!(a.approx_eq(&a.map(|x| x + 1e-9), 0., 1e-8));
+    assert!(!a.approx_eq(&a.reshape(&Shape::new([4])).unwrap(), 1., 1.));
+    assert_tensor_eq!(&a / &b * &b, a);
+    assert_eq!(t.clone().into_shape(&Shape::new([6, 4])).unwrap(), r);
+    assert!(t.clone().into_shape(&Shape::new([25])).is_err());
+    assert_eq!(t.flatten(), t.reshape(&Shape::new([24])).unwrap());
+    assert_eq!(a.transpose(), a.permute(&[1, 0]).unwrap());
+    assert_eq!(t.transpose(), t.permute(&[2, 1, 0]).unwrap());
+    assert_eq!(t.transpose().transpose(), t);
+}
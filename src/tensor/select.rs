@@ -0,0 +1,134 @@
+//! Index ops along an axis: `argmax` and `argmin` reduce the axis to the index of the
+//! extreme element, `argsort` orders it and `topk` keeps its `k` largest elements.
+//! The indices are returned as tensors of `usize`, ties being broken by the lower index.
+//!
+//! ```rust
+//!     use easynn::prelude::*;
+//!     let scores = Tensor::<f32>::new(sh!([2, 3]), vec![0.1, 0.7, 0.2, 0.5, 0.4, 0.9]);
+//!     assert_eq!(scores.argmax(1).unwrap().as_slice(), [1, 2]);
+//!     assert_eq!(scores.argmin(0).unwrap().as_slice(), [0, 1, 0]);
+//!     let (best, at) = scores.topk(1, 2).unwrap();
+//!     assert_eq!((best.as_slice(), at.as_slice()), (&[0.7, 0.2, 0.9, 0.5][..], &[1, 2, 2, 0][..]));
+//! ```
+
+use crate::tensor::*;
+
+use std::cmp::Ordering;
+
+impl<T: NumT> Tensor<T> {
+    /// The count of the lines along the axis before it, their length, and the stride of the axis
+    fn lines(&self, op: &'static str, axis: usize) -> std::result::Result<(usize, usize, usize), EasynnError> {
+        let dims = self.shape.dims();
+        if axis >= dims.len() {
+            return Err(EasynnError::invalid(op, format!("no axis {} in a tensor of rank {}", axis, dims.len())));
+        }
+        Ok((dims[..axis].iter().product(), dims[axis], dims[axis + 1..].iter().product()))
+    }
+    /// The shape with the length of the axis replaced, or the axis removed for None
+    fn along(&self, axis: usize, len: Option<usize>) -> Shape {
+        let mut dims = self.shape.dims().to_vec();
+        match len {
+            Some(len) => dims[axis] = len,
+            None => { dims.remove(axis); }
+        }
+        Shape::from_slice(&dims)
+    }
+    /// The indices of each line along the axis ordered by `cmp` of their elements, stably,
+    /// the first `k` of each line
+    fn order_lines<F>(&self, op: &'static str, axis: usize, k: usize, cmp: F) -> std::result::Result<Vec<usize>, EasynnError>
+    where F: Fn(&T, &T) -> Ordering {
+        let (outer, n, inner) = self.lines(op, axis)?;
+        if k > n {
+            return Err(EasynnError::invalid(op, format!("{} of the {} elements of axis {} requested", k, n, axis)));
+        }
+        let mut orders = Vec::with_capacity(outer * inner);
+        for o in 0..outer {
+            for j in 0..inner {
+                let at = |i: usize| &self.flattened[(o * n + i) * inner + j];
+                let mut line: Vec<usize> = (0..n).collect();
+                line.sort_by(|a, b| cmp(at(*a), at(*b)));
+                line.truncate(k);
+                orders.push(line);
+            }
+        }
+        // the indices of the lines, laid out with the axis back in its place
+        let mut indices = vec![0; outer * k * inner];
+        for (l, line) in orders.iter().enumerate() {
+            let (o, j) = (l / inner, l % inner);
+            for (i, x) in line.iter().enumerate() {
+                indices[(o * k + i) * inner + j] = *x;
+            }
+        }
+        Ok(indices)
+    }
+    fn arg_extreme<F>(&self, op: &'static str, axis: usize, cmp: F) -> std::result::Result<Tensor<usize>, EasynnError>
+    where F: Fn(&T, &T) -> Ordering {
+        let (_, n, _) = self.lines(op, axis)?;
+        if n == 0 {
+            return Err(EasynnError::invalid(op, format!("axis {} is empty", axis)));
+        }
+        Ok(Tensor::new(&self.along(axis, None), self.order_lines(op, axis, 1, cmp)?))
+    }
+
+    /// The index of the largest element of each line along the axis, the axis removed
+    pub fn argmax(&self, axis: usize) -> std::result::Result<Tensor<usize>, EasynnError> {
+        self.arg_extreme("argmax", axis, descending)
+    }
+    /// The index of the smallest element of each line along the axis, the axis removed
+    pub fn argmin(&self, axis: usize) -> std::result::Result<Tensor<usize>, EasynnError> {
+        self.arg_extreme("argmin", axis, ascending)
+    }
+    /// The indices ordering each line along the axis, in ascending or descending order
+    pub fn argsort(&self, axis: usize, descending_order: bool) -> std::result::Result<Tensor<usize>, EasynnError> {
+        let (_, n, _) = self.lines("argsort", axis)?;
+        let indices = match descending_order {
+            true => self.order_lines("argsort", axis, n, descending)?,
+            false => self.order_lines("argsort", axis, n, ascending)?,
+        };
+        Ok(Tensor::new(&self.shape, indices))
+    }
+    /// The `k` largest elements of each line along the axis, largest first, and their indices
+    pub fn topk(&self, axis: usize, k: usize) -> std::result::Result<(Tensor<T>, Tensor<usize>), EasynnError> {
+        let indices = self.order_lines("topk", axis, k, descending)?;
+        let (_, n, inner) = self.lines("topk", axis)?;
+        let values = indices.iter().enumerate().map(|(p, i)| {
+            let (o, j) = (p / inner / k, p % inner);
+            self.flattened[(o * n + i) * inner + j]
+        }).collect();
+        let shape = self.along(axis, Some(k));
+        Ok((Tensor::new(&shape, values), Tensor::new(&shape, indices)))
+    }
+}
+
+/// The order of the numbers, NaN being equal to anything
+fn ascending<T: NumT>(a: &T, b: &T) -> Ordering {
+    a.partial_cmp(b).unwrap_or(Ordering::Equal)
+}
+
+fn descending<T: NumT>(a: &T, b: &T) -> Ordering {
+    b.partial_cmp(a).unwrap_or(Ordering::Equal)
+}
+
+#[test]
+fn test_tensor_select() {
+    let t = Tensor::<f64>::new(&Shape::new([2, 2, 3]), vec![
+        3., 1., 2.,
+        0., 5., 5.,
+
+        -1., 4., 4.,
+        7., 2., 6.,
+    ]);
+    assert_eq!(t.argmax(2).unwrap(), Tensor::new(&Shape::new([2, 2]), vec![0, 1, 1, 0]));
+    assert_eq!(t.argmin(1).unwrap(), Tensor::new(&Shape::new([2, 3]), vec![1, 0, 0, 0, 1, 0]));
+    assert_eq!(t.argmax(0).unwrap().as_slice(), [0, 1, 1, 1, 0, 1]);
+    assert_eq!(t.argsort(2, false).unwrap().as_slice(), [1, 2, 0, 0, 1, 2, 0, 1, 2, 1, 2, 0]);
+    assert_eq!(t.argsort(2, true).unwrap().as_slice(), [0, 2, 1, 1, 2, 0, 1, 2, 0, 0, 2, 1]);
+    let (values, indices) = t.topk(1, 1).unwrap();
+    assert_eq!(values, Tensor::new(&Shape::new([2, 1, 3]), vec![3., 5., 5., 7., 4., 6.]));
+    assert_eq!(indices.as_slice(), [0, 1, 1, 1, 0, 1]);
+    let (values, _) = t.topk(2, 2).unwrap();
+    assert_eq!(values.as_slice(), [3., 2., 5., 5., 4., 4., 7., 6.]);
+    assert!(t.topk(2, 4).is_err());
+    assert!(t.argmax(3).is_err());
+    assert!(Tensor::<f32>::zeros(&Shape::new([2, 0])).argmin(1).is_err());
+}
@@ -1,5 +1,8 @@
+use std::fmt;
 use std::ops::Index;
 
+use crate::tensor::error::EasynnError;
+
 /// Shape: describes the shape of a tensor given the rank,
 /// which is the dimention count of the tensor.
 #[derive(Debug, Clone, PartialEq)]
@@ -28,12 +31,118 @@ impl Shape {
     ///     let s = Shape::new([2, 3, 5]);
     ///     assert_eq!(s.size(), 30_usize);
     /// ```
+    ///
+    /// Panics if the size overflows, see `checked_size`.
     pub fn size(&self) -> usize {
-        let mut ret: usize = 1;
-        for b in &self.bound {
-            ret *= b;
+        match self.checked_size() {
+            Ok(size) => size,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// The element counts, or an error if it overflows `usize`
+    pub fn checked_size(&self) -> Result<usize, EasynnError> {
+        self.bound.iter().try_fold(1_usize, |acc, b| acc.checked_mul(*b))
+            .ok_or_else(|| EasynnError::SizeOverflow(self.bound.clone()))
+    }
+
+    /// Create a Shape object described by a slice
+    pub fn from_slice(s: &[usize]) -> Self {
+        Shape { bound: s.to_vec() }
+    }
+
+    /// The rank, i.e. the dimention count
+    pub fn rank(&self) -> usize {
+        self.bound.len()
+    }
+
+    /// The bounds of each dimention
+    pub fn dims(&self) -> &[usize] {
+        &self.bound
+    }
+
+    /// The shape of a batch of `n` tensors of this shape, `[n, ..self]`
+    pub fn batched(&self, n: usize) -> Shape {
+        let mut bound = Vec::with_capacity(self.bound.len() + 1);
+        bound.push(n);
+        bound.extend_from_slice(&self.bound);
+        Shape { bound }
+    }
+
+    /// Infer the output shape of a 2D sliding window (conv or pooling)
+    /// applied on the last two dimentions, the leading dimentions are kept,
+    /// e.g.:
+    /// 
+    /// ```rust
+    ///     use easynn::tensor::{ Shape, Padding };
+    ///     let s = Shape::new([3, 28, 28]);
+    ///     assert_eq!(s.window_output((5, 5), (1, 1), Padding::Valid).unwrap(), Shape::new([3, 24, 24]));
+    ///     assert_eq!(s.window_output((5, 5), (2, 2), Padding::Same).unwrap(), Shape::new([3, 14, 14]));
+    /// ```
+    pub fn window_output(&self, kernel: (usize, usize), stride: (usize, usize), padding: Padding) -> Result<Shape, EasynnError> {
+        let rank = self.rank();
+        if rank < 2 || kernel.0 == 0 || kernel.1 == 0 || stride.0 == 0 || stride.1 == 0 {
+            return Err(EasynnError::invalid("window_output", format!("no window {:?} of stride {:?} over the shape {:?}", kernel, stride, self.bound)));
+        }
+        let (h, w) = (self.bound[rank - 2], self.bound[rank - 1]);
+        let (top, bottom, left, right) = padding.amounts((h, w), kernel, stride);
+        let (ph, pw) = (h + top + bottom, w + left + right);
+        if ph < kernel.0 || pw < kernel.1 {
+            return Err(EasynnError::invalid("window_output", format!("the window {:?} exceeds the padded size {:?}", kernel, (ph, pw))));
+        }
+        let mut bound = self.bound.clone();
+        bound[rank - 2] = (ph - kernel.0) / stride.0 + 1;
+        bound[rank - 1] = (pw - kernel.1) / stride.1 + 1;
+        Ok(Shape { bound })
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.bound)
+    }
+}
+
+/// Padding: how the borders of the last two dimentions are padded
+/// by the sliding window layers, e.g. conv and pooling
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Padding {
+    /// No padding, the windows that do not fit are dropped
+    Valid,
+    /// Pad with zeros so that the output is `ceil(input / stride)`,
+    /// the extra row or column goes to the bottom or right
+    Same,
+    /// Explicit (top, bottom, left, right) zero paddings
+    Explicit(usize, usize, usize, usize),
+}
+
+impl Padding {
+    /// The (top, bottom, left, right) paddings of an input of (height, width)
+    pub fn amounts(&self, input: (usize, usize), kernel: (usize, usize), stride: (usize, usize)) -> (usize, usize, usize, usize) {
+        // total padding of one dimention needed by Same
+        let same = |i: usize, k: usize, s: usize| {
+            let o = i.div_ceil(s).max(1);
+            ((o - 1) * s + k).saturating_sub(i)
+        };
+        match *self {
+            Padding::Valid => (0, 0, 0, 0),
+            Padding::Same => {
+                let th = same(input.0, kernel.0, stride.0);
+                let tw = same(input.1, kernel.1, stride.1);
+                (th / 2, th - th / 2, tw / 2, tw - tw / 2)
+            },
+            Padding::Explicit(t, b, l, r) => (t, b, l, r),
+        }
+    }
+}
+
+impl fmt::Display for Padding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Padding::Valid => write!(f, "valid padding"),
+            Padding::Same => write!(f, "same padding"),
+            Padding::Explicit(t, b, l, r) => write!(f, "padding (top {}, bottom {}, left {}, right {})", t, b, l, r),
         }
-        ret
     }
 }
 
@@ -42,4 +151,23 @@ macro_rules! sh {
     ($t: tt) => {
         &Shape::new($t)
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_checked_size() {
+    assert_eq!(Shape::new([2, 3]).checked_size(), Ok(6));
+    assert_eq!(Shape::new([usize::MAX, 2]).checked_size(), Err(EasynnError::SizeOverflow(vec![usize::MAX, 2])));
+}
+
+#[test]
+fn test_window_output() {
+    let s = Shape::new([7, 6]);
+    assert_eq!(s.window_output((3, 3), (1, 1), Padding::Valid).unwrap(), Shape::new([5, 4]));
+    assert_eq!(s.window_output((3, 3), (2, 2), Padding::Valid).unwrap(), Shape::new([3, 2]));
+    assert_eq!(s.window_output((3, 3), (1, 1), Padding::Same).unwrap(), Shape::new([7, 6]));
+    assert_eq!(s.window_output((3, 3), (2, 2), Padding::Same).unwrap(), Shape::new([4, 3]));
+    assert_eq!(Padding::Same.amounts((6, 6), (3, 3), (2, 2)), (0, 1, 0, 1));
+    assert_eq!(s.window_output((2, 2), (1, 1), Padding::Explicit(1, 0, 2, 1)).unwrap(), Shape::new([7, 8]));
+    assert!(s.window_output((8, 3), (1, 1), Padding::Valid).is_err());
+    assert!(Shape::new([5]).window_output((1, 1), (1, 1), Padding::Valid).is_err());
+}
@@ -0,0 +1,200 @@
+//! Bounding-box utilities for object detection: IoU, non-maximum suppression,
+//! and the encoding of boxes as offsets to anchors.
+//!
+//! A box is given by its corners `(x1, y1, x2, y2)` with `x1 <= x2` and `y1 <= y2`,
+//! a tensor of boxes is of shape `[n, 4]`.
+//!
+//! ```rust
+//!     use easynn::vision::*;
+//!     let boxes = [BBox::new(0., 0., 10., 10.), BBox::new(1., 1., 11., 11.), BBox::new(20., 20., 30., 30.)];
+//!     let keep = nms(&boxes, &[0.9, 0.8, 0.7], 0.5).unwrap();
+//!     assert_eq!(keep, vec![0, 2]);
+//! ```
+
+use crate::tensor::*;
+type Result<T> = std::result::Result<T, EasynnError>;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BBox<T: NumT> {
+    pub x1: T,
+    pub y1: T,
+    pub x2: T,
+    pub y2: T,
+}
+
+impl<T: NumT> BBox<T> {
+    pub fn new(x1: T, y1: T, x2: T, y2: T) -> Self {
+        BBox { x1, y1, x2, y2 }
+    }
+    /// The box of the center and the size
+    pub fn from_center(cx: T, cy: T, w: T, h: T) -> Self {
+        let half = T::from(0.5).unwrap();
+        BBox::new(cx - w * half, cy - h * half, cx + w * half, cy + h * half)
+    }
+    pub fn center(&self) -> (T, T) {
+        let half = T::from(0.5).unwrap();
+        ((self.x1 + self.x2) * half, (self.y1 + self.y2) * half)
+    }
+    pub fn width(&self) -> T {
+        (self.x2 - self.x1).max(T::zero())
+    }
+    pub fn height(&self) -> T {
+        (self.y2 - self.y1).max(T::zero())
+    }
+    pub fn area(&self) -> T {
+        self.width() * self.height()
+    }
+    /// The area of the overlap
+    pub fn intersection(&self, other: &Self) -> T {
+        let w = self.x2.min(other.x2) - self.x1.max(other.x1);
+        let h = self.y2.min(other.y2) - self.y1.max(other.y1);
+        w.max(T::zero()) * h.max(T::zero())
+    }
+    /// The intersection over union, 0 if both boxes are empty
+    pub fn iou(&self, other: &Self) -> T {
+        let inter = self.intersection(other);
+        let union = self.area() + other.area() - inter;
+        if union > T::zero() { inter / union } else { T::zero() }
+    }
+}
+
+/// The boxes of a `[n, 4]` tensor
+pub fn boxes_from_tensor<T: NumT>(t: &Tensor<T>) -> Result<Vec<BBox<T>>> {
+    if t.shape.rank() != 2 || t.shape[1] != 4 {
+        let n = t.shape.dims().first().copied().unwrap_or(0);
+        return Err(EasynnError::mismatch("boxes_from_tensor", &Shape::new([n, 4]), &t.shape));
+    }
+    Ok(t.flattened.chunks(4).map(|c| BBox::new(c[0], c[1], c[2], c[3])).collect())
+}
+
+/// The `[n, 4]` tensor of the boxes
+pub fn boxes_to_tensor<T: NumT>(boxes: &[BBox<T>]) -> Tensor<T> {
+    let data = boxes.iter().flat_map(|b| [b.x1, b.y1, b.x2, b.y2]).collect();
+    Tensor::new(&Shape::new([boxes.len(), 4]), data)
+}
+
+/// The `[n, m]` IoU of each box in `a` with each box in `b`
+pub fn pairwise_iou<T: NumT>(a: &[BBox<T>], b: &[BBox<T>]) -> Tensor<T> {
+    let data = a.iter().flat_map(|x| b.iter().map(move |y| x.iou(y))).collect();
+    Tensor::new(&Shape::new([a.len(), b.len()]), data)
+}
+
+/// The indices by descending score, ties keeping the order of the boxes
+fn by_score<T: NumT>(scores: &[T]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|i, j| scores[*j].partial_cmp(&scores[*i]).unwrap_or(std::cmp::Ordering::Equal));
+    order
+}
+
+/// Non-maximum suppression: greedily keep the box of the highest score and drop the boxes
+/// overlapping it with an IoU over the threshold, returning the kept indices by descending score
+pub fn nms<T: NumT>(boxes: &[BBox<T>], scores: &[T], iou_threshold: T) -> Result<Vec<usize>> {
+    check_len("nms", scores.len(), boxes.len())?;
+    let mut keep: Vec<usize> = Vec::new();
+    for i in by_score(scores) {
+        if keep.iter().all(|k| boxes[*k].iou(&boxes[i]) <= iou_threshold) {
+            keep.push(i);
+        }
+    }
+    Ok(keep)
+}
+
+/// Non-maximum suppression within each class, boxes of different classes never suppress each other
+pub fn batched_nms<T: NumT>(boxes: &[BBox<T>], scores: &[T], classes: &[usize], iou_threshold: T) -> Result<Vec<usize>> {
+    check_len("batched_nms", scores.len(), boxes.len())?;
+    check_len("batched_nms", classes.len(), boxes.len())?;
+    let mut keep: Vec<usize> = Vec::new();
+    for i in by_score(scores) {
+        if keep.iter().all(|k| classes[*k] != classes[i] || boxes[*k].iou(&boxes[i]) <= iou_threshold) {
+            keep.push(i);
+        }
+    }
+    Ok(keep)
+}
+
+/// Encodes boxes as the offsets `(dx, dy, dw, dh)` to their anchors as in Faster R-CNN:
+/// the center shift divided by the anchor size and the log of the size ratio,
+/// each multiplied by its weight
+#[derive(Debug, Copy, Clone)]
+pub struct BoxCoder<T: NumT> {
+    pub weights: (T, T, T, T),
+}
+
+impl<T: NumT> Default for BoxCoder<T> {
+    fn default() -> Self {
+        BoxCoder { weights: (T::one(), T::one(), T::one(), T::one()) }
+    }
+}
+
+impl<T: NumT> BoxCoder<T> {
+    pub fn new(weights: (T, T, T, T)) -> Self {
+        BoxCoder { weights }
+    }
+    /// The `[n, 4]` offsets of each box to its anchor, the anchors must not be empty
+    pub fn encode(&self, anchors: &[BBox<T>], boxes: &[BBox<T>]) -> Result<Tensor<T>> {
+        check_len("encode", boxes.len(), anchors.len())?;
+        let (wx, wy, ww, wh) = self.weights;
+        let data = anchors.iter().zip(boxes.iter()).flat_map(|(a, b)| {
+            let ((acx, acy), (bcx, bcy)) = (a.center(), b.center());
+            [
+                wx * (bcx - acx) / a.width(),
+                wy * (bcy - acy) / a.height(),
+                ww * (b.width() / a.width()).ln(),
+                wh * (b.height() / a.height()).ln(),
+            ]
+        }).collect();
+        Ok(Tensor::new(&Shape::new([boxes.len(), 4]), data))
+    }
+    /// The boxes of the `[n, 4]` offsets to the anchors, inverting `encode`
+    pub fn decode(&self, anchors: &[BBox<T>], deltas: &Tensor<T>) -> Result<Vec<BBox<T>>> {
+        check_shape("decode", &Shape::new([anchors.len(), 4]), &deltas.shape)?;
+        let (wx, wy, ww, wh) = self.weights;
+        Ok(anchors.iter().zip(deltas.flattened.chunks(4)).map(|(a, d)| {
+            let (acx, acy) = a.center();
+            BBox::from_center(
+                acx + d[0] / wx * a.width(),
+                acy + d[1] / wy * a.height(),
+                (d[2] / ww).exp() * a.width(),
+                (d[3] / wh).exp() * a.height(),
+            )
+        }).collect())
+    }
+}
+
+#[test]
+fn test_vision() {
+    let a = BBox::<f64>::new(0., 0., 2., 2.);
+    let b = BBox::new(1., 1., 3., 3.);
+    assert_eq!(a.intersection(&b), 1.);
+    assert!((a.iou(&b) - 1. / 7.).abs() < 1e-12);
+    assert_eq!(a.iou(&BBox::new(5., 5., 6., 6.)), 0.);
+    assert_eq!(BBox::<f64>::new(1., 1., 1., 1.).iou(&BBox::new(1., 1., 1., 1.)), 0.);
+    let iou = pairwise_iou(&[a, b], &[b]);
+    assert_eq!(iou.get_shape(), &Shape::new([2, 1]));
+    assert_eq!(iou.get([1, 0]), 1.);
+
+    let t = boxes_to_tensor(&[a, b]);
+    assert_eq!(boxes_from_tensor(&t).unwrap(), vec![a, b]);
+    assert!(boxes_from_tensor(&Tensor::<f64>::zeros(&Shape::new([2, 3]))).is_err());
+
+    // The same boxes in two classes: suppressed within the class only
+    let boxes = [a, BBox::new(0., 0., 2., 2.1), b, a];
+    let scores = [0.5, 0.9, 0.8, 0.7];
+    assert_eq!(nms(&boxes, &scores, 0.5).unwrap(), vec![1, 2]);
+    assert_eq!(batched_nms(&boxes, &scores, &[0, 0, 0, 1], 0.5).unwrap(), vec![1, 2, 3]);
+    assert_eq!(nms(&boxes, &scores, 1.).unwrap(), vec![1, 2, 3, 0]);
+    assert!(nms(&boxes, &scores[1..], 0.5).is_err());
+
+    let coder = BoxCoder::new((10., 10., 5., 5.));
+    let anchors = [BBox::new(0., 0., 4., 4.), BBox::new(10., 10., 12., 16.)];
+    let truths = [BBox::new(1., 0., 5., 8.), BBox::new(9., 11., 12., 14.)];
+    let deltas = coder.encode(&anchors, &truths).unwrap();
+    assert_eq!(deltas.get([0, 0]), 2.5);
+    assert!((deltas.get([0, 3]) - 5. * 2_f64.ln()).abs() < 1e-12);
+    for (d, t) in coder.decode(&anchors, &deltas).unwrap().iter().zip(truths.iter()) {
+        for (x, y) in [(d.x1, t.x1), (d.y1, t.y1), (d.x2, t.x2), (d.y2, t.y2)] {
+            assert!((x - y).abs() < 1e-9);
+        }
+    }
+    assert!(coder.decode(&anchors[1..], &deltas).is_err());
+}
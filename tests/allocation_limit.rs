@@ -0,0 +1,27 @@
+//! The allocation cap is global to the process, so it is tested in its own binary
+//! where no other test allocates meanwhile.
+
+use easynn::prelude::*;
+use easynn::tensor::{ set_allocation_limit, allocation_limit, EasynnError };
+use easynn::datasets::read_idx;
+
+#[test]
+fn test_allocation_limit() {
+    assert_eq!(allocation_limit(), None);
+    // 4096 * 4096 f64 weights are 128 MiB
+    set_allocation_limit(Some(64 << 20));
+    assert_eq!(allocation_limit(), Some(64 << 20));
+    let capped = Dense::<f64>::try_new(sh!([4096]), sh!([4096]), Activation::No);
+    assert_eq!(capped.err(), Some(EasynnError::ResourceLimit { requested: 4096 * 4096 * 8, limit: 64 << 20 }));
+    assert!(Dense::<f64>::try_new(sh!([3]), sh!([2]), Activation::No).is_ok());
+    // as are the other layers of large tables and the loaders
+    let conv = Conv2D::<f64>::try_new(sh!([1024, 8, 8]), 1024, (3, 3), (1, 1), Padding::Same, Activation::No);
+    assert!(matches!(conv.err(), Some(EasynnError::ResourceLimit { .. })));
+    let emb = Embedding::<f64>::try_new(sh!([1]), 1 << 20, 16);
+    assert!(matches!(emb.err(), Some(EasynnError::ResourceLimit { .. })));
+    // a header of 2^24 doubles, rejected before its data is read
+    let idx = [0u8, 0, 0x0E, 1, 1, 0, 0, 0];
+    assert!(read_idx::<f64, _>(&idx[..]).unwrap_err().to_string().contains("exceeds the limit"));
+    set_allocation_limit(None);
+    assert_eq!(allocation_limit(), None);
+}